@@ -1,49 +1,70 @@
-use ctrlc;
+use std::env;
 use std::fs;
 use std::io::{Read, Write};
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::Path;
 use std::process;
+use std::sync::OnceLock;
+use std::time::Instant;
 use tpm::marshal;
 use tpm::platform;
 use tpm::tpm::TpmInstance;
 use tpm::types;
 
+mod nv;
+
 const SOCKET_PATH: &str = "/tmp/rust-tpm";
 
 fn handle_request(tpm: &mut TpmInstance, stream: &mut UnixStream) {
     let mut msg_buf = [0u8; types::MAX_MSG_SIZE];
-    let mut _size = match stream.read_exact(&mut msg_buf[..types::COMMAND_HDR_SIZE]) {
-        Ok(size) => size,
-        Err(_) => {
-            println!("Failed to read request header");
-            return;
-        }
-    };
+    if stream
+        .read_exact(&mut msg_buf[..types::COMMAND_HDR_SIZE])
+        .is_err()
+    {
+        println!("Failed to read request header");
+        return;
+    }
 
-    let mut offset: usize = 0;
-    let hdr = match marshal::unmarshal_command_header(&msg_buf, &mut offset) {
-        Ok(hdr) => hdr,
+    // Only the size is needed here. The TPM validates the rest of the header
+    // and responds with an error if it's bad.
+    let mut offset: usize = 2;
+    let size = match marshal::unmarshal_u32(&msg_buf, &mut offset) {
+        Ok(size) => size as usize,
         Err(e) => {
             println!("Unable to parse command header: {}", e);
             return;
         }
     };
+    let command_code = marshal::unmarshal_u32(&msg_buf, &mut offset).unwrap_or(0);
 
-    _size = match stream.read_exact(&mut msg_buf[types::COMMAND_HDR_SIZE..hdr.size as usize]) {
-        Ok(size) => size,
-        Err(_) => {
-            println!("Failed to read request");
-            return;
-        }
-    };
+    if !(types::COMMAND_HDR_SIZE..=types::MAX_MSG_SIZE).contains(&size) {
+        println!("Invalid request size {}", size);
+        return;
+    }
 
-    println!("Executing TPM Command {:#x}", hdr.command_code as u32);
+    if stream
+        .read_exact(&mut msg_buf[types::COMMAND_HDR_SIZE..size])
+        .is_err()
+    {
+        println!("Failed to read request");
+        return;
+    }
+
+    println!("Executing TPM Command {:#x}", command_code);
 
     let mut response: [u8; 4096] = [0; 4096];
-    let size = tpm::execute_command(tpm, &mut msg_buf, &mut response);
+    let size = tpm::execute_command(tpm, &msg_buf[..size], &mut response);
 
-    match stream.write(&response[..size]) {
+    // Don't report the result until NV holds it. The TPM has already
+    // applied the command, so it can't carry on from state that was never
+    // saved: stop, and the client sees the connection close.
+    if let Err(e) = nv::commit() {
+        println!("Failed to save NV, shutting down: {}", e);
+        cleanup();
+        process::exit(1);
+    }
+
+    match stream.write_all(&response[..size]) {
         Ok(_) => (),
         Err(e) => println!("Failed to write response: {}", e),
     };
@@ -62,7 +83,39 @@ fn print(msg: &str) {
     println!("{}", msg);
 }
 
+fn get_random(buf: &mut [u8]) {
+    let mut urandom = fs::File::open("/dev/urandom").expect("Unable to open /dev/urandom");
+    urandom
+        .read_exact(buf)
+        .expect("Unable to read from /dev/urandom");
+}
+
+// Milliseconds since the simulator started, which is when the TPM powers on.
+fn get_time() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+fn usage() -> ! {
+    println!("Usage: tpm-sim [--nv-file PATH]");
+    process::exit(2);
+}
+
 fn main() -> std::io::Result<()> {
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--nv-file" => {
+                let path = args.next().unwrap_or_else(|| usage());
+                if let Err(e) = nv::open(Path::new(&path)) {
+                    println!("Unable to load NV from {}: {}", path, e);
+                    process::exit(1);
+                }
+            }
+            _ => usage(),
+        }
+    }
+
     let socket = Path::new(SOCKET_PATH);
     // Delete old socket if necessary
     if socket.exists() {
@@ -77,8 +130,16 @@ fn main() -> std::io::Result<()> {
     })
     .unwrap();
 
-    let host_plat = platform::TpmPlatform { log: print };
+    let host_plat = platform::TpmPlatform {
+        log: print,
+        get_random,
+        nv_read: nv::nv_read,
+        nv_write: nv::nv_write,
+        get_time,
+    };
     let mut tpm = TpmInstance::new(&host_plat);
+    // The first run manufactures the TPM, which writes its seeds to NV.
+    nv::commit()?;
 
     for stream in listener.incoming() {
         match stream {
@@ -0,0 +1,281 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tpm::types::NV_MEMORY_SIZE;
+
+// The NV file is a header followed by the image the TPM sees. The header
+// records the format and image size, so a file written by a build with a
+// different NV layout is refused rather than misread.
+const MAGIC: &[u8; 8] = b"RTPM-NV\0";
+const VERSION: u32 = 4;
+const HEADER_SIZE: usize = 16;
+
+struct NvStore {
+    image: [u8; NV_MEMORY_SIZE],
+    // Without a file NV only lasts as long as the simulator process.
+    path: Option<PathBuf>,
+    dirty: bool,
+}
+
+static NV: Mutex<NvStore> = Mutex::new(NvStore::new());
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl NvStore {
+    const fn new() -> NvStore {
+        NvStore {
+            image: [0; NV_MEMORY_SIZE],
+            path: None,
+            dirty: false,
+        }
+    }
+
+    fn open(&mut self, path: &Path) -> io::Result<()> {
+        match File::open(path) {
+            Ok(mut file) => {
+                let mut header = [0u8; HEADER_SIZE];
+                file.read_exact(&mut header)?;
+                if &header[..8] != MAGIC {
+                    return Err(invalid("not an NV file"));
+                }
+                if u32::from_be_bytes(header[8..12].try_into().unwrap()) != VERSION {
+                    return Err(invalid("unsupported NV file version"));
+                }
+                if u32::from_be_bytes(header[12..16].try_into().unwrap()) as usize != NV_MEMORY_SIZE
+                {
+                    return Err(invalid("NV file size doesn't match this build"));
+                }
+                file.read_exact(&mut self.image)?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+
+        self.path = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) if self.dirty => path.clone(),
+            _ => return Ok(()),
+        };
+
+        let mut tmp_path = OsString::from(&path);
+        tmp_path.push(".tmp");
+
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(MAGIC)?;
+        file.write_all(&VERSION.to_be_bytes())?;
+        file.write_all(&(NV_MEMORY_SIZE as u32).to_be_bytes())?;
+        file.write_all(&self.image)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+
+        // Make the rename itself durable.
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+
+        self.dirty = false;
+        Ok(())
+    }
+
+    // The part of the image at `offset`, if it's all in range
+    fn range(&mut self, offset: usize, len: usize) -> Option<&mut [u8]> {
+        let end = offset.checked_add(len)?;
+        self.image.get_mut(offset..end)
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> bool {
+        match self.range(offset, buf.len()) {
+            Some(image) => {
+                buf.copy_from_slice(image);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> bool {
+        match self.range(offset, data.len()) {
+            Some(image) => {
+                image.copy_from_slice(data);
+                self.dirty = true;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// Back NV with the file at `path`, loading it if it exists. A missing file
+// means a TPM that was never powered on.
+pub fn open(path: &Path) -> io::Result<()> {
+    NV.lock().unwrap().open(path)
+}
+
+// Write the image out if the TPM changed it. It goes to a temporary file
+// which is renamed over the old one, so a simulator killed at any point
+// leaves either the old image or the new one.
+pub fn commit() -> io::Result<()> {
+    NV.lock().unwrap().commit()
+}
+
+pub fn nv_read(offset: usize, buf: &mut [u8]) -> bool {
+    NV.lock().unwrap().read(offset, buf)
+}
+
+pub fn nv_write(offset: usize, data: &[u8]) -> bool {
+    NV.lock().unwrap().write(offset, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    // A fresh directory for one test's NV file
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tpm-sim-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn header(version: u32, size: usize) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&version.to_be_bytes());
+        header.extend_from_slice(&(size as u32).to_be_bytes());
+        header
+    }
+
+    #[test]
+    fn commit_and_reopen() {
+        let path = test_dir("reopen").join("nv");
+
+        let mut nv = NvStore::new();
+        nv.open(&path).unwrap();
+        assert!(nv.write(100, b"seed"));
+        nv.commit().unwrap();
+
+        let contents = fs::read(&path).unwrap();
+        assert_eq!(contents.len(), HEADER_SIZE + NV_MEMORY_SIZE);
+        assert_eq!(contents[..HEADER_SIZE], header(VERSION, NV_MEMORY_SIZE));
+
+        let mut nv = NvStore::new();
+        nv.open(&path).unwrap();
+        let mut buf = [0u8; 4];
+        assert!(nv.read(100, &mut buf));
+        assert_eq!(&buf, b"seed");
+    }
+
+    // A missing file is a new TPM, and nothing is written until NV changes.
+    #[test]
+    fn missing_file() {
+        let path = test_dir("missing").join("nv");
+
+        let mut nv = NvStore::new();
+        nv.open(&path).unwrap();
+        assert!(nv.image.iter().all(|b| *b == 0));
+        nv.commit().unwrap();
+        assert!(!path.exists());
+    }
+
+    // The new image only replaces the old one by rename, and the temporary
+    // file is gone afterwards. A temporary file left by a simulator killed
+    // mid-commit doesn't get in the way.
+    #[test]
+    fn commit_replaces_by_rename() {
+        let dir = test_dir("rename");
+        let path = dir.join("nv");
+        let tmp_path = dir.join("nv.tmp");
+
+        let mut nv = NvStore::new();
+        nv.open(&path).unwrap();
+        assert!(nv.write(0, &[1]));
+        nv.commit().unwrap();
+
+        fs::write(&tmp_path, b"partial").unwrap();
+        assert!(nv.write(0, &[2]));
+        nv.commit().unwrap();
+        assert!(!tmp_path.exists());
+        assert_eq!(fs::read(&path).unwrap()[HEADER_SIZE], 2);
+    }
+
+    // When the new image can't be written the old file is left as it was,
+    // and the change is still pending.
+    #[test]
+    fn failed_commit_keeps_old_image() {
+        let dir = test_dir("failed");
+        let path = dir.join("nv");
+
+        let mut nv = NvStore::new();
+        nv.open(&path).unwrap();
+        assert!(nv.write(0, &[1]));
+        nv.commit().unwrap();
+        let old = fs::read(&path).unwrap();
+
+        fs::create_dir(dir.join("nv.tmp")).unwrap();
+        assert!(nv.write(0, &[2]));
+        assert!(nv.commit().is_err());
+        assert_eq!(fs::read(&path).unwrap(), old);
+        assert!(nv.dirty);
+
+        fs::remove_dir(dir.join("nv.tmp")).unwrap();
+        nv.commit().unwrap();
+        assert_eq!(fs::read(&path).unwrap()[HEADER_SIZE], 2);
+    }
+
+    #[test]
+    fn bad_header() {
+        let dir = test_dir("header");
+        let path = dir.join("nv");
+        let image = vec![0u8; NV_MEMORY_SIZE];
+
+        let mut wrong_magic = header(VERSION, NV_MEMORY_SIZE);
+        wrong_magic[0] = b'X';
+        let cases = [
+            wrong_magic,
+            header(VERSION + 1, NV_MEMORY_SIZE),
+            header(VERSION, NV_MEMORY_SIZE + 1),
+        ];
+        for contents in cases {
+            fs::write(&path, [contents, image.clone()].concat()).unwrap();
+            let err = NvStore::new().open(&path).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn short_file() {
+        let dir = test_dir("short");
+        let path = dir.join("nv");
+
+        fs::write(&path, &MAGIC[..4]).unwrap();
+        let err = NvStore::new().open(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let image = vec![0u8; NV_MEMORY_SIZE - 1];
+        fs::write(&path, [header(VERSION, NV_MEMORY_SIZE), image].concat()).unwrap();
+        let err = NvStore::new().open(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn out_of_range() {
+        let mut nv = NvStore::new();
+        let mut buf = [0u8; 2];
+        assert!(!nv.read(NV_MEMORY_SIZE - 1, &mut buf));
+        assert!(!nv.read(usize::MAX, &mut buf));
+        assert!(!nv.write(NV_MEMORY_SIZE - 1, &buf));
+        assert!(!nv.dirty);
+        assert!(nv.read(NV_MEMORY_SIZE - 2, &mut buf));
+    }
+}
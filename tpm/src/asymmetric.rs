@@ -0,0 +1,361 @@
+use crate::crypto::kdf::{kdfe, PlatformRandom};
+use crate::crypto::{ecc, rsa};
+use crate::marshal::*;
+use crate::object::*;
+use crate::tpm::*;
+use crate::types::*;
+
+// A label has to be empty or end with its terminating zero, which is then
+// part of what OAEP hashes.
+fn label_ok(label: &Tpm2bData) -> bool {
+    label.is_empty() || label.as_slice().last() == Some(&0)
+}
+
+// The RSA key behind `handle` and the scheme to use with it. A key with a
+// scheme of its own can only be used with that scheme.
+fn rsa_decrypt_key(
+    tpm: &TpmInstance,
+    handle: TpmHandle,
+    in_scheme: &TpmtAsymScheme,
+) -> Result<(Object, TpmsRsaParms, TpmtAsymScheme), TpmError> {
+    let object = loaded_object(tpm, handle)?;
+    let parms = match object.public.parameters {
+        TpmuPublicParms::Rsa(parms) => parms,
+        _ => return Err(TpmError::handle(TpmRc::Key, 1)),
+    };
+    if !object.public.has_attributes(TPMA_OBJECT_DECRYPT) {
+        return Err(TpmError::handle(TpmRc::Attributes, 1));
+    }
+
+    let key_scheme = parms.scheme;
+    let scheme = if key_scheme.scheme == TpmAlgId::Null {
+        *in_scheme
+    } else if in_scheme.scheme == TpmAlgId::Null
+        || (in_scheme.scheme == key_scheme.scheme && in_scheme.hash_alg == key_scheme.hash_alg)
+    {
+        key_scheme
+    } else {
+        return Err(TpmError::parameter(TpmRc::Scheme, 2));
+    };
+
+    Ok((object, parms, scheme))
+}
+
+// Encrypt with the public part of an RSA key. Since anyone could do this
+// outside the TPM, the key needs no authorization.
+pub fn tpm2_rsa_encrypt(
+    tpm: &mut TpmInstance,
+    args: &RsaEncryptArgs,
+) -> Result<RsaEncryptResponse, TpmError> {
+    let (object, parms, scheme) = rsa_decrypt_key(tpm, args.key_handle, &args.in_scheme)?;
+    if !label_ok(&args.label) {
+        return Err(TpmError::parameter(TpmRc::Value, 3));
+    }
+    let n = match &object.public.unique {
+        TpmuPublicId::Rsa(n) => n,
+        _ => return Err(TpmError::handle(TpmRc::Key, 1)),
+    };
+
+    let mut rand = PlatformRandom {
+        get_random: tpm.platform.get_random,
+    };
+    let mut out_data = Tpm2bPublicKeyRsa::default();
+    rsa::encrypt(
+        &scheme,
+        parms.exponent,
+        n,
+        args.message.as_slice(),
+        args.label.as_slice(),
+        &mut rand,
+        &mut out_data,
+    )
+    .map_err(|e| e.with_parameter(1))?;
+
+    Ok(RsaEncryptResponse { out_data })
+}
+
+// Decrypt with the private part of an RSA key. Restricted decryption keys
+// only decrypt what the TPM itself has protected, so they can't be used.
+pub fn tpm2_rsa_decrypt(
+    tpm: &mut TpmInstance,
+    args: &RsaDecryptArgs,
+) -> Result<RsaDecryptResponse, TpmError> {
+    let (object, parms, scheme) = rsa_decrypt_key(tpm, args.key_handle, &args.in_scheme)?;
+    if object.public.has_attributes(TPMA_OBJECT_RESTRICTED) {
+        return Err(TpmError::handle(TpmRc::Attributes, 1));
+    }
+    if !label_ok(&args.label) {
+        return Err(TpmError::parameter(TpmRc::Value, 3));
+    }
+    let (n, p) = match (&object.public.unique, &object.sensitive.sensitive) {
+        (TpmuPublicId::Rsa(n), TpmuSensitiveComposite::Rsa(p)) => (n, p),
+        // A public-only key
+        _ => return Err(TpmError::handle(TpmRc::Key, 1)),
+    };
+
+    let mut message = Tpm2bPublicKeyRsa::default();
+    rsa::decrypt(
+        &scheme,
+        parms.exponent,
+        n,
+        p,
+        args.cipher_text.as_slice(),
+        args.label.as_slice(),
+        &mut message,
+    )
+    .map_err(|e| e.with_parameter(1))?;
+
+    Ok(RsaDecryptResponse { message })
+}
+
+pub fn tpm2_ecc_parameters(
+    _tpm: &mut TpmInstance,
+    args: &EccParametersArgs,
+) -> Result<EccParametersResponse, TpmError> {
+    let parameters = ecc::curve_detail(args.curve_id).map_err(|e| e.with_parameter(1))?;
+
+    Ok(EccParametersResponse { parameters })
+}
+
+// The ECC key behind `handle`. Key exchange with a restricted decryption
+// key would give away secrets the TPM protects with it, so it has to be
+// unrestricted.
+fn ecc_exchange_key(
+    tpm: &TpmInstance,
+    handle: TpmHandle,
+) -> Result<(Object, TpmsEccParms), TpmError> {
+    let object = loaded_object(tpm, handle)?;
+    let parms = match object.public.parameters {
+        TpmuPublicParms::Ecc(parms) => parms,
+        _ => return Err(TpmError::handle(TpmRc::Key, 1)),
+    };
+    if object.public.has_attributes(TPMA_OBJECT_RESTRICTED)
+        || !object.public.has_attributes(TPMA_OBJECT_DECRYPT)
+    {
+        return Err(TpmError::handle(TpmRc::Attributes, 1));
+    }
+
+    Ok((object, parms))
+}
+
+fn ecc_private(object: &Object) -> Result<&Tpm2bEccParameter, TpmError> {
+    match &object.sensitive.sensitive {
+        TpmuSensitiveComposite::Ecc(d) => Ok(d),
+        // A public-only key
+        _ => Err(TpmError::handle(TpmRc::Key, 1)),
+    }
+}
+
+// One-pass ECDH with the public part of an ECC key: a new ephemeral key,
+// and the point it shares with the key. Like RSA_Encrypt this needs no
+// authorization.
+pub fn tpm2_ecdh_key_gen(
+    tpm: &mut TpmInstance,
+    args: &EcdhKeyGenArgs,
+) -> Result<EcdhKeyGenResponse, TpmError> {
+    let object = loaded_object(tpm, args.key_handle)?;
+    let (parms, q) = match (&object.public.parameters, &object.public.unique) {
+        (TpmuPublicParms::Ecc(parms), TpmuPublicId::Ecc(q)) => (parms, q),
+        _ => return Err(TpmError::handle(TpmRc::Key, 1)),
+    };
+
+    let mut rand = PlatformRandom {
+        get_random: tpm.platform.get_random,
+    };
+    let mut response = EcdhKeyGenResponse::default();
+    ecc::ecdh_keygen(
+        parms.curve_id,
+        q,
+        &mut rand,
+        &mut response.z_point,
+        &mut response.pub_point,
+    )?;
+
+    Ok(response)
+}
+
+// The point a key shares with someone else's public point, dA * inPoint.
+pub fn tpm2_ecdh_zgen(
+    tpm: &mut TpmInstance,
+    args: &EcdhZGenArgs,
+) -> Result<EcdhZGenResponse, TpmError> {
+    let (object, parms) = ecc_exchange_key(tpm, args.key_handle)?;
+    if !matches!(parms.scheme.scheme, TpmAlgId::Null | TpmAlgId::EcDh) {
+        return Err(TpmError::handle(TpmRc::Scheme, 1));
+    }
+    let d = ecc_private(&object)?;
+
+    let mut out_point = TpmsEccPoint::default();
+    ecc::point_multiply(parms.curve_id, d, &args.in_point, &mut out_point)
+        .map_err(|e| e.with_parameter(1))?;
+
+    Ok(EcdhZGenResponse { out_point })
+}
+
+// The second phase of a two-phase key exchange. Party A's ephemeral key
+// comes from an earlier EC_Ephemeral, which `counter` names, and can only
+// be used once.
+pub fn tpm2_zgen_2phase(
+    tpm: &mut TpmInstance,
+    args: &ZGen2PhaseArgs,
+) -> Result<ZGen2PhaseResponse, TpmError> {
+    let (object, parms) = ecc_exchange_key(tpm, args.key_a)?;
+    if parms.scheme.scheme != TpmAlgId::Null && parms.scheme.scheme != args.in_scheme {
+        return Err(TpmError::parameter(TpmRc::Scheme, 3));
+    }
+    let curve_id = parms.curve_id;
+    ecc::validate_point(curve_id, &args.in_qs_b).map_err(|e| e.with_parameter(1))?;
+    ecc::validate_point(curve_id, &args.in_qe_b).map_err(|e| e.with_parameter(2))?;
+    let ds = ecc_private(&object)?;
+
+    let mut de = Tpm2bEccParameter::default();
+    tpm.commit_end(curve_id, &[], args.counter, &mut de)
+        .map_err(|e| e.with_parameter(4))?;
+
+    let mut response = ZGen2PhaseResponse::default();
+    ecc::zgen_2phase(
+        curve_id,
+        args.in_scheme,
+        ds,
+        &de,
+        &args.in_qs_b,
+        &args.in_qe_b,
+        &mut response.out_z1,
+        &mut response.out_z2,
+    )?;
+
+    Ok(response)
+}
+
+// An ephemeral key for the first phase of a two-phase key exchange. Only
+// the public point leaves the TPM; ZGen_2Phase recreates the private key
+// from the counter.
+pub fn tpm2_ec_ephemeral(
+    tpm: &mut TpmInstance,
+    args: &EcEphemeralArgs,
+) -> Result<EcEphemeralResponse, TpmError> {
+    let mut r = Tpm2bEccParameter::default();
+    let mut q = TpmsEccPoint::default();
+    tpm.commit_next(args.curve_id, &[], &mut r, &mut q)
+        .map_err(|e| e.with_parameter(1))?;
+    let counter = tpm.commit_start(&[]);
+
+    Ok(EcEphemeralResponse { q, counter })
+}
+
+// Share a seed with the holder of an asymmetric key, as credentials and
+// duplication do. The seed is as long as a nameAlg digest. With RSA it's
+// encrypted with OAEP using the nameAlg and `label`. With ECC the secret is
+// an ephemeral public point, and the seed is KDFe over the point it shares
+// with the key.
+pub(crate) fn secret_encrypt(
+    tpm: &TpmInstance,
+    public: &TpmtPublic,
+    label: &[u8],
+    seed: &mut Tpm2bDigest,
+    secret: &mut Tpm2bEncryptedSecret,
+) -> Result<(), TpmError> {
+    let hash_alg = public.name_alg;
+    let mut rand = PlatformRandom {
+        get_random: tpm.platform.get_random,
+    };
+    seed.size = hash_alg.digest_size() as u16;
+
+    match (&public.parameters, &public.unique) {
+        (TpmuPublicParms::Rsa(parms), TpmuPublicId::Rsa(n)) => {
+            (tpm.platform.get_random)(&mut seed.buffer[..seed.size as usize]);
+            let scheme = TpmtAsymScheme {
+                scheme: TpmAlgId::Oaep,
+                hash_alg,
+                ..Default::default()
+            };
+            let mut out = Tpm2bPublicKeyRsa::default();
+            rsa::encrypt(
+                &scheme,
+                parms.exponent,
+                n,
+                seed.as_slice(),
+                label,
+                &mut rand,
+                &mut out,
+            )?;
+            secret.size = out.size;
+            secret.buffer[..out.size as usize].copy_from_slice(out.as_slice());
+        }
+        (TpmuPublicParms::Ecc(parms), TpmuPublicId::Ecc(q)) => {
+            let mut z = TpmsEccPoint::default();
+            let mut ephemeral = TpmsEccPoint::default();
+            ecc::ecdh_keygen(parms.curve_id, q, &mut rand, &mut z, &mut ephemeral)?;
+            kdfe(
+                hash_alg,
+                z.x.as_slice(),
+                label,
+                ephemeral.x.as_slice(),
+                q.x.as_slice(),
+                &mut seed.buffer[..seed.size as usize],
+            )?;
+            secret.size = marshal_ecc_point(&mut secret.buffer, &ephemeral)? as u16;
+        }
+        _ => return Err(TpmError::new(TpmRc::Type)),
+    }
+
+    Ok(())
+}
+
+// Recover the seed `secret_encrypt` shared with a key. A seed longer than
+// a nameAlg digest is a Value error.
+pub(crate) fn secret_decrypt(
+    object: &Object,
+    label: &[u8],
+    secret: &Tpm2bEncryptedSecret,
+) -> Result<Tpm2bDigest, TpmError> {
+    let public = &object.public;
+    let hash_alg = public.name_alg;
+    let mut seed = Tpm2bDigest::default();
+
+    match (
+        &public.parameters,
+        &public.unique,
+        &object.sensitive.sensitive,
+    ) {
+        (TpmuPublicParms::Rsa(parms), TpmuPublicId::Rsa(n), TpmuSensitiveComposite::Rsa(p)) => {
+            let scheme = TpmtAsymScheme {
+                scheme: TpmAlgId::Oaep,
+                hash_alg,
+                ..Default::default()
+            };
+            let mut out = Tpm2bPublicKeyRsa::default();
+            rsa::decrypt(
+                &scheme,
+                parms.exponent,
+                n,
+                p,
+                secret.as_slice(),
+                label,
+                &mut out,
+            )?;
+            if out.size as usize > hash_alg.digest_size() {
+                return Err(TpmError::new(TpmRc::Value));
+            }
+            seed.size = out.size;
+            seed.buffer[..out.size as usize].copy_from_slice(out.as_slice());
+        }
+        (TpmuPublicParms::Ecc(parms), TpmuPublicId::Ecc(q), TpmuSensitiveComposite::Ecc(d)) => {
+            let ephemeral = unmarshal_ecc_point(secret.as_slice(), &mut 0)?;
+            let mut z = TpmsEccPoint::default();
+            ecc::point_multiply(parms.curve_id, d, &ephemeral, &mut z)?;
+            seed.size = hash_alg.digest_size() as u16;
+            kdfe(
+                hash_alg,
+                z.x.as_slice(),
+                label,
+                ephemeral.x.as_slice(),
+                q.x.as_slice(),
+                &mut seed.buffer[..seed.size as usize],
+            )?;
+        }
+        _ => return Err(TpmError::new(TpmRc::Key)),
+    }
+
+    Ok(seed)
+}
@@ -0,0 +1,143 @@
+use crate::crypto::hash::hash;
+use crate::crypto::kdf::{kdfa, OBFUSCATE};
+use crate::hierarchy::Hierarchy;
+use crate::marshal::*;
+use crate::object::*;
+use crate::signature::*;
+use crate::tpm::*;
+use crate::types::*;
+
+// A loaded signing key and the scheme it will sign with.
+pub(crate) type Signer = (Object, TpmtAsymScheme);
+
+impl TpmInstance {
+    // Check that `sign_handle` can sign attestations with `in_scheme`. It's
+    // None for TPM_RH_NULL.
+    pub(crate) fn attest_signer(
+        &self,
+        sign_handle: TpmHandle,
+        in_scheme: &TpmtAsymScheme,
+    ) -> Result<Option<Signer>, TpmError> {
+        if TpmRh::from(sign_handle) == TpmRh::Null {
+            return Ok(None);
+        }
+
+        let object = loaded_object(self, sign_handle)?;
+        let public = &object.public;
+        if !public.has_attributes(TPMA_OBJECT_SIGN_ENCRYPT)
+            || public.has_attributes(TPMA_OBJECT_X509_SIGN)
+        {
+            return Err(TpmError::handle(TpmRc::Key, 1));
+        }
+        // inScheme is parameter 2 of all the attestation commands.
+        let scheme = sign_scheme(public, in_scheme).map_err(|e| e.with_parameter(2))?;
+
+        Ok(Some((object, scheme)))
+    }
+
+    // Keys outside the endorsement and platform hierarchies could otherwise
+    // be used to tell that they're on the same TPM, so their attestations
+    // have the counters and firmwareVersion offset by
+    //
+    //   KDFa(shProof, "OBFUSCATE", signerName, 128)
+    //
+    // The first 8 bytes are added to firmwareVersion, the next 4 to
+    // resetCount and the last 4 to restartCount.
+    fn obfuscate(&self, signer: &Object, attest: &mut TpmsAttest) -> Result<(), TpmError> {
+        let mut obfuscation = [0u8; 16];
+        kdfa(
+            CONTEXT_INTEGRITY_HASH_ALG,
+            &self.hierarchy.sh_proof,
+            OBFUSCATE,
+            signer.name.as_slice(),
+            &[],
+            &mut obfuscation,
+        )?;
+
+        let word = |i: usize| u32::from_be_bytes(obfuscation[i..i + 4].try_into().unwrap());
+        let firmware = u64::from_be_bytes(obfuscation[..8].try_into().unwrap());
+        attest.firmware_version = attest.firmware_version.wrapping_add(firmware);
+        let clock_info = &mut attest.clock_info;
+        clock_info.reset_count = clock_info.reset_count.wrapping_add(word(8));
+        clock_info.restart_count = clock_info.restart_count.wrapping_add(word(12));
+
+        Ok(())
+    }
+
+    // Build the TPMS_ATTEST for `attested` and sign it, or leave it unsigned
+    // if there's no signer.
+    pub(crate) fn sign_attest(
+        &mut self,
+        signer: Option<Signer>,
+        extra_data: &Tpm2bData,
+        attested: TpmuAttest,
+    ) -> Result<(Tpm2bAttest, TpmtSignature), TpmError> {
+        let mut attest = TpmsAttest {
+            qualified_signer: match &signer {
+                Some((object, _)) => object.qualified_name,
+                None => Tpm2bName::default(),
+            },
+            extra_data: *extra_data,
+            clock_info: self.clock_info(),
+            firmware_version: FIRMWARE_VERSION,
+            attested,
+        };
+        if let Some((object, _)) = &signer {
+            if !matches!(
+                object.hierarchy,
+                Hierarchy::Endorsement | Hierarchy::Platform
+            ) {
+                self.obfuscate(object, &mut attest)?;
+            }
+        }
+
+        let mut certify_info = Tpm2bAttest::default();
+        certify_info.size = marshal_tpms_attest(&mut certify_info.buffer, &attest)? as u16;
+
+        let signature = match &signer {
+            Some((object, scheme)) => {
+                let digest = hash(scheme.hash_alg, &[certify_info.as_slice()])?;
+                sign_digest(self, object, scheme, digest.as_slice())?
+            }
+            None => TpmtSignature::Null,
+        };
+
+        Ok((certify_info, signature))
+    }
+
+    // Build the TPMS_ATTEST for `attested` and sign it with `sign_handle`,
+    // or leave it unsigned if that's TPM_RH_NULL.
+    pub(crate) fn attest(
+        &mut self,
+        sign_handle: TpmHandle,
+        in_scheme: &TpmtAsymScheme,
+        extra_data: &Tpm2bData,
+        attested: TpmuAttest,
+    ) -> Result<(Tpm2bAttest, TpmtSignature), TpmError> {
+        let signer = self.attest_signer(sign_handle, in_scheme)?;
+        self.sign_attest(signer, extra_data, attested)
+    }
+}
+
+// Sign a digest of the selected PCRs, made with the signing scheme's hash.
+// Without a signer there's no scheme, so the quote is unsigned and the
+// digest is empty.
+pub fn tpm2_quote(tpm: &mut TpmInstance, args: &QuoteArgs) -> Result<QuoteResponse, TpmError> {
+    let signer = tpm.attest_signer(args.sign_handle, &args.in_scheme)?;
+    let hash_alg = match &signer {
+        Some((_, scheme)) => scheme.hash_alg,
+        None => TpmAlgId::Null,
+    };
+
+    let mut pcr_select = args.pcr_select;
+    let pcr_digest = tpm.pcr_digest(hash_alg, &mut pcr_select)?;
+    let info = TpmsQuoteInfo {
+        pcr_select,
+        pcr_digest,
+    };
+
+    let (quoted, signature) =
+        tpm.sign_attest(signer, &args.qualifying_data, TpmuAttest::Quote(info))?;
+
+    Ok(QuoteResponse { quoted, signature })
+}
@@ -0,0 +1,348 @@
+use crate::command::*;
+use crate::crypto::hash::*;
+use crate::marshal::*;
+use crate::nv_index::*;
+use crate::session::*;
+use crate::tpm::*;
+use crate::types::*;
+
+// Auth values compare equal regardless of trailing zeros.
+pub(crate) fn trim_trailing_zeros(val: &[u8]) -> &[u8] {
+    let len = val.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    &val[..len]
+}
+
+// Compare without short-circuiting so timing doesn't leak how much of the
+// value was correct.
+pub(crate) fn auth_equal(a: &[u8], b: &[u8]) -> bool {
+    let a = trim_trailing_zeros(a);
+    let b = trim_trailing_zeros(b);
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+impl TpmInstance {
+    pub(crate) fn entity_auth_value(&self, handle: TpmHandle) -> Result<Tpm2bAuth, TpmError> {
+        if let Some(object) = self.object_get(handle) {
+            return Ok(object.sensitive.auth_value);
+        }
+        if let Some(sequence) = self.sequence_get(handle) {
+            return Ok(sequence.auth_value);
+        }
+        if let Some(index) = self.nv_index_get(handle) {
+            return Ok(index.auth_value);
+        }
+        // There's no TPM2_PCR_SetAuthValue, so PCRs keep the empty
+        // authValue.
+        if TpmHt::from(handle) == TpmHt::Pcr {
+            return Ok(Tpm2bAuth::default());
+        }
+
+        let h = &self.hierarchy;
+        match TpmRh::from(handle) {
+            TpmRh::Owner => Ok(h.owner_auth),
+            TpmRh::Endorsement => Ok(h.endorsement_auth),
+            TpmRh::Platform => Ok(h.platform_auth),
+            TpmRh::Lockout => Ok(h.lockout_auth),
+            TpmRh::Null => Ok(Tpm2bAuth::default()),
+            _ => Err(TpmError::new(TpmRc::Handle)),
+        }
+    }
+
+    // The Name used for a handle in cpHash and nameHash. Sequence objects
+    // have an empty Name, and anything that isn't an object or NV index is
+    // named by its handle.
+    pub(crate) fn entity_name(&self, handle: TpmHandle) -> Result<Tpm2bName, TpmError> {
+        if let Some(object) = self.object_get(handle) {
+            return Ok(object.name);
+        }
+        if self.sequence_get(handle).is_some() {
+            return Ok(Tpm2bName::default());
+        }
+        if let Some(index) = self.nv_index_get(handle) {
+            return nv_name(&index.public);
+        }
+
+        let mut name = Tpm2bName::default();
+        name.size = marshal_u32(&mut name.buffer, handle)? as u16;
+        Ok(name)
+    }
+
+    // The authPolicy a policy session has to match, along with the hash
+    // algorithm it was computed with. Entities without a policy have a NULL
+    // hash algorithm, which no session matches.
+    fn entity_auth_policy(&self, handle: TpmHandle) -> TpmtHa {
+        if let Some(object) = self.object_get(handle) {
+            return TpmtHa {
+                hash_alg: object.public.name_alg,
+                digest: object.public.auth_policy,
+            };
+        }
+        if let Some(index) = self.nv_index_get(handle) {
+            return TpmtHa {
+                hash_alg: index.public.name_alg,
+                digest: index.public.auth_policy,
+            };
+        }
+
+        let h = &self.hierarchy;
+        match TpmRh::from(handle) {
+            TpmRh::Owner => h.owner_policy,
+            TpmRh::Endorsement => h.endorsement_policy,
+            TpmRh::Platform => h.platform_policy,
+            TpmRh::Lockout => h.lockout_policy,
+            _ => TpmtHa::default(),
+        }
+    }
+
+    // A public-only object has no authValue to check against.
+    fn has_auth_value(&self, handle: TpmHandle) -> bool {
+        match self.object_get(handle) {
+            Some(object) => object.sensitive.sensitive_type() != TpmAlgId::Unknown,
+            None => true,
+        }
+    }
+
+    // Check that `role` can be authorized with the authValue, by password
+    // or HMAC session.
+    fn check_auth_value_role(&self, handle: TpmHandle, role: AuthRole) -> Result<(), TpmError> {
+        // Without userWithAuth an object's user role can only be reached
+        // with a policy session, and likewise for adminWithPolicy and the
+        // admin role. The DUP role always needs a policy session.
+        if let Some(object) = self.object_get(handle) {
+            let with_auth = match role {
+                AuthRole::User => object.public.has_attributes(TPMA_OBJECT_USER_WITH_AUTH),
+                AuthRole::Admin => !object.public.has_attributes(TPMA_OBJECT_ADMIN_WITH_POLICY),
+                AuthRole::Dup => false,
+                AuthRole::None => true,
+            };
+            if !with_auth {
+                return Err(TpmError::new(TpmRc::AuthUnavailable));
+            }
+        }
+
+        // An NV index's admin role always needs a policy session.
+        if TpmHt::from(handle) == TpmHt::NvIndex && role == AuthRole::Admin {
+            return Err(TpmError::new(TpmRc::AuthUnavailable));
+        }
+
+        match self.has_auth_value(handle) {
+            true => Ok(()),
+            false => Err(TpmError::new(TpmRc::AuthUnavailable)),
+        }
+    }
+
+    // Run `check` against the entity's authValue. PIN indices limit how
+    // often their authValue can be tried, and count each attempt. Failures
+    // also count towards dictionary attack lockout.
+    fn check_auth_value<F>(&mut self, handle: TpmHandle, check: F) -> Result<(), TpmError>
+    where
+        F: FnOnce(&[u8]) -> Result<bool, TpmError>,
+    {
+        self.da_check(handle)?;
+        if TpmHt::from(handle) == TpmHt::NvIndex {
+            self.nv_index_pin_available(handle)?;
+        }
+
+        let auth_value = self.entity_auth_value(handle)?;
+        let success = check(auth_value.as_slice())?;
+        self.nv_index_pin_attempt(handle, success)?;
+        if !success {
+            self.da_failure(handle)?;
+            return Err(TpmError::new(TpmRc::AuthFail));
+        }
+
+        Ok(())
+    }
+
+    // An HMAC session bound to the entity it authorizes already has the
+    // authValue in its session key, so it's left out of the HMAC.
+    fn session_bound_to(&self, session: &Session, handle: TpmHandle) -> Result<bool, TpmError> {
+        if session.bound_entity.is_empty() {
+            return Ok(false);
+        }
+
+        let name = self.entity_name(handle)?;
+        let auth_value = self.entity_auth_value(handle)?;
+        let bound = hash(
+            session.auth_hash,
+            &[name.as_slice(), trim_trailing_zeros(auth_value.as_slice())],
+        )?;
+        Ok(bound.as_slice() == session.bound_entity.as_slice())
+    }
+
+    // A policy session authorizes an entity once its policyDigest matches
+    // the entity's authPolicy, and the command is the one the policy
+    // asserted, if it asserted one. The admin and DUP roles need the
+    // command to have been asserted.
+    fn check_policy(
+        &self,
+        session: &Session,
+        handle: TpmHandle,
+        role: AuthRole,
+        command: &AuthCommand,
+        cp_hash: &Tpm2bDigest,
+    ) -> Result<(), TpmError> {
+        let auth_policy = self.entity_auth_policy(handle);
+        if auth_policy.hash_alg != session.auth_hash
+            || auth_policy.digest.as_slice() != session.policy.digest.as_slice()
+        {
+            return Err(TpmError::new(TpmRc::PolicyFail));
+        }
+
+        match session.policy.command_code {
+            Some(code) if code != command.command_code => {
+                return Err(TpmError::new(TpmRc::PolicyCc));
+            }
+            None if matches!(role, AuthRole::Admin | AuthRole::Dup) => {
+                return Err(TpmError::new(TpmRc::PolicyFail));
+            }
+            _ => (),
+        }
+
+        let policy = &session.policy;
+        if policy.timeout != 0 && self.clock.clock >= policy.timeout {
+            return Err(TpmError::new(TpmRc::Expired));
+        }
+        if matches!(policy.pcr_update_counter, Some(c) if c != self.pcr.update_counter) {
+            return Err(TpmError::new(TpmRc::PcrChanged));
+        }
+        if !policy.cp_hash.is_empty() && policy.cp_hash.as_slice() != cp_hash.as_slice() {
+            return Err(TpmError::new(TpmRc::PolicyFail));
+        }
+        if !policy.name_hash.is_empty() {
+            let name_hash = name_hash(session.auth_hash, command.names)?;
+            if policy.name_hash.as_slice() != name_hash.as_slice() {
+                return Err(TpmError::new(TpmRc::PolicyFail));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Check the session authorizing `handle` in `role`. Returns whether the
+    // entity's authValue goes into the response HMAC.
+    pub(crate) fn authorize(
+        &mut self,
+        handle: TpmHandle,
+        role: AuthRole,
+        auth: &TpmsAuthCommand,
+        command: &AuthCommand,
+    ) -> Result<bool, TpmError> {
+        match TpmHt::from(auth.session_handle) {
+            TpmHt::HmacSession | TpmHt::PolicySession => (),
+            _ if TpmRh::from(auth.session_handle) == TpmRh::Password => {
+                self.authorize_password(handle, role, auth)?;
+                return Ok(false);
+            }
+            _ => return Err(TpmError::new(TpmRc::Value)),
+        }
+
+        let session = match self.session_get(auth.session_handle) {
+            Some(session) => *session,
+            None => return Err(TpmError::new(TpmRc::ReferenceS0)),
+        };
+
+        // Sessions only authorize; they can't encrypt parameters or audit.
+        if auth.session_attributes & !TPMA_SESSION_CONTINUE_SESSION != 0 {
+            return Err(TpmError::new(TpmRc::Attributes));
+        }
+        let digest_size = session.auth_hash.digest_size();
+        if auth.nonce.size < 16 || auth.nonce.size as usize > digest_size {
+            return Err(TpmError::new(TpmRc::Nonce));
+        }
+
+        let cp_hash = command_hash(
+            session.auth_hash,
+            command.command_code,
+            command.names,
+            command.parameters,
+        )?;
+
+        let with_auth_value = match session.session_type {
+            TpmSe::Hmac => {
+                self.check_auth_value_role(handle, role)?;
+                !self.session_bound_to(&session, handle)?
+            }
+            // A trial session only computes a policyDigest.
+            TpmSe::Trial => return Err(TpmError::new(TpmRc::Attributes)),
+            _ => {
+                self.check_policy(&session, handle, role, command, &cp_hash)?;
+
+                let policy = &session.policy;
+                let needs_auth_value = policy.password_needed || policy.auth_value_needed;
+                if needs_auth_value && !self.has_auth_value(handle) {
+                    return Err(TpmError::new(TpmRc::AuthUnavailable));
+                }
+                // TPM2_PolicyPassword sends the authValue in place of the HMAC.
+                if policy.password_needed {
+                    self.check_auth_value(handle, |auth_value| {
+                        Ok(auth_equal(auth_value, auth.hmac.as_slice()))
+                    })?;
+                    return Ok(false);
+                }
+                policy.auth_value_needed
+            }
+        };
+
+        // A session without a key may leave the HMAC empty.
+        let verify = |auth_value: &[u8]| -> Result<bool, TpmError> {
+            if session.session_key.is_empty()
+                && trim_trailing_zeros(auth_value).is_empty()
+                && auth.hmac.is_empty()
+            {
+                return Ok(true);
+            }
+
+            let expected = session_hmac(
+                &session,
+                auth_value,
+                &cp_hash,
+                auth.nonce.as_slice(),
+                session.nonce_tpm.as_slice(),
+                auth.session_attributes,
+            )?;
+            Ok(expected.size == auth.hmac.size
+                && auth_equal(expected.as_slice(), auth.hmac.as_slice()))
+        };
+
+        match with_auth_value {
+            true => self.check_auth_value(handle, verify)?,
+            false if !verify(&[])? => return Err(TpmError::new(TpmRc::AuthFail)),
+            false => (),
+        }
+
+        Ok(with_auth_value)
+    }
+
+    // Whether the current command authorized `handle` with a policy
+    // session.
+    pub(crate) fn authorized_by_policy(&self, handle: TpmHandle) -> bool {
+        self.policy_authorized.contains(&Some(handle))
+    }
+
+    fn authorize_password(
+        &mut self,
+        handle: TpmHandle,
+        role: AuthRole,
+        auth: &TpmsAuthCommand,
+    ) -> Result<(), TpmError> {
+        if !auth.nonce.is_empty() {
+            return Err(TpmError::new(TpmRc::Nonce));
+        }
+        if auth.session_attributes & !TPMA_SESSION_CONTINUE_SESSION != 0 {
+            return Err(TpmError::new(TpmRc::Attributes));
+        }
+
+        self.check_auth_value_role(handle, role)?;
+        self.check_auth_value(handle, |auth_value| {
+            Ok(auth_equal(auth_value, auth.hmac.as_slice()))
+        })
+    }
+}
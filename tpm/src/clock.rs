@@ -0,0 +1,95 @@
+use crate::nv::*;
+use crate::tpm::*;
+use crate::types::*;
+
+// Clock is written to NV at least this often, in milliseconds. After a
+// power loss it carries on from the last value written plus this, so it
+// never goes backwards.
+const CLOCK_NV_INTERVAL: u64 = 1 << 22;
+
+#[derive(Default)]
+pub struct ClockState {
+    // Milliseconds the TPM has been powered over its life
+    pub(crate) clock: u64,
+
+    // Milliseconds since power on
+    pub(crate) time: u64,
+
+    // The platform time Clock and Time were last advanced to
+    pub(crate) last_time: u64,
+
+    // Persistent. Clock as last written to NV.
+    pub(crate) nv_clock: u64,
+
+    // Whether Clock is sure to be no less than any value reported before.
+    // A power loss can lose up to CLOCK_NV_INTERVAL of it.
+    pub(crate) safe: bool,
+}
+
+impl TpmInstance {
+    pub(crate) fn clock_restore(&mut self) -> Result<(), TpmError> {
+        let mut clock = [0u8; 8];
+        self.nv_read(NV_CLOCK, &mut clock)?;
+        self.clock.nv_clock = u64::from_be_bytes(clock);
+        self.clock.clock = self.clock.nv_clock;
+
+        Ok(())
+    }
+
+    // After an orderly shutdown NV has Clock as it was. Otherwise it can be
+    // behind what was reported, so Clock skips ahead past anything it could
+    // have been.
+    pub(crate) fn clock_startup(&mut self, orderly: bool) -> Result<(), TpmError> {
+        if orderly {
+            self.clock.safe = true;
+            return Ok(());
+        }
+
+        self.clock.clock = self.clock.nv_clock + CLOCK_NV_INTERVAL;
+        self.clock_store()?;
+        self.clock.safe = false;
+
+        Ok(())
+    }
+
+    // Advance Clock and Time to the platform's time. Called before every
+    // command.
+    pub(crate) fn clock_update(&mut self) {
+        let now = (self.platform.get_time)();
+        let elapsed = now.saturating_sub(self.clock.last_time);
+        self.clock.last_time = now;
+        self.clock.time += elapsed;
+        self.clock.clock += elapsed;
+
+        if self.clock.clock >= self.clock.nv_clock + CLOCK_NV_INTERVAL {
+            // If NV can't be written now, the next command tries again.
+            let _ = self.clock_store();
+        }
+    }
+
+    pub(crate) fn clock_store(&mut self) -> Result<(), TpmError> {
+        self.nv_write(NV_CLOCK, &self.clock.clock.to_be_bytes())?;
+        self.clock.nv_clock = self.clock.clock;
+        self.clock.safe = true;
+
+        Ok(())
+    }
+
+    pub(crate) fn clock_info(&self) -> TpmsClockInfo {
+        TpmsClockInfo {
+            clock: self.clock.clock,
+            reset_count: self.context.total_reset_count as u32,
+            restart_count: self.context.restart_count,
+            safe: self.clock.safe,
+        }
+    }
+}
+
+pub fn tpm2_read_clock(tpm: &mut TpmInstance) -> Result<ReadClockResponse, TpmError> {
+    Ok(ReadClockResponse {
+        current_time: TpmsTimeInfo {
+            time: tpm.clock.time,
+            clock_info: tpm.clock_info(),
+        },
+    })
+}
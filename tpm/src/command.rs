@@ -0,0 +1,738 @@
+use crate::marshal::*;
+use crate::tpm::*;
+use crate::types::*;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AuthRole {
+    None,
+    User,
+    Admin,
+    Dup,
+}
+
+// The interface types used for command handles. Each one restricts which
+// handle values a command will accept in that position.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum HandleKind {
+    // TPMI_RH_PLATFORM
+    Platform,
+    // TPMI_RH_CLEAR
+    Clear,
+    // TPMI_RH_LOCKOUT
+    Lockout,
+    // TPMI_RH_HIERARCHY
+    Hierarchy,
+    // TPMI_RH_HIERARCHY+
+    HierarchyNull,
+    // TPMI_RH_HIERARCHY_AUTH and TPMI_RH_HIERARCHY_POLICY
+    HierarchyAuth,
+    // TPMI_RH_PROVISION
+    Provision,
+    // TPMI_RH_NV_AUTH
+    NvAuth,
+    // TPMI_RH_NV_INDEX
+    NvIndex,
+    // TPMI_DH_OBJECT
+    Object,
+    // TPMI_DH_OBJECT+
+    ObjectNull,
+    // TPMI_DH_PARENT+
+    Parent,
+    // TPMI_DH_CONTEXT
+    Context,
+    // TPMI_DH_PCR+
+    Pcr,
+    // TPMI_DH_ENTITY+
+    Entity,
+    // TPMI_SH_POLICY
+    PolicySession,
+}
+
+impl HandleKind {
+    fn accepts(&self, handle: TpmHandle) -> bool {
+        let rh = TpmRh::from(handle);
+        match self {
+            HandleKind::Platform => rh == TpmRh::Platform,
+            HandleKind::Clear => matches!(rh, TpmRh::Lockout | TpmRh::Platform),
+            HandleKind::Lockout => rh == TpmRh::Lockout,
+            HandleKind::Hierarchy => {
+                matches!(rh, TpmRh::Owner | TpmRh::Endorsement | TpmRh::Platform)
+            }
+            HandleKind::HierarchyNull => matches!(
+                rh,
+                TpmRh::Owner | TpmRh::Endorsement | TpmRh::Platform | TpmRh::Null
+            ),
+            HandleKind::HierarchyAuth => matches!(
+                rh,
+                TpmRh::Owner | TpmRh::Endorsement | TpmRh::Platform | TpmRh::Lockout
+            ),
+            HandleKind::Provision => matches!(rh, TpmRh::Owner | TpmRh::Platform),
+            HandleKind::NvAuth => {
+                HandleKind::Provision.accepts(handle) || HandleKind::NvIndex.accepts(handle)
+            }
+            HandleKind::NvIndex => TpmHt::from(handle) == TpmHt::NvIndex,
+            HandleKind::Object => {
+                matches!(TpmHt::from(handle), TpmHt::Transient | TpmHt::Persistent)
+            }
+            HandleKind::ObjectNull => HandleKind::Object.accepts(handle) || rh == TpmRh::Null,
+            HandleKind::Context => matches!(
+                TpmHt::from(handle),
+                TpmHt::Transient | TpmHt::HmacSession | TpmHt::PolicySession
+            ),
+            HandleKind::Parent => {
+                HandleKind::Object.accepts(handle) || HandleKind::HierarchyNull.accepts(handle)
+            }
+            HandleKind::Pcr => TpmHt::from(handle) == TpmHt::Pcr || rh == TpmRh::Null,
+            HandleKind::Entity => {
+                HandleKind::HierarchyAuth.accepts(handle)
+                    || HandleKind::ObjectNull.accepts(handle)
+                    || HandleKind::NvIndex.accepts(handle)
+                    || TpmHt::from(handle) == TpmHt::Pcr
+            }
+            HandleKind::PolicySession => TpmHt::from(handle) == TpmHt::PolicySession,
+        }
+    }
+}
+
+pub struct HandleSpec {
+    pub kind: HandleKind,
+    pub auth: AuthRole,
+}
+
+pub struct CommandAttributes {
+    pub handles: &'static [HandleSpec],
+    // The response starts with a handle, which goes ahead of parameterSize.
+    pub response_handle: bool,
+}
+
+// What authorization checks need to know about the command: the Names of
+// its handles and its parameters, for cpHash and nameHash.
+pub struct AuthCommand<'a> {
+    pub command_code: TpmCommandCode,
+    pub names: &'a [Tpm2bName],
+    pub parameters: &'a [u8],
+}
+
+const NO_HANDLES: CommandAttributes = CommandAttributes {
+    handles: &[],
+    response_handle: false,
+};
+
+pub fn command_attributes(command_code: TpmCommandCode) -> CommandAttributes {
+    use AuthRole::*;
+    use HandleKind::*;
+
+    match command_code {
+        TpmCommandCode::HierarchyControl => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Hierarchy,
+                auth: User,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::SetPrimaryPolicy | TpmCommandCode::HierarchyChangeAuth => {
+            CommandAttributes {
+                handles: &[HandleSpec {
+                    kind: HierarchyAuth,
+                    auth: User,
+                }],
+                response_handle: false,
+            }
+        }
+        TpmCommandCode::ChangePps | TpmCommandCode::ChangeEps => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Platform,
+                auth: User,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::Clear | TpmCommandCode::ClearControl => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Clear,
+                auth: User,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::DictionaryAttackLockReset | TpmCommandCode::DictionaryAttackParameters => {
+            CommandAttributes {
+                handles: &[HandleSpec {
+                    kind: Lockout,
+                    auth: User,
+                }],
+                response_handle: false,
+            }
+        }
+        TpmCommandCode::CreatePrimary => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: HierarchyNull,
+                auth: User,
+            }],
+            response_handle: true,
+        },
+        TpmCommandCode::Create => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Object,
+                auth: User,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::Load => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Object,
+                auth: User,
+            }],
+            response_handle: true,
+        },
+        TpmCommandCode::Unseal => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Object,
+                auth: User,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::RsaEncrypt => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Object,
+                auth: None,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::RsaDecrypt => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Object,
+                auth: User,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::EcdhKeyGen => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Object,
+                auth: None,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::VerifySignature => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Object,
+                auth: None,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::Quote => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: ObjectNull,
+                auth: User,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::Sign
+        | TpmCommandCode::Commit
+        | TpmCommandCode::EncryptDecrypt
+        | TpmCommandCode::EncryptDecrypt2
+        | TpmCommandCode::Mac
+        | TpmCommandCode::SequenceUpdate
+        | TpmCommandCode::SequenceComplete => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Object,
+                auth: User,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::MacStart => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Object,
+                auth: User,
+            }],
+            response_handle: true,
+        },
+        TpmCommandCode::EcdhZGen | TpmCommandCode::ZGen2Phase => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Object,
+                auth: User,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::MakeCredential => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Object,
+                auth: None,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::ActivateCredential => CommandAttributes {
+            handles: &[
+                HandleSpec {
+                    kind: Object,
+                    auth: Admin,
+                },
+                HandleSpec {
+                    kind: Object,
+                    auth: User,
+                },
+            ],
+            response_handle: false,
+        },
+        TpmCommandCode::Duplicate => CommandAttributes {
+            handles: &[
+                HandleSpec {
+                    kind: Object,
+                    auth: Dup,
+                },
+                HandleSpec {
+                    kind: ObjectNull,
+                    auth: None,
+                },
+            ],
+            response_handle: false,
+        },
+        TpmCommandCode::Import => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Object,
+                auth: User,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::Rewrap => CommandAttributes {
+            handles: &[
+                HandleSpec {
+                    kind: ObjectNull,
+                    auth: User,
+                },
+                HandleSpec {
+                    kind: ObjectNull,
+                    auth: None,
+                },
+            ],
+            response_handle: false,
+        },
+        TpmCommandCode::ReadPublic => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Object,
+                auth: None,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::ObjectChangeAuth => CommandAttributes {
+            handles: &[
+                HandleSpec {
+                    kind: Object,
+                    auth: Admin,
+                },
+                HandleSpec {
+                    kind: Object,
+                    auth: None,
+                },
+            ],
+            response_handle: false,
+        },
+        TpmCommandCode::EvictControl => CommandAttributes {
+            handles: &[
+                HandleSpec {
+                    kind: Provision,
+                    auth: User,
+                },
+                HandleSpec {
+                    kind: Object,
+                    auth: None,
+                },
+            ],
+            response_handle: false,
+        },
+        TpmCommandCode::NvDefineSpace => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Provision,
+                auth: User,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::NvUndefineSpace => CommandAttributes {
+            handles: &[
+                HandleSpec {
+                    kind: Provision,
+                    auth: User,
+                },
+                HandleSpec {
+                    kind: NvIndex,
+                    auth: None,
+                },
+            ],
+            response_handle: false,
+        },
+        TpmCommandCode::NvUndefineSpaceSpecial => CommandAttributes {
+            handles: &[
+                HandleSpec {
+                    kind: NvIndex,
+                    auth: Admin,
+                },
+                HandleSpec {
+                    kind: Platform,
+                    auth: User,
+                },
+            ],
+            response_handle: false,
+        },
+        TpmCommandCode::NvGlobalWriteLock => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Provision,
+                auth: User,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::NvChangeAuth => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: NvIndex,
+                auth: Admin,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::NvCertify => CommandAttributes {
+            handles: &[
+                HandleSpec {
+                    kind: ObjectNull,
+                    auth: User,
+                },
+                HandleSpec {
+                    kind: NvAuth,
+                    auth: User,
+                },
+                HandleSpec {
+                    kind: NvIndex,
+                    auth: None,
+                },
+            ],
+            response_handle: false,
+        },
+        TpmCommandCode::NvReadPublic => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: NvIndex,
+                auth: None,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::NvWrite
+        | TpmCommandCode::NvRead
+        | TpmCommandCode::NvIncrement
+        | TpmCommandCode::NvSetBits
+        | TpmCommandCode::NvExtend
+        | TpmCommandCode::NvWriteLock
+        | TpmCommandCode::NvReadLock => CommandAttributes {
+            handles: &[
+                HandleSpec {
+                    kind: NvAuth,
+                    auth: User,
+                },
+                HandleSpec {
+                    kind: NvIndex,
+                    auth: None,
+                },
+            ],
+            response_handle: false,
+        },
+        TpmCommandCode::ContextSave => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Context,
+                auth: None,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::ContextLoad => CommandAttributes {
+            handles: &[],
+            response_handle: true,
+        },
+        TpmCommandCode::LoadExternal => CommandAttributes {
+            handles: &[],
+            response_handle: true,
+        },
+        TpmCommandCode::CreateLoaded => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Parent,
+                auth: User,
+            }],
+            response_handle: true,
+        },
+        TpmCommandCode::PcrExtend => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: Pcr,
+                auth: User,
+            }],
+            response_handle: false,
+        },
+        TpmCommandCode::StartAuthSession => CommandAttributes {
+            handles: &[
+                HandleSpec {
+                    kind: ObjectNull,
+                    auth: None,
+                },
+                HandleSpec {
+                    kind: Entity,
+                    auth: None,
+                },
+            ],
+            response_handle: true,
+        },
+        TpmCommandCode::PolicySecret => CommandAttributes {
+            handles: &[
+                HandleSpec {
+                    kind: Entity,
+                    auth: User,
+                },
+                HandleSpec {
+                    kind: PolicySession,
+                    auth: None,
+                },
+            ],
+            response_handle: false,
+        },
+        TpmCommandCode::PolicyRestart
+        | TpmCommandCode::PolicyGetDigest
+        | TpmCommandCode::PolicyCommandCode
+        | TpmCommandCode::PolicyPassword
+        | TpmCommandCode::PolicyAuthValue
+        | TpmCommandCode::PolicyOr
+        | TpmCommandCode::PolicyCpHash
+        | TpmCommandCode::PolicyNameHash
+        | TpmCommandCode::PolicyPcr
+        | TpmCommandCode::PolicyDuplicationSelect => CommandAttributes {
+            handles: &[HandleSpec {
+                kind: PolicySession,
+                auth: None,
+            }],
+            response_handle: false,
+        },
+        _ => NO_HANDLES,
+    }
+}
+
+impl TpmInstance {
+    // Check that a handle refers to something the TPM can use right now.
+    // Persistent objects are read in from NV for the command to use.
+    fn check_handle_loaded(&mut self, handle: TpmHandle) -> Result<(), TpmError> {
+        match TpmHt::from(handle) {
+            TpmHt::Permanent => {
+                let h = &self.hierarchy;
+                match TpmRh::from(handle) {
+                    TpmRh::Owner if !h.sh_enable => Err(TpmError::new(TpmRc::Hierarchy)),
+                    TpmRh::Endorsement if !h.eh_enable => Err(TpmError::new(TpmRc::Hierarchy)),
+                    TpmRh::Platform if !h.ph_enable => Err(TpmError::new(TpmRc::Hierarchy)),
+                    TpmRh::Unknown => Err(TpmError::new(TpmRc::Value)),
+                    _ => Ok(()),
+                }
+            }
+            TpmHt::Transient if !self.transient_loaded(handle) => {
+                Err(TpmError::new(TpmRc::ReferenceH0))
+            }
+            TpmHt::Transient => Ok(()),
+            TpmHt::Persistent => self.persistent_load(handle),
+            TpmHt::NvIndex => self.nv_index_check(handle),
+            TpmHt::HmacSession | TpmHt::PolicySession if self.session_get(handle).is_none() => {
+                Err(TpmError::new(TpmRc::ReferenceH0))
+            }
+            TpmHt::HmacSession | TpmHt::PolicySession => Ok(()),
+            TpmHt::Pcr if handle as usize >= IMPLEMENTATION_PCR => Err(TpmError::new(TpmRc::Value)),
+            TpmHt::Pcr => Ok(()),
+            _ => Err(TpmError::new(TpmRc::Value)),
+        }
+    }
+
+    // Run a complete command. The response parameters (and, for commands
+    // with sessions, the parameterSize and authorization area) are written
+    // to `response_buffer`. Returns the response tag and the number of
+    // bytes written.
+    pub(crate) fn execute(
+        &mut self,
+        command: &CommandHeader,
+        request: &[u8],
+        response_buffer: &mut [u8],
+    ) -> Result<(TpmCommandTag, usize), TpmError> {
+        let result = self.execute_command(command, request, response_buffer);
+
+        // Startup and Shutdown look after the orderly state themselves.
+        let startup = matches!(
+            command.command_code,
+            TpmCommandCode::Startup | TpmCommandCode::Shutdown
+        );
+        if self.started && !startup {
+            self.shutdown_check()?;
+        }
+
+        result
+    }
+
+    fn execute_command(
+        &mut self,
+        command: &CommandHeader,
+        request: &[u8],
+        response_buffer: &mut [u8],
+    ) -> Result<(TpmCommandTag, usize), TpmError> {
+        if command.size as usize != request.len() {
+            return Err(TpmError::new(TpmRc::CommandSize));
+        }
+
+        if !self.started && !matches!(command.command_code, TpmCommandCode::Startup) {
+            return Err(TpmError::new(TpmRc::Initialize));
+        }
+
+        let attributes = command_attributes(command.command_code);
+        let mut offset = COMMAND_HDR_SIZE;
+
+        self.persistent_unload();
+        self.nv_command_start();
+        self.clock_update();
+        self.da_self_heal();
+        self.policy_authorized = [None; MAX_SESSION_NUM];
+
+        let mut handles = [0 as TpmHandle; MAX_HANDLE_NUM];
+        for (i, spec) in attributes.handles.iter().enumerate() {
+            let n = i as u32 + 1;
+            let handle = unmarshal_handle(request, &mut offset).map_err(|e| e.with_handle(n))?;
+
+            if !spec.kind.accepts(handle) {
+                return Err(TpmError::handle(TpmRc::Value, n));
+            }
+            self.check_handle_loaded(handle)
+                .map_err(|e| e.with_handle(n))?;
+
+            handles[i] = handle;
+        }
+
+        let mut sessions = [TpmsAuthCommand::default(); MAX_SESSION_NUM];
+        let session_count = match command.tag {
+            TpmCommandTag::Sessions => unmarshal_auth_area(request, &mut offset, &mut sessions)?,
+            _ => 0,
+        };
+
+        let auth_count = attributes
+            .handles
+            .iter()
+            .filter(|spec| spec.auth != AuthRole::None)
+            .count();
+        if session_count < auth_count {
+            return Err(TpmError::new(TpmRc::AuthMissing));
+        }
+
+        // Sessions beyond the ones authorizing handles could only be for
+        // parameter encryption or audit, which aren't supported. A session
+        // can't appear twice.
+        for (i, session) in sessions[..session_count].iter().enumerate() {
+            let n = RcIndex::Session(i as u32 + 1);
+            if TpmRh::from(session.session_handle) == TpmRh::Password {
+                continue;
+            }
+            if i >= auth_count {
+                return Err(TpmError::new(TpmRc::Attributes).with_index(n));
+            }
+            if sessions[..i]
+                .iter()
+                .any(|s| s.session_handle == session.session_handle)
+            {
+                return Err(TpmError::new(TpmRc::Handle).with_index(n));
+            }
+        }
+
+        let mut names = [Tpm2bName::default(); MAX_HANDLE_NUM];
+        for (i, name) in names[..attributes.handles.len()].iter_mut().enumerate() {
+            *name = self.entity_name(handles[i])?;
+        }
+        let auth_command = AuthCommand {
+            command_code: command.command_code,
+            names: &names[..attributes.handles.len()],
+            parameters: &request[offset..],
+        };
+
+        // The entity each session authorized, and whether its authValue
+        // goes into the response HMAC
+        let mut authorized = [(0 as TpmHandle, false); MAX_SESSION_NUM];
+        let auth_handles = attributes
+            .handles
+            .iter()
+            .zip(handles)
+            .filter(|(spec, _)| spec.auth != AuthRole::None);
+        for (i, (spec, handle)) in auth_handles.enumerate() {
+            let with_auth_value = self
+                .authorize(handle, spec.auth, &sessions[i], &auth_command)
+                .map_err(|e| e.with_index(RcIndex::Session(i as u32 + 1)))?;
+            authorized[i] = (handle, with_auth_value);
+            if TpmHt::from(sessions[i].session_handle) == TpmHt::PolicySession {
+                self.policy_authorized[i] = Some(handle);
+            }
+        }
+
+        // Commands with sessions carry a parameterSize after any response
+        // handle, so leave room for it. The handle is moved ahead of it
+        // afterwards.
+        let handle_size = match attributes.response_handle {
+            true => 4,
+            false => 0,
+        };
+        let param_start = match command.tag {
+            TpmCommandTag::Sessions => 4,
+            _ => 0,
+        };
+
+        let mut size = self.dispatch_command(
+            command,
+            &handles[..attributes.handles.len()],
+            &request[offset..],
+            &mut response_buffer[param_start..],
+        )?;
+
+        if let TpmCommandTag::NoSessions = command.tag {
+            return Ok((TpmCommandTag::NoSessions, size));
+        }
+
+        // Move the response handle back in front of parameterSize.
+        response_buffer.copy_within(param_start..param_start + handle_size, 0);
+        let param_size = size - handle_size;
+        marshal_u32(&mut response_buffer[handle_size..], param_size as u32)?;
+        size += param_start;
+
+        let param_offset = handle_size + 4;
+        for (i, session) in sessions[..session_count].iter().enumerate() {
+            let (entity, with_auth_value) = authorized[i];
+            let auth_response = match TpmRh::from(session.session_handle) {
+                TpmRh::Password => TpmsAuthResponse {
+                    session_attributes: TPMA_SESSION_CONTINUE_SESSION,
+                    ..Default::default()
+                },
+                _ => self.session_response(
+                    session,
+                    entity,
+                    with_auth_value,
+                    command.command_code,
+                    &response_buffer[param_offset..param_offset + param_size],
+                )?,
+            };
+            size += marshal_tpms_auth_response(&mut response_buffer[size..], &auth_response)?;
+        }
+
+        Ok((TpmCommandTag::Sessions, size))
+    }
+}
+
+fn unmarshal_auth_area(
+    buffer: &[u8],
+    offset: &mut usize,
+    sessions: &mut [TpmsAuthCommand; MAX_SESSION_NUM],
+) -> Result<usize, TpmError> {
+    let auth_size = unmarshal_u32(buffer, offset)? as usize;
+
+    // Smallest possible session: handle, two empty buffers and attributes
+    if auth_size < 9 || auth_size > buffer.len() - *offset {
+        return Err(TpmError::new(TpmRc::AuthSize));
+    }
+
+    let end = *offset + auth_size;
+    let mut count = 0;
+    while *offset < end {
+        if count == MAX_SESSION_NUM {
+            return Err(TpmError::new(TpmRc::AuthSize));
+        }
+
+        let n = count as u32 + 1;
+        sessions[count] = unmarshal_tpms_auth_command(&buffer[..end], offset)
+            .map_err(|e| e.with_index(RcIndex::Session(n)))?;
+        count += 1;
+    }
+
+    Ok(count)
+}
@@ -0,0 +1,126 @@
+use crate::crypto::ecc;
+use crate::crypto::kdf::KdfRandom;
+use crate::tpm::*;
+use crate::types::*;
+
+pub(crate) const COMMIT_NONCE_SIZE: usize = 32;
+
+// Number of commits that can be outstanding. A commit older than this many
+// later ones can no longer be used.
+const COMMIT_ARRAY_BITS: u64 = 128;
+pub(crate) const COMMIT_ARRAY_SIZE: usize = COMMIT_ARRAY_BITS as usize / 8;
+
+const COMMIT: &[u8] = b"ECDAA Commit";
+
+// The ephemeral keys of EC_Ephemeral and TPM2_Commit aren't kept.
+// Each is derived again from the commit nonce and its counter value when
+// it's used, and a bit in the commit array makes sure that only happens
+// once.
+#[derive(Default)]
+pub struct CommitState {
+    // New on every TPM Reset, so no commit survives one.
+    pub(crate) nonce: [u8; COMMIT_NONCE_SIZE],
+    pub(crate) counter: u64,
+    // Bit (count % COMMIT_ARRAY_BITS) is set while that commit is unused.
+    pub(crate) array: [u8; COMMIT_ARRAY_SIZE],
+    // The same bit is set in here if the commit is EC_Ephemeral's, which
+    // isn't bound to a key.
+    pub(crate) ephemeral: [u8; COMMIT_ARRAY_SIZE],
+}
+
+impl TpmInstance {
+    pub(crate) fn commit_reset(&mut self) {
+        let get_random = self.platform.get_random;
+
+        get_random(&mut self.commit.nonce);
+        self.commit.counter = 0;
+        self.commit.array = [0; COMMIT_ARRAY_SIZE];
+        self.commit.ephemeral = [0; COMMIT_ARRAY_SIZE];
+    }
+
+    // r = KDFa(hashAlg, commitNonce, "ECDAA Commit", name, count), and the
+    // point r * G.
+    fn commit_key(
+        &self,
+        curve_id: TpmEccCurve,
+        name: &[u8],
+        count: u64,
+        r: &mut Tpm2bEccParameter,
+        point: &mut TpmsEccPoint,
+    ) -> Result<(), TpmError> {
+        let count = count.to_be_bytes();
+        let mut rand = KdfRandom::new(
+            CONTEXT_INTEGRITY_HASH_ALG,
+            &self.commit.nonce,
+            COMMIT,
+            name,
+            &count,
+        );
+
+        ecc::generate_key(curve_id, &mut rand, r, point)
+    }
+
+    // The ephemeral key the next commit on `curve_id` gets, bound to the
+    // object `name` (empty for EC_Ephemeral). Nothing is used up until
+    // commit_start, so a command can fail after getting it.
+    pub(crate) fn commit_next(
+        &self,
+        curve_id: TpmEccCurve,
+        name: &[u8],
+        r: &mut Tpm2bEccParameter,
+        point: &mut TpmsEccPoint,
+    ) -> Result<(), TpmError> {
+        self.commit_key(curve_id, name, self.commit.counter, r, point)
+    }
+
+    // Start the commit whose key commit_next gave for `name`. Returns the
+    // low 16 bits of its counter value.
+    pub(crate) fn commit_start(&mut self, name: &[u8]) -> u16 {
+        let count = self.commit.counter;
+        let bit = (count % COMMIT_ARRAY_BITS) as usize;
+        self.commit.array[bit / 8] |= 1 << (bit % 8);
+        match name.is_empty() {
+            true => self.commit.ephemeral[bit / 8] |= 1 << (bit % 8),
+            false => self.commit.ephemeral[bit / 8] &= !(1 << (bit % 8)),
+        }
+        self.commit.counter += 1;
+
+        count as u16
+    }
+
+    // The ephemeral private key of the commit with counter value `counter`,
+    // which then can't be used again. An unknown, used or expired commit, or
+    // one made for something else, is TPM_RC_VALUE.
+    pub(crate) fn commit_end(
+        &mut self,
+        curve_id: TpmEccCurve,
+        name: &[u8],
+        counter: u16,
+        r: &mut Tpm2bEccParameter,
+    ) -> Result<(), TpmError> {
+        // The high bits are the current counter's, or one less if the low
+        // bits have wrapped since.
+        let current = self.commit.counter;
+        let mut count = (current & !0xffff) | counter as u64;
+        if count >= current {
+            count = count.wrapping_sub(0x10000);
+        }
+        if count >= current || count + COMMIT_ARRAY_BITS < current {
+            return Err(TpmError::new(TpmRc::Value));
+        }
+
+        // A key's commit can't be used without the key, nor EC_Ephemeral's
+        // with one.
+        let bit = (count % COMMIT_ARRAY_BITS) as usize;
+        let ephemeral = self.commit.ephemeral[bit / 8] & (1 << (bit % 8)) != 0;
+        if self.commit.array[bit / 8] & (1 << (bit % 8)) == 0 || ephemeral != name.is_empty() {
+            return Err(TpmError::new(TpmRc::Value));
+        }
+
+        let mut point = TpmsEccPoint::default();
+        self.commit_key(curve_id, name, count, r, &mut point)?;
+        self.commit.array[bit / 8] &= !(1 << (bit % 8));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+use crate::authorization::auth_equal;
+use crate::crypto::hash::*;
+use crate::crypto::kdf::*;
+use crate::crypto::sym;
+use crate::hierarchy::Hierarchy;
+use crate::marshal::*;
+use crate::nv::*;
+use crate::object::*;
+use crate::sequence::*;
+use crate::session::*;
+use crate::tpm::*;
+use crate::types::*;
+
+// Saved contexts are encrypted with AES-256 in CFB mode.
+const CONTEXT_ENCRYPT_ALG: TpmAlgId = TpmAlgId::Aes;
+const CONTEXT_ENCRYPT_KEY_BYTES: usize = 32;
+
+// savedHandle values for transient objects and sequences. A session's
+// context has its own handle.
+const SAVED_OBJECT: TpmHandle = 0x80000000;
+const SAVED_SEQUENCE: TpmHandle = 0x80000001;
+const SAVED_ST_CLEAR_OBJECT: TpmHandle = 0x80000002;
+
+#[derive(Default)]
+pub struct ContextState {
+    // Persistent. Counts every TPM Reset, and goes into the integrity of
+    // saved contexts so none of them survive one.
+    pub(crate) total_reset_count: u64,
+
+    // Sequence number of the next saved object context.
+    pub(crate) object_context_id: u64,
+
+    // contextID of the next saved session context.
+    pub(crate) session_context_id: u64,
+
+    // Counts TPM Restarts since the last TPM Reset, and goes into the
+    // integrity of stClear objects so they don't survive one.
+    pub(crate) clear_count: u32,
+
+    // Counts TPM Restarts and Resumes since the last TPM Reset.
+    pub(crate) restart_count: u32,
+}
+
+impl TpmInstance {
+    pub(crate) fn context_restore(&mut self) -> Result<(), TpmError> {
+        let mut count = [0u8; 8];
+        self.nv_read(NV_RESET_COUNT, &mut count)?;
+        self.context.total_reset_count = u64::from_be_bytes(count);
+
+        Ok(())
+    }
+
+    pub(crate) fn context_reset(&mut self) -> Result<(), TpmError> {
+        let count = self.context.total_reset_count + 1;
+        self.nv_write(NV_RESET_COUNT, &count.to_be_bytes())?;
+
+        self.context.total_reset_count = count;
+        self.context.object_context_id = 0;
+        self.context.session_context_id = 0;
+        self.context.clear_count = 0;
+        self.context.restart_count = 0;
+
+        Ok(())
+    }
+
+    // (symKey, iv) = KDFa(hashAlg, hProof, "CONTEXT", sequence, handle)
+    fn context_key(
+        &self,
+        hierarchy: Hierarchy,
+        sequence: u64,
+        handle: TpmHandle,
+        key: &mut [u8; CONTEXT_ENCRYPT_KEY_BYTES],
+        iv: &mut [u8; sym::AES_BLOCK_SIZE],
+    ) -> Result<(), TpmError> {
+        let mut out = [0u8; CONTEXT_ENCRYPT_KEY_BYTES + sym::AES_BLOCK_SIZE];
+        kdfa(
+            CONTEXT_INTEGRITY_HASH_ALG,
+            self.hierarchy_proof(hierarchy),
+            CONTEXT,
+            &sequence.to_be_bytes(),
+            &handle.to_be_bytes(),
+            &mut out,
+        )?;
+        key.copy_from_slice(&out[..CONTEXT_ENCRYPT_KEY_BYTES]);
+        iv.copy_from_slice(&out[CONTEXT_ENCRYPT_KEY_BYTES..]);
+
+        Ok(())
+    }
+
+    // HMAC(hProof, totalResetCount {|| clearCount} || sequence || handle ||
+    // encContext), where only stClear objects have the clearCount.
+    fn context_integrity(
+        &self,
+        hierarchy: Hierarchy,
+        sequence: u64,
+        handle: TpmHandle,
+        enc: &[u8],
+    ) -> Result<Tpm2bDigest, TpmError> {
+        let clear_count = self.context.clear_count.to_be_bytes();
+        let clear_count: &[u8] = match handle {
+            SAVED_ST_CLEAR_OBJECT => &clear_count,
+            _ => &[],
+        };
+
+        hmac(
+            CONTEXT_INTEGRITY_HASH_ALG,
+            self.hierarchy_proof(hierarchy),
+            &[
+                &self.context.total_reset_count.to_be_bytes(),
+                clear_count,
+                &sequence.to_be_bytes(),
+                &handle.to_be_bytes(),
+                enc,
+            ],
+        )
+    }
+}
+
+// What a context holds. There's no heap to box the object on.
+#[allow(clippy::large_enum_variant)]
+enum Saved {
+    Object(Object),
+    Sequence(Sequence),
+    Session(Session),
+}
+
+// The saved form of a context: the sequence number again as a fingerprint,
+// then the object, sequence or session as stored outside the TPM.
+fn marshal_saved(buffer: &mut [u8], sequence: u64, saved: &Saved) -> Result<usize, TpmError> {
+    let offset = marshal_u64(buffer, sequence)?;
+    let buffer = &mut buffer[offset..];
+
+    Ok(offset
+        + match saved {
+            Saved::Object(object) => marshal_object(buffer, object)?,
+            Saved::Sequence(sequence) => marshal_sequence(buffer, sequence)?,
+            Saved::Session(session) => marshal_session(buffer, session)?,
+        })
+}
+
+fn unmarshal_saved(
+    buffer: &[u8],
+    sequence: u64,
+    saved_handle: TpmHandle,
+    hierarchy: Hierarchy,
+) -> Result<Saved, TpmError> {
+    let mut offset = 0;
+    if unmarshal_u64(buffer, &mut offset)? != sequence {
+        return Err(TpmError::new(TpmRc::BadContext));
+    }
+
+    let saved = match saved_handle {
+        SAVED_OBJECT | SAVED_ST_CLEAR_OBJECT => {
+            Saved::Object(unmarshal_object(buffer, &mut offset, hierarchy)?)
+        }
+        SAVED_SEQUENCE => Saved::Sequence(unmarshal_sequence(buffer, &mut offset)?),
+        _ => Saved::Session(unmarshal_session(buffer, &mut offset)?),
+    };
+    if offset != buffer.len() {
+        return Err(TpmError::new(TpmRc::BadContext));
+    }
+
+    Ok(saved)
+}
+
+// Save a transient object, a sequence or a session. Objects and sequences
+// stay loaded. A session leaves its slot, and only the context just saved
+// can load it again. Sequences and sessions are saved under the null
+// hierarchy, so none of them survive a TPM Reset.
+pub fn tpm2_context_save(
+    tpm: &mut TpmInstance,
+    args: &ContextSaveArgs,
+) -> Result<ContextSaveResponse, TpmError> {
+    let handle = args.save_handle;
+    let (saved, hierarchy, saved_handle, sequence) = match TpmHt::from(handle) {
+        TpmHt::HmacSession | TpmHt::PolicySession => {
+            let session = match tpm.session_get(handle) {
+                Some(session) => *session,
+                None => return Err(TpmError::handle(TpmRc::ReferenceH0, 1)),
+            };
+            let context_id = tpm.session_context_id()?;
+            (Saved::Session(session), Hierarchy::Null, handle, context_id)
+        }
+        _ => {
+            let sequence = tpm.context.object_context_id;
+            match (tpm.object_get(handle), tpm.sequence_get(handle)) {
+                (Some(object), _) => {
+                    let saved_handle = match object.public.has_attributes(TPMA_OBJECT_ST_CLEAR) {
+                        true => SAVED_ST_CLEAR_OBJECT,
+                        false => SAVED_OBJECT,
+                    };
+                    (
+                        Saved::Object(*object),
+                        object.hierarchy,
+                        saved_handle,
+                        sequence,
+                    )
+                }
+                (None, Some(state)) => (
+                    Saved::Sequence(state.clone()),
+                    Hierarchy::Null,
+                    SAVED_SEQUENCE,
+                    sequence,
+                ),
+                (None, None) => return Err(TpmError::handle(TpmRc::ReferenceH0, 1)),
+            }
+        }
+    };
+
+    let mut context = TpmsContext {
+        sequence,
+        saved_handle,
+        hierarchy: hierarchy.handle(),
+        ..Default::default()
+    };
+
+    let digest_size = CONTEXT_INTEGRITY_HASH_ALG.digest_size();
+    let start = 2 + digest_size;
+    let blob = &mut context.context_blob;
+    let size = marshal_saved(&mut blob.buffer[start..], sequence, &saved)?;
+
+    let mut key = [0u8; CONTEXT_ENCRYPT_KEY_BYTES];
+    let mut iv = [0u8; sym::AES_BLOCK_SIZE];
+    tpm.context_key(hierarchy, sequence, saved_handle, &mut key, &mut iv)?;
+    let enc = &mut blob.buffer[start..start + size];
+    sym::cfb_encrypt(CONTEXT_ENCRYPT_ALG, &key, &iv, enc)?;
+
+    let integrity = tpm.context_integrity(hierarchy, sequence, saved_handle, enc)?;
+    marshal_tpm2b(&mut blob.buffer, &integrity)?;
+    blob.size = (start + size) as u16;
+
+    match saved {
+        Saved::Session(_) => tpm.session_context_saved(handle, sequence),
+        _ => {
+            tpm.context.object_context_id = match sequence.checked_add(1) {
+                Some(next) => next,
+                None => return Err(TpmError::new(TpmRc::TooManyContexts)),
+            }
+        }
+    }
+
+    Ok(ContextSaveResponse { context })
+}
+
+pub fn tpm2_context_load(
+    tpm: &mut TpmInstance,
+    args: &ContextLoadArgs,
+) -> Result<ContextLoadResponse, TpmError> {
+    let context = &args.context;
+
+    let hierarchy = match Hierarchy::from_handle(context.hierarchy) {
+        Some(hierarchy) => hierarchy,
+        None => return Err(TpmError::parameter(TpmRc::Value, 1)),
+    };
+    if !tpm.hierarchy_is_enabled(hierarchy) {
+        return Err(TpmError::parameter(TpmRc::Hierarchy, 1));
+    }
+
+    match TpmHt::from(context.saved_handle) {
+        _ if matches!(
+            context.saved_handle,
+            SAVED_OBJECT | SAVED_SEQUENCE | SAVED_ST_CLEAR_OBJECT
+        ) => {}
+        TpmHt::HmacSession | TpmHt::PolicySession => {}
+        _ => return Err(TpmError::parameter(TpmRc::Value, 1)),
+    }
+
+    let blob = context.context_blob.as_slice();
+    let mut offset = 0;
+    let integrity: Tpm2bDigest =
+        unmarshal_tpm2b(blob, &mut offset).map_err(|_| TpmError::parameter(TpmRc::Integrity, 1))?;
+
+    let mut enc = [0u8; MAX_CONTEXT_SIZE];
+    let size = blob.len() - offset;
+    let enc = &mut enc[..size];
+    enc.copy_from_slice(&blob[offset..]);
+
+    let expected = tpm.context_integrity(hierarchy, context.sequence, context.saved_handle, enc)?;
+    if integrity.size != expected.size || !auth_equal(integrity.as_slice(), expected.as_slice()) {
+        return Err(TpmError::parameter(TpmRc::Integrity, 1));
+    }
+
+    let mut key = [0u8; CONTEXT_ENCRYPT_KEY_BYTES];
+    let mut iv = [0u8; sym::AES_BLOCK_SIZE];
+    tpm.context_key(
+        hierarchy,
+        context.sequence,
+        context.saved_handle,
+        &mut key,
+        &mut iv,
+    )?;
+    sym::cfb_decrypt(CONTEXT_ENCRYPT_ALG, &key, &iv, enc)?;
+
+    let saved = unmarshal_saved(enc, context.sequence, context.saved_handle, hierarchy)
+        .map_err(|_| TpmError::new(TpmRc::BadContext))?;
+    let loaded_handle = match saved {
+        Saved::Object(object) => tpm.object_load(object)?,
+        Saved::Sequence(sequence) => tpm.sequence_load(sequence)?,
+        Saved::Session(session) => {
+            let handle = context.saved_handle;
+            tpm.session_context_load(handle, context.sequence, session)?;
+            handle
+        }
+    };
+
+    Ok(ContextLoadResponse { loaded_handle })
+}
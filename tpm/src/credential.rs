@@ -0,0 +1,114 @@
+use crate::asymmetric::*;
+use crate::crypto::kdf::IDENTITY;
+use crate::marshal::*;
+use crate::object::*;
+use crate::tpm::*;
+use crate::types::*;
+
+// Credentials are protected with an asymmetric restricted decryption key,
+// normally an EK, the same way a storage key protects its children.
+fn is_credential_key(public: &TpmtPublic) -> bool {
+    matches!(
+        public.parameters,
+        TpmuPublicParms::Rsa(_) | TpmuPublicParms::Ecc(_)
+    ) && public.has_attributes(TPMA_OBJECT_RESTRICTED | TPMA_OBJECT_DECRYPT)
+}
+
+// Protect a credential so that only the TPM holding the private part of
+// `handle` can recover it, and only for an object with the Name
+// `objectName`. The seed is shared with the key, and the credential goes
+// in an outer wrap made with the seed and objectName. Everything here can
+// be done outside a TPM, so the key needs no authorization.
+pub fn tpm2_make_credential(
+    tpm: &mut TpmInstance,
+    args: &MakeCredentialArgs,
+) -> Result<MakeCredentialResponse, TpmError> {
+    let object = loaded_object(tpm, args.handle)?;
+    if !is_credential_key(&object.public) {
+        return Err(TpmError::handle(TpmRc::Type, 1));
+    }
+    let hash_alg = object.public.name_alg;
+    if args.credential.size as usize > hash_alg.digest_size() {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+
+    let mut seed = Tpm2bDigest::default();
+    let mut secret = Tpm2bEncryptedSecret::default();
+    secret_encrypt(tpm, &object.public, IDENTITY, &mut seed, &mut secret)?;
+
+    let start = 2 + hash_alg.digest_size();
+    let mut credential_blob = Tpm2bIdObject::default();
+    let size = marshal_tpm2b(&mut credential_blob.buffer[start..], &args.credential)?;
+    let enc = &mut credential_blob.buffer[start..start + size];
+
+    let symmetric = parent_symmetric(&object.public)?;
+    let integrity = outer_wrap(
+        hash_alg,
+        &symmetric,
+        seed.as_slice(),
+        &args.object_name,
+        enc,
+    )?;
+
+    marshal_tpm2b(&mut credential_blob.buffer, &integrity)?;
+    credential_blob.size = (start + size) as u16;
+
+    Ok(MakeCredentialResponse {
+        credential_blob,
+        secret,
+    })
+}
+
+// Release a credential made for the object behind activateHandle, if keyHandle
+// is the key it was made with.
+pub fn tpm2_activate_credential(
+    tpm: &mut TpmInstance,
+    args: &ActivateCredentialArgs,
+) -> Result<ActivateCredentialResponse, TpmError> {
+    let activate = loaded_object(tpm, args.activate_handle)?;
+    let key = match tpm.object_get(args.key_handle) {
+        Some(object) => *object,
+        None => return Err(TpmError::handle(TpmRc::ReferenceH0, 2)),
+    };
+    if !is_credential_key(&key.public) {
+        return Err(TpmError::handle(TpmRc::Type, 2));
+    }
+
+    let seed = secret_decrypt(&key, IDENTITY, &args.secret).map_err(|e| e.with_parameter(2))?;
+
+    let blob = args.credential_blob.as_slice();
+    let mut offset = 0;
+    let integrity: Tpm2bDigest =
+        unmarshal_tpm2b(blob, &mut offset).map_err(|_| TpmError::parameter(TpmRc::Integrity, 1))?;
+
+    let mut enc = [0u8; 2 + MAX_DIGEST_SIZE];
+    let size = blob.len() - offset;
+    if size > enc.len() {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+    let enc = &mut enc[..size];
+    enc.copy_from_slice(&blob[offset..]);
+
+    let hash_alg = key.public.name_alg;
+    let symmetric = parent_symmetric(&key.public)?;
+    outer_unwrap(
+        hash_alg,
+        &symmetric,
+        seed.as_slice(),
+        &activate.name,
+        &integrity,
+        enc,
+    )
+    .map_err(|e| e.with_parameter(1))?;
+
+    // The credential has to fill what was encrypted, and be no longer than
+    // MakeCredential allows.
+    let mut offset = 0;
+    let cert_info: Tpm2bDigest =
+        unmarshal_tpm2b(enc, &mut offset).map_err(|_| TpmError::parameter(TpmRc::Size, 1))?;
+    if offset != size || cert_info.size as usize > hash_alg.digest_size() {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+
+    Ok(ActivateCredentialResponse { cert_info })
+}
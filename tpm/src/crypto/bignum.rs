@@ -0,0 +1,150 @@
+use crate::crypto::kdf::RandomSource;
+use crypto_bigint::modular::runtime_mod::{DynResidue, DynResidueParams};
+use crypto_bigint::{Limb, Uint, Word};
+
+// Big-endian bytes to an integer. Leading bytes that don't fit are dropped,
+// so callers must check sizes first.
+pub fn from_bytes<const L: usize>(bytes: &[u8]) -> Uint<L> {
+    let mut words = [0 as Word; L];
+    for (i, b) in bytes.iter().rev().enumerate() {
+        let word = i / Limb::BYTES;
+        if word >= L {
+            break;
+        }
+        words[word] |= (*b as Word) << ((i % Limb::BYTES) * 8);
+    }
+
+    Uint::from_words(words)
+}
+
+// Write an integer as exactly `out.len()` big-endian bytes.
+pub fn to_bytes<const L: usize>(n: &Uint<L>, out: &mut [u8]) {
+    let words = n.as_words();
+    let len = out.len();
+    for (i, b) in out.iter_mut().enumerate() {
+        let pos = len - 1 - i;
+        let word = pos / Limb::BYTES;
+        *b = match word < L {
+            true => (words[word] >> ((pos % Limb::BYTES) * 8)) as u8,
+            false => 0,
+        };
+    }
+}
+
+pub fn is_zero<const L: usize>(n: &Uint<L>) -> bool {
+    n.as_words().iter().all(|w| *w == 0)
+}
+
+pub fn is_odd<const L: usize>(n: &Uint<L>) -> bool {
+    n.as_words()[0] & 1 == 1
+}
+
+pub fn rem<const L: usize>(a: &Uint<L>, m: &Uint<L>) -> Uint<L> {
+    a.const_rem(m).0
+}
+
+pub fn gcd<const L: usize>(a: &Uint<L>, b: &Uint<L>) -> Uint<L> {
+    let mut a = *a;
+    let mut b = *b;
+    while !is_zero(&b) {
+        let r = rem(&a, &b);
+        a = b;
+        b = r;
+    }
+
+    a
+}
+
+// A random integer of `bits` bits (top bits may be zero).
+pub fn random_bits<const L: usize>(rand: &mut dyn RandomSource, bits: usize) -> Uint<L> {
+    let mut buf = [0u8; 1024];
+    let len = bits.div_ceil(8);
+    rand.fill(&mut buf[..len]);
+    if !bits.is_multiple_of(8) {
+        buf[0] &= (1u8 << (bits % 8)) - 1;
+    }
+
+    from_bytes(&buf[..len])
+}
+
+// Small primes used to sieve candidates before the more expensive
+// Miller-Rabin test.
+const SMALL_PRIMES: [u32; 53] = [
+    3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251,
+];
+
+fn has_small_factor<const L: usize>(n: &Uint<L>) -> bool {
+    for p in SMALL_PRIMES {
+        let p = Uint::<L>::from_u32(p);
+        if is_zero(&rem(n, &p)) && n != &p {
+            return true;
+        }
+    }
+
+    false
+}
+
+// Number of Miller-Rabin rounds for a prime of the given size, from table
+// C.3 of FIPS 186-4.
+fn miller_rabin_rounds(bits: usize) -> usize {
+    match bits {
+        0..=512 => 8,
+        513..=1024 => 5,
+        1025..=1536 => 4,
+        _ => 3,
+    }
+}
+
+// Probabilistic primality test. Witnesses are drawn from `rand` so primary
+// key generation stays deterministic.
+pub fn is_probable_prime<const L: usize>(n: &Uint<L>, rand: &mut dyn RandomSource) -> bool {
+    let two = Uint::<L>::from_u8(2);
+    if n < &two {
+        return false;
+    }
+    if !is_odd(n) {
+        return n == &two;
+    }
+    if has_small_factor(n) {
+        return false;
+    }
+
+    let n_minus_1 = n.wrapping_sub(&Uint::ONE);
+    let s = n_minus_1.trailing_zeros();
+    let d = n_minus_1.shr_vartime(s);
+    let bits = n.bits_vartime();
+
+    let params = DynResidueParams::new(n);
+    let one = DynResidue::one(params);
+    let minus_one = DynResidue::new(&n_minus_1, params);
+
+    for _ in 0..miller_rabin_rounds(bits) {
+        // Witness in [2, n - 2]
+        let mut a = random_bits::<L>(rand, bits);
+        a = rem(&a, &n_minus_1.wrapping_sub(&Uint::ONE));
+        if a < two {
+            a = two;
+        }
+
+        let mut x = DynResidue::new(&a, params).pow_bounded_exp(&d, d.bits_vartime());
+        if x == one || x == minus_one {
+            continue;
+        }
+
+        let mut composite = true;
+        for _ in 1..s {
+            x = x.square();
+            if x == minus_one {
+                composite = false;
+                break;
+            }
+        }
+        if composite {
+            return false;
+        }
+    }
+
+    true
+}
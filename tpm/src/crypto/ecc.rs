@@ -0,0 +1,1020 @@
+use crate::crypto::bignum::*;
+use crate::crypto::hash::hash;
+use crate::crypto::kdf::RandomSource;
+use crate::types::*;
+use crypto_bigint::modular::runtime_mod::{DynResidue, DynResidueParams};
+use crypto_bigint::{Uint, U384, U448, U640};
+
+pub struct CurveParams {
+    pub curve_id: TpmEccCurve,
+    pub key_bits: usize,
+    pub p: &'static str,
+    pub a: &'static str,
+    pub b: &'static str,
+    pub gx: &'static str,
+    pub gy: &'static str,
+    pub n: &'static str,
+    pub h: &'static str,
+}
+
+const NIST_P256: CurveParams = CurveParams {
+    curve_id: TpmEccCurve::NistP256,
+    key_bits: 256,
+    p: "FFFFFFFF00000001000000000000000000000000FFFFFFFFFFFFFFFFFFFFFFFF",
+    a: "FFFFFFFF00000001000000000000000000000000FFFFFFFFFFFFFFFFFFFFFFFC",
+    b: "5AC635D8AA3A93E7B3EBBD55769886BC651D06B0CC53B0F63BCE3C3E27D2604B",
+    gx: "6B17D1F2E12C4247F8BCE6E563A440F277037D812DEB33A0F4A13945D898C296",
+    gy: "4FE342E2FE1A7F9B8EE7EB4A7C0F9E162BCE33576B315ECECBB6406837BF51F5",
+    n: "FFFFFFFF00000000FFFFFFFFFFFFFFFFBCE6FAADA7179E84F3B9CAC2FC632551",
+    h: "01",
+};
+
+const NIST_P384: CurveParams = CurveParams {
+    curve_id: TpmEccCurve::NistP384,
+    key_bits: 384,
+    p: "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFE\
+        FFFFFFFF0000000000000000FFFFFFFF",
+    a: "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFE\
+        FFFFFFFF0000000000000000FFFFFFFC",
+    b: "B3312FA7E23EE7E4988E056BE3F82D19181D9C6EFE8141120314088F5013875A\
+        C656398D8A2ED19D2A85C8EDD3EC2AEF",
+    gx: "AA87CA22BE8B05378EB1C71EF320AD746E1D3B628BA79B9859F741E082542A38\
+         5502F25DBF55296C3A545E3872760AB7",
+    gy: "3617DE4A96262C6F5D9E98BF9292DC29F8F41DBD289A147CE9DA3113B5F0B8C0\
+         0A60B1CE1D7E819D7A431D7C90EA0E5F",
+    n: "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFC7634D81F4372DDF\
+        581A0DB248B0A77AECEC196ACCC52973",
+    h: "01",
+};
+
+const NIST_P521: CurveParams = CurveParams {
+    curve_id: TpmEccCurve::NistP521,
+    key_bits: 521,
+    p: "01FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF\
+        FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+    a: "01FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF\
+        FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFC",
+    b: "0051953EB9618E1C9A1F929A21A0B68540EEA2DA725B99B315F3B8B489918EF1\
+        09E156193951EC7E937B1652C0BD3BB1BF073573DF883D2C34F1EF451FD46B503F00",
+    gx: "00C6858E06B70404E9CD9E3ECB662395B4429C648139053FB521F828AF606B4D\
+         3DBAA14B5E77EFE75928FE1DC127A2FFA8DE3348B3C1856A429BF97E7E31C2E5BD66",
+    gy: "011839296A789A3BC0045C8A5FB42C7D1BD998F54449579B446817AFBD17273E\
+         662C97EE72995EF42640C550B9013FAD0761353C7086A272C24088BE94769FD16650",
+    n: "01FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF\
+        FA51868783BF2F966B7FCC0148F709A5D03BB5C9B8899C47AEBB6FB71E91386409",
+    h: "01",
+};
+
+#[cfg(feature = "bn-p256")]
+const BN_P256: CurveParams = CurveParams {
+    curve_id: TpmEccCurve::BnP256,
+    key_bits: 256,
+    p: "FFFFFFFFFFFCF0CD46E5F25EEE71A49F0CDC65FB12980A82D3292DDBAED33013",
+    a: "00",
+    b: "03",
+    gx: "01",
+    gy: "02",
+    n: "FFFFFFFFFFFCF0CD46E5F25EEE71A49E0CDC65FB1299921AF62D536CD10B500D",
+    h: "01",
+};
+
+#[cfg(feature = "sm2-p256")]
+const SM2_P256: CurveParams = CurveParams {
+    curve_id: TpmEccCurve::Sm2P256,
+    key_bits: 256,
+    p: "FFFFFFFEFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF00000000FFFFFFFFFFFFFFFF",
+    a: "FFFFFFFEFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF00000000FFFFFFFFFFFFFFFC",
+    b: "28E9FA9E9D9F5E344D5A9E4BCF6509A7F39789F515AB8F92DDBCBD414D940E93",
+    gx: "32C4AE2C1F1981195F9904466A39C9948FE30BBFF2660BE1715A4589334C74C7",
+    gy: "BC3736A2F4F6779C59BDCEE36B692153D0A9877CC62A474002DF32E52139F0A0",
+    n: "FFFFFFFEFFFFFFFFFFFFFFFFFFFFFFFF7203DF6B21C6052B53BBF40939D54123",
+    h: "01",
+};
+
+// Supported curves in ascending order of TPM_ECC_CURVE
+pub const CURVES: &[CurveParams] = &[
+    NIST_P256,
+    NIST_P384,
+    NIST_P521,
+    #[cfg(feature = "bn-p256")]
+    BN_P256,
+    #[cfg(feature = "sm2-p256")]
+    SM2_P256,
+];
+
+pub fn curve_params(curve_id: TpmEccCurve) -> Option<&'static CurveParams> {
+    CURVES.iter().find(|c| c.curve_id == curve_id)
+}
+
+fn lookup(curve_id: TpmEccCurve) -> Result<&'static CurveParams, TpmError> {
+    match curve_params(curve_id) {
+        Some(params) => Ok(params),
+        None => Err(TpmError::new(TpmRc::Curve)),
+    }
+}
+
+// Call `f::<L>(params, ...)` with L limbs wide enough for the curve plus the
+// 64 extra bits used when deriving private keys. Each curve gets the
+// narrowest width that fits, so P-256 isn't slowed down by P-521.
+macro_rules! with_curve {
+    ($params:expr, $f:ident($($arg:expr),*)) => {
+        match $params.key_bits {
+            0..=320 => $f::<{ U384::LIMBS }>($params, $($arg),*),
+            321..=448 => $f::<{ U448::LIMBS }>($params, $($arg),*),
+            _ => $f::<{ U640::LIMBS }>($params, $($arg),*),
+        }
+    };
+}
+
+// Hex digits to bytes, skipping line continuation whitespace.
+fn hex_bytes(hex: &str, out: &mut [u8; MAX_ECC_KEY_BYTES]) -> usize {
+    let nibble = |c: u8| match c {
+        b'0'..=b'9' => c - b'0',
+        b'a'..=b'f' => c - b'a' + 10,
+        _ => c - b'A' + 10,
+    };
+
+    let mut len = 0;
+    let mut digits = hex.bytes().filter(|c| c.is_ascii_hexdigit());
+    while let (Some(hi), Some(lo)) = (digits.next(), digits.next()) {
+        out[len] = (nibble(hi) << 4) | nibble(lo);
+        len += 1;
+    }
+
+    len
+}
+
+fn from_hex<const L: usize>(hex: &str) -> Uint<L> {
+    let mut bytes = [0u8; MAX_ECC_KEY_BYTES];
+    let len = hex_bytes(hex, &mut bytes);
+    from_bytes(&bytes[..len])
+}
+
+// A curve parameter as a TPM2B_ECC_PARAMETER, without leading zeros.
+fn parameter(hex: &str) -> Tpm2bEccParameter {
+    let mut bytes = [0u8; MAX_ECC_KEY_BYTES];
+    let len = hex_bytes(hex, &mut bytes);
+    let start = bytes[..len].iter().position(|b| *b != 0).unwrap_or(len);
+
+    let mut param = Tpm2bEccParameter {
+        size: (len - start) as u16,
+        ..Default::default()
+    };
+    param.buffer[..len - start].copy_from_slice(&bytes[start..len]);
+    param
+}
+
+// TPMS_ALGORITHM_DETAIL_ECC for a supported curve. None of them has a
+// KDF or signing scheme of its own.
+pub fn curve_detail(curve_id: TpmEccCurve) -> Result<TpmsAlgorithmDetailEcc, TpmError> {
+    let params = lookup(curve_id)?;
+
+    Ok(TpmsAlgorithmDetailEcc {
+        curve_id,
+        key_size: params.key_bits as u16,
+        kdf: TpmtKdfScheme::default(),
+        sign: TpmtAsymScheme::default(),
+        p: parameter(params.p),
+        a: parameter(params.a),
+        b: parameter(params.b),
+        gx: parameter(params.gx),
+        gy: parameter(params.gy),
+        n: parameter(params.n),
+        h: parameter(params.h),
+    })
+}
+
+// Point in Jacobian coordinates. The point at infinity has z == 0.
+#[derive(Clone, Copy)]
+pub struct Point<const L: usize> {
+    x: DynResidue<L>,
+    y: DynResidue<L>,
+    z: DynResidue<L>,
+}
+
+// A curve with its parameters converted for arithmetic.
+pub struct Curve<const L: usize> {
+    pub params: &'static CurveParams,
+    pub n: Uint<L>,
+    p: Uint<L>,
+    field: DynResidueParams<L>,
+    order: DynResidueParams<L>,
+    a: DynResidue<L>,
+    b: DynResidue<L>,
+    g: Point<L>,
+}
+
+impl<const L: usize> Curve<L> {
+    pub fn new(params: &'static CurveParams) -> Curve<L> {
+        let p = from_hex(params.p);
+        let n = from_hex(params.n);
+        let field = DynResidueParams::new(&p);
+        let g = Point {
+            x: DynResidue::new(&from_hex(params.gx), field),
+            y: DynResidue::new(&from_hex(params.gy), field),
+            z: DynResidue::one(field),
+        };
+
+        Curve {
+            params,
+            n,
+            p,
+            field,
+            order: DynResidueParams::new(&n),
+            a: DynResidue::new(&from_hex(params.a), field),
+            b: DynResidue::new(&from_hex(params.b), field),
+            g,
+        }
+    }
+
+    pub fn key_bytes(&self) -> usize {
+        self.params.key_bits.div_ceil(8)
+    }
+
+    fn infinity(&self) -> Point<L> {
+        Point {
+            x: DynResidue::one(self.field),
+            y: DynResidue::one(self.field),
+            z: DynResidue::zero(self.field),
+        }
+    }
+
+    fn is_infinity(&self, pt: &Point<L>) -> bool {
+        is_zero(&pt.z.retrieve())
+    }
+
+    pub fn generator(&self) -> Point<L> {
+        self.g
+    }
+
+    // The point with affine coordinates (x, y), if it's on the curve.
+    pub fn point(&self, x: &Uint<L>, y: &Uint<L>) -> Option<Point<L>> {
+        if x >= &self.p || y >= &self.p {
+            return None;
+        }
+
+        let x = DynResidue::new(x, self.field);
+        let y = DynResidue::new(y, self.field);
+        // y^2 = x^3 + ax + b
+        let rhs = x.square().mul(&x).add(&self.a.mul(&x)).add(&self.b);
+        if y.square().retrieve() != rhs.retrieve() {
+            return None;
+        }
+
+        Some(Point {
+            x,
+            y,
+            z: DynResidue::one(self.field),
+        })
+    }
+
+    pub fn to_affine(&self, pt: &Point<L>) -> Option<(Uint<L>, Uint<L>)> {
+        if self.is_infinity(pt) {
+            return None;
+        }
+
+        let z_inv = pt.z.invert().0;
+        let z_inv2 = z_inv.square();
+        let x = pt.x.mul(&z_inv2);
+        let y = pt.y.mul(&z_inv2.mul(&z_inv));
+
+        Some((x.retrieve(), y.retrieve()))
+    }
+
+    pub fn double(&self, pt: &Point<L>) -> Point<L> {
+        if self.is_infinity(pt) || is_zero(&pt.y.retrieve()) {
+            return self.infinity();
+        }
+
+        let xx = pt.x.square();
+        let yy = pt.y.square();
+        let yyyy = yy.square();
+        let zz = pt.z.square();
+
+        // S = 4 * X * YY, M = 3 * XX + a * ZZ^2
+        let s = pt.x.mul(&yy);
+        let s = s.add(&s);
+        let s = s.add(&s);
+        let m = xx.add(&xx).add(&xx).add(&self.a.mul(&zz.square()));
+
+        let x3 = m.square().sub(&s).sub(&s);
+        let yyyy8 = yyyy.add(&yyyy);
+        let yyyy8 = yyyy8.add(&yyyy8);
+        let yyyy8 = yyyy8.add(&yyyy8);
+        let y3 = m.mul(&s.sub(&x3)).sub(&yyyy8);
+        let yz = pt.y.mul(&pt.z);
+        let z3 = yz.add(&yz);
+
+        Point {
+            x: x3,
+            y: y3,
+            z: z3,
+        }
+    }
+
+    pub fn add(&self, p1: &Point<L>, p2: &Point<L>) -> Point<L> {
+        if self.is_infinity(p1) {
+            return *p2;
+        }
+        if self.is_infinity(p2) {
+            return *p1;
+        }
+
+        let z1z1 = p1.z.square();
+        let z2z2 = p2.z.square();
+        let u1 = p1.x.mul(&z2z2);
+        let u2 = p2.x.mul(&z1z1);
+        let s1 = p1.y.mul(&p2.z).mul(&z2z2);
+        let s2 = p2.y.mul(&p1.z).mul(&z1z1);
+
+        if u1.retrieve() == u2.retrieve() {
+            if s1.retrieve() == s2.retrieve() {
+                return self.double(p1);
+            }
+            return self.infinity();
+        }
+
+        let h = u2.sub(&u1);
+        let r = s2.sub(&s1);
+        let hh = h.square();
+        let hhh = hh.mul(&h);
+        let v = u1.mul(&hh);
+
+        let x3 = r.square().sub(&hhh).sub(&v).sub(&v);
+        let y3 = r.mul(&v.sub(&x3)).sub(&s1.mul(&hhh));
+        let z3 = h.mul(&p1.z).mul(&p2.z);
+
+        Point {
+            x: x3,
+            y: y3,
+            z: z3,
+        }
+    }
+
+    pub fn mul(&self, k: &Uint<L>, pt: &Point<L>) -> Point<L> {
+        let mut result = self.infinity();
+        for i in (0..k.bits_vartime()).rev() {
+            result = self.double(&result);
+            if k.bit_vartime(i) {
+                result = self.add(&result, pt);
+            }
+        }
+
+        result
+    }
+
+    // a + b * c mod n
+    fn scalar_mul_add(&self, a: &Uint<L>, b: &Uint<L>, c: &Uint<L>) -> Uint<L> {
+        let a = self.scalar(a);
+        let b = self.scalar(b);
+        let c = self.scalar(c);
+
+        (a + b * c).retrieve()
+    }
+
+    // An integer mod n
+    fn scalar(&self, v: &Uint<L>) -> DynResidue<L> {
+        DynResidue::new(v, self.order)
+    }
+
+    // x(k * G + l * pt), or None for the point at infinity
+    fn mul_add_x(&self, k: &Uint<L>, l: &Uint<L>, pt: &Point<L>) -> Option<Uint<L>> {
+        let sum = self.add(&self.mul(k, &self.g), &self.mul(l, pt));
+        self.to_affine(&sum).map(|(x, _)| x)
+    }
+
+    fn order_bytes(&self) -> usize {
+        self.n.bits_vartime().div_ceil(8)
+    }
+
+    // The leftmost bits of a digest, as many as n has, as ECDSA uses it
+    fn digest_bits(&self, digest: &[u8]) -> Uint<L> {
+        let n_bits = self.n.bits_vartime();
+        let len = digest.len().min(self.order_bytes());
+        let e: Uint<L> = from_bytes(&digest[..len]);
+
+        match len * 8 > n_bits {
+            true => e.shr_vartime(len * 8 - n_bits),
+            false => e,
+        }
+    }
+
+    // A big-endian number of any length reduced by the modulus of `params`
+    fn reduce(&self, bytes: &[u8], params: DynResidueParams<L>) -> DynResidue<L> {
+        let base = DynResidue::new(&Uint::from_u16(256), params);
+        bytes.iter().fold(DynResidue::zero(params), |e, b| {
+            e * base + DynResidue::new(&Uint::from_u8(*b), params)
+        })
+    }
+
+    // The whole digest mod n, as SM2 and ECDAA use it
+    fn digest_mod_n(&self, digest: &[u8]) -> DynResidue<L> {
+        self.reduce(digest, self.order)
+    }
+
+    // EC-Schnorr's H(x(R) || digest), cut down to the length of n
+    fn schnorr_hash(
+        &self,
+        hash_alg: TpmAlgId,
+        x: &Uint<L>,
+        digest: &[u8],
+    ) -> Result<Uint<L>, TpmError> {
+        let key_bytes = self.key_bytes();
+        let mut rx = [0u8; MAX_ECC_KEY_BYTES];
+        to_bytes(x, &mut rx[..key_bytes]);
+
+        let e = hash(hash_alg, &[&rx[..key_bytes], digest])?;
+        let len = e.as_slice().len().min(self.order_bytes());
+        Ok(from_bytes(&e.as_slice()[..len]))
+    }
+
+    // The associate value function: 2^w + (x mod 2^w) for the point's x.
+    fn avf(&self, pt: &Point<L>, w: usize) -> Result<Uint<L>, TpmError> {
+        let x = match self.to_affine(pt) {
+            Some((x, _)) => x,
+            None => return Err(TpmError::new(TpmRc::NoResult)),
+        };
+        let bit = Uint::<L>::ONE.shl_vartime(w);
+
+        Ok(x.bitand(&bit.wrapping_sub(&Uint::ONE)).bitor(&bit))
+    }
+
+    // Private key in [1, n - 1] from n_bits + 64 bits of randomness, as in
+    // FIPS 186-4 B.4.1.
+    pub fn generate_private(&self, rand: &mut dyn RandomSource) -> Uint<L> {
+        let c: Uint<L> = random_bits(rand, self.params.key_bits + 64);
+        let n_minus_1 = self.n.wrapping_sub(&Uint::ONE);
+
+        rem(&c, &n_minus_1).wrapping_add(&Uint::ONE)
+    }
+}
+
+pub fn generate_key(
+    curve_id: TpmEccCurve,
+    rand: &mut dyn RandomSource,
+    d_out: &mut Tpm2bEccParameter,
+    point_out: &mut TpmsEccPoint,
+) -> Result<(), TpmError> {
+    let params = lookup(curve_id)?;
+    with_curve!(params, generate(rand, d_out, point_out))
+}
+
+fn generate<const L: usize>(
+    params: &'static CurveParams,
+    rand: &mut dyn RandomSource,
+    d_out: &mut Tpm2bEccParameter,
+    point_out: &mut TpmsEccPoint,
+) -> Result<(), TpmError> {
+    let curve = Curve::<L>::new(params);
+    let key_bytes = curve.key_bytes();
+
+    let d = curve.generate_private(rand);
+    store_point(&curve, &curve.mul(&d, &curve.generator()), point_out)?;
+
+    d_out.size = key_bytes as u16;
+    to_bytes(&d, &mut d_out.buffer[..key_bytes]);
+
+    Ok(())
+}
+
+// Check that a private key is in [1, n - 1] and belongs to the public point.
+pub fn validate_key(
+    curve_id: TpmEccCurve,
+    d: &Tpm2bEccParameter,
+    point: &TpmsEccPoint,
+) -> Result<(), TpmError> {
+    let params = lookup(curve_id)?;
+    with_curve!(params, validate(d, point))
+}
+
+fn validate<const L: usize>(
+    params: &'static CurveParams,
+    d: &Tpm2bEccParameter,
+    point: &TpmsEccPoint,
+) -> Result<(), TpmError> {
+    let curve = Curve::<L>::new(params);
+    let key_bytes = curve.key_bytes();
+    if d.size as usize > key_bytes
+        || point.x.size as usize > key_bytes
+        || point.y.size as usize > key_bytes
+    {
+        return Err(TpmError::new(TpmRc::KeySize));
+    }
+
+    let d: Uint<L> = from_bytes(d.as_slice());
+    if is_zero(&d) || d >= curve.n {
+        return Err(TpmError::new(TpmRc::Binding));
+    }
+
+    let q = curve.mul(&d, &curve.generator());
+    match curve.to_affine(&q) {
+        Some((x, y))
+            if x == from_bytes(point.x.as_slice()) && y == from_bytes(point.y.as_slice()) =>
+        {
+            Ok(())
+        }
+        _ => Err(TpmError::new(TpmRc::Binding)),
+    }
+}
+
+fn load_point<const L: usize>(
+    curve: &Curve<L>,
+    point: &TpmsEccPoint,
+) -> Result<Point<L>, TpmError> {
+    let key_bytes = curve.key_bytes();
+    if point.x.size as usize > key_bytes || point.y.size as usize > key_bytes {
+        return Err(TpmError::new(TpmRc::EccPoint));
+    }
+
+    match curve.point(
+        &from_bytes(point.x.as_slice()),
+        &from_bytes(point.y.as_slice()),
+    ) {
+        Some(pt) => Ok(pt),
+        None => Err(TpmError::new(TpmRc::EccPoint)),
+    }
+}
+
+fn store_point<const L: usize>(
+    curve: &Curve<L>,
+    pt: &Point<L>,
+    out: &mut TpmsEccPoint,
+) -> Result<(), TpmError> {
+    let key_bytes = curve.key_bytes();
+    let (x, y) = match curve.to_affine(pt) {
+        Some(xy) => xy,
+        None => return Err(TpmError::new(TpmRc::NoResult)),
+    };
+
+    out.x.size = key_bytes as u16;
+    to_bytes(&x, &mut out.x.buffer[..key_bytes]);
+    out.y.size = key_bytes as u16;
+    to_bytes(&y, &mut out.y.buffer[..key_bytes]);
+
+    Ok(())
+}
+
+// Check that a public point is on the curve.
+pub fn validate_point(curve_id: TpmEccCurve, point: &TpmsEccPoint) -> Result<(), TpmError> {
+    let params = lookup(curve_id)?;
+    with_curve!(params, check_point(point))
+}
+
+fn check_point<const L: usize>(
+    params: &'static CurveParams,
+    point: &TpmsEccPoint,
+) -> Result<(), TpmError> {
+    load_point(&Curve::<L>::new(params), point).map(|_| ())
+}
+
+// One-pass ECDH: an ephemeral key pair, and the shared point it makes with
+// the public point `q`.
+pub fn ecdh_keygen(
+    curve_id: TpmEccCurve,
+    q: &TpmsEccPoint,
+    rand: &mut dyn RandomSource,
+    z_out: &mut TpmsEccPoint,
+    pub_out: &mut TpmsEccPoint,
+) -> Result<(), TpmError> {
+    let params = lookup(curve_id)?;
+    with_curve!(params, keygen(q, rand, z_out, pub_out))
+}
+
+fn keygen<const L: usize>(
+    params: &'static CurveParams,
+    q: &TpmsEccPoint,
+    rand: &mut dyn RandomSource,
+    z_out: &mut TpmsEccPoint,
+    pub_out: &mut TpmsEccPoint,
+) -> Result<(), TpmError> {
+    let curve = Curve::<L>::new(params);
+    let q = load_point(&curve, q)?;
+
+    let d = curve.generate_private(rand);
+    store_point(&curve, &curve.mul(&d, &curve.generator()), pub_out)?;
+    store_point(&curve, &curve.mul(&d, &q), z_out)
+}
+
+// d * point, the shared point of ECDH
+pub fn point_multiply(
+    curve_id: TpmEccCurve,
+    d: &Tpm2bEccParameter,
+    point: &TpmsEccPoint,
+    out: &mut TpmsEccPoint,
+) -> Result<(), TpmError> {
+    let params = lookup(curve_id)?;
+    with_curve!(params, multiply(d, point, out))
+}
+
+fn multiply<const L: usize>(
+    params: &'static CurveParams,
+    d: &Tpm2bEccParameter,
+    point: &TpmsEccPoint,
+    out: &mut TpmsEccPoint,
+) -> Result<(), TpmError> {
+    let curve = Curve::<L>::new(params);
+    let pt = load_point(&curve, point)?;
+    store_point(&curve, &curve.mul(&from_bytes(d.as_slice()), &pt), out)
+}
+
+// A digest reduced mod p as an x coordinate, the way TPM2_Commit turns s2
+// into x2.
+pub fn digest_to_x(
+    curve_id: TpmEccCurve,
+    digest: &[u8],
+    x: &mut Tpm2bEccParameter,
+) -> Result<(), TpmError> {
+    let params = lookup(curve_id)?;
+    with_curve!(params, reduce_x(digest, x))
+}
+
+fn reduce_x<const L: usize>(
+    params: &'static CurveParams,
+    digest: &[u8],
+    x: &mut Tpm2bEccParameter,
+) -> Result<(), TpmError> {
+    let curve = Curve::<L>::new(params);
+    let key_bytes = curve.key_bytes();
+
+    x.size = key_bytes as u16;
+    to_bytes(
+        &curve.reduce(digest, curve.field).retrieve(),
+        &mut x.buffer[..key_bytes],
+    );
+
+    Ok(())
+}
+
+// The second phase of a two-phase key exchange, from party A's static and
+// ephemeral private keys and party B's static and ephemeral public points.
+// ECDH is the full unified model and gives two points. ECMQV and SM2 give
+// one, and leave `z2` empty. All supported curves have a cofactor of 1, so
+// it's left out.
+#[allow(clippy::too_many_arguments)]
+pub fn zgen_2phase(
+    curve_id: TpmEccCurve,
+    scheme: TpmAlgId,
+    ds_a: &Tpm2bEccParameter,
+    de_a: &Tpm2bEccParameter,
+    qs_b: &TpmsEccPoint,
+    qe_b: &TpmsEccPoint,
+    z1: &mut TpmsEccPoint,
+    z2: &mut TpmsEccPoint,
+) -> Result<(), TpmError> {
+    let params = lookup(curve_id)?;
+    with_curve!(params, two_phase(scheme, ds_a, de_a, qs_b, qe_b, z1, z2))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn two_phase<const L: usize>(
+    params: &'static CurveParams,
+    scheme: TpmAlgId,
+    ds_a: &Tpm2bEccParameter,
+    de_a: &Tpm2bEccParameter,
+    qs_b: &TpmsEccPoint,
+    qe_b: &TpmsEccPoint,
+    z1: &mut TpmsEccPoint,
+    z2: &mut TpmsEccPoint,
+) -> Result<(), TpmError> {
+    let curve = Curve::<L>::new(params);
+    let qs_b = load_point(&curve, qs_b)?;
+    let qe_b = load_point(&curve, qe_b)?;
+    let ds_a: Uint<L> = from_bytes(ds_a.as_slice());
+    let de_a: Uint<L> = from_bytes(de_a.as_slice());
+
+    *z2 = TpmsEccPoint::default();
+    let f = curve.n.bits_vartime();
+    let p = match scheme {
+        TpmAlgId::EcDh => {
+            store_point(&curve, &curve.mul(&de_a, &qe_b), z2)?;
+            curve.mul(&ds_a, &qs_b)
+        }
+        // SP800-56A C(2e, 2s, ECC MQV)
+        TpmAlgId::EcMqv => {
+            let half = f.div_ceil(2);
+            let qe_a = curve.mul(&de_a, &curve.generator());
+            let implicit = curve.scalar_mul_add(&de_a, &curve.avf(&qe_a, half)?, &ds_a);
+            let sum = curve.add(&qe_b, &curve.mul(&curve.avf(&qe_b, half)?, &qs_b));
+            curve.mul(&implicit, &sum)
+        }
+        // GM/T 0003.3 key exchange
+        TpmAlgId::Sm2 => {
+            let w = f.div_ceil(2) - 1;
+            let qe_a = curve.mul(&de_a, &curve.generator());
+            let t_a = curve.scalar_mul_add(&ds_a, &curve.avf(&qe_a, w)?, &de_a);
+            let sum = curve.add(&qs_b, &curve.mul(&curve.avf(&qe_b, w)?, &qe_b));
+            curve.mul(&t_a, &sum)
+        }
+        _ => return Err(TpmError::new(TpmRc::Scheme)),
+    };
+
+    store_point(&curve, &p, z1)
+}
+
+// Sign a digest with the private key `d`. The scheme's hash goes in
+// `sig.hash` first, since EC-Schnorr hashes with it again.
+pub fn sign(
+    curve_id: TpmEccCurve,
+    scheme: TpmAlgId,
+    d: &Tpm2bEccParameter,
+    digest: &[u8],
+    rand: &mut dyn RandomSource,
+    sig: &mut TpmsSignatureEcc,
+) -> Result<(), TpmError> {
+    let params = lookup(curve_id)?;
+    with_curve!(params, sign_digest(scheme, d, digest, rand, sig))
+}
+
+fn sign_digest<const L: usize>(
+    params: &'static CurveParams,
+    scheme: TpmAlgId,
+    d: &Tpm2bEccParameter,
+    digest: &[u8],
+    rand: &mut dyn RandomSource,
+    sig: &mut TpmsSignatureEcc,
+) -> Result<(), TpmError> {
+    let curve = Curve::<L>::new(params);
+    let key_bytes = curve.key_bytes();
+    let d = curve.scalar(&from_bytes(d.as_slice()));
+
+    // Retry with a new k in the unlikely cases that give a zero.
+    let (r, s) = loop {
+        let k = curve.generate_private(rand);
+        let x = match curve.to_affine(&curve.mul(&k, &curve.g)) {
+            Some((x, _)) => x,
+            None => continue,
+        };
+        let k = curve.scalar(&k);
+
+        let (r, s) = match scheme {
+            // s = k^-1 (e + r * d)
+            TpmAlgId::EcDsa => {
+                let r = curve.scalar(&x);
+                let e = curve.scalar(&curve.digest_bits(digest));
+                (r.retrieve(), (k.invert().0 * (e + r * d)).retrieve())
+            }
+            // r = e + x, s = (1 + d)^-1 (k - r * d)
+            TpmAlgId::Sm2 => {
+                let r = curve.digest_mod_n(digest) + curve.scalar(&x);
+                if is_zero(&(r + k).retrieve()) {
+                    continue;
+                }
+                let one = DynResidue::one(curve.order);
+                (
+                    r.retrieve(),
+                    ((one + d).invert().0 * (k - r * d)).retrieve(),
+                )
+            }
+            // r = H(x || digest), s = k + r * d. r is the hash itself, which
+            // can be larger than n.
+            TpmAlgId::EcSchnorr => {
+                let r = curve.schnorr_hash(sig.hash, &x, digest)?;
+                (r, (k + curve.scalar(&r) * d).retrieve())
+            }
+            _ => return Err(TpmError::new(TpmRc::Scheme)),
+        };
+
+        if !is_zero(&r) && !is_zero(&s) {
+            break (r, s);
+        }
+    };
+
+    sig.signature_r.size = key_bytes as u16;
+    to_bytes(&r, &mut sig.signature_r.buffer[..key_bytes]);
+    sig.signature_s.size = key_bytes as u16;
+    to_bytes(&s, &mut sig.signature_s.buffer[..key_bytes]);
+
+    Ok(())
+}
+
+// Sign a digest with ECDAA, where `r` is the private part of a TPM2_Commit.
+// The signature's R is a random nonceK, T = H(nonceK || digest) and
+// S = r + T * d. As with `sign`, `sig.hash` is set first.
+pub fn sign_ecdaa(
+    curve_id: TpmEccCurve,
+    d: &Tpm2bEccParameter,
+    r: &Tpm2bEccParameter,
+    digest: &[u8],
+    rand: &mut dyn RandomSource,
+    sig: &mut TpmsSignatureEcc,
+) -> Result<(), TpmError> {
+    let params = lookup(curve_id)?;
+    with_curve!(params, sign_anonymous(d, r, digest, rand, sig))
+}
+
+fn sign_anonymous<const L: usize>(
+    params: &'static CurveParams,
+    d: &Tpm2bEccParameter,
+    r: &Tpm2bEccParameter,
+    digest: &[u8],
+    rand: &mut dyn RandomSource,
+    sig: &mut TpmsSignatureEcc,
+) -> Result<(), TpmError> {
+    let curve = Curve::<L>::new(params);
+    let key_bytes = curve.key_bytes();
+
+    let nonce_k = curve.generate_private(rand);
+    sig.signature_r.size = key_bytes as u16;
+    to_bytes(&nonce_k, &mut sig.signature_r.buffer[..key_bytes]);
+
+    let t = hash(sig.hash, &[sig.signature_r.as_slice(), digest])?;
+    let t = curve.digest_mod_n(t.as_slice());
+    let d = curve.scalar(&from_bytes(d.as_slice()));
+    let r = curve.scalar(&from_bytes(r.as_slice()));
+
+    sig.signature_s.size = key_bytes as u16;
+    to_bytes(
+        &(r + t * d).retrieve(),
+        &mut sig.signature_s.buffer[..key_bytes],
+    );
+
+    Ok(())
+}
+
+// Check a signature made by `sign` against the public point. Anything that
+// doesn't verify is TPM_RC_SIGNATURE.
+pub fn verify(
+    curve_id: TpmEccCurve,
+    scheme: TpmAlgId,
+    point: &TpmsEccPoint,
+    digest: &[u8],
+    sig: &TpmsSignatureEcc,
+) -> Result<(), TpmError> {
+    let params = lookup(curve_id)?;
+    with_curve!(params, verify_digest(scheme, point, digest, sig))
+}
+
+fn verify_digest<const L: usize>(
+    params: &'static CurveParams,
+    scheme: TpmAlgId,
+    point: &TpmsEccPoint,
+    digest: &[u8],
+    sig: &TpmsSignatureEcc,
+) -> Result<(), TpmError> {
+    let curve = Curve::<L>::new(params);
+    let q = load_point(&curve, point)?;
+    let key_bytes = curve.key_bytes();
+    if sig.signature_r.size as usize > key_bytes || sig.signature_s.size as usize > key_bytes {
+        return Err(TpmError::new(TpmRc::Signature));
+    }
+
+    let r: Uint<L> = from_bytes(sig.signature_r.as_slice());
+    let s: Uint<L> = from_bytes(sig.signature_s.as_slice());
+    let in_range = |v: &Uint<L>| !is_zero(v) && *v < curve.n;
+    // An EC-Schnorr r is a hash, not a number mod n.
+    if !in_range(&s) || (scheme != TpmAlgId::EcSchnorr && !in_range(&r)) {
+        return Err(TpmError::new(TpmRc::Signature));
+    }
+    let r_n = curve.scalar(&r);
+    let s_n = curve.scalar(&s);
+
+    let good = match scheme {
+        // x(e / s * G + r / s * Q) == r
+        TpmAlgId::EcDsa => {
+            let w = s_n.invert().0;
+            let e = curve.scalar(&curve.digest_bits(digest));
+            let u1 = (e * w).retrieve();
+            let u2 = (r_n * w).retrieve();
+            matches!(curve.mul_add_x(&u1, &u2, &q), Some(x) if curve.scalar(&x).retrieve() == r)
+        }
+        // e + x(s * G + (r + s) * Q) == r
+        TpmAlgId::Sm2 => {
+            let t = (r_n + s_n).retrieve();
+            !is_zero(&t)
+                && matches!(curve.mul_add_x(&s, &t, &q),
+                    Some(x) if (curve.digest_mod_n(digest) + curve.scalar(&x)).retrieve() == r)
+        }
+        // H(x(s * G - r * Q) || digest) == r
+        TpmAlgId::EcSchnorr => {
+            let minus_r = (DynResidue::zero(curve.order) - r_n).retrieve();
+            match curve.mul_add_x(&s, &minus_r, &q) {
+                Some(x) => curve.schnorr_hash(sig.hash, &x, digest)? == r,
+                None => false,
+            }
+        }
+        _ => return Err(TpmError::new(TpmRc::Scheme)),
+    };
+
+    match good {
+        true => Ok(()),
+        false => Err(TpmError::new(TpmRc::Signature)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::from_hex;
+    use crate::crypto::kdf::KdfRandom;
+
+    // The P-256 key and SHA-256 signature of "sample" from RFC 6979 A.2.5.
+    const D: &str = "c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721";
+    const UX: &str = "60fed4ba255a9d31c961eb74c6356d68c049b8923b61fa6ce669622e60f29fb6";
+    const UY: &str = "7903fe1008b8bc99a41ae9e95628bc64f2f1b20c2d7e9f5177a3c294d4462299";
+    const R: &str = "efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716";
+    const S: &str = "f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8";
+
+    fn param(s: &str) -> Tpm2bEccParameter {
+        Tpm2bEccParameter::from_slice(&from_hex::<32>(s)).unwrap()
+    }
+
+    fn public() -> TpmsEccPoint {
+        TpmsEccPoint {
+            x: param(UX),
+            y: param(UY),
+        }
+    }
+
+    #[test]
+    fn ecdsa_known_signature() {
+        let digest = hash(TpmAlgId::Sha256, &[b"sample"]).unwrap();
+        let mut sig = TpmsSignatureEcc {
+            hash: TpmAlgId::Sha256,
+            signature_r: param(R),
+            signature_s: param(S),
+        };
+        let curve = TpmEccCurve::NistP256;
+        verify(curve, TpmAlgId::EcDsa, &public(), digest.as_slice(), &sig).unwrap();
+
+        sig.signature_s.buffer[31] ^= 1;
+        assert!(verify(curve, TpmAlgId::EcDsa, &public(), digest.as_slice(), &sig).is_err());
+    }
+
+    // k is random, so the signature can only be checked by verifying it.
+    #[test]
+    fn ecdsa_round_trip() {
+        let digest = hash(TpmAlgId::Sha256, &[b"sample"]).unwrap();
+        let mut rand = KdfRandom::new(TpmAlgId::Sha256, &[1], b"TEST", &[], &[]);
+        let mut sig = TpmsSignatureEcc::default();
+        let curve = TpmEccCurve::NistP256;
+        sign(
+            curve,
+            TpmAlgId::EcDsa,
+            &param(D),
+            digest.as_slice(),
+            &mut rand,
+            &mut sig,
+        )
+        .unwrap();
+        verify(curve, TpmAlgId::EcDsa, &public(), digest.as_slice(), &sig).unwrap();
+
+        let other = hash(TpmAlgId::Sha256, &[b"samplf"]).unwrap();
+        assert!(verify(curve, TpmAlgId::EcDsa, &public(), other.as_slice(), &sig).is_err());
+    }
+
+    // Keys and deterministic SHA-256 signatures of "sample" on P-384 and
+    // P-521 from an independent implementation
+    const P384: [&str; 4] = [
+        "2725d8245d2f4c6e35344ba87536ce071749ed63cf8f452be5e241cb711dccefca00d90b3fdb6b32afd93bbcc93307bc",
+        "6177ff65dc56eeb010bfef4dddae76b623cb1fcd6df8796746934f294af4f33705536422a4f50fa8621b3e53c24c7ab0",
+        "eeff076046ca0e91ddc1df992e8d11abd64ad03bbbafdefce465c6c9ec0815b50a4867f9b62b3570159642261b4efd8a",
+        "ca659187283b30d3b495d9c2bcf63b645423046fade61cb5df438e1817cc5c08892502fe5658bb4fa9a42baed8261024",
+    ];
+    const P521: [&str; 4] = [
+        "01806ef38a0cd69980929d7b21351126017e3d86173eccfdae306f4a1af58b6b6808a8419b5ab251e600fead105d8940d69be8fbf1dd813e2f32354c418816f1b2a4",
+        "00e7e7e1093344ecb380bb0835127377c89290492008f03d49fe5e5ddccb2f983879c206f097b4df9f298de661ed71a83724ad72709fadbc18a546be1e4bf87048c1",
+        "0161664df3515642eb5a2ee2a13acd52b8bf1eb8768201ed32b84e3d28711dcc7de16338995fd88d13e136a195e4a7a4bd3bbadf12c0a764a4b17b970be5330c4935",
+        "01c77e801e8fd384583803aa4438699d34775dae59066ce48af1a022cad639442a167eb2d7c592535d7e0a757bf9301fb3fe0415979027cc1a9a4ba4906fcc6f3341",
+    ];
+
+    #[test]
+    fn ecdsa_known_signature_large_curves() {
+        fn check<const N: usize>(curve: TpmEccCurve, values: [&str; 4]) {
+            let [x, y, r, s] =
+                values.map(|v| Tpm2bEccParameter::from_slice(&from_hex::<N>(v)).unwrap());
+            let digest = hash(TpmAlgId::Sha256, &[b"sample"]).unwrap();
+            let point = TpmsEccPoint { x, y };
+            let mut sig = TpmsSignatureEcc {
+                hash: TpmAlgId::Sha256,
+                signature_r: r,
+                signature_s: s,
+            };
+            verify(curve, TpmAlgId::EcDsa, &point, digest.as_slice(), &sig).unwrap();
+
+            sig.signature_r.buffer[N - 1] ^= 1;
+            assert!(verify(curve, TpmAlgId::EcDsa, &point, digest.as_slice(), &sig).is_err());
+        }
+
+        check::<48>(TpmEccCurve::NistP384, P384);
+        check::<66>(TpmEccCurve::NistP521, P521);
+    }
+
+    // A point has to be on the curve and inside the field.
+    #[test]
+    fn off_curve_point() {
+        let mut point = public();
+        validate_point(TpmEccCurve::NistP256, &point).unwrap();
+
+        point.y.buffer[31] ^= 1;
+        let rc = validate_point(TpmEccCurve::NistP256, &point);
+        assert!(matches!(rc, Err(e) if e.rc == TpmRc::EccPoint));
+
+        let p = "ffffffff00000001000000000000000000000000ffffffffffffffffffffffff";
+        let point = TpmsEccPoint {
+            x: param(p),
+            y: param(UY),
+        };
+        let rc = validate_point(TpmEccCurve::NistP256, &point);
+        assert!(matches!(rc, Err(e) if e.rc == TpmRc::EccPoint));
+    }
+}
@@ -0,0 +1,315 @@
+use crate::types::*;
+use sha2::digest::generic_array::GenericArray;
+
+pub const MAX_HASH_BLOCK_SIZE: usize = 128;
+
+const SHA1_IV: [u64; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+const SHA256_IV: [u64; 8] = [
+    0x6A09E667, 0xBB67AE85, 0x3C6EF372, 0xA54FF53A, 0x510E527F, 0x9B05688C, 0x1F83D9AB, 0x5BE0CD19,
+];
+const SHA384_IV: [u64; 8] = [
+    0xCBBB9D5DC1059ED8,
+    0x629A292A367CD507,
+    0x9159015A3070DD17,
+    0x152FECD8F70E5939,
+    0x67332667FFC00B31,
+    0x8EB44A8768581511,
+    0xDB0C2E0D64F98FA7,
+    0x47B5481DBEFA4FA4,
+];
+const SHA512_IV: [u64; 8] = [
+    0x6A09E667F3BCC908,
+    0xBB67AE8584CAA73B,
+    0x3C6EF372FE94F82B,
+    0xA54FF53A5F1D36F1,
+    0x510E527FADE682D1,
+    0x9B05688C2B3E6C1F,
+    0x1F83D9ABFB41BD6B,
+    0x5BE0CD19137E2179,
+];
+
+// A hash in progress. Only the compression functions come from the hash
+// crates, so the chaining value and the partial block can be saved with a
+// sequence context and picked up again.
+#[derive(Clone, Copy)]
+pub struct HashState {
+    pub(crate) alg: TpmAlgId,
+    // SHA-1 and SHA-256 use the low 32 bits of each word.
+    pub(crate) chain: [u64; 8],
+    pub(crate) block: [u8; MAX_HASH_BLOCK_SIZE],
+    // Bytes hashed so far. The block holds the last length % block size.
+    pub(crate) length: u64,
+}
+
+impl HashState {
+    pub fn new(alg: TpmAlgId) -> Result<HashState, TpmError> {
+        let mut chain = [0u64; 8];
+        match alg {
+            TpmAlgId::Sha1 => chain[..5].copy_from_slice(&SHA1_IV),
+            TpmAlgId::Sha256 => chain = SHA256_IV,
+            TpmAlgId::Sha384 => chain = SHA384_IV,
+            TpmAlgId::Sha512 => chain = SHA512_IV,
+            _ => return Err(TpmError::new(TpmRc::Hash)),
+        }
+
+        Ok(HashState {
+            alg,
+            chain,
+            block: [0; MAX_HASH_BLOCK_SIZE],
+            length: 0,
+        })
+    }
+
+    pub fn block_size(&self) -> usize {
+        match self.alg {
+            TpmAlgId::Sha1 | TpmAlgId::Sha256 => 64,
+            _ => 128,
+        }
+    }
+
+    fn compress(&mut self) {
+        match self.alg {
+            TpmAlgId::Sha1 => {
+                let mut state = [0u32; 5];
+                narrow(&mut state, &self.chain);
+                let block = GenericArray::from_slice(&self.block[..64]);
+                sha1::compress(&mut state, core::slice::from_ref(block));
+                widen(&mut self.chain, &state);
+            }
+            TpmAlgId::Sha256 => {
+                let mut state = [0u32; 8];
+                narrow(&mut state, &self.chain);
+                let block = GenericArray::from_slice(&self.block[..64]);
+                sha2::compress256(&mut state, core::slice::from_ref(block));
+                widen(&mut self.chain, &state);
+            }
+            _ => {
+                let block = GenericArray::from_slice(&self.block[..]);
+                sha2::compress512(&mut self.chain, core::slice::from_ref(block));
+            }
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        let block_size = self.block_size();
+        while !data.is_empty() {
+            let fill = (self.length % block_size as u64) as usize;
+            let n = core::cmp::min(block_size - fill, data.len());
+            self.block[fill..fill + n].copy_from_slice(&data[..n]);
+            self.length += n as u64;
+            data = &data[n..];
+
+            if fill + n == block_size {
+                self.compress();
+            }
+        }
+    }
+
+    // Pad with a one bit, zeros and the length in bits, which takes 8
+    // bytes with 64-byte blocks and 16 bytes with 128-byte blocks.
+    pub fn finish(mut self) -> Tpm2bDigest {
+        let block_size = self.block_size();
+        let length_size = block_size / 8;
+        let bits = (self.length as u128) * 8;
+        let fill = (self.length % block_size as u64) as usize;
+
+        let mut pad = [0u8; 2 * MAX_HASH_BLOCK_SIZE];
+        let pad_size = match fill + 1 + length_size <= block_size {
+            true => block_size - fill,
+            false => 2 * block_size - fill,
+        };
+        pad[0] = 0x80;
+        pad[pad_size - length_size..pad_size]
+            .copy_from_slice(&bits.to_be_bytes()[16 - length_size..]);
+        self.update(&pad[..pad_size]);
+
+        let mut digest = Tpm2bDigest {
+            size: self.alg.digest_size() as u16,
+            ..Default::default()
+        };
+        match self.alg {
+            TpmAlgId::Sha1 | TpmAlgId::Sha256 => {
+                for (out, word) in digest.buffer.chunks_mut(4).zip(self.chain.iter()) {
+                    out.copy_from_slice(&(*word as u32).to_be_bytes());
+                }
+            }
+            _ => {
+                for (out, word) in digest.buffer.chunks_mut(8).zip(self.chain.iter()) {
+                    out.copy_from_slice(&word.to_be_bytes());
+                }
+            }
+        }
+        // SHA-1 and SHA-384 drop the rest of the chaining value.
+        digest.buffer[digest.size as usize..].fill(0);
+
+        digest
+    }
+}
+
+fn narrow<const N: usize>(state: &mut [u32; N], chain: &[u64; 8]) {
+    for (s, c) in state.iter_mut().zip(chain.iter()) {
+        *s = *c as u32;
+    }
+}
+
+fn widen<const N: usize>(chain: &mut [u64; 8], state: &[u32; N]) {
+    for (c, s) in chain.iter_mut().zip(state.iter()) {
+        *c = *s as u64;
+    }
+}
+
+// HMAC as two hash states. The outer one has already taken in the key
+// block, and gets the inner digest at the end.
+#[derive(Clone, Copy)]
+pub struct HmacState {
+    pub(crate) inner: HashState,
+    pub(crate) outer: HashState,
+}
+
+impl HmacState {
+    pub fn new(alg: TpmAlgId, key: &[u8]) -> Result<HmacState, TpmError> {
+        let mut inner = HashState::new(alg)?;
+        let mut outer = inner;
+        let block_size = inner.block_size();
+
+        // Keys longer than a block are hashed first.
+        let mut key_block = [0u8; MAX_HASH_BLOCK_SIZE];
+        match key.len() > block_size {
+            true => {
+                let digest = hash(alg, &[key])?;
+                key_block[..digest.size as usize].copy_from_slice(digest.as_slice());
+            }
+            false => key_block[..key.len()].copy_from_slice(key),
+        }
+
+        let mut pad = [0u8; MAX_HASH_BLOCK_SIZE];
+        for (p, k) in pad.iter_mut().zip(key_block.iter()) {
+            *p = k ^ 0x36;
+        }
+        inner.update(&pad[..block_size]);
+        for (p, k) in pad.iter_mut().zip(key_block.iter()) {
+            *p = k ^ 0x5C;
+        }
+        outer.update(&pad[..block_size]);
+
+        Ok(HmacState { inner, outer })
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    pub fn finish(mut self) -> Tpm2bDigest {
+        let inner = self.inner.finish();
+        self.outer.update(inner.as_slice());
+        self.outer.finish()
+    }
+}
+
+// Hash the concatenation of `data`.
+pub fn hash(alg: TpmAlgId, data: &[&[u8]]) -> Result<Tpm2bDigest, TpmError> {
+    let mut state = HashState::new(alg)?;
+    for d in data {
+        state.update(d);
+    }
+
+    Ok(state.finish())
+}
+
+// HMAC the concatenation of `data`.
+pub fn hmac(alg: TpmAlgId, key: &[u8], data: &[&[u8]]) -> Result<Tpm2bDigest, TpmError> {
+    let mut state = HmacState::new(alg, key)?;
+    for d in data {
+        state.update(d);
+    }
+
+    Ok(state.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::from_hex;
+
+    #[test]
+    fn sha_abc() {
+        let digest = hash(TpmAlgId::Sha1, &[b"abc"]).unwrap();
+        assert_eq!(
+            digest.as_slice(),
+            from_hex::<20>("a9993e364706816aba3e25717850c26c9cd0d89d")
+        );
+        let digest = hash(TpmAlgId::Sha256, &[b"abc"]).unwrap();
+        assert_eq!(
+            digest.as_slice(),
+            from_hex::<32>("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+        let digest = hash(TpmAlgId::Sha384, &[b"abc"]).unwrap();
+        assert_eq!(
+            digest.as_slice(),
+            from_hex::<48>(
+                "cb00753f45a35e8bb5a03d699ac65007272c32ab0eded163\
+                 1a8b605a43ff5bed8086072ba1e7cc2358baeca134c825a7"
+            )
+        );
+        let digest = hash(TpmAlgId::Sha512, &[b"abc"]).unwrap();
+        assert_eq!(
+            digest.as_slice(),
+            from_hex::<64>(
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                 2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+            )
+        );
+    }
+
+    // The padding doesn't fit after 56 bytes, so it takes a second block.
+    // The message is split across updates that don't line up with blocks.
+    #[test]
+    fn sha256_two_blocks() {
+        let message = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        let digest = hash(TpmAlgId::Sha256, &[&message[..5], &message[5..]]).unwrap();
+        assert_eq!(
+            digest.as_slice(),
+            from_hex::<32>("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1")
+        );
+    }
+
+    // RFC 4231 test cases 2 and 6
+    #[test]
+    fn hmac_rfc4231() {
+        let mac = hmac(
+            TpmAlgId::Sha256,
+            b"Jefe",
+            &[b"what do ya want for nothing?"],
+        )
+        .unwrap();
+        assert_eq!(
+            mac.as_slice(),
+            from_hex::<32>("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843")
+        );
+        let mac = hmac(
+            TpmAlgId::Sha512,
+            b"Jefe",
+            &[b"what do ya want for nothing?"],
+        )
+        .unwrap();
+        assert_eq!(
+            mac.as_slice(),
+            from_hex::<64>(
+                "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+                 9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+            )
+        );
+
+        let key = [0xAAu8; 131];
+        let mac = hmac(
+            TpmAlgId::Sha256,
+            &key,
+            &[b"Test Using Larger Than Block-Size Key - Hash Key First"],
+        )
+        .unwrap();
+        assert_eq!(
+            mac.as_slice(),
+            from_hex::<32>("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54")
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use crate::crypto::hash::*;
+use crate::types::*;
+
+// Labels used with KDFa
+pub const PRIMARY_OBJECT_CREATION: &[u8] = b"Primary Object Creation";
+pub const STORAGE: &[u8] = b"STORAGE";
+pub const INTEGRITY: &[u8] = b"INTEGRITY";
+pub const SESSION_KEY: &[u8] = b"ATH";
+pub const CONTEXT: &[u8] = b"CONTEXT";
+pub const OBFUSCATE: &[u8] = b"OBFUSCATE";
+// Secret sharing labels are also OAEP labels, which include the terminating
+// zero.
+pub const IDENTITY: &[u8] = b"IDENTITY\0";
+pub const DUPLICATE: &[u8] = b"DUPLICATE\0";
+pub const SECRET: &[u8] = b"SECRET\0";
+
+// KDFa from part 1 of the spec. Fills `out` with `out.len()` bytes.
+pub fn kdfa(
+    hash_alg: TpmAlgId,
+    key: &[u8],
+    label: &[u8],
+    context_u: &[u8],
+    context_v: &[u8],
+    out: &mut [u8],
+) -> Result<(), TpmError> {
+    KdfRandom::new(hash_alg, key, label, context_u, context_v).generate(out)
+}
+
+// KDFe from part 1 of the spec, which derives a seed from the x coordinate
+// `z` of an ECDH shared point. Fills `out` with `out.len()` bytes.
+pub fn kdfe(
+    hash_alg: TpmAlgId,
+    z: &[u8],
+    label: &[u8],
+    party_u: &[u8],
+    party_v: &[u8],
+    out: &mut [u8],
+) -> Result<(), TpmError> {
+    let mut counter = 0u32;
+    let mut offset = 0;
+    while offset < out.len() {
+        counter += 1;
+
+        let mut state = HashState::new(hash_alg)?;
+        state.update(&counter.to_be_bytes());
+        state.update(z);
+        state.update(label);
+        if label.last() != Some(&0) {
+            state.update(&[0]);
+        }
+        state.update(party_u);
+        state.update(party_v);
+        let block = state.finish();
+
+        let n = core::cmp::min(block.size as usize, out.len() - offset);
+        out[offset..offset + n].copy_from_slice(&block.as_slice()[..n]);
+        offset += n;
+    }
+
+    Ok(())
+}
+
+// Source of random bytes for key generation. Ordinary objects draw from the
+// platform, primary objects from a KDF seeded with the hierarchy seed so the
+// same template always produces the same key.
+pub trait RandomSource {
+    fn fill(&mut self, buf: &mut [u8]);
+}
+
+pub struct PlatformRandom {
+    pub get_random: fn(&mut [u8]),
+}
+
+impl RandomSource for PlatformRandom {
+    fn fill(&mut self, buf: &mut [u8]) {
+        (self.get_random)(buf)
+    }
+}
+
+// Stream of KDFa output. Each request is one KDFa invocation whose length
+// is the size of the request, with the block counter carried over from the
+// previous request.
+pub struct KdfRandom<'a> {
+    pub hash_alg: TpmAlgId,
+    pub seed: &'a [u8],
+    pub label: &'a [u8],
+    pub context_u: &'a [u8],
+    pub context_v: &'a [u8],
+    pub counter: u32,
+}
+
+impl<'a> KdfRandom<'a> {
+    pub fn new(
+        hash_alg: TpmAlgId,
+        seed: &'a [u8],
+        label: &'a [u8],
+        context_u: &'a [u8],
+        context_v: &'a [u8],
+    ) -> KdfRandom<'a> {
+        KdfRandom {
+            hash_alg,
+            seed,
+            label,
+            context_u,
+            context_v,
+            counter: 0,
+        }
+    }
+
+    // SP800-108 counter mode KDF with HMAC. The label is always followed
+    // by a zero octet, as the spec requires for labels that aren't already
+    // terminated.
+    pub fn generate(&mut self, out: &mut [u8]) -> Result<(), TpmError> {
+        let bits = (out.len() * 8) as u32;
+
+        let mut offset = 0;
+        while offset < out.len() {
+            self.counter += 1;
+
+            let mut state = HmacState::new(self.hash_alg, self.seed)?;
+            state.update(&self.counter.to_be_bytes());
+            state.update(self.label);
+            if self.label.last() != Some(&0) {
+                state.update(&[0]);
+            }
+            state.update(self.context_u);
+            state.update(self.context_v);
+            state.update(&bits.to_be_bytes());
+            let block = state.finish();
+
+            let n = core::cmp::min(block.size as usize, out.len() - offset);
+            out[offset..offset + n].copy_from_slice(&block.as_slice()[..n]);
+            offset += n;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> RandomSource for KdfRandom<'a> {
+    fn fill(&mut self, buf: &mut [u8]) {
+        // The hash algorithm is validated before a stream is created, so
+        // this can't fail.
+        let _ = self.generate(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::from_hex;
+
+    fn counting<const N: usize>(start: u8) -> [u8; N] {
+        core::array::from_fn(|i| start + i as u8)
+    }
+
+    #[test]
+    fn kdfa_sha256() {
+        let key: [u8; 32] = counting(0);
+        let u: [u8; 32] = counting(0x40);
+        let mut out = [0u8; 16];
+        kdfa(TpmAlgId::Sha256, &key, STORAGE, &u, &[], &mut out).unwrap();
+        assert_eq!(out, from_hex::<16>("3505e4e84b52c2789ad7973162e87d6c"));
+    }
+
+    // Several blocks, and a length that isn't a multiple of the block size.
+    #[test]
+    fn kdfa_sha1_multiple_blocks() {
+        let key: [u8; 32] = counting(0);
+        let u: [u8; 32] = counting(0x40);
+        let v: [u8; 32] = counting(0x80);
+        let mut out = [0u8; 48];
+        kdfa(TpmAlgId::Sha1, &key, INTEGRITY, &u, &v, &mut out).unwrap();
+        assert_eq!(
+            out,
+            from_hex::<48>(
+                "c5873bd05c288e476216c210fa5af0b4ab3af013c37b4906\
+                 de8ccddad6b9a693f1bbe4e7ecf92edee760c1e96890cf9b"
+            )
+        );
+    }
+
+    // A label that's already terminated doesn't get a second zero.
+    #[test]
+    fn kdfe_sha256() {
+        let z: [u8; 32] = counting(0);
+        let u: [u8; 32] = counting(0x40);
+        let v: [u8; 32] = counting(0x80);
+        let mut out = [0u8; 40];
+        kdfe(TpmAlgId::Sha256, &z, IDENTITY, &u, &v, &mut out).unwrap();
+        assert_eq!(
+            out,
+            from_hex::<40>(
+                "a0340f5b67663d0c37b3b2607d17693b167829dfb47db3cb\
+                 7f1ed9ce140870c85391c76da4069d48"
+            )
+        );
+    }
+
+    // Requests from the stream carry the counter over, so the stream isn't
+    // the same as one KDFa of the combined length.
+    #[test]
+    fn kdf_random_continues_counter() {
+        let key: [u8; 32] = counting(0);
+        let mut stream = KdfRandom::new(TpmAlgId::Sha256, &key, STORAGE, &[], &[]);
+        let mut first = [0u8; 16];
+        let mut second = [0u8; 16];
+        stream.generate(&mut first).unwrap();
+        stream.generate(&mut second).unwrap();
+        assert_eq!(stream.counter, 2);
+
+        let mut again = [0u8; 16];
+        kdfa(TpmAlgId::Sha256, &key, STORAGE, &[], &[], &mut again).unwrap();
+        assert_eq!(first, again);
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,17 @@
+pub mod bignum;
+pub mod ecc;
+pub mod hash;
+pub mod kdf;
+pub mod rsa;
+pub mod sym;
+
+// Decode a hex string of exactly 2 * N digits.
+#[cfg(test)]
+pub(crate) fn from_hex<const N: usize>(s: &str) -> [u8; N] {
+    assert_eq!(s.len(), 2 * N);
+    let mut out = [0u8; N];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+    }
+    out
+}
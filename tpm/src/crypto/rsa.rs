@@ -0,0 +1,925 @@
+use crate::crypto::bignum::*;
+use crate::crypto::hash::*;
+use crate::crypto::kdf::RandomSource;
+use crate::types::*;
+use crypto_bigint::modular::runtime_mod::{DynResidue, DynResidueParams};
+use crypto_bigint::{Uint, U1024, U1536, U2048, U3072, U4096, U512};
+
+pub const RSA_DEFAULT_EXPONENT: u32 = 65537;
+
+// A public exponent of zero selects the default. Anything else has to be a
+// prime greater than 2.
+pub fn is_valid_exponent(exponent: u32) -> bool {
+    if exponent == 0 {
+        return true;
+    }
+    if exponent < 3 || exponent.is_multiple_of(2) {
+        return false;
+    }
+
+    let mut i = 3u32;
+    while i.saturating_mul(i) <= exponent {
+        if exponent.is_multiple_of(i) {
+            return false;
+        }
+        i += 2;
+    }
+
+    true
+}
+
+pub fn generate_key(
+    key_bits: u16,
+    exponent: u32,
+    rand: &mut dyn RandomSource,
+    n_out: &mut Tpm2bPublicKeyRsa,
+    p_out: &mut Tpm2bPrivateKeyRsa,
+) -> Result<(), TpmError> {
+    let e = public_exponent(exponent);
+
+    match key_bits {
+        1024 => generate::<{ U1024::LIMBS }, { U512::LIMBS }>(1024, e, rand, n_out, p_out),
+        2048 => generate::<{ U2048::LIMBS }, { U1024::LIMBS }>(2048, e, rand, n_out, p_out),
+        3072 => generate::<{ U3072::LIMBS }, { U1536::LIMBS }>(3072, e, rand, n_out, p_out),
+        4096 => generate::<{ U4096::LIMBS }, { U2048::LIMBS }>(4096, e, rand, n_out, p_out),
+        _ => Err(TpmError::new(TpmRc::KeySize)),
+    }
+}
+
+// Check that the stored prime is a factor of the public modulus, and that
+// both factors fit the private operation: odd, at most half the modulus
+// length, and with e invertible mod p - 1 and q - 1.
+pub fn validate_key(
+    key_bits: u16,
+    exponent: u32,
+    n: &Tpm2bPublicKeyRsa,
+    p: &Tpm2bPrivateKeyRsa,
+) -> Result<(), TpmError> {
+    if n.size != key_bits / 8 || p.size != key_bits / 16 {
+        return Err(TpmError::new(TpmRc::KeySize));
+    }
+
+    let e = U4096::from_u32(public_exponent(exponent));
+    let n: U4096 = from_bytes(n.as_slice());
+    let p: U4096 = from_bytes(p.as_slice());
+    if p <= U4096::ONE || !is_zero(&rem(&n, &p)) {
+        return Err(TpmError::new(TpmRc::Binding));
+    }
+
+    let q = n.wrapping_div(&p);
+    if q <= U4096::ONE || q.bits_vartime() > key_bits as usize / 2 {
+        return Err(TpmError::new(TpmRc::Binding));
+    }
+    for f in [p, q] {
+        if !is_odd(&f) || gcd(&f.wrapping_sub(&U4096::ONE), &e) != U4096::ONE {
+            return Err(TpmError::new(TpmRc::Binding));
+        }
+    }
+
+    Ok(())
+}
+
+fn public_exponent(exponent: u32) -> u32 {
+    match exponent {
+        0 => RSA_DEFAULT_EXPONENT,
+        e => e,
+    }
+}
+
+// L limbs hold the modulus and H limbs hold each prime.
+fn generate<const L: usize, const H: usize>(
+    bits: usize,
+    e: u32,
+    rand: &mut dyn RandomSource,
+    n_out: &mut Tpm2bPublicKeyRsa,
+    p_out: &mut Tpm2bPrivateKeyRsa,
+) -> Result<(), TpmError> {
+    let half = bits / 2;
+    let e = Uint::<H>::from_u32(e);
+
+    loop {
+        let p = generate_prime::<H>(half, &e, rand);
+        let q = generate_prime::<H>(half, &e, rand);
+
+        // The primes must differ somewhere in their top 100 bits (FIPS 186-4
+        // B.3.3).
+        let diff = match p > q {
+            true => p.wrapping_sub(&q),
+            false => q.wrapping_sub(&p),
+        };
+        if diff.bits_vartime() <= half - 100 {
+            continue;
+        }
+
+        let n = p.resize::<L>().wrapping_mul(&q.resize::<L>());
+
+        n_out.size = (bits / 8) as u16;
+        to_bytes(&n, &mut n_out.buffer[..bits / 8]);
+        p_out.size = (half / 8) as u16;
+        to_bytes(&p, &mut p_out.buffer[..half / 8]);
+
+        return Ok(());
+    }
+}
+
+fn generate_prime<const H: usize>(
+    bits: usize,
+    e: &Uint<H>,
+    rand: &mut dyn RandomSource,
+) -> Uint<H> {
+    loop {
+        let candidate: Uint<H> = random_bits(rand, bits);
+
+        // Map the top 32 bits into [0xB504F334, 2^32) so the candidate is at
+        // least sqrt(2) * 2^(bits - 1). The product of two such primes is
+        // then exactly twice as long.
+        let top = candidate.shr_vartime(bits - 32).as_words()[0] as u32;
+        let top = 0xB504_F334 + ((top as u64 * (0xFFFF_FFFF - 0xB504_F334)) >> 32) as u32;
+        let low_mask = Uint::<H>::ONE
+            .shl_vartime(bits - 32)
+            .wrapping_sub(&Uint::ONE);
+        let candidate = candidate
+            .bitand(&low_mask)
+            .bitor(&Uint::from_u32(top).shl_vartime(bits - 32))
+            .bitor(&Uint::ONE);
+
+        // e must be invertible mod p - 1
+        if gcd(&candidate.wrapping_sub(&Uint::ONE), e) != Uint::ONE {
+            continue;
+        }
+
+        if is_probable_prime(&candidate, rand) {
+            return candidate;
+        }
+    }
+}
+
+// RSA_Encrypt with the scheme already picked: RSAES-PKCS1-v1_5, OAEP or
+// TPM_ALG_NULL, which is the raw operation on the message padded with
+// leading zeros. The label is only used by OAEP.
+pub fn encrypt(
+    scheme: &TpmtAsymScheme,
+    exponent: u32,
+    n: &Tpm2bPublicKeyRsa,
+    message: &[u8],
+    label: &[u8],
+    rand: &mut dyn RandomSource,
+    out: &mut Tpm2bPublicKeyRsa,
+) -> Result<(), TpmError> {
+    let k = n.size as usize;
+    let mut em = [0u8; MAX_RSA_KEY_BYTES];
+    let em = &mut em[..k];
+
+    match scheme.scheme {
+        TpmAlgId::Oaep => oaep_pad(scheme.hash_alg, message, label, rand, em)?,
+        TpmAlgId::RsaEs => pkcs1_pad(message, rand, em)?,
+        _ => {
+            if message.len() > k {
+                return Err(TpmError::new(TpmRc::Value));
+            }
+            em[k - message.len()..].copy_from_slice(message);
+        }
+    }
+
+    out.size = k as u16;
+    public_op(exponent, n.as_slice(), em, &mut out.buffer[..k])
+}
+
+// RSA_Decrypt with the scheme already picked. The cipher text has to be
+// exactly as long as the modulus.
+pub fn decrypt(
+    scheme: &TpmtAsymScheme,
+    exponent: u32,
+    n: &Tpm2bPublicKeyRsa,
+    p: &Tpm2bPrivateKeyRsa,
+    cipher_text: &[u8],
+    label: &[u8],
+    out: &mut Tpm2bPublicKeyRsa,
+) -> Result<(), TpmError> {
+    let k = n.size as usize;
+    if cipher_text.len() != k {
+        return Err(TpmError::new(TpmRc::Size));
+    }
+
+    let mut em = [0u8; MAX_RSA_KEY_BYTES];
+    let em = &mut em[..k];
+    private_op(exponent, n.as_slice(), p.as_slice(), cipher_text, em)?;
+
+    let message = match scheme.scheme {
+        TpmAlgId::Oaep => oaep_unpad(scheme.hash_alg, label, em)?,
+        TpmAlgId::RsaEs => pkcs1_unpad(em)?,
+        _ => &em[..],
+    };
+
+    out.size = message.len() as u16;
+    out.buffer[..message.len()].copy_from_slice(message);
+    Ok(())
+}
+
+// RSASSA-PKCS1-v1_5 or RSASSA-PSS signature of a digest made with the
+// scheme's hash
+pub fn sign(
+    scheme: &TpmtAsymScheme,
+    exponent: u32,
+    n: &Tpm2bPublicKeyRsa,
+    p: &Tpm2bPrivateKeyRsa,
+    digest: &[u8],
+    rand: &mut dyn RandomSource,
+    out: &mut Tpm2bPublicKeyRsa,
+) -> Result<(), TpmError> {
+    let k = n.size as usize;
+    let mut em = [0u8; MAX_RSA_KEY_BYTES];
+    let em = &mut em[..k];
+
+    match scheme.scheme {
+        TpmAlgId::RsaSsa => pkcs1_sig_pad(scheme.hash_alg, digest, em)?,
+        TpmAlgId::RsaPss => pss_encode(scheme.hash_alg, digest, rand, em)?,
+        _ => return Err(TpmError::new(TpmRc::Scheme)),
+    }
+
+    out.size = k as u16;
+    private_op(
+        exponent,
+        n.as_slice(),
+        p.as_slice(),
+        em,
+        &mut out.buffer[..k],
+    )
+}
+
+// Check a signature made by `sign`. Anything that doesn't verify is
+// TPM_RC_SIGNATURE.
+pub fn verify(
+    scheme: &TpmtAsymScheme,
+    exponent: u32,
+    n: &Tpm2bPublicKeyRsa,
+    digest: &[u8],
+    signature: &[u8],
+) -> Result<(), TpmError> {
+    let k = n.size as usize;
+    if signature.len() != k {
+        return Err(TpmError::new(TpmRc::Signature));
+    }
+
+    let mut em = [0u8; MAX_RSA_KEY_BYTES];
+    let em = &mut em[..k];
+    public_op(exponent, n.as_slice(), signature, em)
+        .map_err(|_| TpmError::new(TpmRc::Signature))?;
+
+    let good = match scheme.scheme {
+        TpmAlgId::RsaSsa => {
+            let mut expected = [0u8; MAX_RSA_KEY_BYTES];
+            let expected = &mut expected[..k];
+            pkcs1_sig_pad(scheme.hash_alg, digest, expected).is_ok() && expected == em
+        }
+        TpmAlgId::RsaPss => pss_verify(scheme.hash_alg, digest, em)?,
+        _ => return Err(TpmError::new(TpmRc::Scheme)),
+    };
+
+    match good {
+        true => Ok(()),
+        false => Err(TpmError::new(TpmRc::Signature)),
+    }
+}
+
+// m^e mod n. The input has to be smaller than the modulus.
+fn public_op(exponent: u32, n: &[u8], input: &[u8], out: &mut [u8]) -> Result<(), TpmError> {
+    let e = public_exponent(exponent);
+    match n.len() * 8 {
+        1024 => public_op_n::<{ U1024::LIMBS }>(e, n, input, out),
+        2048 => public_op_n::<{ U2048::LIMBS }>(e, n, input, out),
+        3072 => public_op_n::<{ U3072::LIMBS }>(e, n, input, out),
+        4096 => public_op_n::<{ U4096::LIMBS }>(e, n, input, out),
+        _ => Err(TpmError::new(TpmRc::KeySize)),
+    }
+}
+
+fn public_op_n<const L: usize>(
+    e: u32,
+    n: &[u8],
+    input: &[u8],
+    out: &mut [u8],
+) -> Result<(), TpmError> {
+    let n: Uint<L> = from_bytes(n);
+    let m: Uint<L> = from_bytes(input);
+    if m >= n {
+        return Err(TpmError::new(TpmRc::Value));
+    }
+
+    let params = DynResidueParams::new(&n);
+    let c = DynResidue::new(&m, params).pow_bounded_exp(&Uint::<L>::from_u32(e), 32);
+    to_bytes(&c.retrieve(), out);
+
+    Ok(())
+}
+
+// c^d mod n. Only p is stored, so q and the CRT exponents are worked out
+// from it each time.
+fn private_op(
+    exponent: u32,
+    n: &[u8],
+    p: &[u8],
+    input: &[u8],
+    out: &mut [u8],
+) -> Result<(), TpmError> {
+    let e = public_exponent(exponent);
+    match n.len() * 8 {
+        1024 => private_op_n::<{ U1024::LIMBS }, { U512::LIMBS }>(e, n, p, input, out),
+        2048 => private_op_n::<{ U2048::LIMBS }, { U1024::LIMBS }>(e, n, p, input, out),
+        3072 => private_op_n::<{ U3072::LIMBS }, { U1536::LIMBS }>(e, n, p, input, out),
+        4096 => private_op_n::<{ U4096::LIMBS }, { U2048::LIMBS }>(e, n, p, input, out),
+        _ => Err(TpmError::new(TpmRc::KeySize)),
+    }
+}
+
+fn private_op_n<const L: usize, const H: usize>(
+    e: u32,
+    n: &[u8],
+    p: &[u8],
+    input: &[u8],
+    out: &mut [u8],
+) -> Result<(), TpmError> {
+    let n: Uint<L> = from_bytes(n);
+    let c: Uint<L> = from_bytes(input);
+    if c >= n {
+        return Err(TpmError::new(TpmRc::Value));
+    }
+
+    // Loading checked that both factors fit in H limbs.
+    let p_l: Uint<L> = from_bytes(p);
+    let q_l = n.wrapping_div(&p_l);
+    let p = p_l.resize::<H>();
+    let q = q_l.resize::<H>();
+
+    let m1 = crt_exp(e, &c, &p)?;
+    let m2 = crt_exp(e, &c, &q)?;
+
+    // Garner: m = m2 + q * (q^-1 * (m1 - m2) mod p)
+    let params = DynResidueParams::new(&p);
+    let q_mod_p = DynResidue::new(&rem(&q, &p), params);
+    let p_minus_2 = p.wrapping_sub(&Uint::from_u8(2));
+    let q_inv = q_mod_p.pow_bounded_exp(&p_minus_2, Uint::<H>::BITS);
+    let diff = DynResidue::new(&m1, params) - DynResidue::new(&rem(&m2, &p), params);
+    let h = (q_inv * diff).retrieve();
+
+    let m = h
+        .resize::<L>()
+        .wrapping_mul(&q_l)
+        .wrapping_add(&m2.resize::<L>());
+    to_bytes(&m, out);
+
+    Ok(())
+}
+
+// c^(d mod (f - 1)) mod f for the prime factor f
+fn crt_exp<const L: usize, const H: usize>(
+    e: u32,
+    c: &Uint<L>,
+    f: &Uint<H>,
+) -> Result<Uint<H>, TpmError> {
+    let d = crt_exponent::<L, H>(e, f)?;
+    let c = rem(c, &f.resize::<L>()).resize::<H>();
+
+    let params = DynResidueParams::new(f);
+    Ok(DynResidue::new(&c, params)
+        .pow_bounded_exp(&d, Uint::<H>::BITS)
+        .retrieve())
+}
+
+// e^-1 mod (f - 1). With k = -(f - 1)^-1 mod e, 1 + k * (f - 1) is a
+// multiple of e, and dividing it by e gives the inverse. That keeps the
+// modular inversion down to numbers smaller than e. The product is worked
+// out in L limbs since it can be longer than f.
+fn crt_exponent<const L: usize, const H: usize>(e: u32, f: &Uint<H>) -> Result<Uint<H>, TpmError> {
+    let e = e as u64;
+    let f_minus_1 = f.resize::<L>().wrapping_sub(&Uint::ONE);
+    let mut r = [0u8; 8];
+    to_bytes(&rem(&f_minus_1, &Uint::from_u64(e)), &mut r);
+    let r = u64::from_be_bytes(r);
+    let k = match inverse_mod(r, e) {
+        Some(inv) => (e - inv) % e,
+        None => return Err(TpmError::new(TpmRc::Binding)),
+    };
+
+    let t = f_minus_1
+        .wrapping_mul(&Uint::<L>::from_u64(k))
+        .wrapping_add(&Uint::ONE);
+    Ok(t.wrapping_div(&Uint::from_u64(e)).resize::<H>())
+}
+
+// a^-1 mod m by the extended Euclidean algorithm, if a and m are coprime
+fn inverse_mod(a: u64, m: u64) -> Option<u64> {
+    let (mut r0, mut r1) = (m as i128, a as i128);
+    let (mut t0, mut t1) = (0i128, 1i128);
+    while r1 != 0 {
+        let q = r0 / r1;
+        (r0, r1) = (r1, r0 - q * r1);
+        (t0, t1) = (t1, t0 - q * t1);
+    }
+
+    match r0 {
+        1 => Some(t0.rem_euclid(m as i128) as u64),
+        _ => None,
+    }
+}
+
+// MGF1 from PKCS #1: XOR `out` with the mask generated from `seed`.
+fn mgf1_xor(hash_alg: TpmAlgId, seed: &[u8], out: &mut [u8]) -> Result<(), TpmError> {
+    for (counter, chunk) in out.chunks_mut(hash_alg.digest_size()).enumerate() {
+        let mask = hash(hash_alg, &[seed, &(counter as u32).to_be_bytes()])?;
+        for (b, m) in chunk.iter_mut().zip(mask.as_slice()) {
+            *b ^= m;
+        }
+    }
+
+    Ok(())
+}
+
+// EME-OAEP encoding: 0x00 || maskedSeed || maskedDB, where DB is
+// H(label) || zeros || 0x01 || message.
+fn oaep_pad(
+    hash_alg: TpmAlgId,
+    message: &[u8],
+    label: &[u8],
+    rand: &mut dyn RandomSource,
+    em: &mut [u8],
+) -> Result<(), TpmError> {
+    let k = em.len();
+    let h_len = hash_alg.digest_size();
+    if h_len == 0 {
+        return Err(TpmError::new(TpmRc::Hash));
+    }
+    if k < 2 * h_len + 2 || message.len() > k - 2 * h_len - 2 {
+        return Err(TpmError::new(TpmRc::Value));
+    }
+
+    em.fill(0);
+    let (seed, db) = em[1..].split_at_mut(h_len);
+    db[..h_len].copy_from_slice(hash(hash_alg, &[label])?.as_slice());
+    let db_len = db.len();
+    db[db_len - message.len() - 1] = 1;
+    db[db_len - message.len()..].copy_from_slice(message);
+
+    rand.fill(seed);
+    mgf1_xor(hash_alg, seed, db)?;
+    mgf1_xor(hash_alg, db, seed)?;
+
+    Ok(())
+}
+
+fn oaep_unpad<'a>(
+    hash_alg: TpmAlgId,
+    label: &[u8],
+    em: &'a mut [u8],
+) -> Result<&'a [u8], TpmError> {
+    let k = em.len();
+    let h_len = hash_alg.digest_size();
+    if h_len == 0 {
+        return Err(TpmError::new(TpmRc::Hash));
+    }
+    if k < 2 * h_len + 2 {
+        return Err(TpmError::new(TpmRc::Value));
+    }
+
+    let (first, rest) = em.split_at_mut(1);
+    let (seed, db) = rest.split_at_mut(h_len);
+    mgf1_xor(hash_alg, db, seed)?;
+    mgf1_xor(hash_alg, seed, db)?;
+
+    let l_hash = hash(hash_alg, &[label])?;
+    let separator = db[h_len..].iter().position(|b| *b != 0);
+    match separator {
+        Some(i) if first[0] == 0 && db[..h_len] == *l_hash.as_slice() && db[h_len + i] == 1 => {
+            Ok(&db[h_len + i + 1..])
+        }
+        _ => Err(TpmError::new(TpmRc::Value)),
+    }
+}
+
+// EME-PKCS1-v1_5 encoding: 0x00 || 0x02 || at least 8 nonzero random
+// bytes || 0x00 || message.
+fn pkcs1_pad(message: &[u8], rand: &mut dyn RandomSource, em: &mut [u8]) -> Result<(), TpmError> {
+    let k = em.len();
+    if message.len() + 11 > k {
+        return Err(TpmError::new(TpmRc::Value));
+    }
+
+    let ps_len = k - message.len() - 3;
+    em[0] = 0;
+    em[1] = 2;
+    for b in &mut em[2..2 + ps_len] {
+        while *b == 0 {
+            rand.fill(core::slice::from_mut(b));
+        }
+    }
+    em[2 + ps_len] = 0;
+    em[3 + ps_len..].copy_from_slice(message);
+
+    Ok(())
+}
+
+fn pkcs1_unpad(em: &[u8]) -> Result<&[u8], TpmError> {
+    if em[0] != 0 || em[1] != 2 {
+        return Err(TpmError::new(TpmRc::Value));
+    }
+
+    match em[2..].iter().position(|b| *b == 0) {
+        Some(ps_len) if ps_len >= 8 => Ok(&em[3 + ps_len..]),
+        _ => Err(TpmError::new(TpmRc::Value)),
+    }
+}
+
+// DER encoding of the DigestInfo up to the digest itself
+fn digest_info_prefix(hash_alg: TpmAlgId) -> Result<&'static [u8], TpmError> {
+    match hash_alg {
+        TpmAlgId::Sha1 => Ok(&[
+            0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04,
+            0x14,
+        ]),
+        TpmAlgId::Sha256 => Ok(&[
+            0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+            0x01, 0x05, 0x00, 0x04, 0x20,
+        ]),
+        TpmAlgId::Sha384 => Ok(&[
+            0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+            0x02, 0x05, 0x00, 0x04, 0x30,
+        ]),
+        TpmAlgId::Sha512 => Ok(&[
+            0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+            0x03, 0x05, 0x00, 0x04, 0x40,
+        ]),
+        _ => Err(TpmError::new(TpmRc::Hash)),
+    }
+}
+
+// EMSA-PKCS1-v1_5 encoding: 0x00 || 0x01 || 0xff... || 0x00 || DigestInfo
+fn pkcs1_sig_pad(hash_alg: TpmAlgId, digest: &[u8], em: &mut [u8]) -> Result<(), TpmError> {
+    let prefix = digest_info_prefix(hash_alg)?;
+    let k = em.len();
+    let t_len = prefix.len() + digest.len();
+    if digest.len() != hash_alg.digest_size() || t_len + 11 > k {
+        return Err(TpmError::new(TpmRc::Value));
+    }
+
+    em[0] = 0;
+    em[1] = 1;
+    em[2..k - t_len - 1].fill(0xff);
+    em[k - t_len - 1] = 0;
+    em[k - t_len..k - digest.len()].copy_from_slice(prefix);
+    em[k - digest.len()..].copy_from_slice(digest);
+
+    Ok(())
+}
+
+// H(0^64 || mHash || salt)
+fn pss_hash(hash_alg: TpmAlgId, digest: &[u8], salt: &[u8]) -> Result<Tpm2bDigest, TpmError> {
+    hash(hash_alg, &[&[0u8; 8], digest, salt])
+}
+
+// EMSA-PSS encoding: maskedDB || H || 0xbc, where DB is zeros || 0x01 ||
+// salt. The modulus is always a whole number of bytes, so emBits is one
+// less and only the top bit of EM is cleared. The salt is as long as the
+// digest, or as long as will fit.
+fn pss_encode(
+    hash_alg: TpmAlgId,
+    digest: &[u8],
+    rand: &mut dyn RandomSource,
+    em: &mut [u8],
+) -> Result<(), TpmError> {
+    let k = em.len();
+    let h_len = hash_alg.digest_size();
+    if h_len == 0 {
+        return Err(TpmError::new(TpmRc::Hash));
+    }
+    if digest.len() != h_len || k < 2 * h_len + 2 {
+        return Err(TpmError::new(TpmRc::Value));
+    }
+    let s_len = h_len.min(k - h_len - 2);
+
+    em.fill(0);
+    let (db, rest) = em.split_at_mut(k - h_len - 1);
+    let db_len = db.len();
+    let salt = &mut db[db_len - s_len..];
+    rand.fill(salt);
+    let h = pss_hash(hash_alg, digest, salt)?;
+    db[db_len - s_len - 1] = 1;
+
+    rest[..h_len].copy_from_slice(h.as_slice());
+    rest[h_len] = 0xbc;
+    mgf1_xor(hash_alg, h.as_slice(), db)?;
+    db[0] &= 0x7f;
+
+    Ok(())
+}
+
+// Whether EM is a PSS encoding of the digest, with a salt of any length
+fn pss_verify(hash_alg: TpmAlgId, digest: &[u8], em: &mut [u8]) -> Result<bool, TpmError> {
+    let k = em.len();
+    let h_len = hash_alg.digest_size();
+    if h_len == 0 {
+        return Err(TpmError::new(TpmRc::Hash));
+    }
+    if digest.len() != h_len || k < 2 * h_len + 2 || em[k - 1] != 0xbc || em[0] & 0x80 != 0 {
+        return Ok(false);
+    }
+
+    let (db, rest) = em.split_at_mut(k - h_len - 1);
+    let h = &rest[..h_len];
+    mgf1_xor(hash_alg, h, db)?;
+    db[0] &= 0x7f;
+
+    let salt = match db.iter().position(|b| *b != 0) {
+        Some(i) if db[i] == 1 => &db[i + 1..],
+        _ => return Ok(false),
+    };
+
+    Ok(pss_hash(hash_alg, digest, salt)?.as_slice() == h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::from_hex;
+    use crate::crypto::kdf::KdfRandom;
+
+    // A 1024-bit key and an RSASSA-PKCS1-v1_5 signature of SHA-256("abc")
+    // made with it by an independent implementation.
+    const N: &str = "a1de113c88b0762bee1b10a019cebb134ed81dbc6a85b8e2508f76b83876223c\
+                     a1bea1b7e1d727cf58afdb6452d2cc2c17e321e2259961c4c6af3be6ad9e81e3\
+                     120a8dab403012a264271161226f51a94bb2b35dedaa07dff708611986657dee\
+                     e3492cca61ed0afa4d44c48cda3235965c6789e1bf47d317d2b4906a99c84de5";
+    const P: &str = "d0886c4900a113ef88991ec3fb5503bf1ced684f7a11b18cca92d6804756687c\
+                     286cc052c2260d98190fd16ef5455bc342792da97e1951a863f7fe9c1adfe33f";
+    const SIGNATURE: &str = "68bca4bc4a71067bad6d77bcfa04e10bada6b719cbd5d96598ac059101503ece\
+                             0da45dd2229adfedbca3071d95880e6fe2b3ce7f6f0effe138c25435d17b3de5\
+                             9055ebf30803ba1355fbfe7c164c5ab730719b76a30520f53a2950523332e1fe\
+                             f43ee8ea19072ac4fe5fea470475d831aeb53811a5d3a5b585fa935c0a24ee30";
+
+    // "secret" encrypted to the same key by an independent implementation,
+    // with SHA-256 OAEP and the label "label\0", and with RSAES-PKCS1-v1_5.
+    const OAEP: &str = "407f5827e43b7dc747ce2561ef7e26b2c6a6f91826c280cc1eef09f6352f2373\
+                        85dd17237b067be216dbc997b388b48b38d0aafb48c9867bacf921d475dd1947\
+                        31e24ded3c437be6d4caf29ffc358a0ab62cf99672cf2f68f2e4af6c377dc481\
+                        43a503f29a0bba3ebd4086dfd4fc7cdaf78d58ce51ae3fecdb0962120ad0f908";
+    const PKCS1: &str = "8ef72c8603bb4b073a8c07ca1cba767bbaa4f476921b2b54389c88f18e1291b0\
+                         4f329172ffaf123831171309eb119aab80cd34980fefaba44f9900b0f18bf2e6\
+                         93f101ff839fcc9167a693f07087177639594515d6a0f7c3ea56d55bd4b0b5d1\
+                         2bebb348d511f78a0c6f532d1a5f352604cc11269ea53514b19ce8a5afdb7518";
+
+    // 3072 and 4096-bit keys and "secret" encrypted to them with SHA-256
+    // OAEP and no label, from the same implementation. Generating keys this
+    // long takes too long for a debug build.
+    const N_3072: &str = "aaf450970e75fd8e4e583b383fb4e7223e090a10fbc43a1c864b2098aca664b7\
+                          15540386c5967c0e5ece04ddc58656564713f3d1d22fd9b73cd4c47fd7640443\
+                          c316eec6a8a60fbe97966b8cb037fbc518f8fa73d5898197bf303d4dd1baf161\
+                          91113e17e2ecaf1fd695e1d45b8d61512f96592688c57ed9af584b565364b01a\
+                          e3081fe88c049c7224f255314d90802b0538e99bfc07e8c7a5f73baf474d2dd7\
+                          ad43cfffc8a33dde72444bc8969d15f8894aa9c332f217c75a3203f7d01a16a1\
+                          f47ef84634fac38261d79a91355ba8802b9ac216832e699ca083dedbcda17651\
+                          2edd90565e145f9b749cae7472f64b6ce018bd5f3991cc289b92869db23bac04\
+                          7eec3f0f6960fbb5cd786fae0c7a18e2301ebd5b77f66ad486ddfb50fadaef81\
+                          c4e8bdad1d7df53406d8ac1aeb96b44e6c37a51840afb527d7511eb626a65534\
+                          616ee7edd73dcc2afff1afd7bea9a800d428fe1a99a76499d8baa773e513fb48\
+                          1a7020387befb76176830e456849a0869d096a9a1417f2be1eec93b5a8f6f35d";
+    const P_3072: &str = "eb24639486fb9a7c10f5c4f89e51e991452960fdf8c3a83308f99cfc55edfc51\
+                          6d1c289b71e978e375d4390a86510e01019497598c996dfd7f6f7a55ae6f4306\
+                          5aa237088e9706281c304aca8057519aff67b1b17c78fefd53e5e357f287d0d9\
+                          6bffe0b5f79e8a0e092e4b11a9633e46bd3c0d2121b67b845e17be13386c18a0\
+                          df15c01cab2dee3453ad731e7efb94e8d523916fcca33de46e88c4da2310ebf0\
+                          3845a893cdbc3a20b0a98eb48399869cedba335ac8656efeee8fc6bd4838d719";
+    const OAEP_3072: &str = "a850f10f7576c61d31097ce6d3971a7511700cb7568ff135b7ab520243239503\
+                             809be0cee7d8fd70ba5f05b6c39bdc96c9821a8f8abc366bbe92050cd0cbcac1\
+                             5fde83ddb741733fa8aeaa9c1fb31e6134ec44e0b1e73c83dcf840e8433d223c\
+                             0f807ba30ca2066d2ae7bb4803f1a96349ab4a9c57f21e3cc5b5aefa02fd60c0\
+                             6d5e372846de493f149bae343ff965ad03984bc8d1154f0aa87af31e004b2a5b\
+                             8be3c6cc4f3220359ffcaa6e929da39b2fb77b3bdd373a13cf80dfcbeb53076a\
+                             62ab02ce84b0865a944ba2cefa7b6adf0568e51c1c80d0a8874ee43944a88d74\
+                             ed7a0e129525bb5f41f357f4ee963e3231d1811b9a08ff22497698d2f058c9a4\
+                             3b1cd423fe22b425141486ee53a554898b8587cb7f27852603c911e342882228\
+                             14a7122a70ce71ea931bc69042fc51d975b3b965b1db8c47065693fb8d19e479\
+                             a86de38f19a8da1f2911703280a14a504e6475ae0ad80cf269e306581d689646\
+                             9a1a89d833f0e05d38096e67c8dde7df58bb48ecf770730762b85d831344d728";
+    const N_4096: &str = "9bb1c2ad8b433afdf2265317e2fd3bab955cff8bff97c6248ac326e7db119640\
+                          cca5433837b8c66962fac6cd2a73c79215e11dee2addc772b1d84a018a22c1d3\
+                          f0b99955855080b5a2fbf102ba12478b970e0a14c11f7f9895f58869fbf04cc4\
+                          85be9188413057b4c6eeb849e6397cd42e04ad5159d62492688fa052b3d0d5e8\
+                          92ffe64015da8ab6f6cc94607c2b3f1e2dc06667df497e84d5b39f9cb49692d2\
+                          cfa8245f3034f0e8ffd7f33ac40f8bb3ecb35a10266b0c8bcb9305b75693e9be\
+                          0a8885395721a7952904b8f5d29fa883ea359be2d1401a7f8e1633dd65b5e024\
+                          6ae25cd4fb7962142755575ceff0a8068e3361f0010ac4cdbbeaeb7de9db221e\
+                          fa6aa0e6eb117a32386e558653549503cd122d026f55cff23a52b65657f53cda\
+                          9b1f77350429ebe7f90fe8c5ee72bac4239f41f41a50c41e06eebe856d214352\
+                          8720146f32b0f0d6e64fe6593512fad4ba457166806be298d1f73aad8b988bd4\
+                          c27951a40b6e0f25be7174e8d50263f8c31da606fec7ca47a3897ba2fb2ea602\
+                          f07e33529d4315e6193470a5230f0ddb5f48db1a82b9db652c635d1f818a7352\
+                          05336c6b07177203990d0f006767994e1421ced718e013902daa8a93790b67ca\
+                          20516a09dbb598a7eb99fff05e9b90a18ca44a53fb0283b4a39aa5db97189185\
+                          a22a1911edadb7a96a1395416a654317f0164cbbbacf00618b0d27c3687cbd85";
+    const P_4096: &str = "ce14180d46aff3411fb47c1e117d5d3613eef783cbb739e76e9677a32964a5c8\
+                          9ac42d3397b455fbc13c911d26142ed9ae4472f1c11085811ab7bec137fda2f3\
+                          7f8edc5371bca6ccbab30c346a60838de22f273bb1f67ee0f9894ab5b0d56b99\
+                          b55c2d5d5f5fd0f60fd51a902131ebb79f40d2396476c9c2ad2de126ad6588ea\
+                          bd51ccf577680637e33d1c62a19d2373160cff11f57fbca764685b53c3cd0f3e\
+                          64169f3dbb0bb699fd1f18e52b7f08fc2f1192de2e35cd33e032b2b14bd1ddd5\
+                          21ee40ae2ad15cd3adf19e2dde429373abab5df5be8c5667adb7c6aa8540976a\
+                          fd7605aa6ca1f20fa45697aa5679fb2f8b21736a06f0af584b2b72bd1d245ccf";
+    const OAEP_4096: &str = "26ed808df3fbd5db7d4af4607591f08b0491f10868554fe44531dd9730f4995b\
+                             e83b95cab591bcc93568a16d95b15566b253e0dce20e95c238ddff3189435acc\
+                             80e4537fac548c958bbff44f1f26c8c584890004020a1dfaa9c74ef646465245\
+                             30642bb4e1f7b1e62ead35b5c2fd5602affb2a385ab927a05079f610bce706b1\
+                             51dea03f7559fdb8cd8836579d5d98738c8425e025c37d25d328e42e9957f80d\
+                             2db603a2c48a772955f3d56d77aa4d001394b19f6dce9030bb98e2f4b459adb3\
+                             e9f7051e6f8486c368c6fd0c69d1ee9e121df93d44a2b3ab515ed2b7ec68adcb\
+                             ef16b9d83fc80058eca5312aad9212012994c219504919801163269443bc8f20\
+                             fe1fd661b600bd48f43766424f4a4b7b115319de84b589fc87628e88d615a808\
+                             8a3fcfec8431ab9f10469f0088b5be40d001a3193bebe6bf155a9871a4e57a67\
+                             cea5b2cc7f7004a77a5c17cb9032ac274c71f85509b61acc74062a6c8b623c93\
+                             cf57a7fb1c2ecfad2f4ec36566873656c86fad8b7fdd8a14a8d862ca3af1387c\
+                             5877b558e2f7951eddc401ffc509a7a2ed591d8a0500446e116bea6a18b4c49d\
+                             06e20892a7a7b0c6c3e5dd020bfa7ec3dd82251b430f93253f966565deb06c56\
+                             7d4709e61281af6aa011b2d0bf6cd40cccf57c60386018d55a705d684a0a0946\
+                             c65b756e83e6ae0d8889625eaecb06cda6256bccb7dd53dee3922fa9d42c1cf9";
+
+    fn key() -> (Tpm2bPublicKeyRsa, Tpm2bPrivateKeyRsa) {
+        let n = Tpm2bPublicKeyRsa::from_slice(&from_hex::<128>(N)).unwrap();
+        let p = Tpm2bPrivateKeyRsa::from_slice(&from_hex::<64>(P)).unwrap();
+        (n, p)
+    }
+
+    fn scheme(scheme: TpmAlgId) -> TpmtAsymScheme {
+        TpmtAsymScheme {
+            scheme,
+            hash_alg: TpmAlgId::Sha256,
+            count: 0,
+        }
+    }
+
+    #[test]
+    fn rsassa_known_signature() {
+        let (n, p) = key();
+        let digest = hash(TpmAlgId::Sha256, &[b"abc"]).unwrap();
+        let mut rand = KdfRandom::new(TpmAlgId::Sha256, &[1], b"TEST", &[], &[]);
+
+        let mut signature = Tpm2bPublicKeyRsa::default();
+        let scheme = scheme(TpmAlgId::RsaSsa);
+        sign(
+            &scheme,
+            0,
+            &n,
+            &p,
+            digest.as_slice(),
+            &mut rand,
+            &mut signature,
+        )
+        .unwrap();
+        assert_eq!(signature.as_slice(), from_hex::<128>(SIGNATURE));
+
+        verify(&scheme, 0, &n, digest.as_slice(), signature.as_slice()).unwrap();
+        let other = hash(TpmAlgId::Sha256, &[b"abd"]).unwrap();
+        assert!(verify(&scheme, 0, &n, other.as_slice(), signature.as_slice()).is_err());
+    }
+
+    #[test]
+    fn rsapss_round_trip() {
+        let (n, p) = key();
+        let digest = hash(TpmAlgId::Sha256, &[b"abc"]).unwrap();
+        let mut rand = KdfRandom::new(TpmAlgId::Sha256, &[1], b"TEST", &[], &[]);
+
+        let mut signature = Tpm2bPublicKeyRsa::default();
+        let scheme = scheme(TpmAlgId::RsaPss);
+        sign(
+            &scheme,
+            0,
+            &n,
+            &p,
+            digest.as_slice(),
+            &mut rand,
+            &mut signature,
+        )
+        .unwrap();
+        verify(&scheme, 0, &n, digest.as_slice(), signature.as_slice()).unwrap();
+
+        signature.buffer[10] ^= 1;
+        assert!(verify(&scheme, 0, &n, digest.as_slice(), signature.as_slice()).is_err());
+    }
+
+    #[test]
+    fn oaep_known_cipher_text() {
+        let (n, p) = key();
+        let scheme = scheme(TpmAlgId::Oaep);
+        let cipher_text = from_hex::<128>(OAEP);
+
+        let mut message = Tpm2bPublicKeyRsa::default();
+        decrypt(&scheme, 0, &n, &p, &cipher_text, b"label\0", &mut message).unwrap();
+        assert_eq!(message.as_slice(), b"secret");
+
+        let rc = decrypt(&scheme, 0, &n, &p, &cipher_text, b"other\0", &mut message);
+        assert!(rc.is_err());
+        let rc = decrypt(&scheme, 0, &n, &p, &cipher_text, &[], &mut message);
+        assert!(rc.is_err());
+    }
+
+    #[test]
+    fn pkcs1_known_cipher_text() {
+        let (n, p) = key();
+        let scheme = scheme(TpmAlgId::RsaEs);
+        let mut message = Tpm2bPublicKeyRsa::default();
+        decrypt(
+            &scheme,
+            0,
+            &n,
+            &p,
+            &from_hex::<128>(PKCS1),
+            &[],
+            &mut message,
+        )
+        .unwrap();
+        assert_eq!(message.as_slice(), b"secret");
+    }
+
+    // The padding is random, so encryption can only be checked by
+    // decrypting.
+    #[test]
+    fn encrypt_round_trip() {
+        let (n, p) = key();
+        let mut rand = KdfRandom::new(TpmAlgId::Sha256, &[1], b"TEST", &[], &[]);
+        for alg in [TpmAlgId::Oaep, TpmAlgId::RsaEs] {
+            let scheme = scheme(alg);
+            let mut cipher_text = Tpm2bPublicKeyRsa::default();
+            encrypt(
+                &scheme,
+                0,
+                &n,
+                b"secret",
+                b"label\0",
+                &mut rand,
+                &mut cipher_text,
+            )
+            .unwrap();
+
+            let mut message = Tpm2bPublicKeyRsa::default();
+            let cipher_text = cipher_text.as_slice();
+            decrypt(&scheme, 0, &n, &p, cipher_text, b"label\0", &mut message).unwrap();
+            assert_eq!(message.as_slice(), b"secret");
+        }
+
+        // The message has to leave room for the padding.
+        let mut cipher_text = Tpm2bPublicKeyRsa::default();
+        let long = [1u8; 128 - 10];
+        let rc = encrypt(
+            &scheme(TpmAlgId::RsaEs),
+            0,
+            &n,
+            &long,
+            &[],
+            &mut rand,
+            &mut cipher_text,
+        );
+        assert!(rc.is_err());
+    }
+
+    // Keys come from the random source alone, so the same seed gives the
+    // same key. That's what makes primary keys reproducible.
+    #[test]
+    fn deterministic_generation() {
+        let generate = |seed: &[u8]| {
+            let mut rand = KdfRandom::new(TpmAlgId::Sha256, seed, b"TEST", &[], &[]);
+            let mut n = Tpm2bPublicKeyRsa::default();
+            let mut p = Tpm2bPrivateKeyRsa::default();
+            generate_key(1024, 0, &mut rand, &mut n, &mut p).unwrap();
+            (n, p)
+        };
+
+        let (n1, p1) = generate(&[1]);
+        let (n2, p2) = generate(&[1]);
+        assert_eq!(n1.as_slice(), n2.as_slice());
+        assert_eq!(p1.as_slice(), p2.as_slice());
+        validate_key(1024, 0, &n1, &p1).unwrap();
+    }
+
+    #[test]
+    fn large_keys() {
+        fn check<const N: usize, const P: usize>(n: &str, p: &str, cipher_text: &str) {
+            let n = Tpm2bPublicKeyRsa::from_slice(&from_hex::<N>(n)).unwrap();
+            let p = Tpm2bPrivateKeyRsa::from_slice(&from_hex::<P>(p)).unwrap();
+            let bits = N as u16 * 8;
+            validate_key(bits, 0, &n, &p).unwrap();
+
+            let scheme = scheme(TpmAlgId::Oaep);
+            let mut message = Tpm2bPublicKeyRsa::default();
+            let cipher_text = from_hex::<N>(cipher_text);
+            decrypt(&scheme, 0, &n, &p, &cipher_text, &[], &mut message).unwrap();
+            assert_eq!(message.as_slice(), b"secret");
+
+            let mut rand = KdfRandom::new(TpmAlgId::Sha256, &[1], b"TEST", &[], &[]);
+            let mut cipher_text = Tpm2bPublicKeyRsa::default();
+            encrypt(&scheme, 0, &n, b"secret", &[], &mut rand, &mut cipher_text).unwrap();
+            let cipher_text = cipher_text.as_slice();
+            decrypt(&scheme, 0, &n, &p, cipher_text, &[], &mut message).unwrap();
+            assert_eq!(message.as_slice(), b"secret");
+        }
+
+        check::<384, 192>(N_3072, P_3072, OAEP_3072);
+        check::<512, 256>(N_4096, P_4096, OAEP_4096);
+    }
+}
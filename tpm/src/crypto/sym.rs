@@ -0,0 +1,308 @@
+use crate::types::*;
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::{Aes128, Aes192, Aes256};
+#[cfg(feature = "camellia")]
+use camellia::{Camellia128, Camellia192, Camellia256};
+#[cfg(feature = "sm4")]
+use sm4::Sm4;
+
+pub const AES_BLOCK_SIZE: usize = 16;
+#[cfg(feature = "sm4")]
+const SM4_BLOCK_SIZE: usize = 16;
+#[cfg(feature = "camellia")]
+const CAMELLIA_BLOCK_SIZE: usize = 16;
+
+// Run `mode` over `data` in place. `iv` ends up as the IV that continues the
+// chain, which is what TPM2_EncryptDecrypt returns as ivOut: the last
+// cipher text block for CFB and CBC (a short final CFB block is padded with
+// zeros), the last key stream block for OFB and the next counter for CTR.
+fn crypt<C>(
+    encrypt: bool,
+    mode: TpmAlgId,
+    key: &[u8],
+    iv: &mut [u8],
+    data: &mut [u8],
+) -> Result<(), TpmError>
+where
+    C: BlockEncrypt + BlockDecrypt + KeyInit,
+{
+    let cipher = C::new_from_slice(key).map_err(|_| TpmError::new(TpmRc::KeySize))?;
+    let block_size = C::block_size();
+    if mode != TpmAlgId::Ecb && iv.len() != block_size {
+        return Err(TpmError::new(TpmRc::Size));
+    }
+    let whole_blocks = data.len().is_multiple_of(block_size);
+
+    match mode {
+        TpmAlgId::Cfb => {
+            for chunk in data.chunks_mut(block_size) {
+                let mut stream = GenericArray::clone_from_slice(iv);
+                cipher.encrypt_block(&mut stream);
+                iv.fill(0);
+                for (i, b) in chunk.iter_mut().enumerate() {
+                    let input = *b;
+                    *b ^= stream[i];
+                    iv[i] = if encrypt { *b } else { input };
+                }
+            }
+        }
+        TpmAlgId::Ofb => {
+            for chunk in data.chunks_mut(block_size) {
+                cipher.encrypt_block(GenericArray::from_mut_slice(iv));
+                chunk.iter_mut().zip(iv.iter()).for_each(|(b, s)| *b ^= s);
+            }
+        }
+        TpmAlgId::Ctr => {
+            for chunk in data.chunks_mut(block_size) {
+                let mut stream = GenericArray::clone_from_slice(iv);
+                cipher.encrypt_block(&mut stream);
+                chunk
+                    .iter_mut()
+                    .zip(stream.iter())
+                    .for_each(|(b, s)| *b ^= s);
+                // The whole block is a big-endian counter.
+                for b in iv.iter_mut().rev() {
+                    *b = b.wrapping_add(1);
+                    if *b != 0 {
+                        break;
+                    }
+                }
+            }
+        }
+        TpmAlgId::Cbc if whole_blocks => {
+            for chunk in data.chunks_mut(block_size) {
+                if encrypt {
+                    chunk.iter_mut().zip(iv.iter()).for_each(|(b, v)| *b ^= v);
+                    cipher.encrypt_block(GenericArray::from_mut_slice(chunk));
+                    iv.copy_from_slice(chunk);
+                } else {
+                    let mut next = [0u8; MAX_SYM_BLOCK_SIZE];
+                    next[..block_size].copy_from_slice(chunk);
+                    cipher.decrypt_block(GenericArray::from_mut_slice(chunk));
+                    chunk.iter_mut().zip(iv.iter()).for_each(|(b, v)| *b ^= v);
+                    iv.copy_from_slice(&next[..block_size]);
+                }
+            }
+        }
+        TpmAlgId::Ecb if whole_blocks => {
+            for chunk in data.chunks_mut(block_size) {
+                match encrypt {
+                    true => cipher.encrypt_block(GenericArray::from_mut_slice(chunk)),
+                    false => cipher.decrypt_block(GenericArray::from_mut_slice(chunk)),
+                }
+            }
+        }
+        TpmAlgId::Cbc | TpmAlgId::Ecb => return Err(TpmError::new(TpmRc::Size)),
+        _ => return Err(TpmError::new(TpmRc::Mode)),
+    }
+
+    Ok(())
+}
+
+// The cipher is picked by the algorithm and the key size.
+fn sym_crypt(
+    encrypt: bool,
+    alg: TpmAlgId,
+    mode: TpmAlgId,
+    key: &[u8],
+    iv: &mut [u8],
+    data: &mut [u8],
+) -> Result<(), TpmError> {
+    match (alg, key.len()) {
+        (TpmAlgId::Aes, 16) => crypt::<Aes128>(encrypt, mode, key, iv, data),
+        (TpmAlgId::Aes, 24) => crypt::<Aes192>(encrypt, mode, key, iv, data),
+        (TpmAlgId::Aes, 32) => crypt::<Aes256>(encrypt, mode, key, iv, data),
+        #[cfg(feature = "sm4")]
+        (TpmAlgId::Sm4, 16) => crypt::<Sm4>(encrypt, mode, key, iv, data),
+        #[cfg(feature = "camellia")]
+        (TpmAlgId::Camellia, 16) => crypt::<Camellia128>(encrypt, mode, key, iv, data),
+        #[cfg(feature = "camellia")]
+        (TpmAlgId::Camellia, 24) => crypt::<Camellia192>(encrypt, mode, key, iv, data),
+        #[cfg(feature = "camellia")]
+        (TpmAlgId::Camellia, 32) => crypt::<Camellia256>(encrypt, mode, key, iv, data),
+        _ if block_size(alg) != 0 => Err(TpmError::new(TpmRc::KeySize)),
+        _ => Err(TpmError::new(TpmRc::Symmetric)),
+    }
+}
+
+pub fn encrypt(
+    alg: TpmAlgId,
+    mode: TpmAlgId,
+    key: &[u8],
+    iv: &mut [u8],
+    data: &mut [u8],
+) -> Result<(), TpmError> {
+    sym_crypt(true, alg, mode, key, iv, data)
+}
+
+pub fn decrypt(
+    alg: TpmAlgId,
+    mode: TpmAlgId,
+    key: &[u8],
+    iv: &mut [u8],
+    data: &mut [u8],
+) -> Result<(), TpmError> {
+    sym_crypt(false, alg, mode, key, iv, data)
+}
+
+// CFB without chaining, as used for context blobs and private areas.
+pub fn cfb_encrypt(alg: TpmAlgId, key: &[u8], iv: &[u8], data: &mut [u8]) -> Result<(), TpmError> {
+    let mut chain = [0u8; MAX_SYM_BLOCK_SIZE];
+    let chain = &mut chain[..iv.len()];
+    chain.copy_from_slice(iv);
+    encrypt(alg, TpmAlgId::Cfb, key, chain, data)
+}
+
+pub fn cfb_decrypt(alg: TpmAlgId, key: &[u8], iv: &[u8], data: &mut [u8]) -> Result<(), TpmError> {
+    let mut chain = [0u8; MAX_SYM_BLOCK_SIZE];
+    let chain = &mut chain[..iv.len()];
+    chain.copy_from_slice(iv);
+    decrypt(alg, TpmAlgId::Cfb, key, chain, data)
+}
+
+pub fn block_size(alg: TpmAlgId) -> usize {
+    match alg {
+        TpmAlgId::Aes => AES_BLOCK_SIZE,
+        #[cfg(feature = "sm4")]
+        TpmAlgId::Sm4 => SM4_BLOCK_SIZE,
+        #[cfg(feature = "camellia")]
+        TpmAlgId::Camellia => CAMELLIA_BLOCK_SIZE,
+        _ => 0,
+    }
+}
+
+// CMAC from NIST SP 800-38B, as a CBC-MAC over the cipher. Like the hash
+// states it's plain values, so a sequence context can carry it.
+#[derive(Clone, Copy)]
+pub struct CmacState {
+    pub(crate) alg: TpmAlgId,
+    pub(crate) key: Tpm2bSymKey,
+    pub(crate) chain: [u8; MAX_SYM_BLOCK_SIZE],
+    // The last block is held back until it's known to be the final one,
+    // which gets a subkey mixed in.
+    pub(crate) block: [u8; MAX_SYM_BLOCK_SIZE],
+    pub(crate) fill: usize,
+}
+
+impl CmacState {
+    pub fn new(alg: TpmAlgId, key: &[u8]) -> Result<CmacState, TpmError> {
+        // Encrypting a block checks the key size for the algorithm.
+        let mut zero = [0u8; MAX_SYM_BLOCK_SIZE];
+        encrypt(
+            alg,
+            TpmAlgId::Ecb,
+            key,
+            &mut [],
+            &mut zero[..block_size(alg)],
+        )?;
+
+        Ok(CmacState {
+            alg,
+            key: Tpm2bSymKey::from_slice(key)?,
+            chain: [0; MAX_SYM_BLOCK_SIZE],
+            block: [0; MAX_SYM_BLOCK_SIZE],
+            fill: 0,
+        })
+    }
+
+    // chain = E(chain ^ block), which is one step of CBC. The key was
+    // checked when the state was created, so encryption can't fail.
+    fn chain_block(&mut self) {
+        let n = block_size(self.alg);
+        let mut data = self.block;
+        encrypt(
+            self.alg,
+            TpmAlgId::Cbc,
+            self.key.as_slice(),
+            &mut self.chain[..n],
+            &mut data[..n],
+        )
+        .unwrap();
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        let n = block_size(self.alg);
+        while !data.is_empty() {
+            if self.fill == n {
+                self.chain_block();
+                self.fill = 0;
+            }
+
+            let take = core::cmp::min(n - self.fill, data.len());
+            self.block[self.fill..self.fill + take].copy_from_slice(&data[..take]);
+            self.fill += take;
+            data = &data[take..];
+        }
+    }
+
+    // A full final block is mixed with K1, a padded one with K2, where K1
+    // and K2 are doublings of E(0). The MAC is a whole cipher block.
+    pub fn finish(mut self) -> Tpm2bDigest {
+        let n = block_size(self.alg);
+        let mut subkey = [0u8; MAX_SYM_BLOCK_SIZE];
+        encrypt(
+            self.alg,
+            TpmAlgId::Ecb,
+            self.key.as_slice(),
+            &mut [],
+            &mut subkey[..n],
+        )
+        .unwrap();
+        double(&mut subkey[..n]);
+        if self.fill < n {
+            self.block[self.fill] = 0x80;
+            self.block[self.fill + 1..n].fill(0);
+            double(&mut subkey[..n]);
+        }
+        for (b, k) in self.block[..n].iter_mut().zip(subkey.iter()) {
+            *b ^= k;
+        }
+        self.chain_block();
+
+        let mut digest = Tpm2bDigest::default();
+        digest.buffer[..n].copy_from_slice(&self.chain[..n]);
+        digest.size = n as u16;
+
+        digest
+    }
+}
+
+// Multiply by x in GF(2^128).
+fn double(block: &mut [u8]) {
+    let carry = block[0] >> 7;
+    for i in 0..block.len() - 1 {
+        block[i] = (block[i] << 1) | (block[i + 1] >> 7);
+    }
+    let last = block.len() - 1;
+    block[last] = (block[last] << 1) ^ (carry * 0x87);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::from_hex;
+
+    // RFC 4493 examples 1 to 4: empty, one block, a partial last block and
+    // a full last block.
+    #[test]
+    fn cmac_aes128() {
+        let key = from_hex::<16>("2b7e151628aed2a6abf7158809cf4f3c");
+        let message = from_hex::<64>(
+            "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51\
+             30c81c46a35ce411e5fbc1191a0a52eff69f2445df4f9b17ad2b417be66c3710",
+        );
+        let expected = [
+            (0, from_hex::<16>("bb1d6929e95937287fa37d129b756746")),
+            (16, from_hex::<16>("070a16b46b4d4144f79bdd9dd04a287c")),
+            (40, from_hex::<16>("dfa66747de9ae63030ca32611497c827")),
+            (64, from_hex::<16>("51f0bebf7e3b9d92fc49741779363cfe")),
+        ];
+        for (length, mac) in expected {
+            let mut state = CmacState::new(TpmAlgId::Aes, &key).unwrap();
+            state.update(&message[..length / 2]);
+            state.update(&message[length / 2..length]);
+            assert_eq!(state.finish().as_slice(), mac);
+        }
+    }
+}
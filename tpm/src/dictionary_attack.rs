@@ -0,0 +1,214 @@
+use crate::marshal::*;
+use crate::nv::*;
+use crate::tpm::*;
+use crate::types::*;
+
+// Dictionary attack protection. Every wrong authValue for a DA-protected
+// entity counts towards maxTries, after which they're all locked out. The
+// count goes down by one every recoveryTime seconds the TPM is powered.
+// lockoutAuth gets one try, and after a failure can't be used again for
+// lockoutRecovery seconds.
+
+// Marks the dictionary attack state in NV. NV that was never written holds
+// zeros, meaning the TPM hasn't been manufactured.
+const DA_NV_FORMAT: u32 = 1;
+
+pub struct DaState {
+    // Persistent
+    pub(crate) failed_tries: u32,
+    pub(crate) max_tries: u32,
+    // In seconds. A recoveryTime of 0 turns off the protection, and a
+    // lockoutRecovery of 0 leaves lockoutAuth locked out until a TPM Reset.
+    pub(crate) recovery_time: u32,
+    pub(crate) lockout_recovery: u32,
+    pub(crate) lockout_auth_enabled: bool,
+
+    // Time when failedTries last went down, or up from 0, and when
+    // lockoutAuth was last locked out. Healing only counts time the TPM is
+    // powered, so both start again at power on.
+    pub(crate) self_heal_start: u64,
+    pub(crate) lockout_start: u64,
+}
+
+impl Default for DaState {
+    fn default() -> DaState {
+        DaState {
+            failed_tries: 0,
+            max_tries: 3,
+            recovery_time: 1000,
+            lockout_recovery: 1000,
+            lockout_auth_enabled: true,
+            self_heal_start: 0,
+            lockout_start: 0,
+        }
+    }
+}
+
+impl TpmInstance {
+    // Load the dictionary attack state from NV, or store the defaults if
+    // there is none.
+    pub(crate) fn da_restore(&mut self) -> Result<(), TpmError> {
+        let mut buffer = [0u8; DA_NV_SIZE];
+        self.nv_read(NV_DA, &mut buffer)?;
+
+        let mut offset = 0;
+        if unmarshal_u32(&buffer, &mut offset)? != DA_NV_FORMAT {
+            return self.da_store();
+        }
+
+        let da = &mut self.da;
+        da.failed_tries = unmarshal_u32(&buffer, &mut offset)?;
+        da.max_tries = unmarshal_u32(&buffer, &mut offset)?;
+        da.recovery_time = unmarshal_u32(&buffer, &mut offset)?;
+        da.lockout_recovery = unmarshal_u32(&buffer, &mut offset)?;
+        da.lockout_auth_enabled = unmarshal_u8(&buffer, &mut offset)? != 0;
+
+        Ok(())
+    }
+
+    pub(crate) fn da_store(&mut self) -> Result<(), TpmError> {
+        let da = &self.da;
+        let mut buffer = [0u8; DA_NV_SIZE];
+
+        let mut offset = marshal_u32(&mut buffer, DA_NV_FORMAT)?;
+        for val in [
+            da.failed_tries,
+            da.max_tries,
+            da.recovery_time,
+            da.lockout_recovery,
+        ] {
+            offset += marshal_u32(&mut buffer[offset..], val)?;
+        }
+        offset += marshal_u8(&mut buffer[offset..], da.lockout_auth_enabled as u8)?;
+
+        self.nv_write(NV_DA, &buffer[..offset])
+    }
+
+    // A TPM Reset gives lockoutAuth back if only a TPM Reset can. A power
+    // loss might have hidden a failed try, so it counts as one.
+    pub(crate) fn da_startup(&mut self, reset: bool, orderly: bool) -> Result<(), TpmError> {
+        let da = &mut self.da;
+        let mut changed = false;
+
+        if reset && da.lockout_recovery == 0 && !da.lockout_auth_enabled {
+            da.lockout_auth_enabled = true;
+            changed = true;
+        }
+        if !orderly && da.recovery_time != 0 && da.failed_tries < da.max_tries {
+            da.failed_tries += 1;
+            changed = true;
+        }
+        da.self_heal_start = self.clock.time;
+        da.lockout_start = self.clock.time;
+
+        match changed {
+            true => self.da_store(),
+            false => Ok(()),
+        }
+    }
+
+    // Take failedTries down for every recoveryTime that has passed, and
+    // give lockoutAuth back once lockoutRecovery has. Called before every
+    // command.
+    pub(crate) fn da_self_heal(&mut self) {
+        let time = self.clock.time;
+        let da = &mut self.da;
+        let mut changed = false;
+
+        if da.failed_tries != 0 {
+            if da.recovery_time == 0 {
+                da.failed_tries = 0;
+                changed = true;
+            } else {
+                let interval = da.recovery_time as u64 * 1000;
+                let healed = (time - da.self_heal_start) / interval;
+                if healed != 0 {
+                    da.failed_tries = da.failed_tries.min(da.max_tries);
+                    da.failed_tries -= healed.min(da.failed_tries as u64) as u32;
+                    da.self_heal_start += healed * interval;
+                    changed = true;
+                }
+            }
+        }
+
+        if !da.lockout_auth_enabled
+            && da.lockout_recovery != 0
+            && time - da.lockout_start >= da.lockout_recovery as u64 * 1000
+        {
+            da.lockout_auth_enabled = true;
+            changed = true;
+        }
+
+        if changed {
+            // If NV can't be written now, the next command tries again.
+            let _ = self.da_store();
+        }
+    }
+
+    pub(crate) fn da_in_lockout(&self) -> bool {
+        self.da.recovery_time != 0 && self.da.failed_tries >= self.da.max_tries
+    }
+
+    // Hierarchies other than lockout, sequences, and objects and NV indices
+    // marked noDA are exempt.
+    fn da_protected(&self, handle: TpmHandle) -> bool {
+        if let Some(object) = self.object_get(handle) {
+            return !object.public.has_attributes(TPMA_OBJECT_NO_DA);
+        }
+        if let Some(index) = self.nv_index_get(handle) {
+            return !index.public.has_attributes(TPMA_NV_NO_DA);
+        }
+
+        false
+    }
+
+    // Fail authValue checks for `handle` while it's locked out.
+    pub(crate) fn da_check(&self, handle: TpmHandle) -> Result<(), TpmError> {
+        let locked_out = match TpmRh::from(handle) {
+            TpmRh::Lockout => !self.da.lockout_auth_enabled,
+            _ => self.da_protected(handle) && self.da_in_lockout(),
+        };
+
+        match locked_out {
+            true => Err(TpmError::new(TpmRc::Lockout)),
+            false => Ok(()),
+        }
+    }
+
+    // Count a wrong authValue for `handle`. It's written to NV straight
+    // away, so a power loss can't undo it.
+    pub(crate) fn da_failure(&mut self, handle: TpmHandle) -> Result<(), TpmError> {
+        let time = self.clock.time;
+
+        if TpmRh::from(handle) == TpmRh::Lockout {
+            self.da.lockout_auth_enabled = false;
+            self.da.lockout_start = time;
+        } else if self.da_protected(handle) && self.da.recovery_time != 0 {
+            if self.da.failed_tries == 0 {
+                self.da.self_heal_start = time;
+            }
+            self.da.failed_tries += 1;
+        } else {
+            return Ok(());
+        }
+
+        self.da_store()
+    }
+}
+
+pub fn tpm2_dictionary_attack_lock_reset(tpm: &mut TpmInstance) -> Result<(), TpmError> {
+    tpm.da.failed_tries = 0;
+    tpm.da_store()
+}
+
+// failedTries is left alone, as the errata to the spec has it.
+pub fn tpm2_dictionary_attack_parameters(
+    tpm: &mut TpmInstance,
+    args: &DictionaryAttackParametersArgs,
+) -> Result<(), TpmError> {
+    tpm.da.max_tries = args.new_max_tries;
+    tpm.da.recovery_time = args.new_recovery_time;
+    tpm.da.lockout_recovery = args.lockout_recovery;
+
+    tpm.da_store()
+}
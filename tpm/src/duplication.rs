@@ -0,0 +1,386 @@
+use crate::asymmetric::*;
+use crate::authorization::auth_equal;
+use crate::crypto::hash::*;
+use crate::crypto::kdf::DUPLICATE;
+use crate::crypto::sym;
+use crate::marshal::*;
+use crate::object::*;
+use crate::tpm::*;
+use crate::types::*;
+
+// Duplicated objects are sent to an asymmetric storage key, which shares the
+// outer wrap seed with whoever made the duplicate.
+fn is_duplication_parent(public: &TpmtPublic) -> bool {
+    is_storage_parent(public)
+        && matches!(
+            public.parameters,
+            TpmuPublicParms::Rsa(_) | TpmuPublicParms::Ecc(_)
+        )
+}
+
+// The object behind handle `n`, or None for TPM_RH_NULL.
+fn optional_object(
+    tpm: &TpmInstance,
+    handle: TpmHandle,
+    n: u32,
+) -> Result<Option<Object>, TpmError> {
+    if TpmRh::from(handle) == TpmRh::Null {
+        return Ok(None);
+    }
+
+    match tpm.object_get(handle) {
+        Some(object) => Ok(Some(*object)),
+        None => Err(TpmError::handle(TpmRc::ReferenceH0, n)),
+    }
+}
+
+// The inner wrap is always CFB with a zero IV, whatever mode symmetricAlg
+// names.
+fn inner_crypt(
+    encrypt: bool,
+    symmetric: &TpmtSymDefObject,
+    key: &[u8],
+    data: &mut [u8],
+) -> Result<(), TpmError> {
+    let iv = [0u8; MAX_SYM_BLOCK_SIZE];
+    let iv = &iv[..sym::block_size(symmetric.algorithm)];
+    match encrypt {
+        true => sym::cfb_encrypt(symmetric.algorithm, key, iv, data),
+        false => sym::cfb_decrypt(symmetric.algorithm, key, iv, data),
+    }
+}
+
+// A duplicated object is the marshaled TPM2B_SENSITIVE, optionally in an
+// inner wrap made with the object's nameAlg and `key`, then optionally in an
+// outer wrap with the new parent's seed:
+//
+//   TPM2B_DIGEST(outerHMAC) || CFB(CFB(TPM2B_DIGEST(innerIntegrity) || sensitive))
+//
+// where innerIntegrity is H(sensitive || Name). A NULL symmetric algorithm
+// leaves out the inner wrap, and a NULL new parent the outer one.
+fn sensitive_to_duplicate(
+    object: &Object,
+    new_parent: Option<&Object>,
+    seed: &Tpm2bDigest,
+    symmetric: &TpmtSymDefObject,
+    key: &[u8],
+) -> Result<Tpm2bPrivate, TpmError> {
+    let outer_size = match new_parent {
+        Some(parent) => 2 + parent.public.name_alg.digest_size(),
+        None => 0,
+    };
+    let inner_size = match symmetric.algorithm {
+        TpmAlgId::Null => 0,
+        _ => 2 + object.public.name_alg.digest_size(),
+    };
+    let start = outer_size + inner_size;
+
+    let mut duplicate = Tpm2bPrivate::default();
+    let size = marshal_tpm2b_sensitive(&mut duplicate.buffer[start..], &object.sensitive)?;
+    let end = start + size;
+
+    if inner_size != 0 {
+        let integrity = hash(
+            object.public.name_alg,
+            &[&duplicate.buffer[start..end], object.name.as_slice()],
+        )?;
+        marshal_tpm2b(&mut duplicate.buffer[outer_size..], &integrity)?;
+        inner_crypt(true, symmetric, key, &mut duplicate.buffer[outer_size..end])?;
+    }
+
+    if let Some(parent) = new_parent {
+        let parent_symmetric = parent_symmetric(&parent.public)?;
+        let integrity = outer_wrap(
+            parent.public.name_alg,
+            &parent_symmetric,
+            seed.as_slice(),
+            &object.name,
+            &mut duplicate.buffer[outer_size..end],
+        )?;
+        marshal_tpm2b(&mut duplicate.buffer, &integrity)?;
+    }
+    duplicate.size = end as u16;
+
+    Ok(duplicate)
+}
+
+// Undo `sensitive_to_duplicate` for the object with `public` and `name`.
+// The outer wrap is only there if a seed was shared with `parent`. A bad
+// HMAC or inner integrity value is an Integrity error, and anything that
+// doesn't decrypt to a well formed TPM2B_SENSITIVE is a Size error.
+fn duplicate_to_sensitive(
+    parent: &Object,
+    public: &TpmtPublic,
+    name: &Tpm2bName,
+    seed: Option<&Tpm2bDigest>,
+    symmetric: &TpmtSymDefObject,
+    key: &[u8],
+    duplicate: &Tpm2bPrivate,
+) -> Result<TpmtSensitive, TpmError> {
+    let buffer = duplicate.as_slice();
+    let mut offset = 0;
+    let outer_integrity: Option<Tpm2bDigest> = match seed {
+        Some(_) => Some(
+            unmarshal_tpm2b(buffer, &mut offset).map_err(|_| TpmError::new(TpmRc::Integrity))?,
+        ),
+        None => None,
+    };
+
+    let mut data = [0u8; MAX_PRIVATE_SIZE];
+    let size = buffer.len() - offset;
+    let data = &mut data[..size];
+    data.copy_from_slice(&buffer[offset..]);
+
+    if let (Some(seed), Some(integrity)) = (seed, &outer_integrity) {
+        let symmetric = parent_symmetric(&parent.public)?;
+        outer_unwrap(
+            parent.public.name_alg,
+            &symmetric,
+            seed.as_slice(),
+            name,
+            integrity,
+            data,
+        )?;
+    }
+
+    let mut offset = 0;
+    if symmetric.algorithm != TpmAlgId::Null {
+        inner_crypt(false, symmetric, key, data)?;
+        let integrity: Tpm2bDigest =
+            unmarshal_tpm2b(data, &mut offset).map_err(|_| TpmError::new(TpmRc::Integrity))?;
+        let expected = hash(public.name_alg, &[&data[offset..], name.as_slice()])?;
+        if integrity.size != expected.size || !auth_equal(integrity.as_slice(), expected.as_slice())
+        {
+            return Err(TpmError::new(TpmRc::Integrity));
+        }
+    }
+
+    let sensitive =
+        unmarshal_tpm2b_sensitive(data, &mut offset).map_err(|_| TpmError::new(TpmRc::Size))?;
+    if offset != size {
+        return Err(TpmError::new(TpmRc::Size));
+    }
+
+    Ok(sensitive)
+}
+
+// Duplicate an object so it can be imported under `newParentHandle`. The
+// object's DUP role is only reachable with a policy session.
+pub fn tpm2_duplicate(
+    tpm: &mut TpmInstance,
+    args: &DuplicateArgs,
+) -> Result<DuplicateResponse, TpmError> {
+    let object = loaded_object(tpm, args.object_handle)?;
+    let public = &object.public;
+    if public.has_attributes(TPMA_OBJECT_FIXED_PARENT) {
+        return Err(TpmError::handle(TpmRc::Attributes, 1));
+    }
+    if public.name_alg == TpmAlgId::Null {
+        return Err(TpmError::handle(TpmRc::Type, 1));
+    }
+
+    let new_parent = optional_object(tpm, args.new_parent_handle, 2)?;
+    if let Some(parent) = &new_parent {
+        if !is_duplication_parent(&parent.public) {
+            return Err(TpmError::handle(TpmRc::Type, 2));
+        }
+    }
+
+    // encryptedDuplication asks for both wraps.
+    let symmetric = &args.symmetric_alg;
+    if public.has_attributes(TPMA_OBJECT_ENCRYPTED_DUPLICATION) {
+        if symmetric.algorithm == TpmAlgId::Null {
+            return Err(TpmError::parameter(TpmRc::Symmetric, 2));
+        }
+        if new_parent.is_none() {
+            return Err(TpmError::handle(TpmRc::Hierarchy, 2));
+        }
+    }
+
+    // The caller can pick the inner wrap key, or leave it to the TPM, which
+    // then hands it back.
+    let key_size = args.encryption_key_in.size as usize;
+    let key_ok = match symmetric.algorithm {
+        TpmAlgId::Null => key_size == 0,
+        _ => key_size == 0 || key_size == symmetric.key_bits as usize / 8,
+    };
+    if !key_ok {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+    let mut encryption_key = args.encryption_key_in;
+    let mut encryption_key_out = Tpm2bData::default();
+    if symmetric.algorithm != TpmAlgId::Null && key_size == 0 {
+        encryption_key.size = symmetric.key_bits / 8;
+        (tpm.platform.get_random)(&mut encryption_key.buffer[..encryption_key.size as usize]);
+        encryption_key_out = encryption_key;
+    }
+
+    let mut seed = Tpm2bDigest::default();
+    let mut out_sym_seed = Tpm2bEncryptedSecret::default();
+    if let Some(parent) = &new_parent {
+        secret_encrypt(tpm, &parent.public, DUPLICATE, &mut seed, &mut out_sym_seed)?;
+    }
+
+    let duplicate = sensitive_to_duplicate(
+        &object,
+        new_parent.as_ref(),
+        &seed,
+        symmetric,
+        encryption_key.as_slice(),
+    )?;
+
+    Ok(DuplicateResponse {
+        encryption_key_out,
+        duplicate,
+        out_sym_seed,
+    })
+}
+
+// Turn a duplicated object, or a sensitive area made outside the TPM, into
+// a private area for `parentHandle`. The object is checked the same way
+// TPM2_Load would, so what comes out will load.
+pub fn tpm2_import(tpm: &mut TpmInstance, args: &ImportArgs) -> Result<ImportResponse, TpmError> {
+    let symmetric = &args.symmetric_alg;
+    let key_size = match symmetric.algorithm {
+        TpmAlgId::Null => 0,
+        _ => symmetric.key_bits as usize / 8,
+    };
+    if args.encryption_key.size as usize != key_size {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+
+    // Only objects that were free to leave their old parent can come in.
+    let public = &args.object_public;
+    if public.object_attributes & (TPMA_OBJECT_FIXED_TPM | TPMA_OBJECT_FIXED_PARENT) != 0 {
+        return Err(TpmError::parameter(TpmRc::Attributes, 2));
+    }
+
+    let parent = loaded_object(tpm, args.parent_handle)?;
+    if !is_storage_parent(&parent.public) {
+        return Err(TpmError::handle(TpmRc::Type, 1));
+    }
+
+    if public.has_attributes(TPMA_OBJECT_ENCRYPTED_DUPLICATION) {
+        if symmetric.algorithm == TpmAlgId::Null {
+            return Err(TpmError::parameter(TpmRc::Attributes, 5));
+        }
+        if args.in_sym_seed.is_empty() {
+            return Err(TpmError::parameter(TpmRc::Attributes, 4));
+        }
+    }
+
+    if !public.name_alg.is_hash() {
+        return Err(TpmError::parameter(TpmRc::Hash, 2));
+    }
+    public_attributes_validation(Some(&parent.public), public).map_err(|e| e.with_parameter(2))?;
+    scheme_checks(public).map_err(|e| e.with_parameter(2))?;
+
+    let seed = match args.in_sym_seed.is_empty() {
+        true => None,
+        false => Some(
+            secret_decrypt(&parent, DUPLICATE, &args.in_sym_seed)
+                .map_err(|e| e.with_parameter(4))?,
+        ),
+    };
+
+    let name = public_name(public)?;
+    let sensitive = duplicate_to_sensitive(
+        &parent,
+        public,
+        &name,
+        seed.as_ref(),
+        symmetric,
+        args.encryption_key.as_slice(),
+        &args.duplicate,
+    )
+    .map_err(|e| e.with_parameter(3))?;
+    validate_keys(public, &sensitive).map_err(|e| e.with_parameter(2))?;
+
+    let out_private = sensitive_to_private(&parent, &name, &sensitive)?;
+
+    Ok(ImportResponse { out_private })
+}
+
+// Move the outer wrap of a duplicated object from `oldParent` to
+// `newParent`. Either can be TPM_RH_NULL for a duplicate with no outer wrap.
+// The inner wrap, if any, is left alone.
+pub fn tpm2_rewrap(tpm: &mut TpmInstance, args: &RewrapArgs) -> Result<RewrapResponse, TpmError> {
+    let old_parent = optional_object(tpm, args.old_parent, 1)?;
+    if old_parent.is_some() == args.in_sym_seed.is_empty() {
+        return Err(TpmError::handle(TpmRc::Handle, 1));
+    }
+
+    let buffer = args.in_duplicate.as_slice();
+    let mut offset = 0;
+    let mut data = [0u8; MAX_PRIVATE_SIZE];
+    let size;
+    match &old_parent {
+        Some(parent) => {
+            if !is_duplication_parent(&parent.public) {
+                return Err(TpmError::handle(TpmRc::Type, 1));
+            }
+            let seed = secret_decrypt(parent, DUPLICATE, &args.in_sym_seed)
+                .map_err(|_| TpmError::parameter(TpmRc::Value, 3))?;
+
+            let integrity: Tpm2bDigest = unmarshal_tpm2b(buffer, &mut offset)
+                .map_err(|_| TpmError::parameter(TpmRc::Integrity, 1))?;
+            size = buffer.len() - offset;
+            data[..size].copy_from_slice(&buffer[offset..]);
+
+            let symmetric = parent_symmetric(&parent.public)?;
+            outer_unwrap(
+                parent.public.name_alg,
+                &symmetric,
+                seed.as_slice(),
+                &args.name,
+                &integrity,
+                &mut data[..size],
+            )
+            .map_err(|e| e.with_parameter(1))?;
+        }
+        None => {
+            size = buffer.len();
+            data[..size].copy_from_slice(buffer);
+        }
+    }
+
+    let new_parent = optional_object(tpm, args.new_parent, 2)?;
+    let mut out_duplicate = Tpm2bPrivate::default();
+    let mut out_sym_seed = Tpm2bEncryptedSecret::default();
+    match &new_parent {
+        Some(parent) => {
+            if !is_duplication_parent(&parent.public) {
+                return Err(TpmError::handle(TpmRc::Type, 2));
+            }
+            let mut seed = Tpm2bDigest::default();
+            secret_encrypt(tpm, &parent.public, DUPLICATE, &mut seed, &mut out_sym_seed)?;
+
+            let start = 2 + parent.public.name_alg.digest_size();
+            if start + size > MAX_PRIVATE_SIZE {
+                return Err(TpmError::parameter(TpmRc::Size, 1));
+            }
+            let enc = &mut out_duplicate.buffer[start..start + size];
+            enc.copy_from_slice(&data[..size]);
+
+            let symmetric = parent_symmetric(&parent.public)?;
+            let integrity = outer_wrap(
+                parent.public.name_alg,
+                &symmetric,
+                seed.as_slice(),
+                &args.name,
+                enc,
+            )?;
+            marshal_tpm2b(&mut out_duplicate.buffer, &integrity)?;
+            out_duplicate.size = (start + size) as u16;
+        }
+        None => {
+            out_duplicate.buffer[..size].copy_from_slice(&data[..size]);
+            out_duplicate.size = size as u16;
+        }
+    }
+
+    Ok(RewrapResponse {
+        out_duplicate,
+        out_sym_seed,
+    })
+}
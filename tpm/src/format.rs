@@ -13,7 +13,7 @@ impl<'a> WriteTo<'a> {
         WriteTo { buffer, used: 0 }
     }
 
-    pub fn as_str(self) -> Option<&'a str> {
+    pub fn into_str(self) -> Option<&'a str> {
         if self.used <= self.buffer.len() {
             // only successful concats of str - must be a valid str.
             use core::str::from_utf8_unchecked;
@@ -45,5 +45,5 @@ impl<'a> fmt::Write for WriteTo<'a> {
 pub fn show<'a>(buffer: &'a mut [u8], args: fmt::Arguments) -> Result<&'a str, fmt::Error> {
     let mut w = WriteTo::new(buffer);
     fmt::write(&mut w, args)?;
-    w.as_str().ok_or(fmt::Error)
+    w.into_str().ok_or(fmt::Error)
 }
@@ -1,44 +1,160 @@
+use crate::crypto::ecc;
+use crate::object::TRANSIENT_FIRST;
+use crate::pcr::PCR_HASH_ALG;
 use crate::tpm::*;
 use crate::types::*;
 
+// Permanent handles in ascending order
+const PERMANENT_HANDLES: [TpmRh; 7] = [
+    TpmRh::Owner,
+    TpmRh::Null,
+    TpmRh::Password,
+    TpmRh::Lockout,
+    TpmRh::Endorsement,
+    TpmRh::Platform,
+    TpmRh::PlatformNv,
+];
+
 fn get_tpm_property(
-    _tpm: &mut TpmInstance,
+    tpm: &mut TpmInstance,
     property: TpmPt,
     _count: u32,
 ) -> Result<TpmuCapabilityData, TpmError> {
-    let mut props =
-        TpmuCapabilityData::TpmProperties(0, [TpmsTaggedProperty::default(); MAX_TPM_PROPERTIES]);
-
-    match property {
+    let val = match property {
         // TODO: Put a real manufacturer ID
-        TpmPt::Manufacturer => {
-            if let TpmuCapabilityData::TpmProperties(ref mut count, ref mut properties) = props {
-                *count = 1;
-                properties[0].property = property;
-                properties[0].val = 0x0;
-            }
+        TpmPt::Manufacturer => 0x0,
+        TpmPt::FirmwareVersion1 => (FIRMWARE_VERSION >> 32) as u32,
+        TpmPt::FirmwareVersion2 => FIRMWARE_VERSION as u32,
+        TpmPt::HrTransientMin => MAX_LOADED_OBJECTS as u32,
+        TpmPt::HrPersistentMin => MAX_PERSISTENT_OBJECTS as u32,
+        TpmPt::PcrCount => IMPLEMENTATION_PCR as u32,
+        TpmPt::PcrSelectMin => PCR_SELECT_MIN as u32,
+        TpmPt::NvIndexMax => MAX_NV_INDEX_SIZE as u32,
+        TpmPt::NvBufferMax => MAX_NV_BUFFER_SIZE as u32,
+        TpmPt::Permanent => tpm.permanent_attributes(),
+        TpmPt::StartupClear => tpm.startup_clear_attributes(),
+        TpmPt::HrNvIndex => (MAX_NV_INDICES - tpm.nv_index_slots_free()) as u32,
+        TpmPt::HrLoaded => (MAX_LOADED_SESSIONS - tpm.session_slots_free()) as u32,
+        TpmPt::HrLoadedAvail => tpm.session_slots_free() as u32,
+        TpmPt::HrTransientAvail => tpm.object_slots_free() as u32,
+        TpmPt::HrPersistent => (MAX_PERSISTENT_OBJECTS - tpm.persistent_slots_free()) as u32,
+        TpmPt::HrPersistentAvail => tpm.persistent_slots_free() as u32,
+        TpmPt::LockoutCounter => tpm.da.failed_tries,
+        TpmPt::MaxAuthFail => tpm.da.max_tries,
+        TpmPt::LockoutInterval => tpm.da.recovery_time,
+        TpmPt::LockoutRecovery => tpm.da.lockout_recovery,
+        _ => return Err(TpmError::new(TpmRc::Value)),
+    };
+
+    let mut properties = [TpmsTaggedProperty::default(); MAX_TPM_PROPERTIES];
+    properties[0].property = property;
+    properties[0].val = val;
+
+    Ok(TpmuCapabilityData::TpmProperties(1, properties))
+}
+
+// The one bank, with every PCR in it
+fn assigned_pcrs() -> TpmuCapabilityData {
+    let mut selection = TpmlPcrSelection {
+        count: 1,
+        ..Default::default()
+    };
+    selection.pcr_selections[0] = TpmsPcrSelection {
+        hash: PCR_HASH_ALG,
+        size_of_select: PCR_SELECT_MAX as u8,
+        pcr_select: [0xFF; PCR_SELECT_MAX],
+    };
+
+    TpmuCapabilityData::AssignedPcr(selection)
+}
+
+// List up to `count` of `handles`, which must be in ascending order,
+// starting at `start`. Returns whether any were left out.
+fn handle_list(
+    handles: impl Iterator<Item = TpmHandle>,
+    start: TpmHandle,
+    count: u32,
+) -> (bool, TpmuCapabilityData) {
+    let count = (count as usize).min(MAX_CAP_HANDLES);
+    let mut list = [0; MAX_CAP_HANDLES];
+    let mut n = 0;
+
+    for handle in handles.filter(|h| *h >= start) {
+        if n == count {
+            return (true, TpmuCapabilityData::Handles(n as u32, list));
         }
-        _ => return Err(TpmError { rc: TpmRc::Value }),
+        list[n] = handle;
+        n += 1;
+    }
+
+    (false, TpmuCapabilityData::Handles(n as u32, list))
+}
+
+fn get_handles(
+    tpm: &mut TpmInstance,
+    start: TpmHandle,
+    count: u32,
+) -> Result<(bool, TpmuCapabilityData), TpmError> {
+    let list = match TpmHt::from(start) {
+        TpmHt::Transient => {
+            let handles = (0..MAX_LOADED_OBJECTS).map(|slot| TRANSIENT_FIRST + slot as TpmHandle);
+            let loaded = handles.filter(|handle| tpm.transient_loaded(*handle));
+            handle_list(loaded, start, count)
+        }
+        TpmHt::Persistent => {
+            let (handles, n) = tpm.persistent_handles();
+            handle_list(handles[..n].iter().copied(), start, count)
+        }
+        TpmHt::Permanent => handle_list(
+            PERMANENT_HANDLES.iter().map(|rh| *rh as TpmHandle),
+            start,
+            count,
+        ),
+        TpmHt::NvIndex => {
+            let (handles, n) = tpm.nv_index_handles();
+            handle_list(handles[..n].iter().copied(), start, count)
+        }
+        ht @ (TpmHt::HmacSession | TpmHt::PolicySession) => {
+            handle_list(tpm.session_handles(ht), start, count)
+        }
+        TpmHt::Pcr => handle_list(0..IMPLEMENTATION_PCR as TpmHandle, start, count),
+        TpmHt::Unknown => return Err(TpmError::new(TpmRc::Value)),
     };
 
-    Ok(props)
+    Ok(list)
+}
+
+// The supported curves from `start` on
+fn get_ecc_curves(start: u32, count: u32) -> (bool, TpmuCapabilityData) {
+    let count = (count as usize).min(MAX_CAP_CURVES);
+    let mut list = [TpmEccCurve::default(); MAX_CAP_CURVES];
+    let mut n = 0;
+
+    for curve in ecc::CURVES.iter().filter(|c| c.curve_id as u32 >= start) {
+        if n == count {
+            return (true, TpmuCapabilityData::EccCurves(n as u32, list));
+        }
+        list[n] = curve.curve_id;
+        n += 1;
+    }
+
+    (false, TpmuCapabilityData::EccCurves(n as u32, list))
 }
 
 pub fn tpm2_get_capability(
     tpm: &mut TpmInstance,
     args: &GetCapabilityArgs,
 ) -> Result<GetCapabilityResponse, TpmError> {
-    let mut response = GetCapabilityResponse::default();
-
-    response.data = match args.cap {
-        TpmCapability::TpmProperty => {
-            match get_tpm_property(tpm, args.property, args.property_count) {
-                Ok(data) => data,
-                Err(e) => return Err(e),
-            }
-        }
-        _ => return Err(TpmError { rc: TpmRc::Value }),
+    let (more_data, data) = match args.cap {
+        TpmCapability::Handles => get_handles(tpm, args.property, args.property_count)?,
+        TpmCapability::TpmProperty => (
+            false,
+            get_tpm_property(tpm, TpmPt::from(args.property), args.property_count)?,
+        ),
+        TpmCapability::EccCurves => get_ecc_curves(args.property, args.property_count),
+        TpmCapability::Pcrs => (false, assigned_pcrs()),
+        _ => return Err(TpmError::new(TpmRc::Value)),
     };
 
-    Ok(response)
+    Ok(GetCapabilityResponse { more_data, data })
 }
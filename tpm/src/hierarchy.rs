@@ -0,0 +1,524 @@
+use crate::authorization::trim_trailing_zeros;
+use crate::crypto::kdf::*;
+use crate::marshal::*;
+use crate::nv::*;
+use crate::object::*;
+use crate::tpm::*;
+use crate::types::*;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Hierarchy {
+    Platform,
+    Owner,
+    Endorsement,
+    #[default]
+    Null,
+}
+
+impl Hierarchy {
+    pub fn from_handle(handle: TpmHandle) -> Option<Hierarchy> {
+        match TpmRh::from(handle) {
+            TpmRh::Platform => Some(Hierarchy::Platform),
+            TpmRh::Owner => Some(Hierarchy::Owner),
+            TpmRh::Endorsement => Some(Hierarchy::Endorsement),
+            TpmRh::Null => Some(Hierarchy::Null),
+            _ => None,
+        }
+    }
+
+    pub fn handle(&self) -> TpmHandle {
+        match self {
+            Hierarchy::Platform => TpmRh::Platform as TpmHandle,
+            Hierarchy::Owner => TpmRh::Owner as TpmHandle,
+            Hierarchy::Endorsement => TpmRh::Endorsement as TpmHandle,
+            Hierarchy::Null => TpmRh::Null as TpmHandle,
+        }
+    }
+}
+
+// State for the four hierarchies, grouped by how long each value lives.
+#[derive(Default)]
+pub struct HierarchyState {
+    // Persistent. Generated when the TPM is manufactured and only replaced by
+    // TPM2_ChangePPS, TPM2_ChangeEPS and TPM2_Clear.
+    pub(crate) pps: [u8; PRIMARY_SEED_SIZE],
+    pub(crate) sps: [u8; PRIMARY_SEED_SIZE],
+    pub(crate) eps: [u8; PRIMARY_SEED_SIZE],
+    pub(crate) ph_proof: [u8; PROOF_SIZE],
+    pub(crate) sh_proof: [u8; PROOF_SIZE],
+    pub(crate) eh_proof: [u8; PROOF_SIZE],
+    pub(crate) owner_auth: Tpm2bAuth,
+    pub(crate) endorsement_auth: Tpm2bAuth,
+    pub(crate) lockout_auth: Tpm2bAuth,
+    pub(crate) owner_policy: TpmtHa,
+    pub(crate) endorsement_policy: TpmtHa,
+    pub(crate) lockout_policy: TpmtHa,
+    pub(crate) disable_clear: bool,
+
+    // Reset by every Startup(CLEAR).
+    pub(crate) platform_auth: Tpm2bAuth,
+    pub(crate) platform_policy: TpmtHa,
+    pub(crate) ph_enable: bool,
+    pub(crate) sh_enable: bool,
+    pub(crate) eh_enable: bool,
+    pub(crate) ph_enable_nv: bool,
+
+    // Regenerated on every TPM Reset.
+    pub(crate) null_seed: [u8; PRIMARY_SEED_SIZE],
+    pub(crate) null_proof: [u8; PROOF_SIZE],
+}
+
+// Marks the persistent hierarchy state in NV. NV that was never written
+// holds zeros, meaning the TPM hasn't been manufactured.
+const HIERARCHY_NV_FORMAT: u32 = 1;
+
+impl TpmInstance {
+    // Load the persistent hierarchy state from NV. If there is none, this is
+    // the first power on, and the state from hierarchy_manufacture() is
+    // stored instead. Returns whether there was any.
+    pub(crate) fn hierarchy_restore(&mut self) -> Result<bool, TpmError> {
+        let manufactured = self.hierarchy_load()?;
+        if !manufactured {
+            self.hierarchy_store()?;
+        }
+
+        Ok(manufactured)
+    }
+
+    // Read the persistent hierarchy state from NV, if there is any.
+    fn hierarchy_load(&mut self) -> Result<bool, TpmError> {
+        let mut buffer = [0u8; HIERARCHY_NV_SIZE];
+        self.nv_read(NV_HIERARCHY, &mut buffer)?;
+
+        let mut offset = 0;
+        if unmarshal_u32(&buffer, &mut offset)? != HIERARCHY_NV_FORMAT {
+            return Ok(false);
+        }
+
+        let h = &mut self.hierarchy;
+        h.pps
+            .copy_from_slice(unmarshal_bytes(&buffer, &mut offset, PRIMARY_SEED_SIZE)?);
+        h.sps
+            .copy_from_slice(unmarshal_bytes(&buffer, &mut offset, PRIMARY_SEED_SIZE)?);
+        h.eps
+            .copy_from_slice(unmarshal_bytes(&buffer, &mut offset, PRIMARY_SEED_SIZE)?);
+        h.ph_proof
+            .copy_from_slice(unmarshal_bytes(&buffer, &mut offset, PROOF_SIZE)?);
+        h.sh_proof
+            .copy_from_slice(unmarshal_bytes(&buffer, &mut offset, PROOF_SIZE)?);
+        h.eh_proof
+            .copy_from_slice(unmarshal_bytes(&buffer, &mut offset, PROOF_SIZE)?);
+        h.owner_auth = unmarshal_tpm2b(&buffer, &mut offset)?;
+        h.endorsement_auth = unmarshal_tpm2b(&buffer, &mut offset)?;
+        h.lockout_auth = unmarshal_tpm2b(&buffer, &mut offset)?;
+        h.owner_policy = unmarshal_tpmt_ha(&buffer, &mut offset)?;
+        h.endorsement_policy = unmarshal_tpmt_ha(&buffer, &mut offset)?;
+        h.lockout_policy = unmarshal_tpmt_ha(&buffer, &mut offset)?;
+        h.disable_clear = unmarshal_u8(&buffer, &mut offset)? != 0;
+
+        Ok(true)
+    }
+
+    // Write the persistent part of the hierarchy state to NV. Commands that
+    // change it call this before returning.
+    pub(crate) fn hierarchy_store(&mut self) -> Result<(), TpmError> {
+        let h = &self.hierarchy;
+        let mut buffer = [0u8; HIERARCHY_NV_SIZE];
+
+        let mut offset = marshal_u32(&mut buffer, HIERARCHY_NV_FORMAT)?;
+        for bytes in [
+            &h.pps,
+            &h.sps,
+            &h.eps,
+            &h.ph_proof,
+            &h.sh_proof,
+            &h.eh_proof,
+        ] {
+            offset += marshal_bytes(&mut buffer[offset..], bytes)?;
+        }
+        for auth in [&h.owner_auth, &h.endorsement_auth, &h.lockout_auth] {
+            offset += marshal_tpm2b(&mut buffer[offset..], auth)?;
+        }
+        for policy in [&h.owner_policy, &h.endorsement_policy, &h.lockout_policy] {
+            offset += marshal_tpmt_ha(&mut buffer[offset..], policy)?;
+        }
+        offset += marshal_u8(&mut buffer[offset..], h.disable_clear as u8)?;
+
+        // If NV can't be written, go back to what it still holds, so the
+        // command fails without changing the hierarchies.
+        let result = self.nv_write(NV_HIERARCHY, &buffer[..offset]);
+        if result.is_err() {
+            self.hierarchy_load()?;
+        }
+        result
+    }
+
+    // Put the hierarchies in their as-manufactured state. Called once when
+    // the TPM has no persistent state to load.
+    pub(crate) fn hierarchy_manufacture(&mut self) {
+        let get_random = self.platform.get_random;
+        let h = &mut self.hierarchy;
+
+        get_random(&mut h.pps);
+        get_random(&mut h.sps);
+        get_random(&mut h.eps);
+        get_random(&mut h.ph_proof);
+        get_random(&mut h.sh_proof);
+        get_random(&mut h.eh_proof);
+
+        h.owner_auth = Tpm2bAuth::default();
+        h.endorsement_auth = Tpm2bAuth::default();
+        h.lockout_auth = Tpm2bAuth::default();
+        h.owner_policy = TpmtHa::default();
+        h.endorsement_policy = TpmtHa::default();
+        h.lockout_policy = TpmtHa::default();
+        h.disable_clear = false;
+    }
+
+    pub(crate) fn hierarchy_startup_clear(&mut self) {
+        let h = &mut self.hierarchy;
+
+        h.platform_auth = Tpm2bAuth::default();
+        h.platform_policy = TpmtHa::default();
+        h.ph_enable = true;
+        h.sh_enable = true;
+        h.eh_enable = true;
+        h.ph_enable_nv = true;
+    }
+
+    pub(crate) fn hierarchy_reset(&mut self) {
+        let get_random = self.platform.get_random;
+
+        get_random(&mut self.hierarchy.null_seed);
+        get_random(&mut self.hierarchy.null_proof);
+    }
+
+    pub(crate) fn hierarchy_seed(&self, hierarchy: Hierarchy) -> &[u8; PRIMARY_SEED_SIZE] {
+        match hierarchy {
+            Hierarchy::Platform => &self.hierarchy.pps,
+            Hierarchy::Owner => &self.hierarchy.sps,
+            Hierarchy::Endorsement => &self.hierarchy.eps,
+            Hierarchy::Null => &self.hierarchy.null_seed,
+        }
+    }
+
+    pub(crate) fn hierarchy_proof(&self, hierarchy: Hierarchy) -> &[u8; PROOF_SIZE] {
+        match hierarchy {
+            Hierarchy::Platform => &self.hierarchy.ph_proof,
+            Hierarchy::Owner => &self.hierarchy.sh_proof,
+            Hierarchy::Endorsement => &self.hierarchy.eh_proof,
+            Hierarchy::Null => &self.hierarchy.null_proof,
+        }
+    }
+
+    // The null hierarchy can't be disabled.
+    pub(crate) fn hierarchy_is_enabled(&self, hierarchy: Hierarchy) -> bool {
+        match hierarchy {
+            Hierarchy::Platform => self.hierarchy.ph_enable,
+            Hierarchy::Owner => self.hierarchy.sh_enable,
+            Hierarchy::Endorsement => self.hierarchy.eh_enable,
+            Hierarchy::Null => true,
+        }
+    }
+
+    pub(crate) fn permanent_attributes(&self) -> u32 {
+        let h = &self.hierarchy;
+        let mut attributes = TPMA_PERMANENT_TPM_GENERATED_EPS;
+
+        if !h.owner_auth.is_empty() {
+            attributes |= TPMA_PERMANENT_OWNER_AUTH_SET;
+        }
+        if !h.endorsement_auth.is_empty() {
+            attributes |= TPMA_PERMANENT_ENDORSEMENT_AUTH_SET;
+        }
+        if !h.lockout_auth.is_empty() {
+            attributes |= TPMA_PERMANENT_LOCKOUT_AUTH_SET;
+        }
+        if h.disable_clear {
+            attributes |= TPMA_PERMANENT_DISABLE_CLEAR;
+        }
+        if self.da_in_lockout() {
+            attributes |= TPMA_PERMANENT_IN_LOCKOUT;
+        }
+
+        attributes
+    }
+
+    pub(crate) fn startup_clear_attributes(&self) -> u32 {
+        let h = &self.hierarchy;
+        let mut attributes = 0;
+
+        if h.ph_enable {
+            attributes |= TPMA_STARTUP_CLEAR_PH_ENABLE;
+        }
+        if h.sh_enable {
+            attributes |= TPMA_STARTUP_CLEAR_SH_ENABLE;
+        }
+        if h.eh_enable {
+            attributes |= TPMA_STARTUP_CLEAR_EH_ENABLE;
+        }
+        if h.ph_enable_nv {
+            attributes |= TPMA_STARTUP_CLEAR_PH_ENABLE_NV;
+        }
+        if self.nv.shutdown != Shutdown::None {
+            attributes |= TPMA_STARTUP_CLEAR_ORDERLY;
+        }
+
+        attributes
+    }
+}
+
+pub fn tpm2_hierarchy_control(
+    tpm: &mut TpmInstance,
+    args: &HierarchyControlArgs,
+) -> Result<(), TpmError> {
+    let auth = TpmRh::from(args.auth_handle);
+    let h = &mut tpm.hierarchy;
+
+    let select = match TpmRh::from(args.enable) {
+        TpmRh::Platform | TpmRh::PlatformNv if auth != TpmRh::Platform => {
+            return Err(TpmError::new(TpmRc::AuthType));
+        }
+        TpmRh::Platform => &mut h.ph_enable,
+        TpmRh::PlatformNv => &mut h.ph_enable_nv,
+        // The owner and endorsement hierarchies can disable themselves, but
+        // only platformAuth can turn them back on.
+        TpmRh::Owner if auth != TpmRh::Platform && auth != TpmRh::Owner => {
+            return Err(TpmError::new(TpmRc::AuthType));
+        }
+        TpmRh::Owner => &mut h.sh_enable,
+        TpmRh::Endorsement if auth != TpmRh::Platform && auth != TpmRh::Endorsement => {
+            return Err(TpmError::new(TpmRc::AuthType));
+        }
+        TpmRh::Endorsement => &mut h.eh_enable,
+        _ => return Err(TpmError::parameter(TpmRc::Value, 1)),
+    };
+
+    if args.state && auth != TpmRh::Platform {
+        return Err(TpmError::new(TpmRc::AuthType));
+    }
+
+    *select = args.state;
+
+    // Objects in a disabled hierarchy can't be used, so get rid of them.
+    if !args.state {
+        if let Some(hierarchy) = Hierarchy::from_handle(args.enable) {
+            tpm.object_flush_hierarchy(hierarchy);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn tpm2_set_primary_policy(
+    tpm: &mut TpmInstance,
+    args: &SetPrimaryPolicyArgs,
+) -> Result<(), TpmError> {
+    if args.auth_policy.size as usize != args.hash_alg.digest_size() {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+
+    let policy = TpmtHa {
+        hash_alg: args.hash_alg,
+        digest: args.auth_policy,
+    };
+
+    let h = &mut tpm.hierarchy;
+    match TpmRh::from(args.auth_handle) {
+        TpmRh::Owner => h.owner_policy = policy,
+        TpmRh::Endorsement => h.endorsement_policy = policy,
+        TpmRh::Platform => h.platform_policy = policy,
+        TpmRh::Lockout => h.lockout_policy = policy,
+        _ => return Err(TpmError::handle(TpmRc::Value, 1)),
+    }
+
+    // The platform policy doesn't outlive Startup(CLEAR).
+    match TpmRh::from(args.auth_handle) {
+        TpmRh::Platform => Ok(()),
+        _ => tpm.hierarchy_store(),
+    }
+}
+
+pub fn tpm2_hierarchy_change_auth(
+    tpm: &mut TpmInstance,
+    args: &HierarchyChangeAuthArgs,
+) -> Result<(), TpmError> {
+    // New auth values can't be longer than the digest used for proofs
+    if trim_trailing_zeros(args.new_auth.as_slice()).len() > PROOF_SIZE {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+
+    let h = &mut tpm.hierarchy;
+    match TpmRh::from(args.auth_handle) {
+        TpmRh::Owner => h.owner_auth = args.new_auth,
+        TpmRh::Endorsement => h.endorsement_auth = args.new_auth,
+        TpmRh::Platform => h.platform_auth = args.new_auth,
+        TpmRh::Lockout => h.lockout_auth = args.new_auth,
+        _ => return Err(TpmError::handle(TpmRc::Value, 1)),
+    }
+
+    match TpmRh::from(args.auth_handle) {
+        TpmRh::Platform => Ok(()),
+        _ => tpm.hierarchy_store(),
+    }
+}
+
+pub fn tpm2_change_pps(tpm: &mut TpmInstance) -> Result<(), TpmError> {
+    tpm.persistent_evict_hierarchy(Hierarchy::Platform)?;
+
+    let get_random = tpm.platform.get_random;
+    let h = &mut tpm.hierarchy;
+
+    // A new proof invalidates any saved platform hierarchy contexts and
+    // tickets along with the seed.
+    get_random(&mut h.pps);
+    get_random(&mut h.ph_proof);
+    h.platform_policy = TpmtHa::default();
+
+    tpm.object_flush_hierarchy(Hierarchy::Platform);
+
+    tpm.hierarchy_store()
+}
+
+pub fn tpm2_change_eps(tpm: &mut TpmInstance) -> Result<(), TpmError> {
+    tpm.persistent_evict_hierarchy(Hierarchy::Endorsement)?;
+
+    let get_random = tpm.platform.get_random;
+    let h = &mut tpm.hierarchy;
+
+    get_random(&mut h.eps);
+    get_random(&mut h.eh_proof);
+    h.endorsement_auth = Tpm2bAuth::default();
+    h.endorsement_policy = TpmtHa::default();
+
+    tpm.object_flush_hierarchy(Hierarchy::Endorsement);
+
+    tpm.hierarchy_store()
+}
+
+pub fn tpm2_clear(tpm: &mut TpmInstance) -> Result<(), TpmError> {
+    let get_random = tpm.platform.get_random;
+    let h = &mut tpm.hierarchy;
+
+    if h.disable_clear {
+        return Err(TpmError::new(TpmRc::Disabled));
+    }
+
+    // Persistent objects and owner NV indices go first, so a failed NV
+    // write leaves the hierarchies as they were.
+    tpm.persistent_evict_hierarchy(Hierarchy::Owner)?;
+    tpm.persistent_evict_hierarchy(Hierarchy::Endorsement)?;
+    tpm.nv_index_undefine_owner()?;
+
+    let h = &mut tpm.hierarchy;
+
+    get_random(&mut h.sps);
+    get_random(&mut h.sh_proof);
+    get_random(&mut h.eh_proof);
+
+    h.sh_enable = true;
+    h.eh_enable = true;
+
+    h.owner_auth = Tpm2bAuth::default();
+    h.endorsement_auth = Tpm2bAuth::default();
+    h.lockout_auth = Tpm2bAuth::default();
+    h.owner_policy = TpmtHa::default();
+    h.endorsement_policy = TpmtHa::default();
+    h.lockout_policy = TpmtHa::default();
+
+    tpm.object_flush_hierarchy(Hierarchy::Owner);
+    tpm.object_flush_hierarchy(Hierarchy::Endorsement);
+
+    tpm.hierarchy_store()
+}
+
+pub fn tpm2_clear_control(tpm: &mut TpmInstance, args: &ClearControlArgs) -> Result<(), TpmError> {
+    // lockoutAuth can disable TPM2_Clear but only platformAuth can re-enable
+    // it.
+    if TpmRh::from(args.auth) == TpmRh::Lockout && !args.disable {
+        return Err(TpmError::new(TpmRc::AuthFail));
+    }
+
+    tpm.hierarchy.disable_clear = args.disable;
+
+    tpm.hierarchy_store()
+}
+
+// Generate a primary object from its template. The key comes from a KDF
+// over the hierarchy seed with the Name of the template, so the same template
+// always gives the same key and callers can vary the unique field to get
+// different ones.
+pub(crate) fn generate_primary(
+    tpm: &TpmInstance,
+    hierarchy: Hierarchy,
+    public: &mut TpmtPublic,
+    in_sensitive: &TpmsSensitiveCreate,
+) -> Result<TpmtSensitive, TpmError> {
+    let template_name = public_name(public)?;
+    let seed = tpm.hierarchy_seed(hierarchy);
+    let mut rand = KdfRandom::new(
+        public.name_alg,
+        seed,
+        PRIMARY_OBJECT_CREATION,
+        template_name.as_slice(),
+        in_sensitive.data.as_slice(),
+    );
+
+    let mut sensitive = TpmtSensitive {
+        auth_value: Tpm2bAuth::from_slice(trim_trailing_zeros(in_sensitive.user_auth.as_slice()))?,
+        ..Default::default()
+    };
+    generate_object(public, &mut sensitive, &in_sensitive.data, &mut rand)?;
+
+    Ok(sensitive)
+}
+
+pub fn tpm2_create_primary(
+    tpm: &mut TpmInstance,
+    args: &CreatePrimaryArgs,
+) -> Result<CreatePrimaryResponse, TpmError> {
+    let hierarchy = match Hierarchy::from_handle(args.primary_handle) {
+        Some(hierarchy) => hierarchy,
+        None => return Err(TpmError::handle(TpmRc::Value, 1)),
+    };
+
+    let mut public = args.in_public;
+    create_checks(None, &public, &args.in_sensitive)?;
+
+    // Fail before the (possibly slow) key generation if there's nowhere to
+    // put the result.
+    tpm.object_free_slot()?;
+
+    let sensitive = generate_primary(tpm, hierarchy, &mut public, &args.in_sensitive)?;
+    let name = public_name(&public)?;
+
+    // A primary's parent is its hierarchy, whose Name is its handle.
+    let parent_name = Tpm2bName::from_slice(&hierarchy.handle().to_be_bytes())?;
+    let (creation_data, creation_hash) = creation_data(
+        tpm,
+        &public,
+        TpmAlgId::Null,
+        &parent_name,
+        &parent_name,
+        &args.outside_info,
+        &args.creation_pcr,
+    )?;
+    let creation_ticket = tpm.creation_ticket(hierarchy, &name, &creation_hash)?;
+
+    let object_handle = tpm.object_load(Object {
+        public,
+        sensitive,
+        name,
+        qualified_name: qualified_name(parent_name.as_slice(), &public, &name)?,
+        hierarchy,
+    })?;
+
+    Ok(CreatePrimaryResponse {
+        object_handle,
+        out_public: public,
+        creation_data,
+        creation_hash,
+        creation_ticket,
+        name,
+    })
+}
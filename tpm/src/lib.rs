@@ -7,9 +7,32 @@ pub mod types;
 
 // Command modules
 // TODO: This is going to be annoying for every command. Maybe group them?
+mod asymmetric;
+mod attest;
+mod authorization;
+mod clock;
+mod command;
+mod commit;
+mod context;
+mod credential;
+mod crypto;
+mod dictionary_attack;
+mod duplication;
 mod format;
 mod get_capability;
+mod hierarchy;
+mod nv;
+mod nv_index;
+mod object;
+mod pcr;
+mod persistent;
+mod policy;
+mod sequence;
+mod session;
+mod signature;
 mod startup;
+mod symmetric;
+mod ticket;
 
 use crate::marshal::*;
 use crate::types::*;
@@ -18,38 +41,21 @@ use tpm::TpmInstance;
 pub fn execute_command(tpm: &mut TpmInstance, request: &[u8], response: &mut [u8]) -> usize {
     let mut offset = 0;
 
-    let command_hdr = match unmarshal_command_header(request, &mut offset) {
-        Ok(header) => header,
-        Err(e) => {
-            let response_hdr = ResponseHeader {
-                tag: TpmCommandTag::NoSessions,
-                size: 0,
-                rc: e.rc,
-            };
-            match marshal_response_header(&mut response[..RESPONSE_HDR_SIZE], &response_hdr) {
-                Ok(_) => {
-                    return 0usize;
-                }
-                Err(_) => panic!("Reponse buffer was not big enough for response header"),
-            };
-        }
+    let result = match unmarshal_command_header(request, &mut offset) {
+        Ok(command_hdr) => tpm.execute(&command_hdr, request, &mut response[RESPONSE_HDR_SIZE..]),
+        Err(e) => Err(e),
     };
 
-    let response_hdr = match tpm.dispatch_command(
-        &command_hdr,
-        &request[COMMAND_HDR_SIZE..],
-        &mut response[RESPONSE_HDR_SIZE..],
-    ) {
-        Ok(size) => ResponseHeader {
-            tag: command_hdr.tag,
-            size: size as u32,
-            rc: TpmRc::Success,
-        },
-        Err(e) => ResponseHeader {
-            tag: command_hdr.tag,
-            size: 0,
-            rc: e.rc,
-        },
+    // Failed commands never return sessions or parameters.
+    let (tag, size, rc) = match result {
+        Ok((tag, size)) => (tag, size, TpmRc::Success as u32),
+        Err(e) => (TpmCommandTag::NoSessions, 0, e.code()),
+    };
+
+    let response_hdr = ResponseHeader {
+        tag,
+        size: (RESPONSE_HDR_SIZE + size) as u32,
+        rc,
     };
 
     // If response is too small to hold a header that is a bug in the caller
@@ -61,5 +67,5 @@ pub fn execute_command(tpm: &mut TpmInstance, request: &[u8], response: &mut [u8
         Err(_) => panic!("Reponse buffer was not big enough for response header"),
     };
 
-    RESPONSE_HDR_SIZE + response_hdr.size as usize
+    RESPONSE_HDR_SIZE + size
 }
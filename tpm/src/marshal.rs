@@ -2,20 +2,20 @@ use crate::types::*;
 use core::mem;
 
 pub fn unmarshal_u8(buffer: &[u8], offset: &mut usize) -> Result<u8, TpmError> {
+    let val = match buffer.get(*offset) {
+        Some(val) => *val,
+        None => return Err(TpmError::new(TpmRc::Insufficient)),
+    };
     *offset += 1;
 
-    Ok(buffer[*offset])
+    Ok(val)
 }
 
 pub fn unmarshal_u16(buffer: &[u8], offset: &mut usize) -> Result<u16, TpmError> {
     let size = mem::size_of::<u16>();
-    let arr = match buffer[*offset..*offset + size].try_into() {
-        Ok(arr) => arr,
-        Err(_) => {
-            return Err(TpmError {
-                rc: TpmRc::Insufficient,
-            });
-        }
+    let arr = match buffer.get(*offset..*offset + size) {
+        Some(bytes) => bytes.try_into().unwrap(),
+        None => return Err(TpmError::new(TpmRc::Insufficient)),
     };
 
     let val = u16::from_be_bytes(arr);
@@ -26,13 +26,9 @@ pub fn unmarshal_u16(buffer: &[u8], offset: &mut usize) -> Result<u16, TpmError>
 
 pub fn unmarshal_u32(buffer: &[u8], offset: &mut usize) -> Result<u32, TpmError> {
     let size = mem::size_of::<u32>();
-    let arr = match buffer[*offset..*offset + size].try_into() {
-        Ok(arr) => arr,
-        Err(_) => {
-            return Err(TpmError {
-                rc: TpmRc::Insufficient,
-            });
-        }
+    let arr = match buffer.get(*offset..*offset + size) {
+        Some(bytes) => bytes.try_into().unwrap(),
+        None => return Err(TpmError::new(TpmRc::Insufficient)),
     };
 
     let val = u32::from_be_bytes(arr);
@@ -41,6 +37,75 @@ pub fn unmarshal_u32(buffer: &[u8], offset: &mut usize) -> Result<u32, TpmError>
     Ok(val)
 }
 
+pub fn unmarshal_u64(buffer: &[u8], offset: &mut usize) -> Result<u64, TpmError> {
+    let size = mem::size_of::<u64>();
+    let arr = match buffer.get(*offset..*offset + size) {
+        Some(bytes) => bytes.try_into().unwrap(),
+        None => return Err(TpmError::new(TpmRc::Insufficient)),
+    };
+
+    let val = u64::from_be_bytes(arr);
+    *offset += size;
+
+    Ok(val)
+}
+
+pub fn unmarshal_bytes<'a>(
+    buffer: &'a [u8],
+    offset: &mut usize,
+    size: usize,
+) -> Result<&'a [u8], TpmError> {
+    let bytes = match buffer.get(*offset..*offset + size) {
+        Some(bytes) => bytes,
+        None => return Err(TpmError::new(TpmRc::Insufficient)),
+    };
+    *offset += size;
+
+    Ok(bytes)
+}
+
+pub fn unmarshal_tpm2b<const N: usize>(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<Tpm2b<N>, TpmError> {
+    let size = unmarshal_u16(buffer, offset)? as usize;
+    if size > N {
+        return Err(TpmError::new(TpmRc::Size));
+    }
+
+    Tpm2b::from_slice(unmarshal_bytes(buffer, offset, size)?)
+}
+
+pub fn unmarshal_handle(buffer: &[u8], offset: &mut usize) -> Result<TpmHandle, TpmError> {
+    unmarshal_u32(buffer, offset)
+}
+
+pub fn unmarshal_yes_no(buffer: &[u8], offset: &mut usize) -> Result<bool, TpmError> {
+    match unmarshal_u8(buffer, offset)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(TpmError::new(TpmRc::Value)),
+    }
+}
+
+pub fn unmarshal_alg_id(buffer: &[u8], offset: &mut usize) -> Result<TpmAlgId, TpmError> {
+    Ok(TpmAlgId::from(unmarshal_u16(buffer, offset)?))
+}
+
+// TPMI_ALG_HASH. `allow_null` is the '+' flag on the interface type.
+pub fn unmarshal_hash_alg(
+    buffer: &[u8],
+    offset: &mut usize,
+    allow_null: bool,
+) -> Result<TpmAlgId, TpmError> {
+    let alg = unmarshal_alg_id(buffer, offset)?;
+    if alg.is_hash() || (allow_null && alg == TpmAlgId::Null) {
+        Ok(alg)
+    } else {
+        Err(TpmError::new(TpmRc::Hash))
+    }
+}
+
 pub fn unmarshal_command_code(
     buffer: &[u8],
     offset: &mut usize,
@@ -49,9 +114,7 @@ pub fn unmarshal_command_code(
         Ok(code) => {
             let cc = TpmCommandCode::from(code);
             match cc {
-                TpmCommandCode::Unknown => Err(TpmError {
-                    rc: TpmRc::CommandCode,
-                }),
+                TpmCommandCode::Unknown => Err(TpmError::new(TpmRc::CommandCode)),
                 _ => Ok(cc),
             }
         }
@@ -64,7 +127,7 @@ pub fn unmarshal_tag(buffer: &[u8], offset: &mut usize) -> Result<TpmCommandTag,
         Ok(tag_u16) => {
             let tag = TpmCommandTag::from(tag_u16);
             match tag {
-                TpmCommandTag::Unknown => Err(TpmError { rc: TpmRc::BadTag }),
+                TpmCommandTag::Unknown => Err(TpmError::new(TpmRc::BadTag)),
                 _ => Ok(tag),
             }
         }
@@ -104,47 +167,30 @@ pub fn unmarshal_get_capability_args(
     buffer: &[u8],
     offset: &mut usize,
 ) -> Result<GetCapabilityArgs, TpmError> {
-    let mut args = GetCapabilityArgs::default();
-    args.cap = match unmarshal_capability(buffer, offset) {
-        Ok(cap) => cap,
-        Err(e) => return Err(e),
-    };
-
-    args.property = match unmarshal_pt(buffer, offset) {
-        Ok(prop) => prop,
-        Err(e) => return Err(e),
-    };
-
-    args.property_count = match unmarshal_u32(buffer, offset) {
-        Ok(count) => count,
-        Err(e) => return Err(e),
-    };
+    let cap = unmarshal_capability(buffer, offset)?;
+    let property = unmarshal_u32(buffer, offset)?;
+    let property_count = unmarshal_u32(buffer, offset)?;
 
-    Ok(args)
+    Ok(GetCapabilityArgs {
+        cap,
+        property,
+        property_count,
+    })
 }
 
 pub fn unmarshal_command_header(
     buffer: &[u8],
     offset: &mut usize,
 ) -> Result<CommandHeader, TpmError> {
-    let tag = match unmarshal_tag(buffer, offset) {
-        Ok(val) => val,
-        Err(e) => return Err(e),
-    };
+    let tag = unmarshal_tag(buffer, offset)?;
 
-    let size = match unmarshal_u32(buffer, offset) {
-        Ok(val) => val,
-        Err(e) => return Err(e),
-    };
+    let size = unmarshal_u32(buffer, offset)?;
 
-    let command_code = match unmarshal_command_code(buffer, offset) {
-        Ok(val) => val,
-        Err(e) => return Err(e),
-    };
+    let command_code = unmarshal_command_code(buffer, offset)?;
 
     Ok(CommandHeader {
         tag,
-        size: size,
+        size,
         command_code,
     })
 }
@@ -155,9 +201,7 @@ pub fn marshal_tag(buffer: &mut [u8], val: TpmCommandTag) -> Result<usize, TpmEr
 
 pub fn marshal_u8(buffer: &mut [u8], val: u8) -> Result<usize, TpmError> {
     if buffer.len() < mem::size_of::<u8>() {
-        return Err(TpmError {
-            rc: TpmRc::Insufficient,
-        });
+        return Err(TpmError::new(TpmRc::Insufficient));
     }
 
     buffer[0] = val;
@@ -167,9 +211,7 @@ pub fn marshal_u8(buffer: &mut [u8], val: u8) -> Result<usize, TpmError> {
 
 pub fn marshal_u16(buffer: &mut [u8], val: u16) -> Result<usize, TpmError> {
     if buffer.len() < mem::size_of::<u16>() {
-        return Err(TpmError {
-            rc: TpmRc::Insufficient,
-        });
+        return Err(TpmError::new(TpmRc::Insufficient));
     }
 
     let bytes = val.to_be_bytes();
@@ -180,9 +222,7 @@ pub fn marshal_u16(buffer: &mut [u8], val: u16) -> Result<usize, TpmError> {
 
 pub fn marshal_u32(buffer: &mut [u8], val: u32) -> Result<usize, TpmError> {
     if buffer.len() < mem::size_of::<u32>() {
-        return Err(TpmError {
-            rc: TpmRc::Insufficient,
-        });
+        return Err(TpmError::new(TpmRc::Insufficient));
     }
 
     let bytes = val.to_be_bytes();
@@ -191,25 +231,45 @@ pub fn marshal_u32(buffer: &mut [u8], val: u32) -> Result<usize, TpmError> {
     Ok(mem::size_of::<u32>())
 }
 
-pub fn marshal_rc(buffer: &mut [u8], val: TpmRc) -> Result<usize, TpmError> {
-    marshal_u32(buffer, val as u32)
+pub fn marshal_u64(buffer: &mut [u8], val: u64) -> Result<usize, TpmError> {
+    if buffer.len() < mem::size_of::<u64>() {
+        return Err(TpmError::new(TpmRc::Insufficient));
+    }
+
+    let bytes = val.to_be_bytes();
+    buffer[0..8].clone_from_slice(&bytes);
+
+    Ok(mem::size_of::<u64>())
+}
+
+pub fn marshal_bytes(buffer: &mut [u8], val: &[u8]) -> Result<usize, TpmError> {
+    if buffer.len() < val.len() {
+        return Err(TpmError::new(TpmRc::Insufficient));
+    }
+
+    buffer[..val.len()].copy_from_slice(val);
+
+    Ok(val.len())
+}
+
+pub fn marshal_tpm2b<const N: usize>(buffer: &mut [u8], val: &Tpm2b<N>) -> Result<usize, TpmError> {
+    let mut offset = marshal_u16(buffer, val.size)?;
+
+    offset += marshal_bytes(&mut buffer[offset..], val.as_slice())?;
+
+    Ok(offset)
+}
+
+pub fn marshal_handle(buffer: &mut [u8], val: TpmHandle) -> Result<usize, TpmError> {
+    marshal_u32(buffer, val)
 }
 
 pub fn marshal_response_header(buffer: &mut [u8], val: &ResponseHeader) -> Result<usize, TpmError> {
-    let mut offset = match marshal_tag(buffer, val.tag) {
-        Ok(offset) => offset,
-        Err(e) => return Err(e),
-    };
+    let mut offset = marshal_tag(buffer, val.tag)?;
 
-    offset = match marshal_u32(&mut buffer[offset..], val.size) {
-        Ok(offset) => offset,
-        Err(e) => return Err(e),
-    };
+    offset += marshal_u32(&mut buffer[offset..], val.size)?;
 
-    offset = match marshal_rc(&mut buffer[offset..], val.rc) {
-        Ok(offset) => offset,
-        Err(e) => return Err(e),
-    };
+    offset += marshal_u32(&mut buffer[offset..], val.rc)?;
 
     Ok(offset)
 }
@@ -218,15 +278,9 @@ pub fn marshal_tpms_tagged_property(
     buffer: &mut [u8],
     val: &TpmsTaggedProperty,
 ) -> Result<usize, TpmError> {
-    let mut offset = match marshal_u32(buffer, val.property as u32) {
-        Ok(offset) => offset,
-        Err(e) => return Err(e),
-    };
+    let mut offset = marshal_u32(buffer, val.property as u32)?;
 
-    offset += match marshal_u32(&mut buffer[offset..], val.val) {
-        Ok(offset) => offset,
-        Err(e) => return Err(e),
-    };
+    offset += marshal_u32(&mut buffer[offset..], val.val)?;
 
     Ok(offset)
 }
@@ -238,27 +292,39 @@ pub fn marshal_tpmu_capability_data(
     let mut offset = 0;
     match val {
         TpmuCapabilityData::TpmProperties(count, properties) => {
-            offset += match marshal_u32(buffer, TpmCapability::TpmProperty as u32) {
-                Ok(offset) => offset,
-                Err(e) => return Err(e),
-            };
+            offset += marshal_u32(buffer, TpmCapability::TpmProperty as u32)?;
 
-            offset += match marshal_u32(&mut buffer[offset..], *count) {
-                Ok(offset) => offset,
-                Err(e) => return Err(e),
-            };
+            offset += marshal_u32(&mut buffer[offset..], *count)?;
 
             for i in 0..*count {
-                offset += match marshal_tpms_tagged_property(
-                    &mut buffer[offset..],
-                    &properties[i as usize],
-                ) {
-                    Ok(offset) => offset,
-                    Err(e) => return Err(e),
-                };
+                offset +=
+                    marshal_tpms_tagged_property(&mut buffer[offset..], &properties[i as usize])?;
+            }
+        }
+        TpmuCapabilityData::AssignedPcr(selection) => {
+            offset += marshal_u32(buffer, TpmCapability::Pcrs as u32)?;
+
+            offset += marshal_tpml_pcr_selection(&mut buffer[offset..], selection)?;
+        }
+        TpmuCapabilityData::Handles(count, handles) => {
+            offset += marshal_u32(buffer, TpmCapability::Handles as u32)?;
+
+            offset += marshal_u32(&mut buffer[offset..], *count)?;
+
+            for handle in &handles[..*count as usize] {
+                offset += marshal_u32(&mut buffer[offset..], *handle)?;
+            }
+        }
+        TpmuCapabilityData::EccCurves(count, curves) => {
+            offset += marshal_u32(buffer, TpmCapability::EccCurves as u32)?;
+
+            offset += marshal_u32(&mut buffer[offset..], *count)?;
+
+            for curve in &curves[..*count as usize] {
+                offset += marshal_u16(&mut buffer[offset..], *curve as u16)?;
             }
         }
-        TpmuCapabilityData::Unknown => return Err(TpmError { rc: TpmRc::Value }),
+        TpmuCapabilityData::Unknown => return Err(TpmError::new(TpmRc::Value)),
     }
 
     Ok(offset)
@@ -268,15 +334,2108 @@ pub fn marshal_get_capability_response(
     buffer: &mut [u8],
     val: &GetCapabilityResponse,
 ) -> Result<usize, TpmError> {
-    let mut offset = match marshal_u8(buffer, val.more_data as u8) {
-        Ok(offset) => offset,
-        Err(e) => return Err(e),
+    let mut offset = marshal_u8(buffer, val.more_data as u8)?;
+
+    offset += marshal_tpmu_capability_data(&mut buffer[offset..], &val.data)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_tpms_auth_command(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<TpmsAuthCommand, TpmError> {
+    let session_handle = unmarshal_handle(buffer, offset)?;
+    let nonce = unmarshal_tpm2b(buffer, offset)?;
+    let session_attributes = unmarshal_u8(buffer, offset)?;
+    let hmac = unmarshal_tpm2b(buffer, offset)?;
+
+    Ok(TpmsAuthCommand {
+        session_handle,
+        nonce,
+        session_attributes,
+        hmac,
+    })
+}
+
+pub fn marshal_tpms_auth_response(
+    buffer: &mut [u8],
+    val: &TpmsAuthResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b(buffer, &val.nonce)?;
+
+    offset += marshal_u8(&mut buffer[offset..], val.session_attributes)?;
+
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.hmac)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_hierarchy_control_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<HierarchyControlArgs, TpmError> {
+    // TPMI_RH_ENABLES
+    let enable = unmarshal_handle(buffer, offset)?;
+    match TpmRh::from(enable) {
+        TpmRh::Owner | TpmRh::Endorsement | TpmRh::Platform | TpmRh::PlatformNv => (),
+        _ => return Err(TpmError::parameter(TpmRc::Value, 1)),
+    }
+
+    let state = unmarshal_yes_no(buffer, offset).map_err(|e| e.with_parameter(2))?;
+
+    Ok(HierarchyControlArgs {
+        enable,
+        state,
+        ..Default::default()
+    })
+}
+
+pub fn unmarshal_set_primary_policy_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<SetPrimaryPolicyArgs, TpmError> {
+    let auth_policy = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let hash_alg = unmarshal_hash_alg(buffer, offset, true).map_err(|e| e.with_parameter(2))?;
+
+    Ok(SetPrimaryPolicyArgs {
+        auth_policy,
+        hash_alg,
+        ..Default::default()
+    })
+}
+
+pub fn unmarshal_clear_control_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<ClearControlArgs, TpmError> {
+    let disable = unmarshal_yes_no(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(ClearControlArgs {
+        disable,
+        ..Default::default()
+    })
+}
+
+pub fn unmarshal_dictionary_attack_parameters_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<DictionaryAttackParametersArgs, TpmError> {
+    let new_max_tries = unmarshal_u32(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let new_recovery_time = unmarshal_u32(buffer, offset).map_err(|e| e.with_parameter(2))?;
+    let lockout_recovery = unmarshal_u32(buffer, offset).map_err(|e| e.with_parameter(3))?;
+
+    Ok(DictionaryAttackParametersArgs {
+        new_max_tries,
+        new_recovery_time,
+        lockout_recovery,
+        ..Default::default()
+    })
+}
+
+pub fn unmarshal_hierarchy_change_auth_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<HierarchyChangeAuthArgs, TpmError> {
+    let new_auth = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(HierarchyChangeAuthArgs {
+        new_auth,
+        ..Default::default()
+    })
+}
+
+// Sized structures (TPM2B_PUBLIC, TPM2B_SENSITIVE_CREATE, ...) carry a size
+// that must exactly cover the structure inside. They can't be empty.
+fn unmarshal_sized<T>(
+    buffer: &[u8],
+    offset: &mut usize,
+    unmarshal: fn(&[u8], &mut usize) -> Result<T, TpmError>,
+) -> Result<T, TpmError> {
+    let size = unmarshal_u16(buffer, offset)? as usize;
+    if size == 0 {
+        return Err(TpmError::new(TpmRc::Size));
+    }
+
+    let inner = unmarshal_bytes(buffer, offset, size)?;
+    let mut inner_offset = 0;
+    let val = unmarshal(inner, &mut inner_offset)?;
+    if inner_offset != size {
+        return Err(TpmError::new(TpmRc::Size));
+    }
+
+    Ok(val)
+}
+
+fn marshal_sized<T>(
+    buffer: &mut [u8],
+    val: &T,
+    marshal: fn(&mut [u8], &T) -> Result<usize, TpmError>,
+) -> Result<usize, TpmError> {
+    if buffer.len() < 2 {
+        return Err(TpmError::new(TpmRc::Insufficient));
+    }
+
+    let size = marshal(&mut buffer[2..], val)?;
+    marshal_u16(buffer, size as u16)?;
+
+    Ok(2 + size)
+}
+
+// TPMI_ALG_SYM_MODE+
+fn unmarshal_sym_mode(buffer: &[u8], offset: &mut usize) -> Result<TpmAlgId, TpmError> {
+    match unmarshal_alg_id(buffer, offset)? {
+        mode @ (TpmAlgId::Ctr
+        | TpmAlgId::Ofb
+        | TpmAlgId::Cbc
+        | TpmAlgId::Cfb
+        | TpmAlgId::Ecb
+        | TpmAlgId::Null) => Ok(mode),
+        _ => Err(TpmError::new(TpmRc::Mode)),
+    }
+}
+
+// TPMT_SYM_DEF_OBJECT
+pub fn unmarshal_sym_def_object(
+    buffer: &[u8],
+    offset: &mut usize,
+    allow_null: bool,
+) -> Result<TpmtSymDefObject, TpmError> {
+    let algorithm = unmarshal_alg_id(buffer, offset)?;
+    match algorithm {
+        TpmAlgId::Aes => (),
+        #[cfg(feature = "sm4")]
+        TpmAlgId::Sm4 => (),
+        #[cfg(feature = "camellia")]
+        TpmAlgId::Camellia => (),
+        TpmAlgId::Null if allow_null => return Ok(TpmtSymDefObject::default()),
+        _ => return Err(TpmError::new(TpmRc::Symmetric)),
+    }
+
+    let key_bits = unmarshal_u16(buffer, offset)?;
+    let key_bits_ok = match algorithm {
+        TpmAlgId::Sm4 => key_bits == 128,
+        _ => matches!(key_bits, 128 | 192 | 256),
     };
+    if !key_bits_ok {
+        return Err(TpmError::new(TpmRc::Value));
+    }
+
+    let mode = unmarshal_sym_mode(buffer, offset)?;
+
+    Ok(TpmtSymDefObject {
+        algorithm,
+        key_bits,
+        mode,
+    })
+}
+
+pub fn marshal_sym_def_object(
+    buffer: &mut [u8],
+    val: &TpmtSymDefObject,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_u16(buffer, val.algorithm as u16)?;
+    if val.algorithm == TpmAlgId::Null {
+        return Ok(offset);
+    }
+
+    offset += marshal_u16(&mut buffer[offset..], val.key_bits)?;
+    offset += marshal_u16(&mut buffer[offset..], val.mode as u16)?;
+
+    Ok(offset)
+}
+
+// TPMT_RSA_SCHEME and TPMT_ECC_SCHEME, with `allowed` being the schemes
+// the interface type accepts. TPM_ALG_NULL is always accepted.
+pub fn unmarshal_asym_scheme(
+    buffer: &[u8],
+    offset: &mut usize,
+    allowed: &[TpmAlgId],
+) -> Result<TpmtAsymScheme, TpmError> {
+    let scheme = unmarshal_alg_id(buffer, offset)?;
+    if scheme == TpmAlgId::Null {
+        return Ok(TpmtAsymScheme::default());
+    }
+    if !allowed.contains(&scheme) {
+        return Err(TpmError::new(TpmRc::Value));
+    }
 
-    offset += match marshal_tpmu_capability_data(&mut buffer[offset..], &val.data) {
-        Ok(offset) => offset,
-        Err(e) => return Err(e),
+    // RSAES is the only scheme without a hash
+    if scheme == TpmAlgId::RsaEs {
+        return Ok(TpmtAsymScheme {
+            scheme,
+            ..Default::default()
+        });
+    }
+
+    let hash_alg = unmarshal_hash_alg(buffer, offset, false)?;
+    let count = match scheme {
+        TpmAlgId::EcDaa => unmarshal_u16(buffer, offset)?,
+        _ => 0,
     };
 
+    Ok(TpmtAsymScheme {
+        scheme,
+        hash_alg,
+        count,
+    })
+}
+
+pub fn marshal_asym_scheme(buffer: &mut [u8], val: &TpmtAsymScheme) -> Result<usize, TpmError> {
+    let mut offset = marshal_u16(buffer, val.scheme as u16)?;
+    if matches!(val.scheme, TpmAlgId::Null | TpmAlgId::RsaEs) {
+        return Ok(offset);
+    }
+
+    offset += marshal_u16(&mut buffer[offset..], val.hash_alg as u16)?;
+    if val.scheme == TpmAlgId::EcDaa {
+        offset += marshal_u16(&mut buffer[offset..], val.count)?;
+    }
+
+    Ok(offset)
+}
+
+// TPMI_ALG_KDF
+fn unmarshal_kdf_alg(
+    buffer: &[u8],
+    offset: &mut usize,
+    allow_null: bool,
+) -> Result<TpmAlgId, TpmError> {
+    let alg = unmarshal_alg_id(buffer, offset)?;
+    match alg {
+        TpmAlgId::Mgf1 | TpmAlgId::Kdf1Sp800_56a | TpmAlgId::Kdf2 | TpmAlgId::Kdf1Sp800_108 => {
+            Ok(alg)
+        }
+        TpmAlgId::Null if allow_null => Ok(alg),
+        _ => Err(TpmError::new(TpmRc::Kdf)),
+    }
+}
+
+pub fn unmarshal_kdf_scheme(buffer: &[u8], offset: &mut usize) -> Result<TpmtKdfScheme, TpmError> {
+    let scheme = unmarshal_kdf_alg(buffer, offset, true)?;
+    if scheme == TpmAlgId::Null {
+        return Ok(TpmtKdfScheme::default());
+    }
+
+    let hash_alg = unmarshal_hash_alg(buffer, offset, false)?;
+
+    Ok(TpmtKdfScheme { scheme, hash_alg })
+}
+
+pub fn marshal_kdf_scheme(buffer: &mut [u8], val: &TpmtKdfScheme) -> Result<usize, TpmError> {
+    let mut offset = marshal_u16(buffer, val.scheme as u16)?;
+    if val.scheme != TpmAlgId::Null {
+        offset += marshal_u16(&mut buffer[offset..], val.hash_alg as u16)?;
+    }
+
+    Ok(offset)
+}
+
+pub fn unmarshal_keyed_hash_scheme(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<TpmtKeyedHashScheme, TpmError> {
+    let scheme = unmarshal_alg_id(buffer, offset)?;
+    match scheme {
+        TpmAlgId::Hmac => {
+            let hash_alg = unmarshal_hash_alg(buffer, offset, false)?;
+            Ok(TpmtKeyedHashScheme {
+                scheme,
+                hash_alg,
+                ..Default::default()
+            })
+        }
+        TpmAlgId::Xor => {
+            let hash_alg = unmarshal_hash_alg(buffer, offset, false)?;
+            let kdf = unmarshal_kdf_alg(buffer, offset, false)?;
+            Ok(TpmtKeyedHashScheme {
+                scheme,
+                hash_alg,
+                kdf,
+            })
+        }
+        TpmAlgId::Null => Ok(TpmtKeyedHashScheme::default()),
+        _ => Err(TpmError::new(TpmRc::Value)),
+    }
+}
+
+pub fn marshal_keyed_hash_scheme(
+    buffer: &mut [u8],
+    val: &TpmtKeyedHashScheme,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_u16(buffer, val.scheme as u16)?;
+    match val.scheme {
+        TpmAlgId::Hmac => {
+            offset += marshal_u16(&mut buffer[offset..], val.hash_alg as u16)?;
+        }
+        TpmAlgId::Xor => {
+            offset += marshal_u16(&mut buffer[offset..], val.hash_alg as u16)?;
+            offset += marshal_u16(&mut buffer[offset..], val.kdf as u16)?;
+        }
+        _ => (),
+    }
+
+    Ok(offset)
+}
+
+pub fn unmarshal_ecc_point(buffer: &[u8], offset: &mut usize) -> Result<TpmsEccPoint, TpmError> {
+    let x = unmarshal_tpm2b(buffer, offset)?;
+    let y = unmarshal_tpm2b(buffer, offset)?;
+
+    Ok(TpmsEccPoint { x, y })
+}
+
+pub fn marshal_ecc_point(buffer: &mut [u8], val: &TpmsEccPoint) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b(buffer, &val.x)?;
+
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.y)?;
+
     Ok(offset)
 }
+
+pub fn unmarshal_tpm2b_ecc_point(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<TpmsEccPoint, TpmError> {
+    unmarshal_sized(buffer, offset, unmarshal_ecc_point)
+}
+
+// An empty point, like outZ2 of the schemes that only make one, is just a
+// zero size.
+pub fn marshal_tpm2b_ecc_point(buffer: &mut [u8], val: &TpmsEccPoint) -> Result<usize, TpmError> {
+    if val.x.size == 0 && val.y.size == 0 {
+        return marshal_u16(buffer, 0);
+    }
+
+    marshal_sized(buffer, val, marshal_ecc_point)
+}
+
+const RSA_SCHEMES: &[TpmAlgId] = &[
+    TpmAlgId::RsaSsa,
+    TpmAlgId::RsaEs,
+    TpmAlgId::RsaPss,
+    TpmAlgId::Oaep,
+];
+
+const ECC_SCHEMES: &[TpmAlgId] = &[
+    TpmAlgId::EcDsa,
+    TpmAlgId::EcDh,
+    TpmAlgId::EcDaa,
+    TpmAlgId::Sm2,
+    TpmAlgId::EcSchnorr,
+    TpmAlgId::EcMqv,
+];
+
+pub fn unmarshal_tpmt_public(buffer: &[u8], offset: &mut usize) -> Result<TpmtPublic, TpmError> {
+    unmarshal_public_area(buffer, offset, None)
+}
+
+// A TPMT_PUBLIC, or with `derive` set, a template for a derived object whose
+// unique field holds a TPMS_DERIVE instead. The unique field of the returned
+// area is then left empty.
+fn unmarshal_public_area(
+    buffer: &[u8],
+    offset: &mut usize,
+    derive: Option<&mut TpmsDerive>,
+) -> Result<TpmtPublic, TpmError> {
+    // TPMI_ALG_PUBLIC
+    let object_type = unmarshal_alg_id(buffer, offset)?;
+    if !matches!(
+        object_type,
+        TpmAlgId::KeyedHash | TpmAlgId::SymCipher | TpmAlgId::Rsa | TpmAlgId::Ecc
+    ) {
+        return Err(TpmError::new(TpmRc::Type));
+    }
+
+    let name_alg = unmarshal_hash_alg(buffer, offset, true)?;
+
+    let object_attributes = unmarshal_u32(buffer, offset)?;
+    if object_attributes & TPMA_OBJECT_RESERVED != 0 {
+        return Err(TpmError::new(TpmRc::ReservedBits));
+    }
+
+    let auth_policy = unmarshal_tpm2b(buffer, offset)?;
+
+    let parameters = match object_type {
+        TpmAlgId::KeyedHash => {
+            let scheme = unmarshal_keyed_hash_scheme(buffer, offset)?;
+            TpmuPublicParms::KeyedHash(TpmsKeyedHashParms { scheme })
+        }
+        TpmAlgId::SymCipher => {
+            let sym = unmarshal_sym_def_object(buffer, offset, false)?;
+            TpmuPublicParms::SymCipher(TpmsSymCipherParms { sym })
+        }
+        TpmAlgId::Rsa => {
+            let symmetric = unmarshal_sym_def_object(buffer, offset, true)?;
+            let scheme = unmarshal_asym_scheme(buffer, offset, RSA_SCHEMES)?;
+            let key_bits = unmarshal_u16(buffer, offset)?;
+            if !matches!(key_bits, 1024 | 2048 | 3072 | 4096) {
+                return Err(TpmError::new(TpmRc::Value));
+            }
+            let exponent = unmarshal_u32(buffer, offset)?;
+            TpmuPublicParms::Rsa(TpmsRsaParms {
+                symmetric,
+                scheme,
+                key_bits,
+                exponent,
+            })
+        }
+        _ => {
+            let symmetric = unmarshal_sym_def_object(buffer, offset, true)?;
+            let scheme = unmarshal_asym_scheme(buffer, offset, ECC_SCHEMES)?;
+            let curve_id = TpmEccCurve::from(unmarshal_u16(buffer, offset)?);
+            if matches!(curve_id, TpmEccCurve::None | TpmEccCurve::Unknown) {
+                return Err(TpmError::new(TpmRc::Curve));
+            }
+            let kdf = unmarshal_kdf_scheme(buffer, offset)?;
+            TpmuPublicParms::Ecc(TpmsEccParms {
+                symmetric,
+                scheme,
+                curve_id,
+                kdf,
+            })
+        }
+    };
+
+    let unique = match derive {
+        Some(derive) => {
+            derive.label = unmarshal_tpm2b(buffer, offset)?;
+            derive.context = unmarshal_tpm2b(buffer, offset)?;
+            match object_type {
+                TpmAlgId::KeyedHash => TpmuPublicId::KeyedHash(Default::default()),
+                TpmAlgId::SymCipher => TpmuPublicId::SymCipher(Default::default()),
+                TpmAlgId::Rsa => TpmuPublicId::Rsa(Default::default()),
+                _ => TpmuPublicId::Ecc(Default::default()),
+            }
+        }
+        None => match object_type {
+            TpmAlgId::KeyedHash => TpmuPublicId::KeyedHash(unmarshal_tpm2b(buffer, offset)?),
+            TpmAlgId::SymCipher => TpmuPublicId::SymCipher(unmarshal_tpm2b(buffer, offset)?),
+            TpmAlgId::Rsa => TpmuPublicId::Rsa(unmarshal_tpm2b(buffer, offset)?),
+            _ => TpmuPublicId::Ecc(unmarshal_ecc_point(buffer, offset)?),
+        },
+    };
+
+    Ok(TpmtPublic {
+        name_alg,
+        object_attributes,
+        auth_policy,
+        parameters,
+        unique,
+    })
+}
+
+// The contents of a TPM2B_TEMPLATE, which must be used up exactly.
+pub fn unmarshal_template(
+    template: &Tpm2bTemplate,
+    derive: Option<&mut TpmsDerive>,
+) -> Result<TpmtPublic, TpmError> {
+    let buffer = template.as_slice();
+    let mut offset = 0;
+    let public = unmarshal_public_area(buffer, &mut offset, derive)?;
+    if offset != buffer.len() {
+        return Err(TpmError::new(TpmRc::Size));
+    }
+
+    Ok(public)
+}
+
+pub fn marshal_tpmt_public(buffer: &mut [u8], val: &TpmtPublic) -> Result<usize, TpmError> {
+    let mut offset = marshal_u16(buffer, val.object_type() as u16)?;
+
+    offset += marshal_u16(&mut buffer[offset..], val.name_alg as u16)?;
+    offset += marshal_u32(&mut buffer[offset..], val.object_attributes)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.auth_policy)?;
+
+    match &val.parameters {
+        TpmuPublicParms::KeyedHash(parms) => {
+            offset += marshal_keyed_hash_scheme(&mut buffer[offset..], &parms.scheme)?;
+        }
+        TpmuPublicParms::SymCipher(parms) => {
+            offset += marshal_sym_def_object(&mut buffer[offset..], &parms.sym)?;
+        }
+        TpmuPublicParms::Rsa(parms) => {
+            offset += marshal_sym_def_object(&mut buffer[offset..], &parms.symmetric)?;
+            offset += marshal_asym_scheme(&mut buffer[offset..], &parms.scheme)?;
+            offset += marshal_u16(&mut buffer[offset..], parms.key_bits)?;
+            offset += marshal_u32(&mut buffer[offset..], parms.exponent)?;
+        }
+        TpmuPublicParms::Ecc(parms) => {
+            offset += marshal_sym_def_object(&mut buffer[offset..], &parms.symmetric)?;
+            offset += marshal_asym_scheme(&mut buffer[offset..], &parms.scheme)?;
+            offset += marshal_u16(&mut buffer[offset..], parms.curve_id as u16)?;
+            offset += marshal_kdf_scheme(&mut buffer[offset..], &parms.kdf)?;
+        }
+        TpmuPublicParms::Unknown => return Err(TpmError::new(TpmRc::Type)),
+    }
+
+    match &val.unique {
+        TpmuPublicId::KeyedHash(digest) | TpmuPublicId::SymCipher(digest) => {
+            offset += marshal_tpm2b(&mut buffer[offset..], digest)?;
+        }
+        TpmuPublicId::Rsa(n) => offset += marshal_tpm2b(&mut buffer[offset..], n)?,
+        TpmuPublicId::Ecc(point) => offset += marshal_ecc_point(&mut buffer[offset..], point)?,
+        TpmuPublicId::Unknown => return Err(TpmError::new(TpmRc::Type)),
+    }
+
+    Ok(offset)
+}
+
+pub fn unmarshal_tpm2b_public(buffer: &[u8], offset: &mut usize) -> Result<TpmtPublic, TpmError> {
+    unmarshal_sized(buffer, offset, unmarshal_tpmt_public)
+}
+
+pub fn marshal_tpm2b_public(buffer: &mut [u8], val: &TpmtPublic) -> Result<usize, TpmError> {
+    marshal_sized(buffer, val, marshal_tpmt_public)
+}
+
+pub fn unmarshal_tpmt_sensitive(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<TpmtSensitive, TpmError> {
+    let sensitive_type = unmarshal_alg_id(buffer, offset)?;
+    let auth_value = unmarshal_tpm2b(buffer, offset)?;
+    let seed_value = unmarshal_tpm2b(buffer, offset)?;
+
+    let sensitive = match sensitive_type {
+        TpmAlgId::Rsa => TpmuSensitiveComposite::Rsa(unmarshal_tpm2b(buffer, offset)?),
+        TpmAlgId::Ecc => TpmuSensitiveComposite::Ecc(unmarshal_tpm2b(buffer, offset)?),
+        TpmAlgId::KeyedHash => TpmuSensitiveComposite::Bits(unmarshal_tpm2b(buffer, offset)?),
+        TpmAlgId::SymCipher => TpmuSensitiveComposite::Sym(unmarshal_tpm2b(buffer, offset)?),
+        _ => return Err(TpmError::new(TpmRc::Type)),
+    };
+
+    Ok(TpmtSensitive {
+        auth_value,
+        seed_value,
+        sensitive,
+    })
+}
+
+pub fn marshal_tpmt_sensitive(buffer: &mut [u8], val: &TpmtSensitive) -> Result<usize, TpmError> {
+    let mut offset = marshal_u16(buffer, val.sensitive_type() as u16)?;
+
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.auth_value)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.seed_value)?;
+
+    match &val.sensitive {
+        TpmuSensitiveComposite::Rsa(p) => offset += marshal_tpm2b(&mut buffer[offset..], p)?,
+        TpmuSensitiveComposite::Ecc(d) => offset += marshal_tpm2b(&mut buffer[offset..], d)?,
+        TpmuSensitiveComposite::Bits(bits) => offset += marshal_tpm2b(&mut buffer[offset..], bits)?,
+        TpmuSensitiveComposite::Sym(key) => offset += marshal_tpm2b(&mut buffer[offset..], key)?,
+        TpmuSensitiveComposite::Unknown => return Err(TpmError::new(TpmRc::Type)),
+    }
+
+    Ok(offset)
+}
+
+pub fn unmarshal_tpm2b_sensitive(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<TpmtSensitive, TpmError> {
+    unmarshal_sized(buffer, offset, unmarshal_tpmt_sensitive)
+}
+
+// A TPM2B_SENSITIVE that may be empty.
+pub fn unmarshal_tpm2b_sensitive_optional(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<Option<TpmtSensitive>, TpmError> {
+    let mut size_offset = *offset;
+    if unmarshal_u16(buffer, &mut size_offset)? == 0 {
+        *offset = size_offset;
+        return Ok(None);
+    }
+
+    Ok(Some(unmarshal_tpm2b_sensitive(buffer, offset)?))
+}
+
+pub fn marshal_tpm2b_sensitive(buffer: &mut [u8], val: &TpmtSensitive) -> Result<usize, TpmError> {
+    marshal_sized(buffer, val, marshal_tpmt_sensitive)
+}
+
+fn unmarshal_tpms_sensitive_create(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<TpmsSensitiveCreate, TpmError> {
+    let user_auth = unmarshal_tpm2b(buffer, offset)?;
+    let data = unmarshal_tpm2b(buffer, offset)?;
+
+    Ok(TpmsSensitiveCreate { user_auth, data })
+}
+
+pub fn unmarshal_tpm2b_sensitive_create(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<TpmsSensitiveCreate, TpmError> {
+    unmarshal_sized(buffer, offset, unmarshal_tpms_sensitive_create)
+}
+
+pub fn unmarshal_tpml_pcr_selection(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<TpmlPcrSelection, TpmError> {
+    let count = unmarshal_u32(buffer, offset)?;
+    if count as usize > HASH_COUNT {
+        return Err(TpmError::new(TpmRc::Size));
+    }
+
+    let mut val = TpmlPcrSelection {
+        count,
+        ..Default::default()
+    };
+    for selection in val.pcr_selections[..count as usize].iter_mut() {
+        selection.hash = unmarshal_hash_alg(buffer, offset, false)?;
+        selection.size_of_select = unmarshal_u8(buffer, offset)?;
+
+        let size = selection.size_of_select as usize;
+        if !(PCR_SELECT_MIN..=PCR_SELECT_MAX).contains(&size) {
+            return Err(TpmError::new(TpmRc::Value));
+        }
+        selection.pcr_select[..size].copy_from_slice(unmarshal_bytes(buffer, offset, size)?);
+    }
+
+    Ok(val)
+}
+
+pub fn marshal_tpml_pcr_selection(
+    buffer: &mut [u8],
+    val: &TpmlPcrSelection,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_u32(buffer, val.count)?;
+
+    for selection in val.pcr_selections[..val.count as usize].iter() {
+        let size = selection.size_of_select as usize;
+        offset += marshal_u16(&mut buffer[offset..], selection.hash as u16)?;
+        offset += marshal_u8(&mut buffer[offset..], selection.size_of_select)?;
+        offset += marshal_bytes(&mut buffer[offset..], &selection.pcr_select[..size])?;
+    }
+
+    Ok(offset)
+}
+
+pub fn marshal_tpms_creation_data(
+    buffer: &mut [u8],
+    val: &TpmsCreationData,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpml_pcr_selection(buffer, &val.pcr_select)?;
+
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.pcr_digest)?;
+    offset += marshal_u8(&mut buffer[offset..], val.locality)?;
+    offset += marshal_u16(&mut buffer[offset..], val.parent_name_alg as u16)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.parent_name)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.parent_qualified_name)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.outside_info)?;
+
+    Ok(offset)
+}
+
+pub fn marshal_tpm2b_creation_data(
+    buffer: &mut [u8],
+    val: &TpmsCreationData,
+) -> Result<usize, TpmError> {
+    marshal_sized(buffer, val, marshal_tpms_creation_data)
+}
+
+pub fn marshal_tpmt_tk_creation(
+    buffer: &mut [u8],
+    val: &TpmtTkCreation,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_u16(buffer, TPM_ST_CREATION)?;
+
+    offset += marshal_handle(&mut buffer[offset..], val.hierarchy)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.digest)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_create_primary_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<CreatePrimaryArgs, TpmError> {
+    let in_sensitive =
+        unmarshal_tpm2b_sensitive_create(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let in_public = unmarshal_tpm2b_public(buffer, offset).map_err(|e| e.with_parameter(2))?;
+    let outside_info = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(3))?;
+    let creation_pcr =
+        unmarshal_tpml_pcr_selection(buffer, offset).map_err(|e| e.with_parameter(4))?;
+
+    Ok(CreatePrimaryArgs {
+        in_sensitive,
+        in_public,
+        outside_info,
+        creation_pcr,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_create_primary_response(
+    buffer: &mut [u8],
+    val: &CreatePrimaryResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_handle(buffer, val.object_handle)?;
+
+    offset += marshal_tpm2b_public(&mut buffer[offset..], &val.out_public)?;
+    offset += marshal_tpm2b_creation_data(&mut buffer[offset..], &val.creation_data)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.creation_hash)?;
+    offset += marshal_tpmt_tk_creation(&mut buffer[offset..], &val.creation_ticket)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.name)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_create_args(buffer: &[u8], offset: &mut usize) -> Result<CreateArgs, TpmError> {
+    let in_sensitive =
+        unmarshal_tpm2b_sensitive_create(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let in_public = unmarshal_tpm2b_public(buffer, offset).map_err(|e| e.with_parameter(2))?;
+    let outside_info = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(3))?;
+    let creation_pcr =
+        unmarshal_tpml_pcr_selection(buffer, offset).map_err(|e| e.with_parameter(4))?;
+
+    Ok(CreateArgs {
+        in_sensitive,
+        in_public,
+        outside_info,
+        creation_pcr,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_create_response(buffer: &mut [u8], val: &CreateResponse) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b(buffer, &val.out_private)?;
+
+    offset += marshal_tpm2b_public(&mut buffer[offset..], &val.out_public)?;
+    offset += marshal_tpm2b_creation_data(&mut buffer[offset..], &val.creation_data)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.creation_hash)?;
+    offset += marshal_tpmt_tk_creation(&mut buffer[offset..], &val.creation_ticket)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_load_args(buffer: &[u8], offset: &mut usize) -> Result<LoadArgs, TpmError> {
+    let in_private = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let in_public = unmarshal_tpm2b_public(buffer, offset).map_err(|e| e.with_parameter(2))?;
+
+    Ok(LoadArgs {
+        in_private,
+        in_public,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_load_response(buffer: &mut [u8], val: &LoadResponse) -> Result<usize, TpmError> {
+    let mut offset = marshal_handle(buffer, val.object_handle)?;
+
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.name)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_create_loaded_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<CreateLoadedArgs, TpmError> {
+    let in_sensitive =
+        unmarshal_tpm2b_sensitive_create(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let in_public = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(2))?;
+
+    Ok(CreateLoadedArgs {
+        in_sensitive,
+        in_public,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_create_loaded_response(
+    buffer: &mut [u8],
+    val: &CreateLoadedResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_handle(buffer, val.object_handle)?;
+
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.out_private)?;
+    offset += marshal_tpm2b_public(&mut buffer[offset..], &val.out_public)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.name)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_load_external_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<LoadExternalArgs, TpmError> {
+    let in_private =
+        unmarshal_tpm2b_sensitive_optional(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let in_public = unmarshal_tpm2b_public(buffer, offset).map_err(|e| e.with_parameter(2))?;
+    let hierarchy = unmarshal_handle(buffer, offset).map_err(|e| e.with_parameter(3))?;
+
+    Ok(LoadExternalArgs {
+        in_private,
+        in_public,
+        hierarchy,
+    })
+}
+
+pub fn marshal_load_external_response(
+    buffer: &mut [u8],
+    val: &LoadExternalResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_handle(buffer, val.object_handle)?;
+
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.name)?;
+
+    Ok(offset)
+}
+
+pub fn marshal_read_public_response(
+    buffer: &mut [u8],
+    val: &ReadPublicResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b_public(buffer, &val.out_public)?;
+
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.name)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.qualified_name)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_object_change_auth_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<ObjectChangeAuthArgs, TpmError> {
+    let new_auth = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(ObjectChangeAuthArgs {
+        new_auth,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_object_change_auth_response(
+    buffer: &mut [u8],
+    val: &ObjectChangeAuthResponse,
+) -> Result<usize, TpmError> {
+    marshal_tpm2b(buffer, &val.out_private)
+}
+
+fn unmarshal_session_type(buffer: &[u8], offset: &mut usize) -> Result<TpmSe, TpmError> {
+    match TpmSe::from(unmarshal_u8(buffer, offset)?) {
+        TpmSe::Unknown => Err(TpmError::new(TpmRc::Value)),
+        session_type => Ok(session_type),
+    }
+}
+
+pub fn unmarshal_start_auth_session_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<StartAuthSessionArgs, TpmError> {
+    let nonce_caller = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let encrypted_salt = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(2))?;
+    let session_type = unmarshal_session_type(buffer, offset).map_err(|e| e.with_parameter(3))?;
+    let symmetric =
+        unmarshal_sym_def_object(buffer, offset, true).map_err(|e| e.with_parameter(4))?;
+    let auth_hash = unmarshal_hash_alg(buffer, offset, false).map_err(|e| e.with_parameter(5))?;
+
+    Ok(StartAuthSessionArgs {
+        nonce_caller,
+        encrypted_salt,
+        session_type,
+        symmetric,
+        auth_hash,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_start_auth_session_response(
+    buffer: &mut [u8],
+    val: &StartAuthSessionResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_handle(buffer, val.session_handle)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.nonce_tpm)?;
+
+    Ok(offset)
+}
+
+pub fn marshal_policy_get_digest_response(
+    buffer: &mut [u8],
+    val: &PolicyGetDigestResponse,
+) -> Result<usize, TpmError> {
+    marshal_tpm2b(buffer, &val.policy_digest)
+}
+
+pub fn unmarshal_policy_command_code_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<PolicyCommandCodeArgs, TpmError> {
+    let code = unmarshal_u32(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(PolicyCommandCodeArgs {
+        code,
+        ..Default::default()
+    })
+}
+
+pub fn unmarshal_tpml_digest(buffer: &[u8], offset: &mut usize) -> Result<TpmlDigest, TpmError> {
+    let count = unmarshal_u32(buffer, offset)?;
+    let mut val = TpmlDigest {
+        count,
+        ..Default::default()
+    };
+    if count as usize > val.digests.len() {
+        return Err(TpmError::new(TpmRc::Size));
+    }
+
+    for digest in val.digests[..count as usize].iter_mut() {
+        *digest = unmarshal_tpm2b(buffer, offset)?;
+    }
+
+    Ok(val)
+}
+
+pub fn unmarshal_policy_or_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<PolicyOrArgs, TpmError> {
+    let p_hash_list = unmarshal_tpml_digest(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(PolicyOrArgs {
+        p_hash_list,
+        ..Default::default()
+    })
+}
+
+pub fn unmarshal_policy_secret_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<PolicySecretArgs, TpmError> {
+    let nonce_tpm = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let cp_hash_a = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(2))?;
+    let policy_ref = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(3))?;
+    let expiration = unmarshal_u32(buffer, offset).map_err(|e| e.with_parameter(4))? as i32;
+
+    Ok(PolicySecretArgs {
+        nonce_tpm,
+        cp_hash_a,
+        policy_ref,
+        expiration,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_tpmt_tk_auth(buffer: &mut [u8], val: &TpmtTkAuth) -> Result<usize, TpmError> {
+    let mut offset = marshal_u16(buffer, val.tag)?;
+    offset += marshal_handle(&mut buffer[offset..], val.hierarchy)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.digest)?;
+
+    Ok(offset)
+}
+
+pub fn marshal_policy_secret_response(
+    buffer: &mut [u8],
+    val: &PolicySecretResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b(buffer, &val.timeout)?;
+    offset += marshal_tpmt_tk_auth(&mut buffer[offset..], &val.policy_ticket)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_policy_cp_hash_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<PolicyCpHashArgs, TpmError> {
+    let cp_hash_a = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(PolicyCpHashArgs {
+        cp_hash_a,
+        ..Default::default()
+    })
+}
+
+pub fn unmarshal_policy_name_hash_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<PolicyNameHashArgs, TpmError> {
+    let name_hash = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(PolicyNameHashArgs {
+        name_hash,
+        ..Default::default()
+    })
+}
+
+pub fn unmarshal_policy_pcr_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<PolicyPcrArgs, TpmError> {
+    let pcr_digest = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let pcrs = unmarshal_tpml_pcr_selection(buffer, offset).map_err(|e| e.with_parameter(2))?;
+
+    Ok(PolicyPcrArgs {
+        pcr_digest,
+        pcrs,
+        ..Default::default()
+    })
+}
+
+pub fn unmarshal_policy_duplication_select_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<PolicyDuplicationSelectArgs, TpmError> {
+    let object_name = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let new_parent_name = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(2))?;
+    let include_object = unmarshal_yes_no(buffer, offset).map_err(|e| e.with_parameter(3))?;
+
+    Ok(PolicyDuplicationSelectArgs {
+        object_name,
+        new_parent_name,
+        include_object,
+        ..Default::default()
+    })
+}
+
+// The digests in a TPMT_HA are the size of their hash, without a size
+// field.
+pub fn unmarshal_tpml_digest_values(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<TpmlDigestValues, TpmError> {
+    let count = unmarshal_u32(buffer, offset)?;
+    if count as usize > HASH_COUNT {
+        return Err(TpmError::new(TpmRc::Size));
+    }
+
+    let mut val = TpmlDigestValues {
+        count,
+        ..Default::default()
+    };
+    for digest in val.digests[..count as usize].iter_mut() {
+        digest.hash_alg = unmarshal_hash_alg(buffer, offset, false)?;
+        let size = digest.hash_alg.digest_size();
+        digest.digest = Tpm2bDigest::from_slice(unmarshal_bytes(buffer, offset, size)?)?;
+    }
+
+    Ok(val)
+}
+
+pub fn marshal_tpml_digest(buffer: &mut [u8], val: &TpmlDigest) -> Result<usize, TpmError> {
+    let mut offset = marshal_u32(buffer, val.count)?;
+    for digest in val.digests[..val.count as usize].iter() {
+        offset += marshal_tpm2b(&mut buffer[offset..], digest)?;
+    }
+
+    Ok(offset)
+}
+
+pub fn unmarshal_pcr_extend_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<PcrExtendArgs, TpmError> {
+    let digests = unmarshal_tpml_digest_values(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(PcrExtendArgs {
+        digests,
+        ..Default::default()
+    })
+}
+
+pub fn unmarshal_pcr_read_args(buffer: &[u8], offset: &mut usize) -> Result<PcrReadArgs, TpmError> {
+    let pcr_selection_in =
+        unmarshal_tpml_pcr_selection(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(PcrReadArgs { pcr_selection_in })
+}
+
+pub fn marshal_pcr_read_response(
+    buffer: &mut [u8],
+    val: &PcrReadResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_u32(buffer, val.pcr_update_counter)?;
+    offset += marshal_tpml_pcr_selection(&mut buffer[offset..], &val.pcr_selection_out)?;
+    offset += marshal_tpml_digest(&mut buffer[offset..], &val.pcr_values)?;
+
+    Ok(offset)
+}
+
+pub fn marshal_unseal_response(buffer: &mut [u8], val: &UnsealResponse) -> Result<usize, TpmError> {
+    marshal_tpm2b(buffer, &val.out_data)
+}
+
+// TPMT_RSA_DECRYPT
+const RSA_DECRYPT_SCHEMES: &[TpmAlgId] = &[TpmAlgId::RsaEs, TpmAlgId::Oaep];
+
+pub fn unmarshal_rsa_encrypt_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<RsaEncryptArgs, TpmError> {
+    let message = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let in_scheme = unmarshal_asym_scheme(buffer, offset, RSA_DECRYPT_SCHEMES)
+        .map_err(|e| e.with_parameter(2))?;
+    let label = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(3))?;
+
+    Ok(RsaEncryptArgs {
+        message,
+        in_scheme,
+        label,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_rsa_encrypt_response(
+    buffer: &mut [u8],
+    val: &RsaEncryptResponse,
+) -> Result<usize, TpmError> {
+    marshal_tpm2b(buffer, &val.out_data)
+}
+
+pub fn unmarshal_rsa_decrypt_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<RsaDecryptArgs, TpmError> {
+    let cipher_text = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let in_scheme = unmarshal_asym_scheme(buffer, offset, RSA_DECRYPT_SCHEMES)
+        .map_err(|e| e.with_parameter(2))?;
+    let label = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(3))?;
+
+    Ok(RsaDecryptArgs {
+        cipher_text,
+        in_scheme,
+        label,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_rsa_decrypt_response(
+    buffer: &mut [u8],
+    val: &RsaDecryptResponse,
+) -> Result<usize, TpmError> {
+    marshal_tpm2b(buffer, &val.message)
+}
+
+pub fn unmarshal_ecc_parameters_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<EccParametersArgs, TpmError> {
+    let curve_id = unmarshal_u16(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(EccParametersArgs {
+        curve_id: TpmEccCurve::from(curve_id),
+    })
+}
+
+pub fn marshal_ecc_parameters_response(
+    buffer: &mut [u8],
+    val: &EccParametersResponse,
+) -> Result<usize, TpmError> {
+    let detail = &val.parameters;
+    let mut offset = marshal_u16(buffer, detail.curve_id as u16)?;
+    offset += marshal_u16(&mut buffer[offset..], detail.key_size)?;
+    offset += marshal_kdf_scheme(&mut buffer[offset..], &detail.kdf)?;
+    offset += marshal_asym_scheme(&mut buffer[offset..], &detail.sign)?;
+    for param in [
+        &detail.p, &detail.a, &detail.b, &detail.gx, &detail.gy, &detail.n, &detail.h,
+    ] {
+        offset += marshal_tpm2b(&mut buffer[offset..], param)?;
+    }
+
+    Ok(offset)
+}
+
+pub fn marshal_ecdh_key_gen_response(
+    buffer: &mut [u8],
+    val: &EcdhKeyGenResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b_ecc_point(buffer, &val.z_point)?;
+    offset += marshal_tpm2b_ecc_point(&mut buffer[offset..], &val.pub_point)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_ecdh_zgen_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<EcdhZGenArgs, TpmError> {
+    let in_point = unmarshal_tpm2b_ecc_point(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(EcdhZGenArgs {
+        in_point,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_ecdh_zgen_response(
+    buffer: &mut [u8],
+    val: &EcdhZGenResponse,
+) -> Result<usize, TpmError> {
+    marshal_tpm2b_ecc_point(buffer, &val.out_point)
+}
+
+// TPMI_ECC_KEY_EXCHANGE
+fn unmarshal_key_exchange(buffer: &[u8], offset: &mut usize) -> Result<TpmAlgId, TpmError> {
+    match unmarshal_alg_id(buffer, offset)? {
+        scheme @ (TpmAlgId::EcDh | TpmAlgId::EcMqv | TpmAlgId::Sm2) => Ok(scheme),
+        _ => Err(TpmError::new(TpmRc::Scheme)),
+    }
+}
+
+pub fn unmarshal_zgen_2phase_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<ZGen2PhaseArgs, TpmError> {
+    let in_qs_b = unmarshal_tpm2b_ecc_point(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let in_qe_b = unmarshal_tpm2b_ecc_point(buffer, offset).map_err(|e| e.with_parameter(2))?;
+    let in_scheme = unmarshal_key_exchange(buffer, offset).map_err(|e| e.with_parameter(3))?;
+    let counter = unmarshal_u16(buffer, offset).map_err(|e| e.with_parameter(4))?;
+
+    Ok(ZGen2PhaseArgs {
+        in_qs_b,
+        in_qe_b,
+        in_scheme,
+        counter,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_zgen_2phase_response(
+    buffer: &mut [u8],
+    val: &ZGen2PhaseResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b_ecc_point(buffer, &val.out_z1)?;
+    offset += marshal_tpm2b_ecc_point(&mut buffer[offset..], &val.out_z2)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_ec_ephemeral_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<EcEphemeralArgs, TpmError> {
+    let curve_id = unmarshal_u16(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(EcEphemeralArgs {
+        curve_id: TpmEccCurve::from(curve_id),
+    })
+}
+
+pub fn marshal_ec_ephemeral_response(
+    buffer: &mut [u8],
+    val: &EcEphemeralResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b_ecc_point(buffer, &val.q)?;
+    offset += marshal_u16(&mut buffer[offset..], val.counter)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_commit_args(buffer: &[u8], offset: &mut usize) -> Result<CommitArgs, TpmError> {
+    // Unlike other points, P1 can be an Empty Buffer.
+    let p1 = match buffer.get(*offset..*offset + 2) {
+        Some([0, 0]) => {
+            *offset += 2;
+            TpmsEccPoint::default()
+        }
+        _ => unmarshal_tpm2b_ecc_point(buffer, offset).map_err(|e| e.with_parameter(1))?,
+    };
+    let s2 = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(2))?;
+    let y2 = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(3))?;
+
+    Ok(CommitArgs {
+        p1,
+        s2,
+        y2,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_commit_response(buffer: &mut [u8], val: &CommitResponse) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b_ecc_point(buffer, &val.k)?;
+    offset += marshal_tpm2b_ecc_point(&mut buffer[offset..], &val.l)?;
+    offset += marshal_tpm2b_ecc_point(&mut buffer[offset..], &val.e)?;
+    offset += marshal_u16(&mut buffer[offset..], val.counter)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_flush_context_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<FlushContextArgs, TpmError> {
+    let flush_handle = unmarshal_handle(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(FlushContextArgs { flush_handle })
+}
+
+pub fn unmarshal_evict_control_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<EvictControlArgs, TpmError> {
+    let persistent_handle = unmarshal_handle(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(EvictControlArgs {
+        persistent_handle,
+        ..Default::default()
+    })
+}
+
+pub fn unmarshal_tpms_context(buffer: &[u8], offset: &mut usize) -> Result<TpmsContext, TpmError> {
+    let sequence = unmarshal_u64(buffer, offset)?;
+    let saved_handle = unmarshal_handle(buffer, offset)?;
+    let hierarchy = unmarshal_handle(buffer, offset)?;
+    let context_blob = unmarshal_tpm2b(buffer, offset)?;
+
+    Ok(TpmsContext {
+        sequence,
+        saved_handle,
+        hierarchy,
+        context_blob,
+    })
+}
+
+pub fn marshal_tpms_context(buffer: &mut [u8], val: &TpmsContext) -> Result<usize, TpmError> {
+    let mut offset = marshal_u64(buffer, val.sequence)?;
+
+    offset += marshal_handle(&mut buffer[offset..], val.saved_handle)?;
+    offset += marshal_handle(&mut buffer[offset..], val.hierarchy)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.context_blob)?;
+
+    Ok(offset)
+}
+
+pub fn marshal_context_save_response(
+    buffer: &mut [u8],
+    val: &ContextSaveResponse,
+) -> Result<usize, TpmError> {
+    marshal_tpms_context(buffer, &val.context)
+}
+
+pub fn unmarshal_context_load_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<ContextLoadArgs, TpmError> {
+    let context = unmarshal_tpms_context(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(ContextLoadArgs { context })
+}
+
+pub fn marshal_context_load_response(
+    buffer: &mut [u8],
+    val: &ContextLoadResponse,
+) -> Result<usize, TpmError> {
+    marshal_handle(buffer, val.loaded_handle)
+}
+
+// TPMS_NV_PUBLIC
+pub fn unmarshal_tpms_nv_public(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<TpmsNvPublic, TpmError> {
+    let nv_index = unmarshal_handle(buffer, offset)?;
+    if TpmHt::from(nv_index) != TpmHt::NvIndex {
+        return Err(TpmError::new(TpmRc::Value));
+    }
+
+    let name_alg = unmarshal_hash_alg(buffer, offset, false)?;
+
+    let attributes = unmarshal_u32(buffer, offset)?;
+    if attributes & TPMA_NV_RESERVED != 0 {
+        return Err(TpmError::new(TpmRc::ReservedBits));
+    }
+
+    let auth_policy = unmarshal_tpm2b(buffer, offset)?;
+    let data_size = unmarshal_u16(buffer, offset)?;
+
+    Ok(TpmsNvPublic {
+        nv_index,
+        name_alg,
+        attributes,
+        auth_policy,
+        data_size,
+    })
+}
+
+pub fn marshal_tpms_nv_public(buffer: &mut [u8], val: &TpmsNvPublic) -> Result<usize, TpmError> {
+    let mut offset = marshal_handle(buffer, val.nv_index)?;
+
+    offset += marshal_u16(&mut buffer[offset..], val.name_alg as u16)?;
+    offset += marshal_u32(&mut buffer[offset..], val.attributes)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.auth_policy)?;
+    offset += marshal_u16(&mut buffer[offset..], val.data_size)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_tpm2b_nv_public(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<TpmsNvPublic, TpmError> {
+    unmarshal_sized(buffer, offset, unmarshal_tpms_nv_public)
+}
+
+pub fn marshal_tpm2b_nv_public(buffer: &mut [u8], val: &TpmsNvPublic) -> Result<usize, TpmError> {
+    marshal_sized(buffer, val, marshal_tpms_nv_public)
+}
+
+pub fn unmarshal_nv_define_space_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<NvDefineSpaceArgs, TpmError> {
+    let auth = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let public_info = unmarshal_tpm2b_nv_public(buffer, offset).map_err(|e| e.with_parameter(2))?;
+
+    Ok(NvDefineSpaceArgs {
+        auth,
+        public_info,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_nv_read_public_response(
+    buffer: &mut [u8],
+    val: &NvReadPublicResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b_nv_public(buffer, &val.nv_public)?;
+
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.nv_name)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_nv_write_args(buffer: &[u8], offset: &mut usize) -> Result<NvWriteArgs, TpmError> {
+    let data = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let write_offset = unmarshal_u16(buffer, offset).map_err(|e| e.with_parameter(2))?;
+
+    Ok(NvWriteArgs {
+        data,
+        offset: write_offset,
+        ..Default::default()
+    })
+}
+
+pub fn unmarshal_nv_read_args(buffer: &[u8], offset: &mut usize) -> Result<NvReadArgs, TpmError> {
+    let size = unmarshal_u16(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let read_offset = unmarshal_u16(buffer, offset).map_err(|e| e.with_parameter(2))?;
+
+    Ok(NvReadArgs {
+        size,
+        offset: read_offset,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_nv_read_response(
+    buffer: &mut [u8],
+    val: &NvReadResponse,
+) -> Result<usize, TpmError> {
+    marshal_tpm2b(buffer, &val.data)
+}
+
+pub fn unmarshal_nv_extend_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<NvExtendArgs, TpmError> {
+    let data = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(NvExtendArgs {
+        data,
+        ..Default::default()
+    })
+}
+
+pub fn unmarshal_nv_set_bits_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<NvSetBitsArgs, TpmError> {
+    let bits = unmarshal_u64(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(NvSetBitsArgs {
+        bits,
+        ..Default::default()
+    })
+}
+
+pub fn unmarshal_tpms_nv_pin_counter_parameters(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<TpmsNvPinCounterParameters, TpmError> {
+    let pin_count = unmarshal_u32(buffer, offset)?;
+    let pin_limit = unmarshal_u32(buffer, offset)?;
+
+    Ok(TpmsNvPinCounterParameters {
+        pin_count,
+        pin_limit,
+    })
+}
+
+pub fn marshal_tpms_nv_pin_counter_parameters(
+    buffer: &mut [u8],
+    val: &TpmsNvPinCounterParameters,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_u32(buffer, val.pin_count)?;
+    offset += marshal_u32(&mut buffer[offset..], val.pin_limit)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_nv_change_auth_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<NvChangeAuthArgs, TpmError> {
+    let new_auth = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(NvChangeAuthArgs {
+        new_auth,
+        ..Default::default()
+    })
+}
+
+// TPMI_ALG_SIG_SCHEME
+const SIG_SCHEMES: &[TpmAlgId] = &[
+    TpmAlgId::Hmac,
+    TpmAlgId::RsaSsa,
+    TpmAlgId::RsaPss,
+    TpmAlgId::EcDsa,
+    TpmAlgId::EcDaa,
+    TpmAlgId::Sm2,
+    TpmAlgId::EcSchnorr,
+];
+
+pub fn unmarshal_sig_scheme(buffer: &[u8], offset: &mut usize) -> Result<TpmtAsymScheme, TpmError> {
+    unmarshal_asym_scheme(buffer, offset, SIG_SCHEMES)
+}
+
+pub fn unmarshal_nv_certify_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<NvCertifyArgs, TpmError> {
+    let qualifying_data = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let in_scheme = unmarshal_sig_scheme(buffer, offset).map_err(|e| e.with_parameter(2))?;
+    let size = unmarshal_u16(buffer, offset).map_err(|e| e.with_parameter(3))?;
+    let read_offset = unmarshal_u16(buffer, offset).map_err(|e| e.with_parameter(4))?;
+
+    Ok(NvCertifyArgs {
+        qualifying_data,
+        in_scheme,
+        size,
+        offset: read_offset,
+        ..Default::default()
+    })
+}
+
+pub fn unmarshal_quote_args(buffer: &[u8], offset: &mut usize) -> Result<QuoteArgs, TpmError> {
+    let qualifying_data = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let in_scheme = unmarshal_sig_scheme(buffer, offset).map_err(|e| e.with_parameter(2))?;
+    let pcr_select =
+        unmarshal_tpml_pcr_selection(buffer, offset).map_err(|e| e.with_parameter(3))?;
+
+    Ok(QuoteArgs {
+        qualifying_data,
+        in_scheme,
+        pcr_select,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_quote_response(buffer: &mut [u8], val: &QuoteResponse) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b(buffer, &val.quoted)?;
+    offset += marshal_signature(&mut buffer[offset..], &val.signature)?;
+
+    Ok(offset)
+}
+
+pub fn marshal_tpms_clock_info(buffer: &mut [u8], val: &TpmsClockInfo) -> Result<usize, TpmError> {
+    let mut offset = marshal_u64(buffer, val.clock)?;
+    offset += marshal_u32(&mut buffer[offset..], val.reset_count)?;
+    offset += marshal_u32(&mut buffer[offset..], val.restart_count)?;
+    offset += marshal_u8(&mut buffer[offset..], val.safe as u8)?;
+
+    Ok(offset)
+}
+
+pub fn marshal_tpms_time_info(buffer: &mut [u8], val: &TpmsTimeInfo) -> Result<usize, TpmError> {
+    let mut offset = marshal_u64(buffer, val.time)?;
+    offset += marshal_tpms_clock_info(&mut buffer[offset..], &val.clock_info)?;
+
+    Ok(offset)
+}
+
+pub fn marshal_read_clock_response(
+    buffer: &mut [u8],
+    val: &ReadClockResponse,
+) -> Result<usize, TpmError> {
+    marshal_tpms_time_info(buffer, &val.current_time)
+}
+
+pub fn marshal_tpms_attest(buffer: &mut [u8], val: &TpmsAttest) -> Result<usize, TpmError> {
+    let attest_type = match val.attested {
+        TpmuAttest::Nv(_) => TPM_ST_ATTEST_NV,
+        TpmuAttest::Quote(_) => TPM_ST_ATTEST_QUOTE,
+    };
+
+    let mut offset = marshal_u32(buffer, TPM_GENERATED_VALUE)?;
+    offset += marshal_u16(&mut buffer[offset..], attest_type)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.qualified_signer)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.extra_data)?;
+    offset += marshal_tpms_clock_info(&mut buffer[offset..], &val.clock_info)?;
+    offset += marshal_u64(&mut buffer[offset..], val.firmware_version)?;
+
+    match &val.attested {
+        TpmuAttest::Nv(info) => {
+            offset += marshal_tpm2b(&mut buffer[offset..], &info.index_name)?;
+            offset += marshal_u16(&mut buffer[offset..], info.offset)?;
+            offset += marshal_tpm2b(&mut buffer[offset..], &info.nv_contents)?;
+        }
+        TpmuAttest::Quote(info) => {
+            offset += marshal_tpml_pcr_selection(&mut buffer[offset..], &info.pcr_select)?;
+            offset += marshal_tpm2b(&mut buffer[offset..], &info.pcr_digest)?;
+        }
+    }
+
+    Ok(offset)
+}
+
+pub fn marshal_signature(buffer: &mut [u8], val: &TpmtSignature) -> Result<usize, TpmError> {
+    let mut offset = marshal_u16(buffer, val.sig_alg() as u16)?;
+
+    match val {
+        TpmtSignature::RsaSsa(sig) | TpmtSignature::RsaPss(sig) => {
+            offset += marshal_u16(&mut buffer[offset..], sig.hash as u16)?;
+            offset += marshal_tpm2b(&mut buffer[offset..], &sig.sig)?;
+        }
+        TpmtSignature::EcDsa(sig)
+        | TpmtSignature::EcDaa(sig)
+        | TpmtSignature::Sm2(sig)
+        | TpmtSignature::EcSchnorr(sig) => {
+            offset += marshal_u16(&mut buffer[offset..], sig.hash as u16)?;
+            offset += marshal_tpm2b(&mut buffer[offset..], &sig.signature_r)?;
+            offset += marshal_tpm2b(&mut buffer[offset..], &sig.signature_s)?;
+        }
+        // A TPMT_HA, whose digest isn't sized
+        TpmtSignature::Hmac(ha) => {
+            offset += marshal_u16(&mut buffer[offset..], ha.hash_alg as u16)?;
+            offset += marshal_bytes(&mut buffer[offset..], ha.digest.as_slice())?;
+        }
+        TpmtSignature::Null => (),
+    }
+
+    Ok(offset)
+}
+
+pub fn unmarshal_signature(buffer: &[u8], offset: &mut usize) -> Result<TpmtSignature, TpmError> {
+    let sig_alg = unmarshal_alg_id(buffer, offset)?;
+    let signature = match sig_alg {
+        TpmAlgId::RsaSsa | TpmAlgId::RsaPss => {
+            let sig = TpmsSignatureRsa {
+                hash: unmarshal_hash_alg(buffer, offset, false)?,
+                sig: unmarshal_tpm2b(buffer, offset)?,
+            };
+            match sig_alg {
+                TpmAlgId::RsaSsa => TpmtSignature::RsaSsa(sig),
+                _ => TpmtSignature::RsaPss(sig),
+            }
+        }
+        TpmAlgId::EcDsa | TpmAlgId::EcDaa | TpmAlgId::Sm2 | TpmAlgId::EcSchnorr => {
+            let sig = TpmsSignatureEcc {
+                hash: unmarshal_hash_alg(buffer, offset, false)?,
+                signature_r: unmarshal_tpm2b(buffer, offset)?,
+                signature_s: unmarshal_tpm2b(buffer, offset)?,
+            };
+            match sig_alg {
+                TpmAlgId::EcDsa => TpmtSignature::EcDsa(sig),
+                TpmAlgId::EcDaa => TpmtSignature::EcDaa(sig),
+                TpmAlgId::Sm2 => TpmtSignature::Sm2(sig),
+                _ => TpmtSignature::EcSchnorr(sig),
+            }
+        }
+        TpmAlgId::Hmac => {
+            let hash_alg = unmarshal_hash_alg(buffer, offset, false)?;
+            let digest = unmarshal_bytes(buffer, offset, hash_alg.digest_size())?;
+            TpmtSignature::Hmac(TpmtHa {
+                hash_alg,
+                digest: Tpm2bDigest::from_slice(digest)?,
+            })
+        }
+        _ => return Err(TpmError::new(TpmRc::Scheme)),
+    };
+
+    Ok(signature)
+}
+
+// TPMI_RH_HIERARCHY+
+fn unmarshal_ticket_hierarchy(buffer: &[u8], offset: &mut usize) -> Result<TpmHandle, TpmError> {
+    let hierarchy = unmarshal_handle(buffer, offset)?;
+    match TpmRh::from(hierarchy) {
+        TpmRh::Owner | TpmRh::Endorsement | TpmRh::Platform | TpmRh::Null => Ok(hierarchy),
+        _ => Err(TpmError::new(TpmRc::Value)),
+    }
+}
+
+pub fn unmarshal_tpmt_tk_hashcheck(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<TpmtTkHashcheck, TpmError> {
+    if unmarshal_u16(buffer, offset)? != TPM_ST_HASHCHECK {
+        return Err(TpmError::new(TpmRc::Tag));
+    }
+    let hierarchy = unmarshal_ticket_hierarchy(buffer, offset)?;
+    let digest = unmarshal_tpm2b(buffer, offset)?;
+
+    Ok(TpmtTkHashcheck { hierarchy, digest })
+}
+
+pub fn marshal_tpmt_tk_hashcheck(
+    buffer: &mut [u8],
+    val: &TpmtTkHashcheck,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_u16(buffer, TPM_ST_HASHCHECK)?;
+
+    offset += marshal_handle(&mut buffer[offset..], val.hierarchy)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.digest)?;
+
+    Ok(offset)
+}
+
+pub fn marshal_tpmt_tk_verified(
+    buffer: &mut [u8],
+    val: &TpmtTkVerified,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_u16(buffer, TPM_ST_VERIFIED)?;
+
+    offset += marshal_handle(&mut buffer[offset..], val.hierarchy)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.digest)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_sign_args(buffer: &[u8], offset: &mut usize) -> Result<SignArgs, TpmError> {
+    let digest = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let in_scheme = unmarshal_sig_scheme(buffer, offset).map_err(|e| e.with_parameter(2))?;
+    let validation =
+        unmarshal_tpmt_tk_hashcheck(buffer, offset).map_err(|e| e.with_parameter(3))?;
+
+    Ok(SignArgs {
+        digest,
+        in_scheme,
+        validation,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_sign_response(buffer: &mut [u8], val: &SignResponse) -> Result<usize, TpmError> {
+    marshal_signature(buffer, &val.signature)
+}
+
+pub fn unmarshal_verify_signature_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<VerifySignatureArgs, TpmError> {
+    let digest = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let signature = unmarshal_signature(buffer, offset).map_err(|e| e.with_parameter(2))?;
+
+    Ok(VerifySignatureArgs {
+        digest,
+        signature,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_verify_signature_response(
+    buffer: &mut [u8],
+    val: &VerifySignatureResponse,
+) -> Result<usize, TpmError> {
+    marshal_tpmt_tk_verified(buffer, &val.validation)
+}
+
+pub fn unmarshal_hash_args(buffer: &[u8], offset: &mut usize) -> Result<HashArgs, TpmError> {
+    let data = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let hash_alg = unmarshal_hash_alg(buffer, offset, false).map_err(|e| e.with_parameter(2))?;
+    let hierarchy = unmarshal_ticket_hierarchy(buffer, offset).map_err(|e| e.with_parameter(3))?;
+
+    Ok(HashArgs {
+        data,
+        hash_alg,
+        hierarchy,
+    })
+}
+
+pub fn marshal_hash_response(buffer: &mut [u8], val: &HashResponse) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b(buffer, &val.out_hash)?;
+    offset += marshal_tpmt_tk_hashcheck(&mut buffer[offset..], &val.validation)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_encrypt_decrypt_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<EncryptDecryptArgs, TpmError> {
+    let decrypt = unmarshal_yes_no(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let mode = unmarshal_sym_mode(buffer, offset).map_err(|e| e.with_parameter(2))?;
+    let iv_in = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(3))?;
+    let in_data = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(4))?;
+
+    Ok(EncryptDecryptArgs {
+        decrypt,
+        mode,
+        iv_in,
+        in_data,
+        ..Default::default()
+    })
+}
+
+// TPM2_EncryptDecrypt2 puts inData first so that it can be encrypted as a
+// session parameter.
+pub fn unmarshal_encrypt_decrypt2_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<EncryptDecryptArgs, TpmError> {
+    let in_data = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let decrypt = unmarshal_yes_no(buffer, offset).map_err(|e| e.with_parameter(2))?;
+    let mode = unmarshal_sym_mode(buffer, offset).map_err(|e| e.with_parameter(3))?;
+    let iv_in = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(4))?;
+
+    Ok(EncryptDecryptArgs {
+        decrypt,
+        mode,
+        iv_in,
+        in_data,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_encrypt_decrypt_response(
+    buffer: &mut [u8],
+    val: &EncryptDecryptResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b(buffer, &val.out_data)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.iv_out)?;
+
+    Ok(offset)
+}
+
+// TPMI_ALG_MAC_SCHEME+
+fn unmarshal_mac_scheme(buffer: &[u8], offset: &mut usize) -> Result<TpmAlgId, TpmError> {
+    let alg = unmarshal_alg_id(buffer, offset)?;
+    if alg.is_hash() || matches!(alg, TpmAlgId::Cmac | TpmAlgId::Null) {
+        Ok(alg)
+    } else {
+        Err(TpmError::new(TpmRc::Symmetric))
+    }
+}
+
+pub fn unmarshal_mac_args(buffer: &[u8], offset: &mut usize) -> Result<MacArgs, TpmError> {
+    let data = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let in_scheme = unmarshal_mac_scheme(buffer, offset).map_err(|e| e.with_parameter(2))?;
+
+    Ok(MacArgs {
+        buffer: data,
+        in_scheme,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_mac_response(buffer: &mut [u8], val: &MacResponse) -> Result<usize, TpmError> {
+    marshal_tpm2b(buffer, &val.out_mac)
+}
+
+pub fn unmarshal_mac_start_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<MacStartArgs, TpmError> {
+    let auth = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let in_scheme = unmarshal_mac_scheme(buffer, offset).map_err(|e| e.with_parameter(2))?;
+
+    Ok(MacStartArgs {
+        auth,
+        in_scheme,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_mac_start_response(
+    buffer: &mut [u8],
+    val: &MacStartResponse,
+) -> Result<usize, TpmError> {
+    marshal_handle(buffer, val.sequence_handle)
+}
+
+pub fn unmarshal_sequence_update_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<SequenceUpdateArgs, TpmError> {
+    let data = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(SequenceUpdateArgs {
+        buffer: data,
+        ..Default::default()
+    })
+}
+
+pub fn unmarshal_sequence_complete_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<SequenceCompleteArgs, TpmError> {
+    let data = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let hierarchy = unmarshal_ticket_hierarchy(buffer, offset).map_err(|e| e.with_parameter(2))?;
+
+    Ok(SequenceCompleteArgs {
+        buffer: data,
+        hierarchy,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_sequence_complete_response(
+    buffer: &mut [u8],
+    val: &SequenceCompleteResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b(buffer, &val.result)?;
+    offset += marshal_tpmt_tk_hashcheck(&mut buffer[offset..], &val.validation)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_make_credential_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<MakeCredentialArgs, TpmError> {
+    let credential = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let object_name = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(2))?;
+
+    Ok(MakeCredentialArgs {
+        credential,
+        object_name,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_make_credential_response(
+    buffer: &mut [u8],
+    val: &MakeCredentialResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b(buffer, &val.credential_blob)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.secret)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_activate_credential_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<ActivateCredentialArgs, TpmError> {
+    let credential_blob = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let secret = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(2))?;
+
+    Ok(ActivateCredentialArgs {
+        credential_blob,
+        secret,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_activate_credential_response(
+    buffer: &mut [u8],
+    val: &ActivateCredentialResponse,
+) -> Result<usize, TpmError> {
+    marshal_tpm2b(buffer, &val.cert_info)
+}
+
+pub fn unmarshal_duplicate_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<DuplicateArgs, TpmError> {
+    let encryption_key_in = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let symmetric_alg =
+        unmarshal_sym_def_object(buffer, offset, true).map_err(|e| e.with_parameter(2))?;
+
+    Ok(DuplicateArgs {
+        encryption_key_in,
+        symmetric_alg,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_duplicate_response(
+    buffer: &mut [u8],
+    val: &DuplicateResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b(buffer, &val.encryption_key_out)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.duplicate)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.out_sym_seed)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_import_args(buffer: &[u8], offset: &mut usize) -> Result<ImportArgs, TpmError> {
+    let encryption_key = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let object_public = unmarshal_tpm2b_public(buffer, offset).map_err(|e| e.with_parameter(2))?;
+    let duplicate = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(3))?;
+    let in_sym_seed = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(4))?;
+    let symmetric_alg =
+        unmarshal_sym_def_object(buffer, offset, true).map_err(|e| e.with_parameter(5))?;
+
+    Ok(ImportArgs {
+        encryption_key,
+        object_public,
+        duplicate,
+        in_sym_seed,
+        symmetric_alg,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_import_response(buffer: &mut [u8], val: &ImportResponse) -> Result<usize, TpmError> {
+    marshal_tpm2b(buffer, &val.out_private)
+}
+
+pub fn unmarshal_rewrap_args(buffer: &[u8], offset: &mut usize) -> Result<RewrapArgs, TpmError> {
+    let in_duplicate = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(1))?;
+    let name = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(2))?;
+    let in_sym_seed = unmarshal_tpm2b(buffer, offset).map_err(|e| e.with_parameter(3))?;
+
+    Ok(RewrapArgs {
+        in_duplicate,
+        name,
+        in_sym_seed,
+        ..Default::default()
+    })
+}
+
+pub fn marshal_rewrap_response(buffer: &mut [u8], val: &RewrapResponse) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b(buffer, &val.out_duplicate)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.out_sym_seed)?;
+
+    Ok(offset)
+}
+
+pub fn marshal_nv_certify_response(
+    buffer: &mut [u8],
+    val: &NvCertifyResponse,
+) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b(buffer, &val.certify_info)?;
+    offset += marshal_signature(&mut buffer[offset..], &val.signature)?;
+
+    Ok(offset)
+}
+
+// TPMT_HA as the TPM stores it, with the digest sized
+pub fn marshal_tpmt_ha(buffer: &mut [u8], val: &TpmtHa) -> Result<usize, TpmError> {
+    let mut offset = marshal_u16(buffer, val.hash_alg as u16)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &val.digest)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_tpmt_ha(buffer: &[u8], offset: &mut usize) -> Result<TpmtHa, TpmError> {
+    let hash_alg = unmarshal_hash_alg(buffer, offset, true)?;
+    let digest = unmarshal_tpm2b(buffer, offset)?;
+
+    Ok(TpmtHa { hash_alg, digest })
+}
+
+pub fn unmarshal_shutdown_args(
+    buffer: &[u8],
+    offset: &mut usize,
+) -> Result<ShutdownArgs, TpmError> {
+    let su_type = unmarshal_startup_type(buffer, offset).map_err(|e| e.with_parameter(1))?;
+
+    Ok(ShutdownArgs { su_type })
+}
@@ -0,0 +1,226 @@
+use crate::tpm::*;
+use crate::types::*;
+
+// Layout of the platform's NV memory. Persistent objects and NV indices
+// take fixed size slots so one can be rewritten without touching the others.
+pub(crate) const NV_HIERARCHY: usize = 0;
+pub(crate) const NV_PERSISTENT_OBJECTS: usize = NV_HIERARCHY + HIERARCHY_NV_SIZE;
+pub(crate) const NV_INDICES: usize =
+    NV_PERSISTENT_OBJECTS + MAX_PERSISTENT_OBJECTS * PERSISTENT_OBJECT_NV_SIZE;
+pub(crate) const NV_MAX_COUNTER: usize = NV_INDICES + MAX_NV_INDICES * NV_INDEX_NV_SIZE;
+pub(crate) const NV_RESET_COUNT: usize = NV_MAX_COUNTER + 8;
+pub(crate) const NV_CLOCK: usize = NV_RESET_COUNT + 8;
+pub(crate) const NV_DA: usize = NV_CLOCK + 8;
+pub(crate) const NV_ORDERLY: usize = NV_DA + DA_NV_SIZE;
+
+// The orderly area holds how the TPM was shut down and the lifetime write
+// count, then whatever Shutdown(STATE) saved.
+const ORDERLY_HEADER_SIZE: usize = 1 + 8;
+pub(crate) const ORDERLY_STATE_SIZE: usize = ORDERLY_NV_SIZE - ORDERLY_HEADER_SIZE;
+
+// How the TPM was last shut down
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Shutdown {
+    #[default]
+    None = 0,
+    Clear = 1,
+    State = 2,
+}
+
+// A limit of 0 means there's no limit.
+fn used_up(writes: u64, limit: usize) -> bool {
+    limit != 0 && writes >= limit as u64
+}
+
+pub struct NvState {
+    // Whether the command being executed has written NV yet. Every command
+    // that writes NV uses up one write, however many writes it does.
+    pub(crate) command_wrote: bool,
+
+    // Writes since power on, and how many are allowed
+    pub(crate) writes: usize,
+    pub(crate) write_budget: usize,
+
+    // Orderly. Writes over the TPM's life. Only reaches NV at an orderly
+    // shutdown, so the count misses anything after the last one.
+    pub(crate) lifetime_writes: u64,
+    pub(crate) endurance: usize,
+
+    // The shutdown recorded in NV. Cleared once the TPM has started again.
+    pub(crate) shutdown: Shutdown,
+}
+
+impl Default for NvState {
+    fn default() -> NvState {
+        NvState {
+            command_wrote: false,
+            writes: 0,
+            write_budget: NV_WRITE_BUDGET,
+            lifetime_writes: 0,
+            endurance: NV_ENDURANCE,
+            shutdown: Shutdown::None,
+        }
+    }
+}
+
+impl TpmInstance {
+    // Pick up the hierarchy state, persistent objects, NV indices, Clock and
+    // dictionary attack state already in NV.
+    pub(crate) fn nv_restore(&mut self) -> Result<(), TpmError> {
+        let manufactured = self.hierarchy_restore()?;
+        self.context_restore()?;
+        self.clock_restore()?;
+        self.da_restore()?;
+        self.persistent_restore()?;
+        self.nv_index_restore()?;
+
+        // A new TPM starts as if it had been shut down in order, so its
+        // first Startup doesn't count as following a power loss.
+        if !manufactured {
+            self.nv_shutdown(Shutdown::Clear, &[])?;
+        }
+        self.nv_orderly_restore()
+    }
+
+    fn nv_orderly_restore(&mut self) -> Result<(), TpmError> {
+        let mut header = [0u8; ORDERLY_HEADER_SIZE];
+        self.nv_read(NV_ORDERLY, &mut header)?;
+
+        self.nv.shutdown = match header[0] {
+            1 => Shutdown::Clear,
+            2 => Shutdown::State,
+            _ => Shutdown::None,
+        };
+        self.nv.lifetime_writes = u64::from_be_bytes(header[1..].try_into().unwrap());
+
+        Ok(())
+    }
+
+    pub(crate) fn nv_command_start(&mut self) {
+        self.nv.command_wrote = false;
+    }
+
+    // Account for the current command writing NV, failing if the write
+    // budget or NV itself is used up.
+    fn nv_use_write(&mut self) -> Result<(), TpmError> {
+        if self.nv.command_wrote {
+            return Ok(());
+        }
+        if used_up(self.nv.writes as u64, self.nv.write_budget) {
+            return Err(TpmError::new(TpmRc::NvRate));
+        }
+        if used_up(self.nv.lifetime_writes, self.nv.endurance) {
+            return Err(TpmError::new(TpmRc::NvUnavailable));
+        }
+
+        self.nv.command_wrote = true;
+        self.nv.writes += 1;
+        self.nv.lifetime_writes += 1;
+
+        Ok(())
+    }
+
+    // Record an orderly shutdown along with the state to resume from.
+    pub(crate) fn nv_shutdown(&mut self, shutdown: Shutdown, state: &[u8]) -> Result<(), TpmError> {
+        self.nv_use_write()?;
+
+        let mut orderly = [0u8; ORDERLY_NV_SIZE];
+        orderly[0] = shutdown as u8;
+        orderly[1..ORDERLY_HEADER_SIZE].copy_from_slice(&self.nv.lifetime_writes.to_be_bytes());
+        orderly[ORDERLY_HEADER_SIZE..ORDERLY_HEADER_SIZE + state.len()].copy_from_slice(state);
+        self.nv_write(NV_ORDERLY, &orderly[..ORDERLY_HEADER_SIZE + state.len()])?;
+
+        self.nv.shutdown = shutdown;
+        Ok(())
+    }
+
+    // The state saved by Shutdown(STATE)
+    pub(crate) fn nv_read_orderly_state(
+        &self,
+        state: &mut [u8; ORDERLY_STATE_SIZE],
+    ) -> Result<(), TpmError> {
+        self.nv_read(NV_ORDERLY + ORDERLY_HEADER_SIZE, state)
+    }
+
+    // Once anything kept in RAM changes, what the last shutdown saved is out
+    // of date and a power loss is no longer orderly.
+    pub(crate) fn nv_clear_orderly(&mut self) -> Result<(), TpmError> {
+        if self.nv.shutdown == Shutdown::None {
+            return Ok(());
+        }
+
+        self.nv_write(NV_ORDERLY, &[Shutdown::None as u8])?;
+        self.nv.shutdown = Shutdown::None;
+
+        Ok(())
+    }
+
+    pub(crate) fn nv_read(&self, offset: usize, buf: &mut [u8]) -> Result<(), TpmError> {
+        debug_assert!(offset + buf.len() <= NV_MEMORY_SIZE);
+
+        match (self.platform.nv_read)(offset, buf) {
+            true => Ok(()),
+            false => Err(TpmError::new(TpmRc::NvUnavailable)),
+        }
+    }
+
+    pub(crate) fn nv_write(&mut self, offset: usize, data: &[u8]) -> Result<(), TpmError> {
+        debug_assert!(offset + data.len() <= NV_MEMORY_SIZE);
+        self.nv_use_write()?;
+
+        match (self.platform.nv_write)(offset, data) {
+            true => Ok(()),
+            false => Err(TpmError::new(TpmRc::NvUnavailable)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::test::platform;
+
+    // Every command that writes NV uses one write from the budget, however
+    // many writes it makes. Once it's used up, writes fail until power off.
+    #[test]
+    fn write_budget() {
+        let mut tpm = TpmInstance::new(&platform());
+        let writes = tpm.nv.writes;
+        tpm.nv.write_budget = writes + 2;
+
+        tpm.nv_command_start();
+        tpm.nv_write(NV_RESET_COUNT, &[0; 8]).unwrap();
+        tpm.nv_write(NV_MAX_COUNTER, &[0; 8]).unwrap();
+        assert_eq!(tpm.nv.writes, writes + 1);
+
+        tpm.nv_command_start();
+        tpm.nv_write(NV_RESET_COUNT, &[0; 8]).unwrap();
+
+        tpm.nv_command_start();
+        let result = tpm.nv_write(NV_RESET_COUNT, &[0; 8]);
+        assert!(matches!(result, Err(e) if e.rc == TpmRc::NvRate));
+        assert_eq!(tpm.nv.writes, writes + 2);
+
+        // Reads don't count.
+        let mut buf = [0u8; 8];
+        tpm.nv_read(NV_RESET_COUNT, &mut buf).unwrap();
+    }
+
+    // Once NV is worn out nothing more is written, and reads still work.
+    #[test]
+    fn endurance() {
+        let mut tpm = TpmInstance::new(&platform());
+        tpm.nv.endurance = tpm.nv.lifetime_writes as usize + 1;
+
+        tpm.nv_command_start();
+        tpm.nv_write(NV_RESET_COUNT, &[1; 8]).unwrap();
+
+        tpm.nv_command_start();
+        let result = tpm.nv_write(NV_RESET_COUNT, &[2; 8]);
+        assert!(matches!(result, Err(e) if e.rc == TpmRc::NvUnavailable));
+
+        let mut buf = [0u8; 8];
+        tpm.nv_read(NV_RESET_COUNT, &mut buf).unwrap();
+        assert_eq!(buf, [1; 8]);
+    }
+}
@@ -0,0 +1,787 @@
+use crate::crypto::hash::*;
+use crate::marshal::*;
+use crate::nv::*;
+use crate::tpm::*;
+use crate::types::*;
+
+// A defined NV index. The public area and authValue are kept in RAM as well
+// as NV, the data only in NV.
+#[derive(Clone, Copy, Default)]
+pub struct NvIndex {
+    pub public: TpmsNvPublic,
+    pub auth_value: Tpm2bAuth,
+}
+
+pub struct NvIndexState {
+    pub(crate) slots: [Option<NvIndex>; MAX_NV_INDICES],
+
+    // Current value of each orderly counter. NV only sees one increment in
+    // every MAX_ORDERLY_COUNT + 1.
+    pub(crate) orderly_counters: [u64; MAX_NV_INDICES],
+
+    // Persistent. The highest value any undefined counter reached. New
+    // counters start from here so no value ever repeats.
+    pub(crate) max_counter: u64,
+}
+
+impl Default for NvIndexState {
+    fn default() -> NvIndexState {
+        NvIndexState {
+            slots: [None; MAX_NV_INDICES],
+            orderly_counters: [0; MAX_NV_INDICES],
+            max_counter: 0,
+        }
+    }
+}
+
+const MAX_ORDERLY_COUNT: u64 = 255;
+
+fn is_orderly_counter(public: &TpmsNvPublic) -> bool {
+    public.nv_type() == TpmNt::Counter && public.has_attributes(TPMA_NV_ORDERLY)
+}
+
+// Each NV slot holds the public area and authValue, then the data at a
+// fixed offset. A slot that doesn't start with an NV index handle is free.
+fn slot_offset(slot: usize) -> usize {
+    NV_INDICES + slot * NV_INDEX_NV_SIZE
+}
+
+fn data_offset(slot: usize, offset: usize) -> usize {
+    slot_offset(slot) + NV_INDEX_HEADER_NV_SIZE + offset
+}
+
+// nameAlg || H_nameAlg(TPMS_NV_PUBLIC)
+pub fn nv_name(public: &TpmsNvPublic) -> Result<Tpm2bName, TpmError> {
+    let mut buffer = [0u8; NV_INDEX_HEADER_NV_SIZE];
+    let size = marshal_tpms_nv_public(&mut buffer, public)?;
+    let digest = hash(public.name_alg, &[&buffer[..size]])?;
+
+    let mut name = Tpm2bName::default();
+    marshal_u16(&mut name.buffer, public.name_alg as u16)?;
+    name.buffer[2..2 + digest.size as usize].copy_from_slice(digest.as_slice());
+    name.size = 2 + digest.size;
+
+    Ok(name)
+}
+
+impl TpmInstance {
+    // Read the public area and authValue of every defined index, and the
+    // current value of the orderly counters.
+    pub(crate) fn nv_index_restore(&mut self) -> Result<(), TpmError> {
+        let mut max_counter = [0u8; 8];
+        self.nv_read(NV_MAX_COUNTER, &mut max_counter)?;
+        self.nv_indices.max_counter = u64::from_be_bytes(max_counter);
+
+        for slot in 0..MAX_NV_INDICES {
+            let mut header = [0u8; NV_INDEX_HEADER_NV_SIZE];
+            self.nv_read(slot_offset(slot), &mut header)?;
+
+            let mut offset = 0;
+            self.nv_indices.slots[slot] = unmarshal_tpms_nv_public(&header, &mut offset)
+                .and_then(|public| {
+                    let auth_value = unmarshal_tpm2b(&header, &mut offset)?;
+                    Ok(NvIndex { public, auth_value })
+                })
+                .ok();
+
+            if let Some(index) = self.nv_indices.slots[slot] {
+                if is_orderly_counter(&index.public) {
+                    self.nv_indices.orderly_counters[slot] = self.nv_index_read_nv_u64(slot)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Release the locks that only last until the next Startup(CLEAR), and
+    // forget the contents of CLEAR_STCLEAR indices. A write lock on a written
+    // WRITEDEFINE index is permanent.
+    //
+    // Without an orderly shutdown the last increments of an orderly counter
+    // may never have reached NV. Move it past any value it could have had.
+    pub(crate) fn nv_index_startup(&mut self, orderly: bool) -> Result<(), TpmError> {
+        for slot in 0..MAX_NV_INDICES {
+            let mut index = match self.nv_indices.slots[slot] {
+                Some(index) => index,
+                None => continue,
+            };
+
+            let public = &mut index.public;
+            let attributes = public.attributes;
+            let stclear_lock = TPMA_NV_WRITE_STCLEAR | TPMA_NV_GLOBALLOCK;
+            if public.attributes & stclear_lock != 0
+                && !public.has_attributes(TPMA_NV_WRITEDEFINE | TPMA_NV_WRITTEN)
+            {
+                public.attributes &= !TPMA_NV_WRITELOCKED;
+            }
+            if public.has_attributes(TPMA_NV_CLEAR_STCLEAR) {
+                public.attributes &= !TPMA_NV_WRITTEN;
+            }
+            public.attributes &= !TPMA_NV_READLOCKED;
+            if public.attributes != attributes {
+                self.nv_index_store(slot, &index)?;
+            }
+
+            if orderly
+                || !is_orderly_counter(&index.public)
+                || !index.public.has_attributes(TPMA_NV_WRITTEN)
+            {
+                continue;
+            }
+
+            let value = self
+                .nv_index_read_nv_u64(slot)?
+                .saturating_add(MAX_ORDERLY_COUNT + 1);
+            self.nv_write(data_offset(slot, 0), &value.to_be_bytes())?;
+            self.nv_indices.orderly_counters[slot] = value;
+        }
+
+        Ok(())
+    }
+
+    // Bring the NV copy of every orderly counter up to date.
+    pub(crate) fn nv_index_shutdown(&mut self) -> Result<(), TpmError> {
+        for slot in 0..MAX_NV_INDICES {
+            let index = match self.nv_indices.slots[slot] {
+                Some(index) => index,
+                None => continue,
+            };
+            if !is_orderly_counter(&index.public) || !index.public.has_attributes(TPMA_NV_WRITTEN) {
+                continue;
+            }
+
+            let value = self.nv_indices.orderly_counters[slot];
+            if self.nv_index_read_nv_u64(slot)? != value {
+                self.nv_write(data_offset(slot, 0), &value.to_be_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn nv_index_read_nv_u64(&self, slot: usize) -> Result<u64, TpmError> {
+        let mut data = [0u8; 8];
+        self.nv_read(data_offset(slot, 0), &mut data)?;
+
+        Ok(u64::from_be_bytes(data))
+    }
+
+    // Read index data. Orderly counters are read from RAM.
+    fn nv_index_read_data(
+        &self,
+        slot: usize,
+        public: &TpmsNvPublic,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result<(), TpmError> {
+        if is_orderly_counter(public) {
+            let value = self.nv_indices.orderly_counters[slot].to_be_bytes();
+            buf.copy_from_slice(&value[offset..offset + buf.len()]);
+            return Ok(());
+        }
+
+        self.nv_read(data_offset(slot, offset), buf)
+    }
+
+    // Write index data and mark the index as written.
+    fn nv_index_write_data(
+        &mut self,
+        slot: usize,
+        index: &NvIndex,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), TpmError> {
+        self.nv_write(data_offset(slot, offset), data)?;
+
+        if !index.public.has_attributes(TPMA_NV_WRITTEN) {
+            let mut index = *index;
+            index.public.attributes |= TPMA_NV_WRITTEN;
+            self.nv_index_store(slot, &index)?;
+        }
+
+        Ok(())
+    }
+
+    fn nv_index_find(&self, handle: TpmHandle) -> Option<usize> {
+        self.nv_indices
+            .slots
+            .iter()
+            .position(|s| matches!(s, Some(index) if index.public.nv_index == handle))
+    }
+
+    pub(crate) fn nv_index_get(&self, handle: TpmHandle) -> Option<&NvIndex> {
+        self.nv_indices.slots[self.nv_index_find(handle)?].as_ref()
+    }
+
+    // Platform indices can't be used while phEnableNV is clear, and owner
+    // indices can't be used while the storage hierarchy is disabled.
+    pub(crate) fn nv_index_check(&self, handle: TpmHandle) -> Result<(), TpmError> {
+        let index = match self.nv_index_get(handle) {
+            Some(index) => index,
+            None => return Err(TpmError::new(TpmRc::Handle)),
+        };
+
+        let enabled = match index.public.has_attributes(TPMA_NV_PLATFORMCREATE) {
+            true => self.hierarchy.ph_enable_nv,
+            false => self.hierarchy.sh_enable,
+        };
+        match enabled {
+            true => Ok(()),
+            false => Err(TpmError::new(TpmRc::Handle)),
+        }
+    }
+
+    // Write the public area and authValue of the index in `slot`.
+    fn nv_index_store(&mut self, slot: usize, index: &NvIndex) -> Result<(), TpmError> {
+        let mut header = [0u8; NV_INDEX_HEADER_NV_SIZE];
+        let mut size = marshal_tpms_nv_public(&mut header, &index.public)?;
+        size += marshal_tpm2b(&mut header[size..], &index.auth_value)?;
+        self.nv_write(slot_offset(slot), &header[..size])?;
+
+        self.nv_indices.slots[slot] = Some(*index);
+
+        Ok(())
+    }
+
+    fn nv_index_remove(&mut self, slot: usize) -> Result<(), TpmError> {
+        let index = self.nv_indices.slots[slot].unwrap_or_default();
+        let public = &index.public;
+        if public.nv_type() == TpmNt::Counter && public.has_attributes(TPMA_NV_WRITTEN) {
+            let mut value = [0u8; 8];
+            self.nv_index_read_data(slot, public, 0, &mut value)?;
+            let value = u64::from_be_bytes(value);
+
+            if value > self.nv_indices.max_counter {
+                self.nv_write(NV_MAX_COUNTER, &value.to_be_bytes())?;
+                self.nv_indices.max_counter = value;
+            }
+        }
+
+        self.nv_write(slot_offset(slot), &[0u8; 4])?;
+        self.nv_indices.slots[slot] = None;
+
+        Ok(())
+    }
+
+    // Undefine every index the owner created.
+    pub(crate) fn nv_index_undefine_owner(&mut self) -> Result<(), TpmError> {
+        for slot in 0..MAX_NV_INDICES {
+            if let Some(index) = self.nv_indices.slots[slot] {
+                if !index.public.has_attributes(TPMA_NV_PLATFORMCREATE) {
+                    self.nv_index_remove(slot)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Handles of all defined indices, in ascending order.
+    pub(crate) fn nv_index_handles(&self) -> ([TpmHandle; MAX_NV_INDICES], usize) {
+        let mut handles = [0; MAX_NV_INDICES];
+        let mut count = 0;
+        for index in self.nv_indices.slots.iter().flatten() {
+            handles[count] = index.public.nv_index;
+            count += 1;
+        }
+        handles[..count].sort_unstable();
+
+        (handles, count)
+    }
+
+    pub(crate) fn nv_index_slots_free(&self) -> usize {
+        self.nv_indices.slots.iter().filter(|s| s.is_none()).count()
+    }
+}
+
+// Which of the index's attributes lets `auth_handle` write it. The index
+// itself needs TPMA_NV_POLICYWRITE when a policy session authorized it, and
+// TPMA_NV_AUTHWRITE otherwise.
+fn check_write_access(
+    tpm: &TpmInstance,
+    auth_handle: TpmHandle,
+    public: &TpmsNvPublic,
+) -> Result<(), TpmError> {
+    let attribute = match TpmRh::from(auth_handle) {
+        TpmRh::Owner => TPMA_NV_OWNERWRITE,
+        TpmRh::Platform => TPMA_NV_PPWRITE,
+        _ if auth_handle != public.nv_index => return Err(TpmError::new(TpmRc::NvAuthorization)),
+        _ if tpm.authorized_by_policy(auth_handle) => TPMA_NV_POLICYWRITE,
+        _ => TPMA_NV_AUTHWRITE,
+    };
+
+    match public.has_attributes(attribute) {
+        true => Ok(()),
+        false => Err(TpmError::new(TpmRc::NvAuthorization)),
+    }
+}
+
+fn check_read_access(
+    tpm: &TpmInstance,
+    auth_handle: TpmHandle,
+    public: &TpmsNvPublic,
+) -> Result<(), TpmError> {
+    let attribute = match TpmRh::from(auth_handle) {
+        TpmRh::Owner => TPMA_NV_OWNERREAD,
+        TpmRh::Platform => TPMA_NV_PPREAD,
+        _ if auth_handle != public.nv_index => return Err(TpmError::new(TpmRc::NvAuthorization)),
+        _ if tpm.authorized_by_policy(auth_handle) => TPMA_NV_POLICYREAD,
+        _ => TPMA_NV_AUTHREAD,
+    };
+
+    match public.has_attributes(attribute) {
+        true => Ok(()),
+        false => Err(TpmError::new(TpmRc::NvAuthorization)),
+    }
+}
+
+// Look up the slot and index behind a handle that has already been checked.
+fn defined_index(
+    tpm: &TpmInstance,
+    handle: TpmHandle,
+    n: u32,
+) -> Result<(usize, NvIndex), TpmError> {
+    match tpm.nv_index_find(handle) {
+        Some(slot) => Ok((slot, tpm.nv_indices.slots[slot].unwrap_or_default())),
+        None => Err(TpmError::handle(TpmRc::Handle, n)),
+    }
+}
+
+pub fn tpm2_nv_define_space(
+    tpm: &mut TpmInstance,
+    args: &NvDefineSpaceArgs,
+) -> Result<(), TpmError> {
+    let public = &args.public_info;
+    let platform = TpmRh::from(args.auth_handle) == TpmRh::Platform;
+
+    if platform && !tpm.hierarchy.ph_enable_nv {
+        return Err(TpmError::handle(TpmRc::Hierarchy, 1));
+    }
+
+    let digest_size = public.name_alg.digest_size();
+    if args.auth.size as usize > digest_size {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+    if public.auth_policy.size != 0 && public.auth_policy.size as usize != digest_size {
+        return Err(TpmError::parameter(TpmRc::Size, 2));
+    }
+
+    let data_size = public.data_size as usize;
+    let size_ok = match public.nv_type() {
+        TpmNt::Ordinary => data_size <= MAX_NV_INDEX_SIZE,
+        TpmNt::Counter | TpmNt::Bits | TpmNt::PinFail | TpmNt::PinPass => data_size == 8,
+        TpmNt::Extend => data_size == digest_size,
+        TpmNt::Unknown => return Err(TpmError::parameter(TpmRc::Attributes, 2)),
+    };
+    if !size_ok {
+        return Err(TpmError::parameter(TpmRc::Size, 2));
+    }
+
+    // Counters can never go back. A PIN index's authValue is the PIN, so
+    // it can't be used to change the PIN counts, and a PIN_FAIL index does
+    // its own dictionary attack protection.
+    let pin_fixed = TPMA_NV_AUTHWRITE | TPMA_NV_GLOBALLOCK | TPMA_NV_WRITEDEFINE;
+    let type_conflict = match public.nv_type() {
+        TpmNt::Counter => public.has_attributes(TPMA_NV_CLEAR_STCLEAR),
+        TpmNt::PinFail => {
+            public.attributes & pin_fixed != 0 || !public.has_attributes(TPMA_NV_NO_DA)
+        }
+        TpmNt::PinPass => public.attributes & pin_fixed != 0,
+        _ => false,
+    };
+    if type_conflict {
+        return Err(TpmError::parameter(TpmRc::Attributes, 2));
+    }
+
+    // The index records which hierarchy created it, only the platform can
+    // create one that's deleted with a policy, it has to be readable and
+    // writable by someone, and the TPM sets the state bits itself.
+    let readable = TPMA_NV_PPREAD | TPMA_NV_OWNERREAD | TPMA_NV_AUTHREAD | TPMA_NV_POLICYREAD;
+    let writable = TPMA_NV_PPWRITE | TPMA_NV_OWNERWRITE | TPMA_NV_AUTHWRITE | TPMA_NV_POLICYWRITE;
+    let state = TPMA_NV_WRITELOCKED | TPMA_NV_READLOCKED | TPMA_NV_WRITTEN;
+    if public.has_attributes(TPMA_NV_PLATFORMCREATE) != platform
+        || (public.has_attributes(TPMA_NV_POLICY_DELETE) && !platform)
+        || public.attributes & readable == 0
+        || public.attributes & writable == 0
+        || public.attributes & state != 0
+    {
+        return Err(TpmError::parameter(TpmRc::Attributes, 2));
+    }
+
+    if tpm.nv_index_find(public.nv_index).is_some() {
+        return Err(TpmError::new(TpmRc::NvDefined));
+    }
+    let slot = match tpm.nv_indices.slots.iter().position(|s| s.is_none()) {
+        Some(slot) => slot,
+        None => return Err(TpmError::new(TpmRc::NvSpace)),
+    };
+
+    // Don't let the new index read back whatever the slot held before.
+    let erased = [0xFFu8; 256];
+    for offset in (0..data_size).step_by(erased.len()) {
+        let size = erased.len().min(data_size - offset);
+        tpm.nv_write(data_offset(slot, offset), &erased[..size])?;
+    }
+
+    let index = NvIndex {
+        public: *public,
+        auth_value: args.auth,
+    };
+    tpm.nv_index_store(slot, &index)
+}
+
+pub fn tpm2_nv_undefine_space(
+    tpm: &mut TpmInstance,
+    args: &NvUndefineSpaceArgs,
+) -> Result<(), TpmError> {
+    let (slot, index) = defined_index(tpm, args.nv_index, 2)?;
+
+    if index.public.has_attributes(TPMA_NV_POLICY_DELETE) {
+        return Err(TpmError::handle(TpmRc::Attributes, 2));
+    }
+    if TpmRh::from(args.auth_handle) == TpmRh::Owner
+        && index.public.has_attributes(TPMA_NV_PLATFORMCREATE)
+    {
+        return Err(TpmError::new(TpmRc::NvAuthorization));
+    }
+
+    tpm.nv_index_remove(slot)
+}
+
+// The index's admin role is only reachable with a policy session, whose
+// policy has to include TPM2_PolicyCommandCode(TPM_CC_NV_UndefineSpaceSpecial).
+pub fn tpm2_nv_undefine_space_special(
+    tpm: &mut TpmInstance,
+    args: &NvUndefineSpaceSpecialArgs,
+) -> Result<(), TpmError> {
+    let (slot, index) = defined_index(tpm, args.nv_index, 1)?;
+
+    if !index.public.has_attributes(TPMA_NV_POLICY_DELETE) {
+        return Err(TpmError::handle(TpmRc::Attributes, 1));
+    }
+
+    tpm.nv_index_remove(slot)
+}
+
+pub fn tpm2_nv_read_public(
+    tpm: &mut TpmInstance,
+    args: &NvReadPublicArgs,
+) -> Result<NvReadPublicResponse, TpmError> {
+    let (_, index) = defined_index(tpm, args.nv_index, 1)?;
+
+    Ok(NvReadPublicResponse {
+        nv_public: index.public,
+        nv_name: nv_name(&index.public)?,
+    })
+}
+
+pub fn tpm2_nv_write(tpm: &mut TpmInstance, args: &NvWriteArgs) -> Result<(), TpmError> {
+    let (slot, index) = defined_index(tpm, args.nv_index, 2)?;
+    let public = &index.public;
+
+    check_write_access(tpm, args.auth_handle, public)?;
+    if public.has_attributes(TPMA_NV_WRITELOCKED) {
+        return Err(TpmError::new(TpmRc::NvLocked));
+    }
+    // Counters, bit fields and extend indices have their own commands.
+    if !matches!(
+        public.nv_type(),
+        TpmNt::Ordinary | TpmNt::PinFail | TpmNt::PinPass
+    ) {
+        return Err(TpmError::handle(TpmRc::Attributes, 2));
+    }
+
+    let size = args.data.size as usize;
+    let offset = args.offset as usize;
+    if offset + size > public.data_size as usize {
+        return Err(TpmError::new(TpmRc::NvRange));
+    }
+    if public.has_attributes(TPMA_NV_WRITEALL) && size != public.data_size as usize {
+        return Err(TpmError::new(TpmRc::NvRange));
+    }
+
+    tpm.nv_index_write_data(slot, &index, offset, args.data.as_slice())
+}
+
+pub fn tpm2_nv_read(tpm: &mut TpmInstance, args: &NvReadArgs) -> Result<NvReadResponse, TpmError> {
+    let (slot, index) = defined_index(tpm, args.nv_index, 2)?;
+    let public = &index.public;
+
+    check_read_access(tpm, args.auth_handle, public)?;
+    if public.has_attributes(TPMA_NV_READLOCKED) {
+        return Err(TpmError::new(TpmRc::NvLocked));
+    }
+    if !public.has_attributes(TPMA_NV_WRITTEN) {
+        return Err(TpmError::new(TpmRc::NvUninitialized));
+    }
+
+    let size = args.size as usize;
+    let offset = args.offset as usize;
+    if size > MAX_NV_BUFFER_SIZE {
+        return Err(TpmError::parameter(TpmRc::Value, 1));
+    }
+    if offset + size > public.data_size as usize {
+        return Err(TpmError::new(TpmRc::NvRange));
+    }
+
+    let mut response = NvReadResponse::default();
+    tpm.nv_index_read_data(slot, public, offset, &mut response.data.buffer[..size])?;
+    response.data.size = size as u16;
+
+    Ok(response)
+}
+
+pub fn tpm2_nv_write_lock(tpm: &mut TpmInstance, args: &NvWriteLockArgs) -> Result<(), TpmError> {
+    let (slot, mut index) = defined_index(tpm, args.nv_index, 2)?;
+
+    check_write_access(tpm, args.auth_handle, &index.public)?;
+    if index.public.has_attributes(TPMA_NV_WRITELOCKED) {
+        return Ok(());
+    }
+    if !index.public.has_attributes(TPMA_NV_WRITEDEFINE)
+        && !index.public.has_attributes(TPMA_NV_WRITE_STCLEAR)
+    {
+        return Err(TpmError::handle(TpmRc::Attributes, 2));
+    }
+
+    index.public.attributes |= TPMA_NV_WRITELOCKED;
+    tpm.nv_index_store(slot, &index)
+}
+
+// Lock every index with GLOBALLOCK until the next Startup(CLEAR).
+pub fn tpm2_nv_global_write_lock(
+    tpm: &mut TpmInstance,
+    _args: &NvGlobalWriteLockArgs,
+) -> Result<(), TpmError> {
+    for slot in 0..MAX_NV_INDICES {
+        let mut index = match tpm.nv_indices.slots[slot] {
+            Some(index) => index,
+            None => continue,
+        };
+        if !index.public.has_attributes(TPMA_NV_GLOBALLOCK)
+            || index.public.has_attributes(TPMA_NV_WRITELOCKED)
+        {
+            continue;
+        }
+
+        index.public.attributes |= TPMA_NV_WRITELOCKED;
+        tpm.nv_index_store(slot, &index)?;
+    }
+
+    Ok(())
+}
+
+pub fn tpm2_nv_read_lock(tpm: &mut TpmInstance, args: &NvReadLockArgs) -> Result<(), TpmError> {
+    let (slot, mut index) = defined_index(tpm, args.nv_index, 2)?;
+
+    check_read_access(tpm, args.auth_handle, &index.public)?;
+    if !index.public.has_attributes(TPMA_NV_READ_STCLEAR) {
+        return Err(TpmError::handle(TpmRc::Attributes, 2));
+    }
+    if index.public.has_attributes(TPMA_NV_READLOCKED) {
+        return Ok(());
+    }
+
+    index.public.attributes |= TPMA_NV_READLOCKED;
+    tpm.nv_index_store(slot, &index)
+}
+
+// Like TPM2_NV_UndefineSpaceSpecial, this needs the admin role, which only a
+// policy session asserting TPM_CC_NV_ChangeAuth can authorize. The response
+// HMAC, if the policy needs the authValue, uses the new one.
+pub fn tpm2_nv_change_auth(tpm: &mut TpmInstance, args: &NvChangeAuthArgs) -> Result<(), TpmError> {
+    let (slot, mut index) = defined_index(tpm, args.nv_index, 1)?;
+
+    if args.new_auth.size as usize > index.public.name_alg.digest_size() {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+
+    index.auth_value = args.new_auth;
+    tpm.nv_index_store(slot, &index)
+}
+
+pub fn tpm2_nv_certify(
+    tpm: &mut TpmInstance,
+    args: &NvCertifyArgs,
+) -> Result<NvCertifyResponse, TpmError> {
+    let (slot, index) = defined_index(tpm, args.nv_index, 3)?;
+    let public = &index.public;
+
+    check_read_access(tpm, args.auth_handle, public)?;
+    if public.has_attributes(TPMA_NV_READLOCKED) {
+        return Err(TpmError::new(TpmRc::NvLocked));
+    }
+    if !public.has_attributes(TPMA_NV_WRITTEN) {
+        return Err(TpmError::new(TpmRc::NvUninitialized));
+    }
+
+    let size = args.size as usize;
+    let offset = args.offset as usize;
+    if size > MAX_NV_BUFFER_SIZE {
+        return Err(TpmError::parameter(TpmRc::Value, 3));
+    }
+    if offset + size > public.data_size as usize {
+        return Err(TpmError::new(TpmRc::NvRange));
+    }
+
+    let mut info = TpmsNvCertifyInfo {
+        index_name: nv_name(public)?,
+        offset: args.offset,
+        ..Default::default()
+    };
+    tpm.nv_index_read_data(slot, public, offset, &mut info.nv_contents.buffer[..size])?;
+    info.nv_contents.size = size as u16;
+
+    let (certify_info, signature) = tpm.attest(
+        args.sign_handle,
+        &args.in_scheme,
+        &args.qualifying_data,
+        TpmuAttest::Nv(info),
+    )?;
+
+    Ok(NvCertifyResponse {
+        certify_info,
+        signature,
+    })
+}
+
+// Checks shared by the commands that update an index of a particular type.
+fn check_update(
+    tpm: &TpmInstance,
+    auth_handle: TpmHandle,
+    index: &NvIndex,
+    nv_type: TpmNt,
+) -> Result<(), TpmError> {
+    let public = &index.public;
+
+    check_write_access(tpm, auth_handle, public)?;
+    if public.has_attributes(TPMA_NV_WRITELOCKED) {
+        return Err(TpmError::new(TpmRc::NvLocked));
+    }
+    if public.nv_type() != nv_type {
+        return Err(TpmError::handle(TpmRc::Attributes, 2));
+    }
+
+    Ok(())
+}
+
+pub fn tpm2_nv_increment(tpm: &mut TpmInstance, args: &NvIncrementArgs) -> Result<(), TpmError> {
+    let (slot, index) = defined_index(tpm, args.nv_index, 2)?;
+    check_update(tpm, args.auth_handle, &index, TpmNt::Counter)?;
+
+    let public = &index.public;
+    let written = public.has_attributes(TPMA_NV_WRITTEN);
+    let current = match written {
+        true => {
+            let mut value = [0u8; 8];
+            tpm.nv_index_read_data(slot, public, 0, &mut value)?;
+            u64::from_be_bytes(value)
+        }
+        false => tpm.nv_indices.max_counter,
+    };
+    let value = match current.checked_add(1) {
+        Some(value) => value,
+        None => return Err(TpmError::new(TpmRc::NvRange)),
+    };
+
+    if !is_orderly_counter(public) || !written || value & MAX_ORDERLY_COUNT == 0 {
+        tpm.nv_index_write_data(slot, &index, 0, &value.to_be_bytes())?;
+    } else {
+        tpm.nv_clear_orderly()?;
+    }
+    tpm.nv_indices.orderly_counters[slot] = value;
+
+    Ok(())
+}
+
+pub fn tpm2_nv_set_bits(tpm: &mut TpmInstance, args: &NvSetBitsArgs) -> Result<(), TpmError> {
+    let (slot, index) = defined_index(tpm, args.nv_index, 2)?;
+    check_update(tpm, args.auth_handle, &index, TpmNt::Bits)?;
+
+    let mut value = [0u8; 8];
+    if index.public.has_attributes(TPMA_NV_WRITTEN) {
+        tpm.nv_index_read_data(slot, &index.public, 0, &mut value)?;
+    }
+    let value = u64::from_be_bytes(value) | args.bits;
+
+    tpm.nv_index_write_data(slot, &index, 0, &value.to_be_bytes())
+}
+
+// newValue = H_nameAlg(oldValue || data), where an index that was never
+// written starts out as zeros.
+pub fn tpm2_nv_extend(tpm: &mut TpmInstance, args: &NvExtendArgs) -> Result<(), TpmError> {
+    let (slot, index) = defined_index(tpm, args.nv_index, 2)?;
+    check_update(tpm, args.auth_handle, &index, TpmNt::Extend)?;
+
+    let public = &index.public;
+    let size = public.data_size as usize;
+    let mut value = [0u8; MAX_DIGEST_SIZE];
+    if public.has_attributes(TPMA_NV_WRITTEN) {
+        tpm.nv_index_read_data(slot, public, 0, &mut value[..size])?;
+    }
+    let digest = hash(public.name_alg, &[&value[..size], args.data.as_slice()])?;
+
+    tpm.nv_index_write_data(slot, &index, 0, digest.as_slice())
+}
+
+impl TpmInstance {
+    fn nv_index_find_pin(&self, handle: TpmHandle) -> Option<(usize, NvIndex)> {
+        let slot = self.nv_index_find(handle)?;
+        let index = self.nv_indices.slots[slot]?;
+
+        match index.public.nv_type() {
+            TpmNt::PinFail | TpmNt::PinPass => Some((slot, index)),
+            _ => None,
+        }
+    }
+
+    fn nv_index_read_pin(&self, slot: usize) -> Result<TpmsNvPinCounterParameters, TpmError> {
+        let mut data = [0u8; 8];
+        self.nv_read(data_offset(slot, 0), &mut data)?;
+
+        unmarshal_tpms_nv_pin_counter_parameters(&data, &mut 0)
+    }
+
+    // A PIN index's authValue can be used while pinCount is below
+    // pinLimit. That counts successful authorizations for PIN_PASS and
+    // failures since the last success for PIN_FAIL.
+    pub(crate) fn nv_index_pin_available(&self, handle: TpmHandle) -> Result<(), TpmError> {
+        let (slot, index) = match self.nv_index_find_pin(handle) {
+            Some(pin) => pin,
+            None => return Ok(()),
+        };
+        if !index.public.has_attributes(TPMA_NV_WRITTEN) {
+            return Err(TpmError::new(TpmRc::AuthUnavailable));
+        }
+
+        let pin = self.nv_index_read_pin(slot)?;
+        match pin.pin_count < pin.pin_limit {
+            true => Ok(()),
+            false => Err(TpmError::new(TpmRc::AuthUnavailable)),
+        }
+    }
+
+    // Count an authorization attempt against a PIN index.
+    pub(crate) fn nv_index_pin_attempt(
+        &mut self,
+        handle: TpmHandle,
+        success: bool,
+    ) -> Result<(), TpmError> {
+        let (slot, index) = match self.nv_index_find_pin(handle) {
+            Some(pin) => pin,
+            None => return Ok(()),
+        };
+
+        let mut pin = self.nv_index_read_pin(slot)?;
+        match (index.public.nv_type(), success) {
+            (TpmNt::PinPass, true) | (TpmNt::PinFail, false) => pin.pin_count += 1,
+            (TpmNt::PinFail, true) if pin.pin_count != 0 => pin.pin_count = 0,
+            _ => return Ok(()),
+        }
+
+        let mut data = [0u8; 8];
+        marshal_tpms_nv_pin_counter_parameters(&mut data, &pin)?;
+        self.nv_write(data_offset(slot, 0), &data)
+    }
+}
@@ -0,0 +1,1227 @@
+use crate::authorization::{auth_equal, trim_trailing_zeros};
+use crate::crypto::hash::*;
+use crate::crypto::kdf::*;
+use crate::crypto::{ecc, rsa, sym};
+use crate::hierarchy::*;
+use crate::marshal::*;
+use crate::sequence::*;
+use crate::tpm::*;
+use crate::types::*;
+
+pub const TRANSIENT_FIRST: TpmHandle = 0x80000000;
+
+// A loaded object. The Name and Qualified Name are computed once when the
+// object is loaded.
+#[derive(Clone, Copy, Default)]
+pub struct Object {
+    pub public: TpmtPublic,
+    pub sensitive: TpmtSensitive,
+    pub name: Tpm2bName,
+    pub qualified_name: Tpm2bName,
+    pub hierarchy: Hierarchy,
+}
+
+// nameAlg || H_nameAlg(TPMT_PUBLIC). An object with a nameAlg of
+// TPM_ALG_NULL has an empty Name.
+pub fn public_name(public: &TpmtPublic) -> Result<Tpm2bName, TpmError> {
+    if public.name_alg == TpmAlgId::Null {
+        return Ok(Tpm2bName::default());
+    }
+
+    let mut buffer = [0u8; MAX_PUBLIC_SIZE];
+    let size = marshal_tpmt_public(&mut buffer, public)?;
+    let digest = hash(public.name_alg, &[&buffer[..size]])?;
+
+    let mut name = Tpm2bName::default();
+    marshal_u16(&mut name.buffer, public.name_alg as u16)?;
+    name.buffer[2..2 + digest.size as usize].copy_from_slice(digest.as_slice());
+    name.size = 2 + digest.size;
+
+    Ok(name)
+}
+
+// nameAlg || H_nameAlg(QN(parent) || Name). The Qualified Name of a
+// hierarchy is its handle.
+pub fn qualified_name(
+    parent_qn: &[u8],
+    public: &TpmtPublic,
+    name: &Tpm2bName,
+) -> Result<Tpm2bName, TpmError> {
+    if public.name_alg == TpmAlgId::Null {
+        return Ok(*name);
+    }
+
+    let digest = hash(public.name_alg, &[parent_qn, name.as_slice()])?;
+
+    let mut qn = Tpm2bName::default();
+    marshal_u16(&mut qn.buffer, public.name_alg as u16)?;
+    qn.buffer[2..2 + digest.size as usize].copy_from_slice(digest.as_slice());
+    qn.size = 2 + digest.size;
+
+    Ok(qn)
+}
+
+// A storage parent protects the private areas of its children.
+pub fn is_storage_parent(public: &TpmtPublic) -> bool {
+    public.has_attributes(TPMA_OBJECT_RESTRICTED | TPMA_OBJECT_DECRYPT)
+        && !public.has_attributes(TPMA_OBJECT_SIGN_ENCRYPT)
+        && matches!(
+            public.parameters,
+            TpmuPublicParms::Rsa(_) | TpmuPublicParms::Ecc(_) | TpmuPublicParms::SymCipher(_)
+        )
+}
+
+// A derivation parent is a keyed hash object whose secret seeds the keys of
+// its children.
+pub fn is_derivation_parent(public: &TpmtPublic) -> bool {
+    public.has_attributes(TPMA_OBJECT_RESTRICTED | TPMA_OBJECT_DECRYPT)
+        && !public.has_attributes(TPMA_OBJECT_SIGN_ENCRYPT)
+        && matches!(
+            public.parameters,
+            TpmuPublicParms::KeyedHash(parms) if parms.scheme.scheme == TpmAlgId::Xor
+        )
+}
+
+// Attribute checks for any object the TPM creates or loads. `parent` is
+// None for primary objects, whose parent is a hierarchy.
+pub fn public_attributes_validation(
+    parent: Option<&TpmtPublic>,
+    public: &TpmtPublic,
+) -> Result<(), TpmError> {
+    let is_set = |bit: u32| public.object_attributes & bit != 0;
+
+    if is_set(TPMA_OBJECT_FIXED_TPM) && !is_set(TPMA_OBJECT_FIXED_PARENT) {
+        return Err(TpmError::new(TpmRc::Attributes));
+    }
+
+    if let Some(parent) = parent {
+        // Only a parent that can't leave the TPM can have children that
+        // can't either.
+        if is_set(TPMA_OBJECT_FIXED_TPM) && !parent.has_attributes(TPMA_OBJECT_FIXED_TPM) {
+            return Err(TpmError::new(TpmRc::Attributes));
+        }
+        // Anything that can be duplicated inherits encryptedDuplication.
+        let parent_encrypted = parent.has_attributes(TPMA_OBJECT_ENCRYPTED_DUPLICATION);
+        if !is_set(TPMA_OBJECT_FIXED_TPM)
+            && parent_encrypted != is_set(TPMA_OBJECT_ENCRYPTED_DUPLICATION)
+        {
+            return Err(TpmError::new(TpmRc::Attributes));
+        }
+    }
+
+    if is_set(TPMA_OBJECT_RESTRICTED)
+        && is_set(TPMA_OBJECT_SIGN_ENCRYPT)
+        && is_set(TPMA_OBJECT_DECRYPT)
+    {
+        return Err(TpmError::new(TpmRc::Attributes));
+    }
+
+    // x509sign keys sign certificates and nothing else.
+    if is_set(TPMA_OBJECT_X509_SIGN)
+        && (!is_set(TPMA_OBJECT_SIGN_ENCRYPT)
+            || is_set(TPMA_OBJECT_DECRYPT)
+            || is_set(TPMA_OBJECT_RESTRICTED))
+    {
+        return Err(TpmError::new(TpmRc::Attributes));
+    }
+
+    if !public.auth_policy.is_empty()
+        && public.auth_policy.size as usize != public.name_alg.digest_size()
+    {
+        return Err(TpmError::new(TpmRc::Size));
+    }
+
+    Ok(())
+}
+
+// Check that the schemes and symmetric algorithms in a public area agree
+// with how the key may be used.
+pub fn scheme_checks(public: &TpmtPublic) -> Result<(), TpmError> {
+    let restricted = public.has_attributes(TPMA_OBJECT_RESTRICTED);
+    let sign = public.has_attributes(TPMA_OBJECT_SIGN_ENCRYPT);
+    let decrypt = public.has_attributes(TPMA_OBJECT_DECRYPT);
+
+    // An unrestricted key can leave the scheme for each operation to pick,
+    // a restricted one can't.
+    let scheme_ok = |scheme: TpmAlgId, allowed: &[TpmAlgId]| {
+        allowed.contains(&scheme) || (scheme == TpmAlgId::Null && !restricted)
+    };
+
+    let (symmetric, scheme) = match &public.parameters {
+        TpmuPublicParms::KeyedHash(parms) => {
+            let scheme = parms.scheme.scheme;
+            let ok = match (sign, decrypt) {
+                (true, false) => scheme_ok(scheme, &[TpmAlgId::Hmac]),
+                (false, true) => scheme_ok(scheme, &[TpmAlgId::Xor]),
+                _ => scheme == TpmAlgId::Null,
+            };
+            if !ok {
+                return Err(TpmError::new(TpmRc::Scheme));
+            }
+            return Ok(());
+        }
+        TpmuPublicParms::SymCipher(parms) => {
+            if !sign && !decrypt {
+                return Err(TpmError::new(TpmRc::Attributes));
+            }
+            // Storage keys protect their children with CFB.
+            if restricted && decrypt && parms.sym.mode != TpmAlgId::Cfb {
+                return Err(TpmError::new(TpmRc::Mode));
+            }
+            return Ok(());
+        }
+        TpmuPublicParms::Rsa(parms) => (&parms.symmetric, parms.scheme.scheme),
+        TpmuPublicParms::Ecc(parms) => {
+            if parms.kdf.scheme != TpmAlgId::Null {
+                return Err(TpmError::new(TpmRc::Kdf));
+            }
+            (&parms.symmetric, parms.scheme.scheme)
+        }
+        TpmuPublicParms::Unknown => return Err(TpmError::new(TpmRc::Type)),
+    };
+
+    if !sign && !decrypt {
+        return Err(TpmError::new(TpmRc::Attributes));
+    }
+
+    // Only storage keys have a symmetric algorithm, and they must have one.
+    if restricted && decrypt {
+        if symmetric.algorithm == TpmAlgId::Null {
+            return Err(TpmError::new(TpmRc::Symmetric));
+        }
+        if symmetric.mode != TpmAlgId::Cfb {
+            return Err(TpmError::new(TpmRc::Mode));
+        }
+        if scheme != TpmAlgId::Null {
+            return Err(TpmError::new(TpmRc::Scheme));
+        }
+        return Ok(());
+    }
+    if symmetric.algorithm != TpmAlgId::Null {
+        return Err(TpmError::new(TpmRc::Symmetric));
+    }
+
+    let ok = match (sign, decrypt) {
+        (true, false) => scheme_ok(scheme, SIGNING_SCHEMES),
+        (false, true) => scheme_ok(scheme, DECRYPT_SCHEMES),
+        _ => scheme == TpmAlgId::Null,
+    };
+    if !ok {
+        return Err(TpmError::new(TpmRc::Scheme));
+    }
+
+    Ok(())
+}
+
+const SIGNING_SCHEMES: &[TpmAlgId] = &[
+    TpmAlgId::RsaSsa,
+    TpmAlgId::RsaPss,
+    TpmAlgId::EcDsa,
+    TpmAlgId::EcDaa,
+    TpmAlgId::Sm2,
+    TpmAlgId::EcSchnorr,
+];
+
+const DECRYPT_SCHEMES: &[TpmAlgId] = &[
+    TpmAlgId::RsaEs,
+    TpmAlgId::Oaep,
+    TpmAlgId::EcDh,
+    TpmAlgId::EcMqv,
+];
+
+// Checks on a creation template and the sensitive data that goes with it.
+// Errors are returned for the inSensitive (1) and inPublic (2) parameters,
+// which are the same for every creation command.
+pub fn create_checks(
+    parent: Option<&TpmtPublic>,
+    public: &TpmtPublic,
+    in_sensitive: &TpmsSensitiveCreate,
+) -> Result<(), TpmError> {
+    if !public.name_alg.is_hash() {
+        return Err(TpmError::parameter(TpmRc::Hash, 2));
+    }
+
+    public_attributes_validation(parent, public).map_err(|e| e.with_parameter(2))?;
+    scheme_checks(public).map_err(|e| e.with_parameter(2))?;
+
+    let user_auth = trim_trailing_zeros(in_sensitive.user_auth.as_slice());
+    if user_auth.len() > public.name_alg.digest_size() {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+
+    let is_set = |bit: u32| public.object_attributes & bit != 0;
+    let data_origin = is_set(TPMA_OBJECT_SENSITIVE_DATA_ORIGIN);
+    let data_size = in_sensitive.data.size as usize;
+    match &public.parameters {
+        TpmuPublicParms::Rsa(_) | TpmuPublicParms::Ecc(_) => {
+            // The TPM always generates asymmetric keys itself.
+            if data_size != 0 {
+                return Err(TpmError::parameter(TpmRc::Size, 1));
+            }
+            if !data_origin {
+                return Err(TpmError::parameter(TpmRc::Attributes, 2));
+            }
+        }
+        TpmuPublicParms::KeyedHash(_) | TpmuPublicParms::SymCipher(_) => {
+            // A data object's contents always come from the caller.
+            let data_object = !is_set(TPMA_OBJECT_SIGN_ENCRYPT) && !is_set(TPMA_OBJECT_DECRYPT);
+            if data_object && data_origin {
+                return Err(TpmError::parameter(TpmRc::Attributes, 2));
+            }
+            // A restricted key that can't be duplicated must not have a key
+            // the caller knows.
+            if is_set(TPMA_OBJECT_RESTRICTED)
+                && !data_origin
+                && (is_set(TPMA_OBJECT_FIXED_PARENT) || is_set(TPMA_OBJECT_FIXED_TPM))
+            {
+                return Err(TpmError::parameter(TpmRc::Attributes, 2));
+            }
+            // The secret comes either from the TPM or from the caller.
+            if data_origin == (data_size != 0) {
+                return Err(TpmError::parameter(TpmRc::Attributes, 2));
+            }
+        }
+        TpmuPublicParms::Unknown => return Err(TpmError::parameter(TpmRc::Type, 2)),
+    }
+
+    match &public.parameters {
+        TpmuPublicParms::Rsa(parms) if !rsa::is_valid_exponent(parms.exponent) => {
+            Err(TpmError::parameter(TpmRc::Range, 2))
+        }
+        TpmuPublicParms::Ecc(parms) if ecc::curve_params(parms.curve_id).is_none() => {
+            Err(TpmError::parameter(TpmRc::Curve, 2))
+        }
+        TpmuPublicParms::SymCipher(parms)
+            if !data_origin && data_size != parms.sym.key_bits as usize / 8 =>
+        {
+            Err(TpmError::parameter(TpmRc::KeySize, 1))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Generate the sensitive part of an object from its template, filling in
+// the unique field of the public area. All randomness comes from `rand`, so
+// a deterministic source gives a deterministic object.
+pub fn generate_object(
+    public: &mut TpmtPublic,
+    sensitive: &mut TpmtSensitive,
+    data: &Tpm2bSensitiveData,
+    rand: &mut dyn RandomSource,
+) -> Result<(), TpmError> {
+    let name_alg = public.name_alg;
+    // The checks on the template make sure that the caller supplies the
+    // secret exactly when sensitiveDataOrigin is clear. Derived objects
+    // have it clear but take their secret from `rand` too.
+    let data_origin = data.is_empty();
+
+    match &public.parameters {
+        TpmuPublicParms::Rsa(parms) => {
+            let mut n = Tpm2bPublicKeyRsa::default();
+            let mut p = Tpm2bPrivateKeyRsa::default();
+            rsa::generate_key(parms.key_bits, parms.exponent, rand, &mut n, &mut p)?;
+            public.unique = TpmuPublicId::Rsa(n);
+            sensitive.sensitive = TpmuSensitiveComposite::Rsa(p);
+        }
+        TpmuPublicParms::Ecc(parms) => {
+            let mut d = Tpm2bEccParameter::default();
+            let mut q = TpmsEccPoint::default();
+            ecc::generate_key(parms.curve_id, rand, &mut d, &mut q)?;
+            public.unique = TpmuPublicId::Ecc(q);
+            sensitive.sensitive = TpmuSensitiveComposite::Ecc(d);
+        }
+        TpmuPublicParms::KeyedHash(parms) => {
+            let mut bits = *data;
+            if data_origin {
+                let size = match parms.scheme.scheme {
+                    TpmAlgId::Hmac => parms.scheme.hash_alg.digest_size(),
+                    _ => name_alg.digest_size(),
+                };
+                bits.size = size as u16;
+                rand.fill(&mut bits.buffer[..size]);
+            }
+            sensitive.sensitive = TpmuSensitiveComposite::Bits(bits);
+        }
+        TpmuPublicParms::SymCipher(parms) => {
+            let mut key = Tpm2bSymKey::from_slice(data.as_slice())?;
+            if data_origin {
+                let size = parms.sym.key_bits as usize / 8;
+                key.size = size as u16;
+                rand.fill(&mut key.buffer[..size]);
+            }
+            sensitive.sensitive = TpmuSensitiveComposite::Sym(key);
+        }
+        TpmuPublicParms::Unknown => return Err(TpmError::new(TpmRc::Type)),
+    }
+
+    // Storage keys get a seed for protecting their children. Keyed hash and
+    // symmetric objects get one to obfuscate the unique value, which would
+    // otherwise be a plain hash of the secret.
+    let storage = public.has_attributes(TPMA_OBJECT_RESTRICTED | TPMA_OBJECT_DECRYPT);
+    let symmetric = matches!(
+        public.parameters,
+        TpmuPublicParms::KeyedHash(_) | TpmuPublicParms::SymCipher(_)
+    );
+    if storage || symmetric {
+        let size = name_alg.digest_size();
+        sensitive.seed_value.size = size as u16;
+        rand.fill(&mut sensitive.seed_value.buffer[..size]);
+    }
+
+    if symmetric {
+        let secret = match &sensitive.sensitive {
+            TpmuSensitiveComposite::Bits(bits) => bits.as_slice(),
+            TpmuSensitiveComposite::Sym(key) => key.as_slice(),
+            _ => &[],
+        };
+        let unique = hash(name_alg, &[sensitive.seed_value.as_slice(), secret])?;
+        public.unique = match public.parameters {
+            TpmuPublicParms::KeyedHash(_) => TpmuPublicId::KeyedHash(unique),
+            _ => TpmuPublicId::SymCipher(unique),
+        };
+    }
+
+    Ok(())
+}
+
+// The data covered by the creation ticket, and its digest.
+pub fn creation_data(
+    tpm: &TpmInstance,
+    public: &TpmtPublic,
+    parent_name_alg: TpmAlgId,
+    parent_name: &Tpm2bName,
+    parent_qualified_name: &Tpm2bName,
+    outside_info: &Tpm2bData,
+    creation_pcr: &TpmlPcrSelection,
+) -> Result<(TpmsCreationData, Tpm2bDigest), TpmError> {
+    let mut pcr_select = *creation_pcr;
+    let pcr_digest = tpm.pcr_digest(public.name_alg, &mut pcr_select)?;
+    let creation_data = TpmsCreationData {
+        pcr_select,
+        pcr_digest,
+        locality: TPMA_LOCALITY_ZERO,
+        parent_name_alg,
+        parent_name: *parent_name,
+        parent_qualified_name: *parent_qualified_name,
+        outside_info: *outside_info,
+    };
+
+    let mut buffer = [0u8; MAX_CREATION_DATA_SIZE];
+    let size = marshal_tpms_creation_data(&mut buffer, &creation_data)?;
+    let creation_hash = hash(public.name_alg, &[&buffer[..size]])?;
+
+    Ok((creation_data, creation_hash))
+}
+
+// The symmetric algorithm a storage parent protects its children with.
+pub(crate) fn parent_symmetric(parent: &TpmtPublic) -> Result<TpmtSymDefObject, TpmError> {
+    match &parent.parameters {
+        TpmuPublicParms::Rsa(parms) => Ok(parms.symmetric),
+        TpmuPublicParms::Ecc(parms) => Ok(parms.symmetric),
+        TpmuPublicParms::SymCipher(parms) => Ok(parms.sym),
+        _ => Err(TpmError::new(TpmRc::Type)),
+    }
+}
+
+// An outer wrap, as used for private areas, credentials and duplicated
+// objects. The data is encrypted in CFB mode with a key derived from a seed
+// and the protected object's Name, then protected by an HMAC over the
+// ciphertext and Name:
+//
+//   TPM2B_DIGEST(outerHMAC) || CFB(data)
+//
+// `enc` is encrypted in place and the outer HMAC is returned.
+pub(crate) fn outer_wrap(
+    hash_alg: TpmAlgId,
+    symmetric: &TpmtSymDefObject,
+    seed: &[u8],
+    name: &Tpm2bName,
+    enc: &mut [u8],
+) -> Result<Tpm2bDigest, TpmError> {
+    outer_crypt(true, hash_alg, symmetric, seed, name, enc)?;
+    outer_hmac(hash_alg, seed, name, enc)
+}
+
+// Check the outer HMAC, then decrypt `enc` in place. A bad HMAC is an
+// Integrity error.
+pub(crate) fn outer_unwrap(
+    hash_alg: TpmAlgId,
+    symmetric: &TpmtSymDefObject,
+    seed: &[u8],
+    name: &Tpm2bName,
+    integrity: &Tpm2bDigest,
+    enc: &mut [u8],
+) -> Result<(), TpmError> {
+    let expected = outer_hmac(hash_alg, seed, name, enc)?;
+    if integrity.size != expected.size || !auth_equal(integrity.as_slice(), expected.as_slice()) {
+        return Err(TpmError::new(TpmRc::Integrity));
+    }
+
+    outer_crypt(false, hash_alg, symmetric, seed, name, enc)
+}
+
+fn outer_crypt(
+    encrypt: bool,
+    hash_alg: TpmAlgId,
+    symmetric: &TpmtSymDefObject,
+    seed: &[u8],
+    name: &Tpm2bName,
+    enc: &mut [u8],
+) -> Result<(), TpmError> {
+    let mut key = [0u8; MAX_SYM_KEY_BYTES];
+    let key = &mut key[..symmetric.key_bits as usize / 8];
+    kdfa(hash_alg, seed, STORAGE, name.as_slice(), &[], key)?;
+    let iv = [0u8; MAX_SYM_BLOCK_SIZE];
+    let iv = &iv[..sym::block_size(symmetric.algorithm)];
+    match encrypt {
+        true => sym::cfb_encrypt(symmetric.algorithm, key, iv, enc),
+        false => sym::cfb_decrypt(symmetric.algorithm, key, iv, enc),
+    }
+}
+
+fn outer_hmac(
+    hash_alg: TpmAlgId,
+    seed: &[u8],
+    name: &Tpm2bName,
+    enc: &[u8],
+) -> Result<Tpm2bDigest, TpmError> {
+    let mut key = [0u8; MAX_DIGEST_SIZE];
+    let key = &mut key[..hash_alg.digest_size()];
+    kdfa(hash_alg, seed, INTEGRITY, &[], &[], key)?;
+
+    hmac(hash_alg, key, &[enc, name.as_slice()])
+}
+
+// A private area is the marshaled TPM2B_SENSITIVE in an outer wrap with the
+// parent's seed.
+pub fn sensitive_to_private(
+    parent: &Object,
+    name: &Tpm2bName,
+    sensitive: &TpmtSensitive,
+) -> Result<Tpm2bPrivate, TpmError> {
+    let hash_alg = parent.public.name_alg;
+    let start = 2 + hash_alg.digest_size();
+
+    let mut private = Tpm2bPrivate::default();
+    let size = marshal_tpm2b_sensitive(&mut private.buffer[start..], sensitive)?;
+    let enc = &mut private.buffer[start..start + size];
+
+    let symmetric = parent_symmetric(&parent.public)?;
+    let seed = parent.sensitive.seed_value.as_slice();
+    let integrity = outer_wrap(hash_alg, &symmetric, seed, name, enc)?;
+
+    marshal_tpm2b(&mut private.buffer, &integrity)?;
+    private.size = (start + size) as u16;
+
+    Ok(private)
+}
+
+// Undo `sensitive_to_private`. A bad HMAC is an Integrity error and a
+// private area that doesn't decrypt to a well formed TPMT_SENSITIVE is a
+// Sensitive error.
+pub fn private_to_sensitive(
+    parent: &Object,
+    name: &Tpm2bName,
+    private: &Tpm2bPrivate,
+) -> Result<TpmtSensitive, TpmError> {
+    let buffer = private.as_slice();
+    let mut offset = 0;
+    let integrity: Tpm2bDigest =
+        unmarshal_tpm2b(buffer, &mut offset).map_err(|_| TpmError::new(TpmRc::Integrity))?;
+
+    let mut enc = [0u8; MAX_PRIVATE_SIZE];
+    let size = buffer.len() - offset;
+    let enc = &mut enc[..size];
+    enc.copy_from_slice(&buffer[offset..]);
+
+    let hash_alg = parent.public.name_alg;
+    let symmetric = parent_symmetric(&parent.public)?;
+    let seed = parent.sensitive.seed_value.as_slice();
+    outer_unwrap(hash_alg, &symmetric, seed, name, &integrity, enc)?;
+
+    let mut offset = 0;
+    let sensitive =
+        unmarshal_tpm2b_sensitive(enc, &mut offset).map_err(|_| TpmError::new(TpmRc::Sensitive))?;
+    if offset != size {
+        return Err(TpmError::new(TpmRc::Sensitive));
+    }
+
+    Ok(sensitive)
+}
+
+// Check that a loaded sensitive area belongs with its public area.
+pub fn validate_keys(public: &TpmtPublic, sensitive: &TpmtSensitive) -> Result<(), TpmError> {
+    if public.object_type() != sensitive.sensitive_type() {
+        return Err(TpmError::new(TpmRc::Type));
+    }
+
+    match (&public.parameters, &public.unique, &sensitive.sensitive) {
+        (TpmuPublicParms::Rsa(parms), TpmuPublicId::Rsa(n), TpmuSensitiveComposite::Rsa(p)) => {
+            rsa::validate_key(parms.key_bits, parms.exponent, n, p)
+        }
+        (TpmuPublicParms::Ecc(parms), TpmuPublicId::Ecc(q), TpmuSensitiveComposite::Ecc(d)) => {
+            ecc::validate_key(parms.curve_id, d, q)
+        }
+        (_, TpmuPublicId::KeyedHash(unique), TpmuSensitiveComposite::Bits(bits)) => {
+            symmetric_binding(public, sensitive, unique, bits.as_slice())
+        }
+        (_, TpmuPublicId::SymCipher(unique), TpmuSensitiveComposite::Sym(key)) => {
+            symmetric_binding(public, sensitive, unique, key.as_slice())
+        }
+        _ => Err(TpmError::new(TpmRc::Type)),
+    }
+}
+
+// The unique field of a symmetric object is H(seedValue || secret).
+fn symmetric_binding(
+    public: &TpmtPublic,
+    sensitive: &TpmtSensitive,
+    unique: &Tpm2bDigest,
+    secret: &[u8],
+) -> Result<(), TpmError> {
+    let expected = hash(public.name_alg, &[sensitive.seed_value.as_slice(), secret])?;
+    if expected.as_slice() != unique.as_slice() {
+        return Err(TpmError::new(TpmRc::Binding));
+    }
+
+    Ok(())
+}
+
+// The stored form of an object outside the TPM: the public area, the
+// sensitive area (empty for a public-only object) and the Qualified Name,
+// which can't be recomputed without the parent.
+pub fn marshal_object(buffer: &mut [u8], object: &Object) -> Result<usize, TpmError> {
+    let mut offset = marshal_tpm2b_public(buffer, &object.public)?;
+    offset += match object.sensitive.sensitive_type() {
+        TpmAlgId::Unknown => marshal_u16(&mut buffer[offset..], 0)?,
+        _ => marshal_tpm2b_sensitive(&mut buffer[offset..], &object.sensitive)?,
+    };
+    offset += marshal_tpm2b(&mut buffer[offset..], &object.qualified_name)?;
+
+    Ok(offset)
+}
+
+pub fn unmarshal_object(
+    buffer: &[u8],
+    offset: &mut usize,
+    hierarchy: Hierarchy,
+) -> Result<Object, TpmError> {
+    let public = unmarshal_tpm2b_public(buffer, offset)?;
+    let sensitive = unmarshal_tpm2b_sensitive_optional(buffer, offset)?.unwrap_or_default();
+    let qualified_name = unmarshal_tpm2b(buffer, offset)?;
+
+    Ok(Object {
+        public,
+        sensitive,
+        name: public_name(&public)?,
+        qualified_name,
+        hierarchy,
+    })
+}
+
+// Look up the object behind a handle that has already been checked as
+// loaded.
+pub(crate) fn loaded_object(tpm: &TpmInstance, handle: TpmHandle) -> Result<Object, TpmError> {
+    match tpm.object_get(handle) {
+        Some(object) => Ok(*object),
+        None if tpm.sequence_get(handle).is_some() => Err(TpmError::handle(TpmRc::Sequence, 1)),
+        None => Err(TpmError::handle(TpmRc::ReferenceH0, 1)),
+    }
+}
+
+pub fn tpm2_create(tpm: &mut TpmInstance, args: &CreateArgs) -> Result<CreateResponse, TpmError> {
+    let parent = loaded_object(tpm, args.parent_handle)?;
+    if !is_storage_parent(&parent.public) {
+        return Err(TpmError::handle(TpmRc::Type, 1));
+    }
+
+    let mut public = args.in_public;
+    create_checks(Some(&parent.public), &public, &args.in_sensitive)?;
+
+    let mut sensitive = TpmtSensitive {
+        auth_value: Tpm2bAuth::from_slice(trim_trailing_zeros(
+            args.in_sensitive.user_auth.as_slice(),
+        ))?,
+        ..Default::default()
+    };
+    let mut rand = PlatformRandom {
+        get_random: tpm.platform.get_random,
+    };
+    generate_object(
+        &mut public,
+        &mut sensitive,
+        &args.in_sensitive.data,
+        &mut rand,
+    )?;
+    let name = public_name(&public)?;
+
+    let (creation_data, creation_hash) = creation_data(
+        tpm,
+        &public,
+        parent.public.name_alg,
+        &parent.name,
+        &parent.qualified_name,
+        &args.outside_info,
+        &args.creation_pcr,
+    )?;
+    let creation_ticket = tpm.creation_ticket(parent.hierarchy, &name, &creation_hash)?;
+
+    let out_private = sensitive_to_private(&parent, &name, &sensitive)?;
+
+    Ok(CreateResponse {
+        out_private,
+        out_public: public,
+        creation_data,
+        creation_hash,
+        creation_ticket,
+    })
+}
+
+pub fn tpm2_load(tpm: &mut TpmInstance, args: &LoadArgs) -> Result<LoadResponse, TpmError> {
+    let parent = loaded_object(tpm, args.parent_handle)?;
+    if !is_storage_parent(&parent.public) {
+        return Err(TpmError::handle(TpmRc::Type, 1));
+    }
+
+    let public = &args.in_public;
+    if !public.name_alg.is_hash() {
+        return Err(TpmError::parameter(TpmRc::Hash, 2));
+    }
+    public_attributes_validation(Some(&parent.public), public).map_err(|e| e.with_parameter(2))?;
+    scheme_checks(public).map_err(|e| e.with_parameter(2))?;
+
+    tpm.object_free_slot()?;
+
+    let name = public_name(public)?;
+    let sensitive =
+        private_to_sensitive(&parent, &name, &args.in_private).map_err(|e| e.with_parameter(1))?;
+    validate_keys(public, &sensitive).map_err(|e| e.with_parameter(2))?;
+
+    let object_handle = tpm.object_load(Object {
+        public: *public,
+        sensitive,
+        name,
+        qualified_name: qualified_name(parent.qualified_name.as_slice(), public, &name)?,
+        hierarchy: parent.hierarchy,
+    })?;
+
+    Ok(LoadResponse {
+        object_handle,
+        name,
+    })
+}
+
+// Create an object and load it in one go. The parent can be a hierarchy,
+// in which case this makes a primary object, a storage parent, or a
+// derivation parent.
+pub fn tpm2_create_loaded(
+    tpm: &mut TpmInstance,
+    args: &CreateLoadedArgs,
+) -> Result<CreateLoadedResponse, TpmError> {
+    if let Some(hierarchy) = Hierarchy::from_handle(args.parent_handle) {
+        let mut public =
+            unmarshal_template(&args.in_public, None).map_err(|e| e.with_parameter(2))?;
+        create_checks(None, &public, &args.in_sensitive)?;
+        tpm.object_free_slot()?;
+
+        let sensitive = generate_primary(tpm, hierarchy, &mut public, &args.in_sensitive)?;
+        let name = public_name(&public)?;
+        let object_handle = tpm.object_load(Object {
+            public,
+            sensitive,
+            name,
+            qualified_name: qualified_name(&hierarchy.handle().to_be_bytes(), &public, &name)?,
+            hierarchy,
+        })?;
+
+        // A primary object is recreated from its template rather than
+        // reloaded, so it has no private area.
+        return Ok(CreateLoadedResponse {
+            object_handle,
+            out_public: public,
+            name,
+            ..Default::default()
+        });
+    }
+
+    let parent = loaded_object(tpm, args.parent_handle)?;
+    if is_derivation_parent(&parent.public) {
+        return create_derived(tpm, &parent, args);
+    }
+    if !is_storage_parent(&parent.public) {
+        return Err(TpmError::handle(TpmRc::Type, 1));
+    }
+
+    let mut public = unmarshal_template(&args.in_public, None).map_err(|e| e.with_parameter(2))?;
+    create_checks(Some(&parent.public), &public, &args.in_sensitive)?;
+    tpm.object_free_slot()?;
+
+    let mut sensitive = TpmtSensitive {
+        auth_value: Tpm2bAuth::from_slice(trim_trailing_zeros(
+            args.in_sensitive.user_auth.as_slice(),
+        ))?,
+        ..Default::default()
+    };
+    let mut rand = PlatformRandom {
+        get_random: tpm.platform.get_random,
+    };
+    generate_object(
+        &mut public,
+        &mut sensitive,
+        &args.in_sensitive.data,
+        &mut rand,
+    )?;
+    let name = public_name(&public)?;
+    let out_private = sensitive_to_private(&parent, &name, &sensitive)?;
+
+    let object_handle = tpm.object_load(Object {
+        public,
+        sensitive,
+        name,
+        qualified_name: qualified_name(parent.qualified_name.as_slice(), &public, &name)?,
+        hierarchy: parent.hierarchy,
+    })?;
+
+    Ok(CreateLoadedResponse {
+        object_handle,
+        out_private,
+        out_public: public,
+        name,
+    })
+}
+
+// The key of a derived object comes from a KDF keyed with the parent's
+// secret, over the label and context the template carries in place of its
+// unique field. The caller can't provide the secret, so sensitiveDataOrigin
+// must be clear and there must be no sensitive data.
+fn create_derived(
+    tpm: &mut TpmInstance,
+    parent: &Object,
+    args: &CreateLoadedArgs,
+) -> Result<CreateLoadedResponse, TpmError> {
+    let mut derive = TpmsDerive::default();
+    let mut public =
+        unmarshal_template(&args.in_public, Some(&mut derive)).map_err(|e| e.with_parameter(2))?;
+
+    if !public.name_alg.is_hash() {
+        return Err(TpmError::parameter(TpmRc::Hash, 2));
+    }
+    if public.has_attributes(TPMA_OBJECT_SENSITIVE_DATA_ORIGIN) {
+        return Err(TpmError::parameter(TpmRc::Attributes, 2));
+    }
+    public_attributes_validation(Some(&parent.public), &public).map_err(|e| e.with_parameter(2))?;
+    scheme_checks(&public).map_err(|e| e.with_parameter(2))?;
+
+    let user_auth = trim_trailing_zeros(args.in_sensitive.user_auth.as_slice());
+    if user_auth.len() > public.name_alg.digest_size() {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+    if !args.in_sensitive.data.is_empty() {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+
+    match &public.parameters {
+        TpmuPublicParms::Rsa(parms) if !rsa::is_valid_exponent(parms.exponent) => {
+            return Err(TpmError::parameter(TpmRc::Range, 2));
+        }
+        TpmuPublicParms::Ecc(parms) if ecc::curve_params(parms.curve_id).is_none() => {
+            return Err(TpmError::parameter(TpmRc::Curve, 2));
+        }
+        _ => (),
+    }
+
+    tpm.object_free_slot()?;
+
+    let kdf_hash = match &parent.public.parameters {
+        TpmuPublicParms::KeyedHash(parms) => parms.scheme.hash_alg,
+        _ => return Err(TpmError::handle(TpmRc::Type, 1)),
+    };
+    let secret = match &parent.sensitive.sensitive {
+        TpmuSensitiveComposite::Bits(bits) => bits.as_slice(),
+        _ => return Err(TpmError::handle(TpmRc::Type, 1)),
+    };
+    let mut rand = KdfRandom::new(
+        kdf_hash,
+        secret,
+        derive.label.as_slice(),
+        derive.context.as_slice(),
+        &[],
+    );
+
+    let mut sensitive = TpmtSensitive {
+        auth_value: Tpm2bAuth::from_slice(user_auth)?,
+        ..Default::default()
+    };
+    generate_object(
+        &mut public,
+        &mut sensitive,
+        &args.in_sensitive.data,
+        &mut rand,
+    )?;
+    let name = public_name(&public)?;
+
+    let object_handle = tpm.object_load(Object {
+        public,
+        sensitive,
+        name,
+        qualified_name: qualified_name(parent.qualified_name.as_slice(), &public, &name)?,
+        hierarchy: parent.hierarchy,
+    })?;
+
+    // Like a primary, a derived object is derived again whenever it's
+    // needed, so no private area is returned.
+    Ok(CreateLoadedResponse {
+        object_handle,
+        out_public: public,
+        name,
+        ..Default::default()
+    })
+}
+
+// Return the data held by a sealed data object, which is a keyed hash
+// object that can't sign or decrypt.
+pub fn tpm2_unseal(tpm: &mut TpmInstance, args: &UnsealArgs) -> Result<UnsealResponse, TpmError> {
+    let object = loaded_object(tpm, args.item_handle)?;
+
+    let out_data = match &object.sensitive.sensitive {
+        TpmuSensitiveComposite::Bits(bits) => *bits,
+        _ => return Err(TpmError::handle(TpmRc::Type, 1)),
+    };
+    if object.public.has_attributes(TPMA_OBJECT_SIGN_ENCRYPT)
+        || object.public.has_attributes(TPMA_OBJECT_DECRYPT)
+        || object.public.has_attributes(TPMA_OBJECT_RESTRICTED)
+    {
+        return Err(TpmError::handle(TpmRc::Attributes, 1));
+    }
+
+    Ok(UnsealResponse { out_data })
+}
+
+// Load an object from outside the TPM, public-only or with its sensitive
+// area. Either way it goes in the null hierarchy, and one with a sensitive
+// area mustn't claim to be something the TPM made.
+pub fn tpm2_load_external(
+    tpm: &mut TpmInstance,
+    args: &LoadExternalArgs,
+) -> Result<LoadExternalResponse, TpmError> {
+    let hierarchy = match Hierarchy::from_handle(args.hierarchy) {
+        Some(hierarchy) => hierarchy,
+        None => return Err(TpmError::parameter(TpmRc::Value, 3)),
+    };
+    if hierarchy != Hierarchy::Null {
+        return Err(TpmError::parameter(TpmRc::Hierarchy, 3));
+    }
+
+    let public = &args.in_public;
+    let sensitive = match &args.in_private {
+        Some(sensitive) => {
+            if public.has_attributes(TPMA_OBJECT_FIXED_TPM)
+                || public.has_attributes(TPMA_OBJECT_FIXED_PARENT)
+                || public.has_attributes(TPMA_OBJECT_RESTRICTED)
+            {
+                return Err(TpmError::parameter(TpmRc::Attributes, 2));
+            }
+            if !public.name_alg.is_hash() {
+                return Err(TpmError::parameter(TpmRc::Hash, 2));
+            }
+            let auth = trim_trailing_zeros(sensitive.auth_value.as_slice());
+            if auth.len() > public.name_alg.digest_size() {
+                return Err(TpmError::parameter(TpmRc::Size, 1));
+            }
+            *sensitive
+        }
+        // A public-only object has no secret and no authValue.
+        None => TpmtSensitive::default(),
+    };
+
+    public_attributes_validation(None, public).map_err(|e| e.with_parameter(2))?;
+    scheme_checks(public).map_err(|e| e.with_parameter(2))?;
+    match (&args.in_private, &public.parameters, &public.unique) {
+        (Some(_), _, _) => validate_keys(public, &sensitive).map_err(|e| e.with_parameter(2))?,
+        // There's no private key to check a public-only ECC key against, but
+        // its point at least has to be on the curve.
+        (None, TpmuPublicParms::Ecc(parms), TpmuPublicId::Ecc(point)) => {
+            ecc::validate_point(parms.curve_id, point).map_err(|e| e.with_parameter(2))?
+        }
+        _ => (),
+    }
+
+    tpm.object_free_slot()?;
+
+    let name = public_name(public)?;
+    let object_handle = tpm.object_load(Object {
+        public: *public,
+        sensitive,
+        name,
+        qualified_name: qualified_name(&hierarchy.handle().to_be_bytes(), public, &name)?,
+        hierarchy,
+    })?;
+
+    Ok(LoadExternalResponse {
+        object_handle,
+        name,
+    })
+}
+
+pub fn tpm2_read_public(
+    tpm: &mut TpmInstance,
+    args: &ReadPublicArgs,
+) -> Result<ReadPublicResponse, TpmError> {
+    let object = loaded_object(tpm, args.object_handle)?;
+
+    Ok(ReadPublicResponse {
+        out_public: object.public,
+        name: object.name,
+        qualified_name: object.qualified_name,
+    })
+}
+
+// Wrap a copy of the object's sensitive area with a new authValue. The
+// loaded object keeps its old one. The parent has to be the object's actual
+// parent, which is checked through the Qualified Name.
+pub fn tpm2_object_change_auth(
+    tpm: &mut TpmInstance,
+    args: &ObjectChangeAuthArgs,
+) -> Result<ObjectChangeAuthResponse, TpmError> {
+    let object = loaded_object(tpm, args.object_handle)?;
+    let parent = match tpm.object_get(args.parent_handle) {
+        Some(parent) => *parent,
+        None => return Err(TpmError::handle(TpmRc::ReferenceH0, 2)),
+    };
+
+    let new_auth = trim_trailing_zeros(args.new_auth.as_slice());
+    if new_auth.len() > object.public.name_alg.digest_size() {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+
+    let expected = qualified_name(
+        parent.qualified_name.as_slice(),
+        &object.public,
+        &object.name,
+    )?;
+    if expected.as_slice() != object.qualified_name.as_slice() || !is_storage_parent(&parent.public)
+    {
+        return Err(TpmError::handle(TpmRc::Type, 2));
+    }
+
+    let sensitive = TpmtSensitive {
+        auth_value: Tpm2bAuth::from_slice(new_auth)?,
+        ..object.sensitive
+    };
+    let out_private = sensitive_to_private(&parent, &object.name, &sensitive)?;
+
+    Ok(ObjectChangeAuthResponse { out_private })
+}
+
+// Flush a transient object or sequence, or a session.
+pub fn tpm2_flush_context(tpm: &mut TpmInstance, args: &FlushContextArgs) -> Result<(), TpmError> {
+    match TpmHt::from(args.flush_handle) {
+        TpmHt::Transient => tpm.object_flush(args.flush_handle),
+        TpmHt::HmacSession | TpmHt::PolicySession => tpm.session_flush(args.flush_handle),
+        _ => Err(TpmError::parameter(TpmRc::Value, 1)),
+    }
+}
+
+impl TpmInstance {
+    pub(crate) fn object_get(&self, handle: TpmHandle) -> Option<&Object> {
+        match TpmHt::from(handle) {
+            TpmHt::Transient => {
+                let slot = (handle - TRANSIENT_FIRST) as usize;
+                self.objects.get(slot)?.as_ref()
+            }
+            TpmHt::Persistent => self.persistent_get(handle),
+            _ => None,
+        }
+    }
+
+    // Sequences share the transient handles with objects.
+    pub(crate) fn sequence_get(&self, handle: TpmHandle) -> Option<&Sequence> {
+        match TpmHt::from(handle) {
+            TpmHt::Transient => {
+                let slot = (handle - TRANSIENT_FIRST) as usize;
+                self.sequences.get(slot)?.as_ref()
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn sequence_get_mut(&mut self, handle: TpmHandle) -> Option<&mut Sequence> {
+        match TpmHt::from(handle) {
+            TpmHt::Transient => {
+                let slot = (handle - TRANSIENT_FIRST) as usize;
+                self.sequences.get_mut(slot)?.as_mut()
+            }
+            _ => None,
+        }
+    }
+
+    // Remove a sequence from its slot, which flushes it.
+    pub(crate) fn sequence_take(&mut self, handle: TpmHandle) -> Option<Sequence> {
+        match TpmHt::from(handle) {
+            TpmHt::Transient => {
+                let slot = (handle - TRANSIENT_FIRST) as usize;
+                self.sequences.get_mut(slot)?.take()
+            }
+            _ => None,
+        }
+    }
+
+    // Whether a transient handle refers to an object or a sequence.
+    pub(crate) fn transient_loaded(&self, handle: TpmHandle) -> bool {
+        TpmHt::from(handle) == TpmHt::Transient
+            && (self.object_get(handle).is_some() || self.sequence_get(handle).is_some())
+    }
+
+    pub(crate) fn object_free_slot(&self) -> Result<usize, TpmError> {
+        let free = |slot: &usize| self.objects[*slot].is_none() && self.sequences[*slot].is_none();
+        match (0..MAX_LOADED_OBJECTS).find(free) {
+            Some(slot) => Ok(slot),
+            None => Err(TpmError::new(TpmRc::ObjectMemory)),
+        }
+    }
+
+    // Put an object in a free transient slot and return its handle.
+    pub(crate) fn object_load(&mut self, object: Object) -> Result<TpmHandle, TpmError> {
+        let slot = self.object_free_slot()?;
+        self.objects[slot] = Some(object);
+
+        Ok(TRANSIENT_FIRST + slot as TpmHandle)
+    }
+
+    pub(crate) fn sequence_load(&mut self, sequence: Sequence) -> Result<TpmHandle, TpmError> {
+        let slot = self.object_free_slot()?;
+        self.sequences[slot] = Some(sequence);
+
+        Ok(TRANSIENT_FIRST + slot as TpmHandle)
+    }
+
+    // Flush a transient object or sequence.
+    pub(crate) fn object_flush(&mut self, handle: TpmHandle) -> Result<(), TpmError> {
+        if !self.transient_loaded(handle) {
+            return Err(TpmError::parameter(TpmRc::Handle, 1));
+        }
+
+        let slot = (handle - TRANSIENT_FIRST) as usize;
+        self.objects[slot] = None;
+        self.sequences[slot] = None;
+
+        Ok(())
+    }
+
+    pub(crate) fn object_slots_free(&self) -> usize {
+        (0..MAX_LOADED_OBJECTS)
+            .filter(|slot| self.objects[*slot].is_none() && self.sequences[*slot].is_none())
+            .count()
+    }
+
+    // Flush every transient object belonging to `hierarchy`. Sequences
+    // outlive the key that started them.
+    pub(crate) fn object_flush_hierarchy(&mut self, hierarchy: Hierarchy) {
+        for slot in self.objects.iter_mut() {
+            if matches!(slot, Some(object) if object.hierarchy == hierarchy) {
+                *slot = None;
+            }
+        }
+    }
+
+    pub(crate) fn object_flush_all(&mut self) {
+        self.objects = [None; MAX_LOADED_OBJECTS];
+        self.sequences = core::array::from_fn(|_| None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::from_hex;
+
+    // A keyedhash object with nameAlg SHA-256, fixedTPM | fixedParent |
+    // userWithAuth | noDA, an empty policy, scheme NULL and a unique of 32
+    // 0xAA bytes. The expected values are from an independent implementation.
+    const PUBLIC: &str = "0008000b00000052000000100020\
+                          aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const NAME: &str = "000b585611f4b3fc8004a13442196efda74b14b55122cd9dd0e4d522c70e7723cf93";
+    const OWNER_QN: &str = "000b806d3f19f15f9d22850adde753cb643581ab5c91493266f6bf25fff1d9cd5ef6";
+
+    fn public() -> TpmtPublic {
+        let buffer = from_hex::<46>(PUBLIC);
+        let mut offset = 0;
+        let public = unmarshal_tpmt_public(&buffer, &mut offset).unwrap();
+        assert_eq!(offset, buffer.len());
+        public
+    }
+
+    #[test]
+    fn name() {
+        let name = public_name(&public()).unwrap();
+        assert_eq!(name.as_slice(), from_hex::<34>(NAME));
+    }
+
+    #[test]
+    fn qualified_name_under_owner() {
+        let public = public();
+        let name = public_name(&public).unwrap();
+        let owner = (TpmRh::Owner as u32).to_be_bytes();
+        let qn = qualified_name(&owner, &public, &name).unwrap();
+        assert_eq!(qn.as_slice(), from_hex::<34>(OWNER_QN));
+    }
+
+    #[test]
+    fn outer_wrap_known_answer() {
+        let symmetric = TpmtSymDefObject {
+            algorithm: TpmAlgId::Aes,
+            key_bits: 128,
+            mode: TpmAlgId::Cfb,
+        };
+        let seed: [u8; 32] = core::array::from_fn(|i| 0x10 + i as u8);
+        let name = Tpm2bName::from_slice(&from_hex::<34>(NAME)).unwrap();
+        let mut enc = *b"sensitive area bytes";
+
+        let integrity = outer_wrap(TpmAlgId::Sha256, &symmetric, &seed, &name, &mut enc).unwrap();
+        assert_eq!(
+            enc,
+            from_hex::<20>("49eaebcb5ab87bdb001852403176a795b08d9ec6")
+        );
+        assert_eq!(
+            integrity.as_slice(),
+            from_hex::<32>("be0e23bb890b90af81b37ec5bef1f9fd3ba457ac8d0602bb00aa44e948f0ad51")
+        );
+
+        outer_unwrap(
+            TpmAlgId::Sha256,
+            &symmetric,
+            &seed,
+            &name,
+            &integrity,
+            &mut enc,
+        )
+        .unwrap();
+        assert_eq!(&enc, b"sensitive area bytes");
+    }
+
+    // The HMAC covers the Name, so the blob can't be moved to another object.
+    #[test]
+    fn outer_unwrap_checks_name() {
+        let symmetric = TpmtSymDefObject {
+            algorithm: TpmAlgId::Aes,
+            key_bits: 128,
+            mode: TpmAlgId::Cfb,
+        };
+        let seed = [7u8; 32];
+        let name = Tpm2bName::from_slice(&from_hex::<34>(NAME)).unwrap();
+        let mut enc = *b"sensitive area bytes";
+        let integrity = outer_wrap(TpmAlgId::Sha256, &symmetric, &seed, &name, &mut enc).unwrap();
+
+        let mut other = name;
+        other.buffer[2] ^= 1;
+        let result = outer_unwrap(
+            TpmAlgId::Sha256,
+            &symmetric,
+            &seed,
+            &other,
+            &integrity,
+            &mut enc,
+        );
+        assert!(matches!(result, Err(e) if e.rc == TpmRc::Integrity));
+    }
+}
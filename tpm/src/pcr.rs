@@ -0,0 +1,142 @@
+use crate::crypto::hash::*;
+use crate::tpm::*;
+use crate::types::*;
+
+// There's a single bank of PCRs, of SHA-256.
+pub(crate) const PCR_HASH_ALG: TpmAlgId = TpmAlgId::Sha256;
+const PCR_DIGEST_SIZE: usize = 32;
+
+// PCRs 17 to 22 belong to a dynamic root of trust, which only localities 2
+// to 4 can extend. They're all ones until one is launched, which can't
+// happen at locality 0.
+const DRTM_PCR_FIRST: usize = 17;
+const DRTM_PCR_LAST: usize = 22;
+
+pub struct PcrState {
+    pub(crate) values: [[u8; PCR_DIGEST_SIZE]; IMPLEMENTATION_PCR],
+    // Counts extends since the last TPM Reset or Restart, so a reader can
+    // tell whether PCRs changed between two reads.
+    pub(crate) update_counter: u32,
+}
+
+impl Default for PcrState {
+    fn default() -> PcrState {
+        let mut pcr = PcrState {
+            values: [[0; PCR_DIGEST_SIZE]; IMPLEMENTATION_PCR],
+            update_counter: 0,
+        };
+        pcr.reset();
+        pcr
+    }
+}
+
+impl PcrState {
+    pub(crate) fn reset(&mut self) {
+        for (i, value) in self.values.iter_mut().enumerate() {
+            let fill = match i {
+                DRTM_PCR_FIRST..=DRTM_PCR_LAST => 0xFF,
+                _ => 0,
+            };
+            value.fill(fill);
+        }
+        self.update_counter = 0;
+    }
+}
+
+// The PCRs `selection` picks, in ascending order
+fn selected(selection: &TpmsPcrSelection) -> impl Iterator<Item = usize> + '_ {
+    let size = selection.size_of_select as usize;
+    (0..size * 8)
+        .filter(move |i| selection.pcr_select[i / 8] & (1 << (i % 8)) != 0)
+        .filter(|i| *i < IMPLEMENTATION_PCR)
+}
+
+// Drop what `selection` picks from banks the TPM doesn't have, so it only
+// covers PCRs that exist.
+pub(crate) fn pcr_select_filter(selection: &mut TpmlPcrSelection) {
+    for s in selection.pcr_selections[..selection.count as usize].iter_mut() {
+        if s.hash != PCR_HASH_ALG {
+            s.pcr_select = [0; PCR_SELECT_MAX];
+        }
+    }
+}
+
+impl TpmInstance {
+    // The digest, made with `hash_alg`, of the PCRs `selection` picks, in
+    // the order it picks them. The selection is filtered first, as it's
+    // usually reported along with the digest. With no hash algorithm the
+    // digest is empty.
+    pub(crate) fn pcr_digest(
+        &self,
+        hash_alg: TpmAlgId,
+        selection: &mut TpmlPcrSelection,
+    ) -> Result<Tpm2bDigest, TpmError> {
+        pcr_select_filter(selection);
+        if hash_alg == TpmAlgId::Null {
+            return Ok(Tpm2bDigest::default());
+        }
+
+        let mut state = HashState::new(hash_alg)?;
+        for s in selection.pcr_selections[..selection.count as usize].iter() {
+            for i in selected(s) {
+                state.update(&self.pcr.values[i]);
+            }
+        }
+
+        Ok(state.finish())
+    }
+}
+
+// PCR[i] = H(PCR[i] || digest) for the digest of each bank. Digests for
+// banks the TPM doesn't have are ignored, and extending TPM_RH_NULL does
+// nothing.
+pub fn tpm2_pcr_extend(tpm: &mut TpmInstance, args: &PcrExtendArgs) -> Result<(), TpmError> {
+    if TpmRh::from(args.pcr_handle) == TpmRh::Null {
+        return Ok(());
+    }
+
+    let i = args.pcr_handle as usize;
+    if (DRTM_PCR_FIRST..=DRTM_PCR_LAST).contains(&i) {
+        return Err(TpmError::new(TpmRc::Locality));
+    }
+
+    let digests = &args.digests.digests[..args.digests.count as usize];
+    for digest in digests.iter().filter(|d| d.hash_alg == PCR_HASH_ALG) {
+        let value = &mut tpm.pcr.values[i];
+        let extended = hash(PCR_HASH_ALG, &[value.as_slice(), digest.digest.as_slice()])?;
+        value.copy_from_slice(extended.as_slice());
+    }
+    tpm.pcr.update_counter = tpm.pcr.update_counter.wrapping_add(1);
+
+    Ok(())
+}
+
+// Read the selected PCRs, as many as fit in a TPML_DIGEST. The selection
+// that comes back says which ones those were.
+pub fn tpm2_pcr_read(
+    tpm: &mut TpmInstance,
+    args: &PcrReadArgs,
+) -> Result<PcrReadResponse, TpmError> {
+    let mut selection = args.pcr_selection_in;
+    pcr_select_filter(&mut selection);
+
+    let mut values = TpmlDigest::default();
+    for s in selection.pcr_selections[..selection.count as usize].iter_mut() {
+        let mut read = [0u8; PCR_SELECT_MAX];
+        for i in selected(s) {
+            if values.count as usize == values.digests.len() {
+                break;
+            }
+            values.digests[values.count as usize] = Tpm2bDigest::from_slice(&tpm.pcr.values[i])?;
+            values.count += 1;
+            read[i / 8] |= 1 << (i % 8);
+        }
+        s.pcr_select = read;
+    }
+
+    Ok(PcrReadResponse {
+        pcr_update_counter: tpm.pcr.update_counter,
+        pcr_selection_out: selection,
+        pcr_values: values,
+    })
+}
@@ -0,0 +1,228 @@
+use crate::hierarchy::Hierarchy;
+use crate::marshal::*;
+use crate::nv::*;
+use crate::object::*;
+use crate::tpm::*;
+use crate::types::*;
+
+// Persistent handles below this belong to the owner.
+pub const PLATFORM_PERSISTENT_FIRST: TpmHandle = 0x81800000;
+
+// Each NV slot holds the handle, the hierarchy and then the object. A slot
+// whose handle isn't a persistent one is free.
+const SLOT_HEADER_SIZE: usize = 8;
+
+// Persistent objects only live in NV. The TPM remembers which handle each
+// slot holds, and reads an object in when a command names it.
+pub struct PersistentState {
+    pub(crate) slots: [Option<(TpmHandle, Hierarchy)>; MAX_PERSISTENT_OBJECTS],
+
+    // Objects named by the handles of the command being executed.
+    pub(crate) loaded: [Option<(TpmHandle, Object)>; MAX_HANDLE_NUM],
+}
+
+impl Default for PersistentState {
+    fn default() -> PersistentState {
+        PersistentState {
+            slots: [None; MAX_PERSISTENT_OBJECTS],
+            loaded: [None; MAX_HANDLE_NUM],
+        }
+    }
+}
+
+fn slot_offset(slot: usize) -> usize {
+    NV_PERSISTENT_OBJECTS + slot * PERSISTENT_OBJECT_NV_SIZE
+}
+
+fn is_platform_persistent(handle: TpmHandle) -> bool {
+    handle >= PLATFORM_PERSISTENT_FIRST
+}
+
+impl TpmInstance {
+    // Find out which slots are in use. Only the slot headers are read.
+    pub(crate) fn persistent_restore(&mut self) -> Result<(), TpmError> {
+        for slot in 0..MAX_PERSISTENT_OBJECTS {
+            let mut header = [0u8; SLOT_HEADER_SIZE];
+            self.nv_read(slot_offset(slot), &mut header)?;
+
+            let mut offset = 0;
+            let handle = unmarshal_handle(&header, &mut offset)?;
+            let hierarchy = Hierarchy::from_handle(unmarshal_handle(&header, &mut offset)?);
+
+            self.persistent.slots[slot] = match (TpmHt::from(handle), hierarchy) {
+                (TpmHt::Persistent, Some(hierarchy)) => Some((handle, hierarchy)),
+                _ => None,
+            };
+        }
+
+        Ok(())
+    }
+
+    fn persistent_find(&self, handle: TpmHandle) -> Option<(usize, Hierarchy)> {
+        self.persistent
+            .slots
+            .iter()
+            .enumerate()
+            .find_map(|(slot, entry)| match entry {
+                Some((h, hierarchy)) if *h == handle => Some((slot, *hierarchy)),
+                _ => None,
+            })
+    }
+
+    pub(crate) fn persistent_get(&self, handle: TpmHandle) -> Option<&Object> {
+        self.persistent.loaded.iter().find_map(|entry| match entry {
+            Some((h, object)) if *h == handle => Some(object),
+            _ => None,
+        })
+    }
+
+    // Forget the objects read in for the previous command.
+    pub(crate) fn persistent_unload(&mut self) {
+        self.persistent.loaded = [None; MAX_HANDLE_NUM];
+    }
+
+    // Read a persistent object from NV for use by the current command.
+    pub(crate) fn persistent_load(&mut self, handle: TpmHandle) -> Result<(), TpmError> {
+        let (slot, hierarchy) = match self.persistent_find(handle) {
+            Some(entry) => entry,
+            None => return Err(TpmError::new(TpmRc::Handle)),
+        };
+        if !self.hierarchy_is_enabled(hierarchy) {
+            return Err(TpmError::new(TpmRc::Hierarchy));
+        }
+        if self.persistent_get(handle).is_some() {
+            return Ok(());
+        }
+
+        let mut buf = [0u8; PERSISTENT_OBJECT_NV_SIZE];
+        self.nv_read(slot_offset(slot), &mut buf)?;
+
+        // NV that no longer parses is beyond repair.
+        let mut offset = SLOT_HEADER_SIZE;
+        let object = unmarshal_object(&buf, &mut offset, hierarchy)
+            .map_err(|_| TpmError::new(TpmRc::Failure))?;
+
+        match self.persistent.loaded.iter_mut().find(|e| e.is_none()) {
+            Some(entry) => *entry = Some((handle, object)),
+            None => return Err(TpmError::new(TpmRc::Failure)),
+        }
+
+        Ok(())
+    }
+
+    fn persistent_store(&mut self, handle: TpmHandle, object: &Object) -> Result<(), TpmError> {
+        let slot = match self.persistent.slots.iter().position(|s| s.is_none()) {
+            Some(slot) => slot,
+            None => return Err(TpmError::new(TpmRc::NvSpace)),
+        };
+
+        let mut buf = [0u8; PERSISTENT_OBJECT_NV_SIZE];
+        let mut size = marshal_u32(&mut buf, handle)?;
+        size += marshal_u32(&mut buf[size..], object.hierarchy.handle())?;
+        size +=
+            marshal_object(&mut buf[size..], object).map_err(|_| TpmError::new(TpmRc::NvSpace))?;
+        self.nv_write(slot_offset(slot), &buf[..size])?;
+
+        self.persistent.slots[slot] = Some((handle, object.hierarchy));
+
+        Ok(())
+    }
+
+    fn persistent_evict(&mut self, handle: TpmHandle) -> Result<(), TpmError> {
+        let slot = match self.persistent_find(handle) {
+            Some((slot, _)) => slot,
+            None => return Err(TpmError::new(TpmRc::Handle)),
+        };
+
+        self.nv_write(slot_offset(slot), &[0u8; SLOT_HEADER_SIZE])?;
+        self.persistent.slots[slot] = None;
+        self.persistent_unload();
+
+        Ok(())
+    }
+
+    // Evict every persistent object belonging to `hierarchy`.
+    pub(crate) fn persistent_evict_hierarchy(
+        &mut self,
+        hierarchy: Hierarchy,
+    ) -> Result<(), TpmError> {
+        for slot in 0..MAX_PERSISTENT_OBJECTS {
+            if let Some((handle, h)) = self.persistent.slots[slot] {
+                if h == hierarchy {
+                    self.persistent_evict(handle)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Handles of all persistent objects, in ascending order.
+    pub(crate) fn persistent_handles(&self) -> ([TpmHandle; MAX_PERSISTENT_OBJECTS], usize) {
+        let mut handles = [0; MAX_PERSISTENT_OBJECTS];
+        let mut count = 0;
+        for (handle, _) in self.persistent.slots.iter().flatten() {
+            handles[count] = *handle;
+            count += 1;
+        }
+        handles[..count].sort_unstable();
+
+        (handles, count)
+    }
+
+    pub(crate) fn persistent_slots_free(&self) -> usize {
+        self.persistent.slots.iter().filter(|s| s.is_none()).count()
+    }
+}
+
+// Make a transient object persistent, or evict a persistent one. The owner
+// and the platform each have their own half of the persistent range.
+pub fn tpm2_evict_control(tpm: &mut TpmInstance, args: &EvictControlArgs) -> Result<(), TpmError> {
+    let persistent_handle = args.persistent_handle;
+    if TpmHt::from(persistent_handle) != TpmHt::Persistent {
+        return Err(TpmError::parameter(TpmRc::Value, 1));
+    }
+
+    let platform = TpmRh::from(args.auth) == TpmRh::Platform;
+    if is_platform_persistent(persistent_handle) != platform {
+        return Err(TpmError::parameter(TpmRc::Range, 1));
+    }
+
+    if TpmHt::from(args.object_handle) == TpmHt::Persistent {
+        if args.object_handle != persistent_handle {
+            return Err(TpmError::parameter(TpmRc::Handle, 1));
+        }
+        return tpm.persistent_evict(persistent_handle);
+    }
+
+    // A sequence belongs to no hierarchy it could be persisted in.
+    let object = match tpm.object_get(args.object_handle) {
+        Some(object) => *object,
+        None if tpm.sequence_get(args.object_handle).is_some() => {
+            return Err(TpmError::handle(TpmRc::Hierarchy, 2));
+        }
+        None => return Err(TpmError::handle(TpmRc::ReferenceH0, 2)),
+    };
+
+    let hierarchy_matches = match object.hierarchy {
+        Hierarchy::Platform => platform,
+        Hierarchy::Owner | Hierarchy::Endorsement => !platform,
+        Hierarchy::Null => false,
+    };
+    if !hierarchy_matches {
+        return Err(TpmError::handle(TpmRc::Hierarchy, 2));
+    }
+
+    // stClear objects don't survive a Restart, and public-only objects come
+    // from outside the TPM.
+    let public_only = object.sensitive.sensitive_type() == TpmAlgId::Unknown;
+    if object.public.has_attributes(TPMA_OBJECT_ST_CLEAR) || public_only {
+        return Err(TpmError::handle(TpmRc::Attributes, 2));
+    }
+
+    if tpm.persistent_find(persistent_handle).is_some() {
+        return Err(TpmError::new(TpmRc::NvDefined));
+    }
+
+    tpm.persistent_store(persistent_handle, &object)
+}
@@ -1,17 +1,177 @@
+use crate::types::NV_MEMORY_SIZE;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
 // TODO: This interface definition is very C-like. I know the Rust way is to
 // use traits, but it ended up a mess of generics and lifetimes. This seemed
 // easier. But ultimately we should fix this.
 #[derive(Clone, Copy)]
 pub struct TpmPlatform {
     pub log: fn(&str),
+    // Fills the buffer with entropy. Seeds and proofs are generated from
+    // this, so platforms must back it with a real entropy source.
+    pub get_random: fn(&mut [u8]),
+    // Read and write NV_MEMORY_SIZE bytes of non-volatile memory at a byte
+    // offset. Memory that was never written reads as zeros. Both return
+    // false if the memory can't be accessed.
+    pub nv_read: fn(usize, &mut [u8]) -> bool,
+    pub nv_write: fn(usize, &[u8]) -> bool,
+    // Milliseconds since some arbitrary point. It must not go backwards
+    // while the TPM is powered. Clock and Time advance with it.
+    pub get_time: fn() -> u64,
 }
 
 impl Default for TpmPlatform {
     fn default() -> TpmPlatform {
-        TpmPlatform { log: default_log }
+        TpmPlatform {
+            log: default_log,
+            get_random: default_get_random,
+            nv_read: default_nv_read,
+            nv_write: default_nv_write,
+            get_time: default_get_time,
+        }
     }
 }
 
-pub fn default_log(_msg: &str) {
-    ()
+pub fn default_log(_msg: &str) {}
+
+#[cfg(feature = "getrandom")]
+pub fn default_get_random(buf: &mut [u8]) {
+    getrandom::getrandom(buf).expect("Unable to get entropy from the OS");
+}
+
+// Seeds made from anything else would be predictable, so there's no
+// fallback.
+#[cfg(not(feature = "getrandom"))]
+pub fn default_get_random(_buf: &mut [u8]) {
+    panic!("No entropy source: the platform must provide get_random");
+}
+
+// There's no timer without a platform, so Clock only moves forward when
+// it's bumped after a power loss.
+pub fn default_get_time() -> u64 {
+    0
+}
+
+// The default platform's NV is RAM, so it only lasts as long as the process.
+// Every TPM on the default platform shares it.
+struct RamNv {
+    locked: AtomicBool,
+    image: UnsafeCell<[u8; NV_MEMORY_SIZE]>,
+}
+
+// The image is only touched while `locked` is held.
+unsafe impl Sync for RamNv {}
+
+static RAM_NV: RamNv = RamNv {
+    locked: AtomicBool::new(false),
+    image: UnsafeCell::new([0; NV_MEMORY_SIZE]),
+};
+
+impl RamNv {
+    fn access(&self, offset: usize, len: usize, f: impl FnOnce(&mut [u8])) -> bool {
+        let end = match offset.checked_add(len) {
+            Some(end) if end <= NV_MEMORY_SIZE => end,
+            _ => return false,
+        };
+
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        f(&mut unsafe { &mut *self.image.get() }[offset..end]);
+        self.locked.store(false, Ordering::Release);
+
+        true
+    }
+}
+
+pub fn default_nv_read(offset: usize, buf: &mut [u8]) -> bool {
+    RAM_NV.access(offset, buf.len(), |nv| buf.copy_from_slice(nv))
+}
+
+pub fn default_nv_write(offset: usize, data: &[u8]) -> bool {
+    RAM_NV.access(offset, data.len(), |nv| nv.copy_from_slice(data))
+}
+
+// A platform for unit tests. Each test thread gets NV of its own, so tests
+// running in parallel don't share it.
+#[cfg(test)]
+pub(crate) mod test {
+    extern crate std;
+
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::vec::Vec;
+
+    std::thread_local! {
+        static NV: RefCell<Vec<u8>> = RefCell::new(std::vec![0; NV_MEMORY_SIZE]);
+        static RANDOM: Cell<u64> = const { Cell::new(0) };
+    }
+
+    // Not random at all, which keeps failures reproducible.
+    fn get_random(buf: &mut [u8]) {
+        RANDOM.with(|counter| {
+            for byte in buf.iter_mut() {
+                let n = counter.get().wrapping_add(1);
+                counter.set(n);
+                *byte = (n.wrapping_mul(0x9E3779B97F4A7C15) >> 56) as u8;
+            }
+        })
+    }
+
+    fn nv_read(offset: usize, buf: &mut [u8]) -> bool {
+        NV.with(|nv| buf.copy_from_slice(&nv.borrow()[offset..offset + buf.len()]));
+        true
+    }
+
+    fn nv_write(offset: usize, data: &[u8]) -> bool {
+        NV.with(|nv| nv.borrow_mut()[offset..offset + data.len()].copy_from_slice(data));
+        true
+    }
+
+    pub(crate) fn platform() -> TpmPlatform {
+        TpmPlatform {
+            log: default_log,
+            get_random,
+            nv_read,
+            nv_write,
+            get_time: default_get_time,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A RAM NV of its own, rather than the default platform's
+    fn ram_nv() -> RamNv {
+        RamNv {
+            locked: AtomicBool::new(false),
+            image: UnsafeCell::new([0; NV_MEMORY_SIZE]),
+        }
+    }
+
+    #[test]
+    fn ram_nv_read_write() {
+        let nv = ram_nv();
+        assert!(nv.access(10, 3, |image| image.copy_from_slice(b"abc")));
+
+        let mut buf = [0u8; 5];
+        assert!(nv.access(9, 5, |image| buf.copy_from_slice(image)));
+        assert_eq!(&buf, b"\0abc\0");
+    }
+
+    // Memory that doesn't exist can't be accessed, rather than panicking.
+    #[test]
+    fn ram_nv_out_of_range() {
+        let nv = ram_nv();
+        assert!(nv.access(NV_MEMORY_SIZE - 1, 1, |_| ()));
+        assert!(!nv.access(NV_MEMORY_SIZE - 1, 2, |_| unreachable!()));
+        assert!(!nv.access(usize::MAX, 2, |_| unreachable!()));
+    }
 }
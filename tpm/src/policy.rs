@@ -0,0 +1,326 @@
+use crate::crypto::hash::*;
+use crate::marshal::*;
+use crate::session::*;
+use crate::tpm::*;
+use crate::types::*;
+
+// The policy session behind a handle that has already been checked.
+fn policy_session(tpm: &mut TpmInstance, handle: TpmHandle) -> Result<&mut Session, TpmError> {
+    match tpm.session_get_mut(handle) {
+        Some(session) => Ok(session),
+        None => Err(TpmError::handle(TpmRc::ReferenceH0, 1)),
+    }
+}
+
+// policyDigest_new = H_authHash(policyDigest_old || commandCode || data)
+fn policy_update(
+    session: &mut Session,
+    command_code: TpmCommandCode,
+    data: &[&[u8]],
+) -> Result<(), TpmError> {
+    let mut state = HashState::new(session.auth_hash)?;
+    state.update(session.policy.digest.as_slice());
+    state.update(&(command_code as u32).to_be_bytes());
+    for d in data {
+        state.update(d);
+    }
+    session.policy.digest = state.finish();
+
+    Ok(())
+}
+
+pub fn tpm2_policy_restart(
+    tpm: &mut TpmInstance,
+    args: &PolicySessionArgs,
+) -> Result<(), TpmError> {
+    policy_session(tpm, args.policy_session)?.policy_reset();
+    Ok(())
+}
+
+pub fn tpm2_policy_get_digest(
+    tpm: &mut TpmInstance,
+    args: &PolicySessionArgs,
+) -> Result<PolicyGetDigestResponse, TpmError> {
+    let session = policy_session(tpm, args.policy_session)?;
+
+    Ok(PolicyGetDigestResponse {
+        policy_digest: session.policy.digest,
+    })
+}
+
+pub fn tpm2_policy_command_code(
+    tpm: &mut TpmInstance,
+    args: &PolicyCommandCodeArgs,
+) -> Result<(), TpmError> {
+    let code = TpmCommandCode::from(args.code);
+    if code == TpmCommandCode::Unknown {
+        return Err(TpmError::parameter(TpmRc::PolicyCc, 1));
+    }
+
+    let session = policy_session(tpm, args.policy_session)?;
+    if matches!(session.policy.command_code, Some(c) if c != code) {
+        return Err(TpmError::parameter(TpmRc::Value, 1));
+    }
+
+    policy_update(
+        session,
+        TpmCommandCode::PolicyCommandCode,
+        &[&args.code.to_be_bytes()],
+    )?;
+    session.policy.command_code = Some(code);
+
+    Ok(())
+}
+
+// TPM2_PolicyPassword extends the digest the same way as
+// TPM2_PolicyAuthValue, so a policy can be satisfied either way. They only
+// differ in what the session sends in place of the HMAC.
+pub fn tpm2_policy_password(
+    tpm: &mut TpmInstance,
+    args: &PolicySessionArgs,
+) -> Result<(), TpmError> {
+    let session = policy_session(tpm, args.policy_session)?;
+    policy_update(session, TpmCommandCode::PolicyAuthValue, &[])?;
+    session.policy.password_needed = true;
+    session.policy.auth_value_needed = false;
+
+    Ok(())
+}
+
+pub fn tpm2_policy_auth_value(
+    tpm: &mut TpmInstance,
+    args: &PolicySessionArgs,
+) -> Result<(), TpmError> {
+    let session = policy_session(tpm, args.policy_session)?;
+    policy_update(session, TpmCommandCode::PolicyAuthValue, &[])?;
+    session.policy.auth_value_needed = true;
+    session.policy.password_needed = false;
+
+    Ok(())
+}
+
+// The digest starts over from zeros, so any of the branches leads to the
+// same policyDigest. A trial session doesn't have to be on one of them.
+pub fn tpm2_policy_or(tpm: &mut TpmInstance, args: &PolicyOrArgs) -> Result<(), TpmError> {
+    let list = &args.p_hash_list;
+    if list.count < 2 {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+    let digests = &list.digests[..list.count as usize];
+
+    let session = policy_session(tpm, args.policy_session)?;
+    let current = session.policy.digest;
+    if session.session_type != TpmSe::Trial
+        && !digests.iter().any(|d| d.as_slice() == current.as_slice())
+    {
+        return Err(TpmError::parameter(TpmRc::Value, 1));
+    }
+
+    let mut data = [&[] as &[u8]; 8];
+    for (d, digest) in data.iter_mut().zip(digests) {
+        *d = digest.as_slice();
+    }
+    session.policy.digest.buffer.fill(0);
+    policy_update(session, TpmCommandCode::PolicyOr, &data[..digests.len()])
+}
+
+// cpHash and nameHash can't both be set, and neither can change. `n` is
+// the parameter cpHash came in.
+fn check_cp_hash(session: &Session, cp_hash: &Tpm2bDigest, n: u32) -> Result<(), TpmError> {
+    if cp_hash.size as usize != session.auth_hash.digest_size() {
+        return Err(TpmError::parameter(TpmRc::Size, n));
+    }
+
+    let policy = &session.policy;
+    if !policy.name_hash.is_empty()
+        || (!policy.cp_hash.is_empty() && policy.cp_hash.as_slice() != cp_hash.as_slice())
+    {
+        return Err(TpmError::new(TpmRc::CpHash));
+    }
+
+    Ok(())
+}
+
+// The policy is satisfied by authorizing authHandle, which the command has
+// already done. Then
+//
+//   policyDigest_new = H_authHash(H_authHash(policyDigest_old ||
+//                      TPM_CC_PolicySecret || authName) || policyRef)
+//
+// A trial session takes the parameters as they are. Tickets are only good
+// for TPM2_PolicyTicket, which isn't supported, so the ticket is always
+// NULL.
+pub fn tpm2_policy_secret(
+    tpm: &mut TpmInstance,
+    args: &PolicySecretArgs,
+) -> Result<PolicySecretResponse, TpmError> {
+    let auth_name = tpm.entity_name(args.auth_handle)?;
+    let clock = tpm.clock.clock;
+    let power_on = clock - tpm.clock.time;
+
+    let session = policy_session(tpm, args.policy_session)?;
+    // expiration is in seconds from when nonceTPM was made, or from power
+    // on if there's no nonceTPM.
+    let timeout = match args.expiration {
+        0 => 0,
+        expiration => {
+            let start = match args.nonce_tpm.is_empty() {
+                true => power_on,
+                false => session.start_time,
+            };
+            start.saturating_add(expiration.unsigned_abs() as u64 * 1000)
+        }
+    };
+
+    if session.session_type != TpmSe::Trial {
+        if !args.nonce_tpm.is_empty() && args.nonce_tpm.as_slice() != session.nonce_tpm.as_slice() {
+            return Err(TpmError::parameter(TpmRc::Nonce, 1));
+        }
+        if timeout != 0 && clock >= timeout {
+            return Err(TpmError::parameter(TpmRc::Expired, 4));
+        }
+        if !args.cp_hash_a.is_empty() {
+            check_cp_hash(session, &args.cp_hash_a, 2)?;
+        }
+    }
+
+    policy_update(
+        session,
+        TpmCommandCode::PolicySecret,
+        &[auth_name.as_slice()],
+    )?;
+    session.policy.digest = hash(
+        session.auth_hash,
+        &[session.policy.digest.as_slice(), args.policy_ref.as_slice()],
+    )?;
+
+    let policy = &mut session.policy;
+    if !args.cp_hash_a.is_empty() {
+        policy.cp_hash = args.cp_hash_a;
+    }
+    if timeout != 0 && (policy.timeout == 0 || timeout < policy.timeout) {
+        policy.timeout = timeout;
+    }
+
+    Ok(PolicySecretResponse {
+        timeout: Tpm2bDigest::default(),
+        policy_ticket: TpmtTkAuth {
+            tag: TPM_ST_AUTH_SECRET,
+            hierarchy: TpmRh::Null as TpmHandle,
+            digest: Tpm2bDigest::default(),
+        },
+    })
+}
+
+pub fn tpm2_policy_cp_hash(tpm: &mut TpmInstance, args: &PolicyCpHashArgs) -> Result<(), TpmError> {
+    let session = policy_session(tpm, args.policy_session)?;
+    check_cp_hash(session, &args.cp_hash_a, 1)?;
+
+    policy_update(
+        session,
+        TpmCommandCode::PolicyCpHash,
+        &[args.cp_hash_a.as_slice()],
+    )?;
+    session.policy.cp_hash = args.cp_hash_a;
+
+    Ok(())
+}
+
+pub fn tpm2_policy_name_hash(
+    tpm: &mut TpmInstance,
+    args: &PolicyNameHashArgs,
+) -> Result<(), TpmError> {
+    let session = policy_session(tpm, args.policy_session)?;
+    if args.name_hash.size as usize != session.auth_hash.digest_size() {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+
+    let policy = &session.policy;
+    if !policy.name_hash.is_empty() || !policy.cp_hash.is_empty() {
+        return Err(TpmError::new(TpmRc::CpHash));
+    }
+
+    policy_update(
+        session,
+        TpmCommandCode::PolicyNameHash,
+        &[args.name_hash.as_slice()],
+    )?;
+    session.policy.name_hash = args.name_hash;
+
+    Ok(())
+}
+
+// Bind the policy to the values of the selected PCRs:
+//
+//   policyDigest_new = H_authHash(policyDigest_old || TPM_CC_PolicyPCR ||
+//                      pcrs || pcrDigest)
+//
+// pcrDigest is of the PCRs as they are now, made with authHash. A trial
+// session takes the caller's, or the current one if it's empty. Any other
+// session checks the caller's, and the PCRs can't then change until the
+// session is used.
+pub fn tpm2_policy_pcr(tpm: &mut TpmInstance, args: &PolicyPcrArgs) -> Result<(), TpmError> {
+    let update_counter = tpm.pcr.update_counter;
+    let auth_hash = policy_session(tpm, args.policy_session)?.auth_hash;
+    let mut pcrs = args.pcrs;
+    let current = tpm.pcr_digest(auth_hash, &mut pcrs)?;
+
+    let session = policy_session(tpm, args.policy_session)?;
+    let trial = session.session_type == TpmSe::Trial;
+    if !trial && !args.pcr_digest.is_empty() && args.pcr_digest.as_slice() != current.as_slice() {
+        return Err(TpmError::parameter(TpmRc::Value, 1));
+    }
+    let pcr_digest = match trial && !args.pcr_digest.is_empty() {
+        true => args.pcr_digest,
+        false => current,
+    };
+
+    let mut buffer = [0u8; 4 + HASH_COUNT * (3 + PCR_SELECT_MAX)];
+    let size = marshal_tpml_pcr_selection(&mut buffer, &pcrs)?;
+    policy_update(
+        session,
+        TpmCommandCode::PolicyPcr,
+        &[&buffer[..size], pcr_digest.as_slice()],
+    )?;
+    if !trial {
+        session.policy.pcr_update_counter = Some(update_counter);
+    }
+
+    Ok(())
+}
+
+// Limit the policy to TPM2_Duplicate of objectName to newParentName, or of
+// any object to newParentName if includeObject is NO. The names are checked
+// through nameHash when the policy is used.
+pub fn tpm2_policy_duplication_select(
+    tpm: &mut TpmInstance,
+    args: &PolicyDuplicationSelectArgs,
+) -> Result<(), TpmError> {
+    let session = policy_session(tpm, args.policy_session)?;
+    let policy = &session.policy;
+    if !policy.name_hash.is_empty() {
+        return Err(TpmError::new(TpmRc::CpHash));
+    }
+    if matches!(policy.command_code, Some(c) if c != TpmCommandCode::Duplicate) {
+        return Err(TpmError::new(TpmRc::CommandCode));
+    }
+
+    let object_name = match args.include_object {
+        true => args.object_name.as_slice(),
+        false => &[],
+    };
+    policy_update(
+        session,
+        TpmCommandCode::PolicyDuplicationSelect,
+        &[
+            object_name,
+            args.new_parent_name.as_slice(),
+            &[args.include_object as u8],
+        ],
+    )?;
+    session.policy.name_hash =
+        name_hash(session.auth_hash, &[args.object_name, args.new_parent_name])?;
+    session.policy.command_code = Some(TpmCommandCode::Duplicate);
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use crate::crypto::hash::*;
+use crate::crypto::sym::{self, CmacState};
+use crate::marshal::*;
+use crate::object::*;
+use crate::symmetric::*;
+use crate::tpm::*;
+use crate::types::*;
+
+// An HMAC or MAC sequence. It takes up a transient object slot, and is
+// authorized with the authValue given when it was started.
+#[derive(Clone)]
+pub struct Sequence {
+    pub(crate) state: MacState,
+    pub(crate) auth_value: Tpm2bAuth,
+}
+
+pub fn tpm2_mac_start(
+    tpm: &mut TpmInstance,
+    args: &MacStartArgs,
+) -> Result<MacStartResponse, TpmError> {
+    let object = loaded_object(tpm, args.handle)?;
+    let state = mac_start(&object, args.in_scheme)?;
+
+    let sequence = Sequence {
+        state,
+        auth_value: args.auth,
+    };
+    let sequence_handle = tpm.sequence_load(sequence)?;
+
+    Ok(MacStartResponse { sequence_handle })
+}
+
+pub fn tpm2_sequence_update(
+    tpm: &mut TpmInstance,
+    args: &SequenceUpdateArgs,
+) -> Result<(), TpmError> {
+    let sequence = match tpm.sequence_get_mut(args.sequence_handle) {
+        Some(sequence) => sequence,
+        None => return Err(TpmError::handle(TpmRc::Mode, 1)),
+    };
+    sequence.state.update(args.buffer.as_slice());
+
+    Ok(())
+}
+
+// Finish the sequence and flush it. A MAC never gets a ticket, so the
+// hierarchy is only checked.
+pub fn tpm2_sequence_complete(
+    tpm: &mut TpmInstance,
+    args: &SequenceCompleteArgs,
+) -> Result<SequenceCompleteResponse, TpmError> {
+    let mut sequence = match tpm.sequence_take(args.sequence_handle) {
+        Some(sequence) => sequence,
+        None => return Err(TpmError::handle(TpmRc::Mode, 1)),
+    };
+    sequence.state.update(args.buffer.as_slice());
+
+    Ok(SequenceCompleteResponse {
+        result: sequence.state.finish(),
+        validation: TpmtTkHashcheck {
+            hierarchy: TpmRh::Null as TpmHandle,
+            ..Default::default()
+        },
+    })
+}
+
+// A hash state as the chaining value, the length so far and the partial
+// block.
+fn marshal_hash_state(buffer: &mut [u8], state: &HashState) -> Result<usize, TpmError> {
+    let mut offset = marshal_u16(buffer, state.alg as u16)?;
+    for word in state.chain {
+        offset += marshal_u64(&mut buffer[offset..], word)?;
+    }
+    offset += marshal_u64(&mut buffer[offset..], state.length)?;
+    let fill = (state.length % state.block_size() as u64) as usize;
+    offset += marshal_bytes(&mut buffer[offset..], &state.block[..fill])?;
+
+    Ok(offset)
+}
+
+fn unmarshal_hash_state(buffer: &[u8], offset: &mut usize) -> Result<HashState, TpmError> {
+    let mut state = HashState::new(unmarshal_alg_id(buffer, offset)?)?;
+    for word in state.chain.iter_mut() {
+        *word = unmarshal_u64(buffer, offset)?;
+    }
+    state.length = unmarshal_u64(buffer, offset)?;
+    let fill = (state.length % state.block_size() as u64) as usize;
+    state.block[..fill].copy_from_slice(unmarshal_bytes(buffer, offset, fill)?);
+
+    Ok(state)
+}
+
+// The saved form of a sequence, for its context: which MAC it is, the MAC
+// state and the sequence's authValue.
+pub(crate) fn marshal_sequence(buffer: &mut [u8], sequence: &Sequence) -> Result<usize, TpmError> {
+    let mut offset = match &sequence.state {
+        MacState::Hmac(state) => {
+            let mut offset = marshal_u16(buffer, TpmAlgId::Hmac as u16)?;
+            offset += marshal_hash_state(&mut buffer[offset..], &state.inner)?;
+            offset += marshal_hash_state(&mut buffer[offset..], &state.outer)?;
+            offset
+        }
+        MacState::Cmac(state) => {
+            let n = sym::block_size(state.alg);
+            let mut offset = marshal_u16(buffer, TpmAlgId::Cmac as u16)?;
+            offset += marshal_u16(&mut buffer[offset..], state.alg as u16)?;
+            offset += marshal_tpm2b(&mut buffer[offset..], &state.key)?;
+            offset += marshal_bytes(&mut buffer[offset..], &state.chain[..n])?;
+            offset += marshal_u8(&mut buffer[offset..], state.fill as u8)?;
+            offset += marshal_bytes(&mut buffer[offset..], &state.block[..state.fill])?;
+            offset
+        }
+    };
+    offset += marshal_tpm2b(&mut buffer[offset..], &sequence.auth_value)?;
+
+    Ok(offset)
+}
+
+pub(crate) fn unmarshal_sequence(buffer: &[u8], offset: &mut usize) -> Result<Sequence, TpmError> {
+    let state = match unmarshal_alg_id(buffer, offset)? {
+        TpmAlgId::Hmac => {
+            let inner = unmarshal_hash_state(buffer, offset)?;
+            let outer = unmarshal_hash_state(buffer, offset)?;
+            if inner.alg != outer.alg {
+                return Err(TpmError::new(TpmRc::Value));
+            }
+            MacState::Hmac(HmacState { inner, outer })
+        }
+        TpmAlgId::Cmac => {
+            let alg = unmarshal_alg_id(buffer, offset)?;
+            let key: Tpm2bSymKey = unmarshal_tpm2b(buffer, offset)?;
+            let mut state = CmacState::new(alg, key.as_slice())?;
+            let n = sym::block_size(alg);
+            state.chain[..n].copy_from_slice(unmarshal_bytes(buffer, offset, n)?);
+            state.fill = unmarshal_u8(buffer, offset)? as usize;
+            if state.fill > n {
+                return Err(TpmError::new(TpmRc::Value));
+            }
+            state.block[..state.fill].copy_from_slice(unmarshal_bytes(buffer, offset, state.fill)?);
+            MacState::Cmac(state)
+        }
+        _ => return Err(TpmError::new(TpmRc::Value)),
+    };
+    let auth_value = unmarshal_tpm2b(buffer, offset)?;
+
+    Ok(Sequence { state, auth_value })
+}
@@ -0,0 +1,550 @@
+use crate::asymmetric::*;
+use crate::authorization::*;
+use crate::crypto::hash::*;
+use crate::crypto::kdf::{kdfa, SECRET, SESSION_KEY};
+use crate::marshal::*;
+use crate::object::*;
+use crate::tpm::*;
+use crate::types::*;
+
+pub const HMAC_SESSION_FIRST: TpmHandle = 0x02000000;
+pub const POLICY_SESSION_FIRST: TpmHandle = 0x03000000;
+
+// How far the contextIDs of saved sessions may spread. Saving a session
+// fails while the oldest saved one is this far behind, until that one is
+// loaded or flushed.
+const MAX_CONTEXT_GAP: u64 = u8::MAX as u64;
+
+// What a policy session has collected since it was started or last reset.
+// Empty cpHash and nameHash haven't been set.
+#[derive(Clone, Copy, Default)]
+pub struct PolicyState {
+    pub digest: Tpm2bDigest,
+    pub command_code: Option<TpmCommandCode>,
+    pub cp_hash: Tpm2bDigest,
+    pub name_hash: Tpm2bDigest,
+    pub auth_value_needed: bool,
+    pub password_needed: bool,
+    // The Clock at which the policy expires, or 0 if it doesn't
+    pub timeout: u64,
+    // pcrUpdateCounter when TPM2_PolicyPCR checked the PCRs. They can't
+    // change before the session is used.
+    pub pcr_update_counter: Option<u32>,
+}
+
+// A loaded HMAC, policy or trial session. Sessions can only authorize;
+// parameter encryption and audit aren't supported.
+#[derive(Clone, Copy, Default)]
+pub struct Session {
+    pub session_type: TpmSe,
+    pub auth_hash: TpmAlgId,
+    pub session_key: Tpm2bDigest,
+    // The last nonce the TPM returned
+    pub nonce_tpm: Tpm2bNonce,
+    // H_authHash(Name || authValue) of the entity the session is bound to,
+    // or empty if it's unbound. A change of authValue unbinds the session.
+    pub bound_entity: Tpm2bDigest,
+    // The Clock when the session started
+    pub start_time: u64,
+    pub policy: PolicyState,
+}
+
+// What a session handle refers to. A session keeps its handle while its
+// context is saved, until it's loaded again or flushed.
+#[derive(Clone, Copy, Default)]
+pub enum ActiveSession {
+    #[default]
+    Free,
+    // The slot the session is loaded in
+    Loaded(usize),
+    // Only the context with this contextID can be loaded.
+    Saved {
+        session_type: TpmSe,
+        context_id: u64,
+    },
+}
+
+impl Session {
+    // The state a policy session starts in, and returns to with
+    // TPM2_PolicyRestart or after it has authorized a command.
+    pub(crate) fn policy_reset(&mut self) {
+        self.policy = PolicyState::default();
+        self.policy.digest.size = self.auth_hash.digest_size() as u16;
+    }
+}
+
+fn session_slot(handle: TpmHandle) -> usize {
+    (handle & 0x00FFFFFF) as usize
+}
+
+// HMAC session handles only refer to HMAC sessions, and policy session
+// handles to policy and trial sessions.
+fn session_type_matches(handle: TpmHandle, session_type: TpmSe) -> bool {
+    matches!(
+        (TpmHt::from(handle), session_type),
+        (TpmHt::HmacSession, TpmSe::Hmac) | (TpmHt::PolicySession, TpmSe::Policy | TpmSe::Trial)
+    )
+}
+
+// H_authHash(commandCode || Name1 || Name2 || Name3 || parameters)
+pub(crate) fn command_hash(
+    hash_alg: TpmAlgId,
+    command_code: TpmCommandCode,
+    names: &[Tpm2bName],
+    parameters: &[u8],
+) -> Result<Tpm2bDigest, TpmError> {
+    let mut state = HashState::new(hash_alg)?;
+    state.update(&(command_code as u32).to_be_bytes());
+    for name in names {
+        state.update(name.as_slice());
+    }
+    state.update(parameters);
+
+    Ok(state.finish())
+}
+
+// H_authHash(Name1 || Name2 || Name3)
+pub(crate) fn name_hash(hash_alg: TpmAlgId, names: &[Tpm2bName]) -> Result<Tpm2bDigest, TpmError> {
+    let mut state = HashState::new(hash_alg)?;
+    for name in names {
+        state.update(name.as_slice());
+    }
+
+    Ok(state.finish())
+}
+
+// H_authHash(responseCode || commandCode || parameters). Only successful
+// responses carry sessions, so the response code is always 0.
+fn response_hash(
+    hash_alg: TpmAlgId,
+    command_code: TpmCommandCode,
+    parameters: &[u8],
+) -> Result<Tpm2bDigest, TpmError> {
+    hash(
+        hash_alg,
+        &[
+            &(TpmRc::Success as u32).to_be_bytes(),
+            &(command_code as u32).to_be_bytes(),
+            parameters,
+        ],
+    )
+}
+
+// HMAC_authHash(sessionKey || authValue, pHash || nonceNewer || nonceOlder
+// || sessionAttributes)
+pub(crate) fn session_hmac(
+    session: &Session,
+    auth_value: &[u8],
+    p_hash: &Tpm2bDigest,
+    nonce_newer: &[u8],
+    nonce_older: &[u8],
+    attributes: u8,
+) -> Result<Tpm2bDigest, TpmError> {
+    let auth_value = trim_trailing_zeros(auth_value);
+    let key_size = session.session_key.size as usize + auth_value.len();
+    let mut key = [0u8; 2 * MAX_DIGEST_SIZE];
+    key[..session.session_key.size as usize].copy_from_slice(session.session_key.as_slice());
+    key[session.session_key.size as usize..key_size].copy_from_slice(auth_value);
+
+    hmac(
+        session.auth_hash,
+        &key[..key_size],
+        &[p_hash.as_slice(), nonce_newer, nonce_older, &[attributes]],
+    )
+}
+
+pub fn tpm2_start_auth_session(
+    tpm: &mut TpmInstance,
+    args: &StartAuthSessionArgs,
+) -> Result<StartAuthSessionResponse, TpmError> {
+    let digest_size = args.auth_hash.digest_size();
+    if args.nonce_caller.size < 16 || args.nonce_caller.size as usize > digest_size {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+    // Only CFB could be used for parameter encryption.
+    if args.symmetric.algorithm != TpmAlgId::Null && args.symmetric.mode != TpmAlgId::Cfb {
+        return Err(TpmError::parameter(TpmRc::Mode, 4));
+    }
+
+    let salt = match TpmRh::from(args.tpm_key) {
+        TpmRh::Null if !args.encrypted_salt.is_empty() => {
+            return Err(TpmError::parameter(TpmRc::Value, 2));
+        }
+        TpmRh::Null => Tpm2bDigest::default(),
+        _ => {
+            let key = loaded_object(tpm, args.tpm_key)?;
+            if key.sensitive.sensitive_type() == TpmAlgId::Unknown {
+                return Err(TpmError::handle(TpmRc::Handle, 1));
+            }
+            if !key.public.has_attributes(TPMA_OBJECT_DECRYPT) {
+                return Err(TpmError::handle(TpmRc::Attributes, 1));
+            }
+            secret_decrypt(&key, SECRET, &args.encrypted_salt).map_err(|e| e.with_parameter(2))?
+        }
+    };
+
+    let loaded = match tpm.sessions.iter().position(|s| s.is_none()) {
+        Some(loaded) => loaded,
+        None => return Err(TpmError::new(TpmRc::SessionMemory)),
+    };
+    let free = |active: &ActiveSession| matches!(active, ActiveSession::Free);
+    let slot = match tpm.active_sessions.iter().position(free) {
+        Some(slot) => slot,
+        None => return Err(TpmError::new(TpmRc::SessionHandles)),
+    };
+
+    let mut session = Session {
+        session_type: args.session_type,
+        auth_hash: args.auth_hash,
+        start_time: tpm.clock.clock,
+        ..Default::default()
+    };
+    session.nonce_tpm.size = digest_size as u16;
+    (tpm.platform.get_random)(&mut session.nonce_tpm.buffer[..digest_size]);
+
+    // sessionKey = KDFa(authHash, bind.authValue || salt, "ATH", nonceTPM,
+    // nonceCaller, bits), or empty for an unbound, unsalted session.
+    let bind_auth = match TpmRh::from(args.bind) {
+        TpmRh::Null => Tpm2bAuth::default(),
+        _ => {
+            let auth_value = tpm.entity_auth_value(args.bind)?;
+            let name = tpm.entity_name(args.bind)?;
+            let bound = trim_trailing_zeros(auth_value.as_slice());
+            session.bound_entity = hash(args.auth_hash, &[name.as_slice(), bound])?;
+            auth_value
+        }
+    };
+    let bind_auth = trim_trailing_zeros(bind_auth.as_slice());
+    if !bind_auth.is_empty() || !salt.is_empty() {
+        let mut key = [0u8; 2 * MAX_DIGEST_SIZE];
+        let key_size = bind_auth.len() + salt.size as usize;
+        key[..bind_auth.len()].copy_from_slice(bind_auth);
+        key[bind_auth.len()..key_size].copy_from_slice(salt.as_slice());
+
+        session.session_key.size = digest_size as u16;
+        kdfa(
+            args.auth_hash,
+            &key[..key_size],
+            SESSION_KEY,
+            session.nonce_tpm.as_slice(),
+            args.nonce_caller.as_slice(),
+            &mut session.session_key.buffer[..digest_size],
+        )?;
+    }
+
+    let session_handle = match args.session_type {
+        TpmSe::Hmac => HMAC_SESSION_FIRST,
+        _ => {
+            session.policy_reset();
+            POLICY_SESSION_FIRST
+        }
+    } + slot as TpmHandle;
+
+    let nonce_tpm = session.nonce_tpm;
+    tpm.sessions[loaded] = Some(session);
+    tpm.active_sessions[slot] = ActiveSession::Loaded(loaded);
+
+    Ok(StartAuthSessionResponse {
+        session_handle,
+        nonce_tpm,
+    })
+}
+
+impl TpmInstance {
+    // The slot of the loaded session behind a session handle.
+    fn session_loaded_slot(&self, handle: TpmHandle) -> Option<usize> {
+        let loaded = match self.active_sessions.get(session_slot(handle))? {
+            ActiveSession::Loaded(loaded) => *loaded,
+            _ => return None,
+        };
+        let session = self.sessions[loaded].as_ref()?;
+        session_type_matches(handle, session.session_type).then_some(loaded)
+    }
+
+    // The loaded session behind a session handle.
+    pub(crate) fn session_get(&self, handle: TpmHandle) -> Option<&Session> {
+        self.sessions[self.session_loaded_slot(handle)?].as_ref()
+    }
+
+    pub(crate) fn session_get_mut(&mut self, handle: TpmHandle) -> Option<&mut Session> {
+        self.sessions[self.session_loaded_slot(handle)?].as_mut()
+    }
+
+    // Flush a loaded session, or forget a saved one so its context can't
+    // be loaded.
+    pub(crate) fn session_flush(&mut self, handle: TpmHandle) -> Result<(), TpmError> {
+        let slot = session_slot(handle);
+        match self.active_sessions.get(slot) {
+            Some(ActiveSession::Saved { session_type, .. })
+                if session_type_matches(handle, *session_type) => {}
+            _ => match self.session_loaded_slot(handle) {
+                Some(loaded) => self.sessions[loaded] = None,
+                None => return Err(TpmError::parameter(TpmRc::Handle, 1)),
+            },
+        }
+
+        self.active_sessions[slot] = ActiveSession::Free;
+        Ok(())
+    }
+
+    pub(crate) fn session_flush_all(&mut self) {
+        self.sessions = [None; MAX_LOADED_SESSIONS];
+        self.active_sessions = [ActiveSession::Free; MAX_ACTIVE_SESSIONS];
+    }
+
+    // The contextID for the next session context save, unless the oldest
+    // saved session is too far behind for it.
+    pub(crate) fn session_context_id(&self) -> Result<u64, TpmError> {
+        let context_id = self.context.session_context_id;
+        for active in &self.active_sessions {
+            if let ActiveSession::Saved { context_id: id, .. } = active {
+                if context_id - id >= MAX_CONTEXT_GAP {
+                    return Err(TpmError::new(TpmRc::ContextGap));
+                }
+            }
+        }
+
+        Ok(context_id)
+    }
+
+    // Take a loaded session out of its slot once its context is saved.
+    pub(crate) fn session_context_saved(&mut self, handle: TpmHandle, context_id: u64) {
+        let loaded = match self.session_loaded_slot(handle) {
+            Some(loaded) => loaded,
+            None => return,
+        };
+        let session_type = self.sessions[loaded].take().unwrap().session_type;
+
+        self.active_sessions[session_slot(handle)] = ActiveSession::Saved {
+            session_type,
+            context_id,
+        };
+        self.context.session_context_id = context_id + 1;
+    }
+
+    // Load a saved session back into a slot. Only its latest context will
+    // do.
+    pub(crate) fn session_context_load(
+        &mut self,
+        handle: TpmHandle,
+        context_id: u64,
+        session: Session,
+    ) -> Result<(), TpmError> {
+        let slot = session_slot(handle);
+        match self.active_sessions.get(slot) {
+            Some(ActiveSession::Saved {
+                session_type,
+                context_id: id,
+            }) if *session_type == session.session_type
+                && session_type_matches(handle, *session_type)
+                && *id == context_id => {}
+            _ => return Err(TpmError::parameter(TpmRc::Handle, 1)),
+        }
+
+        let loaded = match self.sessions.iter().position(|s| s.is_none()) {
+            Some(loaded) => loaded,
+            None => return Err(TpmError::new(TpmRc::SessionMemory)),
+        };
+        self.sessions[loaded] = Some(session);
+        self.active_sessions[slot] = ActiveSession::Loaded(loaded);
+
+        Ok(())
+    }
+
+    pub(crate) fn session_slots_free(&self) -> usize {
+        self.sessions.iter().filter(|s| s.is_none()).count()
+    }
+
+    // Handles of the loaded sessions of handle type `ht`, in ascending
+    // order.
+    pub(crate) fn session_handles(&self, ht: TpmHt) -> impl Iterator<Item = TpmHandle> + '_ {
+        let first = match ht {
+            TpmHt::HmacSession => HMAC_SESSION_FIRST,
+            _ => POLICY_SESSION_FIRST,
+        };
+        (0..MAX_ACTIVE_SESSIONS)
+            .map(move |slot| first + slot as TpmHandle)
+            .filter(|handle| self.session_get(*handle).is_some())
+    }
+
+    // The response for a session that authorized a command. The session
+    // gets a new nonceTPM, and is flushed unless continueSession is set.
+    // A policy session that carries on starts a new policy.
+    pub(crate) fn session_response(
+        &mut self,
+        auth: &TpmsAuthCommand,
+        entity: TpmHandle,
+        with_auth_value: bool,
+        command_code: TpmCommandCode,
+        parameters: &[u8],
+    ) -> Result<TpmsAuthResponse, TpmError> {
+        let mut session = match self.session_get(auth.session_handle) {
+            Some(session) => *session,
+            None => return Err(TpmError::new(TpmRc::Failure)),
+        };
+
+        let digest_size = session.auth_hash.digest_size();
+        (self.platform.get_random)(&mut session.nonce_tpm.buffer[..digest_size]);
+
+        // The authValue is read again, as the command may have changed it.
+        // An entity the command deleted has none.
+        let auth_value = match with_auth_value {
+            true => self.entity_auth_value(entity).unwrap_or_default(),
+            false => Tpm2bAuth::default(),
+        };
+        // A session that could leave the command HMAC empty gets an empty
+        // one back.
+        let no_key =
+            session.session_key.is_empty() && trim_trailing_zeros(auth_value.as_slice()).is_empty();
+        let hmac = match no_key && auth.hmac.is_empty() {
+            true => Tpm2bDigest::default(),
+            false => {
+                let rp_hash = response_hash(session.auth_hash, command_code, parameters)?;
+                session_hmac(
+                    &session,
+                    auth_value.as_slice(),
+                    &rp_hash,
+                    session.nonce_tpm.as_slice(),
+                    auth.nonce.as_slice(),
+                    auth.session_attributes,
+                )?
+            }
+        };
+
+        let response = TpmsAuthResponse {
+            nonce: session.nonce_tpm,
+            session_attributes: auth.session_attributes,
+            hmac,
+        };
+
+        if auth.session_attributes & TPMA_SESSION_CONTINUE_SESSION == 0 {
+            self.session_flush(auth.session_handle)?;
+        } else {
+            if session.session_type != TpmSe::Hmac {
+                session.policy_reset();
+            }
+            *self.session_get_mut(auth.session_handle).unwrap() = session;
+        }
+
+        Ok(response)
+    }
+}
+
+// The saved form of a session, for its context.
+pub(crate) fn marshal_session(buffer: &mut [u8], session: &Session) -> Result<usize, TpmError> {
+    let policy = &session.policy;
+    let command_code = policy.command_code.map_or(0, |code| code as u32);
+
+    let mut offset = marshal_u8(buffer, session.session_type as u8)?;
+    offset += marshal_u16(&mut buffer[offset..], session.auth_hash as u16)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &session.session_key)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &session.nonce_tpm)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &session.bound_entity)?;
+    offset += marshal_u64(&mut buffer[offset..], session.start_time)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &policy.digest)?;
+    offset += marshal_u32(&mut buffer[offset..], command_code)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &policy.cp_hash)?;
+    offset += marshal_tpm2b(&mut buffer[offset..], &policy.name_hash)?;
+    offset += marshal_u8(&mut buffer[offset..], policy.auth_value_needed as u8)?;
+    offset += marshal_u8(&mut buffer[offset..], policy.password_needed as u8)?;
+    offset += marshal_u64(&mut buffer[offset..], policy.timeout)?;
+    offset += marshal_u8(
+        &mut buffer[offset..],
+        policy.pcr_update_counter.is_some() as u8,
+    )?;
+    offset += marshal_u32(
+        &mut buffer[offset..],
+        policy.pcr_update_counter.unwrap_or(0),
+    )?;
+
+    Ok(offset)
+}
+
+pub(crate) fn unmarshal_session(buffer: &[u8], offset: &mut usize) -> Result<Session, TpmError> {
+    let session_type = TpmSe::from(unmarshal_u8(buffer, offset)?);
+    let auth_hash = unmarshal_hash_alg(buffer, offset, false)?;
+    let session_key = unmarshal_tpm2b(buffer, offset)?;
+    let nonce_tpm = unmarshal_tpm2b(buffer, offset)?;
+    let bound_entity = unmarshal_tpm2b(buffer, offset)?;
+    let start_time = unmarshal_u64(buffer, offset)?;
+    let digest = unmarshal_tpm2b(buffer, offset)?;
+    let command_code = match unmarshal_u32(buffer, offset)? {
+        0 => None,
+        code => Some(TpmCommandCode::from(code)),
+    };
+    let cp_hash = unmarshal_tpm2b(buffer, offset)?;
+    let name_hash = unmarshal_tpm2b(buffer, offset)?;
+    let auth_value_needed = unmarshal_yes_no(buffer, offset)?;
+    let password_needed = unmarshal_yes_no(buffer, offset)?;
+    let timeout = unmarshal_u64(buffer, offset)?;
+    let pcr_checked = unmarshal_yes_no(buffer, offset)?;
+    let pcr_update_counter = unmarshal_u32(buffer, offset)?;
+    if session_type == TpmSe::Unknown {
+        return Err(TpmError::new(TpmRc::Value));
+    }
+
+    Ok(Session {
+        session_type,
+        auth_hash,
+        session_key,
+        nonce_tpm,
+        bound_entity,
+        start_time,
+        policy: PolicyState {
+            digest,
+            command_code,
+            cp_hash,
+            name_hash,
+            auth_value_needed,
+            password_needed,
+            timeout,
+            pcr_update_counter: pcr_checked.then_some(pcr_update_counter),
+        },
+    })
+}
+
+// Which sessions are saved, for Shutdown(STATE). Each handle takes the
+// session type, or 0xFF if it isn't saved, and how far its contextID is
+// behind the next one. Loaded sessions don't survive.
+pub(crate) fn marshal_saved_sessions(
+    buffer: &mut [u8],
+    tpm: &TpmInstance,
+) -> Result<usize, TpmError> {
+    let next = tpm.context.session_context_id;
+    let mut offset = 0;
+    for active in &tpm.active_sessions {
+        let (session_type, gap) = match active {
+            ActiveSession::Saved {
+                session_type,
+                context_id,
+            } => (*session_type as u8, (next - context_id) as u8),
+            _ => (0xFF, 0),
+        };
+        offset += marshal_u8(&mut buffer[offset..], session_type)?;
+        offset += marshal_u8(&mut buffer[offset..], gap)?;
+    }
+
+    Ok(offset)
+}
+
+pub(crate) fn unmarshal_saved_sessions(
+    buffer: &[u8],
+    offset: &mut usize,
+    tpm: &mut TpmInstance,
+) -> Result<(), TpmError> {
+    let next = tpm.context.session_context_id;
+    for active in tpm.active_sessions.iter_mut() {
+        let session_type = unmarshal_u8(buffer, offset)?;
+        let gap = unmarshal_u8(buffer, offset)? as u64;
+        *active = match TpmSe::from(session_type) {
+            TpmSe::Unknown => ActiveSession::Free,
+            session_type => ActiveSession::Saved {
+                session_type,
+                context_id: next.wrapping_sub(gap),
+            },
+        };
+    }
+    tpm.sessions = [None; MAX_LOADED_SESSIONS];
+
+    Ok(())
+}
@@ -0,0 +1,306 @@
+use crate::authorization::auth_equal;
+use crate::crypto::hash::{hash, hmac};
+use crate::crypto::kdf::PlatformRandom;
+use crate::crypto::{ecc, rsa};
+use crate::hierarchy::Hierarchy;
+use crate::object::*;
+use crate::tpm::*;
+use crate::types::*;
+
+// The signing scheme a key has of its own, TPM_ALG_NULL if it has none.
+fn key_scheme(public: &TpmtPublic) -> TpmtAsymScheme {
+    match public.parameters {
+        TpmuPublicParms::Rsa(parms) => parms.scheme,
+        TpmuPublicParms::Ecc(parms) => parms.scheme,
+        TpmuPublicParms::KeyedHash(parms) => TpmtAsymScheme {
+            scheme: parms.scheme.scheme,
+            hash_alg: parms.scheme.hash_alg,
+            ..Default::default()
+        },
+        _ => TpmtAsymScheme::default(),
+    }
+}
+
+fn scheme_fits(public: &TpmtPublic, scheme: TpmAlgId) -> bool {
+    match public.parameters {
+        TpmuPublicParms::Rsa(_) => matches!(scheme, TpmAlgId::RsaSsa | TpmAlgId::RsaPss),
+        TpmuPublicParms::Ecc(_) => matches!(
+            scheme,
+            TpmAlgId::EcDsa | TpmAlgId::EcDaa | TpmAlgId::Sm2 | TpmAlgId::EcSchnorr
+        ),
+        TpmuPublicParms::KeyedHash(_) => scheme == TpmAlgId::Hmac,
+        _ => false,
+    }
+}
+
+// The scheme a key signs with: its own, or `in_scheme` if it has none. A
+// key with a scheme of its own only signs with that scheme, though an ECDAA
+// commit count still comes from `in_scheme`. Anything else is TPM_RC_SCHEME,
+// which the caller puts on the right parameter.
+pub(crate) fn sign_scheme(
+    public: &TpmtPublic,
+    in_scheme: &TpmtAsymScheme,
+) -> Result<TpmtAsymScheme, TpmError> {
+    let key_scheme = key_scheme(public);
+    let scheme = if key_scheme.scheme == TpmAlgId::Null {
+        *in_scheme
+    } else if in_scheme.scheme == TpmAlgId::Null {
+        key_scheme
+    } else if in_scheme.scheme == key_scheme.scheme && in_scheme.hash_alg == key_scheme.hash_alg {
+        *in_scheme
+    } else {
+        return Err(TpmError::new(TpmRc::Scheme));
+    };
+
+    match scheme_fits(public, scheme.scheme) {
+        true => Ok(scheme),
+        false => Err(TpmError::new(TpmRc::Scheme)),
+    }
+}
+
+// Sign a digest with a loaded key and the scheme sign_scheme picked. ECDAA
+// uses up the commit that the scheme's count names.
+pub(crate) fn sign_digest(
+    tpm: &mut TpmInstance,
+    object: &Object,
+    scheme: &TpmtAsymScheme,
+    digest: &[u8],
+) -> Result<TpmtSignature, TpmError> {
+    let mut rand = PlatformRandom {
+        get_random: tpm.platform.get_random,
+    };
+
+    match (
+        &object.public.parameters,
+        &object.public.unique,
+        &object.sensitive.sensitive,
+    ) {
+        (TpmuPublicParms::Rsa(parms), TpmuPublicId::Rsa(n), TpmuSensitiveComposite::Rsa(p)) => {
+            let mut sig = TpmsSignatureRsa {
+                hash: scheme.hash_alg,
+                ..Default::default()
+            };
+            rsa::sign(
+                scheme,
+                parms.exponent,
+                n,
+                p,
+                digest,
+                &mut rand,
+                &mut sig.sig,
+            )?;
+            Ok(match scheme.scheme {
+                TpmAlgId::RsaSsa => TpmtSignature::RsaSsa(sig),
+                _ => TpmtSignature::RsaPss(sig),
+            })
+        }
+        (TpmuPublicParms::Ecc(parms), _, TpmuSensitiveComposite::Ecc(d))
+            if scheme.scheme == TpmAlgId::EcDaa =>
+        {
+            let mut r = Tpm2bEccParameter::default();
+            tpm.commit_end(parms.curve_id, object.name.as_slice(), scheme.count, &mut r)?;
+
+            let mut sig = TpmsSignatureEcc {
+                hash: scheme.hash_alg,
+                ..Default::default()
+            };
+            ecc::sign_ecdaa(parms.curve_id, d, &r, digest, &mut rand, &mut sig)?;
+            Ok(TpmtSignature::EcDaa(sig))
+        }
+        (TpmuPublicParms::Ecc(parms), _, TpmuSensitiveComposite::Ecc(d)) => {
+            let mut sig = TpmsSignatureEcc {
+                hash: scheme.hash_alg,
+                ..Default::default()
+            };
+            ecc::sign(
+                parms.curve_id,
+                scheme.scheme,
+                d,
+                digest,
+                &mut rand,
+                &mut sig,
+            )?;
+            Ok(match scheme.scheme {
+                TpmAlgId::EcDsa => TpmtSignature::EcDsa(sig),
+                TpmAlgId::Sm2 => TpmtSignature::Sm2(sig),
+                _ => TpmtSignature::EcSchnorr(sig),
+            })
+        }
+        (TpmuPublicParms::KeyedHash(_), _, TpmuSensitiveComposite::Bits(key)) => {
+            Ok(TpmtSignature::Hmac(TpmtHa {
+                hash_alg: scheme.hash_alg,
+                digest: hmac(scheme.hash_alg, key.as_slice(), &[digest])?,
+            }))
+        }
+        // A public-only key
+        _ => Err(TpmError::handle(TpmRc::Key, 1)),
+    }
+}
+
+// Check a signature over a digest with a loaded key. A signature that
+// doesn't verify is TPM_RC_SIGNATURE.
+fn verify_digest(
+    object: &Object,
+    digest: &[u8],
+    signature: &TpmtSignature,
+) -> Result<(), TpmError> {
+    let hash_alg = match signature {
+        TpmtSignature::RsaSsa(sig) | TpmtSignature::RsaPss(sig) => sig.hash,
+        TpmtSignature::EcDsa(sig)
+        | TpmtSignature::EcDaa(sig)
+        | TpmtSignature::Sm2(sig)
+        | TpmtSignature::EcSchnorr(sig) => sig.hash,
+        TpmtSignature::Hmac(ha) => ha.hash_alg,
+        TpmtSignature::Null => TpmAlgId::Null,
+    };
+    let in_scheme = TpmtAsymScheme {
+        scheme: signature.sig_alg(),
+        hash_alg,
+        ..Default::default()
+    };
+    let scheme = sign_scheme(&object.public, &in_scheme)?;
+    if scheme.hash_alg != hash_alg {
+        return Err(TpmError::new(TpmRc::Scheme));
+    }
+
+    match (
+        signature,
+        &object.public.parameters,
+        &object.public.unique,
+        &object.sensitive.sensitive,
+    ) {
+        (
+            TpmtSignature::RsaSsa(sig) | TpmtSignature::RsaPss(sig),
+            TpmuPublicParms::Rsa(parms),
+            TpmuPublicId::Rsa(n),
+            _,
+        ) => rsa::verify(&scheme, parms.exponent, n, digest, sig.sig.as_slice()),
+        (
+            TpmtSignature::EcDsa(sig)
+            | TpmtSignature::EcDaa(sig)
+            | TpmtSignature::Sm2(sig)
+            | TpmtSignature::EcSchnorr(sig),
+            TpmuPublicParms::Ecc(parms),
+            TpmuPublicId::Ecc(q),
+            _,
+        ) => ecc::verify(parms.curve_id, scheme.scheme, q, digest, sig),
+        (TpmtSignature::Hmac(ha), _, _, TpmuSensitiveComposite::Bits(key)) => {
+            let expected = hmac(hash_alg, key.as_slice(), &[digest])?;
+            if ha.digest.size != expected.size
+                || !auth_equal(ha.digest.as_slice(), expected.as_slice())
+            {
+                return Err(TpmError::new(TpmRc::Signature));
+            }
+            Ok(())
+        }
+        // An HMAC key without its private part can't check anything.
+        _ => Err(TpmError::handle(TpmRc::Key, 1)),
+    }
+}
+
+// Sign a digest. A restricted key only signs digests the TPM made itself
+// from data that didn't start with TPM_GENERATED_VALUE, which the
+// validation ticket proves, so it can't be used to fake an attestation.
+pub fn tpm2_sign(tpm: &mut TpmInstance, args: &SignArgs) -> Result<SignResponse, TpmError> {
+    let object = loaded_object(tpm, args.key_handle)?;
+    let public = &object.public;
+    if !public.has_attributes(TPMA_OBJECT_SIGN_ENCRYPT) {
+        return Err(TpmError::handle(TpmRc::Key, 1));
+    }
+    if public.has_attributes(TPMA_OBJECT_X509_SIGN) {
+        return Err(TpmError::handle(TpmRc::Attributes, 1));
+    }
+    let scheme = sign_scheme(public, &args.in_scheme).map_err(|e| e.with_parameter(2))?;
+
+    if !args.validation.digest.is_empty() || public.has_attributes(TPMA_OBJECT_RESTRICTED) {
+        let hierarchy = Hierarchy::from_handle(args.validation.hierarchy).unwrap_or_default();
+        let ticket = tpm.hashcheck_ticket(hierarchy, &args.digest)?;
+        if ticket.digest.size != args.validation.digest.size
+            || !auth_equal(ticket.digest.as_slice(), args.validation.digest.as_slice())
+        {
+            return Err(TpmError::parameter(TpmRc::Ticket, 3));
+        }
+    } else if args.digest.size as usize != scheme.hash_alg.digest_size() {
+        return Err(TpmError::parameter(TpmRc::Size, 1));
+    }
+
+    let signature = sign_digest(tpm, &object, &scheme, args.digest.as_slice())?;
+
+    Ok(SignResponse { signature })
+}
+
+// Check a signature with a loaded key. The ticket says the TPM did, unless
+// the key is in the null hierarchy or has no Name.
+pub fn tpm2_verify_signature(
+    tpm: &mut TpmInstance,
+    args: &VerifySignatureArgs,
+) -> Result<VerifySignatureResponse, TpmError> {
+    let object = loaded_object(tpm, args.key_handle)?;
+    if !object.public.has_attributes(TPMA_OBJECT_SIGN_ENCRYPT) {
+        return Err(TpmError::handle(TpmRc::Attributes, 1));
+    }
+    verify_digest(&object, args.digest.as_slice(), &args.signature)
+        .map_err(|e| e.with_parameter(2))?;
+
+    let validation =
+        if object.hierarchy == Hierarchy::Null || object.public.name_alg == TpmAlgId::Null {
+            TpmtTkVerified {
+                hierarchy: TpmRh::Null as TpmHandle,
+                ..Default::default()
+            }
+        } else {
+            tpm.verified_ticket(object.hierarchy, &args.digest, &object.name)?
+        };
+
+    Ok(VerifySignatureResponse { validation })
+}
+
+// The first half of an ECDAA signature. With P2 = (H(s2) mod p, y2), K is
+// [d]P2 and L is [r]P2, and E is [r]P1, or [r]G if there's neither point.
+// TPM2_Sign finishes the signature with the same r, which the counter names.
+pub fn tpm2_commit(tpm: &mut TpmInstance, args: &CommitArgs) -> Result<CommitResponse, TpmError> {
+    let object = loaded_object(tpm, args.sign_handle)?;
+    let (parms, d) = match (&object.public.parameters, &object.sensitive.sensitive) {
+        (TpmuPublicParms::Ecc(parms), TpmuSensitiveComposite::Ecc(d)) => (parms, d),
+        _ => return Err(TpmError::handle(TpmRc::Key, 1)),
+    };
+    if parms.scheme.scheme != TpmAlgId::EcDaa {
+        return Err(TpmError::handle(TpmRc::Scheme, 1));
+    }
+    if args.s2.is_empty() != args.y2.is_empty() {
+        return Err(TpmError::parameter(TpmRc::Size, 3));
+    }
+
+    let has_p1 = !args.p1.x.is_empty() || !args.p1.y.is_empty();
+    if has_p1 {
+        ecc::validate_point(parms.curve_id, &args.p1).map_err(|e| e.with_parameter(1))?;
+    }
+    let mut p2 = TpmsEccPoint {
+        y: args.y2,
+        ..Default::default()
+    };
+    if !args.s2.is_empty() {
+        let x2 = hash(object.public.name_alg, &[args.s2.as_slice()])?;
+        ecc::digest_to_x(parms.curve_id, x2.as_slice(), &mut p2.x)?;
+        ecc::validate_point(parms.curve_id, &p2).map_err(|e| e.with_parameter(2))?;
+    }
+
+    // The commit is only used up once all the points are made.
+    let mut response = CommitResponse::default();
+    let mut r = Tpm2bEccParameter::default();
+    let mut rg = TpmsEccPoint::default();
+    tpm.commit_next(parms.curve_id, object.name.as_slice(), &mut r, &mut rg)?;
+
+    if !args.s2.is_empty() {
+        ecc::point_multiply(parms.curve_id, d, &p2, &mut response.k)?;
+        ecc::point_multiply(parms.curve_id, &r, &p2, &mut response.l)?;
+    }
+    if has_p1 {
+        ecc::point_multiply(parms.curve_id, &r, &args.p1, &mut response.e)?;
+    } else if args.s2.is_empty() {
+        response.e = rg;
+    }
+    response.counter = tpm.commit_start(object.name.as_slice());
+
+    Ok(response)
+}
@@ -1,7 +1,175 @@
+use crate::commit::*;
+use crate::marshal::*;
+use crate::nv::*;
+use crate::session::*;
 use crate::tpm::*;
 use crate::types::*;
 
-pub fn tpm2_startup(tpm: &mut TpmInstance, _args: &StartupArgs) -> Result<(), TpmError> {
+// What Shutdown(STATE) keeps for a TPM Resume: the state Startup(CLEAR)
+// would otherwise reset, the null hierarchy so saved contexts from it
+// still load, which sessions are saved, outstanding commits, and the PCRs
+// that are saved.
+fn marshal_saved_state(tpm: &TpmInstance, buffer: &mut [u8]) -> Result<usize, TpmError> {
+    let h = &tpm.hierarchy;
+    let enables = [h.ph_enable, h.sh_enable, h.eh_enable, h.ph_enable_nv];
+
+    let mut offset = 0;
+    for enable in enables {
+        offset += marshal_u8(&mut buffer[offset..], enable as u8)?;
+    }
+    offset += marshal_tpm2b(&mut buffer[offset..], &h.platform_auth)?;
+    offset += marshal_tpmt_ha(&mut buffer[offset..], &h.platform_policy)?;
+    offset += marshal_bytes(&mut buffer[offset..], &h.null_seed)?;
+    offset += marshal_bytes(&mut buffer[offset..], &h.null_proof)?;
+    offset += marshal_u64(&mut buffer[offset..], tpm.context.object_context_id)?;
+    offset += marshal_u64(&mut buffer[offset..], tpm.context.session_context_id)?;
+    offset += marshal_u32(&mut buffer[offset..], tpm.context.clear_count)?;
+    offset += marshal_u32(&mut buffer[offset..], tpm.context.restart_count)?;
+    offset += marshal_saved_sessions(&mut buffer[offset..], tpm)?;
+    offset += marshal_bytes(&mut buffer[offset..], &tpm.commit.nonce)?;
+    offset += marshal_u64(&mut buffer[offset..], tpm.commit.counter)?;
+    offset += marshal_bytes(&mut buffer[offset..], &tpm.commit.array)?;
+    offset += marshal_bytes(&mut buffer[offset..], &tpm.commit.ephemeral)?;
+    for value in &tpm.pcr.values[..PCR_SAVE] {
+        offset += marshal_bytes(&mut buffer[offset..], value)?;
+    }
+    offset += marshal_u32(&mut buffer[offset..], tpm.pcr.update_counter)?;
+
+    Ok(offset)
+}
+
+fn unmarshal_saved_state(tpm: &mut TpmInstance, buffer: &[u8]) -> Result<(), TpmError> {
+    let mut offset = 0;
+    let h = &mut tpm.hierarchy;
+
+    h.ph_enable = unmarshal_u8(buffer, &mut offset)? != 0;
+    h.sh_enable = unmarshal_u8(buffer, &mut offset)? != 0;
+    h.eh_enable = unmarshal_u8(buffer, &mut offset)? != 0;
+    h.ph_enable_nv = unmarshal_u8(buffer, &mut offset)? != 0;
+    h.platform_auth = unmarshal_tpm2b(buffer, &mut offset)?;
+    h.platform_policy = unmarshal_tpmt_ha(buffer, &mut offset)?;
+    h.null_seed
+        .copy_from_slice(unmarshal_bytes(buffer, &mut offset, PRIMARY_SEED_SIZE)?);
+    h.null_proof
+        .copy_from_slice(unmarshal_bytes(buffer, &mut offset, PROOF_SIZE)?);
+    tpm.context.object_context_id = unmarshal_u64(buffer, &mut offset)?;
+    tpm.context.session_context_id = unmarshal_u64(buffer, &mut offset)?;
+    tpm.context.clear_count = unmarshal_u32(buffer, &mut offset)?;
+    tpm.context.restart_count = unmarshal_u32(buffer, &mut offset)?;
+    unmarshal_saved_sessions(buffer, &mut offset, tpm)?;
+    tpm.commit
+        .nonce
+        .copy_from_slice(unmarshal_bytes(buffer, &mut offset, COMMIT_NONCE_SIZE)?);
+    tpm.commit.counter = unmarshal_u64(buffer, &mut offset)?;
+    tpm.commit
+        .array
+        .copy_from_slice(unmarshal_bytes(buffer, &mut offset, COMMIT_ARRAY_SIZE)?);
+    tpm.commit
+        .ephemeral
+        .copy_from_slice(unmarshal_bytes(buffer, &mut offset, COMMIT_ARRAY_SIZE)?);
+    for value in tpm.pcr.values[..PCR_SAVE].iter_mut() {
+        let size = value.len();
+        value.copy_from_slice(unmarshal_bytes(buffer, &mut offset, size)?);
+    }
+    tpm.pcr.update_counter = unmarshal_u32(buffer, &mut offset)?;
+
+    Ok(())
+}
+
+impl TpmInstance {
+    // Pick up what Shutdown(STATE) saved, for a TPM Resume or Restart. PCRs
+    // that aren't saved start again.
+    fn startup_restore(&mut self) -> Result<(), TpmError> {
+        self.pcr.reset();
+        let mut state = [0u8; ORDERLY_STATE_SIZE];
+        self.nv_read_orderly_state(&mut state)?;
+        unmarshal_saved_state(self, &state)?;
+        self.context.restart_count += 1;
+
+        Ok(())
+    }
+
+    // Once a command after Shutdown changes anything the shutdown saved,
+    // the next Startup can't count on it. Orderly counters clear it as
+    // they're incremented.
+    pub(crate) fn shutdown_check(&mut self) -> Result<(), TpmError> {
+        let changed = match self.nv.shutdown {
+            Shutdown::None => return Ok(()),
+            Shutdown::Clear => false,
+            Shutdown::State => {
+                let mut saved = [0u8; ORDERLY_STATE_SIZE];
+                self.nv_read_orderly_state(&mut saved)?;
+                let mut state = [0u8; ORDERLY_STATE_SIZE];
+                let size = marshal_saved_state(self, &mut state)?;
+                saved[..size] != state[..size]
+            }
+        };
+
+        match changed || self.clock.clock != self.clock.nv_clock {
+            true => self.nv_clear_orderly(),
+            false => Ok(()),
+        }
+    }
+}
+
+pub fn tpm2_startup(tpm: &mut TpmInstance, args: &StartupArgs) -> Result<(), TpmError> {
+    if tpm.started {
+        return Err(TpmError::new(TpmRc::Initialize));
+    }
+
+    let orderly = tpm.nv.shutdown != Shutdown::None;
+    match args.su_type {
+        // After Shutdown(STATE) this is a TPM Restart, which picks up the
+        // saved state like a TPM Resume but otherwise starts like a TPM
+        // Reset. stClear objects saved before it no longer load.
+        StartupType::Clear => {
+            let restart = tpm.nv.shutdown == Shutdown::State;
+
+            tpm.object_flush_all();
+            tpm.session_flush_all();
+            if restart {
+                tpm.startup_restore()?;
+                tpm.context.clear_count += 1;
+            } else {
+                tpm.context_reset()?;
+                tpm.hierarchy_reset();
+                tpm.commit_reset();
+            }
+            tpm.hierarchy_startup_clear();
+            tpm.pcr.reset();
+            tpm.nv_index_startup(orderly)?;
+            tpm.da_startup(!restart, orderly)?;
+        }
+        // TPM Resume, which needs the state from a Shutdown(STATE).
+        StartupType::State => {
+            if tpm.nv.shutdown != Shutdown::State {
+                return Err(TpmError::parameter(TpmRc::Value, 1));
+            }
+
+            tpm.startup_restore()?;
+            tpm.da_startup(false, orderly)?;
+        }
+        _ => return Err(TpmError::parameter(TpmRc::Value, 1)),
+    }
+
+    tpm.clock_startup(orderly)?;
+
+    tpm.nv_clear_orderly()?;
+
     tpm.started = true;
     Ok(())
 }
+
+// Write out the state kept in RAM, so the next Startup can trust it.
+pub fn tpm2_shutdown(tpm: &mut TpmInstance, args: &ShutdownArgs) -> Result<(), TpmError> {
+    let mut state = [0u8; ORDERLY_STATE_SIZE];
+    let (shutdown, size) = match args.su_type {
+        StartupType::Clear => (Shutdown::Clear, 0),
+        StartupType::State => (Shutdown::State, marshal_saved_state(tpm, &mut state)?),
+        _ => return Err(TpmError::parameter(TpmRc::Value, 1)),
+    };
+
+    tpm.nv_index_shutdown()?;
+    tpm.clock_store()?;
+    tpm.nv_shutdown(shutdown, &state[..size])
+}
@@ -0,0 +1,201 @@
+use crate::crypto::hash::{hash, HmacState};
+use crate::crypto::sym::{self, CmacState};
+use crate::hierarchy::Hierarchy;
+use crate::object::*;
+use crate::tpm::*;
+use crate::types::*;
+
+// Hash data in one go. The ticket says the data didn't start with
+// TPM_GENERATED_VALUE, which lets a restricted key sign the digest. There's
+// none for the null hierarchy.
+pub fn tpm2_hash(tpm: &mut TpmInstance, args: &HashArgs) -> Result<HashResponse, TpmError> {
+    let data = args.data.as_slice();
+    let out_hash = hash(args.hash_alg, &[data])?;
+
+    let generated = data.starts_with(&TPM_GENERATED_VALUE.to_be_bytes());
+    let validation = match Hierarchy::from_handle(args.hierarchy) {
+        Some(hierarchy) if hierarchy != Hierarchy::Null && !generated => {
+            tpm.hashcheck_ticket(hierarchy, &out_hash)?
+        }
+        _ => TpmtTkHashcheck {
+            hierarchy: TpmRh::Null as TpmHandle,
+            ..Default::default()
+        },
+    };
+
+    Ok(HashResponse {
+        out_hash,
+        validation,
+    })
+}
+
+// Parameter numbers of mode, ivIn and inData, which differ between the two
+// commands.
+struct EncryptDecryptParameters {
+    mode: u32,
+    iv_in: u32,
+    in_data: u32,
+}
+
+fn encrypt_decrypt(
+    tpm: &mut TpmInstance,
+    args: &EncryptDecryptArgs,
+    parameters: EncryptDecryptParameters,
+) -> Result<EncryptDecryptResponse, TpmError> {
+    let object = loaded_object(tpm, args.key_handle)?;
+    let (sym, key) = match (&object.public.parameters, &object.sensitive.sensitive) {
+        (TpmuPublicParms::SymCipher(parms), TpmuSensitiveComposite::Sym(key)) => (parms.sym, key),
+        _ => return Err(TpmError::handle(TpmRc::Key, 1)),
+    };
+    let allowed = match args.decrypt {
+        true => TPMA_OBJECT_DECRYPT,
+        false => TPMA_OBJECT_SIGN_ENCRYPT,
+    };
+    if object.public.has_attributes(TPMA_OBJECT_RESTRICTED)
+        || !object.public.has_attributes(allowed)
+    {
+        return Err(TpmError::handle(TpmRc::Attributes, 1));
+    }
+
+    // A key with a mode of its own only works in that mode.
+    let mode = match (sym.mode, args.mode) {
+        (TpmAlgId::Null, mode) | (mode, TpmAlgId::Null) => mode,
+        (mode, other) if mode == other => mode,
+        _ => TpmAlgId::Null,
+    };
+    if mode == TpmAlgId::Null {
+        return Err(TpmError::parameter(TpmRc::Mode, parameters.mode));
+    }
+
+    let block_size = sym::block_size(sym.algorithm);
+    let iv_size = match mode {
+        TpmAlgId::Ecb => 0,
+        _ => block_size,
+    };
+    if args.iv_in.size as usize != iv_size {
+        return Err(TpmError::parameter(TpmRc::Size, parameters.iv_in));
+    }
+    let in_size = args.in_data.size as usize;
+    if matches!(mode, TpmAlgId::Cbc | TpmAlgId::Ecb) && !in_size.is_multiple_of(block_size) {
+        return Err(TpmError::parameter(TpmRc::Size, parameters.in_data));
+    }
+
+    let mut response = EncryptDecryptResponse {
+        out_data: args.in_data,
+        iv_out: args.iv_in,
+    };
+    let iv = &mut response.iv_out.buffer[..iv_size];
+    let data = &mut response.out_data.buffer[..in_size];
+    match args.decrypt {
+        true => sym::decrypt(sym.algorithm, mode, key.as_slice(), iv, data)?,
+        false => sym::encrypt(sym.algorithm, mode, key.as_slice(), iv, data)?,
+    }
+
+    Ok(response)
+}
+
+pub fn tpm2_encrypt_decrypt(
+    tpm: &mut TpmInstance,
+    args: &EncryptDecryptArgs,
+) -> Result<EncryptDecryptResponse, TpmError> {
+    let parameters = EncryptDecryptParameters {
+        mode: 2,
+        iv_in: 3,
+        in_data: 4,
+    };
+    encrypt_decrypt(tpm, args, parameters)
+}
+
+pub fn tpm2_encrypt_decrypt2(
+    tpm: &mut TpmInstance,
+    args: &EncryptDecryptArgs,
+) -> Result<EncryptDecryptResponse, TpmError> {
+    let parameters = EncryptDecryptParameters {
+        mode: 3,
+        iv_in: 4,
+        in_data: 1,
+    };
+    encrypt_decrypt(tpm, args, parameters)
+}
+
+// An HMAC or a CMAC, running in one command or across a sequence. There's
+// no heap to box the larger HMAC state on.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+pub enum MacState {
+    Hmac(HmacState),
+    Cmac(CmacState),
+}
+
+impl MacState {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            MacState::Hmac(state) => state.update(data),
+            MacState::Cmac(state) => state.update(data),
+        }
+    }
+
+    pub fn finish(self) -> Tpm2bDigest {
+        match self {
+            MacState::Hmac(state) => state.finish(),
+            MacState::Cmac(state) => state.finish(),
+        }
+    }
+}
+
+// Start a MAC with a keyedHash or symmetric cipher key. The key's own
+// scheme (its HMAC hash, or its mode for a symmetric cipher) is used unless
+// it's TPM_ALG_NULL, and inScheme may only repeat it. Errors are for the key
+// handle (1) and inScheme (2), which are the same in every MAC command.
+pub(crate) fn mac_start(object: &Object, in_scheme: TpmAlgId) -> Result<MacState, TpmError> {
+    let key_scheme = match &object.public.parameters {
+        TpmuPublicParms::KeyedHash(parms) if parms.scheme.scheme == TpmAlgId::Null => {
+            TpmAlgId::Null
+        }
+        TpmuPublicParms::KeyedHash(parms) => parms.scheme.hash_alg,
+        TpmuPublicParms::SymCipher(parms) => parms.sym.mode,
+        _ => return Err(TpmError::handle(TpmRc::Type, 1)),
+    };
+    let scheme = match (key_scheme, in_scheme) {
+        (TpmAlgId::Null, TpmAlgId::Null) => return Err(TpmError::parameter(TpmRc::Value, 2)),
+        (TpmAlgId::Null, scheme) | (scheme, TpmAlgId::Null) => scheme,
+        (scheme, other) if scheme == other => scheme,
+        _ => return Err(TpmError::parameter(TpmRc::Value, 2)),
+    };
+
+    if object.public.has_attributes(TPMA_OBJECT_RESTRICTED) {
+        return Err(TpmError::handle(TpmRc::Attributes, 1));
+    }
+    if !object.public.has_attributes(TPMA_OBJECT_SIGN_ENCRYPT) {
+        return Err(TpmError::handle(TpmRc::Key, 1));
+    }
+
+    match (&object.public.parameters, &object.sensitive.sensitive) {
+        (TpmuPublicParms::KeyedHash(_), TpmuSensitiveComposite::Bits(key)) if scheme.is_hash() => {
+            Ok(MacState::Hmac(HmacState::new(scheme, key.as_slice())?))
+        }
+        (TpmuPublicParms::SymCipher(parms), TpmuSensitiveComposite::Sym(key))
+            if scheme == TpmAlgId::Cmac =>
+        {
+            Ok(MacState::Cmac(CmacState::new(
+                parms.sym.algorithm,
+                key.as_slice(),
+            )?))
+        }
+        (_, TpmuSensitiveComposite::Bits(_) | TpmuSensitiveComposite::Sym(_)) => {
+            Err(TpmError::parameter(TpmRc::Scheme, 2))
+        }
+        _ => Err(TpmError::handle(TpmRc::Key, 1)),
+    }
+}
+
+// TPM2_MAC, and TPM2_HMAC before it, in one go.
+pub fn tpm2_mac(tpm: &mut TpmInstance, args: &MacArgs) -> Result<MacResponse, TpmError> {
+    let object = loaded_object(tpm, args.handle)?;
+    let mut state = mac_start(&object, args.in_scheme)?;
+    state.update(args.buffer.as_slice());
+
+    Ok(MacResponse {
+        out_mac: state.finish(),
+    })
+}
@@ -0,0 +1,71 @@
+use crate::crypto::hash::*;
+use crate::hierarchy::Hierarchy;
+use crate::tpm::*;
+use crate::types::*;
+
+impl TpmInstance {
+    // HMAC(proof, TPM_ST_CREATION || name || creationHash). Tickets from the
+    // null hierarchy use the null proof, so they don't survive a TPM Reset.
+    pub(crate) fn creation_ticket(
+        &self,
+        hierarchy: Hierarchy,
+        name: &Tpm2bName,
+        creation_hash: &Tpm2bDigest,
+    ) -> Result<TpmtTkCreation, TpmError> {
+        let digest = hmac(
+            CONTEXT_INTEGRITY_HASH_ALG,
+            self.hierarchy_proof(hierarchy),
+            &[
+                &TPM_ST_CREATION.to_be_bytes(),
+                name.as_slice(),
+                creation_hash.as_slice(),
+            ],
+        )?;
+
+        Ok(TpmtTkCreation {
+            hierarchy: hierarchy.handle(),
+            digest,
+        })
+    }
+
+    // HMAC(proof, TPM_ST_HASHCHECK || digest)
+    pub(crate) fn hashcheck_ticket(
+        &self,
+        hierarchy: Hierarchy,
+        digest: &Tpm2bDigest,
+    ) -> Result<TpmtTkHashcheck, TpmError> {
+        let digest = hmac(
+            CONTEXT_INTEGRITY_HASH_ALG,
+            self.hierarchy_proof(hierarchy),
+            &[&TPM_ST_HASHCHECK.to_be_bytes(), digest.as_slice()],
+        )?;
+
+        Ok(TpmtTkHashcheck {
+            hierarchy: hierarchy.handle(),
+            digest,
+        })
+    }
+
+    // HMAC(proof, TPM_ST_VERIFIED || digest || keyName)
+    pub(crate) fn verified_ticket(
+        &self,
+        hierarchy: Hierarchy,
+        digest: &Tpm2bDigest,
+        key_name: &Tpm2bName,
+    ) -> Result<TpmtTkVerified, TpmError> {
+        let digest = hmac(
+            CONTEXT_INTEGRITY_HASH_ALG,
+            self.hierarchy_proof(hierarchy),
+            &[
+                &TPM_ST_VERIFIED.to_be_bytes(),
+                digest.as_slice(),
+                key_name.as_slice(),
+            ],
+        )?;
+
+        Ok(TpmtTkVerified {
+            hierarchy: hierarchy.handle(),
+            digest,
+        })
+    }
+}
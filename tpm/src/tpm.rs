@@ -1,31 +1,86 @@
+use crate::asymmetric::*;
+use crate::attest::*;
+use crate::clock::*;
+use crate::commit::*;
+use crate::context::*;
+use crate::credential::*;
+use crate::dictionary_attack::*;
+use crate::duplication::*;
 use crate::format;
 use crate::get_capability::*;
+use crate::hierarchy::*;
 use crate::marshal::*;
+use crate::nv::*;
+use crate::nv_index::*;
+use crate::object::*;
+use crate::pcr::*;
+use crate::persistent::*;
 use crate::platform::*;
+use crate::policy::*;
+use crate::sequence::*;
+use crate::session::*;
+use crate::signature::*;
 use crate::startup::*;
+use crate::symmetric::*;
 use crate::types::*;
 use core::fmt::Arguments;
 
 pub struct TpmInstance {
     pub(crate) started: bool,
     pub(crate) platform: TpmPlatform,
+    pub(crate) hierarchy: HierarchyState,
+    pub(crate) objects: [Option<Object>; MAX_LOADED_OBJECTS],
+    pub(crate) sequences: [Option<Sequence>; MAX_LOADED_OBJECTS],
+    pub(crate) sessions: [Option<Session>; MAX_LOADED_SESSIONS],
+    pub(crate) active_sessions: [ActiveSession; MAX_ACTIVE_SESSIONS],
+    pub(crate) pcr: PcrState,
+    pub(crate) persistent: PersistentState,
+    pub(crate) nv_indices: NvIndexState,
+    pub(crate) context: ContextState,
+    pub(crate) nv: NvState,
+    pub(crate) commit: CommitState,
+    pub(crate) clock: ClockState,
+    pub(crate) da: DaState,
+    // The entities the current command authorized with policy sessions
+    pub(crate) policy_authorized: [Option<TpmHandle>; MAX_SESSION_NUM],
 }
 
 impl Default for TpmInstance {
     fn default() -> TpmInstance {
-        TpmInstance {
-            started: false,
-            platform: TpmPlatform::default(),
-        }
+        TpmInstance::new(&TpmPlatform::default())
     }
 }
 
 impl TpmInstance {
     pub fn new(platform: &TpmPlatform) -> TpmInstance {
-        TpmInstance {
+        let mut tpm = TpmInstance {
             started: false,
             platform: *platform,
+            hierarchy: HierarchyState::default(),
+            objects: [None; MAX_LOADED_OBJECTS],
+            sequences: core::array::from_fn(|_| None),
+            sessions: [None; MAX_LOADED_SESSIONS],
+            active_sessions: [ActiveSession::Free; MAX_ACTIVE_SESSIONS],
+            pcr: PcrState::default(),
+            persistent: PersistentState::default(),
+            nv_indices: NvIndexState::default(),
+            context: ContextState::default(),
+            nv: NvState::default(),
+            commit: CommitState::default(),
+            clock: ClockState {
+                last_time: (platform.get_time)(),
+                ..Default::default()
+            },
+            da: DaState::default(),
+            policy_authorized: [None; MAX_SESSION_NUM],
+        };
+
+        tpm.hierarchy_manufacture();
+        if tpm.nv_restore().is_err() {
+            tpm.log(format_args!("NV unavailable, nothing restored"));
         }
+
+        tpm
     }
 }
 
@@ -40,42 +95,488 @@ impl TpmInstance {
     pub fn dispatch_command(
         &mut self,
         command: &CommandHeader,
+        handles: &[TpmHandle],
         param_buffer: &[u8],
         response_buffer: &mut [u8],
     ) -> Result<usize, TpmError> {
         let mut offset = 0;
+
         match command.command_code {
             TpmCommandCode::Startup => {
-                let args = match unmarshal_startup_args(param_buffer, &mut offset) {
-                    Ok(args) => args,
-                    Err(e) => return Err(e),
-                };
-                match tpm2_startup(self, &args) {
-                    Ok(_) => Ok(0),
-                    Err(e) => Err(e),
-                }
+                let args = unmarshal_startup_args(param_buffer, &mut offset)?;
+                tpm2_startup(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::Shutdown => {
+                let args = unmarshal_shutdown_args(param_buffer, &mut offset)?;
+                tpm2_shutdown(self, &args)?;
+                Ok(0)
             }
             TpmCommandCode::GetCapability => {
-                let args = match unmarshal_get_capability_args(param_buffer, &mut offset) {
-                    Ok(args) => args,
-                    Err(e) => return Err(e),
-                };
+                let args = unmarshal_get_capability_args(param_buffer, &mut offset)?;
 
-                let response = match tpm2_get_capability(self, &args) {
-                    Ok(response) => response,
-                    Err(e) => return Err(e),
-                };
+                let response = tpm2_get_capability(self, &args)?;
 
-                let size = match marshal_get_capability_response(response_buffer, &response) {
-                    Ok(size) => size,
-                    Err(e) => return Err(e),
-                };
+                let size = marshal_get_capability_response(response_buffer, &response)?;
 
                 Ok(size)
             }
-            _ => Err(TpmError {
-                rc: TpmRc::CommandCode,
-            }),
+            TpmCommandCode::HierarchyControl => {
+                let mut args = unmarshal_hierarchy_control_args(param_buffer, &mut offset)?;
+                args.auth_handle = handles[0];
+                tpm2_hierarchy_control(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::SetPrimaryPolicy => {
+                let mut args = unmarshal_set_primary_policy_args(param_buffer, &mut offset)?;
+                args.auth_handle = handles[0];
+                tpm2_set_primary_policy(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::HierarchyChangeAuth => {
+                let mut args = unmarshal_hierarchy_change_auth_args(param_buffer, &mut offset)?;
+                args.auth_handle = handles[0];
+                tpm2_hierarchy_change_auth(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::ChangePps => {
+                tpm2_change_pps(self)?;
+                Ok(0)
+            }
+            TpmCommandCode::ChangeEps => {
+                tpm2_change_eps(self)?;
+                Ok(0)
+            }
+            TpmCommandCode::Clear => {
+                tpm2_clear(self)?;
+                Ok(0)
+            }
+            TpmCommandCode::ClearControl => {
+                let mut args = unmarshal_clear_control_args(param_buffer, &mut offset)?;
+                args.auth = handles[0];
+                tpm2_clear_control(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::DictionaryAttackLockReset => {
+                tpm2_dictionary_attack_lock_reset(self)?;
+                Ok(0)
+            }
+            TpmCommandCode::DictionaryAttackParameters => {
+                let mut args =
+                    unmarshal_dictionary_attack_parameters_args(param_buffer, &mut offset)?;
+                args.lock_handle = handles[0];
+                tpm2_dictionary_attack_parameters(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::CreatePrimary => {
+                let mut args = unmarshal_create_primary_args(param_buffer, &mut offset)?;
+                args.primary_handle = handles[0];
+                let response = tpm2_create_primary(self, &args)?;
+                marshal_create_primary_response(response_buffer, &response)
+            }
+            TpmCommandCode::Create => {
+                let mut args = unmarshal_create_args(param_buffer, &mut offset)?;
+                args.parent_handle = handles[0];
+                let response = tpm2_create(self, &args)?;
+                marshal_create_response(response_buffer, &response)
+            }
+            TpmCommandCode::Load => {
+                let mut args = unmarshal_load_args(param_buffer, &mut offset)?;
+                args.parent_handle = handles[0];
+                let response = tpm2_load(self, &args)?;
+                marshal_load_response(response_buffer, &response)
+            }
+            TpmCommandCode::EvictControl => {
+                let mut args = unmarshal_evict_control_args(param_buffer, &mut offset)?;
+                args.auth = handles[0];
+                args.object_handle = handles[1];
+                tpm2_evict_control(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::NvDefineSpace => {
+                let mut args = unmarshal_nv_define_space_args(param_buffer, &mut offset)?;
+                args.auth_handle = handles[0];
+                tpm2_nv_define_space(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::NvUndefineSpace => {
+                let args = NvUndefineSpaceArgs {
+                    auth_handle: handles[0],
+                    nv_index: handles[1],
+                };
+                tpm2_nv_undefine_space(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::NvUndefineSpaceSpecial => {
+                let args = NvUndefineSpaceSpecialArgs {
+                    nv_index: handles[0],
+                    platform: handles[1],
+                };
+                tpm2_nv_undefine_space_special(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::NvReadPublic => {
+                let args = NvReadPublicArgs {
+                    nv_index: handles[0],
+                };
+                let response = tpm2_nv_read_public(self, &args)?;
+                marshal_nv_read_public_response(response_buffer, &response)
+            }
+            TpmCommandCode::NvWrite => {
+                let mut args = unmarshal_nv_write_args(param_buffer, &mut offset)?;
+                args.auth_handle = handles[0];
+                args.nv_index = handles[1];
+                tpm2_nv_write(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::NvIncrement => {
+                let args = NvIncrementArgs {
+                    auth_handle: handles[0],
+                    nv_index: handles[1],
+                };
+                tpm2_nv_increment(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::NvSetBits => {
+                let mut args = unmarshal_nv_set_bits_args(param_buffer, &mut offset)?;
+                args.auth_handle = handles[0];
+                args.nv_index = handles[1];
+                tpm2_nv_set_bits(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::NvWriteLock => {
+                let args = NvWriteLockArgs {
+                    auth_handle: handles[0],
+                    nv_index: handles[1],
+                };
+                tpm2_nv_write_lock(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::NvGlobalWriteLock => {
+                let args = NvGlobalWriteLockArgs {
+                    auth_handle: handles[0],
+                };
+                tpm2_nv_global_write_lock(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::NvReadLock => {
+                let args = NvReadLockArgs {
+                    auth_handle: handles[0],
+                    nv_index: handles[1],
+                };
+                tpm2_nv_read_lock(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::NvChangeAuth => {
+                let mut args = unmarshal_nv_change_auth_args(param_buffer, &mut offset)?;
+                args.nv_index = handles[0];
+                tpm2_nv_change_auth(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::NvCertify => {
+                let mut args = unmarshal_nv_certify_args(param_buffer, &mut offset)?;
+                args.sign_handle = handles[0];
+                args.auth_handle = handles[1];
+                args.nv_index = handles[2];
+                let response = tpm2_nv_certify(self, &args)?;
+                marshal_nv_certify_response(response_buffer, &response)
+            }
+            TpmCommandCode::Quote => {
+                let mut args = unmarshal_quote_args(param_buffer, &mut offset)?;
+                args.sign_handle = handles[0];
+                let response = tpm2_quote(self, &args)?;
+                marshal_quote_response(response_buffer, &response)
+            }
+            TpmCommandCode::NvExtend => {
+                let mut args = unmarshal_nv_extend_args(param_buffer, &mut offset)?;
+                args.auth_handle = handles[0];
+                args.nv_index = handles[1];
+                tpm2_nv_extend(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::NvRead => {
+                let mut args = unmarshal_nv_read_args(param_buffer, &mut offset)?;
+                args.auth_handle = handles[0];
+                args.nv_index = handles[1];
+                let response = tpm2_nv_read(self, &args)?;
+                marshal_nv_read_response(response_buffer, &response)
+            }
+            TpmCommandCode::ContextSave => {
+                let args = ContextSaveArgs {
+                    save_handle: handles[0],
+                };
+                let response = tpm2_context_save(self, &args)?;
+                marshal_context_save_response(response_buffer, &response)
+            }
+            TpmCommandCode::ContextLoad => {
+                let args = unmarshal_context_load_args(param_buffer, &mut offset)?;
+                let response = tpm2_context_load(self, &args)?;
+                marshal_context_load_response(response_buffer, &response)
+            }
+            TpmCommandCode::FlushContext => {
+                let args = unmarshal_flush_context_args(param_buffer, &mut offset)?;
+                tpm2_flush_context(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::Unseal => {
+                let args = UnsealArgs {
+                    item_handle: handles[0],
+                };
+                let response = tpm2_unseal(self, &args)?;
+                marshal_unseal_response(response_buffer, &response)
+            }
+            TpmCommandCode::RsaEncrypt => {
+                let mut args = unmarshal_rsa_encrypt_args(param_buffer, &mut offset)?;
+                args.key_handle = handles[0];
+                let response = tpm2_rsa_encrypt(self, &args)?;
+                marshal_rsa_encrypt_response(response_buffer, &response)
+            }
+            TpmCommandCode::RsaDecrypt => {
+                let mut args = unmarshal_rsa_decrypt_args(param_buffer, &mut offset)?;
+                args.key_handle = handles[0];
+                let response = tpm2_rsa_decrypt(self, &args)?;
+                marshal_rsa_decrypt_response(response_buffer, &response)
+            }
+            TpmCommandCode::EccParameters => {
+                let args = unmarshal_ecc_parameters_args(param_buffer, &mut offset)?;
+                let response = tpm2_ecc_parameters(self, &args)?;
+                marshal_ecc_parameters_response(response_buffer, &response)
+            }
+            TpmCommandCode::EcdhKeyGen => {
+                let args = EcdhKeyGenArgs {
+                    key_handle: handles[0],
+                };
+                let response = tpm2_ecdh_key_gen(self, &args)?;
+                marshal_ecdh_key_gen_response(response_buffer, &response)
+            }
+            TpmCommandCode::EcdhZGen => {
+                let mut args = unmarshal_ecdh_zgen_args(param_buffer, &mut offset)?;
+                args.key_handle = handles[0];
+                let response = tpm2_ecdh_zgen(self, &args)?;
+                marshal_ecdh_zgen_response(response_buffer, &response)
+            }
+            TpmCommandCode::ZGen2Phase => {
+                let mut args = unmarshal_zgen_2phase_args(param_buffer, &mut offset)?;
+                args.key_a = handles[0];
+                let response = tpm2_zgen_2phase(self, &args)?;
+                marshal_zgen_2phase_response(response_buffer, &response)
+            }
+            TpmCommandCode::EcEphemeral => {
+                let args = unmarshal_ec_ephemeral_args(param_buffer, &mut offset)?;
+                let response = tpm2_ec_ephemeral(self, &args)?;
+                marshal_ec_ephemeral_response(response_buffer, &response)
+            }
+            TpmCommandCode::Commit => {
+                let mut args = unmarshal_commit_args(param_buffer, &mut offset)?;
+                args.sign_handle = handles[0];
+                let response = tpm2_commit(self, &args)?;
+                marshal_commit_response(response_buffer, &response)
+            }
+            TpmCommandCode::Sign => {
+                let mut args = unmarshal_sign_args(param_buffer, &mut offset)?;
+                args.key_handle = handles[0];
+                let response = tpm2_sign(self, &args)?;
+                marshal_sign_response(response_buffer, &response)
+            }
+            TpmCommandCode::VerifySignature => {
+                let mut args = unmarshal_verify_signature_args(param_buffer, &mut offset)?;
+                args.key_handle = handles[0];
+                let response = tpm2_verify_signature(self, &args)?;
+                marshal_verify_signature_response(response_buffer, &response)
+            }
+            TpmCommandCode::EncryptDecrypt => {
+                let mut args = unmarshal_encrypt_decrypt_args(param_buffer, &mut offset)?;
+                args.key_handle = handles[0];
+                let response = tpm2_encrypt_decrypt(self, &args)?;
+                marshal_encrypt_decrypt_response(response_buffer, &response)
+            }
+            TpmCommandCode::EncryptDecrypt2 => {
+                let mut args = unmarshal_encrypt_decrypt2_args(param_buffer, &mut offset)?;
+                args.key_handle = handles[0];
+                let response = tpm2_encrypt_decrypt2(self, &args)?;
+                marshal_encrypt_decrypt_response(response_buffer, &response)
+            }
+            TpmCommandCode::Hash => {
+                let args = unmarshal_hash_args(param_buffer, &mut offset)?;
+                let response = tpm2_hash(self, &args)?;
+                marshal_hash_response(response_buffer, &response)
+            }
+            TpmCommandCode::Mac => {
+                let mut args = unmarshal_mac_args(param_buffer, &mut offset)?;
+                args.handle = handles[0];
+                let response = tpm2_mac(self, &args)?;
+                marshal_mac_response(response_buffer, &response)
+            }
+            TpmCommandCode::MacStart => {
+                let mut args = unmarshal_mac_start_args(param_buffer, &mut offset)?;
+                args.handle = handles[0];
+                let response = tpm2_mac_start(self, &args)?;
+                marshal_mac_start_response(response_buffer, &response)
+            }
+            TpmCommandCode::SequenceUpdate => {
+                let mut args = unmarshal_sequence_update_args(param_buffer, &mut offset)?;
+                args.sequence_handle = handles[0];
+                tpm2_sequence_update(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::SequenceComplete => {
+                let mut args = unmarshal_sequence_complete_args(param_buffer, &mut offset)?;
+                args.sequence_handle = handles[0];
+                let response = tpm2_sequence_complete(self, &args)?;
+                marshal_sequence_complete_response(response_buffer, &response)
+            }
+            TpmCommandCode::MakeCredential => {
+                let mut args = unmarshal_make_credential_args(param_buffer, &mut offset)?;
+                args.handle = handles[0];
+                let response = tpm2_make_credential(self, &args)?;
+                marshal_make_credential_response(response_buffer, &response)
+            }
+            TpmCommandCode::ActivateCredential => {
+                let mut args = unmarshal_activate_credential_args(param_buffer, &mut offset)?;
+                args.activate_handle = handles[0];
+                args.key_handle = handles[1];
+                let response = tpm2_activate_credential(self, &args)?;
+                marshal_activate_credential_response(response_buffer, &response)
+            }
+            TpmCommandCode::Duplicate => {
+                let mut args = unmarshal_duplicate_args(param_buffer, &mut offset)?;
+                args.object_handle = handles[0];
+                args.new_parent_handle = handles[1];
+                let response = tpm2_duplicate(self, &args)?;
+                marshal_duplicate_response(response_buffer, &response)
+            }
+            TpmCommandCode::Import => {
+                let mut args = unmarshal_import_args(param_buffer, &mut offset)?;
+                args.parent_handle = handles[0];
+                let response = tpm2_import(self, &args)?;
+                marshal_import_response(response_buffer, &response)
+            }
+            TpmCommandCode::Rewrap => {
+                let mut args = unmarshal_rewrap_args(param_buffer, &mut offset)?;
+                args.old_parent = handles[0];
+                args.new_parent = handles[1];
+                let response = tpm2_rewrap(self, &args)?;
+                marshal_rewrap_response(response_buffer, &response)
+            }
+            TpmCommandCode::LoadExternal => {
+                let args = unmarshal_load_external_args(param_buffer, &mut offset)?;
+                let response = tpm2_load_external(self, &args)?;
+                marshal_load_external_response(response_buffer, &response)
+            }
+            TpmCommandCode::ReadPublic => {
+                let args = ReadPublicArgs {
+                    object_handle: handles[0],
+                };
+                let response = tpm2_read_public(self, &args)?;
+                marshal_read_public_response(response_buffer, &response)
+            }
+            TpmCommandCode::ObjectChangeAuth => {
+                let mut args = unmarshal_object_change_auth_args(param_buffer, &mut offset)?;
+                args.object_handle = handles[0];
+                args.parent_handle = handles[1];
+                let response = tpm2_object_change_auth(self, &args)?;
+                marshal_object_change_auth_response(response_buffer, &response)
+            }
+            TpmCommandCode::CreateLoaded => {
+                let mut args = unmarshal_create_loaded_args(param_buffer, &mut offset)?;
+                args.parent_handle = handles[0];
+                let response = tpm2_create_loaded(self, &args)?;
+                marshal_create_loaded_response(response_buffer, &response)
+            }
+            TpmCommandCode::StartAuthSession => {
+                let mut args = unmarshal_start_auth_session_args(param_buffer, &mut offset)?;
+                args.tpm_key = handles[0];
+                args.bind = handles[1];
+                let response = tpm2_start_auth_session(self, &args)?;
+                marshal_start_auth_session_response(response_buffer, &response)
+            }
+            TpmCommandCode::PolicyRestart => {
+                let args = PolicySessionArgs {
+                    policy_session: handles[0],
+                };
+                tpm2_policy_restart(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::ReadClock => {
+                let response = tpm2_read_clock(self)?;
+                marshal_read_clock_response(response_buffer, &response)
+            }
+            TpmCommandCode::PcrExtend => {
+                let mut args = unmarshal_pcr_extend_args(param_buffer, &mut offset)?;
+                args.pcr_handle = handles[0];
+                tpm2_pcr_extend(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::PcrRead => {
+                let args = unmarshal_pcr_read_args(param_buffer, &mut offset)?;
+                let response = tpm2_pcr_read(self, &args)?;
+                marshal_pcr_read_response(response_buffer, &response)
+            }
+            TpmCommandCode::PolicyGetDigest => {
+                let args = PolicySessionArgs {
+                    policy_session: handles[0],
+                };
+                let response = tpm2_policy_get_digest(self, &args)?;
+                marshal_policy_get_digest_response(response_buffer, &response)
+            }
+            TpmCommandCode::PolicyCommandCode => {
+                let mut args = unmarshal_policy_command_code_args(param_buffer, &mut offset)?;
+                args.policy_session = handles[0];
+                tpm2_policy_command_code(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::PolicyPassword => {
+                let args = PolicySessionArgs {
+                    policy_session: handles[0],
+                };
+                tpm2_policy_password(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::PolicyAuthValue => {
+                let args = PolicySessionArgs {
+                    policy_session: handles[0],
+                };
+                tpm2_policy_auth_value(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::PolicyOr => {
+                let mut args = unmarshal_policy_or_args(param_buffer, &mut offset)?;
+                args.policy_session = handles[0];
+                tpm2_policy_or(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::PolicySecret => {
+                let mut args = unmarshal_policy_secret_args(param_buffer, &mut offset)?;
+                args.auth_handle = handles[0];
+                args.policy_session = handles[1];
+                let response = tpm2_policy_secret(self, &args)?;
+                marshal_policy_secret_response(response_buffer, &response)
+            }
+            TpmCommandCode::PolicyCpHash => {
+                let mut args = unmarshal_policy_cp_hash_args(param_buffer, &mut offset)?;
+                args.policy_session = handles[0];
+                tpm2_policy_cp_hash(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::PolicyNameHash => {
+                let mut args = unmarshal_policy_name_hash_args(param_buffer, &mut offset)?;
+                args.policy_session = handles[0];
+                tpm2_policy_name_hash(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::PolicyPcr => {
+                let mut args = unmarshal_policy_pcr_args(param_buffer, &mut offset)?;
+                args.policy_session = handles[0];
+                tpm2_policy_pcr(self, &args)?;
+                Ok(0)
+            }
+            TpmCommandCode::PolicyDuplicationSelect => {
+                let mut args = unmarshal_policy_duplication_select_args(param_buffer, &mut offset)?;
+                args.policy_session = handles[0];
+                tpm2_policy_duplication_select(self, &args)?;
+                Ok(0)
+            }
+            _ => Err(TpmError::new(TpmRc::CommandCode)),
         }
     }
 }
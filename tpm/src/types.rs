@@ -1,72 +1,384 @@
-use core::fmt::{Display, Error, Formatter};
+use core::fmt::{Debug, Display, Error, Formatter};
 
+// Which handle, parameter or session a format-one response code refers to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RcIndex {
+    None,
+    Handle(u32),
+    Parameter(u32),
+    Session(u32),
+}
+
+#[derive(Clone, Copy)]
 pub struct TpmError {
     pub rc: TpmRc,
+    pub index: RcIndex,
+}
+
+impl TpmError {
+    pub fn new(rc: TpmRc) -> TpmError {
+        TpmError {
+            rc,
+            index: RcIndex::None,
+        }
+    }
+
+    pub fn handle(rc: TpmRc, n: u32) -> TpmError {
+        TpmError::new(rc).with_index(RcIndex::Handle(n))
+    }
+
+    pub fn parameter(rc: TpmRc, n: u32) -> TpmError {
+        TpmError::new(rc).with_index(RcIndex::Parameter(n))
+    }
+
+    pub fn session(rc: TpmRc, n: u32) -> TpmError {
+        TpmError::new(rc).with_index(RcIndex::Session(n))
+    }
+
+    // Attach an index to an error that doesn't already have one. Only
+    // format-one response codes can carry an index, so for anything else
+    // this is a no-op.
+    pub fn with_index(self, index: RcIndex) -> TpmError {
+        let indexable =
+            self.rc.is_format_one() || matches!(self.rc, TpmRc::ReferenceH0 | TpmRc::ReferenceS0);
+        if self.index != RcIndex::None || !indexable {
+            return self;
+        }
+
+        TpmError { rc: self.rc, index }
+    }
+
+    pub fn with_parameter(self, n: u32) -> TpmError {
+        self.with_index(RcIndex::Parameter(n))
+    }
+
+    pub fn with_handle(self, n: u32) -> TpmError {
+        self.with_index(RcIndex::Handle(n))
+    }
+
+    // The response code as it goes on the wire.
+    pub fn code(&self) -> u32 {
+        let rc = self.rc as u32;
+
+        // The reference warnings have one code per handle or session slot
+        // rather than an index field.
+        if let TpmRc::ReferenceH0 | TpmRc::ReferenceS0 = self.rc {
+            return match self.index {
+                RcIndex::Handle(n) | RcIndex::Session(n) => rc + n - 1,
+                _ => rc,
+            };
+        }
+        // Other format-zero codes, like TPM_RC_LOCKOUT, have no index.
+        if !self.rc.is_format_one() {
+            return rc;
+        }
+
+        match self.index {
+            RcIndex::None => rc,
+            RcIndex::Handle(n) => rc | (n << 8),
+            RcIndex::Parameter(n) => rc | RC_P | (n << 8),
+            RcIndex::Session(n) => rc | RC_S | (n << 8),
+        }
+    }
 }
 
 impl Display for TpmError {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        write!(f, "TPM Error {:#04x}", self.rc as u32)
+        write!(f, "TPM Error {:#04x}", self.code())
+    }
+}
+
+// The response code is all there is to show, so unwrap() panics with the
+// same message as Display.
+impl Debug for TpmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        Display::fmt(self, f)
     }
 }
 
-// TODO: Fill in all TPM response codes. Should also have some helpers for
-// building response codes for different layers.
-#[derive(Copy, Clone)]
+const RC_FMT1: u32 = 0x080;
+const RC_P: u32 = 0x040;
+const RC_S: u32 = 0x800;
+
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
 #[repr(u32)]
 pub enum TpmRc {
+    #[default]
     Success = 0x0,
     BadTag = 0x1E,
-    Value = 0x84,
-    Insufficient = 0x9A,
+
+    // Format-zero errors
     Initialize = 0x100,
+    Failure = 0x101,
+    Sequence = 0x103,
+    Disabled = 0x120,
+    Exclusive = 0x121,
+    AuthType = 0x124,
+    AuthMissing = 0x125,
+    Policy = 0x126,
+    Pcr = 0x127,
+    PcrChanged = 0x128,
+    Upgrade = 0x12D,
+    TooManyContexts = 0x12E,
+    AuthUnavailable = 0x12F,
+    Reboot = 0x130,
+    Unbalanced = 0x131,
+    CommandSize = 0x142,
     CommandCode = 0x143,
+    AuthSize = 0x144,
+    AuthContext = 0x145,
+    NvRange = 0x146,
+    NvSize = 0x147,
+    NvLocked = 0x148,
+    NvAuthorization = 0x149,
+    NvUninitialized = 0x14A,
+    NvSpace = 0x14B,
+    NvDefined = 0x14C,
+    BadContext = 0x150,
+    CpHash = 0x151,
+    Parent = 0x152,
+    NeedsTest = 0x153,
+    NoResult = 0x154,
+    Sensitive = 0x155,
+
+    // Format-one errors
+    Asymmetric = 0x81,
+    Attributes = 0x82,
+    Hash = 0x83,
+    Value = 0x84,
+    Hierarchy = 0x85,
+    KeySize = 0x87,
+    Mgf = 0x88,
+    Mode = 0x89,
+    Type = 0x8A,
+    Handle = 0x8B,
+    Kdf = 0x8C,
+    Range = 0x8D,
+    AuthFail = 0x8E,
+    Nonce = 0x8F,
+    Pp = 0x90,
+    Scheme = 0x92,
+    Size = 0x95,
+    Symmetric = 0x96,
+    Tag = 0x97,
+    Selector = 0x98,
+    Insufficient = 0x9A,
+    Signature = 0x9B,
+    Key = 0x9C,
+    PolicyFail = 0x9D,
+    Integrity = 0x9F,
+    Ticket = 0xA0,
+    ReservedBits = 0xA1,
+    BadAuth = 0xA2,
+    Expired = 0xA3,
+    PolicyCc = 0xA4,
+    Binding = 0xA5,
+    Curve = 0xA6,
+    EccPoint = 0xA7,
+
+    // Warnings
+    ContextGap = 0x901,
+    ObjectMemory = 0x902,
+    SessionMemory = 0x903,
+    Memory = 0x904,
+    SessionHandles = 0x905,
+    ObjectHandles = 0x906,
+    Locality = 0x907,
+    Yielded = 0x908,
+    Canceled = 0x909,
+    Testing = 0x90A,
+    ReferenceH0 = 0x910,
+    ReferenceS0 = 0x918,
+    NvRate = 0x920,
+    Lockout = 0x921,
+    Retry = 0x922,
+    NvUnavailable = 0x923,
 }
 
-impl Default for TpmRc {
-    fn default() -> Self {
-        TpmRc::Success
+impl TpmRc {
+    pub fn is_format_one(&self) -> bool {
+        (*self as u32) & RC_FMT1 != 0 && (*self as u32) < 0x100
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
 #[repr(u32)]
 pub enum TpmCommandCode {
+    NvUndefineSpaceSpecial = 0x11F,
+    EvictControl = 0x120,
+    NvUndefineSpace = 0x122,
+    HierarchyControl = 0x121,
+    ChangeEps = 0x124,
+    ChangePps = 0x125,
+    Clear = 0x126,
+    ClearControl = 0x127,
+    HierarchyChangeAuth = 0x129,
+    NvDefineSpace = 0x12A,
+    SetPrimaryPolicy = 0x12E,
+    CreatePrimary = 0x131,
+    NvGlobalWriteLock = 0x132,
+    NvIncrement = 0x134,
+    NvSetBits = 0x135,
+    NvExtend = 0x136,
+    NvWrite = 0x137,
+    NvWriteLock = 0x138,
+    DictionaryAttackLockReset = 0x139,
+    DictionaryAttackParameters = 0x13A,
+    NvChangeAuth = 0x13B,
+    SequenceComplete = 0x13E,
+    ObjectChangeAuth = 0x150,
+    PolicySecret = 0x151,
+    Rewrap = 0x152,
+    Create = 0x153,
+    EcdhZGen = 0x154,
+    // TPM2_HMAC and TPM2_HMAC_Start until TPM2_MAC and TPM2_MAC_Start took
+    // over their codes.
+    Mac = 0x155,
+    Import = 0x156,
+    Load = 0x157,
+    Quote = 0x158,
+    RsaDecrypt = 0x159,
+    MacStart = 0x15B,
+    SequenceUpdate = 0x15C,
+    Sign = 0x15D,
+    Unseal = 0x15E,
+    ContextLoad = 0x161,
+    ContextSave = 0x162,
+    EcdhKeyGen = 0x163,
+    EncryptDecrypt = 0x164,
+    PolicyAuthValue = 0x16B,
+    PolicyCommandCode = 0x16C,
+    PolicyCpHash = 0x16E,
+    PolicyNameHash = 0x170,
+    PolicyOr = 0x171,
+    StartAuthSession = 0x176,
+    PolicyRestart = 0x180,
+    ReadClock = 0x181,
+    PolicyDuplicationSelect = 0x188,
+    PolicyGetDigest = 0x189,
+    PolicyPassword = 0x18C,
     Startup = 0x144,
+    Shutdown = 0x145,
+    ActivateCredential = 0x147,
+    Duplicate = 0x14B,
+    NvRead = 0x14E,
+    NvReadLock = 0x14F,
+    LoadExternal = 0x167,
+    MakeCredential = 0x168,
+    ReadPublic = 0x173,
+    RsaEncrypt = 0x174,
+    VerifySignature = 0x177,
+    EccParameters = 0x178,
+    FlushContext = 0x165,
+    NvReadPublic = 0x169,
     GetCapability = 0x17a,
+    Hash = 0x17D,
+    PcrRead = 0x17E,
+    PolicyPcr = 0x17F,
+    PcrExtend = 0x182,
+    NvCertify = 0x184,
+    Commit = 0x18B,
+    ZGen2Phase = 0x18D,
+    EcEphemeral = 0x18E,
+    CreateLoaded = 0x191,
+    EncryptDecrypt2 = 0x193,
+    #[default]
     Unknown,
 }
 
-impl Default for TpmCommandCode {
-    fn default() -> Self {
-        TpmCommandCode::Unknown
-    }
-}
-
 impl From<u32> for TpmCommandCode {
     fn from(n: u32) -> TpmCommandCode {
         match n {
+            0x11F => TpmCommandCode::NvUndefineSpaceSpecial,
+            0x120 => TpmCommandCode::EvictControl,
+            0x121 => TpmCommandCode::HierarchyControl,
+            0x122 => TpmCommandCode::NvUndefineSpace,
+            0x124 => TpmCommandCode::ChangeEps,
+            0x125 => TpmCommandCode::ChangePps,
+            0x126 => TpmCommandCode::Clear,
+            0x127 => TpmCommandCode::ClearControl,
+            0x129 => TpmCommandCode::HierarchyChangeAuth,
+            0x12A => TpmCommandCode::NvDefineSpace,
+            0x12E => TpmCommandCode::SetPrimaryPolicy,
+            0x131 => TpmCommandCode::CreatePrimary,
+            0x132 => TpmCommandCode::NvGlobalWriteLock,
+            0x134 => TpmCommandCode::NvIncrement,
+            0x135 => TpmCommandCode::NvSetBits,
+            0x136 => TpmCommandCode::NvExtend,
+            0x137 => TpmCommandCode::NvWrite,
+            0x138 => TpmCommandCode::NvWriteLock,
+            0x139 => TpmCommandCode::DictionaryAttackLockReset,
+            0x13A => TpmCommandCode::DictionaryAttackParameters,
+            0x13B => TpmCommandCode::NvChangeAuth,
+            0x13E => TpmCommandCode::SequenceComplete,
+            0x150 => TpmCommandCode::ObjectChangeAuth,
+            0x151 => TpmCommandCode::PolicySecret,
+            0x152 => TpmCommandCode::Rewrap,
+            0x153 => TpmCommandCode::Create,
+            0x154 => TpmCommandCode::EcdhZGen,
+            0x155 => TpmCommandCode::Mac,
+            0x156 => TpmCommandCode::Import,
+            0x157 => TpmCommandCode::Load,
+            0x158 => TpmCommandCode::Quote,
+            0x159 => TpmCommandCode::RsaDecrypt,
+            0x15B => TpmCommandCode::MacStart,
+            0x15C => TpmCommandCode::SequenceUpdate,
+            0x15D => TpmCommandCode::Sign,
+            0x15E => TpmCommandCode::Unseal,
+            0x161 => TpmCommandCode::ContextLoad,
+            0x162 => TpmCommandCode::ContextSave,
+            0x163 => TpmCommandCode::EcdhKeyGen,
+            0x164 => TpmCommandCode::EncryptDecrypt,
+            0x16B => TpmCommandCode::PolicyAuthValue,
+            0x16C => TpmCommandCode::PolicyCommandCode,
+            0x16E => TpmCommandCode::PolicyCpHash,
+            0x170 => TpmCommandCode::PolicyNameHash,
+            0x171 => TpmCommandCode::PolicyOr,
+            0x176 => TpmCommandCode::StartAuthSession,
+            0x180 => TpmCommandCode::PolicyRestart,
+            0x181 => TpmCommandCode::ReadClock,
+            0x188 => TpmCommandCode::PolicyDuplicationSelect,
+            0x189 => TpmCommandCode::PolicyGetDigest,
+            0x18C => TpmCommandCode::PolicyPassword,
             0x144 => TpmCommandCode::Startup,
+            0x145 => TpmCommandCode::Shutdown,
+            0x147 => TpmCommandCode::ActivateCredential,
+            0x14B => TpmCommandCode::Duplicate,
+            0x14E => TpmCommandCode::NvRead,
+            0x14F => TpmCommandCode::NvReadLock,
+            0x167 => TpmCommandCode::LoadExternal,
+            0x168 => TpmCommandCode::MakeCredential,
+            0x173 => TpmCommandCode::ReadPublic,
+            0x174 => TpmCommandCode::RsaEncrypt,
+            0x177 => TpmCommandCode::VerifySignature,
+            0x178 => TpmCommandCode::EccParameters,
+            0x165 => TpmCommandCode::FlushContext,
+            0x169 => TpmCommandCode::NvReadPublic,
             0x17a => TpmCommandCode::GetCapability,
+            0x17D => TpmCommandCode::Hash,
+            0x17E => TpmCommandCode::PcrRead,
+            0x17F => TpmCommandCode::PolicyPcr,
+            0x182 => TpmCommandCode::PcrExtend,
+            0x184 => TpmCommandCode::NvCertify,
+            0x18B => TpmCommandCode::Commit,
+            0x18D => TpmCommandCode::ZGen2Phase,
+            0x18E => TpmCommandCode::EcEphemeral,
+            0x191 => TpmCommandCode::CreateLoaded,
+            0x193 => TpmCommandCode::EncryptDecrypt2,
             _ => TpmCommandCode::Unknown,
         }
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default)]
 #[repr(u16)]
 pub enum TpmCommandTag {
     NoSessions = 0x8001,
     Sessions = 0x8002,
+    #[default]
     Unknown,
 }
 
-impl Default for TpmCommandTag {
-    fn default() -> Self {
-        TpmCommandTag::Unknown
-    }
-}
-
 impl From<u16> for TpmCommandTag {
     fn from(n: u16) -> TpmCommandTag {
         match n {
@@ -77,20 +389,15 @@ impl From<u16> for TpmCommandTag {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default)]
 #[repr(u16)]
 pub enum StartupType {
     Clear = 0x0,
     State = 0x1,
+    #[default]
     Unknown,
 }
 
-impl Default for StartupType {
-    fn default() -> Self {
-        StartupType::Unknown
-    }
-}
-
 impl From<u16> for StartupType {
     fn from(n: u16) -> StartupType {
         match n {
@@ -101,6 +408,28 @@ impl From<u16> for StartupType {
     }
 }
 
+// TPM_SE
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TpmSe {
+    Hmac = 0x0,
+    Policy = 0x1,
+    Trial = 0x3,
+    #[default]
+    Unknown,
+}
+
+impl From<u8> for TpmSe {
+    fn from(n: u8) -> TpmSe {
+        match n {
+            0x0 => TpmSe::Hmac,
+            0x1 => TpmSe::Policy,
+            0x3 => TpmSe::Trial,
+            _ => TpmSe::Unknown,
+        }
+    }
+}
+
 pub const COMMAND_HDR_SIZE: usize = 2 + 4 + 4;
 pub const RESPONSE_HDR_SIZE: usize = 2 + 4 + 4;
 pub const MAX_MSG_SIZE: usize = 4096;
@@ -114,50 +443,747 @@ pub struct CommandHeader {
 pub struct ResponseHeader {
     pub tag: TpmCommandTag,
     pub size: u32,
-    pub rc: TpmRc,
+    pub rc: u32,
 }
 
 // TODO: Calculate this like mstpm does
 pub const MAX_TPM_PROPERTIES: usize = 8;
+pub const MAX_CAP_HANDLES: usize = 16;
+pub const MAX_CAP_CURVES: usize = 8;
+
+pub const MAX_DIGEST_SIZE: usize = 64;
+pub const PRIMARY_SEED_SIZE: usize = 32;
+pub const PROOF_SIZE: usize = 32;
+pub const MAX_RSA_KEY_BYTES: usize = 512;
+pub const MAX_ECC_KEY_BYTES: usize = 66;
+pub const MAX_SYM_KEY_BYTES: usize = 32;
+pub const MAX_SYM_DATA: usize = 128;
+pub const MAX_SYM_BLOCK_SIZE: usize = 16;
+
+// Number of transient object slots. Builds for small devices can lower it by
+// setting TPM_MAX_LOADED_OBJECTS in the environment at build time. Loading
+// anything under a parent needs room for both.
+pub const MAX_LOADED_OBJECTS: usize = match option_env!("TPM_MAX_LOADED_OBJECTS") {
+    Some(n) => parse_build_param(n),
+    None => 3,
+};
+const _: () = assert!(
+    MAX_LOADED_OBJECTS >= 2,
+    "TPM_MAX_LOADED_OBJECTS must be at least 2"
+);
+
+// Number of session slots, set with TPM_MAX_LOADED_SESSIONS. A command can
+// use up to three sessions.
+pub const MAX_LOADED_SESSIONS: usize = match option_env!("TPM_MAX_LOADED_SESSIONS") {
+    Some(n) => parse_build_param(n),
+    None => 3,
+};
+const _: () = assert!(
+    MAX_LOADED_SESSIONS >= 1,
+    "TPM_MAX_LOADED_SESSIONS must be at least 1"
+);
+
+// Number of session handles, set with TPM_MAX_ACTIVE_SESSIONS. A session
+// whose context is saved keeps its handle but not its slot.
+pub const MAX_ACTIVE_SESSIONS: usize = match option_env!("TPM_MAX_ACTIVE_SESSIONS") {
+    Some(n) => parse_build_param(n),
+    None => 64,
+};
+const _: () = assert!(
+    MAX_ACTIVE_SESSIONS >= MAX_LOADED_SESSIONS,
+    "TPM_MAX_ACTIVE_SESSIONS must be at least TPM_MAX_LOADED_SESSIONS"
+);
+
+// NV kept for the seeds, proofs and other persistent hierarchy state
+pub const HIERARCHY_NV_SIZE: usize = 1024;
+
+// NV for the dictionary attack parameters and failure count
+pub const DA_NV_SIZE: usize = 32;
+
+// Number of persistent objects the platform's NV has room for, set with
+// TPM_MAX_PERSISTENT_OBJECTS. Each one takes PERSISTENT_OBJECT_NV_SIZE bytes.
+pub const MAX_PERSISTENT_OBJECTS: usize = match option_env!("TPM_MAX_PERSISTENT_OBJECTS") {
+    Some(n) => parse_build_param(n),
+    None => 7,
+};
+pub const PERSISTENT_OBJECT_NV_SIZE: usize = 1536;
+
+// Number of NV indices that can be defined, set with TPM_MAX_NV_INDICES.
+// Each one takes NV_INDEX_NV_SIZE bytes: its public area and authValue,
+// then room for the largest index.
+pub const MAX_NV_INDICES: usize = match option_env!("TPM_MAX_NV_INDICES") {
+    Some(n) => parse_build_param(n),
+    None => 8,
+};
+pub const MAX_NV_INDEX_SIZE: usize = 2048;
+pub const NV_INDEX_HEADER_NV_SIZE: usize = 256;
+pub const NV_INDEX_NV_SIZE: usize = NV_INDEX_HEADER_NV_SIZE + MAX_NV_INDEX_SIZE;
+
+// Largest amount of NV index data a single command reads or writes
+pub const MAX_NV_BUFFER_SIZE: usize = 1024;
+
+// Largest amount of data a single command hashes
+pub const MAX_DIGEST_BUFFER: usize = 1024;
+
+// NV for what TPM2_Shutdown saves for the next Startup, with room for the
+// saved PCRs and pcrUpdateCounter
+pub const ORDERLY_NV_SIZE: usize = 512 + PCR_SAVE * 32 + 4;
+
+// Commands that write NV the TPM accepts between power on and power off,
+// set with TPM_NV_WRITE_BUDGET. Once they're used up such commands fail
+// with TPM_RC_NV_RATE. 0 means no limit.
+pub const NV_WRITE_BUDGET: usize = match option_env!("TPM_NV_WRITE_BUDGET") {
+    Some(n) => parse_build_param(n),
+    None => 0,
+};
+
+// Commands that write NV the TPM accepts over its life, set with
+// TPM_NV_ENDURANCE. After that NV is worn out and such commands fail with
+// TPM_RC_NV_UNAVAILABLE. 0 means no limit.
+pub const NV_ENDURANCE: usize = match option_env!("TPM_NV_ENDURANCE") {
+    Some(n) => parse_build_param(n),
+    None => 0,
+};
+
+// Size of the NV memory the platform provides. The highest value any
+// counter index has had, the TPM Reset count and Clock come before the
+// dictionary attack and orderly state.
+pub const NV_MEMORY_SIZE: usize = HIERARCHY_NV_SIZE
+    + MAX_PERSISTENT_OBJECTS * PERSISTENT_OBJECT_NV_SIZE
+    + MAX_NV_INDICES * NV_INDEX_NV_SIZE
+    + 24
+    + DA_NV_SIZE
+    + ORDERLY_NV_SIZE;
+
+const fn parse_build_param(s: &str) -> usize {
+    let digits = s.as_bytes();
+    assert!(!digits.is_empty(), "build parameter must be a number");
+
+    let mut n = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        assert!(
+            digits[i].is_ascii_digit(),
+            "build parameter must be a number"
+        );
+        n = n * 10 + (digits[i] - b'0') as usize;
+        i += 1;
+    }
+
+    n
+}
+
+// Upper bounds on marshaled structures that get hashed
+pub const MAX_PUBLIC_SIZE: usize = 1024;
+pub const MAX_CREATION_DATA_SIZE: usize = 512;
+
+// An integrity HMAC plus an encrypted TPM2B_SENSITIVE
+pub const MAX_PRIVATE_SIZE: usize = 512;
+// An integrity HMAC plus an encrypted saved object
+pub const MAX_CONTEXT_SIZE: usize = 2048;
+// Big enough for a TPMS_ATTEST certifying a full NV buffer
+pub const MAX_ATTEST_SIZE: usize = 1280;
+pub const LABEL_MAX_BUFFER: usize = 32;
+
+// Hash used for tickets and, later, context integrity
+pub const CONTEXT_INTEGRITY_HASH_ALG: TpmAlgId = TpmAlgId::Sha256;
+
+// Structure tags that aren't command tags
+pub const TPM_ST_ATTEST_NV: u16 = 0x8014;
+pub const TPM_ST_ATTEST_QUOTE: u16 = 0x8018;
+pub const TPM_ST_CREATION: u16 = 0x8021;
+pub const TPM_ST_VERIFIED: u16 = 0x8022;
+pub const TPM_ST_AUTH_SECRET: u16 = 0x8023;
+pub const TPM_ST_HASHCHECK: u16 = 0x8024;
+
+// Starts every TPMS_ATTEST the TPM produces
+pub const TPM_GENERATED_VALUE: u32 = 0xff544347;
+
+// Version 0.1.0, as major << 48 | minor << 32 | patch. Reported as
+// TPM_PT_FIRMWARE_VERSION_1 and _2, and in every TPMS_ATTEST.
+pub const FIRMWARE_VERSION: u64 = 0x0000_0001_0000_0000;
 
 #[repr(u32)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub enum TpmPt {
     Manufacturer = 0x105,
+    FirmwareVersion1 = 0x10B,
+    FirmwareVersion2 = 0x10C,
+    PcrCount = 0x112,
+    PcrSelectMin = 0x113,
+    HrTransientMin = 0x10E,
+    HrPersistentMin = 0x10F,
+    NvIndexMax = 0x117,
+    NvBufferMax = 0x12C,
+    Permanent = 0x200,
+    StartupClear = 0x201,
+    HrNvIndex = 0x202,
+    HrLoaded = 0x203,
+    HrLoadedAvail = 0x204,
+    HrTransientAvail = 0x207,
+    HrPersistent = 0x208,
+    HrPersistentAvail = 0x209,
+    LockoutCounter = 0x20E,
+    MaxAuthFail = 0x20F,
+    LockoutInterval = 0x210,
+    LockoutRecovery = 0x211,
+    #[default]
     Unknown,
 }
 
-impl Default for TpmPt {
-    fn default() -> Self {
-        TpmPt::Unknown
-    }
-}
-
 impl From<u32> for TpmPt {
     fn from(n: u32) -> TpmPt {
         match n {
             0x105 => TpmPt::Manufacturer,
+            0x10B => TpmPt::FirmwareVersion1,
+            0x10C => TpmPt::FirmwareVersion2,
+            0x112 => TpmPt::PcrCount,
+            0x113 => TpmPt::PcrSelectMin,
+            0x10E => TpmPt::HrTransientMin,
+            0x10F => TpmPt::HrPersistentMin,
+            0x117 => TpmPt::NvIndexMax,
+            0x12C => TpmPt::NvBufferMax,
+            0x200 => TpmPt::Permanent,
+            0x201 => TpmPt::StartupClear,
+            0x202 => TpmPt::HrNvIndex,
+            0x203 => TpmPt::HrLoaded,
+            0x204 => TpmPt::HrLoadedAvail,
+            0x207 => TpmPt::HrTransientAvail,
+            0x208 => TpmPt::HrPersistent,
+            0x209 => TpmPt::HrPersistentAvail,
+            0x20E => TpmPt::LockoutCounter,
+            0x20F => TpmPt::MaxAuthFail,
+            0x210 => TpmPt::LockoutInterval,
+            0x211 => TpmPt::LockoutRecovery,
             _ => TpmPt::Unknown,
         }
     }
 }
 
-#[derive(Clone, Copy)]
-pub enum TpmCapability {
-    TpmProperty = 0x6,
+// TPMA_PERMANENT bits
+pub const TPMA_PERMANENT_OWNER_AUTH_SET: u32 = 1 << 0;
+pub const TPMA_PERMANENT_ENDORSEMENT_AUTH_SET: u32 = 1 << 1;
+pub const TPMA_PERMANENT_LOCKOUT_AUTH_SET: u32 = 1 << 2;
+pub const TPMA_PERMANENT_DISABLE_CLEAR: u32 = 1 << 8;
+pub const TPMA_PERMANENT_IN_LOCKOUT: u32 = 1 << 9;
+pub const TPMA_PERMANENT_TPM_GENERATED_EPS: u32 = 1 << 10;
+
+// TPMA_STARTUP_CLEAR bits
+pub const TPMA_STARTUP_CLEAR_PH_ENABLE: u32 = 1 << 0;
+pub const TPMA_STARTUP_CLEAR_SH_ENABLE: u32 = 1 << 1;
+pub const TPMA_STARTUP_CLEAR_EH_ENABLE: u32 = 1 << 2;
+pub const TPMA_STARTUP_CLEAR_PH_ENABLE_NV: u32 = 1 << 3;
+pub const TPMA_STARTUP_CLEAR_ORDERLY: u32 = 1 << 31;
+
+// TPMA_LOCALITY bits. The TPM only ever runs at locality zero.
+pub const TPMA_LOCALITY_ZERO: u8 = 1 << 0;
+
+pub type TpmHandle = u32;
+
+// Permanent handles
+#[repr(u32)]
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum TpmRh {
+    Owner = 0x40000001,
+    Null = 0x40000007,
+    Lockout = 0x4000000A,
+    Endorsement = 0x4000000B,
+    Password = 0x40000009,
+    Platform = 0x4000000C,
+    PlatformNv = 0x4000000D,
+    #[default]
+    Unknown,
+}
+
+impl From<u32> for TpmRh {
+    fn from(n: u32) -> TpmRh {
+        match n {
+            0x40000001 => TpmRh::Owner,
+            0x40000007 => TpmRh::Null,
+            0x40000009 => TpmRh::Password,
+            0x4000000A => TpmRh::Lockout,
+            0x4000000B => TpmRh::Endorsement,
+            0x4000000C => TpmRh::Platform,
+            0x4000000D => TpmRh::PlatformNv,
+            _ => TpmRh::Unknown,
+        }
+    }
+}
+
+// Handle types, taken from the top byte of a handle
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TpmHt {
+    Pcr = 0x00,
+    NvIndex = 0x01,
+    HmacSession = 0x02,
+    PolicySession = 0x03,
+    Permanent = 0x40,
+    Transient = 0x80,
+    Persistent = 0x81,
+    Unknown,
+}
+
+impl From<TpmHandle> for TpmHt {
+    fn from(handle: TpmHandle) -> TpmHt {
+        match handle >> 24 {
+            0x00 => TpmHt::Pcr,
+            0x01 => TpmHt::NvIndex,
+            0x02 => TpmHt::HmacSession,
+            0x03 => TpmHt::PolicySession,
+            0x40 => TpmHt::Permanent,
+            0x80 => TpmHt::Transient,
+            0x81 => TpmHt::Persistent,
+            _ => TpmHt::Unknown,
+        }
+    }
+}
+
+#[repr(u16)]
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum TpmAlgId {
+    Rsa = 0x1,
+    Sha1 = 0x4,
+    Hmac = 0x5,
+    Aes = 0x6,
+    Mgf1 = 0x7,
+    KeyedHash = 0x8,
+    Xor = 0xA,
+    Sha256 = 0xB,
+    Sha384 = 0xC,
+    Sha512 = 0xD,
+    #[default]
+    Null = 0x10,
+    Sm3_256 = 0x12,
+    Sm4 = 0x13,
+    RsaSsa = 0x14,
+    RsaEs = 0x15,
+    RsaPss = 0x16,
+    Oaep = 0x17,
+    EcDsa = 0x18,
+    EcDh = 0x19,
+    EcDaa = 0x1A,
+    Sm2 = 0x1B,
+    EcSchnorr = 0x1C,
+    EcMqv = 0x1D,
+    Kdf1Sp800_56a = 0x20,
+    Kdf2 = 0x21,
+    Kdf1Sp800_108 = 0x22,
+    Ecc = 0x23,
+    SymCipher = 0x25,
+    Camellia = 0x26,
+    Cmac = 0x3F,
+    Ctr = 0x40,
+    Ofb = 0x41,
+    Cbc = 0x42,
+    Cfb = 0x43,
+    Ecb = 0x44,
+    Unknown,
+}
+
+impl From<u16> for TpmAlgId {
+    fn from(n: u16) -> TpmAlgId {
+        match n {
+            0x1 => TpmAlgId::Rsa,
+            0x4 => TpmAlgId::Sha1,
+            0x5 => TpmAlgId::Hmac,
+            0x6 => TpmAlgId::Aes,
+            0x7 => TpmAlgId::Mgf1,
+            0x8 => TpmAlgId::KeyedHash,
+            0xA => TpmAlgId::Xor,
+            0xB => TpmAlgId::Sha256,
+            0xC => TpmAlgId::Sha384,
+            0xD => TpmAlgId::Sha512,
+            0x10 => TpmAlgId::Null,
+            0x12 => TpmAlgId::Sm3_256,
+            0x13 => TpmAlgId::Sm4,
+            0x14 => TpmAlgId::RsaSsa,
+            0x15 => TpmAlgId::RsaEs,
+            0x16 => TpmAlgId::RsaPss,
+            0x17 => TpmAlgId::Oaep,
+            0x18 => TpmAlgId::EcDsa,
+            0x19 => TpmAlgId::EcDh,
+            0x1A => TpmAlgId::EcDaa,
+            0x1B => TpmAlgId::Sm2,
+            0x1C => TpmAlgId::EcSchnorr,
+            0x1D => TpmAlgId::EcMqv,
+            0x20 => TpmAlgId::Kdf1Sp800_56a,
+            0x21 => TpmAlgId::Kdf2,
+            0x22 => TpmAlgId::Kdf1Sp800_108,
+            0x23 => TpmAlgId::Ecc,
+            0x25 => TpmAlgId::SymCipher,
+            0x26 => TpmAlgId::Camellia,
+            0x3F => TpmAlgId::Cmac,
+            0x40 => TpmAlgId::Ctr,
+            0x41 => TpmAlgId::Ofb,
+            0x42 => TpmAlgId::Cbc,
+            0x43 => TpmAlgId::Cfb,
+            0x44 => TpmAlgId::Ecb,
+            _ => TpmAlgId::Unknown,
+        }
+    }
+}
+
+impl TpmAlgId {
+    pub fn is_hash(&self) -> bool {
+        matches!(
+            self,
+            TpmAlgId::Sha1 | TpmAlgId::Sha256 | TpmAlgId::Sha384 | TpmAlgId::Sha512
+        )
+    }
+
+    pub fn digest_size(&self) -> usize {
+        match self {
+            TpmAlgId::Sha1 => 20,
+            TpmAlgId::Sha256 => 32,
+            TpmAlgId::Sha384 => 48,
+            TpmAlgId::Sha512 => 64,
+            _ => 0,
+        }
+    }
+}
+
+#[repr(u16)]
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum TpmEccCurve {
+    #[default]
+    None = 0x0,
+    NistP192 = 0x1,
+    NistP224 = 0x2,
+    NistP256 = 0x3,
+    NistP384 = 0x4,
+    NistP521 = 0x5,
+    BnP256 = 0x10,
+    BnP638 = 0x11,
+    Sm2P256 = 0x20,
     Unknown,
 }
 
-impl Default for TpmCapability {
+impl From<u16> for TpmEccCurve {
+    fn from(n: u16) -> TpmEccCurve {
+        match n {
+            0x0 => TpmEccCurve::None,
+            0x1 => TpmEccCurve::NistP192,
+            0x2 => TpmEccCurve::NistP224,
+            0x3 => TpmEccCurve::NistP256,
+            0x4 => TpmEccCurve::NistP384,
+            0x5 => TpmEccCurve::NistP521,
+            0x10 => TpmEccCurve::BnP256,
+            0x11 => TpmEccCurve::BnP638,
+            0x20 => TpmEccCurve::Sm2P256,
+            _ => TpmEccCurve::Unknown,
+        }
+    }
+}
+
+// Sized buffers (TPM2B_*) all share the same layout: a u16 size followed by
+// up to N bytes of data. N is the capacity of the largest value the TPM will
+// accept for that type.
+#[derive(Clone, Copy)]
+pub struct Tpm2b<const N: usize> {
+    pub size: u16,
+    pub buffer: [u8; N],
+}
+
+impl<const N: usize> Default for Tpm2b<N> {
     fn default() -> Self {
-        TpmCapability::Unknown
+        Tpm2b {
+            size: 0,
+            buffer: [0; N],
+        }
+    }
+}
+
+impl<const N: usize> Tpm2b<N> {
+    pub fn from_slice(data: &[u8]) -> Result<Tpm2b<N>, TpmError> {
+        if data.len() > N {
+            return Err(TpmError::new(TpmRc::Size));
+        }
+
+        let mut val = Tpm2b::<N>::default();
+        val.buffer[..data.len()].copy_from_slice(data);
+        val.size = data.len() as u16;
+
+        Ok(val)
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buffer[..self.size as usize]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+}
+
+pub type Tpm2bDigest = Tpm2b<MAX_DIGEST_SIZE>;
+pub type Tpm2bAuth = Tpm2bDigest;
+pub type Tpm2bNonce = Tpm2bDigest;
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmtHa {
+    pub hash_alg: TpmAlgId,
+    pub digest: Tpm2bDigest,
+}
+
+pub type Tpm2bData = Tpm2b<{ 2 + MAX_DIGEST_SIZE }>;
+pub type Tpm2bName = Tpm2b<{ 2 + MAX_DIGEST_SIZE }>;
+pub type Tpm2bPublicKeyRsa = Tpm2b<MAX_RSA_KEY_BYTES>;
+pub type Tpm2bPrivateKeyRsa = Tpm2b<{ MAX_RSA_KEY_BYTES / 2 }>;
+pub type Tpm2bEccParameter = Tpm2b<MAX_ECC_KEY_BYTES>;
+pub type Tpm2bSymKey = Tpm2b<MAX_SYM_KEY_BYTES>;
+pub type Tpm2bSensitiveData = Tpm2b<MAX_SYM_DATA>;
+pub type Tpm2bIv = Tpm2b<MAX_SYM_BLOCK_SIZE>;
+pub type Tpm2bPrivate = Tpm2b<MAX_PRIVATE_SIZE>;
+pub type Tpm2bTemplate = Tpm2b<MAX_PUBLIC_SIZE>;
+pub type Tpm2bLabel = Tpm2b<LABEL_MAX_BUFFER>;
+pub type Tpm2bContextData = Tpm2b<MAX_CONTEXT_SIZE>;
+// An RSA encrypted seed, or the ECC point it's shared with
+pub type Tpm2bEncryptedSecret = Tpm2b<MAX_RSA_KEY_BYTES>;
+pub type Tpm2bMaxNvBuffer = Tpm2b<MAX_NV_BUFFER_SIZE>;
+pub type Tpm2bMaxBuffer = Tpm2b<MAX_DIGEST_BUFFER>;
+pub type Tpm2bAttest = Tpm2b<MAX_ATTEST_SIZE>;
+// The integrity HMAC and the encrypted credential, both TPM2B_DIGESTs
+pub type Tpm2bIdObject = Tpm2b<{ 2 * (2 + MAX_DIGEST_SIZE) }>;
+
+// TPMA_OBJECT bits
+pub const TPMA_OBJECT_FIXED_TPM: u32 = 1 << 1;
+pub const TPMA_OBJECT_ST_CLEAR: u32 = 1 << 2;
+pub const TPMA_OBJECT_FIXED_PARENT: u32 = 1 << 4;
+pub const TPMA_OBJECT_SENSITIVE_DATA_ORIGIN: u32 = 1 << 5;
+pub const TPMA_OBJECT_USER_WITH_AUTH: u32 = 1 << 6;
+pub const TPMA_OBJECT_ADMIN_WITH_POLICY: u32 = 1 << 7;
+pub const TPMA_OBJECT_NO_DA: u32 = 1 << 10;
+pub const TPMA_OBJECT_ENCRYPTED_DUPLICATION: u32 = 1 << 11;
+pub const TPMA_OBJECT_RESTRICTED: u32 = 1 << 16;
+pub const TPMA_OBJECT_DECRYPT: u32 = 1 << 17;
+pub const TPMA_OBJECT_SIGN_ENCRYPT: u32 = 1 << 18;
+pub const TPMA_OBJECT_X509_SIGN: u32 = 1 << 19;
+pub const TPMA_OBJECT_RESERVED: u32 = 0xFFF0_F309;
+
+// TPMT_SYM_DEF_OBJECT. The key size and mode are only present when the
+// algorithm isn't TPM_ALG_NULL.
+#[derive(Clone, Copy, Default)]
+pub struct TpmtSymDefObject {
+    pub algorithm: TpmAlgId,
+    pub key_bits: u16,
+    pub mode: TpmAlgId,
+}
+
+// TPMT_RSA_SCHEME and TPMT_ECC_SCHEME. `count` is only used by ECDAA.
+#[derive(Clone, Copy, Default)]
+pub struct TpmtAsymScheme {
+    pub scheme: TpmAlgId,
+    pub hash_alg: TpmAlgId,
+    pub count: u16,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmtKdfScheme {
+    pub scheme: TpmAlgId,
+    pub hash_alg: TpmAlgId,
+}
+
+// TPMT_KEYEDHASH_SCHEME. `kdf` is only used by XOR.
+#[derive(Clone, Copy, Default)]
+pub struct TpmtKeyedHashScheme {
+    pub scheme: TpmAlgId,
+    pub hash_alg: TpmAlgId,
+    pub kdf: TpmAlgId,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsKeyedHashParms {
+    pub scheme: TpmtKeyedHashScheme,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsSymCipherParms {
+    pub sym: TpmtSymDefObject,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsRsaParms {
+    pub symmetric: TpmtSymDefObject,
+    pub scheme: TpmtAsymScheme,
+    pub key_bits: u16,
+    pub exponent: u32,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsEccParms {
+    pub symmetric: TpmtSymDefObject,
+    pub scheme: TpmtAsymScheme,
+    pub curve_id: TpmEccCurve,
+    pub kdf: TpmtKdfScheme,
+}
+
+// The variant is the object type, so TPMT_PUBLIC has no separate type
+// field.
+#[derive(Clone, Copy, Default)]
+pub enum TpmuPublicParms {
+    KeyedHash(TpmsKeyedHashParms),
+    SymCipher(TpmsSymCipherParms),
+    Rsa(TpmsRsaParms),
+    Ecc(TpmsEccParms),
+    #[default]
+    Unknown,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsEccPoint {
+    pub x: Tpm2bEccParameter,
+    pub y: Tpm2bEccParameter,
+}
+
+// Without an allocator there's nothing to box the RSA modulus into.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Copy, Default)]
+pub enum TpmuPublicId {
+    KeyedHash(Tpm2bDigest),
+    SymCipher(Tpm2bDigest),
+    Rsa(Tpm2bPublicKeyRsa),
+    Ecc(TpmsEccPoint),
+    #[default]
+    Unknown,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmtPublic {
+    pub name_alg: TpmAlgId,
+    pub object_attributes: u32,
+    pub auth_policy: Tpm2bDigest,
+    pub parameters: TpmuPublicParms,
+    pub unique: TpmuPublicId,
+}
+
+impl TpmtPublic {
+    pub fn object_type(&self) -> TpmAlgId {
+        match self.parameters {
+            TpmuPublicParms::KeyedHash(_) => TpmAlgId::KeyedHash,
+            TpmuPublicParms::SymCipher(_) => TpmAlgId::SymCipher,
+            TpmuPublicParms::Rsa(_) => TpmAlgId::Rsa,
+            TpmuPublicParms::Ecc(_) => TpmAlgId::Ecc,
+            TpmuPublicParms::Unknown => TpmAlgId::Unknown,
+        }
+    }
+
+    pub fn has_attributes(&self, attributes: u32) -> bool {
+        self.object_attributes & attributes == attributes
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub enum TpmuSensitiveComposite {
+    Rsa(Tpm2bPrivateKeyRsa),
+    Ecc(Tpm2bEccParameter),
+    Bits(Tpm2bSensitiveData),
+    Sym(Tpm2bSymKey),
+    #[default]
+    Unknown,
+}
+
+// As with TPMT_PUBLIC, the variant of `sensitive` gives the type.
+#[derive(Clone, Copy, Default)]
+pub struct TpmtSensitive {
+    pub auth_value: Tpm2bAuth,
+    pub seed_value: Tpm2bDigest,
+    pub sensitive: TpmuSensitiveComposite,
+}
+
+impl TpmtSensitive {
+    pub fn sensitive_type(&self) -> TpmAlgId {
+        match self.sensitive {
+            TpmuSensitiveComposite::Rsa(_) => TpmAlgId::Rsa,
+            TpmuSensitiveComposite::Ecc(_) => TpmAlgId::Ecc,
+            TpmuSensitiveComposite::Bits(_) => TpmAlgId::KeyedHash,
+            TpmuSensitiveComposite::Sym(_) => TpmAlgId::SymCipher,
+            TpmuSensitiveComposite::Unknown => TpmAlgId::Unknown,
+        }
     }
 }
 
+// Takes the place of the unique field in a template for a derived object.
+#[derive(Clone, Copy, Default)]
+pub struct TpmsDerive {
+    pub label: Tpm2bLabel,
+    pub context: Tpm2bLabel,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsSensitiveCreate {
+    pub user_auth: Tpm2bAuth,
+    pub data: Tpm2bSensitiveData,
+}
+
+pub const HASH_COUNT: usize = 4;
+
+// Number of PCRs in each bank. The first PCR_SAVE of them are kept across a
+// TPM Resume.
+pub const IMPLEMENTATION_PCR: usize = 24;
+pub const PCR_SAVE: usize = 16;
+pub const PCR_SELECT_MIN: usize = 3;
+pub const PCR_SELECT_MAX: usize = 3;
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsPcrSelection {
+    pub hash: TpmAlgId,
+    pub size_of_select: u8,
+    pub pcr_select: [u8; PCR_SELECT_MAX],
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmlPcrSelection {
+    pub count: u32,
+    pub pcr_selections: [TpmsPcrSelection; HASH_COUNT],
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsCreationData {
+    pub pcr_select: TpmlPcrSelection,
+    pub pcr_digest: Tpm2bDigest,
+    pub locality: u8,
+    pub parent_name_alg: TpmAlgId,
+    pub parent_name: Tpm2bName,
+    pub parent_qualified_name: Tpm2bName,
+    pub outside_info: Tpm2bData,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmtTkCreation {
+    pub hierarchy: TpmHandle,
+    pub digest: Tpm2bDigest,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmtTkHashcheck {
+    pub hierarchy: TpmHandle,
+    pub digest: Tpm2bDigest,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmtTkVerified {
+    pub hierarchy: TpmHandle,
+    pub digest: Tpm2bDigest,
+}
+
+#[derive(Clone, Copy, Default)]
+pub enum TpmCapability {
+    Pcrs = 0x5,
+    Handles = 0x1,
+    TpmProperty = 0x6,
+    EccCurves = 0x8,
+    #[default]
+    Unknown,
+}
+
 impl From<u32> for TpmCapability {
     fn from(n: u32) -> TpmCapability {
         match n {
+            0x5 => TpmCapability::Pcrs,
+            0x1 => TpmCapability::Handles,
             0x6 => TpmCapability::TpmProperty,
+            0x8 => TpmCapability::EccCurves,
             _ => TpmCapability::Unknown,
         }
     }
@@ -172,16 +1198,40 @@ pub struct TpmsTaggedProperty {
 // TODO: Document how tagged unions work here. Basically with typed
 // enums we can collapse 3 structures into 1, greatly reducinging
 // marshaling toil.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub enum TpmuCapabilityData {
+    Handles(u32, [TpmHandle; MAX_CAP_HANDLES]),
     TpmProperties(u32, [TpmsTaggedProperty; MAX_TPM_PROPERTIES]),
+    EccCurves(u32, [TpmEccCurve; MAX_CAP_CURVES]),
+    AssignedPcr(TpmlPcrSelection),
+    #[default]
     Unknown,
 }
 
-impl Default for TpmuCapabilityData {
-    fn default() -> Self {
-        TpmuCapabilityData::Unknown
-    }
+pub const MAX_SESSION_NUM: usize = 3;
+pub const MAX_HANDLE_NUM: usize = 3;
+
+// TPMA_SESSION bits
+pub const TPMA_SESSION_CONTINUE_SESSION: u8 = 1 << 0;
+pub const TPMA_SESSION_AUDIT_EXCLUSIVE: u8 = 1 << 1;
+pub const TPMA_SESSION_AUDIT_RESET: u8 = 1 << 2;
+pub const TPMA_SESSION_DECRYPT: u8 = 1 << 5;
+pub const TPMA_SESSION_ENCRYPT: u8 = 1 << 6;
+pub const TPMA_SESSION_AUDIT: u8 = 1 << 7;
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsAuthCommand {
+    pub session_handle: TpmHandle,
+    pub nonce: Tpm2bNonce,
+    pub session_attributes: u8,
+    pub hmac: Tpm2bAuth,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsAuthResponse {
+    pub nonce: Tpm2bNonce,
+    pub session_attributes: u8,
+    pub hmac: Tpm2bAuth,
 }
 
 #[derive(Default)]
@@ -190,9 +1240,15 @@ pub struct StartupArgs {
 }
 
 #[derive(Default)]
-pub struct GetCapabilityArgs {
-    pub cap: TpmCapability,
-    pub property: TpmPt,
+pub struct ShutdownArgs {
+    pub su_type: StartupType,
+}
+
+#[derive(Default)]
+pub struct GetCapabilityArgs {
+    pub cap: TpmCapability,
+    // A TPM_PT for properties, or the first handle to list
+    pub property: u32,
     pub property_count: u32,
 }
 
@@ -201,3 +1257,900 @@ pub struct GetCapabilityResponse {
     pub more_data: bool,
     pub data: TpmuCapabilityData,
 }
+
+#[derive(Default)]
+pub struct HierarchyControlArgs {
+    pub auth_handle: TpmHandle,
+    pub enable: TpmHandle,
+    pub state: bool,
+}
+
+#[derive(Default)]
+pub struct SetPrimaryPolicyArgs {
+    pub auth_handle: TpmHandle,
+    pub auth_policy: Tpm2bDigest,
+    pub hash_alg: TpmAlgId,
+}
+
+#[derive(Default)]
+pub struct ClearControlArgs {
+    pub auth: TpmHandle,
+    pub disable: bool,
+}
+
+#[derive(Default)]
+pub struct DictionaryAttackParametersArgs {
+    pub lock_handle: TpmHandle,
+    pub new_max_tries: u32,
+    pub new_recovery_time: u32,
+    pub lockout_recovery: u32,
+}
+
+#[derive(Default)]
+pub struct HierarchyChangeAuthArgs {
+    pub auth_handle: TpmHandle,
+    pub new_auth: Tpm2bAuth,
+}
+
+#[derive(Default)]
+pub struct CreatePrimaryArgs {
+    pub primary_handle: TpmHandle,
+    pub in_sensitive: TpmsSensitiveCreate,
+    pub in_public: TpmtPublic,
+    pub outside_info: Tpm2bData,
+    pub creation_pcr: TpmlPcrSelection,
+}
+
+#[derive(Default)]
+pub struct CreatePrimaryResponse {
+    pub object_handle: TpmHandle,
+    pub out_public: TpmtPublic,
+    pub creation_data: TpmsCreationData,
+    pub creation_hash: Tpm2bDigest,
+    pub creation_ticket: TpmtTkCreation,
+    pub name: Tpm2bName,
+}
+
+#[derive(Default)]
+pub struct CreateArgs {
+    pub parent_handle: TpmHandle,
+    pub in_sensitive: TpmsSensitiveCreate,
+    pub in_public: TpmtPublic,
+    pub outside_info: Tpm2bData,
+    pub creation_pcr: TpmlPcrSelection,
+}
+
+#[derive(Default)]
+pub struct CreateResponse {
+    pub out_private: Tpm2bPrivate,
+    pub out_public: TpmtPublic,
+    pub creation_data: TpmsCreationData,
+    pub creation_hash: Tpm2bDigest,
+    pub creation_ticket: TpmtTkCreation,
+}
+
+#[derive(Default)]
+pub struct LoadArgs {
+    pub parent_handle: TpmHandle,
+    pub in_private: Tpm2bPrivate,
+    pub in_public: TpmtPublic,
+}
+
+#[derive(Default)]
+pub struct LoadResponse {
+    pub object_handle: TpmHandle,
+    pub name: Tpm2bName,
+}
+
+// The template is only unmarshaled once the parent is known, since a
+// derivation parent changes what the unique field holds.
+#[derive(Default)]
+pub struct CreateLoadedArgs {
+    pub parent_handle: TpmHandle,
+    pub in_sensitive: TpmsSensitiveCreate,
+    pub in_public: Tpm2bTemplate,
+}
+
+#[derive(Default)]
+pub struct CreateLoadedResponse {
+    pub object_handle: TpmHandle,
+    pub out_private: Tpm2bPrivate,
+    pub out_public: TpmtPublic,
+    pub name: Tpm2bName,
+}
+
+#[derive(Default)]
+pub struct LoadExternalArgs {
+    // An empty TPM2B_SENSITIVE loads only the public area.
+    pub in_private: Option<TpmtSensitive>,
+    pub in_public: TpmtPublic,
+    pub hierarchy: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct LoadExternalResponse {
+    pub object_handle: TpmHandle,
+    pub name: Tpm2bName,
+}
+
+#[derive(Default)]
+pub struct ReadPublicArgs {
+    pub object_handle: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct ReadPublicResponse {
+    pub out_public: TpmtPublic,
+    pub name: Tpm2bName,
+    pub qualified_name: Tpm2bName,
+}
+
+#[derive(Default)]
+pub struct ObjectChangeAuthArgs {
+    pub object_handle: TpmHandle,
+    pub parent_handle: TpmHandle,
+    pub new_auth: Tpm2bAuth,
+}
+
+#[derive(Default)]
+pub struct ObjectChangeAuthResponse {
+    pub out_private: Tpm2bPrivate,
+}
+
+#[derive(Default)]
+pub struct StartAuthSessionArgs {
+    pub tpm_key: TpmHandle,
+    pub bind: TpmHandle,
+    pub nonce_caller: Tpm2bNonce,
+    pub encrypted_salt: Tpm2bEncryptedSecret,
+    pub session_type: TpmSe,
+    pub symmetric: TpmtSymDefObject,
+    pub auth_hash: TpmAlgId,
+}
+
+#[derive(Default)]
+pub struct StartAuthSessionResponse {
+    pub session_handle: TpmHandle,
+    pub nonce_tpm: Tpm2bNonce,
+}
+
+// The policy commands that take nothing but the policy session
+#[derive(Default)]
+pub struct PolicySessionArgs {
+    pub policy_session: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct PolicyGetDigestResponse {
+    pub policy_digest: Tpm2bDigest,
+}
+
+#[derive(Default)]
+pub struct PolicyCommandCodeArgs {
+    pub policy_session: TpmHandle,
+    pub code: u32,
+}
+
+// TPML_DIGEST
+#[derive(Clone, Copy, Default)]
+pub struct TpmlDigest {
+    pub count: u32,
+    pub digests: [Tpm2bDigest; 8],
+}
+
+#[derive(Default)]
+pub struct PolicyOrArgs {
+    pub policy_session: TpmHandle,
+    pub p_hash_list: TpmlDigest,
+}
+
+#[derive(Default)]
+pub struct PolicySecretArgs {
+    pub auth_handle: TpmHandle,
+    pub policy_session: TpmHandle,
+    pub nonce_tpm: Tpm2bNonce,
+    pub cp_hash_a: Tpm2bDigest,
+    pub policy_ref: Tpm2bNonce,
+    pub expiration: i32,
+}
+
+// TPMT_TK_AUTH. Only the NULL ticket is ever made, as there's no
+// TPM2_PolicyTicket to use one with.
+#[derive(Clone, Copy, Default)]
+pub struct TpmtTkAuth {
+    pub tag: u16,
+    pub hierarchy: TpmHandle,
+    pub digest: Tpm2bDigest,
+}
+
+#[derive(Default)]
+pub struct PolicySecretResponse {
+    pub timeout: Tpm2bDigest,
+    pub policy_ticket: TpmtTkAuth,
+}
+
+#[derive(Default)]
+pub struct PolicyCpHashArgs {
+    pub policy_session: TpmHandle,
+    pub cp_hash_a: Tpm2bDigest,
+}
+
+#[derive(Default)]
+pub struct PolicyNameHashArgs {
+    pub policy_session: TpmHandle,
+    pub name_hash: Tpm2bDigest,
+}
+
+#[derive(Default)]
+pub struct PolicyPcrArgs {
+    pub policy_session: TpmHandle,
+    pub pcr_digest: Tpm2bDigest,
+    pub pcrs: TpmlPcrSelection,
+}
+
+#[derive(Default)]
+pub struct PolicyDuplicationSelectArgs {
+    pub policy_session: TpmHandle,
+    pub object_name: Tpm2bName,
+    pub new_parent_name: Tpm2bName,
+    pub include_object: bool,
+}
+
+// TPML_DIGEST_VALUES
+#[derive(Clone, Copy, Default)]
+pub struct TpmlDigestValues {
+    pub count: u32,
+    pub digests: [TpmtHa; HASH_COUNT],
+}
+
+#[derive(Default)]
+pub struct PcrExtendArgs {
+    pub pcr_handle: TpmHandle,
+    pub digests: TpmlDigestValues,
+}
+
+#[derive(Default)]
+pub struct PcrReadArgs {
+    pub pcr_selection_in: TpmlPcrSelection,
+}
+
+#[derive(Default)]
+pub struct PcrReadResponse {
+    pub pcr_update_counter: u32,
+    pub pcr_selection_out: TpmlPcrSelection,
+    pub pcr_values: TpmlDigest,
+}
+
+#[derive(Default)]
+pub struct UnsealArgs {
+    pub item_handle: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct UnsealResponse {
+    pub out_data: Tpm2bSensitiveData,
+}
+
+#[derive(Default)]
+pub struct RsaEncryptArgs {
+    pub key_handle: TpmHandle,
+    pub message: Tpm2bPublicKeyRsa,
+    pub in_scheme: TpmtAsymScheme,
+    pub label: Tpm2bData,
+}
+
+#[derive(Default)]
+pub struct RsaEncryptResponse {
+    pub out_data: Tpm2bPublicKeyRsa,
+}
+
+#[derive(Default)]
+pub struct RsaDecryptArgs {
+    pub key_handle: TpmHandle,
+    pub cipher_text: Tpm2bPublicKeyRsa,
+    pub in_scheme: TpmtAsymScheme,
+    pub label: Tpm2bData,
+}
+
+#[derive(Default)]
+pub struct RsaDecryptResponse {
+    pub message: Tpm2bPublicKeyRsa,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsAlgorithmDetailEcc {
+    pub curve_id: TpmEccCurve,
+    pub key_size: u16,
+    pub kdf: TpmtKdfScheme,
+    pub sign: TpmtAsymScheme,
+    pub p: Tpm2bEccParameter,
+    pub a: Tpm2bEccParameter,
+    pub b: Tpm2bEccParameter,
+    pub gx: Tpm2bEccParameter,
+    pub gy: Tpm2bEccParameter,
+    pub n: Tpm2bEccParameter,
+    pub h: Tpm2bEccParameter,
+}
+
+#[derive(Default)]
+pub struct EccParametersArgs {
+    pub curve_id: TpmEccCurve,
+}
+
+#[derive(Default)]
+pub struct EccParametersResponse {
+    pub parameters: TpmsAlgorithmDetailEcc,
+}
+
+#[derive(Default)]
+pub struct EcdhKeyGenArgs {
+    pub key_handle: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct EcdhKeyGenResponse {
+    pub z_point: TpmsEccPoint,
+    pub pub_point: TpmsEccPoint,
+}
+
+#[derive(Default)]
+pub struct EcdhZGenArgs {
+    pub key_handle: TpmHandle,
+    pub in_point: TpmsEccPoint,
+}
+
+#[derive(Default)]
+pub struct EcdhZGenResponse {
+    pub out_point: TpmsEccPoint,
+}
+
+#[derive(Default)]
+pub struct ZGen2PhaseArgs {
+    pub key_a: TpmHandle,
+    pub in_qs_b: TpmsEccPoint,
+    pub in_qe_b: TpmsEccPoint,
+    pub in_scheme: TpmAlgId,
+    pub counter: u16,
+}
+
+#[derive(Default)]
+pub struct ZGen2PhaseResponse {
+    pub out_z1: TpmsEccPoint,
+    pub out_z2: TpmsEccPoint,
+}
+
+#[derive(Default)]
+pub struct EcEphemeralArgs {
+    pub curve_id: TpmEccCurve,
+}
+
+#[derive(Default)]
+pub struct EcEphemeralResponse {
+    pub q: TpmsEccPoint,
+    pub counter: u16,
+}
+
+#[derive(Default)]
+pub struct CommitArgs {
+    pub sign_handle: TpmHandle,
+    pub p1: TpmsEccPoint,
+    pub s2: Tpm2bSensitiveData,
+    pub y2: Tpm2bEccParameter,
+}
+
+#[derive(Default)]
+pub struct CommitResponse {
+    pub k: TpmsEccPoint,
+    pub l: TpmsEccPoint,
+    pub e: TpmsEccPoint,
+    pub counter: u16,
+}
+
+#[derive(Default)]
+pub struct SignArgs {
+    pub key_handle: TpmHandle,
+    pub digest: Tpm2bDigest,
+    pub in_scheme: TpmtAsymScheme,
+    pub validation: TpmtTkHashcheck,
+}
+
+#[derive(Default)]
+pub struct SignResponse {
+    pub signature: TpmtSignature,
+}
+
+#[derive(Default)]
+pub struct VerifySignatureArgs {
+    pub key_handle: TpmHandle,
+    pub digest: Tpm2bDigest,
+    pub signature: TpmtSignature,
+}
+
+#[derive(Default)]
+pub struct VerifySignatureResponse {
+    pub validation: TpmtTkVerified,
+}
+
+#[derive(Default)]
+pub struct HashArgs {
+    pub data: Tpm2bMaxBuffer,
+    pub hash_alg: TpmAlgId,
+    pub hierarchy: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct HashResponse {
+    pub out_hash: Tpm2bDigest,
+    pub validation: TpmtTkHashcheck,
+}
+
+// Both TPM2_EncryptDecrypt and TPM2_EncryptDecrypt2, which only differ in
+// the order of their parameters.
+#[derive(Default)]
+pub struct EncryptDecryptArgs {
+    pub key_handle: TpmHandle,
+    pub decrypt: bool,
+    pub mode: TpmAlgId,
+    pub iv_in: Tpm2bIv,
+    pub in_data: Tpm2bMaxBuffer,
+}
+
+#[derive(Default)]
+pub struct EncryptDecryptResponse {
+    pub out_data: Tpm2bMaxBuffer,
+    pub iv_out: Tpm2bIv,
+}
+
+// TPM2_MAC and TPM2_HMAC, which is the same command with a hash algorithm
+// for the scheme.
+#[derive(Default)]
+pub struct MacArgs {
+    pub handle: TpmHandle,
+    pub buffer: Tpm2bMaxBuffer,
+    pub in_scheme: TpmAlgId,
+}
+
+#[derive(Default)]
+pub struct MacResponse {
+    pub out_mac: Tpm2bDigest,
+}
+
+#[derive(Default)]
+pub struct MacStartArgs {
+    pub handle: TpmHandle,
+    pub auth: Tpm2bAuth,
+    pub in_scheme: TpmAlgId,
+}
+
+#[derive(Default)]
+pub struct MacStartResponse {
+    pub sequence_handle: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct SequenceUpdateArgs {
+    pub sequence_handle: TpmHandle,
+    pub buffer: Tpm2bMaxBuffer,
+}
+
+#[derive(Default)]
+pub struct SequenceCompleteArgs {
+    pub sequence_handle: TpmHandle,
+    pub buffer: Tpm2bMaxBuffer,
+    pub hierarchy: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct SequenceCompleteResponse {
+    pub result: Tpm2bDigest,
+    pub validation: TpmtTkHashcheck,
+}
+
+#[derive(Default)]
+pub struct MakeCredentialArgs {
+    pub handle: TpmHandle,
+    pub credential: Tpm2bDigest,
+    pub object_name: Tpm2bName,
+}
+
+#[derive(Default)]
+pub struct MakeCredentialResponse {
+    pub credential_blob: Tpm2bIdObject,
+    pub secret: Tpm2bEncryptedSecret,
+}
+
+#[derive(Default)]
+pub struct ActivateCredentialArgs {
+    pub activate_handle: TpmHandle,
+    pub key_handle: TpmHandle,
+    pub credential_blob: Tpm2bIdObject,
+    pub secret: Tpm2bEncryptedSecret,
+}
+
+#[derive(Default)]
+pub struct ActivateCredentialResponse {
+    pub cert_info: Tpm2bDigest,
+}
+
+#[derive(Default)]
+pub struct DuplicateArgs {
+    pub object_handle: TpmHandle,
+    pub new_parent_handle: TpmHandle,
+    pub encryption_key_in: Tpm2bData,
+    pub symmetric_alg: TpmtSymDefObject,
+}
+
+#[derive(Default)]
+pub struct DuplicateResponse {
+    pub encryption_key_out: Tpm2bData,
+    pub duplicate: Tpm2bPrivate,
+    pub out_sym_seed: Tpm2bEncryptedSecret,
+}
+
+#[derive(Default)]
+pub struct ImportArgs {
+    pub parent_handle: TpmHandle,
+    pub encryption_key: Tpm2bData,
+    pub object_public: TpmtPublic,
+    pub duplicate: Tpm2bPrivate,
+    pub in_sym_seed: Tpm2bEncryptedSecret,
+    pub symmetric_alg: TpmtSymDefObject,
+}
+
+#[derive(Default)]
+pub struct ImportResponse {
+    pub out_private: Tpm2bPrivate,
+}
+
+#[derive(Default)]
+pub struct RewrapArgs {
+    pub old_parent: TpmHandle,
+    pub new_parent: TpmHandle,
+    pub in_duplicate: Tpm2bPrivate,
+    pub name: Tpm2bName,
+    pub in_sym_seed: Tpm2bEncryptedSecret,
+}
+
+#[derive(Default)]
+pub struct RewrapResponse {
+    pub out_duplicate: Tpm2bPrivate,
+    pub out_sym_seed: Tpm2bEncryptedSecret,
+}
+
+#[derive(Default)]
+pub struct FlushContextArgs {
+    pub flush_handle: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct EvictControlArgs {
+    pub auth: TpmHandle,
+    pub object_handle: TpmHandle,
+    pub persistent_handle: TpmHandle,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsContext {
+    pub sequence: u64,
+    pub saved_handle: TpmHandle,
+    pub hierarchy: TpmHandle,
+    pub context_blob: Tpm2bContextData,
+}
+
+#[derive(Default)]
+pub struct ContextSaveArgs {
+    pub save_handle: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct ContextSaveResponse {
+    pub context: TpmsContext,
+}
+
+#[derive(Default)]
+pub struct ContextLoadArgs {
+    pub context: TpmsContext,
+}
+
+#[derive(Default)]
+pub struct ContextLoadResponse {
+    pub loaded_handle: TpmHandle,
+}
+
+// TPMA_NV bits
+pub const TPMA_NV_PPWRITE: u32 = 1 << 0;
+pub const TPMA_NV_OWNERWRITE: u32 = 1 << 1;
+pub const TPMA_NV_AUTHWRITE: u32 = 1 << 2;
+pub const TPMA_NV_POLICYWRITE: u32 = 1 << 3;
+pub const TPMA_NV_TPM_NT_SHIFT: u32 = 4;
+pub const TPMA_NV_TPM_NT: u32 = 0xF << TPMA_NV_TPM_NT_SHIFT;
+pub const TPMA_NV_POLICY_DELETE: u32 = 1 << 10;
+pub const TPMA_NV_WRITELOCKED: u32 = 1 << 11;
+pub const TPMA_NV_WRITEALL: u32 = 1 << 12;
+pub const TPMA_NV_WRITEDEFINE: u32 = 1 << 13;
+pub const TPMA_NV_WRITE_STCLEAR: u32 = 1 << 14;
+pub const TPMA_NV_GLOBALLOCK: u32 = 1 << 15;
+pub const TPMA_NV_PPREAD: u32 = 1 << 16;
+pub const TPMA_NV_OWNERREAD: u32 = 1 << 17;
+pub const TPMA_NV_AUTHREAD: u32 = 1 << 18;
+pub const TPMA_NV_POLICYREAD: u32 = 1 << 19;
+pub const TPMA_NV_NO_DA: u32 = 1 << 25;
+pub const TPMA_NV_ORDERLY: u32 = 1 << 26;
+pub const TPMA_NV_CLEAR_STCLEAR: u32 = 1 << 27;
+pub const TPMA_NV_READLOCKED: u32 = 1 << 28;
+pub const TPMA_NV_WRITTEN: u32 = 1 << 29;
+pub const TPMA_NV_PLATFORMCREATE: u32 = 1 << 30;
+pub const TPMA_NV_READ_STCLEAR: u32 = 1 << 31;
+pub const TPMA_NV_RESERVED: u32 = 0x01F0_0300;
+
+// Index types, from the TPM_NT field of TPMA_NV
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum TpmNt {
+    Ordinary = 0x0,
+    Counter = 0x1,
+    Bits = 0x2,
+    Extend = 0x4,
+    PinFail = 0x8,
+    PinPass = 0x9,
+    #[default]
+    Unknown,
+}
+
+impl From<u32> for TpmNt {
+    fn from(n: u32) -> TpmNt {
+        match n {
+            0x0 => TpmNt::Ordinary,
+            0x1 => TpmNt::Counter,
+            0x2 => TpmNt::Bits,
+            0x4 => TpmNt::Extend,
+            0x8 => TpmNt::PinFail,
+            0x9 => TpmNt::PinPass,
+            _ => TpmNt::Unknown,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsNvPublic {
+    pub nv_index: TpmHandle,
+    pub name_alg: TpmAlgId,
+    pub attributes: u32,
+    pub auth_policy: Tpm2bDigest,
+    pub data_size: u16,
+}
+
+impl TpmsNvPublic {
+    pub fn nv_type(&self) -> TpmNt {
+        TpmNt::from((self.attributes & TPMA_NV_TPM_NT) >> TPMA_NV_TPM_NT_SHIFT)
+    }
+
+    pub fn has_attributes(&self, attributes: u32) -> bool {
+        self.attributes & attributes == attributes
+    }
+}
+
+// The data of a PIN_PASS or PIN_FAIL index
+#[derive(Clone, Copy, Default)]
+pub struct TpmsNvPinCounterParameters {
+    pub pin_count: u32,
+    pub pin_limit: u32,
+}
+
+#[derive(Default)]
+pub struct NvDefineSpaceArgs {
+    pub auth_handle: TpmHandle,
+    pub auth: Tpm2bAuth,
+    pub public_info: TpmsNvPublic,
+}
+
+#[derive(Default)]
+pub struct NvUndefineSpaceArgs {
+    pub auth_handle: TpmHandle,
+    pub nv_index: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct NvUndefineSpaceSpecialArgs {
+    pub nv_index: TpmHandle,
+    pub platform: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct NvReadPublicArgs {
+    pub nv_index: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct NvReadPublicResponse {
+    pub nv_public: TpmsNvPublic,
+    pub nv_name: Tpm2bName,
+}
+
+#[derive(Default)]
+pub struct NvWriteArgs {
+    pub auth_handle: TpmHandle,
+    pub nv_index: TpmHandle,
+    pub data: Tpm2bMaxNvBuffer,
+    pub offset: u16,
+}
+
+#[derive(Default)]
+pub struct NvReadArgs {
+    pub auth_handle: TpmHandle,
+    pub nv_index: TpmHandle,
+    pub size: u16,
+    pub offset: u16,
+}
+
+#[derive(Default)]
+pub struct NvReadResponse {
+    pub data: Tpm2bMaxNvBuffer,
+}
+
+#[derive(Default)]
+pub struct NvIncrementArgs {
+    pub auth_handle: TpmHandle,
+    pub nv_index: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct NvExtendArgs {
+    pub auth_handle: TpmHandle,
+    pub nv_index: TpmHandle,
+    pub data: Tpm2bMaxNvBuffer,
+}
+
+#[derive(Default)]
+pub struct NvSetBitsArgs {
+    pub auth_handle: TpmHandle,
+    pub nv_index: TpmHandle,
+    pub bits: u64,
+}
+
+#[derive(Default)]
+pub struct NvGlobalWriteLockArgs {
+    pub auth_handle: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct NvWriteLockArgs {
+    pub auth_handle: TpmHandle,
+    pub nv_index: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct NvReadLockArgs {
+    pub auth_handle: TpmHandle,
+    pub nv_index: TpmHandle,
+}
+
+#[derive(Default)]
+pub struct NvChangeAuthArgs {
+    pub nv_index: TpmHandle,
+    pub new_auth: Tpm2bAuth,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsClockInfo {
+    pub clock: u64,
+    pub reset_count: u32,
+    pub restart_count: u32,
+    pub safe: bool,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsTimeInfo {
+    pub time: u64,
+    pub clock_info: TpmsClockInfo,
+}
+
+#[derive(Default)]
+pub struct ReadClockResponse {
+    pub current_time: TpmsTimeInfo,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsNvCertifyInfo {
+    pub index_name: Tpm2bName,
+    pub offset: u16,
+    pub nv_contents: Tpm2bMaxNvBuffer,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsQuoteInfo {
+    pub pcr_select: TpmlPcrSelection,
+    pub pcr_digest: Tpm2bDigest,
+}
+
+// TPMU_ATTEST. The variant decides the TPMI_ST_ATTEST type. It only lives
+// on the stack while a TPMS_ATTEST is built, so the size is fine.
+#[derive(Clone, Copy)]
+#[allow(clippy::large_enum_variant)]
+pub enum TpmuAttest {
+    Nv(TpmsNvCertifyInfo),
+    Quote(TpmsQuoteInfo),
+}
+
+#[derive(Clone, Copy)]
+pub struct TpmsAttest {
+    pub qualified_signer: Tpm2bName,
+    pub extra_data: Tpm2bData,
+    pub clock_info: TpmsClockInfo,
+    pub firmware_version: u64,
+    pub attested: TpmuAttest,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsSignatureRsa {
+    pub hash: TpmAlgId,
+    pub sig: Tpm2bPublicKeyRsa,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TpmsSignatureEcc {
+    pub hash: TpmAlgId,
+    pub signature_r: Tpm2bEccParameter,
+    pub signature_s: Tpm2bEccParameter,
+}
+
+// TPMT_SIGNATURE. The variant is sigAlg. Without an allocator there's
+// nothing to box the RSA signature into.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Copy, Default)]
+pub enum TpmtSignature {
+    RsaSsa(TpmsSignatureRsa),
+    RsaPss(TpmsSignatureRsa),
+    EcDsa(TpmsSignatureEcc),
+    EcDaa(TpmsSignatureEcc),
+    Sm2(TpmsSignatureEcc),
+    EcSchnorr(TpmsSignatureEcc),
+    Hmac(TpmtHa),
+    #[default]
+    Null,
+}
+
+impl TpmtSignature {
+    pub fn sig_alg(&self) -> TpmAlgId {
+        match self {
+            TpmtSignature::RsaSsa(_) => TpmAlgId::RsaSsa,
+            TpmtSignature::RsaPss(_) => TpmAlgId::RsaPss,
+            TpmtSignature::EcDsa(_) => TpmAlgId::EcDsa,
+            TpmtSignature::EcDaa(_) => TpmAlgId::EcDaa,
+            TpmtSignature::Sm2(_) => TpmAlgId::Sm2,
+            TpmtSignature::EcSchnorr(_) => TpmAlgId::EcSchnorr,
+            TpmtSignature::Hmac(_) => TpmAlgId::Hmac,
+            TpmtSignature::Null => TpmAlgId::Null,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct NvCertifyArgs {
+    pub sign_handle: TpmHandle,
+    pub auth_handle: TpmHandle,
+    pub nv_index: TpmHandle,
+    pub qualifying_data: Tpm2bData,
+    pub in_scheme: TpmtAsymScheme,
+    pub size: u16,
+    pub offset: u16,
+}
+
+#[derive(Default)]
+pub struct NvCertifyResponse {
+    pub certify_info: Tpm2bAttest,
+    pub signature: TpmtSignature,
+}
+
+#[derive(Default)]
+pub struct QuoteArgs {
+    pub sign_handle: TpmHandle,
+    pub qualifying_data: Tpm2bData,
+    pub in_scheme: TpmtAsymScheme,
+    pub pcr_select: TpmlPcrSelection,
+}
+
+#[derive(Default)]
+pub struct QuoteResponse {
+    pub quoted: Tpm2bAttest,
+    pub signature: TpmtSignature,
+}
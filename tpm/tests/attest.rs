@@ -0,0 +1,73 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+const TPM_CC_QUOTE: u32 = 0x158;
+const TPM_GENERATED_VALUE: u32 = 0xFF544347;
+const TPM_ST_ATTEST_QUOTE: u16 = 0x8018;
+
+// The TPMS_ATTEST and TPMT_SIGNATURE of a quote of `pcrs`
+fn quote(tpm: &mut TpmInstance, sign_handle: u32, pcrs: &[usize]) -> (Vec<u8>, Vec<u8>) {
+    let params = [
+        tpm2b(b"nonce"),
+        0x0010u16.to_be_bytes().to_vec(), // scheme NULL, use the key's
+        pcr_selection(TPM_ALG_SHA256, pcrs),
+    ]
+    .concat();
+    let response = run(tpm, TPM_CC_QUOTE, &[sign_handle], Some(&[&[]]), &params).unwrap();
+    let (_, params) = parameters(&response, false);
+    let mut reader = Reader::new(&params);
+    let quoted = reader.tpm2b().to_vec();
+    let signature = reader.bytes(params.len() - 2 - quoted.len()).to_vec();
+    (quoted, signature)
+}
+
+// The qualifiedSigner, pcrSelect and pcrDigest of a quote
+fn quote_info(quoted: &[u8]) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let mut reader = Reader::new(quoted);
+    assert_eq!(reader.u32(), TPM_GENERATED_VALUE);
+    assert_eq!(reader.u16(), TPM_ST_ATTEST_QUOTE);
+    let signer = reader.tpm2b().to_vec();
+    assert_eq!(reader.tpm2b(), b"nonce");
+    reader.bytes(8 + 4 + 4 + 1 + 8); // clockInfo and firmwareVersion
+    let selection = reader.bytes(4 + 2 + 1 + 3).to_vec();
+    let pcr_digest = reader.tpm2b().to_vec();
+    assert!(reader.is_empty());
+    (signer, selection, pcr_digest)
+}
+
+#[test]
+fn quote_pcrs() {
+    let mut tpm = power_on();
+    let (parent, _) = create_primary(&mut tpm, &ecc_storage_template());
+    let (private, public) = create(&mut tpm, parent, &ecc_signing_template(), &[], &[]);
+    let key = load(&mut tpm, parent, &private, &public);
+
+    pcr_extend(&mut tpm, 0, &sha256(&[b"firmware"]));
+    pcr_extend(&mut tpm, 7, &sha256(&[b"secure boot"]));
+    let pcr0 = sha256(&[&[0; 32], &sha256(&[b"firmware"])]);
+    let pcr7 = sha256(&[&[0; 32], &sha256(&[b"secure boot"])]);
+
+    let (quoted, signature) = quote(&mut tpm, key, &[0, 7]);
+    let (signer, selection, pcr_digest) = quote_info(&quoted);
+    assert!(!signer.is_empty());
+    assert_eq!(selection, pcr_selection(TPM_ALG_SHA256, &[0, 7]));
+    assert_eq!(pcr_digest, sha256(&[&pcr0, &pcr7]));
+
+    let params = [tpm2b(&sha256(&[&quoted])), signature].concat();
+    run(&mut tpm, TPM_CC_VERIFY_SIGNATURE, &[key], None, &params).unwrap();
+}
+
+// Without a signing key the quote comes back unsigned, with no pcrDigest as
+// there's no scheme to pick a hash.
+#[test]
+fn quote_without_key() {
+    let mut tpm = power_on();
+    let (quoted, signature) = quote(&mut tpm, TPM_RH_NULL, &[0]);
+    let (signer, selection, pcr_digest) = quote_info(&quoted);
+    assert!(signer.is_empty());
+    assert_eq!(selection, pcr_selection(TPM_ALG_SHA256, &[0]));
+    assert!(pcr_digest.is_empty());
+    assert_eq!(signature, 0x0010u16.to_be_bytes()); // TPM_ALG_NULL
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+// Clock is written to NV at least this often
+const CLOCK_NV_INTERVAL: u64 = 1 << 22;
+
+// time, clock and safe from TPM2_ReadClock
+fn read_clock(tpm: &mut TpmInstance) -> (u64, u64, bool) {
+    let response = run(tpm, TPM_CC_READ_CLOCK, &[], None, &[]).unwrap();
+    let mut reader = Reader::new(&response);
+    let time = reader.u64();
+    let clock = reader.u64();
+    reader.bytes(4 + 4);
+    let safe = reader.bytes(1)[0] != 0;
+    assert!(reader.is_empty());
+    (time, clock, safe)
+}
+
+fn restart_count(tpm: &mut TpmInstance) -> u32 {
+    let response = run(tpm, TPM_CC_READ_CLOCK, &[], None, &[]).unwrap();
+    let mut reader = Reader::new(&response);
+    reader.bytes(8 + 8 + 4);
+    reader.u32()
+}
+
+#[test]
+fn clock_and_time() {
+    let mut tpm = power_on();
+    let (time, clock, safe) = read_clock(&mut tpm);
+    assert_eq!((time, clock, safe), (0, 0, true));
+
+    advance_time(5000);
+    assert_eq!(read_clock(&mut tpm), (5000, 5000, true));
+
+    // Time starts again at power on, Clock carries on from where an orderly
+    // shutdown left it.
+    shutdown(&mut tpm, TPM_SU_CLEAR).unwrap();
+    let mut tpm = power_on();
+    advance_time(1000);
+    assert_eq!(read_clock(&mut tpm), (1000, 6000, true));
+}
+
+// After a power loss, Clock skips ahead of anything it could have reported
+// and isn't safe until it's next written to NV.
+#[test]
+fn clock_after_power_loss() {
+    let mut tpm = power_on();
+    advance_time(CLOCK_NV_INTERVAL + 10);
+    let (_, clock, safe) = read_clock(&mut tpm);
+    assert_eq!((clock, safe), (CLOCK_NV_INTERVAL + 10, true));
+
+    let mut tpm = power_on();
+    let (_, after, safe) = read_clock(&mut tpm);
+    assert_eq!((after, safe), (2 * CLOCK_NV_INTERVAL + 10, false));
+
+    advance_time(CLOCK_NV_INTERVAL);
+    let (_, _, safe) = read_clock(&mut tpm);
+    assert!(safe);
+}
+
+// TPM Restarts and Resumes count up until the next TPM Reset.
+#[test]
+fn restarts() {
+    let mut tpm = power_on();
+    assert_eq!(restart_count(&mut tpm), 0);
+
+    shutdown(&mut tpm, TPM_SU_STATE).unwrap();
+    let mut tpm = TpmInstance::new(&platform());
+    startup(&mut tpm, TPM_SU_STATE).unwrap();
+    assert_eq!(restart_count(&mut tpm), 1);
+
+    shutdown(&mut tpm, TPM_SU_STATE).unwrap();
+    let mut tpm = power_on();
+    assert_eq!(restart_count(&mut tpm), 2);
+
+    shutdown(&mut tpm, TPM_SU_CLEAR).unwrap();
+    let mut tpm = power_on();
+    assert_eq!(restart_count(&mut tpm), 0);
+}
@@ -0,0 +1,603 @@
+// Helpers shared by the command tests. Each test runs on its own thread and
+// gets its own NV and random stream, so tests can run in parallel.
+#![allow(dead_code)]
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+use tpm::platform::TpmPlatform;
+use tpm::tpm::TpmInstance;
+use tpm::types::NV_MEMORY_SIZE;
+
+pub const TPM_RH_OWNER: u32 = 0x40000001;
+pub const TPM_RH_NULL: u32 = 0x40000007;
+pub const TPM_RS_PW: u32 = 0x40000009;
+
+pub const TPM_CC_NV_DEFINE_SPACE: u32 = 0x12A;
+pub const TPM_CC_CREATE_PRIMARY: u32 = 0x131;
+pub const TPM_CC_NV_WRITE: u32 = 0x137;
+pub const TPM_CC_NV_WRITE_LOCK: u32 = 0x138;
+pub const TPM_CC_STARTUP: u32 = 0x144;
+pub const TPM_CC_SHUTDOWN: u32 = 0x145;
+pub const TPM_CC_NV_READ: u32 = 0x14E;
+pub const TPM_CC_CREATE: u32 = 0x153;
+pub const TPM_CC_LOAD: u32 = 0x157;
+pub const TPM_CC_SIGN: u32 = 0x15D;
+pub const TPM_CC_UNSEAL: u32 = 0x15E;
+pub const TPM_CC_CONTEXT_LOAD: u32 = 0x161;
+pub const TPM_CC_CONTEXT_SAVE: u32 = 0x162;
+pub const TPM_CC_FLUSH_CONTEXT: u32 = 0x165;
+pub const TPM_CC_POLICY_AUTH_VALUE: u32 = 0x16B;
+pub const TPM_CC_POLICY_COMMAND_CODE: u32 = 0x16C;
+pub const TPM_CC_POLICY_OR: u32 = 0x171;
+pub const TPM_CC_START_AUTH_SESSION: u32 = 0x176;
+pub const TPM_CC_VERIFY_SIGNATURE: u32 = 0x177;
+pub const TPM_CC_GET_CAPABILITY: u32 = 0x17A;
+pub const TPM_CC_PCR_READ: u32 = 0x17E;
+pub const TPM_CC_READ_CLOCK: u32 = 0x181;
+pub const TPM_CC_PCR_EXTEND: u32 = 0x182;
+pub const TPM_CC_POLICY_GET_DIGEST: u32 = 0x189;
+pub const TPM_CC_POLICY_PASSWORD: u32 = 0x18C;
+
+pub const TPM_ALG_SHA256: u16 = 0x000B;
+
+pub const TPM_SE_HMAC: u8 = 0;
+pub const TPM_SE_POLICY: u8 = 1;
+pub const TPM_SE_TRIAL: u8 = 3;
+
+pub const TPMA_SESSION_CONTINUE_SESSION: u8 = 1;
+
+pub const TPM_CAP_TPM_PROPERTIES: u32 = 6;
+
+pub const TPM_SU_CLEAR: u16 = 0;
+pub const TPM_SU_STATE: u16 = 1;
+
+const TPM_ST_NO_SESSIONS: u16 = 0x8001;
+const TPM_ST_SESSIONS: u16 = 0x8002;
+
+thread_local! {
+    static NV: RefCell<Vec<u8>> = RefCell::new(vec![0; NV_MEMORY_SIZE]);
+    static RANDOM: Cell<u64> = const { Cell::new(0) };
+    static TIME: Cell<u64> = const { Cell::new(0) };
+    static NV_FAILING: Cell<bool> = const { Cell::new(false) };
+}
+
+fn test_log(_msg: &str) {}
+
+// Not random at all, which keeps failures reproducible.
+fn test_get_random(buf: &mut [u8]) {
+    RANDOM.with(|counter| {
+        for byte in buf.iter_mut() {
+            let n = counter.get().wrapping_add(1);
+            counter.set(n);
+            *byte = (n.wrapping_mul(0x9E3779B97F4A7C15) >> 56) as u8;
+        }
+    })
+}
+
+fn test_nv_read(offset: usize, buf: &mut [u8]) -> bool {
+    NV.with(|nv| buf.copy_from_slice(&nv.borrow()[offset..offset + buf.len()]));
+    true
+}
+
+fn test_nv_write(offset: usize, data: &[u8]) -> bool {
+    if NV_FAILING.with(|failing| failing.get()) {
+        return false;
+    }
+    NV.with(|nv| nv.borrow_mut()[offset..offset + data.len()].copy_from_slice(data));
+    true
+}
+
+// Make the platform's NV writes fail, or work again.
+pub fn fail_nv_writes(failing: bool) {
+    NV_FAILING.with(|f| f.set(failing));
+}
+
+fn test_get_time() -> u64 {
+    TIME.with(|time| time.get())
+}
+
+// Move the platform's time on by `ms` milliseconds. The TPM sees it at the
+// next command.
+pub fn advance_time(ms: u64) {
+    TIME.with(|time| time.set(time.get() + ms));
+}
+
+pub fn platform() -> TpmPlatform {
+    TpmPlatform {
+        log: test_log,
+        get_random: test_get_random,
+        nv_read: test_nv_read,
+        nv_write: test_nv_write,
+        get_time: test_get_time,
+    }
+}
+
+// A TPM on this thread's NV that has been started with Startup(CLEAR). A
+// second call is the same TPM after a power cycle without an orderly
+// shutdown.
+pub fn power_on() -> TpmInstance {
+    let mut tpm = TpmInstance::new(&platform());
+    startup(&mut tpm, TPM_SU_CLEAR).unwrap();
+    tpm
+}
+
+pub fn startup(tpm: &mut TpmInstance, su: u16) -> Result<(), u32> {
+    run(tpm, TPM_CC_STARTUP, &[], None, &su.to_be_bytes()).map(|_| ())
+}
+
+pub fn shutdown(tpm: &mut TpmInstance, su: u16) -> Result<(), u32> {
+    run(tpm, TPM_CC_SHUTDOWN, &[], None, &su.to_be_bytes()).map(|_| ())
+}
+
+// The value of a TPM_PT property from TPM2_GetCapability
+pub fn get_tpm_property(tpm: &mut TpmInstance, property: u32) -> u32 {
+    let params = [TPM_CAP_TPM_PROPERTIES, property, 1]
+        .map(u32::to_be_bytes)
+        .concat();
+    let response = run(tpm, TPM_CC_GET_CAPABILITY, &[], None, &params).unwrap();
+    let mut reader = Reader::new(&response);
+    reader.bytes(1 + 4 + 4);
+    assert_eq!(reader.u32(), property);
+    reader.u32()
+}
+
+// Run a command. `auths` are the password sessions, one per handle that
+// needs authorization, or None for a command without sessions. Returns the
+// response after the header, or the response code.
+pub fn run(
+    tpm: &mut TpmInstance,
+    cc: u32,
+    handles: &[u32],
+    auths: Option<&[&[u8]]>,
+    params: &[u8],
+) -> Result<Vec<u8>, u32> {
+    let auths = auths.map(|auths| {
+        auths
+            .iter()
+            .map(|auth| auth_command(TPM_RS_PW, &[], TPMA_SESSION_CONTINUE_SESSION, auth))
+            .collect::<Vec<_>>()
+    });
+    run_sessions(tpm, cc, handles, auths.as_deref(), params)
+}
+
+// TPMS_AUTH_COMMAND
+pub fn auth_command(handle: u32, nonce: &[u8], attributes: u8, hmac: &[u8]) -> Vec<u8> {
+    [
+        handle.to_be_bytes().to_vec(),
+        tpm2b(nonce),
+        vec![attributes],
+        tpm2b(hmac),
+    ]
+    .concat()
+}
+
+// Run a command with sessions made by `auth_command`.
+pub fn run_sessions(
+    tpm: &mut TpmInstance,
+    cc: u32,
+    handles: &[u32],
+    auths: Option<&[Vec<u8>]>,
+    params: &[u8],
+) -> Result<Vec<u8>, u32> {
+    let mut body = Vec::new();
+    for handle in handles {
+        body.extend(handle.to_be_bytes());
+    }
+    let tag = match auths {
+        Some(auths) => {
+            let area = auths.concat();
+            body.extend((area.len() as u32).to_be_bytes());
+            body.extend(area);
+            TPM_ST_SESSIONS
+        }
+        None => TPM_ST_NO_SESSIONS,
+    };
+    body.extend_from_slice(params);
+
+    let mut request = Vec::new();
+    request.extend(tag.to_be_bytes());
+    request.extend(((10 + body.len()) as u32).to_be_bytes());
+    request.extend(cc.to_be_bytes());
+    request.extend(body);
+
+    let mut response = vec![0u8; 4096];
+    let size = tpm::execute_command(tpm, &request, &mut response);
+    let mut reader = Reader::new(&response[..size]);
+    reader.u16();
+    assert_eq!(reader.u32() as usize, size);
+    match reader.u32() {
+        0 => Ok(response[10..size].to_vec()),
+        rc => Err(rc),
+    }
+}
+
+// The response parameters of a command run with sessions, along with the
+// response handle if there is one.
+pub fn parameters(response: &[u8], has_handle: bool) -> (Option<u32>, Vec<u8>) {
+    let mut reader = Reader::new(response);
+    let handle = has_handle.then(|| reader.u32());
+    let size = reader.u32() as usize;
+    (handle, reader.bytes(size).to_vec())
+}
+
+// The authorization area that follows the parameters in a response
+pub fn response_auths(response: &[u8], has_handle: bool) -> Vec<(Vec<u8>, u8, Vec<u8>)> {
+    let mut reader = Reader::new(response);
+    if has_handle {
+        reader.u32();
+    }
+    let size = reader.u32() as usize;
+    reader.bytes(size);
+
+    let mut auths = Vec::new();
+    while !reader.is_empty() {
+        let nonce = reader.tpm2b().to_vec();
+        let attributes = reader.bytes(1)[0];
+        let hmac = reader.tpm2b().to_vec();
+        auths.push((nonce, attributes, hmac));
+    }
+    auths
+}
+
+pub fn sha256(data: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    for d in data {
+        hasher.update(d);
+    }
+    hasher.finalize().to_vec()
+}
+
+pub fn hmac_sha256(key: &[u8], data: &[&[u8]]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    for d in data {
+        mac.update(d);
+    }
+    mac.finalize().into_bytes().to_vec()
+}
+
+// policyDigest_new = H(policyDigest_old || commandCode || data)
+pub fn policy_extend(digest: &[u8], cc: u32, data: &[u8]) -> Vec<u8> {
+    sha256(&[digest, &cc.to_be_bytes(), data])
+}
+
+// The Name of an object, from its TPM2B_PUBLIC
+pub fn object_name(public: &[u8]) -> Vec<u8> {
+    [&0x000Bu16.to_be_bytes()[..], &sha256(&[&public[2..]])].concat()
+}
+
+pub fn tpm2b(data: &[u8]) -> Vec<u8> {
+    let mut out = (data.len() as u16).to_be_bytes().to_vec();
+    out.extend_from_slice(data);
+    out
+}
+
+pub struct Reader<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buffer: &'a [u8]) -> Reader<'a> {
+        Reader { buffer, offset: 0 }
+    }
+
+    pub fn bytes(&mut self, n: usize) -> &'a [u8] {
+        let bytes = &self.buffer[self.offset..self.offset + n];
+        self.offset += n;
+        bytes
+    }
+
+    pub fn u16(&mut self) -> u16 {
+        u16::from_be_bytes(self.bytes(2).try_into().unwrap())
+    }
+
+    pub fn u32(&mut self) -> u32 {
+        u32::from_be_bytes(self.bytes(4).try_into().unwrap())
+    }
+
+    pub fn u64(&mut self) -> u64 {
+        u64::from_be_bytes(self.bytes(8).try_into().unwrap())
+    }
+
+    pub fn tpm2b(&mut self) -> &'a [u8] {
+        let size = self.u16() as usize;
+        self.bytes(size)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offset == self.buffer.len()
+    }
+}
+
+// TPM2B_SENSITIVE_CREATE
+pub fn sensitive_create(user_auth: &[u8], data: &[u8]) -> Vec<u8> {
+    tpm2b(&[tpm2b(user_auth), tpm2b(data)].concat())
+}
+
+// TPM2B_PUBLIC for an ECC NIST P-256 storage key
+pub fn ecc_storage_template() -> Vec<u8> {
+    let mut public = Vec::new();
+    public.extend(0x0023u16.to_be_bytes()); // TPM_ALG_ECC
+    public.extend(0x000Bu16.to_be_bytes()); // TPM_ALG_SHA256
+
+    // fixedTPM | fixedParent | sensitiveDataOrigin | userWithAuth |
+    // restricted | decrypt
+    public.extend(0x00030072u32.to_be_bytes());
+    public.extend(tpm2b(&[]));
+    public.extend(0x0006u16.to_be_bytes()); // AES
+    public.extend(128u16.to_be_bytes());
+    public.extend(0x0043u16.to_be_bytes()); // CFB
+    public.extend(0x0010u16.to_be_bytes()); // scheme NULL
+    public.extend(0x0003u16.to_be_bytes()); // NIST P-256
+    public.extend(0x0010u16.to_be_bytes()); // KDF NULL
+    public.extend(tpm2b(&[]));
+    public.extend(tpm2b(&[]));
+    tpm2b(&public)
+}
+
+// TPM2B_PUBLIC for an ECDSA SHA-256 NIST P-256 signing key
+pub fn ecc_signing_template() -> Vec<u8> {
+    let mut public = Vec::new();
+    public.extend(0x0023u16.to_be_bytes()); // TPM_ALG_ECC
+    public.extend(0x000Bu16.to_be_bytes()); // TPM_ALG_SHA256
+
+    // fixedTPM | fixedParent | sensitiveDataOrigin | userWithAuth | sign
+    public.extend(0x00040072u32.to_be_bytes());
+    public.extend(tpm2b(&[]));
+    public.extend(0x0010u16.to_be_bytes()); // symmetric NULL
+    public.extend(0x0018u16.to_be_bytes()); // ECDSA
+    public.extend(0x000Bu16.to_be_bytes()); // SHA256
+    public.extend(0x0003u16.to_be_bytes()); // NIST P-256
+    public.extend(0x0010u16.to_be_bytes()); // KDF NULL
+    public.extend(tpm2b(&[]));
+    public.extend(tpm2b(&[]));
+    tpm2b(&public)
+}
+
+// TPM2B_PUBLIC for an HMAC SHA-256 key
+pub fn hmac_key_template() -> Vec<u8> {
+    let mut public = Vec::new();
+    public.extend(0x0008u16.to_be_bytes()); // TPM_ALG_KEYEDHASH
+    public.extend(0x000Bu16.to_be_bytes()); // TPM_ALG_SHA256
+
+    // fixedTPM | fixedParent | sensitiveDataOrigin | userWithAuth | sign
+    public.extend(0x00040072u32.to_be_bytes());
+    public.extend(tpm2b(&[]));
+    public.extend(0x0005u16.to_be_bytes()); // HMAC
+    public.extend(0x000Bu16.to_be_bytes()); // SHA256
+    public.extend(tpm2b(&[]));
+    tpm2b(&public)
+}
+
+// TPM2B_PUBLIC for a sealed data object
+pub fn sealed_data_template() -> Vec<u8> {
+    // fixedTPM | fixedParent | userWithAuth
+    sealed_template(0x00000052, &[])
+}
+
+// TPM2B_PUBLIC for a sealed data object that can only be unsealed with a
+// policy session
+pub fn sealed_policy_template(auth_policy: &[u8]) -> Vec<u8> {
+    // fixedTPM | fixedParent
+    sealed_template(0x00000012, auth_policy)
+}
+
+fn sealed_template(attributes: u32, auth_policy: &[u8]) -> Vec<u8> {
+    let mut public = Vec::new();
+    public.extend(0x0008u16.to_be_bytes()); // TPM_ALG_KEYEDHASH
+    public.extend(0x000Bu16.to_be_bytes()); // TPM_ALG_SHA256
+    public.extend(attributes.to_be_bytes());
+    public.extend(tpm2b(auth_policy));
+    public.extend(0x0010u16.to_be_bytes()); // scheme NULL
+    public.extend(tpm2b(&[]));
+    tpm2b(&public)
+}
+
+// The handle and TPM2B_PUBLIC of a new primary key in the owner hierarchy
+pub fn create_primary(tpm: &mut TpmInstance, template: &[u8]) -> (u32, Vec<u8>) {
+    let params = [
+        sensitive_create(&[], &[]),
+        template.to_vec(),
+        tpm2b(&[]),
+        0u32.to_be_bytes().to_vec(),
+    ]
+    .concat();
+    let response = run(
+        tpm,
+        TPM_CC_CREATE_PRIMARY,
+        &[TPM_RH_OWNER],
+        Some(&[&[]]),
+        &params,
+    )
+    .unwrap();
+    let (handle, params) = parameters(&response, true);
+    let out_public = Reader::new(&params).tpm2b().to_vec();
+    (handle.unwrap(), out_public)
+}
+
+// The TPM2B_PRIVATE and TPM2B_PUBLIC of a new object under `parent`
+pub fn create(
+    tpm: &mut TpmInstance,
+    parent: u32,
+    template: &[u8],
+    user_auth: &[u8],
+    data: &[u8],
+) -> (Vec<u8>, Vec<u8>) {
+    let params = [
+        sensitive_create(user_auth, data),
+        template.to_vec(),
+        tpm2b(&[]),
+        0u32.to_be_bytes().to_vec(),
+    ]
+    .concat();
+    let response = run(tpm, TPM_CC_CREATE, &[parent], Some(&[&[]]), &params).unwrap();
+    let (_, params) = parameters(&response, false);
+    let mut reader = Reader::new(&params);
+    let private = tpm2b(reader.tpm2b());
+    let public = tpm2b(reader.tpm2b());
+    (private, public)
+}
+
+pub fn load(tpm: &mut TpmInstance, parent: u32, private: &[u8], public: &[u8]) -> u32 {
+    let params = [private, public].concat();
+    let response = run(tpm, TPM_CC_LOAD, &[parent], Some(&[&[]]), &params).unwrap();
+    parameters(&response, true).0.unwrap()
+}
+
+pub fn flush(tpm: &mut TpmInstance, handle: u32) {
+    run(tpm, TPM_CC_FLUSH_CONTEXT, &[], None, &handle.to_be_bytes()).unwrap();
+}
+
+// The TPMS_CONTEXT of a saved object, sequence or session
+pub fn context_save(tpm: &mut TpmInstance, handle: u32) -> Result<Vec<u8>, u32> {
+    run(tpm, TPM_CC_CONTEXT_SAVE, &[handle], None, &[])
+}
+
+pub fn context_load(tpm: &mut TpmInstance, context: &[u8]) -> Result<u32, u32> {
+    let response = run(tpm, TPM_CC_CONTEXT_LOAD, &[], None, context)?;
+    Ok(Reader::new(&response).u32())
+}
+
+// A session from StartAuthSession, along with what the caller needs to
+// compute its HMACs. The caller reuses its nonce, which the TPM can't tell.
+pub struct AuthSession {
+    pub handle: u32,
+    pub nonce_tpm: Vec<u8>,
+    pub nonce_caller: Vec<u8>,
+    pub session_key: Vec<u8>,
+}
+
+// Start an unsalted SHA-256 session bound to `bind`, whose authValue is
+// `bind_auth`.
+pub fn start_auth_session(
+    tpm: &mut TpmInstance,
+    session_type: u8,
+    bind: u32,
+    bind_auth: &[u8],
+) -> AuthSession {
+    let nonce_caller = vec![0x11; 16];
+    let params = [
+        tpm2b(&nonce_caller),
+        tpm2b(&[]),
+        vec![session_type],
+        0x0010u16.to_be_bytes().to_vec(), // symmetric NULL
+        0x000Bu16.to_be_bytes().to_vec(), // SHA256
+    ]
+    .concat();
+    let response = run(
+        tpm,
+        TPM_CC_START_AUTH_SESSION,
+        &[TPM_RH_NULL, bind],
+        None,
+        &params,
+    )
+    .unwrap();
+    let mut reader = Reader::new(&response);
+    let handle = reader.u32();
+    let nonce_tpm = reader.tpm2b().to_vec();
+
+    let session_key = match bind_auth.iter().any(|b| *b != 0) {
+        true => kdfa_sha256(bind_auth, b"ATH", &nonce_tpm, &nonce_caller),
+        false => Vec::new(),
+    };
+    AuthSession {
+        handle,
+        nonce_tpm,
+        nonce_caller,
+        session_key,
+    }
+}
+
+// A single block of KDFa with SHA-256
+pub fn kdfa_sha256(key: &[u8], label: &[u8], context_u: &[u8], context_v: &[u8]) -> Vec<u8> {
+    hmac_sha256(
+        key,
+        &[
+            &1u32.to_be_bytes(),
+            label,
+            &[0],
+            context_u,
+            context_v,
+            &256u32.to_be_bytes(),
+        ],
+    )
+}
+
+// H(commandCode || Names || parameters)
+pub fn cp_hash(cc: u32, names: &[&[u8]], params: &[u8]) -> Vec<u8> {
+    sha256(&[&cc.to_be_bytes(), &names.concat(), params])
+}
+
+// H(responseCode || commandCode || parameters)
+pub fn rp_hash(cc: u32, params: &[u8]) -> Vec<u8> {
+    sha256(&[&0u32.to_be_bytes(), &cc.to_be_bytes(), params])
+}
+
+impl AuthSession {
+    // The TPMS_AUTH_COMMAND for a command with `cp_hash`. `auth_value` is
+    // the authValue the HMAC covers, if any.
+    pub fn command(&self, cp_hash: &[u8], auth_value: &[u8], attributes: u8) -> Vec<u8> {
+        let key = [&self.session_key[..], auth_value].concat();
+        let hmac = hmac_sha256(
+            &key,
+            &[cp_hash, &self.nonce_caller, &self.nonce_tpm, &[attributes]],
+        );
+        auth_command(self.handle, &self.nonce_caller, attributes, &hmac)
+    }
+
+    // Check the response HMAC, and take the new nonceTPM.
+    pub fn response(&mut self, rp_hash: &[u8], auth_value: &[u8], auth: &(Vec<u8>, u8, Vec<u8>)) {
+        let (nonce_tpm, attributes, hmac) = auth;
+        let key = [&self.session_key[..], auth_value].concat();
+        let expected = hmac_sha256(
+            &key,
+            &[rp_hash, nonce_tpm, &self.nonce_caller, &[*attributes]],
+        );
+        assert_eq!(*hmac, expected);
+        assert_ne!(*nonce_tpm, self.nonce_tpm);
+        self.nonce_tpm = nonce_tpm.clone();
+    }
+}
+
+pub fn policy_command_code(tpm: &mut TpmInstance, session: u32, cc: u32) {
+    run(
+        tpm,
+        TPM_CC_POLICY_COMMAND_CODE,
+        &[session],
+        None,
+        &cc.to_be_bytes(),
+    )
+    .unwrap();
+}
+
+pub fn policy_get_digest(tpm: &mut TpmInstance, session: u32) -> Vec<u8> {
+    let response = run(tpm, TPM_CC_POLICY_GET_DIGEST, &[session], None, &[]).unwrap();
+    Reader::new(&response).tpm2b().to_vec()
+}
+
+// A TPML_PCR_SELECTION of `pcrs` in one bank
+pub fn pcr_selection(hash_alg: u16, pcrs: &[usize]) -> Vec<u8> {
+    let mut select = [0u8; 3];
+    for pcr in pcrs {
+        select[pcr / 8] |= 1 << (pcr % 8);
+    }
+    [
+        &1u32.to_be_bytes()[..],
+        &hash_alg.to_be_bytes(),
+        &[3],
+        &select,
+    ]
+    .concat()
+}
+
+// Extend a PCR's SHA-256 bank with `digest`.
+pub fn pcr_extend(tpm: &mut TpmInstance, pcr: u32, digest: &[u8]) {
+    let params = [
+        &1u32.to_be_bytes()[..],
+        &TPM_ALG_SHA256.to_be_bytes(),
+        digest,
+    ]
+    .concat();
+    run(tpm, TPM_CC_PCR_EXTEND, &[pcr], Some(&[&[]]), &params).unwrap();
+}
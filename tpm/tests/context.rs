@@ -0,0 +1,192 @@
+mod common;
+
+use common::*;
+
+const TPM_CC_MAC: u32 = 0x155;
+const TPM_CC_MAC_START: u32 = 0x15B;
+const TPM_CC_SEQUENCE_UPDATE: u32 = 0x15C;
+const TPM_CC_SEQUENCE_COMPLETE: u32 = 0x13E;
+
+const TPM_RC_REFERENCE_H0: u32 = 0x910;
+const TPM_RC_CONTEXT_GAP: u32 = 0x901;
+// Format-one codes for the first parameter
+const TPM_RC_HANDLE_P1: u32 = 0x1CB;
+const TPM_RC_INTEGRITY_P1: u32 = 0x1DF;
+
+// An object's context loads into a new handle, any number of times, but
+// not after a TPM Reset.
+#[test]
+fn object_context() {
+    let mut tpm = power_on();
+    let (parent, _) = create_primary(&mut tpm, &ecc_storage_template());
+    let secret = b"the sealed secret";
+    let (private, public) = create(&mut tpm, parent, &sealed_data_template(), &[], secret);
+    let item = load(&mut tpm, parent, &private, &public);
+
+    let context = context_save(&mut tpm, item).unwrap();
+    flush(&mut tpm, item);
+    for _ in 0..2 {
+        let item = context_load(&mut tpm, &context).unwrap();
+        let response = run(&mut tpm, TPM_CC_UNSEAL, &[item], Some(&[&[]]), &[]).unwrap();
+        let (_, params) = parameters(&response, false);
+        assert_eq!(Reader::new(&params).tpm2b(), secret);
+        flush(&mut tpm, item);
+    }
+
+    // The last byte is in the encrypted part.
+    let mut tampered = context.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert_eq!(context_load(&mut tpm, &tampered), Err(TPM_RC_INTEGRITY_P1));
+
+    let mut tpm = power_on();
+    assert_eq!(context_load(&mut tpm, &context), Err(TPM_RC_INTEGRITY_P1));
+}
+
+// A policy session's context takes it out of its slot, and loads back into
+// the same handle with its policy intact. Only the latest context loads.
+#[test]
+fn session_context() {
+    let mut tpm = power_on();
+    let session = start_auth_session(&mut tpm, TPM_SE_POLICY, TPM_RH_NULL, &[]);
+    policy_command_code(&mut tpm, session.handle, TPM_CC_UNSEAL);
+    let digest = policy_get_digest(&mut tpm, session.handle);
+
+    let context = context_save(&mut tpm, session.handle).unwrap();
+    let rc = run(
+        &mut tpm,
+        TPM_CC_POLICY_GET_DIGEST,
+        &[session.handle],
+        None,
+        &[],
+    );
+    assert_eq!(rc, Err(TPM_RC_REFERENCE_H0));
+
+    assert_eq!(context_load(&mut tpm, &context), Ok(session.handle));
+    assert_eq!(policy_get_digest(&mut tpm, session.handle), digest);
+    assert_eq!(context_load(&mut tpm, &context), Err(TPM_RC_HANDLE_P1));
+
+    // Saving again makes the earlier context stale.
+    let stale = context;
+    let context = context_save(&mut tpm, session.handle).unwrap();
+    assert_eq!(context_load(&mut tpm, &stale), Err(TPM_RC_HANDLE_P1));
+
+    // A flushed session can't be loaded.
+    flush(&mut tpm, session.handle);
+    assert_eq!(context_load(&mut tpm, &context), Err(TPM_RC_HANDLE_P1));
+}
+
+// Saved sessions survive a TPM Resume, but not a TPM Reset.
+#[test]
+fn session_context_across_startup() {
+    let mut tpm = power_on();
+    let session = start_auth_session(&mut tpm, TPM_SE_POLICY, TPM_RH_NULL, &[]);
+    policy_command_code(&mut tpm, session.handle, TPM_CC_UNSEAL);
+    let digest = policy_get_digest(&mut tpm, session.handle);
+    let context = context_save(&mut tpm, session.handle).unwrap();
+
+    shutdown(&mut tpm, TPM_SU_STATE).unwrap();
+    let mut tpm = tpm::tpm::TpmInstance::new(&platform());
+    startup(&mut tpm, TPM_SU_STATE).unwrap();
+    assert_eq!(context_load(&mut tpm, &context), Ok(session.handle));
+    assert_eq!(policy_get_digest(&mut tpm, session.handle), digest);
+    let context = context_save(&mut tpm, session.handle).unwrap();
+
+    let mut tpm = power_on();
+    assert_eq!(context_load(&mut tpm, &context), Err(TPM_RC_INTEGRITY_P1));
+}
+
+// The contextIDs of saved sessions can only spread so far. The oldest one
+// has to be loaded or flushed before another session can be saved.
+#[test]
+fn session_context_gap() {
+    let mut tpm = power_on();
+    let oldest = start_auth_session(&mut tpm, TPM_SE_HMAC, TPM_RH_NULL, &[]);
+    context_save(&mut tpm, oldest.handle).unwrap();
+
+    let session = start_auth_session(&mut tpm, TPM_SE_HMAC, TPM_RH_NULL, &[]);
+    let mut saved = 1;
+    let rc = loop {
+        match context_save(&mut tpm, session.handle) {
+            Ok(context) => {
+                context_load(&mut tpm, &context).unwrap();
+                saved += 1;
+            }
+            Err(rc) => break rc,
+        }
+    };
+    assert_eq!(rc, TPM_RC_CONTEXT_GAP);
+    assert_eq!(saved, 255);
+
+    flush(&mut tpm, oldest.handle);
+    context_save(&mut tpm, session.handle).unwrap();
+}
+
+// A MAC sequence picks up where it left off from its context, whichever
+// handle it loads into.
+#[test]
+fn sequence_context() {
+    let mut tpm = power_on();
+    let (key, _) = create_primary(&mut tpm, &hmac_key_template());
+    let data = [0x5Au8; 150];
+    let sha256 = 0x000Bu16.to_be_bytes();
+
+    let params = [tpm2b(&data), sha256.to_vec()].concat();
+    let response = run(&mut tpm, TPM_CC_MAC, &[key], Some(&[&[]]), &params).unwrap();
+    let (_, params) = parameters(&response, false);
+    let expected = Reader::new(&params).tpm2b().to_vec();
+
+    let params = [tpm2b(b"seq"), sha256.to_vec()].concat();
+    let response = run(&mut tpm, TPM_CC_MAC_START, &[key], Some(&[&[]]), &params).unwrap();
+    let sequence = parameters(&response, true).0.unwrap();
+    let params = tpm2b(&data[..100]);
+    run(
+        &mut tpm,
+        TPM_CC_SEQUENCE_UPDATE,
+        &[sequence],
+        Some(&[b"seq"]),
+        &params,
+    )
+    .unwrap();
+
+    let context = context_save(&mut tpm, sequence).unwrap();
+    flush(&mut tpm, sequence);
+    let sequence = context_load(&mut tpm, &context).unwrap();
+
+    let params = [tpm2b(&data[100..]), TPM_RH_NULL.to_be_bytes().to_vec()].concat();
+    let response = run(
+        &mut tpm,
+        TPM_CC_SEQUENCE_COMPLETE,
+        &[sequence],
+        Some(&[b"seq"]),
+        &params,
+    )
+    .unwrap();
+    let (_, params) = parameters(&response, false);
+    assert_eq!(Reader::new(&params).tpm2b(), expected);
+}
+
+// A TPM Restart keeps saved objects loadable, except stClear ones.
+#[test]
+fn st_clear_context_across_restart() {
+    let mut tpm = power_on();
+    let (parent, _) = create_primary(&mut tpm, &ecc_storage_template());
+    let mut st_clear_template = ecc_signing_template();
+    // fixedTPM | stClear | fixedParent | sensitiveDataOrigin | userWithAuth |
+    // sign
+    st_clear_template[6..10].copy_from_slice(&0x00040076u32.to_be_bytes());
+
+    let (private, public) = create(&mut tpm, parent, &ecc_signing_template(), &[], &[]);
+    let key = load(&mut tpm, parent, &private, &public);
+    let context = context_save(&mut tpm, key).unwrap();
+    let (private, public) = create(&mut tpm, parent, &st_clear_template, &[], &[]);
+    let key = load(&mut tpm, parent, &private, &public);
+    let st_clear_context = context_save(&mut tpm, key).unwrap();
+
+    shutdown(&mut tpm, TPM_SU_STATE).unwrap();
+    let mut tpm = power_on();
+    assert!(context_load(&mut tpm, &context).is_ok());
+    assert_eq!(
+        context_load(&mut tpm, &st_clear_context),
+        Err(TPM_RC_INTEGRITY_P1)
+    );
+}
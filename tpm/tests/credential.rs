@@ -0,0 +1,168 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+const TPM_RH_ENDORSEMENT: u32 = 0x4000000B;
+const TPM_CC_ACTIVATE_CREDENTIAL: u32 = 0x147;
+const TPM_CC_POLICY_SECRET: u32 = 0x151;
+const TPM_CC_MAKE_CREDENTIAL: u32 = 0x168;
+
+// Format-one codes
+const TPM_RC_NONCE_P1: u32 = 0x1CF;
+const TPM_RC_EXPIRED_P4: u32 = 0x4E3;
+const TPM_RC_EXPIRED_S2: u32 = 0xAA3;
+
+// The authPolicy of the TCG default EK templates: PolicySecret of the
+// endorsement hierarchy, with no policyRef
+const EK_POLICY: &str = "837197674484b3f81a90cc8d46a5d724fd52d76e06520b64f2a1da1b331469aa";
+
+fn ek_policy() -> Vec<u8> {
+    (0..EK_POLICY.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&EK_POLICY[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+// An ECC storage key in the endorsement hierarchy that, like an EK, can
+// only be used for ActivateCredential through its policy
+fn create_ek(tpm: &mut TpmInstance) -> u32 {
+    let mut template = ecc_storage_template();
+    // fixedTPM | fixedParent | sensitiveDataOrigin | adminWithPolicy |
+    // restricted | decrypt
+    template[6..10].copy_from_slice(&0x000300B2u32.to_be_bytes());
+    template.splice(10..12, tpm2b(&ek_policy()));
+    let size = (template.len() - 2) as u16;
+    template[..2].copy_from_slice(&size.to_be_bytes());
+
+    let params = [
+        sensitive_create(&[], &[]),
+        template,
+        tpm2b(&[]),
+        0u32.to_be_bytes().to_vec(),
+    ]
+    .concat();
+    let response = run(
+        tpm,
+        TPM_CC_CREATE_PRIMARY,
+        &[TPM_RH_ENDORSEMENT],
+        Some(&[&[]]),
+        &params,
+    )
+    .unwrap();
+    parameters(&response, true).0.unwrap()
+}
+
+fn policy_secret(
+    tpm: &mut TpmInstance,
+    session: u32,
+    nonce_tpm: &[u8],
+    expiration: i32,
+) -> Result<Vec<u8>, u32> {
+    let params = [
+        tpm2b(nonce_tpm),
+        tpm2b(&[]),
+        tpm2b(&[]),
+        expiration.to_be_bytes().to_vec(),
+    ]
+    .concat();
+    run(
+        tpm,
+        TPM_CC_POLICY_SECRET,
+        &[TPM_RH_ENDORSEMENT, session],
+        Some(&[&[]]),
+        &params,
+    )
+}
+
+// The credentialBlob and secret from MakeCredential, ready to pass to
+// ActivateCredential
+fn make_credential(tpm: &mut TpmInstance, ek: u32, name: &[u8], credential: &[u8]) -> Vec<u8> {
+    let params = [tpm2b(credential), tpm2b(name)].concat();
+    run(tpm, TPM_CC_MAKE_CREDENTIAL, &[ek], None, &params).unwrap()
+}
+
+fn activate_credential(
+    tpm: &mut TpmInstance,
+    ak: u32,
+    ek: u32,
+    session: &AuthSession,
+    blob: &[u8],
+) -> Result<Vec<u8>, u32> {
+    let auths = [
+        auth_command(TPM_RS_PW, &[], TPMA_SESSION_CONTINUE_SESSION, &[]),
+        auth_command(
+            session.handle,
+            &session.nonce_caller,
+            TPMA_SESSION_CONTINUE_SESSION,
+            &[],
+        ),
+    ];
+    run_sessions(
+        tpm,
+        TPM_CC_ACTIVATE_CREDENTIAL,
+        &[ak, ek],
+        Some(&auths),
+        blob,
+    )
+}
+
+#[test]
+fn ek_policy_digest() {
+    let mut tpm = power_on();
+    let trial = start_auth_session(&mut tpm, TPM_SE_TRIAL, TPM_RH_NULL, &[]);
+    let response = policy_secret(&mut tpm, trial.handle, &[], 0).unwrap();
+
+    // An empty timeout and a NULL ticket
+    let (_, params) = parameters(&response, false);
+    let mut reader = Reader::new(&params);
+    assert!(reader.tpm2b().is_empty());
+    assert_eq!(reader.u16(), 0x8023); // TPM_ST_AUTH_SECRET
+    assert_eq!(reader.u32(), TPM_RH_NULL);
+    assert!(reader.tpm2b().is_empty());
+    assert!(reader.is_empty());
+
+    assert_eq!(policy_get_digest(&mut tpm, trial.handle), ek_policy());
+}
+
+#[test]
+fn activate_with_ek() {
+    let mut tpm = power_on();
+    let ek = create_ek(&mut tpm);
+    let (parent, _) = create_primary(&mut tpm, &ecc_storage_template());
+    let (private, public) = create(&mut tpm, parent, &ecc_signing_template(), &[], &[]);
+    let ak = load(&mut tpm, parent, &private, &public);
+
+    let credential = b"the credential";
+    let blob = make_credential(&mut tpm, ek, &object_name(&public), credential);
+
+    let session = start_auth_session(&mut tpm, TPM_SE_POLICY, TPM_RH_NULL, &[]);
+    policy_secret(&mut tpm, session.handle, &[], 0).unwrap();
+    let response = activate_credential(&mut tpm, ak, ek, &session, &blob).unwrap();
+    let (_, params) = parameters(&response, false);
+    assert_eq!(Reader::new(&params).tpm2b(), credential);
+}
+
+// expiration counts from when the session's nonceTPM was made.
+#[test]
+fn expiration() {
+    let mut tpm = power_on();
+    let ek = create_ek(&mut tpm);
+    let (parent, _) = create_primary(&mut tpm, &ecc_storage_template());
+    let (private, public) = create(&mut tpm, parent, &ecc_signing_template(), &[], &[]);
+    let ak = load(&mut tpm, parent, &private, &public);
+    let blob = make_credential(&mut tpm, ek, &object_name(&public), b"credential");
+
+    let session = start_auth_session(&mut tpm, TPM_SE_POLICY, TPM_RH_NULL, &[]);
+    let rc = policy_secret(&mut tpm, session.handle, &[0; 16], 10);
+    assert_eq!(rc, Err(TPM_RC_NONCE_P1));
+    policy_secret(&mut tpm, session.handle, &session.nonce_tpm, 10).unwrap();
+
+    advance_time(10 * 1000);
+    let rc = activate_credential(&mut tpm, ak, ek, &session, &blob);
+    assert_eq!(rc, Err(TPM_RC_EXPIRED_S2));
+
+    // It's already too late for a new PolicySecret against this nonceTPM.
+    let rc = policy_secret(&mut tpm, session.handle, &session.nonce_tpm, 10);
+    assert_eq!(rc, Err(TPM_RC_EXPIRED_P4));
+}
@@ -0,0 +1,24 @@
+// The default platform needs getrandom.
+#![cfg(feature = "getrandom")]
+
+mod common;
+
+use common::*;
+use tpm::platform::TpmPlatform;
+use tpm::tpm::TpmInstance;
+
+// The default platform has no NV of its own, so its NV is RAM. Everything,
+// including writes to NV, has to work on it. That NV is shared by the whole
+// process, so this test has a binary to itself.
+#[test]
+fn startup_on_default_platform() {
+    let mut tpm = TpmInstance::new(&TpmPlatform::default());
+    startup(&mut tpm, TPM_SU_CLEAR).unwrap();
+    let (_, public) = create_primary(&mut tpm, &ecc_storage_template());
+
+    shutdown(&mut tpm, TPM_SU_STATE).unwrap();
+    let mut tpm = TpmInstance::new(&TpmPlatform::default());
+    startup(&mut tpm, TPM_SU_STATE).unwrap();
+    let (_, again) = create_primary(&mut tpm, &ecc_storage_template());
+    assert_eq!(public, again);
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+const TPM_RH_LOCKOUT: u32 = 0x4000000A;
+const TPM_CC_DICTIONARY_ATTACK_LOCK_RESET: u32 = 0x139;
+const TPM_CC_DICTIONARY_ATTACK_PARAMETERS: u32 = 0x13A;
+
+const TPM_PT_PERMANENT: u32 = 0x200;
+const TPM_PT_LOCKOUT_COUNTER: u32 = 0x20E;
+const TPMA_PERMANENT_IN_LOCKOUT: u32 = 1 << 9;
+
+const TPM_RC_LOCKOUT: u32 = 0x921;
+const TPM_RC_AUTH_FAIL_S1: u32 = 0x98E;
+
+// The defaults: three tries, healing one every 1000 seconds, and 1000
+// seconds before lockoutAuth can be tried again.
+const RECOVERY_TIME_MS: u64 = 1000 * 1000;
+
+// A sealed object with authValue "secret", which DA protects
+fn load_sealed(tpm: &mut TpmInstance) -> u32 {
+    let (parent, _) = create_primary(tpm, &ecc_storage_template());
+    let (private, public) = create(tpm, parent, &sealed_data_template(), b"secret", b"data");
+    load(tpm, parent, &private, &public)
+}
+
+fn unseal(tpm: &mut TpmInstance, handle: u32, auth: &[u8]) -> Result<Vec<u8>, u32> {
+    run(tpm, TPM_CC_UNSEAL, &[handle], Some(&[auth]), &[])
+}
+
+fn lock_reset(tpm: &mut TpmInstance, auth: &[u8]) -> Result<Vec<u8>, u32> {
+    run(
+        tpm,
+        TPM_CC_DICTIONARY_ATTACK_LOCK_RESET,
+        &[TPM_RH_LOCKOUT],
+        Some(&[auth]),
+        &[],
+    )
+}
+
+#[test]
+fn lockout_and_self_heal() {
+    let mut tpm = power_on();
+    let sealed = load_sealed(&mut tpm);
+    assert_eq!(get_tpm_property(&mut tpm, TPM_PT_LOCKOUT_COUNTER), 0);
+
+    for _ in 0..3 {
+        assert_eq!(unseal(&mut tpm, sealed, b"wrong"), Err(TPM_RC_AUTH_FAIL_S1));
+    }
+    assert_eq!(get_tpm_property(&mut tpm, TPM_PT_LOCKOUT_COUNTER), 3);
+    assert_ne!(
+        get_tpm_property(&mut tpm, TPM_PT_PERMANENT) & TPMA_PERMANENT_IN_LOCKOUT,
+        0
+    );
+
+    // Even the right authValue is refused now.
+    assert_eq!(unseal(&mut tpm, sealed, b"secret"), Err(TPM_RC_LOCKOUT));
+
+    // Each recoveryTime takes one failure off.
+    advance_time(RECOVERY_TIME_MS - 1);
+    assert_eq!(unseal(&mut tpm, sealed, b"secret"), Err(TPM_RC_LOCKOUT));
+    advance_time(1);
+    assert!(unseal(&mut tpm, sealed, b"secret").is_ok());
+    assert_eq!(get_tpm_property(&mut tpm, TPM_PT_LOCKOUT_COUNTER), 2);
+}
+
+// Failures are in NV, and a power loss counts as one more.
+#[test]
+fn failures_survive_power_loss() {
+    let mut tpm = power_on();
+    let sealed = load_sealed(&mut tpm);
+    assert_eq!(unseal(&mut tpm, sealed, b"wrong"), Err(TPM_RC_AUTH_FAIL_S1));
+    shutdown(&mut tpm, TPM_SU_CLEAR).unwrap();
+
+    let mut tpm = power_on();
+    assert_eq!(get_tpm_property(&mut tpm, TPM_PT_LOCKOUT_COUNTER), 1);
+
+    let mut tpm = power_on();
+    assert_eq!(get_tpm_property(&mut tpm, TPM_PT_LOCKOUT_COUNTER), 2);
+}
+
+#[test]
+fn lock_reset_and_lockout_auth() {
+    let mut tpm = power_on();
+    let sealed = load_sealed(&mut tpm);
+    for _ in 0..3 {
+        assert_eq!(unseal(&mut tpm, sealed, b"wrong"), Err(TPM_RC_AUTH_FAIL_S1));
+    }
+
+    lock_reset(&mut tpm, &[]).unwrap();
+    assert_eq!(get_tpm_property(&mut tpm, TPM_PT_LOCKOUT_COUNTER), 0);
+    assert!(unseal(&mut tpm, sealed, b"secret").is_ok());
+
+    // lockoutAuth gets a single try, then waits out lockoutRecovery.
+    assert_eq!(lock_reset(&mut tpm, b"wrong"), Err(TPM_RC_AUTH_FAIL_S1));
+    assert_eq!(lock_reset(&mut tpm, &[]), Err(TPM_RC_LOCKOUT));
+    advance_time(RECOVERY_TIME_MS);
+    lock_reset(&mut tpm, &[]).unwrap();
+}
+
+#[test]
+fn parameters() {
+    let mut tpm = power_on();
+    let sealed = load_sealed(&mut tpm);
+
+    // A recoveryTime of 0 turns the protection off.
+    let params = [1u32, 0, 0].map(u32::to_be_bytes).concat();
+    run(
+        &mut tpm,
+        TPM_CC_DICTIONARY_ATTACK_PARAMETERS,
+        &[TPM_RH_LOCKOUT],
+        Some(&[&[]]),
+        &params,
+    )
+    .unwrap();
+    for _ in 0..3 {
+        assert_eq!(unseal(&mut tpm, sealed, b"wrong"), Err(TPM_RC_AUTH_FAIL_S1));
+    }
+    assert!(unseal(&mut tpm, sealed, b"secret").is_ok());
+
+    // With a lockoutRecovery of 0, only a TPM Reset brings lockoutAuth back.
+    assert_eq!(lock_reset(&mut tpm, b"wrong"), Err(TPM_RC_AUTH_FAIL_S1));
+    advance_time(RECOVERY_TIME_MS);
+    assert_eq!(lock_reset(&mut tpm, &[]), Err(TPM_RC_LOCKOUT));
+    shutdown(&mut tpm, TPM_SU_CLEAR).unwrap();
+    let mut tpm = power_on();
+    lock_reset(&mut tpm, &[]).unwrap();
+}
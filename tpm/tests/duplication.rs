@@ -0,0 +1,133 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+const TPM_CC_DUPLICATE: u32 = 0x14B;
+const TPM_CC_IMPORT: u32 = 0x156;
+const TPM_CC_POLICY_DUPLICATION_SELECT: u32 = 0x188;
+
+const TPM_RC_AUTH_UNAVAILABLE: u32 = 0x12F;
+const TPM_RC_POLICY_FAIL_S1: u32 = 0x99D;
+
+// A signing key that can be duplicated only with a policy session, as the
+// DUP role always needs one
+fn duplicable_template(auth_policy: &[u8]) -> Vec<u8> {
+    let mut template = ecc_signing_template();
+    // sensitiveDataOrigin | userWithAuth | sign
+    template[6..10].copy_from_slice(&0x00040060u32.to_be_bytes());
+    template.splice(10..12, tpm2b(auth_policy));
+    let size = (template.len() - 2) as u16;
+    template[..2].copy_from_slice(&size.to_be_bytes());
+    template
+}
+
+// includeObject NO, so the policy can go in the object it selects.
+// objectName is still needed for nameHash when the policy is used, but
+// isn't known to a trial session before the object exists.
+fn duplication_select_params(object_name: &[u8], new_parent_name: &[u8]) -> Vec<u8> {
+    [tpm2b(object_name), tpm2b(new_parent_name), vec![0]].concat()
+}
+
+// TPM2_Duplicate with no inner wrap. Returns the duplicate and outSymSeed.
+fn duplicate(
+    tpm: &mut TpmInstance,
+    object: u32,
+    new_parent: u32,
+    auth: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), u32> {
+    let params = [tpm2b(&[]), 0x0010u16.to_be_bytes().to_vec()].concat();
+    let response = run_sessions(
+        tpm,
+        TPM_CC_DUPLICATE,
+        &[object, new_parent],
+        Some(&[auth.to_vec()]),
+        &params,
+    )?;
+    let (_, params) = parameters(&response, false);
+    let mut reader = Reader::new(&params);
+    assert!(reader.tpm2b().is_empty());
+    let duplicate = tpm2b(reader.tpm2b());
+    let sym_seed = tpm2b(reader.tpm2b());
+    Ok((duplicate, sym_seed))
+}
+
+#[test]
+fn duplicate_to_selected_parent() {
+    let mut tpm = power_on();
+    let (primary, _) = create_primary(&mut tpm, &ecc_storage_template());
+    let (private, new_parent_public) = create(&mut tpm, primary, &ecc_storage_template(), &[], &[]);
+    let new_parent = load(&mut tpm, primary, &private, &new_parent_public);
+    let new_parent_name = object_name(&new_parent_public);
+
+    let trial = start_auth_session(&mut tpm, TPM_SE_TRIAL, TPM_RH_NULL, &[]);
+    let params = duplication_select_params(&[], &new_parent_name);
+    run(
+        &mut tpm,
+        TPM_CC_POLICY_DUPLICATION_SELECT,
+        &[trial.handle],
+        None,
+        &params,
+    )
+    .unwrap();
+    let policy = policy_get_digest(&mut tpm, trial.handle);
+    let expected = policy_extend(
+        &[0; 32],
+        TPM_CC_POLICY_DUPLICATION_SELECT,
+        &[&new_parent_name[..], &[0]].concat(),
+    );
+    assert_eq!(policy, expected);
+    flush(&mut tpm, trial.handle);
+
+    let template = duplicable_template(&policy);
+    let (private, public) = create(&mut tpm, primary, &template, &[], &[]);
+    let object = load(&mut tpm, primary, &private, &public);
+
+    // A password can't authorize the DUP role.
+    let password = auth_command(TPM_RS_PW, &[], 0, &[]);
+    let rc = duplicate(&mut tpm, object, new_parent, &password);
+    assert_eq!(rc, Err(TPM_RC_AUTH_UNAVAILABLE));
+
+    let params = duplication_select_params(&object_name(&public), &new_parent_name);
+    let policy_auth = |tpm: &mut TpmInstance| {
+        let session = start_auth_session(tpm, TPM_SE_POLICY, TPM_RH_NULL, &[]);
+        run(
+            tpm,
+            TPM_CC_POLICY_DUPLICATION_SELECT,
+            &[session.handle],
+            None,
+            &params,
+        )
+        .unwrap();
+        auth_command(session.handle, &session.nonce_caller, 0, &[])
+    };
+
+    // Only to the parent the policy selected
+    let auth = policy_auth(&mut tpm);
+    let rc = duplicate(&mut tpm, object, primary, &auth);
+    assert_eq!(rc, Err(TPM_RC_POLICY_FAIL_S1));
+
+    let auth = policy_auth(&mut tpm);
+    let (duplicate, sym_seed) = duplicate(&mut tpm, object, new_parent, &auth).unwrap();
+    flush(&mut tpm, object);
+
+    let params = [
+        tpm2b(&[]),
+        public.clone(),
+        duplicate,
+        sym_seed,
+        0x0010u16.to_be_bytes().to_vec(),
+    ]
+    .concat();
+    let response = run(
+        &mut tpm,
+        TPM_CC_IMPORT,
+        &[new_parent],
+        Some(&[&[]]),
+        &params,
+    )
+    .unwrap();
+    let (_, params) = parameters(&response, false);
+    let private = tpm2b(Reader::new(&params).tpm2b());
+    load(&mut tpm, new_parent, &private, &public);
+}
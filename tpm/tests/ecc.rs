@@ -0,0 +1,249 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+const TPM_CC_ECDH_ZGEN: u32 = 0x154;
+const TPM_CC_LOAD_EXTERNAL: u32 = 0x167;
+const TPM_CC_ECC_PARAMETERS: u32 = 0x178;
+
+const TPM_ALG_ECC: u16 = 0x0023;
+const TPM_ALG_NULL: u16 = 0x0010;
+const TPM_ALG_ECDSA: u16 = 0x0018;
+
+const TPM_ECC_NIST_P256: u16 = 0x0003;
+const TPM_ECC_NIST_P384: u16 = 0x0004;
+const TPM_ECC_NIST_P521: u16 = 0x0005;
+
+const TPM_CAP_ECC_CURVES: u32 = 8;
+const TPM_ST_HASHCHECK: u16 = 0x8024;
+
+const TPM_RC_SIGNATURE_P2: u32 = 0x2DB;
+const TPM_RC_CURVE_P1: u32 = 0x1E6;
+const TPM_RC_ECC_POINT_P1: u32 = 0x1E7;
+const TPM_RC_ECC_POINT_P2: u32 = 0x2E7;
+
+// TPMT_PUBLIC of an ECC key without the TPM2B around it
+fn ecc_public(curve: u16, attributes: u32, scheme: u16, unique: &[u8]) -> Vec<u8> {
+    let mut public = Vec::new();
+    public.extend(TPM_ALG_ECC.to_be_bytes());
+    public.extend(TPM_ALG_SHA256.to_be_bytes());
+    public.extend(attributes.to_be_bytes());
+    public.extend(tpm2b(&[]));
+    public.extend(TPM_ALG_NULL.to_be_bytes()); // symmetric
+    public.extend(scheme.to_be_bytes());
+    if scheme != TPM_ALG_NULL {
+        public.extend(TPM_ALG_SHA256.to_be_bytes());
+    }
+    public.extend(curve.to_be_bytes());
+    public.extend(TPM_ALG_NULL.to_be_bytes()); // KDF
+    public.extend_from_slice(unique);
+    public
+}
+
+// The x and y of the public point in an ECC key's TPMT_PUBLIC
+fn public_point(public: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut reader = Reader::new(public);
+    reader.bytes(2 + 2 + 4);
+    reader.tpm2b();
+    reader.bytes(2);
+    if reader.u16() != TPM_ALG_NULL {
+        reader.bytes(2);
+    }
+    reader.bytes(2 + 2);
+    (reader.tpm2b().to_vec(), reader.tpm2b().to_vec())
+}
+
+fn ecc_point(x: &[u8], y: &[u8]) -> Vec<u8> {
+    tpm2b(&[tpm2b(x), tpm2b(y)].concat())
+}
+
+// TPMT_SIGNATURE of `digest`, with the key's ECDSA SHA-256 scheme
+fn sign(tpm: &mut TpmInstance, key: u32, digest: &[u8]) -> Vec<u8> {
+    let params = [
+        tpm2b(digest),
+        TPM_ALG_NULL.to_be_bytes().to_vec(),
+        TPM_ST_HASHCHECK.to_be_bytes().to_vec(),
+        TPM_RH_NULL.to_be_bytes().to_vec(),
+        tpm2b(&[]),
+    ]
+    .concat();
+    let response = run(tpm, TPM_CC_SIGN, &[key], Some(&[&[]]), &params).unwrap();
+    parameters(&response, false).1
+}
+
+fn verify(tpm: &mut TpmInstance, key: u32, digest: &[u8], signature: &[u8]) -> Result<(), u32> {
+    let params = [tpm2b(digest), signature.to_vec()].concat();
+    run(tpm, TPM_CC_VERIFY_SIGNATURE, &[key], None, &params).map(|_| ())
+}
+
+// Keys on the longer curves have coordinates as long as the field, and
+// sign and verify like P-256 ones.
+#[test]
+fn p384_p521_keys() {
+    let mut tpm = power_on();
+    for (curve, size) in [(TPM_ECC_NIST_P384, 48), (TPM_ECC_NIST_P521, 66)] {
+        // fixedTPM | fixedParent | sensitiveDataOrigin | userWithAuth | sign
+        let template = ecc_public(
+            curve,
+            0x00040072,
+            TPM_ALG_ECDSA,
+            &[tpm2b(&[]), tpm2b(&[])].concat(),
+        );
+        let (key, public) = create_primary(&mut tpm, &tpm2b(&template));
+        let (x, y) = public_point(&public);
+        assert_eq!((x.len(), y.len()), (size, size));
+
+        let digest = sha256(&[b"message"]);
+        let mut signature = sign(&mut tpm, key, &digest);
+        verify(&mut tpm, key, &digest, &signature).unwrap();
+
+        let last = signature.len() - 1;
+        signature[last] ^= 1;
+        let rc = verify(&mut tpm, key, &digest, &signature);
+        assert_eq!(rc, Err(TPM_RC_SIGNATURE_P2));
+        flush(&mut tpm, key);
+    }
+}
+
+// A point that isn't on the key's curve is rejected, whether it's an input
+// to a key exchange or a public key being loaded.
+#[test]
+fn off_curve_point() {
+    let mut tpm = power_on();
+    // fixedTPM | fixedParent | sensitiveDataOrigin | userWithAuth | decrypt
+    let template = ecc_public(
+        TPM_ECC_NIST_P256,
+        0x00020072,
+        TPM_ALG_NULL,
+        &[tpm2b(&[]), tpm2b(&[])].concat(),
+    );
+    let (key, public) = create_primary(&mut tpm, &tpm2b(&template));
+
+    let (x, y) = public_point(&public);
+    run(
+        &mut tpm,
+        TPM_CC_ECDH_ZGEN,
+        &[key],
+        Some(&[&[]]),
+        &ecc_point(&x, &y),
+    )
+    .unwrap();
+    let mut bad_y = y.clone();
+    bad_y[31] ^= 1;
+    let rc = run(
+        &mut tpm,
+        TPM_CC_ECDH_ZGEN,
+        &[key],
+        Some(&[&[]]),
+        &ecc_point(&x, &bad_y),
+    );
+    assert_eq!(rc, Err(TPM_RC_ECC_POINT_P1));
+
+    // userWithAuth | sign
+    let unique = [tpm2b(&x), tpm2b(&bad_y)].concat();
+    let public = ecc_public(TPM_ECC_NIST_P256, 0x00040040, TPM_ALG_ECDSA, &unique);
+    let params = [
+        tpm2b(&[]),
+        tpm2b(&public),
+        TPM_RH_NULL.to_be_bytes().to_vec(),
+    ]
+    .concat();
+    let rc = run(&mut tpm, TPM_CC_LOAD_EXTERNAL, &[], None, &params);
+    assert_eq!(rc, Err(TPM_RC_ECC_POINT_P2));
+}
+
+// ECC_Parameters gives the curve's parameters as SEC 2 has them.
+#[test]
+fn ecc_parameters() {
+    let mut tpm = power_on();
+    let response = run(
+        &mut tpm,
+        TPM_CC_ECC_PARAMETERS,
+        &[],
+        None,
+        &TPM_ECC_NIST_P256.to_be_bytes(),
+    )
+    .unwrap();
+
+    let mut reader = Reader::new(&response);
+    assert_eq!(reader.u16(), TPM_ECC_NIST_P256);
+    assert_eq!(reader.u16(), 256);
+    assert_eq!(reader.u16(), TPM_ALG_NULL); // KDF
+    assert_eq!(reader.u16(), TPM_ALG_NULL); // signing scheme
+    let expected = [
+        "ffffffff00000001000000000000000000000000ffffffffffffffffffffffff",
+        "ffffffff00000001000000000000000000000000fffffffffffffffffffffffc",
+        "5ac635d8aa3a93e7b3ebbd55769886bc651d06b0cc53b0f63bce3c3e27d2604b",
+        "6b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c296",
+        "4fe342e2fe1a7f9b8ee7eb4a7c0f9e162bce33576b315ececbb6406837bf51f5",
+        "ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551",
+        "01",
+    ];
+    for hex in expected {
+        let value: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        assert_eq!(reader.tpm2b(), value);
+    }
+
+    let rc = run(
+        &mut tpm,
+        TPM_CC_ECC_PARAMETERS,
+        &[],
+        None,
+        &0x0099u16.to_be_bytes(),
+    );
+    assert_eq!(rc, Err(TPM_RC_CURVE_P1));
+}
+
+// moreData and the curves from `start` on, at most `count` of them
+fn ecc_curves(tpm: &mut TpmInstance, start: u32, count: u32) -> (bool, Vec<u16>) {
+    let params = [TPM_CAP_ECC_CURVES, start, count]
+        .map(u32::to_be_bytes)
+        .concat();
+    let response = run(tpm, TPM_CC_GET_CAPABILITY, &[], None, &params).unwrap();
+    let mut reader = Reader::new(&response);
+    let more_data = reader.bytes(1)[0] != 0;
+    assert_eq!(reader.u32(), TPM_CAP_ECC_CURVES);
+    let n = reader.u32();
+    (more_data, (0..n).map(|_| reader.u16()).collect())
+}
+
+#[test]
+fn ecc_curves_paging() {
+    let mut tpm = power_on();
+    let all = [
+        TPM_ECC_NIST_P256,
+        TPM_ECC_NIST_P384,
+        TPM_ECC_NIST_P521,
+        #[cfg(feature = "bn-p256")]
+        0x0010,
+        #[cfg(feature = "sm2-p256")]
+        0x0020,
+    ];
+
+    assert_eq!(ecc_curves(&mut tpm, 0, 100), (false, all.to_vec()));
+
+    // Two at a time, each page starting after the last curve of the one
+    // before
+    let mut listed: Vec<u16> = Vec::new();
+    let mut start = 0;
+    loop {
+        let (more_data, curves) = ecc_curves(&mut tpm, start, 2);
+        assert!(curves.len() <= 2);
+        listed.extend(&curves);
+        if !more_data {
+            break;
+        }
+        start = *curves.last().unwrap() as u32 + 1;
+    }
+    assert_eq!(listed, all);
+
+    assert_eq!(
+        ecc_curves(&mut tpm, TPM_ECC_NIST_P384 as u32, 1),
+        (true, vec![TPM_ECC_NIST_P384])
+    );
+    assert_eq!(ecc_curves(&mut tpm, 0x100, 2), (false, vec![]));
+}
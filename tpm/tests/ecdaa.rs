@@ -0,0 +1,155 @@
+#![cfg(feature = "bn-p256")]
+
+mod common;
+
+use common::*;
+use crypto_bigint::modular::runtime_mod::{DynResidue, DynResidueParams};
+use crypto_bigint::U256;
+
+const TPM_CC_COMMIT: u32 = 0x18B;
+
+const TPM_ALG_ECC: u16 = 0x0023;
+const TPM_ALG_NULL: u16 = 0x0010;
+const TPM_ALG_ECDAA: u16 = 0x001A;
+const TPM_ECC_BN_P256: u16 = 0x0010;
+
+const TPM_ST_HASHCHECK: u16 = 0x8024;
+
+// TPM_ECC_BN_P256, where a = 0
+const P: &str = "FFFFFFFFFFFCF0CD46E5F25EEE71A49F0CDC65FB12980A82D3292DDBAED33013";
+const N: &str = "FFFFFFFFFFFCF0CD46E5F25EEE71A49E0CDC65FB1299921AF62D536CD10B500D";
+
+type Point = Option<(DynResidue<{ U256::LIMBS }>, DynResidue<{ U256::LIMBS }>)>;
+
+fn field(x: &U256) -> DynResidue<{ U256::LIMBS }> {
+    DynResidue::new(x, DynResidueParams::new(&U256::from_be_hex(P)))
+}
+
+// A big-endian number of up to 32 bytes
+fn uint(bytes: &[u8]) -> U256 {
+    let mut padded = [0u8; 32];
+    padded[32 - bytes.len()..].copy_from_slice(bytes);
+    U256::from_be_slice(&padded)
+}
+
+fn point(x: &[u8], y: &[u8]) -> Point {
+    Some((field(&uint(x)), field(&uint(y))))
+}
+
+fn affine(pt: &Point) -> Option<(U256, U256)> {
+    pt.map(|(x, y)| (x.retrieve(), y.retrieve()))
+}
+
+// Affine point addition, slow but enough to check a signature
+fn add(p1: &Point, p2: &Point) -> Point {
+    let (Some((x1, y1)), Some((x2, y2))) = (p1, p2) else {
+        return p1.or(*p2);
+    };
+    let lambda = if x1.retrieve() == x2.retrieve() {
+        if y1.retrieve() != y2.retrieve() || y1.retrieve() == U256::ZERO {
+            return None;
+        }
+        let xx = x1.square();
+        (xx + xx + xx) * (*y1 + *y1).invert().0
+    } else {
+        (*y2 - *y1) * (*x2 - *x1).invert().0
+    };
+    let x3 = lambda.square() - *x1 - *x2;
+    let y3 = lambda * (*x1 - x3) - *y1;
+    Some((x3, y3))
+}
+
+fn mul(k: &U256, pt: &Point) -> Point {
+    let mut result = None;
+    for i in (0..256).rev() {
+        result = add(&result, &result);
+        if k.bit_vartime(i) {
+            result = add(&result, pt);
+        }
+    }
+    result
+}
+
+// A TPM2B_ECC_POINT, as the TPM returns it
+fn read_point(reader: &mut Reader) -> Point {
+    let mut point_reader = Reader::new(reader.tpm2b());
+    let x = point_reader.tpm2b().to_vec();
+    let y = point_reader.tpm2b().to_vec();
+    point(&x, &y)
+}
+
+fn ecdaa_template() -> Vec<u8> {
+    let mut public = Vec::new();
+    public.extend(TPM_ALG_ECC.to_be_bytes());
+    public.extend(TPM_ALG_SHA256.to_be_bytes());
+    // fixedTPM | fixedParent | sensitiveDataOrigin | userWithAuth | sign
+    public.extend(0x00040072u32.to_be_bytes());
+    public.extend(tpm2b(&[]));
+    public.extend(TPM_ALG_NULL.to_be_bytes());
+    public.extend(TPM_ALG_ECDAA.to_be_bytes());
+    public.extend(TPM_ALG_SHA256.to_be_bytes());
+    public.extend(0u16.to_be_bytes());
+    public.extend(TPM_ECC_BN_P256.to_be_bytes());
+    public.extend(TPM_ALG_NULL.to_be_bytes());
+    public.extend(tpm2b(&[]));
+    public.extend(tpm2b(&[]));
+    tpm2b(&public)
+}
+
+// Commit to r with P1 = G, so E = r * G, then sign with that commit. The
+// signature must satisfy S * G = E + T * Q with T = H(R || digest) mod n.
+#[test]
+fn commit_sign_verify() {
+    let mut tpm = power_on();
+    let (key, public) = create_primary(&mut tpm, &ecdaa_template());
+    let mut reader = Reader::new(&public);
+    reader.bytes(2 + 2 + 4);
+    reader.tpm2b();
+    reader.bytes(2 + 2 + 2 + 2 + 2 + 2);
+    let qx = reader.tpm2b().to_vec();
+    let qy = reader.tpm2b().to_vec();
+    let q = point(&qx, &qy);
+
+    let g = tpm2b(&[tpm2b(&[1]), tpm2b(&[2])].concat());
+    let params = [g, tpm2b(&[]), tpm2b(&[])].concat();
+    let response = run(&mut tpm, TPM_CC_COMMIT, &[key], Some(&[&[]]), &params).unwrap();
+    let (_, params) = parameters(&response, false);
+    let mut reader = Reader::new(&params);
+    reader.tpm2b();
+    reader.tpm2b();
+    let e = read_point(&mut reader);
+    let counter = reader.u16();
+
+    let digest = sha256(&[b"message"]);
+    let sign = [
+        tpm2b(&digest),
+        [TPM_ALG_ECDAA, TPM_ALG_SHA256, counter]
+            .map(u16::to_be_bytes)
+            .concat(),
+        TPM_ST_HASHCHECK.to_be_bytes().to_vec(),
+        TPM_RH_NULL.to_be_bytes().to_vec(),
+        tpm2b(&[]),
+    ]
+    .concat();
+    let response = run(&mut tpm, TPM_CC_SIGN, &[key], Some(&[&[]]), &sign).unwrap();
+    let (_, params) = parameters(&response, false);
+    let mut reader = Reader::new(&params);
+    assert_eq!(reader.u16(), TPM_ALG_ECDAA);
+    assert_eq!(reader.u16(), TPM_ALG_SHA256);
+    let r = reader.tpm2b().to_vec();
+    let s = uint(reader.tpm2b());
+
+    // The hash is shorter than 2n, so one subtraction reduces it.
+    let n = U256::from_be_hex(N);
+    let t = uint(&sha256(&[&r, &digest]));
+    let t = if t >= n { t.wrapping_sub(&n) } else { t };
+
+    let generator = point(&[1], &[2]);
+    let expected = add(&e, &mul(&t, &q));
+    assert!(expected.is_some());
+    assert_eq!(affine(&mul(&s, &generator)), affine(&expected));
+
+    // The commit is used up.
+    let rc = run(&mut tpm, TPM_CC_SIGN, &[key], Some(&[&[]]), &sign);
+    assert!(rc.is_err());
+}
@@ -0,0 +1,227 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+const TPM_CC_ENCRYPT_DECRYPT: u32 = 0x164;
+const TPM_CC_LOAD_EXTERNAL: u32 = 0x167;
+const TPM_CC_ENCRYPT_DECRYPT2: u32 = 0x193;
+
+const TPM_ALG_AES: u16 = 0x0006;
+const TPM_ALG_NULL: u16 = 0x0010;
+const TPM_ALG_SYMCIPHER: u16 = 0x0025;
+const TPM_ALG_CTR: u16 = 0x0040;
+const TPM_ALG_OFB: u16 = 0x0041;
+const TPM_ALG_CBC: u16 = 0x0042;
+const TPM_ALG_CFB: u16 = 0x0043;
+const TPM_ALG_ECB: u16 = 0x0044;
+
+// SIZE at ivIn and inData, which are parameters 3 and 4 of EncryptDecrypt
+// and 4 and 1 of EncryptDecrypt2
+const TPM_RC_SIZE_P1: u32 = 0x1D5;
+const TPM_RC_SIZE_P3: u32 = 0x3D5;
+const TPM_RC_SIZE_P4: u32 = 0x4D5;
+
+// The AES-128 examples of NIST SP 800-38A, checked against an independent
+// implementation
+const KEY: &str = "2b7e151628aed2a6abf7158809cf4f3c";
+const PLAIN_TEXT: &str = "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51\
+                          30c81c46a35ce411e5fbc1191a0a52eff69f2445df4f9b17ad2b417be66c3710";
+const IV: &str = "000102030405060708090a0b0c0d0e0f";
+const CTR_IV: &str = "f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff";
+
+const VECTORS: [(u16, &str, &str); 5] = [
+    (
+        TPM_ALG_ECB,
+        "",
+        "3ad77bb40d7a3660a89ecaf32466ef97f5d3d58503b9699de785895a96fdbaaf\
+         43b1cd7f598ece23881b00e3ed0306887b0c785e27e8ad3f8223207104725dd4",
+    ),
+    (
+        TPM_ALG_CBC,
+        IV,
+        "7649abac8119b246cee98e9b12e9197d5086cb9b507219ee95db113a917678b2\
+         73bed6b8e3c1743b7116e69e222295163ff1caa1681fac09120eca307586e1a7",
+    ),
+    (
+        TPM_ALG_CFB,
+        IV,
+        "3b3fd92eb72dad20333449f8e83cfb4ac8a64537a0b3a93fcde3cdad9f1ce58b\
+         26751f67a3cbb140b1808cf187a4f4dfc04b05357c5d1c0eeac4c66f9ff7f2e6",
+    ),
+    (
+        TPM_ALG_OFB,
+        IV,
+        "3b3fd92eb72dad20333449f8e83cfb4a7789508d16918f03f53c52dac54ed825\
+         9740051e9c5fecf64344f7a82260edcc304c6528f659c77866a510d9c1d6ae5e",
+    ),
+    (
+        TPM_ALG_CTR,
+        CTR_IV,
+        "874d6191b620e3261bef6864990db6ce9806f66b7970fdff8617187bb9fffdff\
+         5ae4df3edbd5d35e5b4f09020db03eab1e031dda2fbe03d1792170a0f3009cee",
+    ),
+];
+
+fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+// Load KEY into the null hierarchy, with no mode of its own
+fn load_key(tpm: &mut TpmInstance) -> u32 {
+    let key = hex(KEY);
+    let seed = [0x5a; 32];
+
+    let mut sensitive = Vec::new();
+    sensitive.extend(TPM_ALG_SYMCIPHER.to_be_bytes());
+    sensitive.extend(tpm2b(&[]));
+    sensitive.extend(tpm2b(&seed));
+    sensitive.extend(tpm2b(&key));
+
+    let mut public = Vec::new();
+    public.extend(TPM_ALG_SYMCIPHER.to_be_bytes());
+    public.extend(TPM_ALG_SHA256.to_be_bytes());
+    // userWithAuth | decrypt | sign
+    public.extend(0x00060040u32.to_be_bytes());
+    public.extend(tpm2b(&[]));
+    public.extend(TPM_ALG_AES.to_be_bytes());
+    public.extend(128u16.to_be_bytes());
+    public.extend(TPM_ALG_NULL.to_be_bytes());
+    public.extend(tpm2b(&sha256(&[&seed, &key])));
+
+    let params = [
+        tpm2b(&sensitive),
+        tpm2b(&public),
+        TPM_RH_NULL.to_be_bytes().to_vec(),
+    ]
+    .concat();
+    let response = run(tpm, TPM_CC_LOAD_EXTERNAL, &[], None, &params).unwrap();
+    Reader::new(&response).u32()
+}
+
+// outData and ivOut, through EncryptDecrypt or EncryptDecrypt2
+fn encrypt_decrypt(
+    tpm: &mut TpmInstance,
+    cc: u32,
+    key: u32,
+    decrypt: bool,
+    mode: u16,
+    iv: &[u8],
+    data: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), u32> {
+    let params = match cc {
+        TPM_CC_ENCRYPT_DECRYPT => [
+            vec![decrypt as u8],
+            mode.to_be_bytes().to_vec(),
+            tpm2b(iv),
+            tpm2b(data),
+        ]
+        .concat(),
+        _ => [
+            tpm2b(data),
+            vec![decrypt as u8],
+            mode.to_be_bytes().to_vec(),
+            tpm2b(iv),
+        ]
+        .concat(),
+    };
+    let response = run(tpm, cc, &[key], Some(&[&[]]), &params)?;
+    let (_, params) = parameters(&response, false);
+    let mut reader = Reader::new(&params);
+    let out_data = reader.tpm2b().to_vec();
+    let iv_out = reader.tpm2b().to_vec();
+    Ok((out_data, iv_out))
+}
+
+#[test]
+fn known_answers() {
+    let mut tpm = power_on();
+    let key = load_key(&mut tpm);
+    let plain_text = hex(PLAIN_TEXT);
+
+    for cc in [TPM_CC_ENCRYPT_DECRYPT, TPM_CC_ENCRYPT_DECRYPT2] {
+        for (mode, iv, cipher_text) in VECTORS {
+            let (iv, cipher_text) = (hex(iv), hex(cipher_text));
+            let (out, _) =
+                encrypt_decrypt(&mut tpm, cc, key, false, mode, &iv, &plain_text).unwrap();
+            assert_eq!(out, cipher_text);
+            let (out, _) =
+                encrypt_decrypt(&mut tpm, cc, key, true, mode, &iv, &cipher_text).unwrap();
+            assert_eq!(out, plain_text);
+        }
+    }
+}
+
+// ivOut carries on from where the last call stopped, so a message split
+// across two calls comes out as it does in one.
+#[test]
+fn iv_chains() {
+    let mut tpm = power_on();
+    let key = load_key(&mut tpm);
+    let plain_text = hex(PLAIN_TEXT);
+    let (first, second) = plain_text.split_at(32);
+
+    for (mode, iv, cipher_text) in &VECTORS[1..] {
+        for decrypt in [false, true] {
+            let (input, expected) = match decrypt {
+                false => (plain_text.clone(), hex(cipher_text)),
+                true => (hex(cipher_text), plain_text.clone()),
+            };
+            let (first, second) = input.split_at(32);
+            let cc = TPM_CC_ENCRYPT_DECRYPT;
+            let (mut out, iv) =
+                encrypt_decrypt(&mut tpm, cc, key, decrypt, *mode, &hex(iv), first).unwrap();
+            let (rest, _) =
+                encrypt_decrypt(&mut tpm, cc, key, decrypt, *mode, &iv, second).unwrap();
+            out.extend(rest);
+            assert_eq!(out, expected);
+        }
+    }
+
+    // ECB has no IV to chain.
+    let cc = TPM_CC_ENCRYPT_DECRYPT;
+    let (_, iv) = encrypt_decrypt(&mut tpm, cc, key, false, TPM_ALG_ECB, &[], first).unwrap();
+    assert!(iv.is_empty());
+    encrypt_decrypt(&mut tpm, cc, key, false, TPM_ALG_ECB, &[], second).unwrap();
+}
+
+// The IV is a block, or empty for ECB, and CBC and ECB only take whole
+// blocks since there's no padding.
+#[test]
+fn size_checks() {
+    let mut tpm = power_on();
+    let key = load_key(&mut tpm);
+    let iv = hex(IV);
+    let block = &hex(PLAIN_TEXT)[..16];
+
+    for (cc, iv_rc, data_rc) in [
+        (TPM_CC_ENCRYPT_DECRYPT, TPM_RC_SIZE_P3, TPM_RC_SIZE_P4),
+        (TPM_CC_ENCRYPT_DECRYPT2, TPM_RC_SIZE_P4, TPM_RC_SIZE_P1),
+    ] {
+        for mode in [TPM_ALG_CBC, TPM_ALG_CFB, TPM_ALG_OFB, TPM_ALG_CTR] {
+            let rc = encrypt_decrypt(&mut tpm, cc, key, false, mode, &iv[..8], block);
+            assert_eq!(rc, Err(iv_rc));
+            let rc = encrypt_decrypt(&mut tpm, cc, key, false, mode, &[], block);
+            assert_eq!(rc, Err(iv_rc));
+        }
+        let rc = encrypt_decrypt(&mut tpm, cc, key, false, TPM_ALG_ECB, &iv, block);
+        assert_eq!(rc, Err(iv_rc));
+
+        for decrypt in [false, true] {
+            let rc = encrypt_decrypt(&mut tpm, cc, key, decrypt, TPM_ALG_CBC, &iv, &block[..15]);
+            assert_eq!(rc, Err(data_rc));
+            let rc = encrypt_decrypt(&mut tpm, cc, key, decrypt, TPM_ALG_ECB, &[], &block[..15]);
+            assert_eq!(rc, Err(data_rc));
+        }
+
+        // The stream modes take any length.
+        for mode in [TPM_ALG_CFB, TPM_ALG_OFB, TPM_ALG_CTR] {
+            let (out, _) =
+                encrypt_decrypt(&mut tpm, cc, key, false, mode, &iv, &block[..15]).unwrap();
+            assert_eq!(out.len(), 15);
+        }
+    }
+}
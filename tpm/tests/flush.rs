@@ -0,0 +1,94 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+const TPM_CC_MAC_START: u32 = 0x15B;
+
+const TPM_PT_HR_TRANSIENT_MIN: u32 = 0x10E;
+const TPM_PT_HR_LOADED: u32 = 0x203;
+const TPM_PT_HR_TRANSIENT_AVAIL: u32 = 0x207;
+
+const TPM_RC_VALUE_P1: u32 = 0x1C4;
+const TPM_RC_HANDLE_P1: u32 = 0x1CB;
+const TPM_RC_OBJECT_MEMORY: u32 = 0x902;
+
+fn try_load(tpm: &mut TpmInstance, parent: u32, private: &[u8], public: &[u8]) -> Result<u32, u32> {
+    let params = [private, public].concat();
+    let response = run(tpm, TPM_CC_LOAD, &[parent], Some(&[&[]]), &params)?;
+    Ok(parameters(&response, true).0.unwrap())
+}
+
+fn try_flush(tpm: &mut TpmInstance, handle: u32) -> Result<(), u32> {
+    run(tpm, TPM_CC_FLUSH_CONTEXT, &[], None, &handle.to_be_bytes()).map(|_| ())
+}
+
+// Once every slot is taken, nothing else loads until an object is flushed.
+#[test]
+fn object_memory() {
+    let mut tpm = power_on();
+    let slots = get_tpm_property(&mut tpm, TPM_PT_HR_TRANSIENT_MIN);
+    assert_eq!(get_tpm_property(&mut tpm, TPM_PT_HR_TRANSIENT_AVAIL), slots);
+
+    let (parent, _) = create_primary(&mut tpm, &ecc_storage_template());
+    let (private, public) = create(&mut tpm, parent, &ecc_signing_template(), &[], &[]);
+    let mut handles = vec![parent];
+    for _ in 1..slots {
+        handles.push(try_load(&mut tpm, parent, &private, &public).unwrap());
+    }
+    assert_eq!(get_tpm_property(&mut tpm, TPM_PT_HR_TRANSIENT_AVAIL), 0);
+
+    let rc = try_load(&mut tpm, parent, &private, &public);
+    assert_eq!(rc, Err(TPM_RC_OBJECT_MEMORY));
+    let params = [
+        sensitive_create(&[], &[]),
+        ecc_signing_template(),
+        tpm2b(&[]),
+        0u32.to_be_bytes().to_vec(),
+    ]
+    .concat();
+    let rc = run(
+        &mut tpm,
+        TPM_CC_CREATE_PRIMARY,
+        &[TPM_RH_OWNER],
+        Some(&[&[]]),
+        &params,
+    );
+    assert_eq!(rc.map(|_| ()), Err(TPM_RC_OBJECT_MEMORY));
+
+    // The freed slot takes the next object, whichever one was flushed.
+    let last = handles.pop().unwrap();
+    try_flush(&mut tpm, last).unwrap();
+    assert_eq!(get_tpm_property(&mut tpm, TPM_PT_HR_TRANSIENT_AVAIL), 1);
+    assert_eq!(try_load(&mut tpm, parent, &private, &public), Ok(last));
+    assert_eq!(get_tpm_property(&mut tpm, TPM_PT_HR_TRANSIENT_AVAIL), 0);
+}
+
+// A flushed handle is gone, so flushing it again fails, and a handle that's
+// neither transient nor a session can't be flushed at all.
+#[test]
+fn flush_objects_sequences_and_sessions() {
+    let mut tpm = power_on();
+    let slots = get_tpm_property(&mut tpm, TPM_PT_HR_TRANSIENT_MIN);
+
+    let (key, _) = create_primary(&mut tpm, &hmac_key_template());
+    let params = [tpm2b(&[]), TPM_ALG_SHA256.to_be_bytes().to_vec()].concat();
+    let response = run(&mut tpm, TPM_CC_MAC_START, &[key], Some(&[&[]]), &params).unwrap();
+    let sequence = parameters(&response, true).0.unwrap();
+    assert_eq!(
+        get_tpm_property(&mut tpm, TPM_PT_HR_TRANSIENT_AVAIL),
+        slots - 2
+    );
+
+    let session = start_auth_session(&mut tpm, TPM_SE_HMAC, TPM_RH_NULL, &[]);
+    assert_eq!(get_tpm_property(&mut tpm, TPM_PT_HR_LOADED), 1);
+
+    for handle in [sequence, key, session.handle] {
+        try_flush(&mut tpm, handle).unwrap();
+        assert_eq!(try_flush(&mut tpm, handle), Err(TPM_RC_HANDLE_P1));
+    }
+    assert_eq!(get_tpm_property(&mut tpm, TPM_PT_HR_TRANSIENT_AVAIL), slots);
+    assert_eq!(get_tpm_property(&mut tpm, TPM_PT_HR_LOADED), 0);
+
+    assert_eq!(try_flush(&mut tpm, TPM_RH_OWNER), Err(TPM_RC_VALUE_P1));
+}
@@ -0,0 +1,224 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+const TPM_RH_LOCKOUT: u32 = 0x4000000A;
+const TPM_RH_ENDORSEMENT: u32 = 0x4000000B;
+const TPM_RH_PLATFORM: u32 = 0x4000000C;
+
+const TPM_CC_EVICT_CONTROL: u32 = 0x120;
+const TPM_CC_HIERARCHY_CONTROL: u32 = 0x121;
+const TPM_CC_CHANGE_EPS: u32 = 0x124;
+const TPM_CC_CHANGE_PPS: u32 = 0x125;
+const TPM_CC_CLEAR: u32 = 0x126;
+const TPM_CC_READ_PUBLIC: u32 = 0x173;
+const TPM_CC_HASH: u32 = 0x17D;
+
+const TPM_RC_HIERARCHY_H1: u32 = 0x185;
+const TPM_RC_HANDLE_H1: u32 = 0x18B;
+const TPM_RC_HIERARCHY_P1: u32 = 0x1C5;
+const TPM_RC_INTEGRITY_P1: u32 = 0x1DF;
+const TPM_RC_TICKET_P3: u32 = 0x3E0;
+const TPM_RC_REFERENCE_H0: u32 = 0x910;
+
+const HIERARCHIES: [u32; 3] = [TPM_RH_OWNER, TPM_RH_ENDORSEMENT, TPM_RH_PLATFORM];
+
+// Where each hierarchy's key is persisted. Endorsement keys go in the
+// owner's range.
+const PERSISTENT: [u32; 3] = [0x81000001, 0x81000002, 0x81800001];
+
+fn primary(tpm: &mut TpmInstance, hierarchy: u32) -> Result<u32, u32> {
+    let params = [
+        sensitive_create(&[], &[]),
+        ecc_signing_template(),
+        tpm2b(&[]),
+        0u32.to_be_bytes().to_vec(),
+    ]
+    .concat();
+    let response = run(
+        tpm,
+        TPM_CC_CREATE_PRIMARY,
+        &[hierarchy],
+        Some(&[&[]]),
+        &params,
+    )?;
+    Ok(parameters(&response, true).0.unwrap())
+}
+
+fn evict_control(tpm: &mut TpmInstance, auth: u32, object: u32, persistent: u32) {
+    let params = persistent.to_be_bytes();
+    run(
+        tpm,
+        TPM_CC_EVICT_CONTROL,
+        &[auth, object],
+        Some(&[&[]]),
+        &params,
+    )
+    .unwrap();
+}
+
+fn read_public(tpm: &mut TpmInstance, handle: u32) -> Result<Vec<u8>, u32> {
+    run(tpm, TPM_CC_READ_PUBLIC, &[handle], None, &[])
+}
+
+fn hierarchy_control(tpm: &mut TpmInstance, auth: u32, enable: u32, state: bool) {
+    let params = [enable.to_be_bytes().to_vec(), vec![state as u8]].concat();
+    run(
+        tpm,
+        TPM_CC_HIERARCHY_CONTROL,
+        &[auth],
+        Some(&[&[]]),
+        &params,
+    )
+    .unwrap();
+}
+
+// The digest of `data` and the hashcheck ticket `hierarchy` gives it
+fn hash_ticket(tpm: &mut TpmInstance, hierarchy: u32) -> (Vec<u8>, Vec<u8>) {
+    let params = [
+        tpm2b(b"data"),
+        TPM_ALG_SHA256.to_be_bytes().to_vec(),
+        hierarchy.to_be_bytes().to_vec(),
+    ]
+    .concat();
+    let response = run(tpm, TPM_CC_HASH, &[], None, &params).unwrap();
+    let mut reader = Reader::new(&response);
+    let digest = reader.tpm2b().to_vec();
+    let ticket = reader.bytes(2 + 4 + 2 + 32).to_vec();
+    (digest, ticket)
+}
+
+fn sign_with_ticket(
+    tpm: &mut TpmInstance,
+    key: u32,
+    digest: &[u8],
+    ticket: &[u8],
+) -> Result<Vec<u8>, u32> {
+    // Scheme NULL, so the key's ECDSA
+    let params = [
+        tpm2b(digest),
+        0x0010u16.to_be_bytes().to_vec(),
+        ticket.to_vec(),
+    ]
+    .concat();
+    run(tpm, TPM_CC_SIGN, &[key], Some(&[&[]]), &params)
+}
+
+// Give each hierarchy a loaded key, a persistent key, a saved context and a
+// ticket, run `cc`, then check that exactly the `reset` hierarchies lost
+// theirs.
+fn check_reset(cc: u32, auth: u32, reset: [bool; 3]) {
+    let mut tpm = power_on();
+
+    let mut keys = [0; 3];
+    let mut contexts = Vec::new();
+    let mut tickets = Vec::new();
+    for (i, hierarchy) in HIERARCHIES.into_iter().enumerate() {
+        keys[i] = primary(&mut tpm, hierarchy).unwrap();
+        let persist_auth = match hierarchy {
+            TPM_RH_PLATFORM => TPM_RH_PLATFORM,
+            _ => TPM_RH_OWNER,
+        };
+        evict_control(&mut tpm, persist_auth, keys[i], PERSISTENT[i]);
+        contexts.push(context_save(&mut tpm, keys[i]).unwrap());
+        tickets.push(hash_ticket(&mut tpm, hierarchy));
+    }
+
+    run(&mut tpm, cc, &[auth], Some(&[&[]]), &[]).unwrap();
+
+    for i in 0..3 {
+        match reset[i] {
+            true => {
+                assert_eq!(context_save(&mut tpm, keys[i]), Err(TPM_RC_REFERENCE_H0));
+                assert_eq!(read_public(&mut tpm, PERSISTENT[i]), Err(TPM_RC_HANDLE_H1));
+                assert_eq!(
+                    context_load(&mut tpm, &contexts[i]),
+                    Err(TPM_RC_INTEGRITY_P1)
+                );
+            }
+            false => {
+                context_save(&mut tpm, keys[i]).unwrap();
+                read_public(&mut tpm, PERSISTENT[i]).unwrap();
+            }
+        }
+    }
+
+    // Make room for the signer, then check the tickets with it. Contexts of
+    // the surviving keys load into the freed slots.
+    for i in 0..3 {
+        if !reset[i] {
+            flush(&mut tpm, keys[i]);
+            let handle = context_load(&mut tpm, &contexts[i]).unwrap();
+            flush(&mut tpm, handle);
+        }
+    }
+    let signer = primary(&mut tpm, TPM_RH_NULL).unwrap();
+    for (i, (digest, ticket)) in tickets.iter().enumerate() {
+        let rc = sign_with_ticket(&mut tpm, signer, digest, ticket);
+        match reset[i] {
+            true => assert_eq!(rc, Err(TPM_RC_TICKET_P3)),
+            false => assert!(rc.is_ok()),
+        }
+    }
+}
+
+#[test]
+fn clear() {
+    check_reset(TPM_CC_CLEAR, TPM_RH_LOCKOUT, [true, true, false]);
+}
+
+#[test]
+fn change_eps() {
+    check_reset(TPM_CC_CHANGE_EPS, TPM_RH_PLATFORM, [false, true, false]);
+}
+
+#[test]
+fn change_pps() {
+    check_reset(TPM_CC_CHANGE_PPS, TPM_RH_PLATFORM, [false, false, true]);
+}
+
+// A disabled hierarchy's handle, persistent keys and saved contexts can't
+// be used until it's turned back on, and its loaded keys are flushed.
+#[test]
+fn disabled_hierarchy() {
+    let mut tpm = power_on();
+
+    for (i, hierarchy) in HIERARCHIES.into_iter().enumerate() {
+        let key = primary(&mut tpm, hierarchy).unwrap();
+        let persist_auth = match hierarchy {
+            TPM_RH_PLATFORM => TPM_RH_PLATFORM,
+            _ => TPM_RH_OWNER,
+        };
+        evict_control(&mut tpm, persist_auth, key, PERSISTENT[i]);
+        let context = context_save(&mut tpm, key).unwrap();
+
+        // The owner and endorsement hierarchies can turn themselves off.
+        hierarchy_control(&mut tpm, hierarchy, hierarchy, false);
+
+        assert_eq!(primary(&mut tpm, hierarchy), Err(TPM_RC_HIERARCHY_H1));
+        assert_eq!(context_save(&mut tpm, key), Err(TPM_RC_REFERENCE_H0));
+        assert_eq!(
+            read_public(&mut tpm, PERSISTENT[i]),
+            Err(TPM_RC_HIERARCHY_H1)
+        );
+        assert_eq!(context_load(&mut tpm, &context), Err(TPM_RC_HIERARCHY_P1));
+
+        // Only a Startup(CLEAR) turns the platform hierarchy back on.
+        match hierarchy {
+            TPM_RH_PLATFORM => {
+                shutdown(&mut tpm, TPM_SU_CLEAR).unwrap();
+                tpm = power_on();
+            }
+            _ => hierarchy_control(&mut tpm, TPM_RH_PLATFORM, hierarchy, true),
+        }
+
+        let key = primary(&mut tpm, hierarchy).unwrap();
+        flush(&mut tpm, key);
+        read_public(&mut tpm, PERSISTENT[i]).unwrap();
+        if hierarchy != TPM_RH_PLATFORM {
+            let handle = context_load(&mut tpm, &context).unwrap();
+            flush(&mut tpm, handle);
+        }
+    }
+}
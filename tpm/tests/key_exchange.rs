@@ -0,0 +1,197 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+const TPM_CC_ECDH_ZGEN: u32 = 0x154;
+const TPM_CC_ECDH_KEYGEN: u32 = 0x163;
+const TPM_CC_COMMIT: u32 = 0x18B;
+const TPM_CC_ZGEN_2PHASE: u32 = 0x18D;
+const TPM_CC_EC_EPHEMERAL: u32 = 0x18E;
+
+const TPM_ALG_NULL: u16 = 0x0010;
+const TPM_ALG_ECDH: u16 = 0x0019;
+const TPM_ALG_ECDAA: u16 = 0x001A;
+const TPM_ALG_SM2: u16 = 0x001B;
+const TPM_ALG_ECMQV: u16 = 0x001D;
+const TPM_ECC_NIST_P256: u16 = 0x0003;
+
+const TPM_RC_VALUE_P4: u32 = 0x4C4;
+
+// TPM2B_PUBLIC for an ECC NIST P-256 key with the given attributes and
+// scheme. `unique` tells primary keys from the same template apart.
+fn ecc_template(attributes: u32, scheme: &[u8], unique: &[u8]) -> Vec<u8> {
+    let mut public = Vec::new();
+    public.extend(0x0023u16.to_be_bytes()); // TPM_ALG_ECC
+    public.extend(0x000Bu16.to_be_bytes()); // TPM_ALG_SHA256
+    public.extend(attributes.to_be_bytes());
+    public.extend(tpm2b(&[]));
+    public.extend(TPM_ALG_NULL.to_be_bytes()); // symmetric
+    public.extend_from_slice(scheme);
+    public.extend(TPM_ECC_NIST_P256.to_be_bytes());
+    public.extend(TPM_ALG_NULL.to_be_bytes()); // KDF
+    public.extend(tpm2b(unique));
+    public.extend(tpm2b(&[]));
+    tpm2b(&public)
+}
+
+// A key exchange key with no scheme of its own, so it can be used with any
+fn exchange_key(tpm: &mut TpmInstance, unique: &[u8]) -> (u32, Vec<u8>) {
+    // fixedTPM | fixedParent | sensitiveDataOrigin | userWithAuth | decrypt
+    let template = ecc_template(0x00020072, &TPM_ALG_NULL.to_be_bytes(), unique);
+    let (handle, public) = create_primary(tpm, &template);
+    (handle, public_point(&public))
+}
+
+// The TPM2B_ECC_POINT in the unique field of an ECC key's TPMT_PUBLIC
+fn public_point(public: &[u8]) -> Vec<u8> {
+    let mut reader = Reader::new(public);
+    reader.bytes(2 + 2 + 4);
+    reader.tpm2b();
+    reader.bytes(2);
+    if reader.u16() != TPM_ALG_NULL {
+        reader.bytes(2);
+    }
+    reader.bytes(2 + 2);
+    let x = reader.tpm2b();
+    let y = reader.tpm2b();
+    tpm2b(&[tpm2b(x), tpm2b(y)].concat())
+}
+
+// The point and counter of a new ephemeral key
+fn ec_ephemeral(tpm: &mut TpmInstance) -> (Vec<u8>, u16) {
+    let response = run(
+        tpm,
+        TPM_CC_EC_EPHEMERAL,
+        &[],
+        None,
+        &TPM_ECC_NIST_P256.to_be_bytes(),
+    )
+    .unwrap();
+    let mut reader = Reader::new(&response);
+    let q = tpm2b(reader.tpm2b());
+    (q, reader.u16())
+}
+
+// outZ1 and outZ2 of ZGen_2Phase
+fn zgen_2phase(
+    tpm: &mut TpmInstance,
+    key: u32,
+    qs_b: &[u8],
+    qe_b: &[u8],
+    scheme: u16,
+    counter: u16,
+) -> Result<(Vec<u8>, Vec<u8>), u32> {
+    let params = [
+        qs_b.to_vec(),
+        qe_b.to_vec(),
+        scheme.to_be_bytes().to_vec(),
+        counter.to_be_bytes().to_vec(),
+    ]
+    .concat();
+    let response = run(tpm, TPM_CC_ZGEN_2PHASE, &[key], Some(&[&[]]), &params)?;
+    let (_, params) = parameters(&response, false);
+    let mut reader = Reader::new(&params);
+    let z1 = reader.tpm2b().to_vec();
+    let z2 = reader.tpm2b().to_vec();
+    Ok((z1, z2))
+}
+
+// ECDH_KeyGen makes a point that only the key's holder can make again with
+// ECDH_ZGen.
+#[test]
+fn ecdh_one_pass() {
+    let mut tpm = power_on();
+    let (key, _) = exchange_key(&mut tpm, b"A");
+
+    let response = run(&mut tpm, TPM_CC_ECDH_KEYGEN, &[key], None, &[]).unwrap();
+    let mut reader = Reader::new(&response);
+    let z_point = reader.tpm2b().to_vec();
+    let pub_point = tpm2b(reader.tpm2b());
+
+    let response = run(&mut tpm, TPM_CC_ECDH_ZGEN, &[key], Some(&[&[]]), &pub_point).unwrap();
+    let (_, params) = parameters(&response, false);
+    assert_eq!(Reader::new(&params).tpm2b(), z_point);
+}
+
+// Two parties on the same TPM, each with a static key and an ephemeral
+// one, reach the same secret with every scheme. ECDH gives a second point
+// from the ephemeral keys, the others give one.
+#[test]
+fn two_phase_exchanges() {
+    let mut tpm = power_on();
+    let (key_a, qs_a) = exchange_key(&mut tpm, b"A");
+    let (key_b, qs_b) = exchange_key(&mut tpm, b"B");
+
+    for scheme in [TPM_ALG_ECDH, TPM_ALG_ECMQV, TPM_ALG_SM2] {
+        let (qe_a, counter_a) = ec_ephemeral(&mut tpm);
+        let (qe_b, counter_b) = ec_ephemeral(&mut tpm);
+
+        let a = zgen_2phase(&mut tpm, key_a, &qs_b, &qe_b, scheme, counter_a).unwrap();
+        let b = zgen_2phase(&mut tpm, key_b, &qs_a, &qe_a, scheme, counter_b).unwrap();
+        assert_eq!(a, b);
+        assert!(a.0.len() > 2);
+        assert_eq!(a.1.is_empty(), scheme != TPM_ALG_ECDH);
+    }
+}
+
+// An ephemeral key can only be used once, and only a counter EC_Ephemeral
+// gave out names one.
+#[test]
+fn ephemeral_counter() {
+    let mut tpm = power_on();
+    let (key_a, _) = exchange_key(&mut tpm, b"A");
+    let (_, qs_b) = exchange_key(&mut tpm, b"B");
+    let (qe_b, _) = ec_ephemeral(&mut tpm);
+
+    let (_, counter) = ec_ephemeral(&mut tpm);
+    zgen_2phase(&mut tpm, key_a, &qs_b, &qe_b, TPM_ALG_ECDH, counter).unwrap();
+    let rc = zgen_2phase(&mut tpm, key_a, &qs_b, &qe_b, TPM_ALG_ECDH, counter);
+    assert_eq!(rc, Err(TPM_RC_VALUE_P4));
+
+    let rc = zgen_2phase(&mut tpm, key_a, &qs_b, &qe_b, TPM_ALG_ECDH, counter + 1);
+    assert_eq!(rc, Err(TPM_RC_VALUE_P4));
+}
+
+// A counter from TPM2_Commit names a key's commit, not an ephemeral key.
+// ZGen_2Phase can't use it, and it's still there for the key to sign with.
+#[test]
+fn commit_counter_is_not_ephemeral() {
+    let mut tpm = power_on();
+    let (key_a, _) = exchange_key(&mut tpm, b"A");
+    let (_, qs_b) = exchange_key(&mut tpm, b"B");
+    let (qe_b, _) = ec_ephemeral(&mut tpm);
+
+    // fixedTPM | fixedParent | sensitiveDataOrigin | userWithAuth | sign
+    let scheme = [TPM_ALG_ECDAA, TPM_ALG_SHA256, 0].map(u16::to_be_bytes);
+    let template = ecc_template(0x00040072, &scheme.concat(), &[]);
+    let (signer, _) = create_primary(&mut tpm, &template);
+    let params = [tpm2b(&[]), tpm2b(&[]), tpm2b(&[])].concat();
+    let response = run(&mut tpm, TPM_CC_COMMIT, &[signer], Some(&[&[]]), &params).unwrap();
+    let (_, params) = parameters(&response, false);
+    let mut reader = Reader::new(&params);
+    reader.tpm2b();
+    reader.tpm2b();
+    reader.tpm2b();
+    let counter = reader.u16();
+
+    let rc = zgen_2phase(&mut tpm, key_a, &qs_b, &qe_b, TPM_ALG_ECDH, counter);
+    assert_eq!(rc, Err(TPM_RC_VALUE_P4));
+
+    let digest = sha256(&[b"message"]);
+    let params = [
+        tpm2b(&digest),
+        [TPM_ALG_ECDAA, TPM_ALG_SHA256, counter]
+            .map(u16::to_be_bytes)
+            .concat(),
+        // A NULL TPMT_TK_HASHCHECK
+        [
+            0x8024u16.to_be_bytes().to_vec(),
+            TPM_RH_NULL.to_be_bytes().to_vec(),
+            tpm2b(&[]),
+        ]
+        .concat(),
+    ]
+    .concat();
+    run(&mut tpm, TPM_CC_SIGN, &[signer], Some(&[&[]]), &params).unwrap();
+}
@@ -0,0 +1,240 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+const TPM_CC_SEQUENCE_COMPLETE: u32 = 0x13E;
+const TPM_CC_MAC: u32 = 0x155;
+const TPM_CC_MAC_START: u32 = 0x15B;
+const TPM_CC_SEQUENCE_UPDATE: u32 = 0x15C;
+const TPM_CC_LOAD_EXTERNAL: u32 = 0x167;
+
+const TPM_ALG_HMAC: u16 = 0x0005;
+const TPM_ALG_AES: u16 = 0x0006;
+const TPM_ALG_KEYEDHASH: u16 = 0x0008;
+const TPM_ALG_SHA384: u16 = 0x000C;
+const TPM_ALG_NULL: u16 = 0x0010;
+const TPM_ALG_SYMCIPHER: u16 = 0x0025;
+const TPM_ALG_CMAC: u16 = 0x003F;
+
+// userWithAuth | sign, and userWithAuth | decrypt
+const SIGN: u32 = 0x00040040;
+const DECRYPT: u32 = 0x00020040;
+
+const TPM_RC_ATTRIBUTES_H1: u32 = 0x182;
+const TPM_RC_TYPE_H1: u32 = 0x18A;
+const TPM_RC_KEY_H1: u32 = 0x19C;
+const TPM_RC_VALUE_P2: u32 = 0x2C4;
+const TPM_RC_SCHEME_P2: u32 = 0x2D2;
+
+// RFC 4231 test case 2
+const HMAC_KEY: &[u8] = b"Jefe";
+const HMAC_DATA: &[u8] = b"what do ya want for nothing?";
+const HMAC_SHA256: &str = "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+const HMAC_SHA384: &str = "af45d2e376484031617f78d2b58a6b1b9c7ef464f5a01b47e42ec3736322445e\
+                           8e2240ca5e69e2c78b3239ecfab21649";
+
+// The AES-128 examples of NIST SP 800-38B
+const CMAC_KEY: &str = "2b7e151628aed2a6abf7158809cf4f3c";
+const CMAC_VECTORS: [(&str, &str); 3] = [
+    ("", "bb1d6929e95937287fa37d129b756746"),
+    (
+        "6bc1bee22e409f96e93d7e117393172a",
+        "070a16b46b4d4144f79bdd9dd04a287c",
+    ),
+    (
+        "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e5130c81c46a35ce411",
+        "dfa66747de9ae63030ca32611497c827",
+    ),
+];
+
+fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+// Load `key` into the null hierarchy. `parameters` is the TPMU_PUBLIC_PARMS
+// of a keyedHash or symmetric cipher object.
+fn load_key(
+    tpm: &mut TpmInstance,
+    alg: u16,
+    attributes: u32,
+    parameters: &[u8],
+    key: &[u8],
+) -> u32 {
+    let seed = [0x5a; 32];
+
+    let mut sensitive = Vec::new();
+    sensitive.extend(alg.to_be_bytes());
+    sensitive.extend(tpm2b(&[]));
+    sensitive.extend(tpm2b(&seed));
+    sensitive.extend(tpm2b(key));
+
+    let mut public = Vec::new();
+    public.extend(alg.to_be_bytes());
+    public.extend(TPM_ALG_SHA256.to_be_bytes());
+    public.extend(attributes.to_be_bytes());
+    public.extend(tpm2b(&[]));
+    public.extend_from_slice(parameters);
+    public.extend(tpm2b(&sha256(&[&seed, key])));
+
+    let params = [
+        tpm2b(&sensitive),
+        tpm2b(&public),
+        TPM_RH_NULL.to_be_bytes().to_vec(),
+    ]
+    .concat();
+    let response = run(tpm, TPM_CC_LOAD_EXTERNAL, &[], None, &params).unwrap();
+    Reader::new(&response).u32()
+}
+
+// An HMAC key, with `hash` as its scheme or no scheme for TPM_ALG_NULL
+fn hmac_key(tpm: &mut TpmInstance, hash: u16) -> u32 {
+    let scheme = match hash {
+        TPM_ALG_NULL => TPM_ALG_NULL.to_be_bytes().to_vec(),
+        _ => [TPM_ALG_HMAC, hash].map(u16::to_be_bytes).concat(),
+    };
+    load_key(tpm, TPM_ALG_KEYEDHASH, SIGN, &scheme, HMAC_KEY)
+}
+
+fn aes_key(tpm: &mut TpmInstance, attributes: u32) -> u32 {
+    let parameters = [TPM_ALG_AES, 128, TPM_ALG_NULL].map(u16::to_be_bytes);
+    load_key(
+        tpm,
+        TPM_ALG_SYMCIPHER,
+        attributes,
+        &parameters.concat(),
+        &hex(CMAC_KEY),
+    )
+}
+
+fn mac(tpm: &mut TpmInstance, key: u32, scheme: u16, data: &[u8]) -> Result<Vec<u8>, u32> {
+    let params = [tpm2b(data), scheme.to_be_bytes().to_vec()].concat();
+    let response = run(tpm, TPM_CC_MAC, &[key], Some(&[&[]]), &params)?;
+    let (_, params) = parameters(&response, false);
+    Ok(Reader::new(&params).tpm2b().to_vec())
+}
+
+fn mac_start(tpm: &mut TpmInstance, key: u32, scheme: u16) -> Result<u32, u32> {
+    let params = [tpm2b(b"seq"), scheme.to_be_bytes().to_vec()].concat();
+    let response = run(tpm, TPM_CC_MAC_START, &[key], Some(&[&[]]), &params)?;
+    Ok(parameters(&response, true).0.unwrap())
+}
+
+// The same MAC through a sequence, with the data split across
+// SequenceUpdate calls and SequenceComplete
+fn mac_sequence(tpm: &mut TpmInstance, key: u32, scheme: u16, data: &[u8]) -> Vec<u8> {
+    let sequence = mac_start(tpm, key, scheme).unwrap();
+    let (first, rest) = data.split_at(data.len() / 3);
+    let (second, last) = rest.split_at(rest.len() / 2);
+    for part in [first, second] {
+        run(
+            tpm,
+            TPM_CC_SEQUENCE_UPDATE,
+            &[sequence],
+            Some(&[b"seq"]),
+            &tpm2b(part),
+        )
+        .unwrap();
+    }
+
+    let params = [tpm2b(last), TPM_RH_NULL.to_be_bytes().to_vec()].concat();
+    let response = run(
+        tpm,
+        TPM_CC_SEQUENCE_COMPLETE,
+        &[sequence],
+        Some(&[b"seq"]),
+        &params,
+    )
+    .unwrap();
+    let (_, params) = parameters(&response, false);
+    Reader::new(&params).tpm2b().to_vec()
+}
+
+// With the key's own hash, inScheme can be NULL or the same hash. A key
+// with no scheme takes any hash.
+#[test]
+fn hmac_known_answers() {
+    let mut tpm = power_on();
+    let key = hmac_key(&mut tpm, TPM_ALG_SHA256);
+    let any = hmac_key(&mut tpm, TPM_ALG_NULL);
+
+    for (key, scheme, expected) in [
+        (key, TPM_ALG_NULL, HMAC_SHA256),
+        (key, TPM_ALG_SHA256, HMAC_SHA256),
+        (any, TPM_ALG_SHA256, HMAC_SHA256),
+        (any, TPM_ALG_SHA384, HMAC_SHA384),
+    ] {
+        let expected = hex(expected);
+        assert_eq!(mac(&mut tpm, key, scheme, HMAC_DATA).unwrap(), expected);
+        assert_eq!(mac_sequence(&mut tpm, key, scheme, HMAC_DATA), expected);
+    }
+}
+
+#[test]
+fn cmac_known_answers() {
+    let mut tpm = power_on();
+    let key = aes_key(&mut tpm, SIGN);
+
+    for (data, expected) in CMAC_VECTORS {
+        let (data, expected) = (hex(data), hex(expected));
+        assert_eq!(mac(&mut tpm, key, TPM_ALG_CMAC, &data).unwrap(), expected);
+        assert_eq!(mac_sequence(&mut tpm, key, TPM_ALG_CMAC, &data), expected);
+    }
+}
+
+// Loads the key a check is made with
+type LoadKey = fn(&mut TpmInstance) -> u32;
+
+// The key has to be an unrestricted signing key, and the scheme has to fit
+// both the key and what it holds. MAC and MAC_Start check the same way.
+#[test]
+fn key_and_scheme_checks() {
+    let mut tpm = power_on();
+    let cases: [(LoadKey, u16, u32); 8] = [
+        // Not the key's own hash
+        (
+            |tpm| hmac_key(tpm, TPM_ALG_SHA256),
+            TPM_ALG_SHA384,
+            TPM_RC_VALUE_P2,
+        ),
+        // Neither the key nor inScheme has one
+        (
+            |tpm| hmac_key(tpm, TPM_ALG_NULL),
+            TPM_ALG_NULL,
+            TPM_RC_VALUE_P2,
+        ),
+        (|tpm| aes_key(tpm, SIGN), TPM_ALG_NULL, TPM_RC_VALUE_P2),
+        // A hash for a cipher, or CMAC for an HMAC key
+        (|tpm| aes_key(tpm, SIGN), TPM_ALG_SHA256, TPM_RC_SCHEME_P2),
+        (
+            |tpm| hmac_key(tpm, TPM_ALG_NULL),
+            TPM_ALG_CMAC,
+            TPM_RC_SCHEME_P2,
+        ),
+        (restricted_hmac_key, TPM_ALG_NULL, TPM_RC_ATTRIBUTES_H1),
+        (|tpm| aes_key(tpm, DECRYPT), TPM_ALG_CMAC, TPM_RC_KEY_H1),
+        (
+            |tpm| create_primary(tpm, &ecc_signing_template()).0,
+            TPM_ALG_SHA256,
+            TPM_RC_TYPE_H1,
+        ),
+    ];
+
+    for (load, scheme, expected) in cases {
+        let key = load(&mut tpm);
+        assert_eq!(mac(&mut tpm, key, scheme, HMAC_DATA), Err(expected));
+        assert_eq!(mac_start(&mut tpm, key, scheme), Err(expected));
+        flush(&mut tpm, key);
+    }
+}
+
+fn restricted_hmac_key(tpm: &mut TpmInstance) -> u32 {
+    let mut template = hmac_key_template();
+    // fixedTPM | fixedParent | sensitiveDataOrigin | userWithAuth |
+    // restricted | sign
+    template[6..10].copy_from_slice(&0x00050072u32.to_be_bytes());
+    create_primary(tpm, &template).0
+}
@@ -0,0 +1,498 @@
+mod common;
+
+use common::*;
+
+const INDEX: u32 = 0x01000010;
+
+const TPM_RH_PLATFORM: u32 = 0x4000000C;
+const TPM_CC_NV_UNDEFINE_SPACE_SPECIAL: u32 = 0x11F;
+const TPM_CC_NV_UNDEFINE_SPACE: u32 = 0x122;
+const TPM_CC_NV_INCREMENT: u32 = 0x134;
+const TPM_CC_NV_EXTEND: u32 = 0x136;
+const TPM_CC_NV_SET_BITS: u32 = 0x135;
+const TPM_CC_NV_CHANGE_AUTH: u32 = 0x13B;
+const TPM_CC_NV_READ_PUBLIC: u32 = 0x169;
+
+const TPM_RC_AUTH_UNAVAILABLE: u32 = 0x12F;
+const TPM_RC_NV_LOCKED: u32 = 0x148;
+const TPM_RC_NV_AUTHORIZATION: u32 = 0x149;
+const TPM_RC_NV_UNINITIALIZED: u32 = 0x14A;
+// Format-one codes for the second handle and the first session
+const TPM_RC_ATTRIBUTES_H2: u32 = 0x282;
+const TPM_RC_AUTH_FAIL_S1: u32 = 0x98E;
+const TPM_RC_POLICY_FAIL_S1: u32 = 0x99D;
+const TPM_RC_HANDLE_H1: u32 = 0x18B;
+
+// TPMA_NV bits
+const OWNERWRITE: u32 = 0x00000002;
+const POLICYWRITE: u32 = 0x00000008;
+const AUTHWRITE: u32 = 0x00000004;
+const POLICY_DELETE: u32 = 0x00000400;
+const WRITEDEFINE: u32 = 0x00002000;
+const WRITE_STCLEAR: u32 = 0x00004000;
+const AUTHREAD: u32 = 0x00040000;
+const NO_DA: u32 = 0x02000000;
+const ORDERLY: u32 = 0x04000000;
+const PLATFORMCREATE: u32 = 0x40000000;
+
+// TPM_NT in TPMA_NV
+const COUNTER: u32 = 0x10;
+const BITS: u32 = 0x20;
+const EXTEND: u32 = 0x40;
+const PIN_FAIL: u32 = 0x80;
+const PIN_PASS: u32 = 0x90;
+
+fn define(tpm: &mut tpm::tpm::TpmInstance, attributes: u32) {
+    define_with_policy(
+        tpm,
+        TPM_RH_OWNER,
+        attributes | AUTHWRITE | AUTHREAD | NO_DA,
+        &[],
+    );
+}
+
+fn define_with_policy(
+    tpm: &mut tpm::tpm::TpmInstance,
+    auth_handle: u32,
+    attributes: u32,
+    auth_policy: &[u8],
+) {
+    define_space(tpm, auth_handle, attributes, auth_policy, 8);
+}
+
+fn define_space(
+    tpm: &mut tpm::tpm::TpmInstance,
+    auth_handle: u32,
+    attributes: u32,
+    auth_policy: &[u8],
+    data_size: u16,
+) {
+    let mut public = Vec::new();
+    public.extend(INDEX.to_be_bytes());
+    public.extend(0x000Bu16.to_be_bytes()); // SHA256
+    public.extend(attributes.to_be_bytes());
+    public.extend(tpm2b(auth_policy));
+    public.extend(data_size.to_be_bytes());
+    let params = [tpm2b(b"nv"), tpm2b(&public)].concat();
+    run(
+        tpm,
+        TPM_CC_NV_DEFINE_SPACE,
+        &[auth_handle],
+        Some(&[&[]]),
+        &params,
+    )
+    .unwrap();
+}
+
+// The authorization for a policy session that needs no HMAC. It's flushed
+// after the command.
+fn policy_auth(session: &AuthSession) -> Vec<u8> {
+    auth_command(session.handle, &session.nonce_caller, 0, &[])
+}
+
+fn write(tpm: &mut tpm::tpm::TpmInstance, data: &[u8]) -> Result<(), u32> {
+    let params = [tpm2b(data), 0u16.to_be_bytes().to_vec()].concat();
+    run(
+        tpm,
+        TPM_CC_NV_WRITE,
+        &[INDEX, INDEX],
+        Some(&[b"nv"]),
+        &params,
+    )
+    .map(|_| ())
+}
+
+fn write_lock(tpm: &mut tpm::tpm::TpmInstance) {
+    run(
+        tpm,
+        TPM_CC_NV_WRITE_LOCK,
+        &[INDEX, INDEX],
+        Some(&[b"nv"]),
+        &[],
+    )
+    .unwrap();
+}
+
+fn read(tpm: &mut tpm::tpm::TpmInstance) -> Vec<u8> {
+    read_with(tpm, b"nv", 8).unwrap()
+}
+
+// Read `size` bytes with the index's own authorization
+fn read_with(tpm: &mut tpm::tpm::TpmInstance, auth: &[u8], size: u16) -> Result<Vec<u8>, u32> {
+    let params = [size.to_be_bytes(), 0u16.to_be_bytes()].concat();
+    let response = run(tpm, TPM_CC_NV_READ, &[INDEX, INDEX], Some(&[auth]), &params)?;
+    let (_, params) = parameters(&response, false);
+    Ok(Reader::new(&params).tpm2b().to_vec())
+}
+
+fn read_u64(tpm: &mut tpm::tpm::TpmInstance) -> u64 {
+    u64::from_be_bytes(read(tpm).try_into().unwrap())
+}
+
+fn increment(tpm: &mut tpm::tpm::TpmInstance) {
+    run(
+        tpm,
+        TPM_CC_NV_INCREMENT,
+        &[INDEX, INDEX],
+        Some(&[b"nv"]),
+        &[],
+    )
+    .unwrap();
+}
+
+// A WRITEDEFINE lock lasts until the index is deleted, power cycles
+// included.
+#[test]
+fn write_define_lock_survives_reboot() {
+    let mut tpm = power_on();
+    define(&mut tpm, WRITEDEFINE);
+    write(&mut tpm, b"12345678").unwrap();
+    write_lock(&mut tpm);
+    assert_eq!(write(&mut tpm, b"abcdefgh"), Err(TPM_RC_NV_LOCKED));
+
+    let mut tpm = power_on();
+    assert_eq!(write(&mut tpm, b"abcdefgh"), Err(TPM_RC_NV_LOCKED));
+    assert_eq!(read(&mut tpm), b"12345678");
+
+    shutdown(&mut tpm, TPM_SU_CLEAR).unwrap();
+    let mut tpm = power_on();
+    assert_eq!(write(&mut tpm, b"abcdefgh"), Err(TPM_RC_NV_LOCKED));
+}
+
+// A WRITE_STCLEAR lock lasts until Startup(CLEAR), so it survives
+// Shutdown(STATE) and a TPM Resume.
+#[test]
+fn write_stclear_lock_until_startup_clear() {
+    let mut tpm = power_on();
+    define(&mut tpm, WRITE_STCLEAR);
+    write(&mut tpm, b"12345678").unwrap();
+    write_lock(&mut tpm);
+    assert_eq!(write(&mut tpm, b"abcdefgh"), Err(TPM_RC_NV_LOCKED));
+
+    shutdown(&mut tpm, TPM_SU_STATE).unwrap();
+    let mut tpm = tpm::tpm::TpmInstance::new(&platform());
+    startup(&mut tpm, TPM_SU_STATE).unwrap();
+    assert_eq!(write(&mut tpm, b"abcdefgh"), Err(TPM_RC_NV_LOCKED));
+
+    let mut tpm = power_on();
+    write(&mut tpm, b"abcdefgh").unwrap();
+    assert_eq!(read(&mut tpm), b"abcdefgh");
+}
+
+// An index with TPMA_NV_POLICY_DELETE can only be deleted with
+// TPM2_NV_UndefineSpaceSpecial, by satisfying its policy.
+#[test]
+fn undefine_space_special() {
+    let mut tpm = power_on();
+    let policy = policy_extend(
+        &[0; 32],
+        TPM_CC_POLICY_COMMAND_CODE,
+        &TPM_CC_NV_UNDEFINE_SPACE_SPECIAL.to_be_bytes(),
+    );
+    let attributes = POLICY_DELETE | PLATFORMCREATE | AUTHWRITE | AUTHREAD | NO_DA;
+    define_with_policy(&mut tpm, TPM_RH_PLATFORM, attributes, &policy);
+
+    let handles = [TPM_RH_PLATFORM, INDEX];
+    let rc = run(
+        &mut tpm,
+        TPM_CC_NV_UNDEFINE_SPACE,
+        &handles,
+        Some(&[&[]]),
+        &[],
+    );
+    assert_eq!(rc, Err(TPM_RC_ATTRIBUTES_H2));
+
+    // A policy without the command code doesn't match.
+    let handles = [INDEX, TPM_RH_PLATFORM];
+    let session = start_auth_session(&mut tpm, TPM_SE_POLICY, TPM_RH_NULL, &[]);
+    let auths = [policy_auth(&session), auth_command(TPM_RS_PW, &[], 0, &[])];
+    let rc = run_sessions(
+        &mut tpm,
+        TPM_CC_NV_UNDEFINE_SPACE_SPECIAL,
+        &handles,
+        Some(&auths),
+        &[],
+    );
+    assert_eq!(rc, Err(TPM_RC_POLICY_FAIL_S1));
+
+    policy_command_code(&mut tpm, session.handle, TPM_CC_NV_UNDEFINE_SPACE_SPECIAL);
+    run_sessions(
+        &mut tpm,
+        TPM_CC_NV_UNDEFINE_SPACE_SPECIAL,
+        &handles,
+        Some(&auths),
+        &[],
+    )
+    .unwrap();
+    let rc = run(&mut tpm, TPM_CC_NV_READ_PUBLIC, &[INDEX], None, &[]);
+    assert_eq!(rc, Err(TPM_RC_HANDLE_H1));
+}
+
+// Authorized by a policy session, the index needs TPMA_NV_POLICYWRITE or
+// TPMA_NV_POLICYREAD rather than TPMA_NV_AUTHWRITE or TPMA_NV_AUTHREAD.
+#[test]
+fn policy_read_write() {
+    let mut tpm = power_on();
+    // A policy of zeros is satisfied by any new policy session.
+    define_with_policy(
+        &mut tpm,
+        TPM_RH_OWNER,
+        POLICYWRITE | AUTHREAD | NO_DA,
+        &[0; 32],
+    );
+    assert_eq!(write(&mut tpm, b"12345678"), Err(TPM_RC_NV_AUTHORIZATION));
+
+    let params = [tpm2b(b"12345678"), 0u16.to_be_bytes().to_vec()].concat();
+    let session = start_auth_session(&mut tpm, TPM_SE_POLICY, TPM_RH_NULL, &[]);
+    let auths = [policy_auth(&session)];
+    run_sessions(
+        &mut tpm,
+        TPM_CC_NV_WRITE,
+        &[INDEX, INDEX],
+        Some(&auths),
+        &params,
+    )
+    .unwrap();
+    assert_eq!(read(&mut tpm), b"12345678");
+
+    let params = [8u16.to_be_bytes(), 0u16.to_be_bytes()].concat();
+    let session = start_auth_session(&mut tpm, TPM_SE_POLICY, TPM_RH_NULL, &[]);
+    let auths = [policy_auth(&session)];
+    let rc = run_sessions(
+        &mut tpm,
+        TPM_CC_NV_READ,
+        &[INDEX, INDEX],
+        Some(&auths),
+        &params,
+    );
+    assert_eq!(rc, Err(TPM_RC_NV_AUTHORIZATION));
+}
+
+// An index's authValue can only be changed through its policy, which has to
+// assert TPM2_NV_ChangeAuth.
+#[test]
+fn change_auth() {
+    let mut tpm = power_on();
+    let policy = policy_extend(
+        &[0; 32],
+        TPM_CC_POLICY_COMMAND_CODE,
+        &TPM_CC_NV_CHANGE_AUTH.to_be_bytes(),
+    );
+    define_with_policy(
+        &mut tpm,
+        TPM_RH_OWNER,
+        AUTHWRITE | AUTHREAD | NO_DA,
+        &policy,
+    );
+    write(&mut tpm, b"12345678").unwrap();
+
+    let params = tpm2b(b"new");
+    let rc = run(
+        &mut tpm,
+        TPM_CC_NV_CHANGE_AUTH,
+        &[INDEX],
+        Some(&[b"nv"]),
+        &params,
+    );
+    assert_eq!(rc, Err(TPM_RC_AUTH_UNAVAILABLE));
+
+    let session = start_auth_session(&mut tpm, TPM_SE_POLICY, TPM_RH_NULL, &[]);
+    policy_command_code(&mut tpm, session.handle, TPM_CC_NV_CHANGE_AUTH);
+    let auths = [policy_auth(&session)];
+    run_sessions(
+        &mut tpm,
+        TPM_CC_NV_CHANGE_AUTH,
+        &[INDEX],
+        Some(&auths),
+        &params,
+    )
+    .unwrap();
+
+    assert_eq!(write(&mut tpm, b"abcdefgh"), Err(TPM_RC_AUTH_FAIL_S1));
+    let params = [8u16.to_be_bytes(), 0u16.to_be_bytes()].concat();
+    let response = run(
+        &mut tpm,
+        TPM_CC_NV_READ,
+        &[INDEX, INDEX],
+        Some(&[b"new"]),
+        &params,
+    )
+    .unwrap();
+    let (_, params) = parameters(&response, false);
+    assert_eq!(Reader::new(&params).tpm2b(), b"12345678");
+}
+
+// A counter starts past the highest value any deleted counter reached, so
+// no value is ever seen twice.
+#[test]
+fn counter() {
+    let mut tpm = power_on();
+    define(&mut tpm, COUNTER);
+    assert_eq!(read_with(&mut tpm, b"nv", 8), Err(TPM_RC_NV_UNINITIALIZED));
+    for expected in 1..=3 {
+        increment(&mut tpm);
+        assert_eq!(read_u64(&mut tpm), expected);
+    }
+
+    let handles = [TPM_RH_OWNER, INDEX];
+    run(
+        &mut tpm,
+        TPM_CC_NV_UNDEFINE_SPACE,
+        &handles,
+        Some(&[&[]]),
+        &[],
+    )
+    .unwrap();
+    define(&mut tpm, COUNTER);
+    increment(&mut tpm);
+    assert_eq!(read_u64(&mut tpm), 4);
+}
+
+// An orderly counter only reaches NV when it's first written, every 256
+// increments and at Shutdown. After a power loss it jumps past any value
+// it could have reached.
+#[test]
+fn orderly_counter() {
+    let mut tpm = power_on();
+    define(&mut tpm, COUNTER | ORDERLY);
+    for _ in 0..10 {
+        increment(&mut tpm);
+    }
+    assert_eq!(read_u64(&mut tpm), 10);
+
+    // NV only has 1 from the first write.
+    let mut tpm = power_on();
+    assert_eq!(read_u64(&mut tpm), 1 + 256);
+
+    increment(&mut tpm);
+    shutdown(&mut tpm, TPM_SU_CLEAR).unwrap();
+    let mut tpm = power_on();
+    assert_eq!(read_u64(&mut tpm), 258);
+
+    // NV has 258 from the Shutdown.
+    let mut tpm = power_on();
+    assert_eq!(read_u64(&mut tpm), 258 + 256);
+
+    // 768 is written to NV, 770 isn't.
+    for _ in 514..770 {
+        increment(&mut tpm);
+    }
+    assert_eq!(read_u64(&mut tpm), 770);
+    let mut tpm = power_on();
+    assert_eq!(read_u64(&mut tpm), 768 + 256);
+}
+
+// Bits can be set but never cleared.
+#[test]
+fn set_bits() {
+    let mut tpm = power_on();
+    define(&mut tpm, BITS);
+    for bits in [0x1u64, 0x100, 0] {
+        run(
+            &mut tpm,
+            TPM_CC_NV_SET_BITS,
+            &[INDEX, INDEX],
+            Some(&[b"nv"]),
+            &bits.to_be_bytes(),
+        )
+        .unwrap();
+    }
+    assert_eq!(read_u64(&mut tpm), 0x101);
+}
+
+// Each extend hashes the data into the old value, which starts as zeros.
+#[test]
+fn extend() {
+    let mut tpm = power_on();
+    let attributes = EXTEND | AUTHWRITE | AUTHREAD | NO_DA;
+    define_space(&mut tpm, TPM_RH_OWNER, attributes, &[], 32);
+
+    let mut expected = vec![0; 32];
+    for data in [b"one", b"two"] {
+        run(
+            &mut tpm,
+            TPM_CC_NV_EXTEND,
+            &[INDEX, INDEX],
+            Some(&[b"nv"]),
+            &tpm2b(data),
+        )
+        .unwrap();
+        expected = sha256(&[&expected, data]);
+        assert_eq!(read_with(&mut tpm, b"nv", 32).unwrap(), expected);
+    }
+}
+
+// Set a PIN index's pinCount and pinLimit with ownerAuth.
+fn write_pin(tpm: &mut tpm::tpm::TpmInstance, count: u32, limit: u32) {
+    let data = [count.to_be_bytes(), limit.to_be_bytes()].concat();
+    let params = [tpm2b(&data), 0u16.to_be_bytes().to_vec()].concat();
+    run(
+        tpm,
+        TPM_CC_NV_WRITE,
+        &[TPM_RH_OWNER, INDEX],
+        Some(&[&[]]),
+        &params,
+    )
+    .unwrap();
+}
+
+// A PIN_PASS index's authValue can be used pinLimit times. Failures don't
+// count.
+#[test]
+fn pin_pass() {
+    let mut tpm = power_on();
+    define_space(
+        &mut tpm,
+        TPM_RH_OWNER,
+        PIN_PASS | OWNERWRITE | AUTHREAD | NO_DA,
+        &[],
+        8,
+    );
+    assert_eq!(read_with(&mut tpm, b"nv", 8), Err(TPM_RC_AUTH_UNAVAILABLE));
+
+    write_pin(&mut tpm, 0, 2);
+    assert_eq!(read_with(&mut tpm, b"pin", 8), Err(TPM_RC_AUTH_FAIL_S1));
+    // Each read sees the count from before its own authorization.
+    assert_eq!(
+        read_with(&mut tpm, b"nv", 8).unwrap(),
+        [0, 0, 0, 1, 0, 0, 0, 2]
+    );
+    assert_eq!(
+        read_with(&mut tpm, b"nv", 8).unwrap(),
+        [0, 0, 0, 2, 0, 0, 0, 2]
+    );
+    assert_eq!(read_with(&mut tpm, b"nv", 8), Err(TPM_RC_AUTH_UNAVAILABLE));
+
+    write_pin(&mut tpm, 0, 3);
+    read_with(&mut tpm, b"nv", 8).unwrap();
+}
+
+// A PIN_FAIL index locks out after pinLimit failures in a row. A success
+// starts the count again.
+#[test]
+fn pin_fail() {
+    let mut tpm = power_on();
+    define_space(
+        &mut tpm,
+        TPM_RH_OWNER,
+        PIN_FAIL | OWNERWRITE | AUTHREAD | NO_DA,
+        &[],
+        8,
+    );
+    write_pin(&mut tpm, 0, 2);
+
+    assert_eq!(read_with(&mut tpm, b"pin", 8), Err(TPM_RC_AUTH_FAIL_S1));
+    assert_eq!(
+        read_with(&mut tpm, b"nv", 8).unwrap(),
+        [0, 0, 0, 0, 0, 0, 0, 2]
+    );
+    assert_eq!(read_with(&mut tpm, b"pin", 8), Err(TPM_RC_AUTH_FAIL_S1));
+    assert_eq!(read_with(&mut tpm, b"pin", 8), Err(TPM_RC_AUTH_FAIL_S1));
+    assert_eq!(read_with(&mut tpm, b"nv", 8), Err(TPM_RC_AUTH_UNAVAILABLE));
+
+    write_pin(&mut tpm, 0, 2);
+    assert_eq!(
+        read_with(&mut tpm, b"nv", 8).unwrap(),
+        [0, 0, 0, 0, 0, 0, 0, 2]
+    );
+}
@@ -0,0 +1,239 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+const TPM_RH_ENDORSEMENT: u32 = 0x4000000B;
+const TPM_RH_PLATFORM: u32 = 0x4000000C;
+
+const TPM_CC_OBJECT_CHANGE_AUTH: u32 = 0x150;
+const TPM_CC_LOAD_EXTERNAL: u32 = 0x167;
+const TPM_CC_READ_PUBLIC: u32 = 0x173;
+
+const TPM_RC_TYPE_H2: u32 = 0x28A;
+const TPM_RC_HIERARCHY_P3: u32 = 0x3C5;
+const TPM_RC_AUTH_FAIL_S1: u32 = 0x98E;
+
+// The public area, Name and Qualified Name of a loaded object
+fn read_public(tpm: &mut TpmInstance, handle: u32) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let response = run(tpm, TPM_CC_READ_PUBLIC, &[handle], None, &[]).unwrap();
+    let mut reader = Reader::new(&response);
+    let public = tpm2b(reader.tpm2b());
+    let name = reader.tpm2b().to_vec();
+    let qualified_name = reader.tpm2b().to_vec();
+    (public, name, qualified_name)
+}
+
+// A SHA-256 Qualified Name from the parent's and the object's Name
+fn qualified_name(parent: &[u8], name: &[u8]) -> Vec<u8> {
+    [&TPM_ALG_SHA256.to_be_bytes()[..], &sha256(&[parent, name])].concat()
+}
+
+// Sign a digest with the key's own scheme
+fn sign(tpm: &mut TpmInstance, key: u32, auth: &[u8]) -> Result<Vec<u8>, u32> {
+    let params = [
+        tpm2b(&[0x5A; 32]),
+        0x0010u16.to_be_bytes().to_vec(), // scheme NULL, use the key's
+        0x8024u16.to_be_bytes().to_vec(), // TPM_ST_HASHCHECK
+        TPM_RH_NULL.to_be_bytes().to_vec(),
+        tpm2b(&[]),
+    ]
+    .concat();
+    let response = run(tpm, TPM_CC_SIGN, &[key], Some(&[auth]), &params)?;
+    Ok(parameters(&response, false).1)
+}
+
+// Primary keys come from the hierarchy seed, so the same template makes the
+// same key, even after a power cycle.
+#[test]
+fn create_primary_is_deterministic() {
+    let mut tpm = power_on();
+    let (first, public) = create_primary(&mut tpm, &ecc_storage_template());
+    let (second, again) = create_primary(&mut tpm, &ecc_storage_template());
+    assert_ne!(first, second);
+    assert_eq!(public, again);
+
+    // A different template is a different key.
+    let (_, signing) = create_primary(&mut tpm, &ecc_signing_template());
+    assert_ne!(public[public.len() - 64..], signing[signing.len() - 64..]);
+
+    let mut tpm = power_on();
+    let (_, after_reboot) = create_primary(&mut tpm, &ecc_storage_template());
+    assert_eq!(public, after_reboot);
+}
+
+#[test]
+fn create_load_sign() {
+    let mut tpm = power_on();
+    let (parent, _) = create_primary(&mut tpm, &ecc_storage_template());
+    let (private, public) = create(&mut tpm, parent, &ecc_signing_template(), b"key", &[]);
+    let key = load(&mut tpm, parent, &private, &public);
+
+    let digest = [0x5Au8; 32];
+    let params = [
+        tpm2b(&digest),
+        0x0010u16.to_be_bytes().to_vec(), // scheme NULL, use the key's
+        0x8024u16.to_be_bytes().to_vec(), // TPM_ST_HASHCHECK
+        TPM_RH_NULL.to_be_bytes().to_vec(),
+        tpm2b(&[]),
+    ]
+    .concat();
+    let response = run(&mut tpm, TPM_CC_SIGN, &[key], Some(&[b"key"]), &params).unwrap();
+    let (_, signature) = parameters(&response, false);
+    let mut reader = Reader::new(&signature);
+    assert_eq!(reader.u16(), 0x0018); // ECDSA
+    assert_eq!(reader.u16(), 0x000B); // SHA256
+    assert_eq!(reader.tpm2b().len(), 32);
+    assert_eq!(reader.tpm2b().len(), 32);
+    assert!(reader.is_empty());
+
+    let params = [tpm2b(&digest), signature.clone()].concat();
+    run(&mut tpm, TPM_CC_VERIFY_SIGNATURE, &[key], None, &params).unwrap();
+
+    let params = [tpm2b(&[0xA5; 32]), signature].concat();
+    assert!(run(&mut tpm, TPM_CC_VERIFY_SIGNATURE, &[key], None, &params).is_err());
+
+    // The wrong authValue can't sign.
+    let params = [
+        tpm2b(&digest),
+        0x0010u16.to_be_bytes().to_vec(),
+        0x8024u16.to_be_bytes().to_vec(),
+        TPM_RH_NULL.to_be_bytes().to_vec(),
+        tpm2b(&[]),
+    ]
+    .concat();
+    assert!(run(&mut tpm, TPM_CC_SIGN, &[key], Some(&[b"kez"]), &params).is_err());
+}
+
+// The blobs from Create load again after a power cycle, as long as the
+// parent is recreated.
+#[test]
+fn seal_unseal() {
+    let mut tpm = power_on();
+    let (parent, _) = create_primary(&mut tpm, &ecc_storage_template());
+    let secret = b"the sealed secret";
+    let (private, public) = create(&mut tpm, parent, &sealed_data_template(), b"pw", secret);
+
+    let item = load(&mut tpm, parent, &private, &public);
+    let response = run(&mut tpm, TPM_CC_UNSEAL, &[item], Some(&[b"pw"]), &[]).unwrap();
+    let (_, params) = parameters(&response, false);
+    assert_eq!(Reader::new(&params).tpm2b(), secret);
+    flush(&mut tpm, item);
+
+    let mut tpm = power_on();
+    let (parent, _) = create_primary(&mut tpm, &ecc_storage_template());
+    let item = load(&mut tpm, parent, &private, &public);
+    let response = run(&mut tpm, TPM_CC_UNSEAL, &[item], Some(&[b"pw"]), &[]).unwrap();
+    let (_, params) = parameters(&response, false);
+    assert_eq!(Reader::new(&params).tpm2b(), secret);
+
+    assert!(run(&mut tpm, TPM_CC_UNSEAL, &[item], Some(&[b"px"]), &[]).is_err());
+}
+
+// ReadPublic gives back the public area the key was loaded with, the Name
+// Load returned, and the Qualified Name through its parent.
+#[test]
+fn read_public_names() {
+    let mut tpm = power_on();
+    let (parent, _) = create_primary(&mut tpm, &ecc_storage_template());
+    let (_, parent_name, parent_qn) = read_public(&mut tpm, parent);
+    assert_eq!(
+        parent_qn,
+        qualified_name(&TPM_RH_OWNER.to_be_bytes(), &parent_name)
+    );
+
+    let (private, public) = create(&mut tpm, parent, &ecc_signing_template(), &[], &[]);
+    let params = [private, public.clone()].concat();
+    let response = run(&mut tpm, TPM_CC_LOAD, &[parent], Some(&[&[]]), &params).unwrap();
+    let (key, params) = parameters(&response, true);
+    let name = Reader::new(&params).tpm2b().to_vec();
+    assert_eq!(name, object_name(&public));
+
+    let (out_public, out_name, qn) = read_public(&mut tpm, key.unwrap());
+    assert_eq!(out_public, public);
+    assert_eq!(out_name, name);
+    assert_eq!(qn, qualified_name(&parent_qn, &name));
+}
+
+// The public half of a TPM key loads on its own and verifies what the key
+// signed, but only in the null hierarchy.
+#[test]
+fn load_external_public() {
+    let mut tpm = power_on();
+    let (key, public) = create_primary(&mut tpm, &ecc_signing_template());
+    let signature = sign(&mut tpm, key, &[]).unwrap();
+
+    for hierarchy in [TPM_RH_OWNER, TPM_RH_ENDORSEMENT, TPM_RH_PLATFORM] {
+        let params = [tpm2b(&[]), tpm2b(&public), hierarchy.to_be_bytes().to_vec()].concat();
+        let rc = run(&mut tpm, TPM_CC_LOAD_EXTERNAL, &[], None, &params);
+        assert_eq!(rc, Err(TPM_RC_HIERARCHY_P3));
+    }
+
+    let params = [
+        tpm2b(&[]),
+        tpm2b(&public),
+        TPM_RH_NULL.to_be_bytes().to_vec(),
+    ]
+    .concat();
+    let response = run(&mut tpm, TPM_CC_LOAD_EXTERNAL, &[], None, &params).unwrap();
+    let mut reader = Reader::new(&response);
+    let external = reader.u32();
+    assert_eq!(reader.tpm2b(), object_name(&tpm2b(&public)));
+
+    let (_, _, qn) = read_public(&mut tpm, external);
+    let name = object_name(&tpm2b(&public));
+    assert_eq!(qn, qualified_name(&TPM_RH_NULL.to_be_bytes(), &name));
+
+    let params = [tpm2b(&[0x5A; 32]), signature].concat();
+    run(
+        &mut tpm,
+        TPM_CC_VERIFY_SIGNATURE,
+        &[external],
+        None,
+        &params,
+    )
+    .unwrap();
+}
+
+// ObjectChangeAuth gives a new private blob with the new authValue. The
+// loaded object and the old blob keep the old one.
+#[test]
+fn object_change_auth() {
+    let mut tpm = power_on();
+    let (parent, _) = create_primary(&mut tpm, &ecc_storage_template());
+    let (private, public) = create(&mut tpm, parent, &ecc_signing_template(), b"old", &[]);
+    let key = load(&mut tpm, parent, &private, &public);
+
+    let response = run(
+        &mut tpm,
+        TPM_CC_OBJECT_CHANGE_AUTH,
+        &[key, parent],
+        Some(&[b"old"]),
+        &tpm2b(b"new"),
+    )
+    .unwrap();
+    let (_, params) = parameters(&response, false);
+    let new_private = tpm2b(Reader::new(&params).tpm2b());
+    assert_ne!(new_private, private);
+    sign(&mut tpm, key, b"old").unwrap();
+    flush(&mut tpm, key);
+
+    let key = load(&mut tpm, parent, &new_private, &public);
+    sign(&mut tpm, key, b"new").unwrap();
+    assert_eq!(sign(&mut tpm, key, b"old"), Err(TPM_RC_AUTH_FAIL_S1));
+    flush(&mut tpm, key);
+
+    let key = load(&mut tpm, parent, &private, &public);
+    sign(&mut tpm, key, b"old").unwrap();
+
+    // Only the key's own parent can wrap it.
+    let (other, _) = create_primary(&mut tpm, &ecc_signing_template());
+    let rc = run(
+        &mut tpm,
+        TPM_CC_OBJECT_CHANGE_AUTH,
+        &[key, other],
+        Some(&[b"old"]),
+        &tpm2b(b"new"),
+    );
+    assert_eq!(rc, Err(TPM_RC_TYPE_H2));
+}
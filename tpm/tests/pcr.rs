@@ -0,0 +1,103 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+const TPM_ALG_SHA1: u16 = 0x0004;
+
+const TPM_RC_LOCALITY: u32 = 0x907;
+
+// pcrUpdateCounter, pcrSelectionOut and the values from PCR_Read
+fn pcr_read(tpm: &mut TpmInstance, selection: &[u8]) -> (u32, Vec<u8>, Vec<Vec<u8>>) {
+    let response = run(tpm, TPM_CC_PCR_READ, &[], None, selection).unwrap();
+    let mut reader = Reader::new(&response);
+    let counter = reader.u32();
+    let selection_out = reader.bytes(selection.len()).to_vec();
+    let count = reader.u32();
+    let values = (0..count).map(|_| reader.tpm2b().to_vec()).collect();
+    assert!(reader.is_empty());
+    (counter, selection_out, values)
+}
+
+fn read_pcr(tpm: &mut TpmInstance, pcr: usize) -> Vec<u8> {
+    let (_, _, values) = pcr_read(tpm, &pcr_selection(TPM_ALG_SHA256, &[pcr]));
+    values[0].clone()
+}
+
+// Extend with a SHA-1 and a SHA-256 digest. Only the SHA-256 bank exists.
+fn extend_both_banks(tpm: &mut TpmInstance, pcr: u32, digest: &[u8]) -> Result<Vec<u8>, u32> {
+    let params = [
+        &2u32.to_be_bytes()[..],
+        &TPM_ALG_SHA1.to_be_bytes(),
+        &[0xEE; 20],
+        &TPM_ALG_SHA256.to_be_bytes(),
+        digest,
+    ]
+    .concat();
+    run(tpm, TPM_CC_PCR_EXTEND, &[pcr], Some(&[&[]]), &params)
+}
+
+#[test]
+fn extend_and_read() {
+    let mut tpm = power_on();
+    assert_eq!(read_pcr(&mut tpm, 0), [0; 32]);
+    // The dynamic root of trust PCRs are all ones until it's launched.
+    assert_eq!(read_pcr(&mut tpm, 17), [0xFF; 32]);
+
+    let digest = sha256(&[b"measurement"]);
+    extend_both_banks(&mut tpm, 0, &digest).unwrap();
+    let expected = sha256(&[&[0; 32], &digest]);
+    let (counter, _, values) = pcr_read(&mut tpm, &pcr_selection(TPM_ALG_SHA256, &[0]));
+    assert_eq!((counter, values), (1, vec![expected.clone()]));
+
+    extend_both_banks(&mut tpm, 0, &digest).unwrap();
+    assert_eq!(read_pcr(&mut tpm, 0), sha256(&[&expected, &digest]));
+
+    // Extending TPM_RH_NULL does nothing, and locality 0 can't extend the
+    // dynamic root of trust PCRs.
+    extend_both_banks(&mut tpm, TPM_RH_NULL, &digest).unwrap();
+    assert_eq!(
+        extend_both_banks(&mut tpm, 17, &digest),
+        Err(TPM_RC_LOCALITY)
+    );
+    let (counter, _, _) = pcr_read(&mut tpm, &pcr_selection(TPM_ALG_SHA256, &[]));
+    assert_eq!(counter, 2);
+}
+
+// PCR_Read returns as many values as fit in a TPML_DIGEST, and says which
+// they were. There's nothing to read from a bank the TPM doesn't have.
+#[test]
+fn read_selection() {
+    let mut tpm = power_on();
+    let all: Vec<usize> = (0..24).collect();
+    let (_, selection, values) = pcr_read(&mut tpm, &pcr_selection(TPM_ALG_SHA256, &all));
+    assert_eq!(selection, pcr_selection(TPM_ALG_SHA256, &all[..8]));
+    assert_eq!(values.len(), 8);
+
+    let (_, selection, values) = pcr_read(&mut tpm, &pcr_selection(TPM_ALG_SHA1, &[0, 1]));
+    assert_eq!(selection, pcr_selection(TPM_ALG_SHA1, &[]));
+    assert!(values.is_empty());
+}
+
+// A TPM Resume keeps PCRs 0 to 15 and pcrUpdateCounter. A TPM Restart, like
+// a TPM Reset, starts them all again.
+#[test]
+fn pcrs_across_startup() {
+    let mut tpm = power_on();
+    let digest = sha256(&[b"measurement"]);
+    extend_both_banks(&mut tpm, 0, &digest).unwrap();
+    extend_both_banks(&mut tpm, 16, &digest).unwrap();
+    let extended = read_pcr(&mut tpm, 0);
+
+    shutdown(&mut tpm, TPM_SU_STATE).unwrap();
+    let mut tpm = TpmInstance::new(&platform());
+    startup(&mut tpm, TPM_SU_STATE).unwrap();
+    let (counter, _, values) = pcr_read(&mut tpm, &pcr_selection(TPM_ALG_SHA256, &[0, 16]));
+    assert_eq!(counter, 2);
+    assert_eq!(values, [extended, vec![0; 32]]);
+
+    shutdown(&mut tpm, TPM_SU_STATE).unwrap();
+    let mut tpm = power_on();
+    let (counter, _, values) = pcr_read(&mut tpm, &pcr_selection(TPM_ALG_SHA256, &[0]));
+    assert_eq!((counter, values), (0, vec![vec![0; 32]]));
+}
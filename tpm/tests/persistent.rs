@@ -0,0 +1,211 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+const TPM_RH_PLATFORM: u32 = 0x4000000C;
+
+const TPM_CC_EVICT_CONTROL: u32 = 0x120;
+const TPM_CC_READ_PUBLIC: u32 = 0x173;
+
+const TPM_CAP_HANDLES: u32 = 1;
+const TPM_PT_HR_PERSISTENT_MIN: u32 = 0x10F;
+const TPM_PT_HR_PERSISTENT: u32 = 0x208;
+const TPM_PT_HR_PERSISTENT_AVAIL: u32 = 0x209;
+
+const TPM_RC_HIERARCHY_H2: u32 = 0x285;
+const TPM_RC_HANDLE_H1: u32 = 0x18B;
+const TPM_RC_RANGE_P1: u32 = 0x1CD;
+const TPM_RC_NV_DEFINED: u32 = 0x14C;
+
+// The first handles of the owner's and the platform's ranges
+const OWNER_PERSISTENT: u32 = 0x81000000;
+const PLATFORM_PERSISTENT: u32 = 0x81800000;
+
+fn primary(tpm: &mut TpmInstance, hierarchy: u32) -> u32 {
+    let params = [
+        sensitive_create(&[], &[]),
+        ecc_signing_template(),
+        tpm2b(&[]),
+        0u32.to_be_bytes().to_vec(),
+    ]
+    .concat();
+    let response = run(
+        tpm,
+        TPM_CC_CREATE_PRIMARY,
+        &[hierarchy],
+        Some(&[&[]]),
+        &params,
+    )
+    .unwrap();
+    parameters(&response, true).0.unwrap()
+}
+
+fn evict_control(
+    tpm: &mut TpmInstance,
+    auth: u32,
+    object: u32,
+    persistent: u32,
+) -> Result<(), u32> {
+    run(
+        tpm,
+        TPM_CC_EVICT_CONTROL,
+        &[auth, object],
+        Some(&[&[]]),
+        &persistent.to_be_bytes(),
+    )
+    .map(|_| ())
+}
+
+fn read_public(tpm: &mut TpmInstance, handle: u32) -> Result<Vec<u8>, u32> {
+    run(tpm, TPM_CC_READ_PUBLIC, &[handle], None, &[])
+}
+
+// moreData and the handles from `start` on, at most `count` of them
+fn get_handles(tpm: &mut TpmInstance, start: u32, count: u32) -> (bool, Vec<u32>) {
+    let params = [TPM_CAP_HANDLES, start, count]
+        .map(u32::to_be_bytes)
+        .concat();
+    let response = run(tpm, TPM_CC_GET_CAPABILITY, &[], None, &params).unwrap();
+    let mut reader = Reader::new(&response);
+    let more_data = reader.bytes(1)[0] != 0;
+    assert_eq!(reader.u32(), TPM_CAP_HANDLES);
+    let n = reader.u32();
+    (more_data, (0..n).map(|_| reader.u32()).collect())
+}
+
+// A persisted key is the same key under its new handle, and stays after a
+// power cycle until it's evicted.
+#[test]
+fn persist_and_evict() {
+    let mut tpm = power_on();
+    let key = primary(&mut tpm, TPM_RH_OWNER);
+    let handle = OWNER_PERSISTENT + 1;
+    evict_control(&mut tpm, TPM_RH_OWNER, key, handle).unwrap();
+    let public = read_public(&mut tpm, key).unwrap();
+    assert_eq!(read_public(&mut tpm, handle), Ok(public.clone()));
+
+    let rc = evict_control(&mut tpm, TPM_RH_OWNER, key, handle);
+    assert_eq!(rc, Err(TPM_RC_NV_DEFINED));
+
+    let mut tpm = power_on();
+    assert_eq!(read_public(&mut tpm, handle), Ok(public));
+    assert_eq!(
+        get_handles(&mut tpm, OWNER_PERSISTENT, 8),
+        (false, vec![handle])
+    );
+
+    evict_control(&mut tpm, TPM_RH_OWNER, handle, handle).unwrap();
+    assert_eq!(read_public(&mut tpm, handle), Err(TPM_RC_HANDLE_H1));
+    assert_eq!(get_handles(&mut tpm, OWNER_PERSISTENT, 8), (false, vec![]));
+
+    let mut tpm = power_on();
+    assert_eq!(read_public(&mut tpm, handle), Err(TPM_RC_HANDLE_H1));
+}
+
+// The owner persists owner and endorsement keys in the lower half of the
+// range, and the platform its own keys in the upper half.
+#[test]
+fn persistent_ranges() {
+    let mut tpm = power_on();
+    let owner_key = primary(&mut tpm, TPM_RH_OWNER);
+    let platform_key = primary(&mut tpm, TPM_RH_PLATFORM);
+
+    let rc = evict_control(&mut tpm, TPM_RH_OWNER, owner_key, PLATFORM_PERSISTENT);
+    assert_eq!(rc, Err(TPM_RC_RANGE_P1));
+    let rc = evict_control(&mut tpm, TPM_RH_PLATFORM, platform_key, OWNER_PERSISTENT);
+    assert_eq!(rc, Err(TPM_RC_RANGE_P1));
+
+    // Each only persists keys from its own hierarchies.
+    let rc = evict_control(&mut tpm, TPM_RH_OWNER, platform_key, OWNER_PERSISTENT);
+    assert_eq!(rc, Err(TPM_RC_HIERARCHY_H2));
+    let rc = evict_control(&mut tpm, TPM_RH_PLATFORM, owner_key, PLATFORM_PERSISTENT);
+    assert_eq!(rc, Err(TPM_RC_HIERARCHY_H2));
+    flush(&mut tpm, owner_key);
+    let null_key = primary(&mut tpm, TPM_RH_NULL);
+    let rc = evict_control(&mut tpm, TPM_RH_OWNER, null_key, OWNER_PERSISTENT);
+    assert_eq!(rc, Err(TPM_RC_HIERARCHY_H2));
+
+    evict_control(&mut tpm, TPM_RH_PLATFORM, platform_key, PLATFORM_PERSISTENT).unwrap();
+    // The platform evicts its own keys, and the owner can't.
+    let rc = evict_control(
+        &mut tpm,
+        TPM_RH_OWNER,
+        PLATFORM_PERSISTENT,
+        PLATFORM_PERSISTENT,
+    );
+    assert_eq!(rc, Err(TPM_RC_RANGE_P1));
+    evict_control(
+        &mut tpm,
+        TPM_RH_PLATFORM,
+        PLATFORM_PERSISTENT,
+        PLATFORM_PERSISTENT,
+    )
+    .unwrap();
+}
+
+// Persistent handles are listed in order, a page at a time, and the
+// properties count them.
+#[test]
+fn persistent_handles_paging() {
+    let mut tpm = power_on();
+    let max = get_tpm_property(&mut tpm, TPM_PT_HR_PERSISTENT_MIN);
+    assert_eq!(get_tpm_property(&mut tpm, TPM_PT_HR_PERSISTENT), 0);
+    assert_eq!(get_tpm_property(&mut tpm, TPM_PT_HR_PERSISTENT_AVAIL), max);
+
+    let owner_key = primary(&mut tpm, TPM_RH_OWNER);
+    let platform_key = primary(&mut tpm, TPM_RH_PLATFORM);
+    let handles = [
+        OWNER_PERSISTENT + 0x30,
+        OWNER_PERSISTENT + 2,
+        PLATFORM_PERSISTENT + 1,
+        OWNER_PERSISTENT + 0x10,
+    ];
+    for handle in handles {
+        match handle >= PLATFORM_PERSISTENT {
+            true => evict_control(&mut tpm, TPM_RH_PLATFORM, platform_key, handle),
+            false => evict_control(&mut tpm, TPM_RH_OWNER, owner_key, handle),
+        }
+        .unwrap();
+    }
+    assert_eq!(get_tpm_property(&mut tpm, TPM_PT_HR_PERSISTENT), 4);
+    assert_eq!(
+        get_tpm_property(&mut tpm, TPM_PT_HR_PERSISTENT_AVAIL),
+        max - 4
+    );
+
+    let mut sorted = handles.to_vec();
+    sorted.sort();
+    assert_eq!(
+        get_handles(&mut tpm, OWNER_PERSISTENT, 100),
+        (false, sorted.clone())
+    );
+    assert_eq!(
+        get_handles(&mut tpm, OWNER_PERSISTENT, 4),
+        (false, sorted.clone())
+    );
+
+    // Two at a time, each page starting after the last handle of the one
+    // before
+    let mut listed: Vec<u32> = Vec::new();
+    let mut start = OWNER_PERSISTENT;
+    loop {
+        let (more_data, page) = get_handles(&mut tpm, start, 2);
+        assert!(page.len() <= 2);
+        listed.extend(&page);
+        if !more_data {
+            break;
+        }
+        start = page.last().unwrap() + 1;
+    }
+    assert_eq!(listed, sorted);
+
+    assert_eq!(
+        get_handles(&mut tpm, OWNER_PERSISTENT + 0x11, 1),
+        (true, vec![OWNER_PERSISTENT + 0x30])
+    );
+    assert_eq!(
+        get_handles(&mut tpm, PLATFORM_PERSISTENT, 8),
+        (false, vec![PLATFORM_PERSISTENT + 1])
+    );
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+const TPM_CC_RSA_DECRYPT: u32 = 0x159;
+const TPM_CC_LOAD_EXTERNAL: u32 = 0x167;
+const TPM_CC_RSA_ENCRYPT: u32 = 0x174;
+
+const TPM_ALG_RSA: u16 = 0x0001;
+const TPM_ALG_NULL: u16 = 0x0010;
+const TPM_ALG_RSAES: u16 = 0x0015;
+const TPM_ALG_OAEP: u16 = 0x0017;
+
+const TPM_RC_VALUE_P1: u32 = 0x1C4;
+const TPM_RC_VALUE_P3: u32 = 0x3C4;
+
+// A 1024-bit key and "secret" encrypted to it with SHA-256 OAEP and the
+// label "label\0" by an independent implementation
+const N: &str = "a1de113c88b0762bee1b10a019cebb134ed81dbc6a85b8e2508f76b83876223c\
+                 a1bea1b7e1d727cf58afdb6452d2cc2c17e321e2259961c4c6af3be6ad9e81e3\
+                 120a8dab403012a264271161226f51a94bb2b35dedaa07dff708611986657dee\
+                 e3492cca61ed0afa4d44c48cda3235965c6789e1bf47d317d2b4906a99c84de5";
+const P: &str = "d0886c4900a113ef88991ec3fb5503bf1ced684f7a11b18cca92d6804756687c\
+                 286cc052c2260d98190fd16ef5455bc342792da97e1951a863f7fe9c1adfe33f";
+const OAEP: &str = "407f5827e43b7dc747ce2561ef7e26b2c6a6f91826c280cc1eef09f6352f2373\
+                    85dd17237b067be216dbc997b388b48b38d0aafb48c9867bacf921d475dd1947\
+                    31e24ded3c437be6d4caf29ffc358a0ab62cf99672cf2f68f2e4af6c377dc481\
+                    43a503f29a0bba3ebd4086dfd4fc7cdaf78d58ce51ae3fecdb0962120ad0f908";
+
+fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+// Load the key above into the null hierarchy, with no scheme of its own
+fn load_key(tpm: &mut TpmInstance) -> u32 {
+    let mut sensitive = Vec::new();
+    sensitive.extend(TPM_ALG_RSA.to_be_bytes());
+    sensitive.extend(tpm2b(&[]));
+    sensitive.extend(tpm2b(&[]));
+    sensitive.extend(tpm2b(&hex(P)));
+
+    let mut public = Vec::new();
+    public.extend(TPM_ALG_RSA.to_be_bytes());
+    public.extend(TPM_ALG_SHA256.to_be_bytes());
+    // userWithAuth | decrypt
+    public.extend(0x00020040u32.to_be_bytes());
+    public.extend(tpm2b(&[]));
+    public.extend(TPM_ALG_NULL.to_be_bytes()); // symmetric
+    public.extend(TPM_ALG_NULL.to_be_bytes()); // scheme
+    public.extend(1024u16.to_be_bytes());
+    public.extend(0u32.to_be_bytes());
+    public.extend(tpm2b(&hex(N)));
+
+    let params = [
+        tpm2b(&sensitive),
+        tpm2b(&public),
+        TPM_RH_NULL.to_be_bytes().to_vec(),
+    ]
+    .concat();
+    let response = run(tpm, TPM_CC_LOAD_EXTERNAL, &[], None, &params).unwrap();
+    Reader::new(&response).u32()
+}
+
+// TPMT_RSA_DECRYPT
+fn scheme(alg: u16) -> Vec<u8> {
+    match alg {
+        TPM_ALG_OAEP => [alg, TPM_ALG_SHA256].map(u16::to_be_bytes).concat(),
+        _ => alg.to_be_bytes().to_vec(),
+    }
+}
+
+fn encrypt(tpm: &mut TpmInstance, key: u32, alg: u16, label: &[u8]) -> Result<Vec<u8>, u32> {
+    let params = [tpm2b(b"secret"), scheme(alg), tpm2b(label)].concat();
+    let response = run(tpm, TPM_CC_RSA_ENCRYPT, &[key], None, &params)?;
+    Ok(Reader::new(&response).tpm2b().to_vec())
+}
+
+fn decrypt(
+    tpm: &mut TpmInstance,
+    key: u32,
+    alg: u16,
+    cipher_text: &[u8],
+    label: &[u8],
+) -> Result<Vec<u8>, u32> {
+    let params = [tpm2b(cipher_text), scheme(alg), tpm2b(label)].concat();
+    let response = run(tpm, TPM_CC_RSA_DECRYPT, &[key], Some(&[&[]]), &params)?;
+    let (_, params) = parameters(&response, false);
+    Ok(Reader::new(&params).tpm2b().to_vec())
+}
+
+#[test]
+fn oaep_known_cipher_text() {
+    let mut tpm = power_on();
+    let key = load_key(&mut tpm);
+
+    let message = decrypt(&mut tpm, key, TPM_ALG_OAEP, &hex(OAEP), b"label\0").unwrap();
+    assert_eq!(message, b"secret");
+
+    let rc = decrypt(&mut tpm, key, TPM_ALG_OAEP, &hex(OAEP), b"other\0");
+    assert_eq!(rc, Err(TPM_RC_VALUE_P1));
+}
+
+#[test]
+fn round_trip() {
+    let mut tpm = power_on();
+    let key = load_key(&mut tpm);
+
+    for (alg, label) in [(TPM_ALG_OAEP, &b"label\0"[..]), (TPM_ALG_RSAES, &[])] {
+        let cipher_text = encrypt(&mut tpm, key, alg, label).unwrap();
+        assert_eq!(cipher_text.len(), 128);
+        let message = decrypt(&mut tpm, key, alg, &cipher_text, label).unwrap();
+        assert_eq!(message, b"secret");
+    }
+}
+
+// A label has to end in a zero byte, as the TPM's own labels do.
+#[test]
+fn unterminated_label() {
+    let mut tpm = power_on();
+    let key = load_key(&mut tpm);
+
+    let rc = encrypt(&mut tpm, key, TPM_ALG_OAEP, b"label");
+    assert_eq!(rc, Err(TPM_RC_VALUE_P3));
+    let rc = decrypt(&mut tpm, key, TPM_ALG_OAEP, &hex(OAEP), b"label");
+    assert_eq!(rc, Err(TPM_RC_VALUE_P3));
+}
@@ -0,0 +1,241 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+const TPM_CC_POLICY_PCR: u32 = 0x17F;
+const TPM_CC_POLICY_RESTART: u32 = 0x180;
+
+const TPM_RC_AUTH_UNAVAILABLE: u32 = 0x12F;
+const TPM_RC_PCR_CHANGED: u32 = 0x128;
+const TPM_RC_VALUE_P1: u32 = 0x1C4;
+const TPM_RC_REFERENCE_S0: u32 = 0x918;
+// Format-one codes for the first session
+const TPM_RC_AUTH_FAIL_S1: u32 = 0x98E;
+const TPM_RC_POLICY_FAIL_S1: u32 = 0x99D;
+
+fn unseal_policy() -> Vec<u8> {
+    let digest = policy_extend(
+        &[0; 32],
+        TPM_CC_POLICY_COMMAND_CODE,
+        &TPM_CC_UNSEAL.to_be_bytes(),
+    );
+    policy_extend(&digest, TPM_CC_POLICY_AUTH_VALUE, &[])
+}
+
+fn sign_params(digest: &[u8]) -> Vec<u8> {
+    [
+        tpm2b(digest),
+        0x0010u16.to_be_bytes().to_vec(), // scheme NULL, use the key's
+        0x8024u16.to_be_bytes().to_vec(), // TPM_ST_HASHCHECK
+        TPM_RH_NULL.to_be_bytes().to_vec(),
+        tpm2b(&[]),
+    ]
+    .concat()
+}
+
+// A sealed object without userWithAuth can only be unsealed by satisfying
+// its policy.
+#[test]
+fn policy_unseal() {
+    let mut tpm = power_on();
+    let policy = unseal_policy();
+
+    // A trial session computes the policy without being able to use it.
+    let trial = start_auth_session(&mut tpm, TPM_SE_TRIAL, TPM_RH_NULL, &[]);
+    policy_command_code(&mut tpm, trial.handle, TPM_CC_UNSEAL);
+    run(&mut tpm, TPM_CC_POLICY_PASSWORD, &[trial.handle], None, &[]).unwrap();
+    assert_eq!(policy_get_digest(&mut tpm, trial.handle), policy);
+    flush(&mut tpm, trial.handle);
+
+    let (parent, _) = create_primary(&mut tpm, &ecc_storage_template());
+    let secret = b"the sealed secret";
+    let template = sealed_policy_template(&policy);
+    let (private, public) = create(&mut tpm, parent, &template, b"pw", secret);
+    let item = load(&mut tpm, parent, &private, &public);
+
+    let rc = run(&mut tpm, TPM_CC_UNSEAL, &[item], Some(&[b"pw"]), &[]);
+    assert_eq!(rc, Err(TPM_RC_AUTH_UNAVAILABLE));
+
+    // TPM2_PolicyPassword sends the authValue in the clear.
+    let session = start_auth_session(&mut tpm, TPM_SE_POLICY, TPM_RH_NULL, &[]);
+    policy_command_code(&mut tpm, session.handle, TPM_CC_UNSEAL);
+    run(
+        &mut tpm,
+        TPM_CC_POLICY_PASSWORD,
+        &[session.handle],
+        None,
+        &[],
+    )
+    .unwrap();
+    let auth = [auth_command(
+        session.handle,
+        &session.nonce_caller,
+        0,
+        b"pw",
+    )];
+    let response = run_sessions(&mut tpm, TPM_CC_UNSEAL, &[item], Some(&auth), &[]).unwrap();
+    let (_, params) = parameters(&response, false);
+    assert_eq!(Reader::new(&params).tpm2b(), secret);
+
+    // Without continueSession the session is gone.
+    let rc = run_sessions(&mut tpm, TPM_CC_UNSEAL, &[item], Some(&auth), &[]);
+    assert_eq!(rc, Err(TPM_RC_REFERENCE_S0));
+
+    // A policy for another command doesn't match.
+    let session = start_auth_session(&mut tpm, TPM_SE_POLICY, TPM_RH_NULL, &[]);
+    policy_command_code(&mut tpm, session.handle, TPM_CC_SIGN);
+    run(
+        &mut tpm,
+        TPM_CC_POLICY_AUTH_VALUE,
+        &[session.handle],
+        None,
+        &[],
+    )
+    .unwrap();
+    let auth = [auth_command(
+        session.handle,
+        &session.nonce_caller,
+        0,
+        b"pw",
+    )];
+    let rc = run_sessions(&mut tpm, TPM_CC_UNSEAL, &[item], Some(&auth), &[]);
+    assert_eq!(rc, Err(TPM_RC_POLICY_FAIL_S1));
+    flush(&mut tpm, session.handle);
+
+    // TPM2_PolicyAuthValue puts the authValue in the HMAC instead.
+    let name = object_name(&public);
+    let cp_hash = cp_hash(TPM_CC_UNSEAL, &[&name], &[]);
+    let mut session = start_auth_session(&mut tpm, TPM_SE_POLICY, TPM_RH_NULL, &[]);
+    policy_command_code(&mut tpm, session.handle, TPM_CC_UNSEAL);
+    run(
+        &mut tpm,
+        TPM_CC_POLICY_AUTH_VALUE,
+        &[session.handle],
+        None,
+        &[],
+    )
+    .unwrap();
+
+    let auth = [session.command(&cp_hash, b"px", TPMA_SESSION_CONTINUE_SESSION)];
+    let rc = run_sessions(&mut tpm, TPM_CC_UNSEAL, &[item], Some(&auth), &[]);
+    assert_eq!(rc, Err(TPM_RC_AUTH_FAIL_S1));
+
+    let auth = [session.command(&cp_hash, b"pw", TPMA_SESSION_CONTINUE_SESSION)];
+    let response = run_sessions(&mut tpm, TPM_CC_UNSEAL, &[item], Some(&auth), &[]).unwrap();
+    let (_, params) = parameters(&response, false);
+    assert_eq!(Reader::new(&params).tpm2b(), secret);
+    let auths = response_auths(&response, false);
+    session.response(&rp_hash(TPM_CC_UNSEAL, &params), b"pw", &auths[0]);
+
+    // The policy has to be satisfied again for the next command.
+    assert_eq!(policy_get_digest(&mut tpm, session.handle), [0; 32]);
+    let auth = [session.command(&cp_hash, b"pw", TPMA_SESSION_CONTINUE_SESSION)];
+    let rc = run_sessions(&mut tpm, TPM_CC_UNSEAL, &[item], Some(&auth), &[]);
+    assert_eq!(rc, Err(TPM_RC_POLICY_FAIL_S1));
+}
+
+fn policy_pcr(tpm: &mut TpmInstance, session: u32, pcr_digest: &[u8]) -> Result<Vec<u8>, u32> {
+    let params = [tpm2b(pcr_digest), pcr_selection(TPM_ALG_SHA256, &[7])].concat();
+    run(tpm, TPM_CC_POLICY_PCR, &[session], None, &params)
+}
+
+// An object sealed to the value of PCR 7 unseals while PCR 7 has it.
+#[test]
+fn pcr_policy_unseal() {
+    let mut tpm = power_on();
+    pcr_extend(&mut tpm, 7, &sha256(&[b"secure boot"]));
+    let pcr_digest = sha256(&[&sha256(&[&[0; 32], &sha256(&[b"secure boot"])])]);
+
+    let trial = start_auth_session(&mut tpm, TPM_SE_TRIAL, TPM_RH_NULL, &[]);
+    policy_pcr(&mut tpm, trial.handle, &[]).unwrap();
+    let policy = policy_get_digest(&mut tpm, trial.handle);
+    let selection = pcr_selection(TPM_ALG_SHA256, &[7]);
+    let expected = policy_extend(
+        &[0; 32],
+        TPM_CC_POLICY_PCR,
+        &[selection, pcr_digest.clone()].concat(),
+    );
+    assert_eq!(policy, expected);
+    flush(&mut tpm, trial.handle);
+
+    let (parent, _) = create_primary(&mut tpm, &ecc_storage_template());
+    let secret = b"the sealed secret";
+    let template = sealed_policy_template(&policy);
+    let (private, public) = create(&mut tpm, parent, &template, &[], secret);
+    let item = load(&mut tpm, parent, &private, &public);
+
+    let session = start_auth_session(&mut tpm, TPM_SE_POLICY, TPM_RH_NULL, &[]);
+    policy_pcr(&mut tpm, session.handle, &pcr_digest).unwrap();
+    let auth = [auth_command(
+        session.handle,
+        &session.nonce_caller,
+        TPMA_SESSION_CONTINUE_SESSION,
+        &[],
+    )];
+    let response = run_sessions(&mut tpm, TPM_CC_UNSEAL, &[item], Some(&auth), &[]).unwrap();
+    let (_, params) = parameters(&response, false);
+    assert_eq!(Reader::new(&params).tpm2b(), secret);
+
+    // The PCRs can't change between PolicyPCR and the command.
+    policy_pcr(&mut tpm, session.handle, &[]).unwrap();
+    pcr_extend(&mut tpm, 7, &sha256(&[b"something else"]));
+    let rc = run_sessions(&mut tpm, TPM_CC_UNSEAL, &[item], Some(&auth), &[]);
+    assert_eq!(rc, Err(TPM_RC_PCR_CHANGED));
+
+    // Once PCR 7 has moved on, the policy can't be satisfied.
+    let rc = policy_pcr(&mut tpm, session.handle, &pcr_digest);
+    assert_eq!(rc, Err(TPM_RC_VALUE_P1));
+    run(
+        &mut tpm,
+        TPM_CC_POLICY_RESTART,
+        &[session.handle],
+        None,
+        &[],
+    )
+    .unwrap();
+    policy_pcr(&mut tpm, session.handle, &[]).unwrap();
+    let rc = run_sessions(&mut tpm, TPM_CC_UNSEAL, &[item], Some(&auth), &[]);
+    assert_eq!(rc, Err(TPM_RC_POLICY_FAIL_S1));
+}
+
+// HMAC sessions prove knowledge of the authValue without sending it, with
+// a new nonceTPM for every command.
+#[test]
+fn hmac_session() {
+    let mut tpm = power_on();
+    let (parent, _) = create_primary(&mut tpm, &ecc_storage_template());
+    let (private, public) = create(&mut tpm, parent, &ecc_signing_template(), b"key", &[]);
+    let key = load(&mut tpm, parent, &private, &public);
+    let name = object_name(&public);
+
+    let mut session = start_auth_session(&mut tpm, TPM_SE_HMAC, TPM_RH_NULL, &[]);
+    let params = sign_params(&[0x5A; 32]);
+    let cp_hash = cp_hash(TPM_CC_SIGN, &[&name], &params);
+    for _ in 0..2 {
+        let auth = [session.command(&cp_hash, b"key", TPMA_SESSION_CONTINUE_SESSION)];
+        let response = run_sessions(&mut tpm, TPM_CC_SIGN, &[key], Some(&auth), &params).unwrap();
+        let (_, signature) = parameters(&response, false);
+        let auths = response_auths(&response, false);
+        session.response(&rp_hash(TPM_CC_SIGN, &signature), b"key", &auths[0]);
+    }
+
+    let auth = [session.command(&cp_hash, b"kez", TPMA_SESSION_CONTINUE_SESSION)];
+    let rc = run_sessions(&mut tpm, TPM_CC_SIGN, &[key], Some(&auth), &params);
+    assert_eq!(rc, Err(TPM_RC_AUTH_FAIL_S1));
+
+    // A command HMAC can't be replayed once the nonce has moved on.
+    let auth = [session.command(&cp_hash, b"key", TPMA_SESSION_CONTINUE_SESSION)];
+    run_sessions(&mut tpm, TPM_CC_SIGN, &[key], Some(&auth), &params).unwrap();
+    let rc = run_sessions(&mut tpm, TPM_CC_SIGN, &[key], Some(&auth), &params);
+    assert_eq!(rc, Err(TPM_RC_AUTH_FAIL_S1));
+    flush(&mut tpm, session.handle);
+
+    // A session bound to the key has the authValue in its session key.
+    let mut session = start_auth_session(&mut tpm, TPM_SE_HMAC, key, b"key");
+    let auth = [session.command(&cp_hash, &[], 0)];
+    let response = run_sessions(&mut tpm, TPM_CC_SIGN, &[key], Some(&auth), &params).unwrap();
+    let (_, signature) = parameters(&response, false);
+    let auths = response_auths(&response, false);
+    session.response(&rp_hash(TPM_CC_SIGN, &signature), &[], &auths[0]);
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use common::*;
+use tpm::tpm::TpmInstance;
+
+const TPM_PT_STARTUP_CLEAR: u32 = 0x201;
+const TPMA_STARTUP_CLEAR_ORDERLY: u32 = 1 << 31;
+
+const TPM_CC_HIERARCHY_CHANGE_AUTH: u32 = 0x129;
+
+const TPM_RC_VALUE_P1: u32 = 0x1C4;
+const TPM_RC_NV_UNAVAILABLE: u32 = 0x923;
+
+fn orderly(tpm: &mut TpmInstance) -> bool {
+    get_tpm_property(tpm, TPM_PT_STARTUP_CLEAR) & TPMA_STARTUP_CLEAR_ORDERLY != 0
+}
+
+// Commands after Shutdown that leave its state alone keep it orderly. Once
+// one changes it, the state saved can't be resumed from.
+#[test]
+fn state_change_after_shutdown() {
+    let mut tpm = power_on();
+    let (key, _) = create_primary(&mut tpm, &ecc_storage_template());
+    assert!(!orderly(&mut tpm));
+
+    shutdown(&mut tpm, TPM_SU_STATE).unwrap();
+    assert!(orderly(&mut tpm));
+
+    context_save(&mut tpm, key).unwrap();
+    assert!(!orderly(&mut tpm));
+
+    let mut tpm = TpmInstance::new(&platform());
+    assert_eq!(startup(&mut tpm, TPM_SU_STATE), Err(TPM_RC_VALUE_P1));
+    startup(&mut tpm, TPM_SU_CLEAR).unwrap();
+}
+
+// A command that can't write NV fails with TPM_RC_NV_UNAVAILABLE, and
+// leaves things as they were.
+#[test]
+fn nv_write_failure() {
+    let mut tpm = power_on();
+
+    fail_nv_writes(true);
+    let rc = run(
+        &mut tpm,
+        TPM_CC_HIERARCHY_CHANGE_AUTH,
+        &[TPM_RH_OWNER],
+        Some(&[&[]]),
+        &tpm2b(b"owner"),
+    );
+    fail_nv_writes(false);
+    assert_eq!(rc, Err(TPM_RC_NV_UNAVAILABLE));
+
+    // The owner's authValue is still empty, in RAM and in NV.
+    create_primary(&mut tpm, &ecc_storage_template());
+    let mut tpm = power_on();
+    create_primary(&mut tpm, &ecc_storage_template());
+}